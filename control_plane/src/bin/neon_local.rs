@@ -1310,6 +1310,7 @@ async fn handle_timeline(cmd: &TimelineCmd, env: &mut local_env::LocalEnv) -> Re
                         }],
                     },
                     new_members: None,
+                    witnesses: Vec::new(),
                 };
                 let pg_version = PgVersionId::from(args.pg_version);
                 let req = safekeeper_api::models::TimelineCreateRequest {
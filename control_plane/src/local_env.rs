@@ -214,6 +214,8 @@ pub struct NeonStorageControllerConf {
 
     pub timeline_safekeeper_count: Option<usize>,
 
+    pub timeline_safekeeper_witness_count: Option<usize>,
+
     pub posthog_config: Option<PostHogConfig>,
 
     pub kick_secondary_downloads: Option<bool>,
@@ -251,6 +253,7 @@ fn default() -> Self {
             use_https_safekeeper_api: false,
             use_local_compute_notifications: true,
             timeline_safekeeper_count: None,
+            timeline_safekeeper_witness_count: None,
             posthog_config: None,
             kick_secondary_downloads: None,
             shard_split_request_timeout: None,
@@ -517,6 +517,16 @@ pub fn parse_config(mut settings: HashMap<&str, &str>) -> anyhow::Result<models:
                 .map(serde_json::from_str)
                 .transpose()
                 .context("parse `timeline_get_throttle` from json")?,
+            wal_ingest_throttle: settings
+                .remove("wal_ingest_throttle")
+                .map(serde_json::from_str)
+                .transpose()
+                .context("parse `wal_ingest_throttle` from json")?,
+            wal_ingest_max_unflushed_bytes: settings
+                .remove("wal_ingest_max_unflushed_bytes")
+                .map(|x| x.parse::<u64>())
+                .transpose()
+                .context("Failed to parse 'wal_ingest_max_unflushed_bytes' as integer")?,
             lsn_lease_length: settings.remove("lsn_lease_length")
                 .map(humantime::parse_duration)
                 .transpose()
@@ -648,6 +648,10 @@ pub async fn start(&self, start_args: NeonStorageControllerStartArgs) -> anyhow:
             args.push(format!("--timeline-safekeeper-count={sk_cnt}"));
         }
 
+        if let Some(witness_cnt) = self.config.timeline_safekeeper_witness_count {
+            args.push(format!("--timeline-safekeeper-witness-count={witness_cnt}"));
+        }
+
         if let Some(duration) = self.config.shard_split_request_timeout {
             args.push(format!(
                 "--shard-split-request-timeout={}",
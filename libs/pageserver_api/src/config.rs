@@ -246,6 +246,8 @@ pub struct ConfigToml {
     pub secondary_download_concurrency: usize,
     pub virtual_file_io_engine: Option<crate::models::virtual_file::IoEngineKind>,
     pub ingest_batch_size: u64,
+    /// Number of interpreted WAL batches which may be decoded concurrently ahead of ingest.
+    pub ingest_decode_concurrency: NonZeroUsize,
     pub max_vectored_read_bytes: MaxVectoredReadBytes,
     pub max_get_vectored_keys: MaxGetVectoredKeys,
     pub image_compression: ImageCompressionAlgorithm,
@@ -258,6 +260,9 @@ pub struct ConfigToml {
     pub page_service_pipelining: PageServicePipeliningConfig,
     pub get_vectored_concurrent_io: GetVectoredConcurrentIo,
     pub enable_read_path_debugging: Option<bool>,
+    /// Sample one in this many page reads and WAL ingest batches of each timeline into its
+    /// key access report. Zero disables sampling.
+    pub key_access_sampling_interval: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub validate_wal_contiguity: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -603,6 +608,15 @@ pub struct TenantConfigToml {
 
     pub timeline_get_throttle: crate::models::ThrottleConfig,
 
+    /// Tenant-wide rate limit on WAL ingest, in bytes of interpreted WAL per second.
+    /// Shared by all timelines of the tenant shard. When the limit is exceeded, the walreceiver
+    /// stops pulling WAL from the safekeeper, which lets backpressure build up towards compute.
+    pub wal_ingest_throttle: crate::models::ThrottleConfig,
+
+    /// If set, WAL ingest on a timeline pauses while its frozen in-memory layers that are
+    /// waiting to be flushed add up to more than this many bytes.
+    pub wal_ingest_max_unflushed_bytes: Option<u64>,
+
     // How much WAL must be ingested before checking again whether a new image layer is required.
     // Expresed in multiples of checkpoint distance.
     pub image_layer_creation_check_threshold: u8,
@@ -686,6 +700,9 @@ pub mod defaults {
     pub const DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY: usize = 1;
 
     pub const DEFAULT_INGEST_BATCH_SIZE: u64 = 100;
+    pub const DEFAULT_INGEST_DECODE_CONCURRENCY: usize = 4;
+
+    pub const DEFAULT_KEY_ACCESS_SAMPLING_INTERVAL: u32 = 100;
 
     /// Soft limit for the maximum size of a vectored read.
     ///
@@ -788,6 +805,8 @@ fn default() -> Self {
             secondary_download_concurrency: (DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY),
 
             ingest_batch_size: (DEFAULT_INGEST_BATCH_SIZE),
+            ingest_decode_concurrency: NonZeroUsize::new(DEFAULT_INGEST_DECODE_CONCURRENCY)
+                .unwrap(),
 
             virtual_file_io_engine: None,
 
@@ -817,6 +836,7 @@ fn default() -> Self {
             } else {
                 None
             },
+            key_access_sampling_interval: DEFAULT_KEY_ACCESS_SAMPLING_INTERVAL,
             validate_wal_contiguity: None,
             load_previous_heatmap: None,
             generate_unarchival_heatmap: None,
@@ -946,6 +966,8 @@ fn default() -> Self {
             heatmap_period: Duration::ZERO,
             lazy_slru_download: false,
             timeline_get_throttle: crate::models::ThrottleConfig::disabled(),
+            wal_ingest_throttle: crate::models::ThrottleConfig::disabled(),
+            wal_ingest_max_unflushed_bytes: None,
             image_layer_creation_check_threshold: DEFAULT_IMAGE_LAYER_CREATION_CHECK_THRESHOLD,
             image_creation_preempt_threshold: DEFAULT_IMAGE_CREATION_PREEMPT_THRESHOLD,
             lsn_lease_length: LsnLease::DEFAULT_LENGTH,
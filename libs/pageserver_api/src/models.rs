@@ -21,6 +21,7 @@
 
 use crate::config::Ratio;
 use crate::key::{CompactKey, Key};
+use crate::reltag::RelTag;
 use crate::shard::{
     DEFAULT_STRIPE_SIZE, ShardCount, ShardIdentity, ShardStripeSize, TenantShardId,
 };
@@ -621,6 +622,10 @@ pub struct TenantConfigPatch {
     #[serde(skip_serializing_if = "FieldPatch::is_noop")]
     pub timeline_get_throttle: FieldPatch<ThrottleConfig>,
     #[serde(skip_serializing_if = "FieldPatch::is_noop")]
+    pub wal_ingest_throttle: FieldPatch<ThrottleConfig>,
+    #[serde(skip_serializing_if = "FieldPatch::is_noop")]
+    pub wal_ingest_max_unflushed_bytes: FieldPatch<u64>,
+    #[serde(skip_serializing_if = "FieldPatch::is_noop")]
     pub image_layer_creation_check_threshold: FieldPatch<u8>,
     #[serde(skip_serializing_if = "FieldPatch::is_noop")]
     pub image_creation_preempt_threshold: FieldPatch<usize>,
@@ -743,6 +748,12 @@ pub struct TenantConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timeline_get_throttle: Option<ThrottleConfig>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wal_ingest_throttle: Option<ThrottleConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wal_ingest_max_unflushed_bytes: Option<u64>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub image_layer_creation_check_threshold: Option<u8>,
 
@@ -817,6 +828,8 @@ pub fn apply_patch(
             mut heatmap_period,
             mut lazy_slru_download,
             mut timeline_get_throttle,
+            mut wal_ingest_throttle,
+            mut wal_ingest_max_unflushed_bytes,
             mut image_layer_creation_check_threshold,
             mut image_creation_preempt_threshold,
             mut lsn_lease_length,
@@ -904,6 +917,10 @@ pub fn apply_patch(
         patch
             .timeline_get_throttle
             .apply(&mut timeline_get_throttle);
+        patch.wal_ingest_throttle.apply(&mut wal_ingest_throttle);
+        patch
+            .wal_ingest_max_unflushed_bytes
+            .apply(&mut wal_ingest_max_unflushed_bytes);
         patch
             .image_layer_creation_check_threshold
             .apply(&mut image_layer_creation_check_threshold);
@@ -967,6 +984,8 @@ pub fn apply_patch(
             heatmap_period,
             lazy_slru_download,
             timeline_get_throttle,
+            wal_ingest_throttle,
+            wal_ingest_max_unflushed_bytes,
             image_layer_creation_check_threshold,
             image_creation_preempt_threshold,
             lsn_lease_length,
@@ -1057,6 +1076,13 @@ pub fn merge(
                 .timeline_get_throttle
                 .clone()
                 .unwrap_or(global_conf.timeline_get_throttle),
+            wal_ingest_throttle: self
+                .wal_ingest_throttle
+                .clone()
+                .unwrap_or(global_conf.wal_ingest_throttle),
+            wal_ingest_max_unflushed_bytes: self
+                .wal_ingest_max_unflushed_bytes
+                .or(global_conf.wal_ingest_max_unflushed_bytes),
             image_layer_creation_check_threshold: self
                 .image_layer_creation_check_threshold
                 .unwrap_or(global_conf.image_layer_creation_check_threshold),
@@ -1443,6 +1469,23 @@ pub struct TenantWaitLsnRequest {
     pub timeout: Duration,
 }
 
+/// Hands off page service traffic of an attached tenant shard to another pageserver, which
+/// must already have an attached location (`AttachedMulti`) that has caught up.
+///
+/// While set, page service clients which understand it are answered with a "moved" response
+/// pointing them at `connstr`. Other clients keep being served until the location is detached.
+/// Setting `connstr` to `None` cancels the handoff.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TenantShardHandoffRequest {
+    pub connstr: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TenantShardHandoffResponse {
+    /// Number of page service connections that still use the tenant shard on this pageserver.
+    pub page_service_connections: usize,
+}
+
 /// See [`TenantState::attachment_status`] and the OpenAPI docs for context.
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(tag = "slug", content = "data", rename_all = "snake_case")]
@@ -1963,6 +2006,49 @@ fn default() -> Self {
     }
 }
 
+/// Sampled read and write activity of a timeline, aggregated by key range.
+///
+/// Returned by the `key_access` timeline API. Use `pagectl key-access` to analyze it.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyAccessReport {
+    /// Start of the period covered by the report: the last reset, or timeline load.
+    #[serde_as(as = "SystemTimeAsRfc3339Millis")]
+    pub since: SystemTime,
+    /// One in `sampling_interval` reads and writes was sampled. Counts in
+    /// [`KeyRangeAccess`] are already scaled up by this factor.
+    pub sampling_interval: u32,
+    /// Number of contiguous keys (blocks, for relation keys) per tracked range.
+    pub range_size: u32,
+    /// Samples that were not recorded because too many ranges were tracked.
+    pub dropped_samples: u64,
+    /// Hottest key ranges first.
+    pub ranges: Vec<KeyRangeAccess>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyRangeAccess {
+    pub key_range: CompactKeyRange,
+    /// Set if the range holds relation blocks.
+    pub rel: Option<RelTag>,
+    pub blocks: Option<Range<u32>>,
+    /// Estimated number of page reads served from this range.
+    pub reads: u64,
+    /// Estimated number of values written to this range.
+    pub writes: u64,
+    /// The layers which served the reads, most used first.
+    pub layers: Vec<KeyRangeLayerAccess>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyRangeLayerAccess {
+    /// `None` for in-memory layers.
+    pub layer_file_name: Option<String>,
+    pub reads: u64,
+    /// Reads which found the layer evicted, and likely caused an on-demand download.
+    pub non_resident_reads: u64,
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
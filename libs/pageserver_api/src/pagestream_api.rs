@@ -38,6 +38,7 @@ pub enum PagestreamBeMessage {
     Error(PagestreamErrorResponse),
     DbSize(PagestreamDbSizeResponse),
     GetSlruSegment(PagestreamGetSlruSegmentResponse),
+    Moved(PagestreamMovedResponse),
     #[cfg(feature = "testing")]
     Test(PagestreamTestResponse),
 }
@@ -65,6 +66,7 @@ enum PagestreamBeMessageTag {
     Error = 103,
     DbSize = 104,
     GetSlruSegment = 105,
+    Moved = 106,
     /* future tags above this line */
     /// For testing purposes, not available in production.
     #[cfg(feature = "testing")]
@@ -97,6 +99,7 @@ fn try_from(value: u8) -> Result<Self, u8> {
             103 => Ok(PagestreamBeMessageTag::Error),
             104 => Ok(PagestreamBeMessageTag::DbSize),
             105 => Ok(PagestreamBeMessageTag::GetSlruSegment),
+            106 => Ok(PagestreamBeMessageTag::Moved),
             #[cfg(feature = "testing")]
             199 => Ok(PagestreamBeMessageTag::Test),
             _ => Err(value),
@@ -210,6 +213,16 @@ pub struct PagestreamErrorResponse {
     pub message: String,
 }
 
+/// The shard that serves the request is being handed off to another pageserver. The client
+/// should send this and all subsequent requests for the shard to `connstr` instead, until its
+/// configuration is updated. Only sent to clients which opted in to it, see
+/// `neon.accept_moved` in the page service startup options.
+#[derive(Debug)]
+pub struct PagestreamMovedResponse {
+    pub req: PagestreamRequest,
+    pub connstr: String,
+}
+
 #[derive(Debug)]
 pub struct PagestreamDbSizeResponse {
     pub req: PagestreamDbSizeRequest,
@@ -453,6 +466,12 @@ pub fn serialize(&self, protocol_version: PagestreamProtocolVersion) -> Bytes {
                         bytes.put(&resp.segment[..]);
                     }
 
+                    Self::Moved(resp) => {
+                        bytes.put_u8(Tag::Moved as u8);
+                        bytes.put(resp.connstr.as_bytes());
+                        bytes.put_u8(0); // null terminator
+                    }
+
                     #[cfg(feature = "testing")]
                     Self::Test(resp) => {
                         bytes.put_u8(Tag::Test as u8);
@@ -530,6 +549,15 @@ pub fn serialize(&self, protocol_version: PagestreamProtocolVersion) -> Bytes {
                         bytes.put(&resp.segment[..]);
                     }
 
+                    Self::Moved(resp) => {
+                        bytes.put_u8(Tag::Moved as u8);
+                        bytes.put_u64(resp.req.reqid);
+                        bytes.put_u64(resp.req.request_lsn.0);
+                        bytes.put_u64(resp.req.not_modified_since.0);
+                        bytes.put(resp.connstr.as_bytes());
+                        bytes.put_u8(0); // null terminator
+                    }
+
                     #[cfg(feature = "testing")]
                     Self::Test(resp) => {
                         bytes.put_u8(Tag::Test as u8);
@@ -683,6 +711,22 @@ pub fn deserialize(buf: Bytes) -> anyhow::Result<Self> {
                         segment: segment.into(),
                     })
                 }
+                Tag::Moved => {
+                    let reqid = buf.read_u64::<BigEndian>()?;
+                    let request_lsn = Lsn(buf.read_u64::<BigEndian>()?);
+                    let not_modified_since = Lsn(buf.read_u64::<BigEndian>()?);
+                    let mut msg = Vec::new();
+                    buf.read_until(0, &mut msg)?;
+                    let cstring = std::ffi::CString::from_vec_with_nul(msg)?;
+                    Self::Moved(PagestreamMovedResponse {
+                        req: PagestreamRequest {
+                            reqid,
+                            request_lsn,
+                            not_modified_since,
+                        },
+                        connstr: cstring.to_str()?.to_owned(),
+                    })
+                }
                 #[cfg(feature = "testing")]
                 Tag::Test => {
                     let reqid = buf.read_u64::<BigEndian>()?;
@@ -724,6 +768,7 @@ pub fn kind(&self) -> &'static str {
             Self::Error(_) => "Error",
             Self::DbSize(_) => "DbSize",
             Self::GetSlruSegment(_) => "GetSlruSegment",
+            Self::Moved(_) => "Moved",
             #[cfg(feature = "testing")]
             Self::Test(_) => "Test",
         }
@@ -795,4 +840,28 @@ fn test_pagestream() {
             assert!(msg == reconstructed);
         }
     }
+
+    #[test]
+    fn test_pagestream_moved() {
+        let msg = PagestreamBeMessage::Moved(PagestreamMovedResponse {
+            req: PagestreamRequest {
+                reqid: 7,
+                request_lsn: Lsn(4),
+                not_modified_since: Lsn(3),
+            },
+            connstr: "postgresql://no_user@localhost:64000".to_string(),
+        });
+        let bytes = msg.serialize(PagestreamProtocolVersion::V3);
+        let PagestreamBeMessage::Moved(reconstructed) =
+            PagestreamBeMessage::deserialize(bytes).unwrap()
+        else {
+            panic!("unexpected message kind");
+        };
+        assert_eq!(reconstructed.req.reqid, 7);
+        assert_eq!(reconstructed.req.request_lsn, Lsn(4));
+        assert_eq!(
+            reconstructed.connstr,
+            "postgresql://no_user@localhost:64000"
+        );
+    }
 }
@@ -146,6 +146,13 @@ pub const fn HEAPBLK_TO_OFFSET(x: u32) -> u32 {
 pub const XLH_UPDATE_OLD_ALL_VISIBLE_CLEARED: u8 = (1 << 0) as u8;
 pub const XLH_UPDATE_NEW_ALL_VISIBLE_CLEARED: u8 = (1 << 1) as u8;
 pub const XLH_DELETE_ALL_VISIBLE_CLEARED: u8 = (1 << 0) as u8;
+pub const XLH_INSERT_CONTAINS_NEW_TUPLE: u8 = (1 << 3) as u8;
+pub const XLH_UPDATE_CONTAINS_OLD_TUPLE: u8 = (1 << 2) as u8;
+pub const XLH_UPDATE_CONTAINS_OLD_KEY: u8 = (1 << 3) as u8;
+pub const XLH_UPDATE_PREFIX_FROM_OLD: u8 = (1 << 5) as u8;
+pub const XLH_UPDATE_SUFFIX_FROM_OLD: u8 = (1 << 6) as u8;
+pub const XLH_DELETE_CONTAINS_OLD_TUPLE: u8 = (1 << 1) as u8;
+pub const XLH_DELETE_CONTAINS_OLD_KEY: u8 = (1 << 2) as u8;
 
 // From heapam_xlog.h
 pub const XLOG_HEAP2_REWRITE: u8 = 0x00;
@@ -176,6 +176,7 @@ pub struct DecodedBkpBlock {
     /* Buffer holding the rmgr-specific data associated with this block */
     has_data: bool,
     data_len: u16,
+    data_offset: u32,
 }
 
 impl DecodedBkpBlock {
@@ -197,6 +198,17 @@ pub struct DecodedWALRecord {
 }
 
 impl DecodedWALRecord {
+    /// Rmgr-specific data associated with the block, if any. For heap
+    /// records this is the new tuple, unless it was replaced by a full-page
+    /// image.
+    pub fn block_data(&self, blk: &DecodedBkpBlock) -> Option<Bytes> {
+        if !blk.has_data {
+            return None;
+        }
+        let start = blk.data_offset as usize;
+        Some(self.record.slice(start..start + blk.data_len as usize))
+    }
+
     /// Check if this WAL record represents a legacy "copy" database creation, which populates new relations
     /// by reading other existing relations' data blocks.  This is more complex to apply than new-style database
     /// creations which simply include all the desired blocks in the WAL, so we need a helper function to detect this case.
@@ -479,6 +491,7 @@ pub fn decode_wal_record(
             ptr += blk.bimg_len as usize;
         }
         if blk.has_data {
+            blk.data_offset = ptr as u32;
             ptr += blk.data_len as usize;
         }
     }
@@ -572,6 +572,10 @@ pub enum BeMessage<'a> {
     /// ready for the pageserver to ingest
     InterpretedWalRecords(InterpretedWalRecordsBody<'a>),
 
+    /// Batch of row-level change events decoded from WAL,
+    /// streamed to change data capture subscribers
+    ChangeEvents(ChangeEventsBody<'a>),
+
     Raw(u8, &'a [u8]),
 }
 
@@ -699,6 +703,16 @@ pub struct InterpretedWalRecordsBody<'a> {
     pub data: &'a [u8],
 }
 
+/// Body of a change events message of the safekeeper change events protocol.
+#[derive(Debug)]
+pub struct ChangeEventsBody<'a> {
+    /// End of WAL decoded into [`Self::data`]
+    pub streaming_lsn: u64,
+    /// Current end of WAL on the server
+    pub commit_lsn: u64,
+    pub data: &'a [u8],
+}
+
 pub static HELLO_WORLD_ROW: BeMessage = BeMessage::DataRow(&[Some(b"hello world")]);
 
 // single text column
@@ -1040,6 +1054,18 @@ pub fn write(buf: &mut BytesMut, message: &BeMessage) -> Result<(), ProtocolErro
                     buf.put_slice(rec.data);
                 });
             }
+
+            BeMessage::ChangeEvents(events) => {
+                // Same framing as InterpretedWalRecords, under its own tag
+                // inside CopyData.
+                buf.put_u8(b'd');
+                write_body(buf, |buf| {
+                    buf.put_u8(b'e');
+                    buf.put_u64(events.streaming_lsn);
+                    buf.put_u64(events.commit_lsn);
+                    buf.put_slice(events.data);
+                });
+            }
         }
         Ok(())
     }
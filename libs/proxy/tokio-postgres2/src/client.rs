@@ -17,6 +17,7 @@
 use crate::config::{Host, SslMode};
 use crate::query::RowStream;
 use crate::simple_query::SimpleQueryStream;
+use crate::statement_cache::StatementCache;
 use crate::types::{Oid, Type};
 use crate::{
     CancelToken, Error, ReadyForQueryStatus, SimpleQueryMessage, Transaction, TransactionBuilder,
@@ -176,6 +177,7 @@ pub struct SocketConfig {
 pub struct Client {
     inner: InnerClient,
     cached_typeinfo: CachedTypeInfo,
+    statement_cache: StatementCache,
 
     socket_config: SocketConfig,
     ssl_mode: SslMode,
@@ -204,6 +206,7 @@ pub(crate) fn new(
                 buffer: Default::default(),
             },
             cached_typeinfo: Default::default(),
+            statement_cache: Default::default(),
 
             socket_config,
             ssl_mode,
@@ -217,6 +220,12 @@ pub fn get_process_id(&self) -> i32 {
         self.process_id
     }
 
+    /// Sets how many statements [`Client::query_raw_txt`] keeps prepared for reuse.
+    /// The cache is disabled by default.
+    pub fn set_statement_cache_size(&mut self, size: usize) {
+        self.statement_cache.set_capacity(size);
+    }
+
     pub(crate) fn inner_mut(&mut self) -> &mut InnerClient {
         &mut self.inner
     }
@@ -236,6 +245,7 @@ pub async fn query_raw_txt<S, I>(
         query::query_txt(
             &mut self.inner,
             &mut self.cached_typeinfo,
+            &mut self.statement_cache,
             statement,
             params,
         )
@@ -281,6 +291,8 @@ pub async fn batch_execute(&mut self, query: &str) -> Result<ReadyForQueryStatus
     }
 
     pub async fn discard_all(&mut self) -> Result<ReadyForQueryStatus, Error> {
+        // this deallocates the cached statements.
+        self.statement_cache.clear();
         self.batch_execute("discard all").await
     }
 
@@ -77,6 +77,7 @@ pub struct Config {
     pub(crate) connect_timeout: Option<Duration>,
     pub(crate) channel_binding: ChannelBinding,
     pub(crate) server_params: StartupMessageParams,
+    pub(crate) init_query: Option<String>,
 
     database: bool,
     username: bool,
@@ -95,6 +96,7 @@ pub fn new(host: String, port: u16) -> Config {
             connect_timeout: None,
             channel_binding: ChannelBinding::Prefer,
             server_params: StartupMessageParams::default(),
+            init_query: None,
 
             database: false,
             username: false,
@@ -167,6 +169,14 @@ pub fn set_param(&mut self, name: &str, value: &str) -> &mut Config {
         self
     }
 
+    /// Sets a simple query to run once authenticated, before the connection is returned.
+    ///
+    /// An error from the query fails the connection.
+    pub fn init_query(&mut self, query: impl Into<String>) -> &mut Config {
+        self.init_query = Some(query.into());
+        self
+    }
+
     pub fn set_host_addr(&mut self, addr: IpAddr) -> &mut Config {
         self.host_addr = Some(addr);
         self
@@ -101,7 +101,10 @@ pub async fn connect_raw<S, T>(
 
     startup(&mut stream, config).await?;
     authenticate(&mut stream, config).await?;
-    let (process_id, secret_key, parameters) = read_info(&mut stream).await?;
+    let (process_id, secret_key, mut parameters) = read_info(&mut stream).await?;
+    if let Some(query) = &config.init_query {
+        init_query(&mut stream, query, &mut parameters).await?;
+    }
 
     Ok(RawConnection {
         stream: stream.inner,
@@ -322,3 +325,38 @@ async fn read_info<S, T>(
         }
     }
 }
+
+async fn init_query<S, T>(
+    stream: &mut StartupStream<S, T>,
+    query: &str,
+    parameters: &mut HashMap<String, String>,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = BytesMut::new();
+    frontend::query(query, &mut buf).map_err(Error::encode)?;
+    stream
+        .send(FrontendMessage::Raw(buf.freeze()))
+        .await
+        .map_err(Error::io)?;
+
+    // the error is followed by ReadyForQuery, which leaves the connection usable.
+    let mut error = None;
+    loop {
+        match stream.try_next().await.map_err(Error::io)? {
+            Some(Message::ParameterStatus(body)) => {
+                parameters.insert(
+                    body.name().map_err(Error::parse)?.to_string(),
+                    body.value().map_err(Error::parse)?.to_string(),
+                );
+            }
+            Some(Message::NoticeResponse(body)) => stream.delayed_notice.push(body),
+            Some(Message::ErrorResponse(body)) => error = Some(Error::db(body)),
+            Some(Message::ReadyForQuery(_)) => return error.map_or(Ok(()), Err),
+            Some(_) => {}
+            None => return Err(Error::closed()),
+        }
+    }
+}
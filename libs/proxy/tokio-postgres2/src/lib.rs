@@ -63,6 +63,7 @@ fn from(value: ReadyForQueryBody) -> Self {
 pub mod row;
 mod simple_query;
 mod statement;
+mod statement_cache;
 pub mod tls;
 mod transaction;
 mod transaction_builder;
@@ -7,12 +7,15 @@
 use postgres_protocol2::message::frontend;
 use postgres_types2::Format;
 
-use crate::client::{CachedTypeInfo, InnerClient, Responses};
+use crate::client::{CachedTypeInfo, InnerClient, PartialQuery, Responses};
+use crate::error::SqlState;
+use crate::statement_cache::StatementCache;
 use crate::{Error, ReadyForQueryStatus, Row, Statement};
 
 pub async fn query_txt<'a, S, I>(
     client: &'a mut InnerClient,
     typecache: &mut CachedTypeInfo,
+    statements: &'a mut StatementCache,
     query: &str,
     params: I,
 ) -> Result<RowStream<'a>, Error>
@@ -25,7 +28,7 @@ pub async fn query_txt<'a, S, I>(
     let mut client = client.start()?;
 
     // Flow:
-    // 1. Parse the query
+    // 1. Parse the query, unless it's in the statement cache
     // 2. Inspect the row description for OIDs
     // 3. If there's any OIDs we don't already know about, perform the typeinfo routine
     // 4. Execute the query
@@ -36,43 +39,26 @@ pub async fn query_txt<'a, S, I>(
     // 2. Execute the query on each OID
     // 3. If the result does not match an OID we know, repeat 2.
 
-    // parse the query and get type info
-    let responses = client.send_with_flush(|buf| {
-        frontend::parse(
-            "",                 // unnamed prepared statement
-            query,              // query to parse
-            std::iter::empty(), // give no type info
-            buf,
-        )
-        .map_err(Error::encode)?;
-        frontend::describe(b'S', "", buf).map_err(Error::encode)?;
-        Ok(())
-    })?;
-
-    match responses.next().await? {
-        Message::ParseComplete => {}
-        _ => return Err(Error::unexpected_message()),
-    }
-
-    match responses.next().await? {
-        Message::ParameterDescription(_) => {}
-        _ => return Err(Error::unexpected_message()),
+    let (statement, statement_cache_hit) = if !statements.is_enabled() {
+        // unnamed prepared statement
+        let statement = prepare(&mut client, typecache, String::new(), query, &[]).await?;
+        (statement, None)
+    } else if let Some(statement) = statements.get(query, &[]) {
+        (statement, Some(true))
+    } else {
+        let name = statements.next_name();
+        statements.make_room();
+        let close = statements.take_to_close();
+        let statement = prepare(&mut client, typecache, name, query, &close).await?;
+        statements.insert(query, &[], statement.clone());
+        (statement, Some(false))
     };
 
-    let row_description = match responses.next().await? {
-        Message::RowDescription(body) => Some(body),
-        Message::NoData => None,
-        _ => return Err(Error::unexpected_message()),
-    };
-
-    let columns =
-        crate::prepare::parse_row_description(&mut client, typecache, row_description).await?;
-
     let responses = client.send_with_sync(|buf| {
         // Bind, pass params as text, retrieve as text
         match frontend::bind(
-            "",                 // empty string selects the unnamed portal
-            "",                 // unnamed prepared statement
+            "", // empty string selects the unnamed portal
+            statement.name(),
             std::iter::empty(), // all parameters use the default format (text)
             params,
             |param, buf| match param {
@@ -96,27 +82,94 @@ pub async fn query_txt<'a, S, I>(
         Ok(())
     })?;
 
-    match responses.next().await? {
-        Message::BindComplete => {}
-        _ => return Err(Error::unexpected_message()),
+    match responses.next().await {
+        Ok(Message::BindComplete) => {}
+        Ok(_) => return Err(Error::unexpected_message()),
+        Err(e) => {
+            // the statement was deallocated, or a schema change altered its result type.
+            if let Some(code) = e.code()
+                && (*code == SqlState::INVALID_SQL_STATEMENT_NAME
+                    || *code == SqlState::FEATURE_NOT_SUPPORTED)
+            {
+                statements.remove(query);
+            }
+            return Err(e);
+        }
     }
 
     Ok(RowStream {
         responses,
-        statement: Statement::new("", columns),
+        statements,
+        statement,
         command_tag: None,
         status: ReadyForQueryStatus::Unknown,
         output_format: Format::Text,
+        statement_cache_hit,
     })
 }
 
+/// Parse the query as the named statement, first closing the given statements.
+async fn prepare(
+    client: &mut PartialQuery<'_>,
+    typecache: &mut CachedTypeInfo,
+    name: String,
+    query: &str,
+    close: &[String],
+) -> Result<Statement, Error> {
+    // parse the query and get type info
+    let responses = client.send_with_flush(|buf| {
+        for name in close {
+            frontend::close(b'S', name, buf).map_err(Error::encode)?;
+        }
+        frontend::parse(
+            &name,              // prepared statement
+            query,              // query to parse
+            std::iter::empty(), // give no type info
+            buf,
+        )
+        .map_err(Error::encode)?;
+        frontend::describe(b'S', &name, buf).map_err(Error::encode)?;
+        Ok(())
+    })?;
+
+    for _ in close {
+        match responses.next().await? {
+            Message::CloseComplete => {}
+            _ => return Err(Error::unexpected_message()),
+        }
+    }
+
+    match responses.next().await? {
+        Message::ParseComplete => {}
+        _ => return Err(Error::unexpected_message()),
+    }
+
+    match responses.next().await? {
+        Message::ParameterDescription(_) => {}
+        _ => return Err(Error::unexpected_message()),
+    };
+
+    let row_description = match responses.next().await? {
+        Message::RowDescription(body) => Some(body),
+        Message::NoData => None,
+        _ => return Err(Error::unexpected_message()),
+    };
+
+    let columns = crate::prepare::parse_row_description(client, typecache, row_description).await?;
+
+    Ok(Statement::new(name, columns))
+}
+
 /// A stream of table rows.
 pub struct RowStream<'a> {
     responses: &'a mut Responses,
+    statements: &'a mut StatementCache,
     output_format: Format,
     pub statement: Statement,
     pub command_tag: Option<String>,
     pub status: ReadyForQueryStatus,
+    /// Whether the statement was found in the statement cache, `None` if the cache is disabled.
+    pub statement_cache_hit: Option<bool>,
 }
 
 impl Stream for RowStream<'_> {
@@ -136,6 +189,10 @@ fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::It
                 Message::EmptyQueryResponse | Message::PortalSuspended => {}
                 Message::CommandComplete(body) => {
                     if let Ok(tag) = body.tag() {
+                        // these deallocate all prepared statements of the session.
+                        if tag == "DISCARD ALL" || tag == "DEALLOCATE ALL" {
+                            this.statements.clear();
+                        }
                         this.command_tag = Some(tag.to_string());
                     }
                 }
@@ -148,3 +205,397 @@ fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::It
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::{Arc, Mutex};
+
+    use bytes::{Buf, BytesMut};
+    use futures_util::StreamExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use tokio::sync::mpsc;
+    use tokio_util::codec::Framed;
+
+    use crate::codec::PostgresCodec;
+    use crate::config::{Host, SslMode};
+    use crate::error::SqlState;
+    use crate::maybe_tls_stream::MaybeTlsStream;
+    use crate::tls::NoTlsStream;
+    use crate::{Client, Connection, Error, SocketConfig};
+
+    /// A frontend message as seen by [`mock_backend`].
+    #[derive(Debug, Clone, PartialEq)]
+    enum Frontend {
+        Parse { name: String, query: String },
+        Describe(String),
+        Close(String),
+        Bind(String),
+        Execute,
+        Flush,
+        Sync,
+        Query(String),
+    }
+
+    type Log = Arc<Mutex<Vec<Frontend>>>;
+
+    fn cstr(buf: &mut BytesMut) -> String {
+        let end = buf.iter().position(|&b| b == 0).unwrap();
+        let s = String::from_utf8(buf.split_to(end).to_vec()).unwrap();
+        buf.advance(1);
+        s
+    }
+
+    fn put_message(out: &mut BytesMut, tag: u8, body: &[u8]) {
+        out.extend_from_slice(&[tag]);
+        out.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+        out.extend_from_slice(body);
+    }
+
+    fn put_error(out: &mut BytesMut, code: &str, message: &str) {
+        let mut body = Vec::new();
+        for (field, value) in [(b'S', "ERROR"), (b'C', code), (b'M', message)] {
+            body.push(field);
+            body.extend_from_slice(value.as_bytes());
+            body.push(0);
+        }
+        body.push(0);
+        put_message(out, b'E', &body);
+    }
+
+    fn put_command_complete(out: &mut BytesMut, tag: &str) {
+        put_message(out, b'C', format!("{tag}\0").as_bytes());
+    }
+
+    /// A backend which prepares and executes statements like Postgres does, returning a single
+    /// text column. `alter table` makes statements prepared before it fail to bind, like a
+    /// change of their result type does.
+    async fn mock_backend(mut stream: DuplexStream, log: Log) {
+        // statement name -> (query, schema version)
+        let mut prepared = HashMap::<String, (String, u32)>::new();
+        let mut schema_version = 0;
+        let mut portal = None;
+        let mut failed = false;
+        let mut out = BytesMut::new();
+
+        loop {
+            let Ok(tag) = stream.read_u8().await else {
+                return;
+            };
+            let len = stream.read_i32().await.unwrap() as usize;
+            let mut body = BytesMut::zeroed(len - 4);
+            stream.read_exact(&mut body).await.unwrap();
+
+            let message = match tag {
+                b'P' => {
+                    let name = cstr(&mut body);
+                    let query = cstr(&mut body);
+                    Frontend::Parse { name, query }
+                }
+                b'D' | b'C' => {
+                    assert_eq!(body.get_u8(), b'S');
+                    let name = cstr(&mut body);
+                    if tag == b'D' {
+                        Frontend::Describe(name)
+                    } else {
+                        Frontend::Close(name)
+                    }
+                }
+                b'B' => {
+                    assert_eq!(cstr(&mut body), "");
+                    Frontend::Bind(cstr(&mut body))
+                }
+                b'E' => Frontend::Execute,
+                b'H' => Frontend::Flush,
+                b'S' => Frontend::Sync,
+                b'Q' => Frontend::Query(cstr(&mut body)),
+                b'X' => return,
+                tag => panic!("unexpected frontend message {}", tag as char),
+            };
+
+            if failed && !matches!(message, Frontend::Sync) {
+                // the rest of the pipeline is skipped after an error
+                log.lock().unwrap().push(message);
+                continue;
+            }
+            match &message {
+                Frontend::Parse { name, query } => {
+                    prepared.insert(name.clone(), (query.clone(), schema_version));
+                    put_message(&mut out, b'1', &[]);
+                }
+                Frontend::Describe(name) => {
+                    let (query, _) = &prepared[name];
+                    put_message(&mut out, b't', &0i16.to_be_bytes());
+                    if query.starts_with("discard") {
+                        put_message(&mut out, b'n', &[]);
+                    } else {
+                        let mut body = Vec::new();
+                        body.extend_from_slice(&1i16.to_be_bytes());
+                        body.extend_from_slice(b"?column?\0");
+                        body.extend_from_slice(&0i32.to_be_bytes()); // table oid
+                        body.extend_from_slice(&0i16.to_be_bytes()); // column id
+                        body.extend_from_slice(&25i32.to_be_bytes()); // text
+                        body.extend_from_slice(&(-1i16).to_be_bytes()); // type size
+                        body.extend_from_slice(&(-1i32).to_be_bytes()); // type modifier
+                        body.extend_from_slice(&0i16.to_be_bytes()); // format
+                        put_message(&mut out, b'T', &body);
+                    }
+                }
+                Frontend::Close(name) => {
+                    // closing a statement that doesn't exist is not an error
+                    prepared.remove(name);
+                    put_message(&mut out, b'3', &[]);
+                }
+                Frontend::Bind(name) => match prepared.get(name) {
+                    None => {
+                        put_error(
+                            &mut out,
+                            "26000",
+                            &format!("prepared statement \"{name}\" does not exist"),
+                        );
+                        failed = true;
+                    }
+                    Some((_, version)) if *version != schema_version => {
+                        put_error(&mut out, "0A000", "cached plan must not change result type");
+                        failed = true;
+                    }
+                    Some((query, _)) => {
+                        portal = Some(query.clone());
+                        put_message(&mut out, b'2', &[]);
+                    }
+                },
+                Frontend::Execute => {
+                    let query = portal.take().unwrap();
+                    if query == "discard all" {
+                        prepared.clear();
+                        put_command_complete(&mut out, "DISCARD ALL");
+                    } else {
+                        let mut row = Vec::new();
+                        row.extend_from_slice(&1i16.to_be_bytes());
+                        row.extend_from_slice(&1i32.to_be_bytes());
+                        row.extend_from_slice(b"1");
+                        put_message(&mut out, b'D', &row);
+                        put_command_complete(&mut out, "SELECT 1");
+                    }
+                }
+                Frontend::Flush => {}
+                Frontend::Sync => {
+                    failed = false;
+                    put_message(&mut out, b'Z', b"I");
+                }
+                Frontend::Query(query) => {
+                    if query == "deallocate all" {
+                        prepared.clear();
+                        put_command_complete(&mut out, "DEALLOCATE ALL");
+                    } else if query.starts_with("alter table") {
+                        schema_version += 1;
+                        put_command_complete(&mut out, "ALTER TABLE");
+                    } else {
+                        panic!("unexpected query {query}");
+                    }
+                    put_message(&mut out, b'Z', b"I");
+                }
+            }
+            if matches!(
+                message,
+                Frontend::Flush | Frontend::Sync | Frontend::Query(_)
+            ) {
+                stream.write_all_buf(&mut out).await.unwrap();
+            }
+            log.lock().unwrap().push(message);
+        }
+    }
+
+    fn connect(statement_cache_size: usize) -> (Client, Log) {
+        let (client_stream, backend_stream) = tokio::io::duplex(64 * 1024);
+        let log = Log::default();
+        tokio::spawn(mock_backend(backend_stream, log.clone()));
+
+        let (client_tx, conn_rx) = mpsc::unbounded_channel();
+        let (conn_tx, client_rx) = mpsc::channel(4);
+        let socket_config = SocketConfig {
+            host_addr: None,
+            host: Host::Tcp("localhost".to_owned()),
+            port: 5432,
+            connect_timeout: None,
+        };
+        let mut client = Client::new(client_tx, client_rx, socket_config, SslMode::Disable, 0, 0);
+        client.set_statement_cache_size(statement_cache_size);
+
+        let stream = Framed::new(
+            MaybeTlsStream::<_, NoTlsStream>::Raw(client_stream),
+            PostgresCodec,
+        );
+        let connection = Connection::new(stream, VecDeque::new(), HashMap::new(), conn_tx, conn_rx);
+        tokio::spawn(connection);
+
+        (client, log)
+    }
+
+    /// Run the query and return whether it hit the statement cache and its command tag.
+    async fn query(client: &mut Client, query: &str) -> Result<(bool, String), Error> {
+        let mut rows = client
+            .query_raw_txt(query, std::iter::empty::<Option<&str>>())
+            .await?;
+        while let Some(row) = rows.next().await {
+            row?;
+        }
+        Ok((rows.statement_cache_hit.unwrap(), rows.command_tag.unwrap()))
+    }
+
+    fn take_log(log: &Log) -> Vec<Frontend> {
+        std::mem::take(&mut *log.lock().unwrap())
+    }
+
+    /// The name of the statement parsed by the logged messages.
+    fn parsed_name(messages: &[Frontend]) -> String {
+        messages
+            .iter()
+            .find_map(|message| match message {
+                Frontend::Parse { name, .. } => Some(name.clone()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    fn parse(name: &str, query: &str) -> Vec<Frontend> {
+        vec![
+            Frontend::Parse {
+                name: name.to_owned(),
+                query: query.to_owned(),
+            },
+            Frontend::Describe(name.to_owned()),
+            Frontend::Flush,
+        ]
+    }
+
+    fn execute(name: &str) -> Vec<Frontend> {
+        vec![
+            Frontend::Bind(name.to_owned()),
+            Frontend::Execute,
+            Frontend::Sync,
+        ]
+    }
+
+    #[tokio::test]
+    async fn unnamed_statement_without_cache() {
+        let (mut client, log) = connect(0);
+
+        for _ in 0..2 {
+            let mut rows = client
+                .query_raw_txt("select 1", std::iter::empty::<Option<&str>>())
+                .await
+                .unwrap();
+            while let Some(row) = rows.next().await {
+                row.unwrap();
+            }
+            assert_eq!(rows.statement_cache_hit, None);
+            assert_eq!(
+                take_log(&log),
+                [parse("", "select 1"), execute("")].concat()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn statement_cache_hit_and_eviction() {
+        let (mut client, log) = connect(2);
+
+        assert_eq!(
+            query(&mut client, "select 1").await.unwrap(),
+            (false, "SELECT 1".to_owned())
+        );
+        let messages = take_log(&log);
+        let s1 = parsed_name(&messages);
+        assert_ne!(s1, "");
+        assert_eq!(messages, [parse(&s1, "select 1"), execute(&s1)].concat());
+
+        // a cache hit binds the named statement without parsing it again
+        assert!(query(&mut client, "select 1").await.unwrap().0);
+        assert_eq!(take_log(&log), execute(&s1));
+
+        assert!(!query(&mut client, "select 2").await.unwrap().0);
+        let s2 = parsed_name(&take_log(&log));
+        assert!(query(&mut client, "select 1").await.unwrap().0);
+        assert_eq!(take_log(&log), execute(&s1));
+
+        // "select 2" is the least recently used, it's closed in the same round trip as the
+        // new statement is parsed.
+        assert!(!query(&mut client, "select 3").await.unwrap().0);
+        let messages = take_log(&log);
+        let s3 = parsed_name(&messages);
+        assert_eq!(
+            messages,
+            [
+                vec![Frontend::Close(s2)],
+                parse(&s3, "select 3"),
+                execute(&s3)
+            ]
+            .concat()
+        );
+
+        assert!(query(&mut client, "select 1").await.unwrap().0);
+        assert!(query(&mut client, "select 3").await.unwrap().0);
+        assert_eq!(take_log(&log), [execute(&s1), execute(&s3)].concat());
+    }
+
+    #[tokio::test]
+    async fn statement_cache_invalidation() {
+        let (mut client, log) = connect(4);
+
+        assert!(!query(&mut client, "select * from t").await.unwrap().0);
+        let s1 = parsed_name(&take_log(&log));
+        assert!(query(&mut client, "select * from t").await.unwrap().0);
+        take_log(&log);
+
+        // the result type of the statement changed
+        client
+            .batch_execute("alter table t add column b int")
+            .await
+            .unwrap();
+        let err = query(&mut client, "select * from t").await.unwrap_err();
+        assert_eq!(err.code(), Some(&SqlState::FEATURE_NOT_SUPPORTED));
+        take_log(&log);
+
+        // the statement is parsed again, closing the old one
+        assert!(!query(&mut client, "select * from t").await.unwrap().0);
+        let messages = take_log(&log);
+        let s2 = parsed_name(&messages);
+        assert_ne!(s1, s2);
+        assert_eq!(
+            messages,
+            [
+                vec![Frontend::Close(s1)],
+                parse(&s2, "select * from t"),
+                execute(&s2)
+            ]
+            .concat()
+        );
+
+        // statements deallocated behind the cache's back
+        client.batch_execute("deallocate all").await.unwrap();
+        let err = query(&mut client, "select * from t").await.unwrap_err();
+        assert_eq!(err.code(), Some(&SqlState::INVALID_SQL_STATEMENT_NAME));
+        take_log(&log);
+        assert!(!query(&mut client, "select * from t").await.unwrap().0);
+        let messages = take_log(&log);
+        let s3 = parsed_name(&messages);
+        assert_eq!(messages[0], Frontend::Close(s2));
+
+        // DISCARD ALL deallocates all statements, including the cached ones, so they are
+        // forgotten without closing them.
+        assert_eq!(
+            query(&mut client, "discard all").await.unwrap(),
+            (false, "DISCARD ALL".to_owned())
+        );
+        take_log(&log);
+        assert!(!query(&mut client, "select * from t").await.unwrap().0);
+        let messages = take_log(&log);
+        let s4 = parsed_name(&messages);
+        assert_ne!(s3, s4);
+        assert_eq!(
+            messages,
+            [parse(&s4, "select * from t"), execute(&s4)].concat()
+        );
+    }
+}
@@ -6,7 +6,7 @@
 use postgres_protocol2::message::backend::Field;
 
 struct StatementInner {
-    name: &'static str,
+    name: String,
     columns: Vec<Column>,
 }
 
@@ -17,12 +17,15 @@ struct StatementInner {
 pub struct Statement(Arc<StatementInner>);
 
 impl Statement {
-    pub(crate) fn new(name: &'static str, columns: Vec<Column>) -> Statement {
-        Statement(Arc::new(StatementInner { name, columns }))
+    pub(crate) fn new(name: impl Into<String>, columns: Vec<Column>) -> Statement {
+        Statement(Arc::new(StatementInner {
+            name: name.into(),
+            columns,
+        }))
     }
 
     pub(crate) fn name(&self) -> &str {
-        self.0.name
+        &self.0.name
     }
 
     /// Returns information about the columns returned when the statement is queried.
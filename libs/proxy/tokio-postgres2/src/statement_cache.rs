@@ -0,0 +1,151 @@
+//! A per-connection cache of prepared statements.
+//!
+//! Without the cache, [`Client::query_raw_txt`](crate::Client::query_raw_txt) parses every
+//! query as the unnamed statement, which costs an extra round trip. With the cache, queries
+//! are parsed once as named statements that later calls bind directly.
+
+use std::collections::HashMap;
+
+use crate::Statement;
+use crate::types::Oid;
+
+/// Prefix of the names of cached statements.
+const STATEMENT_PREFIX: &str = "neon_proxy_stmt_";
+
+struct CachedStatement {
+    /// Parameter types given when the statement was parsed.
+    types: Vec<Oid>,
+    statement: Statement,
+    last_used: u64,
+}
+
+/// Least recently used cache of prepared statements, keyed by query text and parameter types.
+#[derive(Default)]
+pub(crate) struct StatementCache {
+    capacity: usize,
+    statements: HashMap<String, CachedStatement>,
+    /// Names of statements removed from the cache that are still prepared on the server.
+    to_close: Vec<String>,
+    /// Incremented on every use, also used to name new statements.
+    clock: u64,
+}
+
+impl StatementCache {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.statements.len() > capacity {
+            self.evict();
+        }
+    }
+
+    pub(crate) fn get(&mut self, query: &str, types: &[Oid]) -> Option<Statement> {
+        self.clock += 1;
+        let cached = self
+            .statements
+            .get_mut(query)
+            .filter(|cached| cached.types == types)?;
+        cached.last_used = self.clock;
+        Some(cached.statement.clone())
+    }
+
+    /// A name no other statement in the cache has used.
+    pub(crate) fn next_name(&mut self) -> String {
+        self.clock += 1;
+        format!("{STATEMENT_PREFIX}{}", self.clock)
+    }
+
+    /// Evict statements until there is room for a new one, so that they can be closed in the
+    /// same round trip as the new one is parsed.
+    pub(crate) fn make_room(&mut self) {
+        while self.statements.len() >= self.capacity && self.evict() {}
+    }
+
+    pub(crate) fn insert(&mut self, query: &str, types: &[Oid], statement: Statement) {
+        if !self.statements.contains_key(query) {
+            self.make_room();
+        }
+
+        let cached = CachedStatement {
+            types: types.to_vec(),
+            statement,
+            last_used: self.clock,
+        };
+        if let Some(old) = self.statements.insert(query.to_owned(), cached) {
+            self.to_close.push(old.statement.name().to_owned());
+        }
+    }
+
+    /// Remove a statement the server no longer accepts.
+    pub(crate) fn remove(&mut self, query: &str) {
+        if let Some(cached) = self.statements.remove(query) {
+            self.to_close.push(cached.statement.name().to_owned());
+        }
+    }
+
+    /// Forget all statements, after the server deallocated them.
+    pub(crate) fn clear(&mut self) {
+        self.statements.clear();
+        self.to_close.clear();
+    }
+
+    /// Take the names of the statements to close on the server.
+    pub(crate) fn take_to_close(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.to_close)
+    }
+
+    fn evict(&mut self) -> bool {
+        let lru = self
+            .statements
+            .iter()
+            .min_by_key(|(_, cached)| cached.last_used)
+            .map(|(query, _)| query.clone());
+        match lru {
+            Some(query) => {
+                self.remove(&query);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prepare(cache: &mut StatementCache, query: &str) -> String {
+        let name = cache.next_name();
+        cache.insert(query, &[], Statement::new(name.clone(), vec![]));
+        name
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = StatementCache::default();
+        cache.set_capacity(2);
+
+        let s1 = prepare(&mut cache, "select 1");
+        let s2 = prepare(&mut cache, "select 2");
+        assert_ne!(s1, s2);
+        assert_eq!(cache.get("select 1", &[]).unwrap().name(), s1);
+        assert!(cache.get("select 1", &[25]).is_none());
+
+        prepare(&mut cache, "select 3");
+        assert!(cache.get("select 2", &[]).is_none());
+        assert!(cache.get("select 1", &[]).is_some());
+        assert_eq!(cache.take_to_close(), [s2]);
+
+        cache.remove("select 1");
+        assert!(cache.get("select 1", &[]).is_none());
+        assert_eq!(cache.take_to_close(), [s1]);
+
+        cache.set_capacity(0);
+        assert!(!cache.is_enabled());
+        assert!(cache.get("select 3", &[]).is_none());
+        assert_eq!(cache.take_to_close().len(), 1);
+    }
+}
@@ -119,6 +119,10 @@ pub struct Configuration {
     pub members: MemberSet,
     /// Some means it is a joint conf.
     pub new_members: Option<MemberSet>,
+    /// Members (of either set) which are witnesses: they vote and acknowledge
+    /// flush positions, but don't store WAL and thus can't serve it.
+    #[serde(default)]
+    pub witnesses: Vec<NodeId>,
 }
 
 impl Configuration {
@@ -128,6 +132,7 @@ pub fn empty() -> Self {
             generation: INVALID_GENERATION,
             members: MemberSet::empty(),
             new_members: None,
+            witnesses: Vec::new(),
         }
     }
 
@@ -136,6 +141,7 @@ pub fn new(members: MemberSet) -> Self {
             generation: INITIAL_GENERATION,
             members,
             new_members: None,
+            witnesses: Vec::new(),
         }
     }
 
@@ -143,6 +149,43 @@ pub fn new(members: MemberSet) -> Self {
     pub fn contains(&self, sk_id: NodeId) -> bool {
         self.members.contains(sk_id) || self.new_members.as_ref().is_some_and(|m| m.contains(sk_id))
     }
+
+    /// Is `sk_id` a witness in the configuration?
+    pub fn is_witness(&self, sk_id: NodeId) -> bool {
+        self.witnesses.contains(&sk_id)
+    }
+
+    /// Check that witnesses are members and that every majority of each
+    /// member set includes at least one safekeeper storing WAL, i.e. that
+    /// committed WAL is never acknowledged by witnesses only.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let witnesses: HashSet<NodeId> = HashSet::from_iter(self.witnesses.iter().copied());
+        if witnesses.len() != self.witnesses.len() {
+            bail!("duplicate witness id in {:?}", self.witnesses);
+        }
+        for w in &self.witnesses {
+            if !self.contains(*w) {
+                bail!(
+                    "witness {} is not a member of the configuration {}",
+                    w,
+                    self
+                );
+            }
+        }
+        for mset in std::iter::once(&self.members).chain(self.new_members.iter()) {
+            let n_witnesses = mset.m.iter().filter(|sk| self.is_witness(sk.id)).count();
+            let quorum = mset.m.len() / 2 + 1;
+            if !mset.m.is_empty() && n_witnesses >= quorum {
+                bail!(
+                    "member set {} has {} witnesses, which is not less than its quorum {}",
+                    mset,
+                    n_witnesses,
+                    quorum
+                );
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Display for Configuration {
@@ -156,7 +199,16 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                 .as_ref()
                 .map(ToString::to_string)
                 .unwrap_or(String::from("none"))
-        )
+        )?;
+        if !self.witnesses.is_empty() {
+            let ids = self
+                .witnesses
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>();
+            write!(f, ", witnesses=({})", ids.join(", "))?;
+        }
+        Ok(())
     }
 }
 
@@ -164,7 +216,15 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 mod tests {
     use utils::id::NodeId;
 
-    use super::{MemberSet, SafekeeperId};
+    use super::{Configuration, MemberSet, SafekeeperId};
+
+    fn sk(id: u64) -> SafekeeperId {
+        SafekeeperId {
+            id: NodeId(id),
+            host: format!("sk-{id}.org"),
+            pg_port: 5432,
+        }
+    }
 
     #[test]
     fn test_member_set() {
@@ -202,4 +262,31 @@ fn test_member_set() {
             r#"[{"id":42,"host":"lala.org","pg_port":5432},{"id":43,"host":"bubu.org","pg_port":5432}]"#
         );
     }
+
+    #[test]
+    fn test_witnesses() {
+        let members = MemberSet::new(vec![sk(1), sk(2), sk(3)]).unwrap();
+        let mut conf = Configuration::new(members);
+        conf.validate().unwrap();
+
+        // Configurations serialized before witnesses were introduced are
+        // still accepted.
+        let j = r#"{"generation":1,"members":[{"id":1,"host":"sk-1.org","pg_port":5432}],"new_members":null}"#;
+        let parsed: Configuration = serde_json::from_str(j).expect("failed to deserialize");
+        assert!(parsed.witnesses.is_empty());
+
+        conf.witnesses = vec![NodeId(3)];
+        conf.validate().unwrap();
+        assert!(conf.is_witness(NodeId(3)));
+        assert!(!conf.is_witness(NodeId(1)));
+
+        // Majority {2, 3} would have only one WAL copy, but majority of
+        // witnesses only must be impossible.
+        conf.witnesses = vec![NodeId(2), NodeId(3)];
+        conf.validate()
+            .expect_err("witnesses must not form a quorum");
+
+        conf.witnesses = vec![NodeId(4)];
+        conf.validate().expect_err("witness must be a member");
+    }
 }
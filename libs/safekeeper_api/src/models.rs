@@ -207,6 +207,10 @@ pub struct TimelineStatus {
     pub peers: Vec<PeerInfo>,
     pub walsenders: Vec<WalSenderState>,
     pub walreceivers: Vec<WalReceiverState>,
+    /// True if the safekeeper is a witness of the timeline and doesn't store
+    /// WAL.
+    #[serde(default)]
+    pub witness: bool,
 }
 
 /// Request to switch membership configuration.
@@ -290,6 +294,104 @@ pub struct TimelineTermBumpResponse {
     pub current_term: u64,
 }
 
+/// Enables or disables export of timeline WAL into the standard PostgreSQL
+/// archive layout on the configured WAL archive storage.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TimelineWalArchiveRequest {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TimelineWalArchiveResponse {
+    pub enabled: bool,
+    /// End of the last complete segment uploaded to the archive by this
+    /// safekeeper.
+    pub archived_lsn: Lsn,
+}
+
+/// Where WAL integrity verification reads WAL segments from.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WalVerifySource {
+    /// Segments on the safekeeper's local disk.
+    Local,
+    /// Segments offloaded to remote storage.
+    Remote,
+}
+
+impl std::str::FromStr for WalVerifySource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(WalVerifySource::Local),
+            "remote" => Ok(WalVerifySource::Remote),
+            _ => anyhow::bail!("unknown WAL source {s}, expected local or remote"),
+        }
+    }
+}
+
+/// First problem found by WAL integrity verification.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct WalVerifyError {
+    pub lsn: Lsn,
+    pub msg: String,
+}
+
+/// Result of decoding and checking every WAL record of a timeline in a range.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TimelineWalVerifyResponse {
+    pub source: WalVerifySource,
+    pub commit_lsn: Lsn,
+    /// Known end of WAL, if any: flush_lsn for local WAL of a running
+    /// safekeeper, backup_lsn for remote WAL. Offline verification of local
+    /// WAL reports where valid WAL ends.
+    pub flush_lsn: Lsn,
+    /// Start of the first verified record.
+    pub start_lsn: Lsn,
+    /// End of the last valid record.
+    pub end_lsn: Lsn,
+    /// Number of valid records.
+    pub records: u64,
+    /// None if WAL in the range is valid.
+    pub error: Option<WalVerifyError>,
+}
+
+/// Retained WAL quota state of a timeline on a safekeeper.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum WalQuotaState {
+    /// Retained WAL is below the backpressure threshold of both quotas.
+    Ok,
+    /// Retained WAL is close to a quota, the walproposer is asked to throttle.
+    Backpressure,
+    /// Timeline retains more WAL than the per-timeline quota, appends are rejected.
+    TimelineExceeded,
+    /// Tenant retains more WAL than the per-tenant quota, appends are rejected.
+    TenantExceeded,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TimelineWalQuotaStatus {
+    pub timeline_id: TimelineId,
+    pub retained_wal_bytes: u64,
+    pub state: WalQuotaState,
+}
+
+/// Retained WAL quotas of a tenant on a safekeeper and their usage.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TenantWalQuotaStatus {
+    /// Per-timeline quota, None if disabled.
+    pub timeline_quota_bytes: Option<u64>,
+    /// Per-tenant quota, None if disabled.
+    pub tenant_quota_bytes: Option<u64>,
+    /// Fraction of a quota above which the walproposer is asked to throttle.
+    pub backpressure_ratio: f64,
+    /// WAL retained by all timelines of the tenant on this safekeeper.
+    pub retained_wal_bytes: u64,
+    pub timelines: Vec<TimelineWalQuotaStatus>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SafekeeperUtilization {
     pub timeline_count: u64,
@@ -302,6 +404,18 @@ pub struct PullTimelineRequest {
     pub timeline_id: TimelineId,
     pub http_hosts: Vec<String>,
     pub ignore_tombstone: Option<bool>,
+    /// If the timeline already exists locally, catch it up from the donor
+    /// transferring only the WAL it misses instead of doing nothing.
+    pub incremental: Option<bool>,
+}
+
+/// Start of an incremental or resumed timeline snapshot: the receiver already
+/// has WAL up to `lsn`, and the last record before it was written in `term`.
+/// Donor refuses the snapshot if its history differs.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SnapshotStart {
+    pub lsn: Lsn,
+    pub term: Term,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -32,6 +32,15 @@ pub struct PageserverFeedback {
     pub replytime: SystemTime,
     /// Used to track feedbacks from different shards. Always zero for unsharded tenants.
     pub shard_number: u32,
+    /// True if the pageserver is holding back ingest of this timeline's WAL because the
+    /// tenant exceeded its ingest limits. Lets consumers tell deliberate throttling apart
+    /// from a slow pageserver.
+    #[serde(default)]
+    pub ingest_throttled: bool,
+    /// Set by the safekeeper, not the pageserver: true if it retains WAL close
+    /// to its quota and the compute should throttle WAL generation.
+    #[serde(default)]
+    pub sk_wal_backpressure: bool,
 }
 
 impl PageserverFeedback {
@@ -43,6 +52,8 @@ pub fn empty() -> PageserverFeedback {
             disk_consistent_lsn: Lsn::INVALID,
             replytime: *PG_EPOCH,
             shard_number: 0,
+            ingest_throttled: false,
+            sk_wal_backpressure: false,
         }
     }
 
@@ -101,6 +112,20 @@ pub fn serialize(&self, buf: &mut BytesMut) {
             buf.put_u32(self.shard_number);
         }
 
+        if self.ingest_throttled {
+            nkeys += 1;
+            buf.put_slice(b"ps_ingest_throttled\0");
+            buf.put_i32(4);
+            buf.put_u32(1);
+        }
+
+        if self.sk_wal_backpressure {
+            nkeys += 1;
+            buf.put_slice(b"sk_wal_backpressure\0");
+            buf.put_i32(4);
+            buf.put_u32(1);
+        }
+
         buf[buf_ptr] = nkeys;
     }
 
@@ -147,6 +172,16 @@ pub fn parse(mut buf: Bytes) -> PageserverFeedback {
                     assert_eq!(len, 4);
                     rf.shard_number = buf.get_u32();
                 }
+                b"ps_ingest_throttled" => {
+                    let len = buf.get_i32();
+                    assert_eq!(len, 4);
+                    rf.ingest_throttled = buf.get_u32() != 0;
+                }
+                b"sk_wal_backpressure" => {
+                    let len = buf.get_i32();
+                    assert_eq!(len, 4);
+                    rf.sk_wal_backpressure = buf.get_u32() != 0;
+                }
                 _ => {
                     let len = buf.get_i32();
                     warn!(
@@ -206,6 +241,30 @@ fn test_replication_feedback_serialization() {
         assert_eq!(rf, rf_parsed);
     }
 
+    #[test]
+    fn test_replication_feedback_ingest_throttled() {
+        let mut rf = PageserverFeedback::empty();
+        rf.shard_number = 3;
+        rf.ingest_throttled = true;
+        let mut data = BytesMut::new();
+        rf.serialize(&mut data);
+
+        let rf_parsed = PageserverFeedback::parse(data.freeze());
+        assert_eq!(rf, rf_parsed);
+    }
+
+    #[test]
+    fn test_replication_feedback_sk_wal_backpressure() {
+        let mut rf = PageserverFeedback::empty();
+        rf.last_received_lsn = Lsn(42);
+        rf.sk_wal_backpressure = true;
+        let mut data = BytesMut::new();
+        rf.serialize(&mut data);
+
+        let rf_parsed = PageserverFeedback::parse(data.freeze());
+        assert_eq!(rf, rf_parsed);
+    }
+
     #[test]
     fn test_replication_feedback_unknown_key() {
         let mut rf = PageserverFeedback::empty();
@@ -32,6 +32,9 @@ pub enum PostgresClientProtocol {
         format: InterpretedFormat,
         compression: Option<Compression>,
     },
+    /// Row-level change events decoded from heap records, for change data
+    /// capture subscribers. See `wal_decoder::change_events`.
+    ChangeEvents { compression: Option<Compression> },
 }
 
 pub struct ConnectionConfigArgs<'a> {
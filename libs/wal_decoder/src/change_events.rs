@@ -0,0 +1,751 @@
+//! Row-level change events decoded from heap and transaction WAL records.
+//!
+//! This is a lightweight server-side counterpart of Postgres logical decoding
+//! for consumers which want change data capture without a replication slot on
+//! the compute: safekeepers decode WAL into [`ChangeEvent`]s and stream them
+//! over the change events protocol, so the stream keeps flowing while the
+//! compute is suspended.
+//!
+//! Unlike logical decoding, events are not reassembled into transactions and
+//! carry raw tuples, not values: consumers buffer changes by xid until a
+//! commit or abort event (which lists subtransactions of the transaction) and
+//! decode tuples with the table's row type. Complete tuples are logged only
+//! with `wal_level=logical`; otherwise a tuple may be replaced by a full-page
+//! image or compressed against the old tuple version, and the event then has
+//! no tuple.
+//!
+//! WAL identifies relations by relfilenode, so the decoder resolves relation
+//! OIDs with [`RelationOids`], built from the pg_class rows it sees in WAL.
+
+use std::collections::HashMap;
+
+use bytes::{Buf, Bytes, BytesMut};
+use postgres_ffi::walrecord::*;
+use postgres_ffi::{
+    BLCKSZ, Oid, PgMajorVersion, TransactionId, bkpimage_is_compressed, pg_constants,
+};
+use postgres_ffi_types::TimestampTz;
+use postgres_ffi_types::forknum::MAIN_FORKNUM;
+use serde::{Deserialize, Serialize};
+use utils::bin_ser::BeSer;
+use utils::lsn::Lsn;
+use utils::postgres_client::Compression;
+
+use crate::wire_format::{FromWireFormatError, ToWireFormatError, compress, decompress};
+
+/// Size of xl_heap_header, including the command id Neon adds to it.
+const SIZE_OF_HEAP_HEADER: usize = 9;
+/// Size of xl_multi_insert_tuple.
+const SIZE_OF_MULTI_INSERT_TUPLE: usize = 7;
+/// Offset of the null bitmap in a heap tuple header, where logged tuple data
+/// starts.
+const SIZE_OF_HEAP_TUPLE_HEADER: usize = 23;
+/// Size of ItemIdData.
+const SIZE_OF_ITEM_ID: usize = 4;
+const LP_NORMAL: u32 = 1;
+
+/// OID and relfilenode of pg_class, unless pg_class itself was rewritten.
+const RELATION_RELATION_ID: Oid = 1259;
+/// Offsets of `oid` and `relfilenode` in pg_class rows. All attributes up to
+/// `relfilenode` are fixed-size and not null, so the offsets are the same in
+/// every row and all supported Postgres versions.
+const PG_CLASS_OID_OFFSET: usize = 0;
+const PG_CLASS_RELFILENODE_OFFSET: usize = 88;
+
+/// A batch of change events sent to a subscriber.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChangeEvents {
+    pub events: Vec<ChangeEvent>,
+    /// Start LSN of the next record after the batch. Subscribers resume
+    /// streaming from here once they have processed the batch.
+    pub next_record_lsn: Lsn,
+}
+
+/// A change decoded from one WAL record.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChangeEvent {
+    /// End LSN of the record.
+    pub lsn: Lsn,
+    /// Transaction or subtransaction which made the change.
+    pub xid: TransactionId,
+    pub change: Change,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Change {
+    Insert {
+        rel: ChangedRel,
+        tuple: Option<RawTuple>,
+    },
+    Update {
+        rel: ChangedRel,
+        /// Old tuple or its replica identity key, if logged.
+        old_tuple: Option<RawTuple>,
+        new_tuple: Option<RawTuple>,
+    },
+    Delete {
+        rel: ChangedRel,
+        /// Old tuple or its replica identity key, if logged.
+        old_tuple: Option<RawTuple>,
+    },
+    Commit {
+        db_id: Oid,
+        subxacts: Vec<TransactionId>,
+        commit_time: TimestampTz,
+    },
+    Abort {
+        subxacts: Vec<TransactionId>,
+    },
+}
+
+/// Relation the change was made in.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChangedRel {
+    /// Relation OID, see [`RelationOids`].
+    pub reloid: Oid,
+    pub spcnode: Oid,
+    pub dbnode: Oid,
+    pub relnode: Oid,
+}
+
+/// Relation OIDs of relfilenodes, learned from pg_class rows in WAL.
+///
+/// A relation gets a relfilenode equal to its OID on creation and a new one
+/// when it is rewritten (TRUNCATE, VACUUM FULL, CLUSTER, some ALTER TABLE
+/// forms), which updates its pg_class row. The row's OID is taken from the
+/// logged tuple or the full-page image of the block, or, if the update only
+/// logged the changed suffix of the tuple, from the previous row version at
+/// the updated item pointer. Relfilenodes not seen in pg_class rows are
+/// assumed to equal the relation OID, which is wrong for relations rewritten
+/// before decoding started, e.g. before the subscriber reconnected.
+#[derive(Default)]
+pub struct RelationOids {
+    /// Relation OID by database and relfilenode.
+    by_relfilenode: HashMap<(Oid, Oid), Oid>,
+    /// Relation OID by database, block and offset of its pg_class row.
+    by_row: HashMap<(Oid, u32, u16), Oid>,
+}
+
+/// Heap tuple as logged in WAL: header fields and the part of the tuple
+/// starting at the null bitmap.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RawTuple {
+    pub infomask2: u16,
+    pub infomask: u16,
+    pub hoff: u8,
+    pub data: Bytes,
+}
+
+impl ChangeEvents {
+    pub async fn to_wire(
+        self,
+        compression: Option<Compression>,
+    ) -> Result<Bytes, ToWireFormatError> {
+        let buf = Bytes::from(self.ser()?);
+        Ok(compress(buf, compression).await?)
+    }
+
+    pub async fn from_wire(
+        buf: &Bytes,
+        compression: Option<Compression>,
+    ) -> Result<Self, FromWireFormatError> {
+        let buf = decompress(buf, compression).await?;
+        ChangeEvents::des(&buf).map_err(FromWireFormatError::Bincode)
+    }
+}
+
+impl ChangeEvent {
+    /// Decode change events from a WAL record ending at `lsn` and append them
+    /// to `events`. Records which don't change rows produce no events.
+    /// `relations` must be the same for all records of the stream.
+    pub fn decode(
+        record: Bytes,
+        lsn: Lsn,
+        pg_version: PgMajorVersion,
+        relations: &mut RelationOids,
+        events: &mut Vec<ChangeEvent>,
+    ) -> anyhow::Result<()> {
+        let mut decoded = DecodedWALRecord::default();
+        decode_wal_record(record, &mut decoded, pg_version)?;
+        let mut buf = decoded.record.clone();
+        buf.advance(decoded.main_data_offset);
+        let info = decoded.xl_info & pg_constants::XLOG_HEAP_OPMASK;
+
+        let push = |events: &mut Vec<ChangeEvent>, change| {
+            events.push(ChangeEvent {
+                lsn,
+                xid: decoded.xl_xid,
+                change,
+            })
+        };
+
+        match (decoded.xl_rmid, pg_version) {
+            (pg_constants::RM_XACT_ID, _) => {
+                let info = decoded.xl_info & pg_constants::XLOG_XACT_OPMASK;
+                let commit = info == pg_constants::XLOG_XACT_COMMIT
+                    || info == pg_constants::XLOG_XACT_COMMIT_PREPARED;
+                let abort = info == pg_constants::XLOG_XACT_ABORT
+                    || info == pg_constants::XLOG_XACT_ABORT_PREPARED;
+                if commit || abort {
+                    let parsed =
+                        XlXactParsedRecord::decode(&mut buf, decoded.xl_xid, decoded.xl_info);
+                    let change = if commit {
+                        Change::Commit {
+                            db_id: parsed.db_id,
+                            subxacts: parsed.subxacts,
+                            commit_time: parsed.xact_time,
+                        }
+                    } else {
+                        Change::Abort {
+                            subxacts: parsed.subxacts,
+                        }
+                    };
+                    // For prepared transactions the record has the xid of the
+                    // transaction it finishes.
+                    events.push(ChangeEvent {
+                        lsn,
+                        xid: parsed.xid,
+                        change,
+                    });
+                }
+            }
+            (pg_constants::RM_HEAP_ID, PgMajorVersion::PG14 | PgMajorVersion::PG15) => {
+                if info == pg_constants::XLOG_HEAP_INSERT {
+                    let xlrec = v14::XlHeapInsert::decode(&mut buf);
+                    push(
+                        events,
+                        insert(&decoded, xlrec.offnum, pg_version, relations),
+                    );
+                } else if info == pg_constants::XLOG_HEAP_DELETE {
+                    let xlrec = v14::XlHeapDelete::decode(&mut buf);
+                    push(
+                        events,
+                        delete(&decoded, xlrec.offnum, xlrec.flags, buf, relations),
+                    );
+                } else if info == pg_constants::XLOG_HEAP_UPDATE
+                    || info == pg_constants::XLOG_HEAP_HOT_UPDATE
+                {
+                    let xlrec = v14::XlHeapUpdate::decode(&mut buf);
+                    let update = HeapUpdate {
+                        flags: xlrec.flags,
+                        old_offnum: xlrec.old_offnum,
+                        new_offnum: xlrec.new_offnum,
+                    };
+                    push(events, update.decode(&decoded, buf, pg_version, relations));
+                }
+            }
+            (pg_constants::RM_HEAP2_ID, PgMajorVersion::PG14 | PgMajorVersion::PG15) => {
+                if info == pg_constants::XLOG_HEAP2_MULTI_INSERT {
+                    let xlrec = v14::XlHeapMultiInsert::decode(&mut buf);
+                    for change in multi_insert_tuples(&decoded, xlrec.ntuples, relations) {
+                        push(events, change);
+                    }
+                }
+            }
+            (pg_constants::RM_NEON_ID, PgMajorVersion::PG16 | PgMajorVersion::PG17) => match info {
+                pg_constants::XLOG_NEON_HEAP_INSERT => {
+                    let xlrec = v17::rm_neon::XlNeonHeapInsert::decode(&mut buf);
+                    push(
+                        events,
+                        insert(&decoded, xlrec.offnum, pg_version, relations),
+                    );
+                }
+                pg_constants::XLOG_NEON_HEAP_DELETE => {
+                    let xlrec = v17::rm_neon::XlNeonHeapDelete::decode(&mut buf);
+                    push(
+                        events,
+                        delete(&decoded, xlrec.offnum, xlrec.flags, buf, relations),
+                    );
+                }
+                pg_constants::XLOG_NEON_HEAP_UPDATE | pg_constants::XLOG_NEON_HEAP_HOT_UPDATE => {
+                    let xlrec = v17::rm_neon::XlNeonHeapUpdate::decode(&mut buf);
+                    let update = HeapUpdate {
+                        flags: xlrec.flags,
+                        old_offnum: xlrec.old_offnum,
+                        new_offnum: xlrec.new_offnum,
+                    };
+                    push(events, update.decode(&decoded, buf, pg_version, relations));
+                }
+                pg_constants::XLOG_NEON_HEAP_MULTI_INSERT => {
+                    let xlrec = v17::rm_neon::XlNeonHeapMultiInsert::decode(&mut buf);
+                    for change in multi_insert_tuples(&decoded, xlrec.ntuples, relations) {
+                        push(events, change);
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl RelationOids {
+    fn changed_rel(&self, blk: &DecodedBkpBlock) -> ChangedRel {
+        let reloid = self
+            .by_relfilenode
+            .get(&(blk.rnode_dbnode, blk.rnode_relnode))
+            .copied()
+            .unwrap_or(blk.rnode_relnode);
+        ChangedRel {
+            reloid,
+            spcnode: blk.rnode_spcnode,
+            dbnode: blk.rnode_dbnode,
+            relnode: blk.rnode_relnode,
+        }
+    }
+
+    /// Remember a new version of a pg_class row, inserted at `offnum` of
+    /// `blk` or updated from `old_row`. `attrs` are the row's attributes
+    /// starting at `attrs_offset`, if logged.
+    fn new_row(
+        &mut self,
+        blk: &DecodedBkpBlock,
+        offnum: u16,
+        old_row: Option<(u32, u16)>,
+        attrs: Option<(Bytes, usize)>,
+    ) {
+        let dbnode = blk.rnode_dbnode;
+        let old_oid =
+            old_row.and_then(|(blkno, offnum)| self.by_row.remove(&(dbnode, blkno, offnum)));
+        let attr = |offset: usize| {
+            let (attrs, attrs_offset) = attrs.as_ref()?;
+            let pos = offset.checked_sub(*attrs_offset)?;
+            let bytes = attrs.get(pos..pos + 4)?;
+            Some(Oid::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        let Some(oid) = attr(PG_CLASS_OID_OFFSET).or(old_oid) else {
+            return;
+        };
+        self.by_row.insert((dbnode, blk.blkno, offnum), oid);
+        // Mapped relations, like pg_class itself, have relfilenode 0.
+        if let Some(relfilenode) = attr(PG_CLASS_RELFILENODE_OFFSET)
+            && relfilenode != 0
+        {
+            self.by_relfilenode.insert((dbnode, relfilenode), oid);
+        }
+    }
+
+    fn deleted_row(&mut self, blk: &DecodedBkpBlock, offnum: u16) {
+        self.by_row.remove(&(blk.rnode_dbnode, blk.blkno, offnum));
+    }
+}
+
+fn is_pg_class(blk: &DecodedBkpBlock) -> bool {
+    blk.rnode_relnode == RELATION_RELATION_ID && blk.forknum == MAIN_FORKNUM
+}
+
+/// Parse xl_heap_header followed by tuple data.
+fn parse_tuple(mut buf: Bytes) -> Option<RawTuple> {
+    if buf.remaining() < SIZE_OF_HEAP_HEADER {
+        return None;
+    }
+    let infomask2 = buf.get_u16_le();
+    let infomask = buf.get_u16_le();
+    let _cid = buf.get_u32_le();
+    let hoff = buf.get_u8();
+    Some(RawTuple {
+        infomask2,
+        infomask,
+        hoff,
+        data: buf,
+    })
+}
+
+/// Attributes of a logged tuple, following the null bitmap and padding.
+fn tuple_attrs(tuple: &RawTuple) -> Option<Bytes> {
+    let start = (tuple.hoff as usize).checked_sub(SIZE_OF_HEAP_TUPLE_HEADER)?;
+    (start <= tuple.data.len()).then(|| tuple.data.slice(start..))
+}
+
+/// Attributes of the tuple at `offnum` in the full-page image of `blk`, if
+/// the record has an uncompressed one.
+fn image_tuple_attrs(
+    decoded: &DecodedWALRecord,
+    blk: &DecodedBkpBlock,
+    offnum: u16,
+    pg_version: PgMajorVersion,
+) -> Option<Bytes> {
+    if !blk.has_image || bkpimage_is_compressed(blk.bimg_info, pg_version) {
+        return None;
+    }
+    let image_start = blk.bimg_offset as usize;
+    let image = decoded
+        .record
+        .get(image_start..image_start + blk.bimg_len as usize)?;
+    let hole_offset = (blk.hole_offset as usize).min(image.len());
+    let mut page = BytesMut::with_capacity(BLCKSZ as usize);
+    page.extend_from_slice(&image[..hole_offset]);
+    page.resize(hole_offset + blk.hole_length as usize, 0);
+    page.extend_from_slice(&image[hole_offset..]);
+
+    let item_pos = pg_constants::SIZE_OF_PAGE_HEADER as usize
+        + (offnum as usize).checked_sub(1)? * SIZE_OF_ITEM_ID;
+    let item_id = u32::from_le_bytes(
+        page.get(item_pos..item_pos + SIZE_OF_ITEM_ID)?
+            .try_into()
+            .unwrap(),
+    );
+    let lp_off = (item_id & 0x7fff) as usize;
+    let lp_flags = (item_id >> 15) & 0x03;
+    let lp_len = (item_id >> 17) as usize;
+    if lp_flags != LP_NORMAL || lp_len < SIZE_OF_HEAP_TUPLE_HEADER || lp_off + lp_len > page.len() {
+        return None;
+    }
+    let hoff = page[lp_off + SIZE_OF_HEAP_TUPLE_HEADER - 1] as usize;
+    (hoff <= lp_len).then(|| page.freeze().slice(lp_off + hoff..lp_off + lp_len))
+}
+
+fn insert(
+    decoded: &DecodedWALRecord,
+    offnum: u16,
+    pg_version: PgMajorVersion,
+    relations: &mut RelationOids,
+) -> Change {
+    let blk = &decoded.blocks[0];
+    let rel = relations.changed_rel(blk);
+    let tuple = decoded.block_data(blk).and_then(parse_tuple);
+    if is_pg_class(blk) {
+        let attrs = match &tuple {
+            Some(tuple) => tuple_attrs(tuple),
+            None => image_tuple_attrs(decoded, blk, offnum, pg_version),
+        };
+        relations.new_row(blk, offnum, None, attrs.map(|attrs| (attrs, 0)));
+    }
+    Change::Insert { rel, tuple }
+}
+
+/// Decode a delete record with main record struct already consumed from
+/// `buf`. The old tuple, if logged, follows it.
+fn delete(
+    decoded: &DecodedWALRecord,
+    offnum: u16,
+    flags: u8,
+    buf: Bytes,
+    relations: &mut RelationOids,
+) -> Change {
+    let blk = &decoded.blocks[0];
+    let rel = relations.changed_rel(blk);
+    if is_pg_class(blk) {
+        relations.deleted_row(blk, offnum);
+    }
+    let old_logged =
+        pg_constants::XLH_DELETE_CONTAINS_OLD_TUPLE | pg_constants::XLH_DELETE_CONTAINS_OLD_KEY;
+    let old_tuple = if flags & old_logged != 0 {
+        parse_tuple(buf)
+    } else {
+        None
+    };
+    Change::Delete { rel, old_tuple }
+}
+
+/// Fields of xl_heap_update needed to decode it, same in all versions.
+struct HeapUpdate {
+    flags: u8,
+    old_offnum: u16,
+    new_offnum: u16,
+}
+
+impl HeapUpdate {
+    /// Decode an update record with main record struct already consumed from
+    /// `buf`. The new tuple is in the data of the first block, prefixed by
+    /// the lengths of prefix and suffix shared with the old tuple, if any.
+    /// The old tuple is on the second block, if it differs from the first
+    /// one, and is logged after the main record struct.
+    fn decode(
+        &self,
+        decoded: &DecodedWALRecord,
+        buf: Bytes,
+        pg_version: PgMajorVersion,
+        relations: &mut RelationOids,
+    ) -> Change {
+        let blk = &decoded.blocks[0];
+        let rel = relations.changed_rel(blk);
+        let new = decoded
+            .block_data(blk)
+            .and_then(|data| self.new_tuple(data));
+
+        if is_pg_class(blk) {
+            let old_blkno = decoded
+                .blocks
+                .get(1)
+                .map_or(blk.blkno, |old_blk| old_blk.blkno);
+            let attrs = match &new {
+                Some((tuple, prefix_len)) => tuple_attrs(tuple).map(|attrs| (attrs, *prefix_len)),
+                None => image_tuple_attrs(decoded, blk, self.new_offnum, pg_version)
+                    .map(|attrs| (attrs, 0)),
+            };
+            relations.new_row(
+                blk,
+                self.new_offnum,
+                Some((old_blkno, self.old_offnum)),
+                attrs,
+            );
+        }
+
+        let shared_with_old =
+            pg_constants::XLH_UPDATE_PREFIX_FROM_OLD | pg_constants::XLH_UPDATE_SUFFIX_FROM_OLD;
+        let new_tuple = if self.flags & shared_with_old == 0 {
+            new.map(|(tuple, _)| tuple)
+        } else {
+            None
+        };
+        let old_logged =
+            pg_constants::XLH_UPDATE_CONTAINS_OLD_TUPLE | pg_constants::XLH_UPDATE_CONTAINS_OLD_KEY;
+        let old_tuple = if self.flags & old_logged != 0 {
+            parse_tuple(buf)
+        } else {
+            None
+        };
+        Change::Update {
+            rel,
+            old_tuple,
+            new_tuple,
+        }
+    }
+
+    /// Parse the new tuple and the length of the attribute prefix it shares
+    /// with the old tuple, which is not logged.
+    fn new_tuple(&self, mut data: Bytes) -> Option<(RawTuple, usize)> {
+        let mut prefix_len = 0;
+        if self.flags & pg_constants::XLH_UPDATE_PREFIX_FROM_OLD != 0 {
+            if data.remaining() < 2 {
+                return None;
+            }
+            prefix_len = data.get_u16_le() as usize;
+        }
+        if self.flags & pg_constants::XLH_UPDATE_SUFFIX_FROM_OLD != 0 {
+            if data.remaining() < 2 {
+                return None;
+            }
+            data.advance(2);
+        }
+        parse_tuple(data).map(|tuple| (tuple, prefix_len))
+    }
+}
+
+/// Decode tuples of a multi-insert record. Each tuple in the data of the
+/// first block is a short-aligned xl_multi_insert_tuple followed by tuple
+/// data.
+fn multi_insert_tuples(
+    decoded: &DecodedWALRecord,
+    ntuples: u16,
+    relations: &RelationOids,
+) -> Vec<Change> {
+    let rel = relations.changed_rel(&decoded.blocks[0]);
+    let Some(data) = decoded.block_data(&decoded.blocks[0]) else {
+        // Catalog or unlogged tables: the page image is all there is.
+        return (0..ntuples)
+            .map(|_| Change::Insert { rel, tuple: None })
+            .collect();
+    };
+    parse_multi_insert_tuples(data, ntuples)
+        .into_iter()
+        .map(|tuple| Change::Insert { rel, tuple })
+        .collect()
+}
+
+fn parse_multi_insert_tuples(data: Bytes, ntuples: u16) -> Vec<Option<RawTuple>> {
+    let mut tuples = Vec::with_capacity(ntuples as usize);
+    let mut pos = 0;
+    for _ in 0..ntuples {
+        pos += pos % 2;
+        if pos + SIZE_OF_MULTI_INSERT_TUPLE > data.len() {
+            tuples.push(None);
+            continue;
+        }
+        let mut hdr = data.slice(pos..pos + SIZE_OF_MULTI_INSERT_TUPLE);
+        let datalen = hdr.get_u16_le() as usize;
+        let infomask2 = hdr.get_u16_le();
+        let infomask = hdr.get_u16_le();
+        let hoff = hdr.get_u8();
+        pos += SIZE_OF_MULTI_INSERT_TUPLE;
+        let end = (pos + datalen).min(data.len());
+        tuples.push(Some(RawTuple {
+            infomask2,
+            infomask,
+            hoff,
+            data: data.slice(pos..end),
+        }));
+        pos = end;
+    }
+    tuples
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bytes::BufMut;
+    use postgres_ffi::WAL_SEGMENT_SIZE;
+    use postgres_ffi::waldecoder::WalStreamDecoder;
+
+    use super::*;
+
+    /// Decode WAL written by pgbench initialization on Postgres 15, shared
+    /// with pageserver tests. Its four tables are created in separate
+    /// transactions, then truncated, which gives them new relfilenodes, and
+    /// filled in the transaction which did the truncation. pg_class rows of
+    /// pgbench_history are logged as full-page image only, updates of all
+    /// rows share the prefix up to relfilenode with the old row version.
+    async fn decode_pgbench_wal() -> (Vec<ChangeEvent>, RelationOids) {
+        use async_compression::tokio::bufread::ZstdDecoder;
+        use tokio::io::AsyncReadExt;
+
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../pageserver/test_data/sk_wal_segment_from_pgbench/000000010000000000000001.zst"
+        );
+        let compressed = std::fs::read(path).unwrap();
+        let mut wal = Vec::new();
+        ZstdDecoder::new(&compressed[..])
+            .read_to_end(&mut wal)
+            .await
+            .unwrap();
+
+        let startpoint = Lsn::from_hex("14AEC08").unwrap();
+        let mut decoder = WalStreamDecoder::new(startpoint, PgMajorVersion::PG15);
+        decoder.feed_bytes(&wal[startpoint.segment_offset(WAL_SEGMENT_SIZE)..]);
+        let mut relations = RelationOids::default();
+        let mut events = Vec::new();
+        while let Some((lsn, recdata)) = decoder.poll_decode().unwrap() {
+            ChangeEvent::decode(
+                recdata,
+                lsn,
+                PgMajorVersion::PG15,
+                &mut relations,
+                &mut events,
+            )
+            .unwrap();
+        }
+        (events, relations)
+    }
+
+    #[tokio::test]
+    async fn test_decode_pgbench_wal() {
+        const DB_ID: Oid = 5;
+        const PGBENCH_HISTORY: Oid = 16384;
+        const PGBENCH_TELLERS: Oid = 16387;
+        const PGBENCH_ACCOUNTS: Oid = 16390;
+        const PGBENCH_BRANCHES: Oid = 16393;
+
+        let (events, relations) = decode_pgbench_wal().await;
+        // 72638 inserts, 53 tuples of catalog multi-inserts, 4 updates of
+        // pg_class and 4 commits.
+        assert_eq!(events.len(), 72699);
+
+        let commits: Vec<_> = events
+            .iter()
+            .filter_map(|event| match &event.change {
+                Change::Commit {
+                    db_id, subxacts, ..
+                } => {
+                    assert_eq!(*db_id, DB_ID);
+                    assert!(subxacts.is_empty());
+                    Some(event.xid)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(commits, vec![1024, 1025, 1026, 1027]);
+
+        let mut inserts = HashMap::new();
+        for event in &events {
+            if let Change::Insert { rel, .. } = &event.change {
+                *inserts.entry((rel.reloid, rel.relnode)).or_insert(0) += 1;
+            }
+        }
+        // Rows are inserted after TRUNCATE, into the new relfilenodes.
+        assert_eq!(inserts[&(PGBENCH_ACCOUNTS, 16396)], 72516);
+        assert_eq!(inserts[&(PGBENCH_BRANCHES, 16397)], 10);
+        assert_eq!(inserts[&(PGBENCH_TELLERS, 16399)], 100);
+        assert_eq!(inserts[&(RELATION_RELATION_ID, RELATION_RELATION_ID)], 4);
+
+        let pg_class_updates = events
+            .iter()
+            .filter(|event| {
+                matches!(
+                    &event.change,
+                    Change::Update {
+                        rel,
+                        old_tuple: None,
+                        new_tuple: None,
+                    } if rel.reloid == RELATION_RELATION_ID
+                )
+            })
+            .count();
+        assert_eq!(pg_class_updates, 4);
+
+        let first_account = events
+            .iter()
+            .find(|event| {
+                matches!(&event.change, Change::Insert { rel, .. } if rel.reloid == PGBENCH_ACCOUNTS)
+            })
+            .unwrap();
+        assert_eq!(first_account.lsn, Lsn::from_hex("14D2D78").unwrap());
+        assert_eq!(first_account.xid, 1028);
+        let Change::Insert {
+            rel,
+            tuple: Some(tuple),
+        } = &first_account.change
+        else {
+            panic!("unexpected change {:?}", first_account.change);
+        };
+        assert_eq!(
+            *rel,
+            ChangedRel {
+                reloid: PGBENCH_ACCOUNTS,
+                spcnode: 1663,
+                dbnode: DB_ID,
+                relnode: 16396,
+            }
+        );
+        assert_eq!(
+            (tuple.infomask2, tuple.infomask, tuple.hoff),
+            (4, 0x0802, 24)
+        );
+        // Padding to hoff, then aid = 1, bid = 1, abalance = 0.
+        assert_eq!(&tuple.data[..13], &[0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+
+        // Learned from the full-page image and the prefix-compressed update.
+        assert_eq!(relations.by_relfilenode[&(DB_ID, 16398)], PGBENCH_HISTORY);
+        assert_eq!(relations.by_row.len(), 4);
+    }
+
+    #[test]
+    fn test_parse_tuple() {
+        let mut buf = Vec::new();
+        buf.put_u16_le(3);
+        buf.put_u16_le(0x0802);
+        buf.put_u32_le(7);
+        buf.put_u8(24);
+        buf.put_slice(b"tuple");
+        let tuple = parse_tuple(Bytes::from(buf)).unwrap();
+        assert_eq!(tuple.infomask2, 3);
+        assert_eq!(tuple.infomask, 0x0802);
+        assert_eq!(tuple.hoff, 24);
+        assert_eq!(&tuple.data[..], b"tuple");
+
+        assert_eq!(parse_tuple(Bytes::from_static(&[1, 2, 3])), None);
+    }
+
+    #[test]
+    fn test_parse_multi_insert_tuples() {
+        let mut buf = Vec::new();
+        for data in [&b"abc"[..], &b"de"[..]] {
+            // tuple headers are short-aligned
+            if buf.len() % 2 == 1 {
+                buf.put_u8(0);
+            }
+            buf.put_u16_le(data.len() as u16);
+            buf.put_u16_le(2);
+            buf.put_u16_le(0);
+            buf.put_u8(24);
+            buf.put_slice(data);
+        }
+        let tuples = parse_multi_insert_tuples(Bytes::from(buf), 2);
+        assert_eq!(tuples.len(), 2);
+        assert_eq!(&tuples[0].as_ref().unwrap().data[..], b"abc");
+        assert_eq!(&tuples[1].as_ref().unwrap().data[..], b"de");
+    }
+}
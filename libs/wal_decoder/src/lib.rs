@@ -1,3 +1,4 @@
+pub mod change_events;
 pub mod decoder;
 pub mod models;
 pub mod serialized_batch;
@@ -74,15 +74,53 @@ fn from_wire(
     ) -> impl std::future::Future<Output = Result<Self::T, FromWireFormatError>> + Send;
 }
 
+/// Compress a serialized message for the wire, if requested.
+pub(crate) async fn compress(
+    buf: Bytes,
+    compression: Option<Compression>,
+) -> Result<Bytes, std::io::Error> {
+    use async_compression::Level;
+    use async_compression::tokio::write::ZstdEncoder;
+
+    match compression {
+        Some(Compression::Zstd { level }) => {
+            let mut encoder = ZstdEncoder::with_quality(
+                Vec::with_capacity(buf.len() / 4),
+                Level::Precise(level as i32),
+            );
+            encoder.write_all(&buf).await?;
+            encoder.shutdown().await?;
+            Ok(Bytes::from(encoder.into_inner()))
+        }
+        None => Ok(buf),
+    }
+}
+
+/// Counterpart of [`compress`].
+pub(crate) async fn decompress(
+    buf: &Bytes,
+    compression: Option<Compression>,
+) -> Result<Bytes, std::io::Error> {
+    use async_compression::tokio::write::ZstdDecoder;
+
+    match compression {
+        Some(Compression::Zstd { .. }) => {
+            let mut decoded_buf = Vec::with_capacity(buf.len());
+            let mut decoder = ZstdDecoder::new(&mut decoded_buf);
+            decoder.write_all(buf).await?;
+            decoder.flush().await?;
+            Ok(Bytes::from(decoded_buf))
+        }
+        None => Ok(buf.clone()),
+    }
+}
+
 impl ToWireFormat for InterpretedWalRecords {
     async fn to_wire(
         self,
         format: InterpretedFormat,
         compression: Option<Compression>,
     ) -> Result<Bytes, ToWireFormatError> {
-        use async_compression::Level;
-        use async_compression::tokio::write::ZstdEncoder;
-
         let encode_res: Result<Bytes, ToWireFormatError> = match format {
             InterpretedFormat::Bincode => {
                 let buf = BytesMut::new();
@@ -101,21 +139,7 @@ async fn to_wire(
             }
         };
 
-        let buf = encode_res?;
-        let compressed_buf = match compression {
-            Some(Compression::Zstd { level }) => {
-                let mut encoder = ZstdEncoder::with_quality(
-                    Vec::with_capacity(buf.len() / 4),
-                    Level::Precise(level as i32),
-                );
-                encoder.write_all(&buf).await?;
-                encoder.shutdown().await?;
-                Bytes::from(encoder.into_inner())
-            }
-            None => buf,
-        };
-
-        Ok(compressed_buf)
+        Ok(compress(encode_res?, compression).await?)
     }
 }
 
@@ -127,17 +151,7 @@ async fn from_wire(
         format: InterpretedFormat,
         compression: Option<Compression>,
     ) -> Result<Self, FromWireFormatError> {
-        let decompressed_buf = match compression {
-            Some(Compression::Zstd { .. }) => {
-                use async_compression::tokio::write::ZstdDecoder;
-                let mut decoded_buf = Vec::with_capacity(buf.len());
-                let mut decoder = ZstdDecoder::new(&mut decoded_buf);
-                decoder.write_all(buf).await?;
-                decoder.flush().await?;
-                Bytes::from(decoded_buf)
-            }
-            None => buf.clone(),
-        };
+        let decompressed_buf = decompress(buf, compression).await?;
 
         match format {
             InterpretedFormat::Bincode => {
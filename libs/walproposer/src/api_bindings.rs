@@ -426,6 +426,8 @@ pub fn empty_shmem() -> crate::bindings::WalproposerShmemState {
         remote_consistent_lsn: 0,
         replytime: 0,
         shard_number: 0,
+        ingest_throttled: false,
+        sk_wal_backpressure: false,
     };
 
     let empty_wal_rate_limiter = crate::bindings::WalRateLimiter {
@@ -447,6 +449,7 @@ pub fn empty_shmem() -> crate::bindings::WalproposerShmemState {
         num_shards: 0,
         replica_promote: false,
         min_ps_feedback: empty_feedback,
+        sk_wal_backpressure: crate::bindings::pg_atomic_uint32 { value: 0 },
         wal_rate_limiter: empty_wal_rate_limiter,
     }
 }
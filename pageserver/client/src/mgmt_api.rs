@@ -904,6 +904,23 @@ pub async fn wait_lsn(
             .map(|resp| resp.status())
     }
 
+    pub async fn tenant_shard_handoff(
+        &self,
+        tenant_shard_id: TenantShardId,
+        request: TenantShardHandoffRequest,
+    ) -> Result<TenantShardHandoffResponse> {
+        let uri = format!(
+            "{}/v1/tenant/{tenant_shard_id}/handoff",
+            self.mgmt_api_endpoint,
+        );
+
+        self.request(Method::PUT, uri, request)
+            .await?
+            .json()
+            .await
+            .map_err(Error::ReceiveBody)
+    }
+
     pub async fn activate_post_import(
         &self,
         tenant_shard_id: TenantShardId,
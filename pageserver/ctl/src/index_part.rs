@@ -6,12 +6,18 @@
     IndexPart,
     layer_map::{LayerMap, SearchResult},
     remote_timeline_client::{index::LayerFileMetadata, remote_layer_path},
-    storage_layer::{LayerName, LayerVisibilityHint, PersistentLayerDesc, ReadableLayerWeak},
+    storage_layer::{
+        LayerName, LayerVisibilityHint, PersistentLayerDesc, ReadableLayerWeak,
+        offline::regenerate_index_part,
+    },
 };
 use pageserver_api::key::Key;
+use pageserver_api::shard::ShardIndex;
+use postgres_ffi::PgMajorVersion;
 use serde::Serialize;
 use std::collections::BTreeMap;
 use utils::{
+    generation::Generation,
     id::{TenantId, TimelineId},
     lsn::Lsn,
     shard::TenantShardId,
@@ -40,6 +46,32 @@ pub(crate) enum IndexPartCmd {
         #[arg(long)]
         path: Utf8PathBuf,
     },
+    /// Build an index_part.json referencing all layer files in a directory.
+    ///
+    /// Timeline metadata is copied from `--template` if given. Layers beyond the resulting
+    /// disk_consistent_lsn are left out, as the pageserver would ignore them.
+    Regenerate {
+        /// Directory of layer files, with or without generation suffixes
+        #[arg(long)]
+        layers_dir: Utf8PathBuf,
+        /// Existing index to take the timeline metadata from
+        #[arg(long)]
+        template: Option<Utf8PathBuf>,
+        /// Postgres major version, required without a template
+        #[arg(long)]
+        pg_version: Option<PgMajorVersion>,
+        #[arg(long)]
+        disk_consistent_lsn: Option<Lsn>,
+        /// Shard that the layers belong to, e.g. `0104` for shard 1 of 4
+        #[arg(long)]
+        shard: Option<ShardIndex>,
+        /// Generation to reference all layers with, instead of their file name suffixes
+        #[arg(long)]
+        generation: Option<u32>,
+        /// Where to write the index; printed to stdout if omitted
+        #[arg(long)]
+        output: Option<Utf8PathBuf>,
+    },
 }
 
 fn create_layer_map_from_index_part(
@@ -194,5 +226,41 @@ pub(crate) async fn main(cmd: &IndexPartCmd) -> anyhow::Result<()> {
             lsn,
         } => search_layers(tenant_id, timeline_id, path, key, lsn).await,
         IndexPartCmd::ListVisibleLayers { path } => list_visible_layers(path).await,
+        IndexPartCmd::Regenerate {
+            layers_dir,
+            template,
+            pg_version,
+            disk_consistent_lsn,
+            shard,
+            generation,
+            output,
+        } => {
+            let template = match template {
+                Some(path) => {
+                    let bytes = tokio::fs::read(path).await.context("read template")?;
+                    Some(IndexPart::from_json_bytes(&bytes).context("deserialize template")?)
+                }
+                None => None,
+            };
+            let (index_part, future_layers) = regenerate_index_part(
+                layers_dir,
+                template,
+                *pg_version,
+                *disk_consistent_lsn,
+                shard.unwrap_or(ShardIndex::unsharded()),
+                generation.map(Generation::new),
+            )?;
+            for layer in future_layers {
+                eprintln!("skipping layer beyond disk_consistent_lsn: {layer}");
+            }
+
+            let output_json =
+                serde_json::to_string_pretty(&index_part).context("serialize output")?;
+            match output {
+                Some(path) => tokio::fs::write(path, output_json).await?,
+                None => println!("{output_json}"),
+            }
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use camino::Utf8PathBuf;
+use clap::Parser;
+use itertools::Itertools as _;
+use pageserver_api::models::KeyAccessReport;
+use pageserver_api::reltag::RelTag;
+use pageserver_api::shard::{
+    DEFAULT_STRIPE_SIZE, ShardCount, ShardStripeSize, key_to_shard_number,
+};
+
+/// Parses a key access report (as emitted by the `key_access` timeline API), and outputs the
+/// hottest key ranges, reads by relation, and optionally the estimated load per shard.
+#[derive(Parser)]
+pub(crate) struct KeyAccessCmd {
+    /// Report input file, in JSON.
+    path: Utf8PathBuf,
+    /// Number of hottest key ranges to print.
+    #[arg(long, default_value_t = 20)]
+    top: usize,
+    /// Estimate the load of each shard, if the tenant was split into this many shards.
+    #[arg(long)]
+    shard_count: Option<u8>,
+    /// Stripe size in pages to use for the shard load estimate.
+    #[arg(long, default_value_t = DEFAULT_STRIPE_SIZE.0)]
+    stripe_size: u32,
+}
+
+pub(crate) fn main(cmd: &KeyAccessCmd) -> anyhow::Result<()> {
+    let report: KeyAccessReport = serde_json::from_slice(&std::fs::read(&cmd.path)?)?;
+
+    println!(
+        "Sampled since {} (1 in {}, ranges of {} keys, {} samples dropped)",
+        humantime::format_rfc3339_millis(report.since),
+        report.sampling_interval,
+        report.range_size,
+        report.dropped_samples,
+    );
+
+    println!("Hottest key ranges:");
+    for range in report.ranges.iter().take(cmd.top) {
+        let location = match (&range.rel, &range.blocks) {
+            (Some(rel), Some(blocks)) => format!("{rel} blocks {}..{}", blocks.start, blocks.end),
+            _ => format!("{}..{}", range.key_range.start, range.key_range.end),
+        };
+        println!(
+            "  {location}: {} reads, {} writes",
+            range.reads, range.writes
+        );
+        for layer in &range.layers {
+            println!(
+                "    {}: {} reads, {} non-resident",
+                layer.layer_file_name.as_deref().unwrap_or("<in-memory>"),
+                layer.reads,
+                layer.non_resident_reads
+            );
+        }
+    }
+
+    let mut by_relation: HashMap<RelTag, (u64, u64)> = HashMap::new();
+    for range in &report.ranges {
+        if let Some(rel) = range.rel {
+            let entry = by_relation.entry(rel).or_default();
+            entry.0 += range.reads;
+            entry.1 += range.writes;
+        }
+    }
+
+    println!("Accesses by relation:");
+    for (rel, (reads, writes)) in by_relation
+        .into_iter()
+        .sorted_by_key(|(rel, (reads, writes))| (std::cmp::Reverse(reads + writes), *rel))
+    {
+        println!("  {rel}: {reads} reads, {writes} writes");
+    }
+
+    if let Some(shard_count) = cmd.shard_count {
+        let count = ShardCount(shard_count);
+        let stripe_size = ShardStripeSize(cmd.stripe_size);
+        if stripe_size.0 % report.range_size != 0 {
+            println!(
+                "Warning: stripe size {} is not a multiple of the range size {}, the estimate is approximate",
+                stripe_size.0, report.range_size
+            );
+        }
+
+        let mut by_shard = vec![(0u64, 0u64); count.count() as usize];
+        for range in &report.ranges {
+            let shard = key_to_shard_number(count, stripe_size, &range.key_range.start);
+            let entry = &mut by_shard[shard.0 as usize];
+            entry.0 += range.reads;
+            entry.1 += range.writes;
+        }
+
+        let total: u64 = by_shard.iter().map(|(r, w)| r + w).sum();
+        println!(
+            "Estimated load with {} shards, stripe size {}:",
+            count.count(),
+            stripe_size
+        );
+        for (shard, (reads, writes)) in by_shard.into_iter().enumerate() {
+            let share = if total > 0 {
+                (reads + writes) as f64 * 100.0 / total as f64
+            } else {
+                0.0
+            };
+            println!("  shard {shard}: {reads} reads, {writes} writes ({share:.1}%)");
+        }
+    }
+
+    Ok(())
+}
@@ -6,11 +6,16 @@
 use clap::Subcommand;
 use pageserver::context::{DownloadBehavior, RequestContext};
 use pageserver::task_mgr::TaskKind;
+use pageserver::tenant::storage_layer::offline::{self, RewriteOptions};
 use pageserver::tenant::storage_layer::{DeltaLayer, ImageLayer, delta_layer, image_layer};
 use pageserver::tenant::{TENANTS_SEGMENT_NAME, TIMELINES_SEGMENT_NAME};
 use pageserver::virtual_file::api::IoMode;
 use pageserver::{page_cache, virtual_file};
 use pageserver_api::key::Key;
+use pageserver_api::models::ImageCompressionAlgorithm;
+use pageserver_api::shard::{
+    DEFAULT_STRIPE_SIZE, ShardCount, ShardIdentity, ShardNumber, ShardStripeSize,
+};
 use utils::id::{TenantId, TimelineId};
 
 use crate::layer_map_analyzer::{LayerFile, parse_filename};
@@ -47,6 +52,31 @@ pub(crate) enum LayerCmd {
         #[clap(long)]
         new_timeline_id: Option<TimelineId>,
     },
+    /// Rewrite a layer file in the current storage format, writing the result to a directory
+    ///
+    /// Optionally recompresses page images, drops the keys which don't belong to a shard, and
+    /// splits the layer into several ones at the given keys. The output layers carry their
+    /// regular file names, so they can be uploaded to remote storage as-is.
+    ///
+    /// Example: `cargo run --bin pagectl layer rewrite <layer> <dir> --image-compression 'zstd(1)'`
+    Rewrite {
+        layer_file_path: Utf8PathBuf,
+        output_dir: Utf8PathBuf,
+        /// Compression for page images, e.g. `disabled` or `zstd(3)`
+        #[clap(long)]
+        image_compression: Option<ImageCompressionAlgorithm>,
+        /// Drop keys which are not stored on this shard number
+        #[clap(long, requires = "shard_count")]
+        shard_number: Option<u8>,
+        #[clap(long, requires = "shard_number")]
+        shard_count: Option<u8>,
+        /// Stripe size of the sharded tenant, in pages
+        #[clap(long, requires = "shard_number")]
+        stripe_size: Option<u32>,
+        /// Start a new output layer at this key (repeatable)
+        #[clap(long)]
+        split_at: Vec<Key>,
+    },
 }
 
 async fn read_delta_file(path: impl AsRef<Path>, ctx: &RequestContext) -> Result<()> {
@@ -242,6 +272,50 @@ macro_rules! rewrite_closure {
 
             anyhow::bail!("not an image or delta layer: {layer_file_path}");
         }
+        LayerCmd::Rewrite {
+            layer_file_path,
+            output_dir,
+            image_compression,
+            shard_number,
+            shard_count,
+            stripe_size,
+            split_at,
+        } => {
+            pageserver::virtual_file::init(
+                10,
+                virtual_file::api::IoEngineKind::StdFs,
+                IoMode::preferred(),
+                virtual_file::SyncMode::Sync,
+            );
+            pageserver::page_cache::init(100);
+
+            let shard = match (shard_number, shard_count) {
+                (Some(number), Some(count)) => Some(ShardIdentity::new(
+                    ShardNumber(*number),
+                    ShardCount(*count),
+                    stripe_size
+                        .map(ShardStripeSize)
+                        .unwrap_or(DEFAULT_STRIPE_SIZE),
+                )?),
+                _ => None,
+            };
+            let options = RewriteOptions {
+                image_compression: *image_compression,
+                shard,
+                split_at: split_at.clone(),
+            };
+
+            std::fs::create_dir_all(output_dir)?;
+            let rewritten =
+                offline::rewrite_layer(layer_file_path, output_dir, &options, &ctx).await?;
+            for layer in rewritten {
+                println!(
+                    "wrote {} ({} keys, {} bytes)",
+                    layer.path, layer.num_keys, layer.file_size
+                );
+            }
+            Ok(())
+        }
     }
 }
 
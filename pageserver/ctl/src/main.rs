@@ -8,6 +8,7 @@
 mod draw_timeline_dir;
 mod index_part;
 mod key;
+mod key_access;
 mod layer_map_analyzer;
 mod layers;
 mod page_trace;
@@ -19,6 +20,7 @@
 use clap::{Parser, Subcommand};
 use download_remote_object::DownloadRemoteObjectCmd;
 use index_part::IndexPartCmd;
+use key_access::KeyAccessCmd;
 use layers::LayerCmd;
 use page_trace::PageTraceCmd;
 use pageserver::context::{DownloadBehavior, RequestContext};
@@ -65,6 +67,7 @@ enum Commands {
     /// Debug print a hex key found from logs
     Key(key::DescribeKeyCommand),
     PageTrace(PageTraceCmd),
+    KeyAccess(KeyAccessCmd),
     DownloadRemoteObject(DownloadRemoteObjectCmd),
 }
 
@@ -188,6 +191,7 @@ async fn main() -> anyhow::Result<()> {
         }
         Commands::Key(dkc) => dkc.execute(),
         Commands::PageTrace(cmd) => page_trace::main(&cmd)?,
+        Commands::KeyAccess(cmd) => key_access::main(&cmd)?,
         Commands::DownloadRemoteObject(cmd) => {
             download_remote_object::main(&cmd).await?;
         }
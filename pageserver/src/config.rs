@@ -188,6 +188,10 @@ pub struct PageServerConf {
     /// Maximum number of WAL records to be ingested and committed at the same time
     pub ingest_batch_size: u64,
 
+    /// How many batches of interpreted WAL records may be decoded concurrently while
+    /// earlier batches are being ingested. Ordering of ingest is always preserved.
+    pub ingest_decode_concurrency: NonZeroUsize,
+
     pub virtual_file_io_engine: virtual_file::IoEngineKind,
 
     pub max_vectored_read_bytes: MaxVectoredReadBytes,
@@ -223,6 +227,10 @@ pub struct PageServerConf {
     /// files read.
     pub enable_read_path_debugging: bool,
 
+    /// Sample one in this many reads and writes into the per-timeline key access report.
+    /// Zero disables sampling.
+    pub key_access_sampling_interval: u32,
+
     /// Interpreted protocol feature: if enabled, validate that the logical WAL received from
     /// safekeepers does not have gaps.
     pub validate_wal_contiguity: bool,
@@ -416,6 +424,7 @@ pub fn parse_and_validate(
             heatmap_upload_concurrency,
             secondary_download_concurrency,
             ingest_batch_size,
+            ingest_decode_concurrency,
             max_vectored_read_bytes,
             max_get_vectored_keys,
             image_compression,
@@ -431,6 +440,7 @@ pub fn parse_and_validate(
             page_service_pipelining,
             get_vectored_concurrent_io,
             enable_read_path_debugging,
+            key_access_sampling_interval,
             validate_wal_contiguity,
             load_previous_heatmap,
             generate_unarchival_heatmap,
@@ -485,6 +495,7 @@ pub fn parse_and_validate(
             heatmap_upload_concurrency,
             secondary_download_concurrency,
             ingest_batch_size,
+            ingest_decode_concurrency,
             max_vectored_read_bytes,
             max_get_vectored_keys,
             image_compression,
@@ -545,6 +556,7 @@ pub fn parse_and_validate(
             virtual_file_io_mode: virtual_file_io_mode.unwrap_or(virtual_file::IoMode::preferred()),
             no_sync: no_sync.unwrap_or(false),
             enable_read_path_debugging: enable_read_path_debugging.unwrap_or(false),
+            key_access_sampling_interval,
             validate_wal_contiguity: validate_wal_contiguity.unwrap_or(false),
             load_previous_heatmap: load_previous_heatmap.unwrap_or(true),
             generate_unarchival_heatmap: generate_unarchival_heatmap.unwrap_or(true),
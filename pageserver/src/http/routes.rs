@@ -36,12 +36,12 @@
     LsnLeaseRequest, OffloadedTimelineInfo, PageTraceEvent, ShardParameters, StatusResponse,
     TenantConfigPatchRequest, TenantConfigRequest, TenantDetails, TenantInfo,
     TenantLocationConfigRequest, TenantLocationConfigResponse, TenantScanRemoteStorageResponse,
-    TenantScanRemoteStorageShard, TenantShardLocation, TenantShardSplitRequest,
-    TenantShardSplitResponse, TenantSorting, TenantState, TenantWaitLsnRequest,
-    TimelineArchivalConfigRequest, TimelineCreateRequest, TimelineCreateRequestMode,
-    TimelineCreateRequestModeImportPgdata, TimelineGcRequest, TimelineInfo,
-    TimelinePatchIndexPartRequest, TimelineVisibilityState, TimelinesInfoAndOffloaded,
-    TopTenantShardItem, TopTenantShardsRequest, TopTenantShardsResponse,
+    TenantScanRemoteStorageShard, TenantShardHandoffRequest, TenantShardHandoffResponse,
+    TenantShardLocation, TenantShardSplitRequest, TenantShardSplitResponse, TenantSorting,
+    TenantState, TenantWaitLsnRequest, TimelineArchivalConfigRequest, TimelineCreateRequest,
+    TimelineCreateRequestMode, TimelineCreateRequestModeImportPgdata, TimelineGcRequest,
+    TimelineInfo, TimelinePatchIndexPartRequest, TimelineVisibilityState,
+    TimelinesInfoAndOffloaded, TopTenantShardItem, TopTenantShardsRequest, TopTenantShardsResponse,
 };
 use pageserver_api::shard::{ShardCount, TenantShardId};
 use postgres_ffi::PgMajorVersion;
@@ -69,7 +69,7 @@
 use crate::metrics::LOCAL_DATA_LOSS_SUSPECTED;
 use crate::pgdatadir_mapping::LsnForTimestamp;
 use crate::task_mgr::TaskKind;
-use crate::tenant::config::LocationConf;
+use crate::tenant::config::{AttachmentMode, LocationConf};
 use crate::tenant::mgr::{
     GetActiveTenantError, GetTenantError, TenantManager, TenantMapError, TenantMapInsertError,
     TenantSlot, TenantSlotError, TenantSlotUpsertError, TenantStateError, UpsertLocationError,
@@ -1751,6 +1751,27 @@ async fn timeline_page_trace_handler(
         .unwrap())
 }
 
+/// Reports the hottest key ranges of a timeline, based on sampled reads and writes.
+/// Use the `pagectl key-access` command to analyze the output.
+async fn timeline_key_access_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    let limit: usize = parse_query_param(&request, "limit")?.unwrap_or(100);
+    let reset: bool = parse_query_param(&request, "reset")?.unwrap_or(false);
+    let state = get_state(&request);
+
+    check_permission(&request, Some(tenant_shard_id.tenant_id))?;
+
+    let timeline =
+        active_timeline_of_active_tenant(&state.tenant_manager, tenant_shard_id, timeline_id)
+            .await?;
+
+    json_response(StatusCode::OK, timeline.key_access.report(limit, reset))
+}
+
 /// Adding a block is `POST ../block_gc`, removing a block is `POST ../unblock_gc`.
 ///
 /// Both are technically unsafe because they might fire off index uploads, thus they are POST.
@@ -3118,6 +3139,40 @@ async fn wait_lsn_handler(
     json_response(status, ())
 }
 
+/// Redirects page service clients of an attached tenant shard to another pageserver, see
+/// [`TenantShardHandoffRequest`]. Idempotent: the storage controller calls this repeatedly
+/// and watches the number of remaining page service connections.
+async fn tenant_shard_handoff_handler(
+    mut request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    check_permission(&request, Some(tenant_shard_id.tenant_id))?;
+    let handoff_request: TenantShardHandoffRequest = json_request(&mut request).await?;
+
+    let state = get_state(&request);
+    let tenant = state
+        .tenant_manager
+        .get_attached_tenant_shard(tenant_shard_id)?;
+
+    if handoff_request.connstr.is_some() && tenant.get_attach_mode() == AttachmentMode::Single {
+        // There must be another attached location to hand off to, otherwise we would be
+        // redirecting clients to a pageserver that cannot serve them.
+        return Err(ApiError::PreconditionFailed(
+            "handoff requires the tenant shard to be in AttachedStale or AttachedMulti mode".into(),
+        ));
+    }
+
+    let page_service_connections = tenant.set_handoff_target(handoff_request.connstr);
+
+    json_response(
+        StatusCode::OK,
+        TenantShardHandoffResponse {
+            page_service_connections,
+        },
+    )
+}
+
 async fn secondary_status_handler(
     request: Request<Body>,
     _cancel: CancellationToken,
@@ -4171,6 +4226,10 @@ pub fn make_router(
             "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/page_trace",
             |r| api_handler(r, timeline_page_trace_handler),
         )
+        .get(
+            "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/key_access",
+            |r| api_handler(r, timeline_key_access_handler),
+        )
         .post("/v1/tenant/:tenant_shard_id/heatmap_upload", |r| {
             api_handler(r, secondary_upload_handler)
         })
@@ -4192,6 +4251,9 @@ pub fn make_router(
         .post("/v1/tenant/:tenant_shard_id/wait_lsn", |r| {
             api_handler(r, wait_lsn_handler)
         })
+        .put("/v1/tenant/:tenant_shard_id/handoff", |r| {
+            api_handler(r, tenant_shard_handoff_handler)
+        })
         .put("/v1/tenant/:tenant_shard_id/break", |r| {
             testing_api_handler("set tenant state to broken", r, handle_tenant_break)
         })
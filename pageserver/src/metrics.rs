@@ -2847,6 +2847,14 @@ fn drop(&mut self) {
     .expect("failed to define a metric")
 });
 
+pub(crate) static MOVED_PAGESTREAM_RESPONSES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_moved_pagestream_responses_total",
+        "Number of pagestream requests answered with a redirect to another pageserver during a shard handoff"
+    )
+    .expect("failed to define a metric")
+});
+
 // Global counter for PageStream request results by outcome. Outcomes are divided into 3 categories:
 // - success
 // - internal_error: errors that indicate bugs in the storage cluster (e.g. page reconstruction errors, misrouted requests, LSN timeout errors)
@@ -2919,6 +2927,16 @@ pub(crate) struct WalIngestMetrics {
     pub(crate) values_committed_data_images: IntCounter,
     pub(crate) values_committed_data_deltas: IntCounter,
     pub(crate) gap_blocks_zeroed_on_rel_extend: IntCounter,
+    /// Time spent decompressing and deserializing a batch of interpreted records.
+    pub(crate) pipeline_decode_seconds: Histogram,
+    /// Time a decoded batch waited for earlier batches to be ingested.
+    pub(crate) pipeline_queued_seconds: Histogram,
+    /// Time spent ingesting and committing a decoded batch.
+    pub(crate) pipeline_apply_seconds: Histogram,
+    /// Time a decoded batch was held back by the tenant's ingest throttle or by
+    /// too many unflushed in-memory layer bytes.
+    pub(crate) pipeline_throttled_seconds: Histogram,
+    pub(crate) pipeline_batches_in_flight: IntGauge,
 }
 
 impl WalIngestMetrics {
@@ -2948,6 +2966,14 @@ pub(crate) fn inc_values_committed(&self, stats: &DatadirModificationStats) {
     )
     .expect("failed to define a metric");
 
+    let pipeline_stage_seconds = register_histogram_vec!(
+        "pageserver_wal_ingest_pipeline_stage_seconds",
+        "Time spent by interpreted WAL batches in each stage of the ingest pipeline",
+        &["stage"],
+        vec![0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0],
+    )
+    .expect("failed to define a metric");
+
     WalIngestMetrics {
     bytes_received: register_int_counter!(
         "pageserver_wal_ingest_bytes_received",
@@ -2978,6 +3004,15 @@ pub(crate) fn inc_values_committed(&self, stats: &DatadirModificationStats) {
         "Total number of zero gap blocks written on relation extends"
     )
     .expect("failed to define a metric"),
+    pipeline_decode_seconds: pipeline_stage_seconds.with_label_values(&["decode"]),
+    pipeline_queued_seconds: pipeline_stage_seconds.with_label_values(&["queued"]),
+    pipeline_apply_seconds: pipeline_stage_seconds.with_label_values(&["apply"]),
+    pipeline_throttled_seconds: pipeline_stage_seconds.with_label_values(&["throttled"]),
+    pipeline_batches_in_flight: register_int_gauge!(
+        "pageserver_wal_ingest_pipeline_batches_in_flight",
+        "Number of interpreted WAL batches received but not yet ingested"
+    )
+    .expect("failed to define a metric"),
 }
 });
 
@@ -4273,11 +4308,14 @@ pub(crate) fn inc_by(&self, n: u64) {
 }
 
 pub(crate) mod tenant_throttling {
+    use std::time::Instant;
+
     use metrics::register_int_counter_vec;
     use once_cell::sync::Lazy;
     use utils::shard::TenantShardId;
 
     use super::GlobalAndPerTenantIntCounter;
+    use crate::tenant::throttle::ThrottleResult;
 
     pub(crate) struct Metrics<const KIND: usize> {
         pub(super) count_accounted_start: GlobalAndPerTenantIntCounter,
@@ -4352,8 +4390,9 @@ pub(crate) struct Metrics<const KIND: usize> {
         .unwrap()
     });
 
-    const KINDS: &[&str] = &["pagestream"];
+    const KINDS: &[&str] = &["pagestream", "wal_ingest"];
     pub type Pagestream = Metrics<0>;
+    pub type WalIngest = Metrics<1>;
 
     impl<const KIND: usize> Metrics<KIND> {
         pub(crate) fn new(tenant_shard_id: &TenantShardId) -> Self {
@@ -4393,6 +4432,19 @@ pub(crate) fn new(tenant_shard_id: &TenantShardId) -> Self {
                 },
             }
         }
+
+        /// Account a completed [`crate::tenant::throttle::Throttle::throttle`] call that started at `start`.
+        ///
+        /// The pagestream throttle is instead accounted in steps by [`super::SmgrOpTimer`].
+        pub(crate) fn observe_throttle(&self, start: Instant, result: &ThrottleResult) {
+            self.count_accounted_start.inc();
+            self.count_accounted_finish.inc();
+            if let ThrottleResult::Throttled { end } = result {
+                self.count_throttled.inc();
+                self.wait_time
+                    .inc_by((*end - start).as_micros().try_into().unwrap());
+            }
+        }
     }
 
     pub(crate) fn preinitialize_global_metrics() {
@@ -4603,6 +4655,7 @@ pub fn preinitialize_metrics(
         &PAGE_SERVICE_SMGR_FLUSH_INPROGRESS_MICROS_GLOBAL,
         &WAIT_LSN_IN_PROGRESS_GLOBAL_MICROS,
         &MISROUTED_PAGESTREAM_REQUESTS,
+        &MOVED_PAGESTREAM_RESPONSES,
     ]
     .into_iter()
     .for_each(|c| {
@@ -30,8 +30,8 @@
     self, PagestreamBeMessage, PagestreamDbSizeRequest, PagestreamDbSizeResponse,
     PagestreamErrorResponse, PagestreamExistsRequest, PagestreamExistsResponse,
     PagestreamFeMessage, PagestreamGetPageRequest, PagestreamGetSlruSegmentRequest,
-    PagestreamGetSlruSegmentResponse, PagestreamNblocksRequest, PagestreamNblocksResponse,
-    PagestreamProtocolVersion, PagestreamRequest,
+    PagestreamGetSlruSegmentResponse, PagestreamMovedResponse, PagestreamNblocksRequest,
+    PagestreamNblocksResponse, PagestreamProtocolVersion, PagestreamRequest,
 };
 use pageserver_api::reltag::SlruKind;
 use pageserver_api::shard::TenantShardId;
@@ -70,7 +70,8 @@
 };
 use crate::metrics::{
     self, COMPUTE_COMMANDS_COUNTERS, ComputeCommandKind, GetPageBatchBreakReason, LIVE_CONNECTIONS,
-    MISROUTED_PAGESTREAM_REQUESTS, PAGESTREAM_HANDLER_RESULTS_TOTAL, SmgrOpTimer, TimelineMetrics,
+    MISROUTED_PAGESTREAM_REQUESTS, MOVED_PAGESTREAM_RESPONSES, PAGESTREAM_HANDLER_RESULTS_TOTAL,
+    SmgrOpTimer, TimelineMetrics,
 };
 use crate::pgdatadir_mapping::{LsnRange, Version};
 use crate::span::{
@@ -413,6 +414,10 @@ struct PageServerHandler {
 
     perf_span_fields: ConnectionPerfSpanFields,
 
+    /// Whether the client asked for [`PagestreamBeMessage::Moved`] responses during a shard
+    /// handoff, via the `neon.accept_moved` startup option.
+    accept_moved: bool,
+
     cancel: CancellationToken,
 
     /// None only while pagestream protocol is being processed.
@@ -613,6 +618,11 @@ enum PageStreamError {
     /// Request asked for something that doesn't make sense, like an invalid LSN
     #[error("Bad request: {0}")]
     BadRequest(Cow<'static, str>),
+
+    /// The shard is being handed off to the pageserver at this connection string, and the
+    /// client understands [`PagestreamBeMessage::Moved`].
+    #[error("Moved to {0}")]
+    Moved(Arc<String>),
 }
 
 impl From<PageStreamError> for tonic::Status {
@@ -632,6 +642,7 @@ fn from(err: PageStreamError) -> Self {
             PageStreamError::LsnTimeout(err) => tonic::Status::from(err).code(),
             PageStreamError::NotFound(_) => Code::NotFound,
             PageStreamError::BadRequest(_) => Code::InvalidArgument,
+            PageStreamError::Moved(_) => Code::Unavailable,
         };
         tonic::Status::new(code, message)
     }
@@ -915,6 +926,7 @@ pub fn new(
             claims: None,
             connection_ctx,
             perf_span_fields,
+            accept_moved: false,
             timeline_handles: Some(TimelineHandles::new(tenant_manager)),
             cancel,
             pipelining_config,
@@ -954,6 +966,7 @@ async fn pagestream_read_message<IO>(
         timeline_id: TimelineId,
         timeline_handles: &mut TimelineHandles,
         conn_perf_span_fields: &ConnectionPerfSpanFields,
+        accept_moved: bool,
         cancel: &CancellationToken,
         ctx: &RequestContext,
         protocol_version: PagestreamProtocolVersion,
@@ -994,6 +1007,20 @@ async fn pagestream_read_message<IO>(
         let neon_fe_msg =
             PagestreamFeMessage::parse(&mut copy_data_bytes.reader(), protocol_version)?;
 
+        if accept_moved {
+            if let Some(moved) = Self::check_handoff(
+                timeline_handles,
+                tenant_id,
+                timeline_id,
+                &neon_fe_msg,
+                &parent_span,
+            )
+            .await
+            {
+                return Ok(Some(moved));
+            }
+        }
+
         let batched_msg = match neon_fe_msg {
             PagestreamFeMessage::Exists(req) => {
                 let shard = timeline_handles
@@ -1266,6 +1293,46 @@ macro_rules! respond_error {
         Ok(Some(batched_msg))
     }
 
+    /// If the shard that serves `msg` is being handed off to another pageserver, returns a
+    /// response that redirects the client there.
+    /// See [`crate::tenant::TenantShard::set_handoff_target`].
+    async fn check_handoff(
+        timeline_handles: &mut TimelineHandles,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        msg: &PagestreamFeMessage,
+        parent_span: &Span,
+    ) -> Option<BatchedFeMessage> {
+        let (hdr, shard_selector) = match msg {
+            PagestreamFeMessage::Exists(req) => (req.hdr, ShardSelector::Zero),
+            PagestreamFeMessage::Nblocks(req) => (req.hdr, ShardSelector::Zero),
+            PagestreamFeMessage::DbSize(req) => (req.hdr, ShardSelector::Zero),
+            PagestreamFeMessage::GetSlruSegment(req) => (req.hdr, ShardSelector::Zero),
+            PagestreamFeMessage::GetPage(req) => (
+                req.hdr,
+                ShardSelector::Page(rel_block_to_key(req.rel, req.blkno)),
+            ),
+            #[cfg(feature = "testing")]
+            PagestreamFeMessage::Test(req) => (req.hdr, ShardSelector::Zero),
+        };
+
+        // Routing errors are reported by the regular request handling.
+        let shard = timeline_handles
+            .get(tenant_id, timeline_id, shard_selector)
+            .await
+            .ok()?;
+        let connstr = shard.handoff_target.load_full()?;
+
+        let span = tracing::info_span!(parent: parent_span, "handle_moved", shard_id = %shard.tenant_shard_id.shard_slug());
+        Some(BatchedFeMessage::RespondError {
+            span,
+            error: BatchedPageStreamError {
+                req: hdr,
+                err: PageStreamError::Moved(connstr),
+            },
+        })
+    }
+
     /// Starts a SmgrOpTimer at received_at and throttles the request.
     async fn record_op_start_and_throttle(
         shard: &Handle<TenantManagerTypes>,
@@ -1472,6 +1539,17 @@ async fn pagestream_handle_batched_message<IO>(
                         // END HADRON
                         return Err(QueryError::Reconnect);
                     }
+                    PageStreamError::Moved(connstr) => {
+                        // Not an error: the client will retry the request against `connstr`.
+                        MOVED_PAGESTREAM_RESPONSES.inc();
+                        (
+                            PagestreamBeMessage::Moved(PagestreamMovedResponse {
+                                req: e.req,
+                                connstr: connstr.to_string(),
+                            }),
+                            None,
+                        )
+                    }
                     PageStreamError::Read(_)
                     | PageStreamError::LsnTimeout(_)
                     | PageStreamError::NotFound(_)
@@ -1866,6 +1944,7 @@ async fn handle_pagerequests_serial<IO>(
                 timeline_id,
                 &mut timeline_handles,
                 &self.perf_span_fields,
+                self.accept_moved,
                 &cancel,
                 ctx,
                 protocol_version,
@@ -2001,6 +2080,7 @@ macro_rules! pipeline_stage {
         //
 
         let perf_span_fields = self.perf_span_fields.clone();
+        let accept_moved = self.accept_moved;
 
         let cancel_batcher = self.cancel.child_token();
         let (mut batch_tx, mut batch_rx) = spsc_fold::channel();
@@ -2016,6 +2096,7 @@ macro_rules! pipeline_stage {
                         timeline_id,
                         &mut timeline_handles,
                         &perf_span_fields,
+                        accept_moved,
                         &cancel_batcher,
                         &ctx,
                         protocol_version,
@@ -3056,6 +3137,8 @@ fn startup(
                     if key == "neon.compute_mode" {
                         self.perf_span_fields.compute_mode = Some(value.clone());
                         Span::current().record("compute_mode", field::display(value));
+                    } else if key == "neon.accept_moved" {
+                        self.accept_moved = matches!(value.as_str(), "on" | "true" | "1");
                     }
                 }
             }
@@ -23,7 +23,7 @@
 use std::{fmt, fs};
 
 use anyhow::{Context, bail};
-use arc_swap::ArcSwap;
+use arc_swap::{ArcSwap, ArcSwapOption};
 use camino::{Utf8Path, Utf8PathBuf};
 use chrono::NaiveDateTime;
 use enumset::EnumSet;
@@ -376,6 +376,17 @@ pub struct TenantShard {
 
     pub(crate) pagestream_throttle_metrics: Arc<crate::metrics::tenant_throttling::Pagestream>,
 
+    /// Throttle applied to WAL ingest, in bytes of interpreted WAL.
+    /// Like [`Self::pagestream_throttle`], it is shared by all timelines of this [`TenantShard`].
+    pub(crate) wal_ingest_throttle: Arc<throttle::Throttle>,
+
+    pub(crate) wal_ingest_throttle_metrics: Arc<crate::metrics::tenant_throttling::WalIngest>,
+
+    /// Page service connection string of the location this shard's page service traffic is
+    /// being handed off to, see [`TenantShard::set_handoff_target`].
+    /// Like [`Self::pagestream_throttle`], it is shared by all timelines of this [`TenantShard`].
+    pub(crate) handoff_target: Arc<ArcSwapOption<String>>,
+
     /// An ongoing timeline detach concurrency limiter.
     ///
     /// As a tenant will likely be restarted as part of timeline detach ancestor it makes no sense
@@ -4296,6 +4307,12 @@ pub(crate) fn set_new_location_config(&self, new_conf: AttachedTenantConf) {
 
         self.tenant_conf.store(Arc::new(new_conf.clone()));
 
+        if new_conf.location.attach_mode == AttachmentMode::Single {
+            // We are the only attached location again, e.g. after an aborted migration: there is
+            // nowhere to hand off to anymore.
+            self.set_handoff_target(None);
+        }
+
         self.tenant_conf_updated(&new_tenant_conf);
         // Don't hold self.timelines.lock() during the notifies.
         // There's no risk of deadlock right now, but there could be if we consolidate
@@ -4306,6 +4323,30 @@ pub(crate) fn set_new_location_config(&self, new_conf: AttachedTenantConf) {
         }
     }
 
+    /// Starts handing off page service traffic to the location at `connstr`, or cancels the
+    /// handoff if `None`. Clients are redirected on their next request, see
+    /// [`pageserver_api::models::TenantShardHandoffRequest`].
+    ///
+    /// Returns the number of page service connections that still use this shard.
+    pub(crate) fn set_handoff_target(&self, connstr: Option<String>) -> usize {
+        let target = connstr.map(Arc::new);
+        let prev = self.handoff_target.swap(target.clone());
+        match (prev, target) {
+            (None, Some(target)) => info!(%target, "starting page service handoff"),
+            (Some(_), None) => info!("cancelled page service handoff"),
+            _ => {}
+        }
+        self.page_service_connections()
+    }
+
+    /// Number of page service connections that hold a handle to any timeline of this shard.
+    pub(crate) fn page_service_connections(&self) -> usize {
+        self.list_timelines()
+            .iter()
+            .map(|timeline| timeline.handles.num_caches())
+            .sum()
+    }
+
     fn get_pagestream_throttle_config(
         psconf: &'static PageServerConf,
         overrides: &pageserver_api::models::TenantConfig,
@@ -4316,9 +4357,21 @@ fn get_pagestream_throttle_config(
             .unwrap_or(psconf.default_tenant_conf.timeline_get_throttle.clone())
     }
 
+    fn get_wal_ingest_throttle_config(
+        psconf: &'static PageServerConf,
+        overrides: &pageserver_api::models::TenantConfig,
+    ) -> throttle::Config {
+        overrides
+            .wal_ingest_throttle
+            .clone()
+            .unwrap_or(psconf.default_tenant_conf.wal_ingest_throttle.clone())
+    }
+
     pub(crate) fn tenant_conf_updated(&self, new_conf: &pageserver_api::models::TenantConfig) {
         let conf = Self::get_pagestream_throttle_config(self.conf, new_conf);
-        self.pagestream_throttle.reconfigure(conf)
+        self.pagestream_throttle.reconfigure(conf);
+        let conf = Self::get_wal_ingest_throttle_config(self.conf, new_conf);
+        self.wal_ingest_throttle.reconfigure(conf);
     }
 
     /// Helper function to create a new Timeline struct.
@@ -4503,6 +4556,13 @@ fn new(
             pagestream_throttle_metrics: Arc::new(
                 crate::metrics::tenant_throttling::Pagestream::new(&tenant_shard_id),
             ),
+            wal_ingest_throttle: Arc::new(throttle::Throttle::new(
+                TenantShard::get_wal_ingest_throttle_config(conf, &attached_conf.tenant_conf),
+            )),
+            wal_ingest_throttle_metrics: Arc::new(
+                crate::metrics::tenant_throttling::WalIngest::new(&tenant_shard_id),
+            ),
+            handoff_target: Arc::new(ArcSwapOption::empty()),
             tenant_conf: Arc::new(ArcSwap::from_pointee(attached_conf)),
             ongoing_timeline_detach: std::sync::Mutex::default(),
             gc_block: Default::default(),
@@ -5441,6 +5501,9 @@ fn get_timeline_resources_for(&self, remote_client: RemoteTimelineClient) -> Tim
             remote_client,
             pagestream_throttle: self.pagestream_throttle.clone(),
             pagestream_throttle_metrics: self.pagestream_throttle_metrics.clone(),
+            wal_ingest_throttle: self.wal_ingest_throttle.clone(),
+            wal_ingest_throttle_metrics: self.wal_ingest_throttle_metrics.clone(),
+            handoff_target: self.handoff_target.clone(),
             l0_compaction_trigger: self.l0_compaction_trigger.clone(),
             l0_flush_global_state: self.l0_flush_global_state.clone(),
             basebackup_cache: self.basebackup_cache.clone(),
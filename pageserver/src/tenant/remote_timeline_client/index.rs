@@ -202,6 +202,36 @@ pub fn references(&self, name: &LayerName, metadata: &LayerFileMetadata) -> bool
         is_same_remote_layer_path(name, metadata, name, index_metadata)
     }
 
+    /// Replace the set of layers referenced by the index, moving `disk_consistent_lsn` along
+    /// with them. Only used when regenerating an index offline, see
+    /// [`crate::tenant::storage_layer::offline::regenerate_index_part`].
+    ///
+    /// `prev_record_lsn` describes the record ending at the old `disk_consistent_lsn`, so it is
+    /// dropped if that moves.
+    pub(crate) fn set_layers(
+        &mut self,
+        layer_metadata: HashMap<LayerName, LayerFileMetadata>,
+        disk_consistent_lsn: Lsn,
+    ) {
+        let metadata = &self.metadata;
+        let prev_record_lsn = if disk_consistent_lsn == metadata.disk_consistent_lsn() {
+            metadata.prev_record_lsn()
+        } else {
+            None
+        };
+        self.metadata = TimelineMetadata::new(
+            disk_consistent_lsn,
+            prev_record_lsn,
+            metadata.ancestor_timeline(),
+            metadata.ancestor_lsn(),
+            metadata.latest_gc_cutoff_lsn(),
+            metadata.initdb_lsn(),
+            metadata.pg_version(),
+        );
+        self.disk_consistent_lsn = disk_consistent_lsn;
+        self.layer_metadata = layer_metadata;
+    }
+
     /// Check for invariants in the index: this is useful when uploading an index to ensure that if
     /// we encounter a bug, we do not persist buggy metadata.
     pub(crate) fn validate(&self) -> Result<(), String> {
@@ -1314,6 +1344,31 @@ fn v14_marked_invisible_at_is_parsed() {
         assert_eq!(part, expected);
     }
 
+    #[test]
+    fn set_layers_drops_stale_prev_record_lsn() {
+        let lsn = Lsn::from_str("0/16960E8").unwrap();
+        let prev = Lsn::from_str("0/1696070").unwrap();
+        let metadata = TimelineMetadata::new(
+            lsn,
+            Some(prev),
+            None,
+            Lsn::INVALID,
+            Lsn::INVALID,
+            Lsn::INVALID,
+            PgMajorVersion::PG14,
+        );
+        let mut part = IndexPart::empty(metadata);
+
+        part.set_layers(HashMap::new(), lsn);
+        assert_eq!(part.metadata.prev_record_lsn(), Some(prev));
+
+        let new_lsn = Lsn::from_str("0/1698000").unwrap();
+        part.set_layers(HashMap::new(), new_lsn);
+        assert_eq!(part.metadata.disk_consistent_lsn(), new_lsn);
+        assert_eq!(part.disk_consistent_lsn, new_lsn);
+        assert_eq!(part.metadata.prev_record_lsn(), None);
+    }
+
     fn parse_naive_datetime(s: &str) -> NaiveDateTime {
         chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S.%f").unwrap()
     }
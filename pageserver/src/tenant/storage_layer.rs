@@ -10,6 +10,7 @@
 mod layer_desc;
 mod layer_name;
 pub mod merge_iterator;
+pub mod offline;
 
 use std::cmp::Ordering;
 use std::collections::hash_map::Entry;
@@ -43,6 +44,7 @@
 use self::inmemory_layer::InMemoryLayerFileId;
 use super::PageReconstructError;
 use super::layer_map::InMemoryLayerDesc;
+use super::timeline::key_access::KeyAccessSample;
 use super::timeline::{GetVectoredError, ReadPath};
 use crate::context::{
     AccessStatsBehavior, PerfInstrumentFutureExt, RequestContext, RequestContextBuilder,
@@ -315,6 +317,9 @@ pub struct ValuesReconstructState {
     num_active_ios: Arc<AtomicUsize>,
 
     pub(crate) read_path: Option<ReadPath>,
+
+    /// Set if this read is sampled into [`super::timeline::Timeline::key_access`].
+    pub(crate) key_access_sample: Option<KeyAccessSample>,
 }
 
 /// The level of IO concurrency to be used on the read path
@@ -664,6 +669,7 @@ pub fn new(io_concurrency: IoConcurrency) -> Self {
             debug_state: ValueReconstructState::default(),
             num_active_ios: Arc::new(AtomicUsize::new(0)),
             read_path: None,
+            key_access_sample: None,
         }
     }
 
@@ -679,6 +685,7 @@ pub(crate) fn new_with_debug(io_concurrency: IoConcurrency) -> Self {
             debug_state: ValueReconstructState::default(),
             num_active_ios: Arc::new(AtomicUsize::new(0)),
             read_path: None,
+            key_access_sample: None,
         }
     }
 
@@ -0,0 +1,896 @@
+//! Offline manipulation of layer files, outside of a running pageserver.
+//!
+//! This is only used by the `pagectl` binary, to rewrite or repair layers during incidents. All
+//! output layers are written in the current [`STORAGE_FORMAT_VERSION`] and named the same way the
+//! pageserver names them, so they can be uploaded with the regular remote storage tooling.
+//!
+//! [`STORAGE_FORMAT_VERSION`]: crate::STORAGE_FORMAT_VERSION
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::str::FromStr;
+
+use anyhow::{Context, bail};
+use camino::{Utf8Path, Utf8PathBuf};
+use pageserver_api::key::Key;
+use pageserver_api::models::ImageCompressionAlgorithm;
+use pageserver_api::shard::{ShardIdentity, ShardIndex, TenantShardId};
+use postgres_ffi::PgMajorVersion;
+use tokio_util::sync::CancellationToken;
+use utils::generation::Generation;
+use utils::lsn::Lsn;
+use utils::sync::gate::Gate;
+use wal_decoder::models::value::Value;
+
+use super::delta_layer::DeltaLayerInner;
+use super::image_layer::ImageLayerInner;
+use super::{DeltaLayer, DeltaLayerWriter, ImageLayer, ImageLayerWriter, LayerName};
+use crate::config::PageServerConf;
+use crate::context::RequestContext;
+use crate::tenant::IndexPart;
+use crate::tenant::metadata::TimelineMetadata;
+use crate::tenant::remote_timeline_client::index::LayerFileMetadata;
+use crate::{DELTA_FILE_MAGIC, IMAGE_FILE_MAGIC};
+
+const MAX_READ_SIZE: u64 = 8 * 1024 * 1024;
+const MAX_BATCH_SIZE: usize = 1024;
+
+/// What to do with the contents of a layer while rewriting it.
+#[derive(Debug, Default)]
+pub struct RewriteOptions {
+    /// Compression for page images in the output. `None` uses the pageserver's default.
+    pub image_compression: Option<ImageCompressionAlgorithm>,
+    /// Drop the keys which the given shard is allowed to dispose of.
+    pub shard: Option<ShardIdentity>,
+    /// Start a new output layer at each of these keys. Keys outside of the input layer's key
+    /// range are ignored.
+    pub split_at: Vec<Key>,
+}
+
+/// A layer file produced by [`rewrite_layer`].
+#[derive(Debug)]
+pub struct RewrittenLayer {
+    pub name: LayerName,
+    pub path: Utf8PathBuf,
+    pub file_size: u64,
+    pub num_keys: usize,
+}
+
+/// Rewrite a layer file into `output_dir`, producing one output layer per key range.
+///
+/// The caller must have initialized [`crate::virtual_file`] and [`crate::page_cache`].
+pub async fn rewrite_layer(
+    input: &Utf8Path,
+    output_dir: &Utf8Path,
+    options: &RewriteOptions,
+    ctx: &RequestContext,
+) -> anyhow::Result<Vec<RewrittenLayer>> {
+    let file = File::open(input).with_context(|| format!("open {input}"))?;
+    let mut header_buf = [0u8; 2];
+    file.read_exact_at(&mut header_buf, 0)?;
+
+    // The layer writers create their temporary files in a timeline directory below the
+    // pageserver workdir, so give them a scratch workdir next to the output.
+    let workdir = camino_tempfile::tempdir_in(output_dir).context("create scratch workdir")?;
+    let mut conf = PageServerConf::dummy_conf(workdir.path().to_path_buf());
+    if let Some(image_compression) = options.image_compression {
+        conf.image_compression = image_compression;
+    }
+    let conf: &'static PageServerConf = Box::leak(Box::new(conf));
+
+    let rewriter = Rewriter {
+        conf,
+        output_dir,
+        options,
+        gate: Gate::default(),
+        cancel: CancellationToken::new(),
+    };
+
+    match u16::from_be_bytes(header_buf) {
+        IMAGE_FILE_MAGIC => {
+            let layer = ImageLayer::new_for_path(input, file)?;
+            let inner = ImageLayerInner::load(input, layer.lsn, None, None, ctx).await?;
+            let tenant_shard_id = rewriter.output_tenant_shard_id(&layer.desc.tenant_shard_id);
+            std::fs::create_dir_all(conf.timeline_path(&tenant_shard_id, &layer.desc.timeline_id))?;
+            rewriter
+                .rewrite_image_layer(&layer, &inner, tenant_shard_id, ctx)
+                .await
+        }
+        DELTA_FILE_MAGIC => {
+            let layer = DeltaLayer::new_for_path(input, file)?;
+            let inner = DeltaLayerInner::load(input, None, None, ctx).await?;
+            let tenant_shard_id = rewriter.output_tenant_shard_id(&layer.desc.tenant_shard_id);
+            std::fs::create_dir_all(conf.timeline_path(&tenant_shard_id, &layer.desc.timeline_id))?;
+            rewriter
+                .rewrite_delta_layer(&layer, &inner, tenant_shard_id, ctx)
+                .await
+        }
+        magic => bail!("unrecognized magic identifier: {:?}", magic),
+    }
+}
+
+struct Rewriter<'a> {
+    conf: &'static PageServerConf,
+    output_dir: &'a Utf8Path,
+    options: &'a RewriteOptions,
+    gate: Gate,
+    cancel: CancellationToken,
+}
+
+impl Rewriter<'_> {
+    fn output_tenant_shard_id(&self, input: &TenantShardId) -> TenantShardId {
+        match &self.options.shard {
+            Some(shard) => TenantShardId {
+                tenant_id: input.tenant_id,
+                shard_number: shard.number,
+                shard_count: shard.count,
+            },
+            None => *input,
+        }
+    }
+
+    fn is_dropped(&self, key: &Key) -> bool {
+        self.options
+            .shard
+            .as_ref()
+            .is_some_and(|shard| shard.is_key_disposable(key))
+    }
+
+    async fn rewrite_image_layer(
+        &self,
+        layer: &ImageLayer,
+        inner: &ImageLayerInner,
+        tenant_shard_id: TenantShardId,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<Vec<RewrittenLayer>> {
+        let mut output = Vec::new();
+        let mut ranges = split_key_range(&layer.desc.key_range, &self.options.split_at).into_iter();
+        let mut iter = inner.iter_with_options(ctx, MAX_READ_SIZE, MAX_BATCH_SIZE);
+
+        let mut range = ranges.next().expect("at least one range");
+        let mut writer = self
+            .new_image_writer(layer, tenant_shard_id, &range, ctx)
+            .await?;
+        while let Some((key, _lsn, value)) = iter.next().await? {
+            while !range.contains(&key) {
+                output.push(self.finish_image_writer(writer, ctx).await?);
+                range = ranges
+                    .next()
+                    .with_context(|| format!("key {key} is outside of the layer's key range"))?;
+                writer = self
+                    .new_image_writer(layer, tenant_shard_id, &range, ctx)
+                    .await?;
+            }
+            if self.is_dropped(&key) {
+                continue;
+            }
+            let Value::Image(img) = value else {
+                bail!("unexpected WAL record for key {key} in an image layer");
+            };
+            writer
+                .put_image(key, img, ctx)
+                .await
+                .map_err(|e| e.into_anyhow())?;
+        }
+        output.push(self.finish_image_writer(writer, ctx).await?);
+
+        // Image layers describe their entire key range, including keys that don't exist, so
+        // the ranges which received no keys still need a layer.
+        for range in ranges {
+            let writer = self
+                .new_image_writer(layer, tenant_shard_id, &range, ctx)
+                .await?;
+            output.push(self.finish_image_writer(writer, ctx).await?);
+        }
+
+        Ok(output)
+    }
+
+    async fn new_image_writer(
+        &self,
+        layer: &ImageLayer,
+        tenant_shard_id: TenantShardId,
+        key_range: &Range<Key>,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<ImageLayerWriter> {
+        ImageLayerWriter::new(
+            self.conf,
+            layer.desc.timeline_id,
+            tenant_shard_id,
+            key_range,
+            layer.lsn,
+            &self.gate,
+            self.cancel.clone(),
+            ctx,
+        )
+        .await
+    }
+
+    async fn finish_image_writer(
+        &self,
+        writer: ImageLayerWriter,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<RewrittenLayer> {
+        let num_keys = writer.num_keys();
+        let (desc, temp_path) = writer.finish(ctx).await?;
+        self.persist(desc.layer_name(), desc.file_size, num_keys, &temp_path)
+    }
+
+    async fn rewrite_delta_layer(
+        &self,
+        layer: &DeltaLayer,
+        inner: &DeltaLayerInner,
+        tenant_shard_id: TenantShardId,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<Vec<RewrittenLayer>> {
+        let mut output = Vec::new();
+        let mut ranges = split_key_range(&layer.desc.key_range, &self.options.split_at).into_iter();
+        let mut iter = inner.iter_with_options(ctx, MAX_READ_SIZE, MAX_BATCH_SIZE);
+
+        let mut range = ranges.next().expect("at least one range");
+        let mut writer = self
+            .new_delta_writer(layer, tenant_shard_id, &range, ctx)
+            .await?;
+        while let Some((key, lsn, value)) = iter.next().await? {
+            while !range.contains(&key) {
+                if let Some(rewritten) = self.finish_delta_writer(writer, &range, ctx).await? {
+                    output.push(rewritten);
+                }
+                range = ranges
+                    .next()
+                    .with_context(|| format!("key {key} is outside of the layer's key range"))?;
+                writer = self
+                    .new_delta_writer(layer, tenant_shard_id, &range, ctx)
+                    .await?;
+            }
+            if self.is_dropped(&key) {
+                continue;
+            }
+            writer
+                .put_value(key, lsn, value, ctx)
+                .await
+                .map_err(|e| e.into_anyhow())?;
+        }
+        if let Some(rewritten) = self.finish_delta_writer(writer, &range, ctx).await? {
+            output.push(rewritten);
+        }
+
+        Ok(output)
+    }
+
+    async fn new_delta_writer(
+        &self,
+        layer: &DeltaLayer,
+        tenant_shard_id: TenantShardId,
+        key_range: &Range<Key>,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<DeltaLayerWriter> {
+        DeltaLayerWriter::new(
+            self.conf,
+            layer.desc.timeline_id,
+            tenant_shard_id,
+            key_range.start,
+            layer.desc.lsn_range.clone(),
+            &self.gate,
+            self.cancel.clone(),
+            ctx,
+        )
+        .await
+    }
+
+    /// Returns `None` if the writer received no values: unlike image layers, an empty delta
+    /// layer carries no information.
+    async fn finish_delta_writer(
+        &self,
+        writer: DeltaLayerWriter,
+        key_range: &Range<Key>,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<Option<RewrittenLayer>> {
+        if writer.is_empty() {
+            // Dropping the writer removes its temporary file.
+            return Ok(None);
+        }
+        let num_keys = writer.num_keys();
+        let (desc, temp_path) = writer.finish(key_range.end, ctx).await?;
+        self.persist(desc.layer_name(), desc.file_size, num_keys, &temp_path)
+            .map(Some)
+    }
+
+    /// Moves a finished layer from the scratch workdir to its final name in the output directory.
+    fn persist(
+        &self,
+        name: LayerName,
+        file_size: u64,
+        num_keys: usize,
+        temp_path: &Utf8Path,
+    ) -> anyhow::Result<RewrittenLayer> {
+        let path = self.output_dir.join(name.to_string());
+        if path.exists() {
+            bail!("refusing to overwrite existing layer file {path}");
+        }
+        std::fs::rename(temp_path, &path)
+            .with_context(|| format!("rename {temp_path} to {path}"))?;
+        Ok(RewrittenLayer {
+            name,
+            path,
+            file_size,
+            num_keys,
+        })
+    }
+}
+
+/// Splits `key_range` at the given keys, ignoring those outside of it.
+fn split_key_range(key_range: &Range<Key>, split_at: &[Key]) -> Vec<Range<Key>> {
+    let mut split_at = split_at
+        .iter()
+        .filter(|key| key_range.start < **key && **key < key_range.end)
+        .copied()
+        .collect::<Vec<_>>();
+    split_at.sort();
+    split_at.dedup();
+
+    let mut ranges = Vec::with_capacity(split_at.len() + 1);
+    let mut start = key_range.start;
+    for key in split_at {
+        ranges.push(start..key);
+        start = key;
+    }
+    ranges.push(start..key_range.end);
+    ranges
+}
+
+/// Parses a layer file name as found locally or in remote storage, where it may carry a
+/// generation suffix.
+fn parse_layer_file_name(name: &str) -> Option<(LayerName, Generation)> {
+    match name.rsplit_once('-') {
+        Some((layer_name, suffix)) if suffix.len() == 8 => Some((
+            LayerName::from_str(layer_name).ok()?,
+            Generation::parse_suffix(suffix)?,
+        )),
+        _ => Some((LayerName::from_str(name).ok()?, Generation::none())),
+    }
+}
+
+/// Build an [`IndexPart`] referencing the layer files found in `layers_dir`.
+///
+/// Timeline metadata is taken from `template` if given, or else synthesized for a root timeline
+/// of `pg_version`. Unless `disk_consistent_lsn` is given, it is derived from the template or
+/// from the newest layer. Layers which are in the future of the resulting `disk_consistent_lsn`
+/// would be dismissed by the pageserver on load, so they are left out and returned separately.
+pub fn regenerate_index_part(
+    layers_dir: &Utf8Path,
+    template: Option<IndexPart>,
+    pg_version: Option<PgMajorVersion>,
+    disk_consistent_lsn: Option<Lsn>,
+    shard: ShardIndex,
+    generation: Option<Generation>,
+) -> anyhow::Result<(IndexPart, Vec<LayerName>)> {
+    let mut layers = HashMap::new();
+    for entry in layers_dir.read_dir_utf8()? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let Some((name, file_generation)) = parse_layer_file_name(entry.file_name()) else {
+            continue;
+        };
+        let file_size = entry.metadata()?.len();
+        let metadata =
+            LayerFileMetadata::new(file_size, generation.unwrap_or(file_generation), shard);
+        if let Some(previous) = layers.insert(name.clone(), metadata) {
+            // The same layer from several generations: keep the newest one, as the pageserver
+            // would have referenced it last.
+            if previous.generation > layers[&name].generation {
+                layers.insert(name, previous);
+            }
+        }
+    }
+
+    let mut index_part = match template {
+        Some(template) => template,
+        None => {
+            let Some(pg_version) = pg_version else {
+                bail!("a postgres version is required when there is no template index");
+            };
+            let initdb_lsn = layers
+                .keys()
+                .map(|name| name.lsn_as_range().start)
+                .min()
+                .context("no layers found and no template index given")?;
+            IndexPart::empty(TimelineMetadata::new(
+                Lsn(0),
+                None,
+                None,
+                Lsn(0),
+                initdb_lsn,
+                initdb_lsn,
+                pg_version,
+            ))
+        }
+    };
+
+    let disk_consistent_lsn = match disk_consistent_lsn {
+        Some(lsn) => lsn,
+        None if index_part.metadata.disk_consistent_lsn() != Lsn(0) => {
+            index_part.metadata.disk_consistent_lsn()
+        }
+        None => layers
+            .keys()
+            .map(|name| name.lsn_as_range().end)
+            .max()
+            .context("no layers found")?
+            .checked_sub(1u64)
+            .context("invalid layer LSN range")?,
+    };
+
+    let (layers, future_layers): (HashMap<_, _>, HashMap<_, _>) = layers
+        .into_iter()
+        .partition(|(name, _)| !name.is_in_future(disk_consistent_lsn));
+
+    index_part.set_layers(layers, disk_consistent_lsn);
+    index_part
+        .validate()
+        .map_err(|e| anyhow::anyhow!("regenerated index is invalid: {e}"))?;
+
+    Ok((index_part, future_layers.into_keys().collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use pageserver_api::shard::{ShardCount, ShardNumber, ShardStripeSize};
+    use wal_decoder::models::record::NeonWalRecord;
+
+    use super::*;
+    use crate::tenant::harness::{TIMELINE_ID, TenantHarness};
+
+    #[test]
+    fn test_split_key_range() {
+        let key = |i| Key::from_i128(i);
+        let range = key(10)..key(20);
+
+        assert_eq!(split_key_range(&range, &[]), vec![range.clone()]);
+        assert_eq!(
+            split_key_range(
+                &range,
+                &[key(15), key(0), key(10), key(20), key(12), key(15)]
+            ),
+            vec![key(10)..key(12), key(12)..key(15), key(15)..key(20)]
+        );
+    }
+
+    #[test]
+    fn test_parse_layer_file_name() {
+        let name = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51";
+
+        let (layer, generation) = parse_layer_file_name(name).unwrap();
+        assert_eq!(layer.to_string(), name);
+        assert_eq!(generation, Generation::none());
+
+        let (layer, generation) = parse_layer_file_name(&format!("{name}-0000000a")).unwrap();
+        assert_eq!(layer.to_string(), name);
+        assert_eq!(generation, Generation::new(10));
+
+        assert!(parse_layer_file_name("index_part.json").is_none());
+    }
+
+    /// A compressible page image.
+    fn test_image(key: Key) -> Bytes {
+        let mut img = format!("image of {key}").into_bytes();
+        img.resize(8192, 0);
+        Bytes::from(img)
+    }
+
+    fn test_record(key: Key, lsn: Lsn) -> Value {
+        Value::WalRecord(NeonWalRecord::wal_append(format!("[{key}@{lsn}]")))
+    }
+
+    /// Moves a layer written by the pageserver's writers into `dir`.
+    fn persist(dir: &Utf8Path, name: LayerName, temp_path: &Utf8Path) -> Utf8PathBuf {
+        let path = dir.join(name.to_string());
+        std::fs::rename(temp_path, &path).unwrap();
+        path
+    }
+
+    async fn write_image_layer(
+        harness: &TenantHarness,
+        dir: &Utf8Path,
+        key_range: Range<Key>,
+        lsn: Lsn,
+        image: impl Fn(Key) -> Bytes,
+        ctx: &RequestContext,
+    ) -> Utf8PathBuf {
+        let gate = Gate::default();
+        let mut writer = ImageLayerWriter::new(
+            harness.conf,
+            TIMELINE_ID,
+            harness.tenant_shard_id,
+            &key_range,
+            lsn,
+            &gate,
+            CancellationToken::new(),
+            ctx,
+        )
+        .await
+        .unwrap();
+        let mut key = key_range.start;
+        while key < key_range.end {
+            writer.put_image(key, image(key), ctx).await.unwrap();
+            key = key.next();
+        }
+        let (desc, temp_path) = writer.finish(ctx).await.unwrap();
+        persist(dir, desc.layer_name(), &temp_path)
+    }
+
+    /// Writes a delta layer with a WAL record at every LSN of `lsns` for each key of `key_range`.
+    async fn write_delta_layer(
+        harness: &TenantHarness,
+        dir: &Utf8Path,
+        key_range: Range<Key>,
+        lsn_range: Range<Lsn>,
+        lsns: &[Lsn],
+        ctx: &RequestContext,
+    ) -> Utf8PathBuf {
+        let gate = Gate::default();
+        let mut writer = DeltaLayerWriter::new(
+            harness.conf,
+            TIMELINE_ID,
+            harness.tenant_shard_id,
+            key_range.start,
+            lsn_range,
+            &gate,
+            CancellationToken::new(),
+            ctx,
+        )
+        .await
+        .unwrap();
+        let mut key = key_range.start;
+        while key < key_range.end {
+            for &lsn in lsns {
+                writer
+                    .put_value(key, lsn, test_record(key, lsn), ctx)
+                    .await
+                    .unwrap();
+            }
+            key = key.next();
+        }
+        let (desc, temp_path) = writer.finish(key_range.end, ctx).await.unwrap();
+        persist(dir, desc.layer_name(), &temp_path)
+    }
+
+    /// Reads all values of a layer file.
+    async fn read_layer(path: &Utf8Path, ctx: &RequestContext) -> Vec<(Key, Lsn, Value)> {
+        let mut values = Vec::new();
+        match LayerName::from_str(path.file_name().unwrap()).unwrap() {
+            LayerName::Image(name) => {
+                let inner = ImageLayerInner::load(path, name.lsn, None, None, ctx)
+                    .await
+                    .unwrap();
+                let mut iter = inner.iter_with_options(ctx, MAX_READ_SIZE, MAX_BATCH_SIZE);
+                while let Some(value) = iter.next().await.unwrap() {
+                    values.push(value);
+                }
+            }
+            LayerName::Delta(_) => {
+                let inner = DeltaLayerInner::load(path, None, None, ctx).await.unwrap();
+                let mut iter = inner.iter_with_options(ctx, MAX_READ_SIZE, MAX_BATCH_SIZE);
+                while let Some(value) = iter.next().await.unwrap() {
+                    values.push(value);
+                }
+            }
+        }
+        values
+    }
+
+    fn create_dirs(harness: &TenantHarness) -> (Utf8PathBuf, Utf8PathBuf) {
+        std::fs::create_dir_all(
+            harness
+                .conf
+                .timeline_path(&harness.tenant_shard_id, &TIMELINE_ID),
+        )
+        .unwrap();
+        let input_dir = harness.conf.workdir.join("input");
+        let output_dir = harness.conf.workdir.join("output");
+        std::fs::create_dir_all(&input_dir).unwrap();
+        std::fs::create_dir_all(&output_dir).unwrap();
+        (input_dir, output_dir)
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_layer_recompress() {
+        let harness = TenantHarness::create("test_rewrite_layer_recompress")
+            .await
+            .unwrap();
+        let (tenant, ctx) = harness.load().await;
+        drop(tenant);
+        let (input_dir, output_dir) = create_dirs(&harness);
+
+        let start = Key::from_hex("000000067f00000001000000ae0000000000").unwrap();
+        let range = start..start.add(0x100);
+        let input = write_image_layer(
+            &harness,
+            &input_dir,
+            range.clone(),
+            Lsn(0x20),
+            test_image,
+            &ctx,
+        )
+        .await;
+        let expected = read_layer(&input, &ctx).await;
+        assert_eq!(expected.len(), 0x100);
+
+        // The input is compressed with the default algorithm; rewrite it uncompressed.
+        let uncompressed = rewrite_layer(
+            &input,
+            &output_dir,
+            &RewriteOptions {
+                image_compression: Some(ImageCompressionAlgorithm::Disabled),
+                ..Default::default()
+            },
+            &ctx,
+        )
+        .await
+        .unwrap();
+        assert_eq!(uncompressed.len(), 1);
+        let uncompressed = &uncompressed[0];
+        assert_eq!(uncompressed.name.to_string(), input.file_name().unwrap());
+        assert_eq!(uncompressed.num_keys, 0x100);
+        assert_eq!(
+            uncompressed.file_size,
+            std::fs::metadata(&uncompressed.path).unwrap().len()
+        );
+        assert!(uncompressed.file_size > std::fs::metadata(&input).unwrap().len());
+        assert_eq!(read_layer(&uncompressed.path, &ctx).await, expected);
+
+        // Refuses to overwrite its own output.
+        let err = rewrite_layer(&input, &output_dir, &RewriteOptions::default(), &ctx)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("refusing to overwrite"), "{err}");
+
+        // And compress it back.
+        let recompressed_dir = harness.conf.workdir.join("recompressed");
+        std::fs::create_dir_all(&recompressed_dir).unwrap();
+        let recompressed = rewrite_layer(
+            &uncompressed.path,
+            &recompressed_dir,
+            &RewriteOptions {
+                image_compression: Some(ImageCompressionAlgorithm::Zstd { level: Some(3) }),
+                ..Default::default()
+            },
+            &ctx,
+        )
+        .await
+        .unwrap();
+        assert_eq!(recompressed.len(), 1);
+        assert!(recompressed[0].file_size < uncompressed.file_size);
+        assert_eq!(read_layer(&recompressed[0].path, &ctx).await, expected);
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_layer_shard_filter() {
+        let harness = TenantHarness::create("test_rewrite_layer_shard_filter")
+            .await
+            .unwrap();
+        let (tenant, ctx) = harness.load().await;
+        drop(tenant);
+        let (input_dir, output_dir) = create_dirs(&harness);
+
+        // This key range contains several 0x800 page stripes, only one of which belongs to
+        // shard zero of four.
+        let start = Key::from_hex("000000067f00000001000000ae0000000000").unwrap();
+        let end = Key::from_hex("000000067f00000001000000ae0000002000").unwrap();
+        let input = write_image_layer(
+            &harness,
+            &input_dir,
+            start..end,
+            Lsn(0x20),
+            |_| Bytes::from_static(&[1, 2, 3, 4]),
+            &ctx,
+        )
+        .await;
+        let expected = read_layer(&input, &ctx).await;
+
+        let shard =
+            ShardIdentity::new(ShardNumber(0), ShardCount::new(4), ShardStripeSize(0x800)).unwrap();
+        let output = rewrite_layer(
+            &input,
+            &output_dir,
+            &RewriteOptions {
+                shard: Some(shard),
+                ..Default::default()
+            },
+            &ctx,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].num_keys, 0x800);
+        assert!(output[0].file_size < std::fs::metadata(&input).unwrap().len());
+        let values = read_layer(&output[0].path, &ctx).await;
+        assert_eq!(
+            values,
+            expected
+                .into_iter()
+                .filter(|(key, _, _)| !shard.is_key_disposable(key))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_layer_split() {
+        let harness = TenantHarness::create("test_rewrite_layer_split")
+            .await
+            .unwrap();
+        let (tenant, ctx) = harness.load().await;
+        drop(tenant);
+        let (input_dir, output_dir) = create_dirs(&harness);
+
+        let start = Key::from_hex("000000067f00000001000000ae0000000000").unwrap();
+        let range = start..start.add(0x100);
+        let input = write_delta_layer(
+            &harness,
+            &input_dir,
+            range.clone(),
+            Lsn(0x20)..Lsn(0x40),
+            &[Lsn(0x20), Lsn(0x30)],
+            &ctx,
+        )
+        .await;
+        let expected = read_layer(&input, &ctx).await;
+        assert_eq!(expected.len(), 0x200);
+
+        let split = start.add(0x80);
+        let output = rewrite_layer(
+            &input,
+            &output_dir,
+            &RewriteOptions {
+                // Keys outside of the layer are ignored.
+                split_at: vec![split, range.end.add(1)],
+                ..Default::default()
+            },
+            &ctx,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(output.len(), 2);
+        let mut values = Vec::new();
+        for (rewritten, key_range) in output.iter().zip([start..split, split..range.end]) {
+            let LayerName::Delta(name) = &rewritten.name else {
+                panic!("unexpected layer {}", rewritten.name);
+            };
+            assert_eq!(name.key_range, key_range);
+            assert_eq!(name.lsn_range, Lsn(0x20)..Lsn(0x40));
+            // Delta layer writers count every key/LSN pair.
+            assert_eq!(rewritten.num_keys, 0x100);
+            let layer_values = read_layer(&rewritten.path, &ctx).await;
+            assert!(
+                layer_values
+                    .iter()
+                    .all(|(key, _, _)| key_range.contains(key))
+            );
+            values.extend(layer_values);
+        }
+        assert_eq!(values, expected);
+    }
+
+    #[tokio::test]
+    async fn test_regenerate_index_part() {
+        let harness = TenantHarness::create("test_regenerate_index_part")
+            .await
+            .unwrap();
+        let (tenant, ctx) = harness.load().await;
+        drop(tenant);
+        let (layers_dir, _) = create_dirs(&harness);
+
+        let start = Key::from_hex("000000067f00000001000000ae0000000000").unwrap();
+        let range = start..start.add(0x10);
+        let image = write_image_layer(
+            &harness,
+            &layers_dir,
+            range.clone(),
+            Lsn(0x10),
+            test_image,
+            &ctx,
+        )
+        .await;
+        let delta = write_delta_layer(
+            &harness,
+            &layers_dir,
+            range.clone(),
+            Lsn(0x11)..Lsn(0x20),
+            &[Lsn(0x18)],
+            &ctx,
+        )
+        .await;
+        // A layer as downloaded from remote storage, uploaded by two generations.
+        let newest = write_delta_layer(
+            &harness,
+            &layers_dir,
+            range.clone(),
+            Lsn(0x20)..Lsn(0x30),
+            &[Lsn(0x28)],
+            &ctx,
+        )
+        .await;
+        let newest_name = LayerName::from_str(newest.file_name().unwrap()).unwrap();
+        std::fs::copy(&newest, format!("{newest}-00000005")).unwrap();
+        std::fs::rename(&newest, format!("{newest}-0000000a")).unwrap();
+        // Not a layer.
+        std::fs::write(layers_dir.join("index_part.json"), b"{}").unwrap();
+
+        let file_size = |path: &Utf8Path| std::fs::metadata(path).unwrap().len();
+        let (index_part, future_layers) = regenerate_index_part(
+            &layers_dir,
+            None,
+            Some(PgMajorVersion::PG17),
+            None,
+            ShardIndex::unsharded(),
+            None,
+        )
+        .unwrap();
+        assert!(future_layers.is_empty());
+        assert_eq!(index_part.metadata.initdb_lsn(), Lsn(0x10));
+        assert_eq!(index_part.metadata.disk_consistent_lsn(), Lsn(0x2f));
+        assert_eq!(index_part.metadata.pg_version(), PgMajorVersion::PG17);
+        assert_eq!(
+            index_part.layer_metadata,
+            HashMap::from([
+                (
+                    LayerName::from_str(image.file_name().unwrap()).unwrap(),
+                    LayerFileMetadata::new(
+                        file_size(&image),
+                        Generation::none(),
+                        ShardIndex::unsharded()
+                    ),
+                ),
+                (
+                    LayerName::from_str(delta.file_name().unwrap()).unwrap(),
+                    LayerFileMetadata::new(
+                        file_size(&delta),
+                        Generation::none(),
+                        ShardIndex::unsharded()
+                    ),
+                ),
+                (
+                    newest_name.clone(),
+                    LayerFileMetadata::new(
+                        file_size(&Utf8PathBuf::from(format!("{newest}-0000000a"))),
+                        Generation::new(10),
+                        ShardIndex::unsharded()
+                    ),
+                ),
+            ])
+        );
+
+        // The index reads back as it would from remote storage.
+        let bytes = index_part.to_json_bytes().unwrap();
+        assert_eq!(IndexPart::from_json_bytes(&bytes).unwrap(), index_part);
+
+        // With an older disk_consistent_lsn, the newest layer is in the future. Regenerating
+        // from the previous index as a template keeps its metadata.
+        let (older, future_layers) = regenerate_index_part(
+            &layers_dir,
+            Some(index_part.clone()),
+            None,
+            Some(Lsn(0x1f)),
+            ShardIndex::unsharded(),
+            Some(Generation::new(3)),
+        )
+        .unwrap();
+        assert_eq!(future_layers, vec![newest_name]);
+        assert_eq!(older.metadata.disk_consistent_lsn(), Lsn(0x1f));
+        assert_eq!(older.metadata.initdb_lsn(), Lsn(0x10));
+        assert_eq!(older.layer_metadata.len(), 2);
+        assert!(
+            older
+                .layer_metadata
+                .values()
+                .all(|metadata| metadata.generation == Generation::new(3))
+        );
+    }
+}
@@ -450,16 +450,16 @@ async fn tenant_housekeeping_loop(tenant: Arc<TenantShard>, cancel: Cancellation
         };
         iteration.run(tenant.housekeeping()).await;
 
-        // Log any getpage throttling.
+        // Log any getpage and WAL ingest throttling.
+        let now = Instant::now();
+        let prev = std::mem::replace(&mut last_throttle_flag_reset_at, now);
+        let delta = now - prev;
         info_span!(parent: None, "pagestream_throttle", tenant_id=%tenant.tenant_shard_id, shard_id=%tenant.tenant_shard_id.shard_slug()).in_scope(|| {
-            let now = Instant::now();
-            let prev = std::mem::replace(&mut last_throttle_flag_reset_at, now);
             let Stats { count_accounted_start, count_accounted_finish, count_throttled, sum_throttled_usecs} = tenant.pagestream_throttle.reset_stats();
             if count_throttled == 0 {
                 return;
             }
             let allowed_rps = tenant.pagestream_throttle.steady_rps();
-            let delta = now - prev;
             info!(
                 n_seconds=%format_args!("{:.3}", delta.as_secs_f64()),
                 count_accounted = count_accounted_finish,  // don't break existing log scraping
@@ -470,6 +470,21 @@ async fn tenant_housekeeping_loop(tenant: Arc<TenantShard>, cancel: Cancellation
                 "shard was throttled in the last n_seconds"
             );
         });
+        info_span!(parent: None, "wal_ingest_throttle", tenant_id=%tenant.tenant_shard_id, shard_id=%tenant.tenant_shard_id.shard_slug()).in_scope(|| {
+            let Stats { count_accounted_start: _, count_accounted_finish, count_throttled, sum_throttled_usecs} = tenant.wal_ingest_throttle.reset_stats();
+            if count_throttled == 0 {
+                return;
+            }
+            let allowed_bytes_per_second = tenant.wal_ingest_throttle.steady_rps();
+            info!(
+                n_seconds=%format_args!("{:.3}", delta.as_secs_f64()),
+                count_accounted = count_accounted_finish,
+                count_throttled,
+                sum_throttled_usecs,
+                allowed_bytes_per_second=%format_args!("{allowed_bytes_per_second:.0}"),
+                "WAL ingest was throttled in the last n_seconds"
+            );
+        });
     }
 }
 
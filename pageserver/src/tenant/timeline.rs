@@ -7,6 +7,7 @@
 mod heatmap_layers_downloader;
 pub(crate) mod import_pgdata;
 mod init;
+pub(crate) mod key_access;
 pub mod layer_manager;
 pub(crate) mod logical_size;
 pub mod offload;
@@ -130,6 +131,7 @@
     ValueReconstructState, ValuesReconstructState,
 };
 use crate::tenant::tasks::BackgroundLoopKind;
+use crate::tenant::throttle::ThrottleResult;
 use crate::tenant::timeline::logical_size::CurrentLogicalSize;
 use crate::virtual_file::{MaybeFatalIo, VirtualFile};
 use crate::walingest::WalLagCooldown;
@@ -197,6 +199,9 @@ pub struct TimelineResources {
     pub remote_client: RemoteTimelineClient,
     pub pagestream_throttle: Arc<crate::tenant::throttle::Throttle>,
     pub pagestream_throttle_metrics: Arc<crate::metrics::tenant_throttling::Pagestream>,
+    pub wal_ingest_throttle: Arc<crate::tenant::throttle::Throttle>,
+    pub wal_ingest_throttle_metrics: Arc<crate::metrics::tenant_throttling::WalIngest>,
+    pub handoff_target: Arc<ArcSwapOption<String>>,
     pub l0_compaction_trigger: Arc<Notify>,
     pub l0_flush_global_state: l0_flush::L0FlushGlobalState,
     pub basebackup_cache: Arc<BasebackupCache>,
@@ -412,6 +417,13 @@ pub struct Timeline {
     /// Cloned from [`super::TenantShard::pagestream_throttle`] on construction.
     pub(crate) pagestream_throttle: Arc<crate::tenant::throttle::Throttle>,
 
+    /// Cloned from [`super::TenantShard::wal_ingest_throttle`] on construction.
+    wal_ingest_throttle: Arc<crate::tenant::throttle::Throttle>,
+    wal_ingest_throttle_metrics: Arc<crate::metrics::tenant_throttling::WalIngest>,
+
+    /// Cloned from [`super::TenantShard::handoff_target`] on construction.
+    pub(crate) handoff_target: Arc<ArcSwapOption<String>>,
+
     /// Size estimator for aux file v2
     pub(crate) aux_file_size_estimator: AuxFileSizeEstimator,
 
@@ -435,6 +447,9 @@ pub struct Timeline {
     /// If Some, collects GetPage metadata for an ongoing PageTrace.
     pub(crate) page_trace: ArcSwapOption<Sender<PageTraceEvent>>,
 
+    /// Sampled read/write counts by key range, see [`key_access`].
+    pub(crate) key_access: key_access::KeyAccessTracker,
+
     pub(super) previous_heatmap: ArcSwapOption<PreviousHeatmap>,
 
     /// May host a background Tokio task which downloads all the layers from the current
@@ -1444,6 +1459,12 @@ pub(super) async fn get_vectored_impl(
 
         reconstruct_state.read_path = read_path;
 
+        // Only sample reads on behalf of computes: background reads (e.g. compaction) would
+        // drown out the access pattern we want to see.
+        if ctx.task_kind() == TaskKind::PageRequestHandler {
+            reconstruct_state.key_access_sample = self.key_access.sample_read();
+        }
+
         let redo_attempt_type = if ctx.task_kind() == TaskKind::Compaction {
             RedoAttemptType::LegacyCompaction
         } else {
@@ -1484,6 +1505,10 @@ pub(super) async fn get_vectored_impl(
             return Err(err);
         };
 
+        if let Some(sample) = reconstruct_state.key_access_sample.take() {
+            self.key_access.record_read(&query.total_keyspace(), sample);
+        }
+
         let layers_visited = reconstruct_state.get_layers_visited();
 
         let ctx = RequestContextBuilder::from(ctx)
@@ -2998,6 +3023,14 @@ fn get_l0_flush_stall_threshold(&self) -> Option<usize> {
         Some(max(l0_flush_stall_threshold, compaction_threshold))
     }
 
+    fn get_wal_ingest_max_unflushed_bytes(&self) -> Option<u64> {
+        let tenant_conf = self.tenant_conf.load();
+        tenant_conf
+            .tenant_conf
+            .wal_ingest_max_unflushed_bytes
+            .or(self.conf.default_tenant_conf.wal_ingest_max_unflushed_bytes)
+    }
+
     fn get_image_creation_threshold(&self) -> usize {
         let tenant_conf = self.tenant_conf.load();
         tenant_conf
@@ -3311,6 +3344,9 @@ pub(super) fn new(
                 standby_horizon: AtomicLsn::new(0),
 
                 pagestream_throttle: resources.pagestream_throttle,
+                wal_ingest_throttle: resources.wal_ingest_throttle,
+                wal_ingest_throttle_metrics: resources.wal_ingest_throttle_metrics,
+                handoff_target: resources.handoff_target,
 
                 aux_file_size_estimator: AuxFileSizeEstimator::new(aux_file_metrics),
 
@@ -3327,6 +3363,8 @@ pub(super) fn new(
 
                 page_trace: Default::default(),
 
+                key_access: key_access::KeyAccessTracker::new(conf.key_access_sampling_interval),
+
                 previous_heatmap: ArcSwapOption::from_pointee(previous_heatmap),
 
                 heatmap_layers_downloader: Mutex::new(None),
@@ -3477,6 +3515,7 @@ fn launch_wal_receiver(
                 auth_token: crate::config::SAFEKEEPER_AUTH_TOKEN.get().cloned(),
                 availability_zone: self.conf.availability_zone.clone(),
                 ingest_batch_size: self.conf.ingest_batch_size,
+                ingest_decode_concurrency: self.conf.ingest_decode_concurrency,
                 validate_wal_contiguity: self.conf.validate_wal_contiguity,
             },
             broker_client,
@@ -4673,6 +4712,9 @@ async fn get_vectored_reconstruct_data_timeline(
             if let Some(ref mut read_path) = reconstruct_state.read_path {
                 read_path.record_layer_visit(&layer_to_read, &keyspace_to_read, &lsn_range);
             }
+            if let Some(ref mut sample) = reconstruct_state.key_access_sample {
+                sample.record_layer_visit(&layer_to_read, &keyspace_to_read);
+            }
 
             // Visit the layer and plan IOs for it
             let next_cont_lsn = lsn_range.start;
@@ -5093,6 +5135,79 @@ async fn flush_loop(
         }
     }
 
+    /// Applies WAL ingest backpressure before ingesting a batch of `bytes` of interpreted WAL.
+    ///
+    /// Waits for the tenant-wide [`Self::wal_ingest_throttle`], and then for the frozen in-memory
+    /// layers of this timeline to be flushed below `wal_ingest_max_unflushed_bytes`. While we wait
+    /// here, the walreceiver doesn't advance `last_record_lsn`, so the lag reported to safekeepers
+    /// grows and compute backpressure kicks in.
+    ///
+    /// Returns true if ingest was held back. Returns early on timeline shutdown, or when `cancel`
+    /// (the walreceiver connection's token) fires.
+    pub(crate) async fn wal_ingest_backpressure(
+        &self,
+        bytes: usize,
+        cancel: &CancellationToken,
+    ) -> bool {
+        let start = Instant::now();
+        let throttled = tokio::select! {
+            res = self.wal_ingest_throttle.throttle(bytes, start) => {
+                self.wal_ingest_throttle_metrics.observe_throttle(start, &res);
+                matches!(res, ThrottleResult::Throttled { .. })
+            }
+            _ = self.cancel.cancelled() => return false,
+            _ = cancel.cancelled() => return false,
+        };
+
+        let Some(max_unflushed_bytes) = self.get_wal_ingest_max_unflushed_bytes() else {
+            return throttled;
+        };
+
+        // Subscribe before checking the layer map, so we don't miss a flush completing in between.
+        let mut flush_done_rx = self.layer_flush_done_tx.subscribe();
+        let mut stall_started_at = None;
+        loop {
+            let unflushed_bytes: u64 = {
+                let layers = self
+                    .layers
+                    .read(LayerManagerLockHolder::WalIngestBackpressure)
+                    .await;
+                let Ok(lm) = layers.layer_map() else {
+                    break;
+                };
+                lm.frozen_layers
+                    .iter()
+                    .map(|l| l.estimated_in_mem_size())
+                    .sum()
+            };
+            if unflushed_bytes <= max_unflushed_bytes {
+                break;
+            }
+            if stall_started_at.is_none() {
+                info!(
+                    "stalling WAL ingest at {unflushed_bytes} unflushed in-memory layer bytes (limit {max_unflushed_bytes})"
+                );
+                stall_started_at = Some(Instant::now());
+            }
+            tokio::select! {
+                res = flush_done_rx.changed() => {
+                    if res.is_err() {
+                        break;
+                    }
+                }
+                _ = self.cancel.cancelled() => break,
+                _ = cancel.cancelled() => break,
+            }
+        }
+
+        if let Some(stall_started_at) = stall_started_at {
+            let delay = stall_started_at.elapsed().as_secs_f64();
+            info!("resuming WAL ingest after {delay:.3}s stall on unflushed in-memory layers");
+        }
+
+        throttled || stall_started_at.is_some()
+    }
+
     /// Waits any flush request created by [`Self::freeze_inmem_layer_at`] to complete.
     async fn wait_flush_completion(&self, request: u64) -> Result<(), FlushLayerError> {
         let mut rx = self.layer_flush_done_tx.subscribe();
@@ -7860,6 +7975,12 @@ pub(crate) async fn put_batch(
             }
         }
 
+        self.key_access
+            .maybe_record_writes(batch.metadata.iter().filter_map(|metadata| match metadata {
+                ValueMeta::Serialized(metadata) => Some(Key::from_compact(metadata.key)),
+                ValueMeta::Observed(_) => None,
+            }));
+
         let batch_max_lsn = batch.max_lsn;
         let buf_size: u64 = batch.buffer_size() as u64;
 
@@ -593,6 +593,17 @@ pub(super) fn shutdown(&self) {
         }
         drop(handles);
     }
+
+    /// Number of [`Cache`]s, i.e., page service connections, which currently hold a handle
+    /// to the [`Types::Timeline`] that embeds this per-timeline state.
+    pub(crate) fn num_caches(&self) -> usize {
+        self.handles
+            .lock()
+            .expect("mutex poisoned")
+            .as_ref()
+            .map(|handles| handles.len())
+            .unwrap_or(0)
+    }
 }
 
 // When dropping a [`Cache`], prune its handles in the [`PerTimelineState`] to break the reference cycle.
@@ -0,0 +1,340 @@
+//! Sampled per-key-range access statistics of a timeline.
+//!
+//! Layer access stats tell us which layers are used, but not which keys inside them are hot.
+//! To guide sharding decisions (`stripe_size`) and to explain on-demand download spikes, we
+//! sample one in [`PageServerConf::key_access_sampling_interval`] page reads and WAL ingest
+//! batches, and aggregate them into ranges of [`RANGE_SIZE`] contiguous keys. For reads, we
+//! also remember which layers served them and whether those layers were resident.
+//!
+//! The aggregated state is bounded: see [`MAX_TRACKED_RANGES`] and [`MAX_LAYERS_PER_RANGE`].
+//!
+//! [`PageServerConf::key_access_sampling_interval`]: crate::config::PageServerConf::key_access_sampling_interval
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use pageserver_api::key::Key;
+use pageserver_api::keyspace::KeySpace;
+use pageserver_api::models::{KeyAccessReport, KeyRangeAccess, KeyRangeLayerAccess};
+
+use crate::tenant::storage_layer::{LayerName, ReadableLayer};
+
+/// Number of contiguous keys aggregated into one tracked range. Relation block keys map to
+/// ranges of this many blocks, which is a fraction of the default shard stripe size, so that
+/// the report can be used to reason about different stripe sizes.
+///
+/// Must be a power of two, such that ranges never straddle a `field6` wraparound.
+pub(crate) const RANGE_SIZE: u32 = 256;
+
+/// Upper bound on the number of ranges tracked per timeline.
+const MAX_TRACKED_RANGES: usize = 16 * 1024;
+
+/// Upper bound on the number of layers remembered per range.
+const MAX_LAYERS_PER_RANGE: usize = 8;
+
+/// Upper bound on the number of ranges a single sampled read is attributed to.
+const MAX_RANGES_PER_SAMPLE: usize = 1024;
+
+pub(crate) struct KeyAccessTracker {
+    sampling_interval: u32,
+    reads: AtomicU64,
+    writes: AtomicU64,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    since: SystemTime,
+    ranges: HashMap<Key, RangeStats>,
+    dropped_samples: u64,
+}
+
+#[derive(Default)]
+struct RangeStats {
+    reads: u64,
+    writes: u64,
+    layers: HashMap<ServingLayer, LayerStats>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct LayerStats {
+    reads: u64,
+    non_resident_reads: u64,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum ServingLayer {
+    Persistent(LayerName),
+    InMemory,
+}
+
+/// The layers visited by a sampled read, collected on the read path.
+#[derive(Default)]
+pub(crate) struct KeyAccessSample {
+    layer_visits: Vec<(ServingLayer, bool, KeySpace)>,
+}
+
+impl KeyAccessSample {
+    pub(crate) fn record_layer_visit(&mut self, layer: &ReadableLayer, keyspace: &KeySpace) {
+        let (layer, resident) = match layer {
+            ReadableLayer::PersistentLayer(layer) => (
+                ServingLayer::Persistent(layer.layer_desc().layer_name()),
+                layer.is_likely_resident(),
+            ),
+            ReadableLayer::InMemoryLayer(_) => (ServingLayer::InMemory, true),
+        };
+        self.layer_visits.push((layer, resident, keyspace.clone()));
+    }
+}
+
+impl KeyAccessTracker {
+    pub(crate) fn new(sampling_interval: u32) -> Self {
+        Self {
+            sampling_interval,
+            reads: AtomicU64::new(0),
+            writes: AtomicU64::new(0),
+            inner: Mutex::new(Inner {
+                since: SystemTime::now(),
+                ranges: HashMap::new(),
+                dropped_samples: 0,
+            }),
+        }
+    }
+
+    fn should_sample(&self, counter: &AtomicU64) -> bool {
+        if self.sampling_interval == 0 {
+            return false;
+        }
+        counter.fetch_add(1, Ordering::Relaxed) % self.sampling_interval as u64 == 0
+    }
+
+    /// Returns a [`KeyAccessSample`] to fill in if this read should be sampled.
+    pub(crate) fn sample_read(&self) -> Option<KeyAccessSample> {
+        self.should_sample(&self.reads)
+            .then(KeyAccessSample::default)
+    }
+
+    /// Records a sampled read of `keyspace`, served by the layers in `sample`.
+    pub(crate) fn record_read(&self, keyspace: &KeySpace, sample: KeyAccessSample) {
+        let mut inner = self.inner.lock().unwrap();
+        for range in &keyspace.ranges {
+            for_each_range(range, |start, count| {
+                if let Some(stats) = inner.get_or_insert(start) {
+                    stats.reads += count;
+                }
+            });
+        }
+        for (layer, resident, keyspace) in sample.layer_visits {
+            for range in &keyspace.ranges {
+                for_each_range(range, |start, count| {
+                    if let Some(stats) = inner.ranges.get_mut(&start) {
+                        stats.record_layer(&layer, resident, count);
+                    }
+                });
+            }
+        }
+    }
+
+    /// Records the keys written by a WAL ingest batch, if the batch is sampled.
+    pub(crate) fn maybe_record_writes(&self, keys: impl Iterator<Item = Key>) {
+        if !self.should_sample(&self.writes) {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        for key in keys {
+            if let Some(stats) = inner.get_or_insert(range_start(key)) {
+                stats.writes += 1;
+            }
+        }
+    }
+
+    /// Builds a report of the `limit` hottest ranges, and optionally resets the statistics.
+    pub(crate) fn report(&self, limit: usize, reset: bool) -> KeyAccessReport {
+        let mut inner = self.inner.lock().unwrap();
+        let scale = self.sampling_interval.max(1) as u64;
+
+        let mut ranges = inner
+            .ranges
+            .iter()
+            .map(|(start, stats)| {
+                let end = start.add(RANGE_SIZE);
+                let (rel, blocks) = match start.to_rel_block() {
+                    Ok((rel, blkno)) if start.is_rel_block_key() => {
+                        (Some(rel), Some(blkno..blkno.saturating_add(RANGE_SIZE)))
+                    }
+                    _ => (None, None),
+                };
+                let mut layers = stats
+                    .layers
+                    .iter()
+                    .map(|(layer, layer_stats)| KeyRangeLayerAccess {
+                        layer_file_name: match layer {
+                            ServingLayer::Persistent(name) => Some(name.to_string()),
+                            ServingLayer::InMemory => None,
+                        },
+                        reads: layer_stats.reads * scale,
+                        non_resident_reads: layer_stats.non_resident_reads * scale,
+                    })
+                    .collect::<Vec<_>>();
+                layers.sort_by(|a, b| b.reads.cmp(&a.reads));
+                KeyRangeAccess {
+                    key_range: (*start..end).into(),
+                    rel,
+                    blocks,
+                    reads: stats.reads * scale,
+                    writes: stats.writes * scale,
+                    layers,
+                }
+            })
+            .collect::<Vec<_>>();
+        ranges.sort_by(|a, b| (b.reads + b.writes).cmp(&(a.reads + a.writes)));
+        ranges.truncate(limit);
+
+        let report = KeyAccessReport {
+            since: inner.since,
+            sampling_interval: self.sampling_interval,
+            range_size: RANGE_SIZE,
+            dropped_samples: inner.dropped_samples,
+            ranges,
+        };
+
+        if reset {
+            *inner = Inner {
+                since: SystemTime::now(),
+                ranges: HashMap::new(),
+                dropped_samples: 0,
+            };
+        }
+
+        report
+    }
+}
+
+impl Inner {
+    fn get_or_insert(&mut self, start: Key) -> Option<&mut RangeStats> {
+        if !self.ranges.contains_key(&start) && self.ranges.len() >= MAX_TRACKED_RANGES {
+            // Age out cold ranges: halve all counters and forget the ones that drop to zero.
+            for stats in self.ranges.values_mut() {
+                stats.reads /= 2;
+                stats.writes /= 2;
+            }
+            self.ranges
+                .retain(|_, stats| stats.reads > 0 || stats.writes > 0);
+            if self.ranges.len() >= MAX_TRACKED_RANGES {
+                self.dropped_samples += 1;
+                return None;
+            }
+        }
+        Some(self.ranges.entry(start).or_default())
+    }
+}
+
+impl RangeStats {
+    fn record_layer(&mut self, layer: &ServingLayer, resident: bool, count: u64) {
+        if !self.layers.contains_key(layer) && self.layers.len() >= MAX_LAYERS_PER_RANGE {
+            // Replace the least used layer: layers get compacted away over time, so the most
+            // recent ones are more interesting than a complete history.
+            let coldest = self
+                .layers
+                .iter()
+                .min_by_key(|(_, stats)| stats.reads)
+                .map(|(layer, _)| layer.clone());
+            if let Some(coldest) = coldest {
+                self.layers.remove(&coldest);
+            }
+        }
+        let stats = self.layers.entry(layer.clone()).or_default();
+        stats.reads += count;
+        if !resident {
+            stats.non_resident_reads += count;
+        }
+    }
+}
+
+fn range_start(key: Key) -> Key {
+    Key {
+        field6: key.field6 - key.field6 % RANGE_SIZE,
+        ..key
+    }
+}
+
+/// Calls `f` with the start of each tracked range overlapping `range`, and the number
+/// of keys of `range` that fall into it.
+fn for_each_range(range: &Range<Key>, mut f: impl FnMut(Key, u64)) {
+    let mut cur = range.start;
+    for _ in 0..MAX_RANGES_PER_SAMPLE {
+        if cur >= range.end {
+            break;
+        }
+        let start = range_start(cur);
+        let end = start.add(RANGE_SIZE);
+        let count = if range.end >= end {
+            RANGE_SIZE - cur.field6 % RANGE_SIZE
+        } else {
+            // `range.end` is inside the same tracked range as `cur`, so only `field6` differs.
+            range.end.field6 - cur.field6
+        };
+        f(start, count as u64);
+        cur = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rel_block_key(blkno: u32) -> Key {
+        Key {
+            field1: 0,
+            field2: 1663,
+            field3: 5,
+            field4: 16384,
+            field5: 0,
+            field6: blkno,
+        }
+    }
+
+    #[test]
+    fn test_for_each_range() {
+        let mut seen = Vec::new();
+        for_each_range(&(rel_block_key(250)..rel_block_key(600)), |start, count| {
+            seen.push((start.field6, count))
+        });
+        assert_eq!(seen, vec![(0, 6), (256, 256), (512, 88)]);
+
+        let mut seen = Vec::new();
+        for_each_range(&(rel_block_key(10)..rel_block_key(11)), |start, count| {
+            seen.push((start.field6, count))
+        });
+        assert_eq!(seen, vec![(0, 1)]);
+    }
+
+    #[test]
+    fn test_report_orders_and_scales() {
+        let tracker = KeyAccessTracker::new(1);
+        let hot = KeySpace::single(rel_block_key(1000)..rel_block_key(1010));
+        let cold = KeySpace::single(rel_block_key(0)..rel_block_key(1));
+        tracker.record_read(&cold, KeyAccessSample::default());
+        tracker.record_read(&hot, KeyAccessSample::default());
+        tracker.maybe_record_writes([rel_block_key(5), rel_block_key(6)].into_iter());
+
+        let report = tracker.report(10, true);
+        assert_eq!(report.ranges.len(), 2);
+        assert_eq!(report.ranges[0].reads, 10);
+        assert_eq!(report.ranges[0].blocks, Some(768..1024));
+        assert_eq!(report.ranges[1].reads, 1);
+        assert_eq!(report.ranges[1].writes, 2);
+
+        assert!(tracker.report(10, false).ranges.is_empty());
+    }
+
+    #[test]
+    fn test_sampling_disabled() {
+        let tracker = KeyAccessTracker::new(0);
+        assert!(tracker.sample_read().is_none());
+        tracker.maybe_record_writes([rel_block_key(5)].into_iter());
+        assert!(tracker.report(10, false).ranges.is_empty());
+    }
+}
@@ -48,6 +48,7 @@ pub(crate) enum LayerManagerLockHolder {
     DetachAncestor,
     Eviction,
     ComputeImageConsistentLsn,
+    WalIngestBackpressure,
     #[cfg(test)]
     Testing,
 }
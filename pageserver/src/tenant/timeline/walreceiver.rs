@@ -24,7 +24,7 @@
 mod walreceiver_connection;
 
 use std::future::Future;
-use std::num::NonZeroU64;
+use std::num::{NonZeroU64, NonZeroUsize};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -55,6 +55,7 @@ pub struct WalReceiverConf {
     pub auth_token: Option<Arc<String>>,
     pub availability_zone: Option<String>,
     pub ingest_batch_size: u64,
+    pub ingest_decode_concurrency: NonZeroUsize,
     pub validate_wal_contiguity: bool,
 }
 
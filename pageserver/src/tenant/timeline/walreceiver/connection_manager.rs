@@ -552,6 +552,7 @@ async fn change_connection(&mut self, new_sk: NewWalConnectionCandidate, ctx: &R
         let node_id = new_sk.safekeeper_id;
         let connect_timeout = self.conf.wal_connect_timeout;
         let ingest_batch_size = self.conf.ingest_batch_size;
+        let ingest_decode_concurrency = self.conf.ingest_decode_concurrency;
         let protocol = self.conf.protocol;
         let validate_wal_contiguity = self.conf.validate_wal_contiguity;
         let timeline = Arc::clone(&self.timeline);
@@ -575,6 +576,7 @@ async fn change_connection(&mut self, new_sk: NewWalConnectionCandidate, ctx: &R
                     ctx,
                     node_id,
                     ingest_batch_size,
+                    ingest_decode_concurrency,
                     validate_wal_contiguity,
                 )
                 .await;
@@ -624,6 +626,7 @@ async fn change_connection(&mut self, new_sk: NewWalConnectionCandidate, ctx: &R
             status: WalConnectionStatus {
                 is_connected: false,
                 has_processed_wal: false,
+                ingest_throttled: false,
                 latest_connection_update: now,
                 latest_wal_update: now,
                 streaming_lsn: None,
@@ -812,6 +815,13 @@ fn next_connection_candidate(&mut self) -> Option<NewWalConnectionCandidate> {
                     self.select_connection_candidate(Some(connected_sk_node))?;
                 let new_availability_zone = new_safekeeper_broker_data.availability_zone.clone();
 
+                if existing_wal_connection.status.ingest_throttled {
+                    // Ingest is held back by backpressure, so the connection isn't being read and
+                    // its timestamps and commit_lsn go stale. That's deliberate: another safekeeper
+                    // would be throttled just the same, so keep the connection.
+                    return None;
+                }
+
                 let now = Utc::now().naive_utc();
                 if let Ok(latest_interaciton) =
                     (now - existing_wal_connection.status.latest_connection_update).to_std()
@@ -1130,6 +1140,8 @@ fn name(&self) -> &str {
 
 #[cfg(test)]
 mod tests {
+    use std::num::NonZeroUsize;
+
     use url::Host;
     use utils::postgres_client::PostgresClientProtocol;
 
@@ -1199,6 +1211,7 @@ async fn connection_no_candidate() -> anyhow::Result<()> {
         let connection_status = WalConnectionStatus {
             is_connected: true,
             has_processed_wal: true,
+            ingest_throttled: false,
             latest_connection_update: now,
             latest_wal_update: now,
             commit_lsn: Some(Lsn(current_lsn)),
@@ -1368,6 +1381,7 @@ async fn lsn_wal_over_threshold_current_candidate() -> anyhow::Result<()> {
         let connection_status = WalConnectionStatus {
             is_connected: true,
             has_processed_wal: true,
+            ingest_throttled: false,
             latest_connection_update: now,
             latest_wal_update: now,
             commit_lsn: Some(current_lsn),
@@ -1436,6 +1450,7 @@ async fn timeout_connection_threshold_current_candidate() -> anyhow::Result<()>
         let connection_status = WalConnectionStatus {
             is_connected: true,
             has_processed_wal: true,
+            ingest_throttled: false,
             latest_connection_update: time_over_threshold,
             latest_wal_update: time_over_threshold,
             commit_lsn: Some(current_lsn),
@@ -1485,6 +1500,76 @@ async fn timeout_connection_threshold_current_candidate() -> anyhow::Result<()>
         Ok(())
     }
 
+    #[tokio::test]
+    async fn throttled_connection_is_kept() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("throttled_connection_is_kept").await?;
+        let mut state = dummy_state(&harness).await;
+        let current_lsn = Lsn(100_000).align();
+        let now = Utc::now().naive_utc();
+
+        let wal_connect_timeout = chrono::Duration::from_std(state.conf.wal_connect_timeout)?;
+        let time_over_threshold =
+            Utc::now().naive_utc() - wal_connect_timeout - wal_connect_timeout;
+
+        // A connection which hasn't been read for a while, because its ingest is throttled.
+        let connection_status = WalConnectionStatus {
+            is_connected: true,
+            has_processed_wal: true,
+            ingest_throttled: true,
+            latest_connection_update: time_over_threshold,
+            latest_wal_update: time_over_threshold,
+            commit_lsn: Some(current_lsn),
+            streaming_lsn: Some(current_lsn),
+            node: NodeId(1),
+        };
+
+        state.wal_connection = Some(WalConnection {
+            started_at: now,
+            sk_id: NodeId(1),
+            availability_zone: None,
+            status: connection_status,
+            connection_task: state.spawn(move |sender, _| async move {
+                sender
+                    .send(TaskStateUpdate::Progress(connection_status))
+                    .ok();
+                Ok(())
+            }),
+            discovered_new_wal: None,
+        });
+        // Meanwhile, another safekeeper has got far more WAL.
+        state.wal_stream_candidates = HashMap::from([(
+            NodeId(0),
+            dummy_broker_sk_timeline(
+                current_lsn.0 + state.conf.max_lsn_wal_lag.get() * 2,
+                DUMMY_SAFEKEEPER_HOST,
+                now,
+            ),
+        )]);
+
+        assert!(
+            state.next_connection_candidate().is_none(),
+            "Throttled connection should not be replaced"
+        );
+
+        // Once unthrottled, the stale connection is replaced as usual.
+        state
+            .wal_connection
+            .as_mut()
+            .unwrap()
+            .status
+            .ingest_throttled = false;
+        let candidate = state
+            .next_connection_candidate()
+            .expect("Expected the stale connection to be replaced");
+        assert_eq!(candidate.safekeeper_id, NodeId(0));
+        assert!(matches!(
+            candidate.reason,
+            ReconnectReason::NoKeepAlives { .. }
+        ));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn timeout_wal_over_threshold_current_candidate() -> anyhow::Result<()> {
         let harness = TenantHarness::create("timeout_wal_over_threshold_current_candidate").await?;
@@ -1500,6 +1585,7 @@ async fn timeout_wal_over_threshold_current_candidate() -> anyhow::Result<()> {
         let connection_status = WalConnectionStatus {
             is_connected: true,
             has_processed_wal: true,
+            ingest_throttled: false,
             latest_connection_update: now,
             latest_wal_update: time_over_threshold,
             commit_lsn: Some(current_lsn),
@@ -1582,6 +1668,7 @@ async fn dummy_state(harness: &TenantHarness) -> ConnectionManagerState {
                 auth_token: None,
                 availability_zone: None,
                 ingest_batch_size: 1,
+                ingest_decode_concurrency: NonZeroUsize::new(1).unwrap(),
                 validate_wal_contiguity: false,
             },
             wal_connection: None,
@@ -1607,6 +1694,7 @@ async fn switch_to_same_availability_zone() -> anyhow::Result<()> {
         let connection_status = WalConnectionStatus {
             is_connected: true,
             has_processed_wal: true,
+            ingest_throttled: false,
             latest_connection_update: now,
             latest_wal_update: now,
             commit_lsn: Some(current_lsn),
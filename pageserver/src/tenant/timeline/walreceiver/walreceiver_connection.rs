@@ -1,16 +1,20 @@
 //! Actual Postgres connection handler to stream WAL to the server.
 
 use std::error::Error;
+use std::future::Future;
+use std::num::NonZeroUsize;
 use std::pin::pin;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{Context, anyhow};
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use chrono::{NaiveDateTime, Utc};
 use fail::fail_point;
-use futures::StreamExt;
+use futures::future::BoxFuture;
+use futures::stream::FuturesOrdered;
+use futures::{FutureExt, StreamExt};
 use postgres_backend::is_expected_io_error;
 use postgres_connection::PgConnectionConfig;
 use postgres_ffi::WAL_SEGMENT_SIZE;
@@ -29,7 +33,7 @@
 use utils::id::NodeId;
 use utils::lsn::Lsn;
 use utils::pageserver_feedback::PageserverFeedback;
-use utils::postgres_client::PostgresClientProtocol;
+use utils::postgres_client::{Compression, InterpretedFormat, PostgresClientProtocol};
 use utils::sync::gate::GateError;
 use wal_decoder::models::{FlushUncommittedRecords, InterpretedWalRecords};
 use wal_decoder::wire_format::FromWireFormat;
@@ -52,6 +56,10 @@ pub(super) struct WalConnectionStatus {
     /// Defines a healthy connection as one on which pageserver received WAL from safekeeper
     /// and is able to process it in walingest without errors.
     pub has_processed_wal: bool,
+    /// Ingest is currently held back by tenant-level backpressure. While throttled, we stop
+    /// reading from the connection, so no keepalives arrive and the connection timestamps
+    /// don't advance: the connection manager must not take that as a sign of a dead connection.
+    pub ingest_throttled: bool,
     /// Connection establishment time or the timestamp of a latest connection message received.
     pub latest_connection_update: NaiveDateTime,
     /// Time of the latest WAL message received.
@@ -110,6 +118,109 @@ fn from(err: WalDecodeError) -> Self {
     }
 }
 
+/// Input to the ingest loop: either a message freshly received from the safekeeper,
+/// or the next (in receive order) batch of interpreted records which finished decoding.
+enum IngestPipelineEvent<'a> {
+    Received(ReplicationMessage),
+    Decoded(anyhow::Result<DecodedBatch>),
+    /// The held back batch was released by backpressure; carries whether it was throttled.
+    Unthrottled(ThrottledBatch<'a>, bool),
+    /// Ingest is still throttled: report that the connection is alive.
+    StillThrottled,
+}
+
+/// How often to report that a connection is alive while its ingest is throttled.
+const THROTTLED_STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A decoded batch waiting on [`Timeline::wal_ingest_backpressure`] before it's ingested.
+///
+/// The backpressure future is polled from the ingest loop rather than awaited inline, so the
+/// connection keeps reporting its status to the connection manager and the safekeeper while
+/// ingest is held back.
+struct ThrottledBatch<'a> {
+    decoded: DecodedBatch,
+    backpressure: BoxFuture<'a, bool>,
+    started_at: Instant,
+    status_ticker: time::Interval,
+}
+
+/// A batch of interpreted WAL records, decoded off the ingest path and ready to be
+/// applied to the timeline.
+struct DecodedBatch {
+    batch: InterpretedWalRecords,
+    /// End LSN of the raw WAL from which the records were interpreted.
+    streaming_lsn: Lsn,
+    /// Size of the batch as received from the safekeeper. Used for ingest throttling.
+    wire_bytes: usize,
+    decoded_at: Instant,
+    _in_flight: InFlightBatch,
+}
+
+/// Tracks a batch in `pageserver_wal_ingest_pipeline_batches_in_flight` from the moment
+/// it's received until it's ingested or dropped.
+struct InFlightBatch;
+
+impl InFlightBatch {
+    fn new() -> Self {
+        WAL_INGEST.pipeline_batches_in_flight.inc();
+        Self
+    }
+}
+
+impl Drop for InFlightBatch {
+    fn drop(&mut self) {
+        WAL_INGEST.pipeline_batches_in_flight.dec();
+    }
+}
+
+/// Decompress and deserialize a raw interpreted WAL message on the walreceiver runtime,
+/// so that decoding of later batches overlaps with ingest of earlier ones.
+///
+/// The decode task stops early when `cancel` fires.
+fn spawn_decode(
+    data: Bytes,
+    streaming_lsn: Lsn,
+    format: InterpretedFormat,
+    compression: Option<Compression>,
+    cancel: &CancellationToken,
+) -> impl Future<Output = anyhow::Result<DecodedBatch>> {
+    let in_flight = InFlightBatch::new();
+    let cancel = cancel.clone();
+    let handle = WALRECEIVER_RUNTIME.spawn(
+        async move {
+            let started_at = Instant::now();
+            let batch = select! {
+                biased;
+                _ = cancel.cancelled() => anyhow::bail!("walreceiver connection cancelled"),
+                batch = InterpretedWalRecords::from_wire(&data, format, compression) => batch,
+            }
+            .with_context(|| {
+                anyhow::anyhow!(
+                    "Failed to deserialize interpreted records ending at LSN {streaming_lsn}"
+                )
+            })?;
+            WAL_INGEST
+                .pipeline_decode_seconds
+                .observe(started_at.elapsed().as_secs_f64());
+
+            anyhow::Ok(DecodedBatch {
+                batch,
+                streaming_lsn,
+                wire_bytes: data.len(),
+                decoded_at: Instant::now(),
+                _in_flight: in_flight,
+            })
+        }
+        .in_current_span(),
+    );
+
+    async move {
+        handle
+            .await
+            .context("interpreted WAL decode task panicked")?
+    }
+}
+
 /// Open a connection to the given safekeeper and receive WAL, sending back progress
 /// messages as we go.
 #[allow(clippy::too_many_arguments)]
@@ -123,6 +234,7 @@ pub(super) async fn handle_walreceiver_connection(
     ctx: RequestContext,
     safekeeper_node: NodeId,
     ingest_batch_size: u64,
+    ingest_decode_concurrency: NonZeroUsize,
     validate_wal_contiguity: bool,
 ) -> Result<(), WalReceiverError> {
     debug_assert_current_span_has_tenant_and_timeline_id();
@@ -164,6 +276,7 @@ pub(super) async fn handle_walreceiver_connection(
     let mut connection_status = WalConnectionStatus {
         is_connected: true,
         has_processed_wal: false,
+        ingest_throttled: false,
         latest_connection_update: Utc::now().naive_utc(),
         latest_wal_update: Utc::now().naive_utc(),
         streaming_lsn: None,
@@ -292,64 +405,205 @@ pub(super) async fn handle_walreceiver_connection(
                 "Vanilla WAL receiver protocol is no longer supported for ingest"
             )));
         }
+        PostgresClientProtocol::ChangeEvents { .. } => {
+            return Err(WalReceiverError::Other(anyhow!(
+                "Change events protocol can't be used for ingest"
+            )));
+        }
     };
 
     let mut expected_wal_start = startpoint;
-    while let Some(replication_message) = {
-        select! {
+
+    // Interpreted batches are decoded on the walreceiver runtime's workers, up to
+    // `ingest_decode_concurrency` at a time. `FuturesOrdered` yields them back in the
+    // order they were received, so ingest into the timeline stays strictly sequential.
+    let mut decode_pipeline = FuturesOrdered::new();
+    // Decode tasks run detached on the runtime: stop them when the connection is cancelled,
+    // or when we return early, e.g. on an ingest error.
+    let decode_cancel = cancellation.child_token();
+    let _decode_cancel_guard = decode_cancel.clone().drop_guard();
+    let mut stream_ended = false;
+    // Whether ingest of the most recent batch was held back by tenant-level backpressure.
+    // Reported to the safekeeper, and from there to compute.
+    let mut ingest_throttled = false;
+    // The decoded batch which is next in line for ingest, while it waits on backpressure.
+    // No further batches are taken from the decode pipeline until it's released, so once the
+    // pipeline is full we stop reading from the safekeeper as well.
+    let mut throttled_batch: Option<ThrottledBatch> = None;
+
+    loop {
+        if stream_ended && decode_pipeline.is_empty() {
+            break;
+        }
+
+        let event = select! {
             biased;
             _ = cancellation.cancelled() => {
                 debug!("walreceiver interrupted");
-                None
+                break;
             }
-            replication_message = physical_stream.next() => replication_message,
-        }
-    } {
-        let replication_message = replication_message?;
+            event = async {
+                let batch = throttled_batch.as_mut().expect("checked by the branch precondition");
+                select! {
+                    biased;
+                    throttled = batch.backpressure.as_mut() => {
+                        let batch = throttled_batch.take().expect("checked above");
+                        IngestPipelineEvent::Unthrottled(batch, throttled)
+                    }
+                    _ = batch.status_ticker.tick() => IngestPipelineEvent::StillThrottled,
+                }
+            }, if throttled_batch.is_some() => event,
+            Some(decoded) = decode_pipeline.next(),
+                if throttled_batch.is_none() && !decode_pipeline.is_empty() =>
+            {
+                IngestPipelineEvent::Decoded(decoded)
+            }
+            replication_message = physical_stream.next(),
+                if !stream_ended && decode_pipeline.len() < ingest_decode_concurrency.get() =>
+            {
+                match replication_message {
+                    Some(replication_message) => {
+                        IngestPipelineEvent::Received(replication_message?)
+                    }
+                    None => {
+                        // Let the batches which were already received drain before exiting.
+                        stream_ended = true;
+                        continue;
+                    }
+                }
+            }
+        };
 
         let now = Utc::now().naive_utc();
         let last_rec_lsn_before_msg = last_rec_lsn;
 
         // Update the connection status before processing the message. If the message processing
         // fails (e.g. in walingest), we still want to know latests LSNs from the safekeeper.
-        match &replication_message {
-            ReplicationMessage::PrimaryKeepAlive(keepalive) => {
-                connection_status.latest_connection_update = now;
-                connection_status.commit_lsn = Some(Lsn::from(keepalive.wal_end()));
-            }
-            ReplicationMessage::RawInterpretedWalRecords(raw) => {
-                connection_status.latest_connection_update = now;
-                if !raw.data().is_empty() {
-                    connection_status.latest_wal_update = now;
+        if let IngestPipelineEvent::Received(replication_message) = &event {
+            match replication_message {
+                ReplicationMessage::PrimaryKeepAlive(keepalive) => {
+                    connection_status.latest_connection_update = now;
+                    connection_status.commit_lsn = Some(Lsn::from(keepalive.wal_end()));
                 }
+                ReplicationMessage::RawInterpretedWalRecords(raw) => {
+                    connection_status.latest_connection_update = now;
+                    if !raw.data().is_empty() {
+                        connection_status.latest_wal_update = now;
+                    }
 
-                connection_status.commit_lsn = Some(Lsn::from(raw.commit_lsn()));
-                connection_status.streaming_lsn = Some(Lsn::from(raw.streaming_lsn()));
+                    connection_status.commit_lsn = Some(Lsn::from(raw.commit_lsn()));
+                    connection_status.streaming_lsn = Some(Lsn::from(raw.streaming_lsn()));
+                }
+                &_ => {}
+            };
+            if let Err(e) = events_sender.send(TaskStateUpdate::Progress(connection_status)) {
+                warn!("Wal connection event listener dropped, aborting the connection: {e}");
+                return Ok(());
             }
-            &_ => {}
-        };
-        if let Err(e) = events_sender.send(TaskStateUpdate::Progress(connection_status)) {
-            warn!("Wal connection event listener dropped, aborting the connection: {e}");
-            return Ok(());
         }
 
-        let status_update = match replication_message {
-            ReplicationMessage::RawInterpretedWalRecords(raw) => {
+        let status_update = match event {
+            IngestPipelineEvent::Received(ReplicationMessage::RawInterpretedWalRecords(raw)) => {
                 WAL_INGEST.bytes_received.inc_by(raw.data().len() as u64);
 
-                let mut uncommitted_records = 0;
-
                 // This is the end LSN of the raw WAL from which the records
                 // were interpreted.
                 let streaming_lsn = Lsn::from(raw.streaming_lsn());
 
-                let batch = InterpretedWalRecords::from_wire(raw.data(), format, compression)
-                    .await
-                    .with_context(|| {
-                        anyhow::anyhow!(
-                        "Failed to deserialize interpreted records ending at LSN {streaming_lsn}"
-                    )
-                    })?;
+                decode_pipeline.push_back(spawn_decode(
+                    raw.data().clone(),
+                    streaming_lsn,
+                    format,
+                    compression,
+                    &decode_cancel,
+                ));
+
+                None
+            }
+
+            IngestPipelineEvent::Decoded(decoded) => {
+                let decoded = decoded?;
+
+                WAL_INGEST
+                    .pipeline_queued_seconds
+                    .observe(decoded.decoded_at.elapsed().as_secs_f64());
+
+                let backpressure = timeline
+                    .wal_ingest_backpressure(decoded.wire_bytes, &cancellation)
+                    .boxed();
+                throttled_batch = Some(ThrottledBatch {
+                    decoded,
+                    backpressure,
+                    started_at: Instant::now(),
+                    status_ticker: time::interval_at(
+                        time::Instant::now() + THROTTLED_STATUS_INTERVAL,
+                        THROTTLED_STATUS_INTERVAL,
+                    ),
+                });
+
+                None
+            }
+
+            IngestPipelineEvent::StillThrottled => {
+                // We aren't reading from the safekeeper while throttled, so tell the connection
+                // manager not to mistake the silence for a dead connection, and the safekeeper
+                // that we're still here.
+                ingest_throttled = true;
+                if !connection_status.ingest_throttled {
+                    connection_status.ingest_throttled = true;
+                    if let Err(e) = events_sender.send(TaskStateUpdate::Progress(connection_status))
+                    {
+                        warn!(
+                            "Wal connection event listener dropped, aborting the connection: {e}"
+                        );
+                        return Ok(());
+                    }
+                }
+
+                Some(last_rec_lsn)
+            }
+
+            IngestPipelineEvent::Unthrottled(released, throttled) => {
+                let ThrottledBatch {
+                    decoded:
+                        DecodedBatch {
+                            batch,
+                            streaming_lsn,
+                            wire_bytes: _,
+                            decoded_at: _,
+                            _in_flight,
+                        },
+                    backpressure: _,
+                    started_at: throttle_started_at,
+                    status_ticker: _,
+                } = released;
+
+                ingest_throttled = throttled;
+                if ingest_throttled {
+                    WAL_INGEST
+                        .pipeline_throttled_seconds
+                        .observe(throttle_started_at.elapsed().as_secs_f64());
+                }
+                if cancellation.is_cancelled() || timeline.is_stopping() {
+                    break;
+                }
+
+                if connection_status.ingest_throttled {
+                    // Messages have piled up on the connection while we weren't reading it.
+                    connection_status.ingest_throttled = false;
+                    connection_status.latest_connection_update = now;
+                    if let Err(e) = events_sender.send(TaskStateUpdate::Progress(connection_status))
+                    {
+                        warn!(
+                            "Wal connection event listener dropped, aborting the connection: {e}"
+                        );
+                        return Ok(());
+                    }
+                }
+
+                let apply_started_at = Instant::now();
+
+                let mut uncommitted_records = 0;
 
                 // Guard against WAL gaps. If the start LSN of the PG WAL section
                 // from which the interpreted records were extracted, doesn't match
@@ -493,6 +747,10 @@ async fn commit(
                     commit(&mut modification, &ctx, &mut uncommitted_records).await?;
                 }
 
+                WAL_INGEST
+                    .pipeline_apply_seconds
+                    .observe(apply_started_at.elapsed().as_secs_f64());
+
                 if !caught_up && streaming_lsn >= end_of_wal {
                     info!("caught up at LSN {streaming_lsn}");
                     caught_up = true;
@@ -509,7 +767,7 @@ async fn commit(
                 Some(streaming_lsn)
             }
 
-            ReplicationMessage::PrimaryKeepAlive(keepalive) => {
+            IngestPipelineEvent::Received(ReplicationMessage::PrimaryKeepAlive(keepalive)) => {
                 let wal_end = keepalive.wal_end();
                 let timestamp = keepalive.timestamp();
                 let reply_requested = keepalive.reply() != 0;
@@ -518,6 +776,8 @@ async fn commit(
                     "received PrimaryKeepAlive(wal_end: {wal_end}, timestamp: {timestamp:?} reply: {reply_requested})"
                 );
 
+                // Batches which are still being decoded are not reflected here: we only
+                // report what has actually been ingested.
                 if reply_requested {
                     Some(last_rec_lsn)
                 } else {
@@ -525,7 +785,7 @@ async fn commit(
                 }
             }
 
-            _ => None,
+            IngestPipelineEvent::Received(_) => None,
         };
 
         if !connection_status.has_processed_wal && last_rec_lsn > last_rec_lsn_before_msg {
@@ -586,6 +846,8 @@ async fn commit(
                 remote_consistent_lsn,
                 replytime: ts,
                 shard_number: timeline.tenant_shard_id.shard_number.0 as u32,
+                ingest_throttled,
+                sk_wal_backpressure: false,
             };
 
             debug!("neon_status_update {status_update:?}");
@@ -651,3 +913,77 @@ fn get_parse<T>(row: &SimpleQueryRow, idx: usize) -> Result<T, IdentifyError>
         Err(IdentifyError.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use wal_decoder::models::InterpretedWalRecord;
+    use wal_decoder::serialized_batch::SerializedValueBatch;
+    use wal_decoder::wire_format::ToWireFormat;
+
+    use super::*;
+
+    async fn encoded_batch(
+        records: usize,
+        next_record_lsn: Lsn,
+        format: InterpretedFormat,
+        compression: Option<Compression>,
+    ) -> Bytes {
+        let record = InterpretedWalRecord {
+            metadata_record: None,
+            batch: SerializedValueBatch::default(),
+            next_record_lsn,
+            flush_uncommitted: FlushUncommittedRecords::No,
+            xid: 0,
+        };
+        let batch = InterpretedWalRecords {
+            records: vec![record; records],
+            next_record_lsn,
+            raw_wal_start_lsn: None,
+        };
+        batch.to_wire(format, compression).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn decode_pipeline_preserves_order() {
+        let format = InterpretedFormat::Protobuf;
+        let compression = Some(Compression::Zstd { level: 1 });
+        let cancel = CancellationToken::new();
+
+        // Earlier batches are larger, so they tend to finish decoding after later ones.
+        let mut decode_pipeline = FuturesOrdered::new();
+        for i in 0..16u64 {
+            let lsn = Lsn(0x1000 * (i + 1));
+            let records = 1 << (16 - i);
+            let data = encoded_batch(records, lsn, format, compression).await;
+            decode_pipeline.push_back(spawn_decode(data, lsn, format, compression, &cancel));
+        }
+
+        let mut expected_lsn = Lsn(0);
+        while let Some(decoded) = decode_pipeline.next().await {
+            let decoded = decoded.unwrap();
+            expected_lsn += 0x1000;
+            assert_eq!(decoded.streaming_lsn, expected_lsn);
+            assert_eq!(decoded.batch.next_record_lsn, expected_lsn);
+            assert!(
+                decoded
+                    .batch
+                    .records
+                    .iter()
+                    .all(|r| r.next_record_lsn == expected_lsn)
+            );
+        }
+        assert_eq!(expected_lsn, Lsn(0x1000 * 16));
+    }
+
+    #[tokio::test]
+    async fn decode_stops_on_cancel() {
+        let format = InterpretedFormat::Protobuf;
+        let lsn = Lsn(0x1000);
+        let data = encoded_batch(1, lsn, format, None).await;
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let res = spawn_decode(data, lsn, format, None, &cancel).await;
+        assert!(res.is_err());
+    }
+}
@@ -1562,6 +1562,7 @@ nm_pack_request(NeonRequest *msg)
 		case T_NeonErrorResponse:
 		case T_NeonDbSizeResponse:
 		case T_NeonGetSlruSegmentResponse:
+		case T_NeonMovedResponse:
 		default:
 			neon_log(PANIC, "unexpected neon message tag 0x%02x", msg->tag);
 			break;
@@ -1682,6 +1683,24 @@ nm_unpack_response(StringInfo s)
 				break;
 			}
 
+		case T_NeonMovedResponse:
+			{
+				NeonMovedResponse *msg_resp;
+				size_t		connstrlen;
+				const char *connstr;
+
+				connstr = pq_getmsgrawstring(s);
+				connstrlen = strlen(connstr);
+
+				msg_resp = palloc0(sizeof(NeonMovedResponse) + connstrlen + 1);
+				msg_resp->req = resp_hdr;
+				memcpy(msg_resp->connstr, connstr, connstrlen + 1);
+				pq_getmsgend(s);
+
+				resp = (NeonResponse *) msg_resp;
+				break;
+			}
+
 		case T_NeonGetSlruSegmentResponse:
 		    {
 				NeonGetSlruSegmentResponse *msg_resp;
@@ -1861,6 +1880,15 @@ nm_to_string(NeonMessage *msg)
 
 				break;
 			}
+		case T_NeonMovedResponse:
+			{
+				NeonMovedResponse *msg_resp = (NeonMovedResponse *) msg;
+
+				appendStringInfoString(&s, "{\"type\": \"NeonMovedResponse\"");
+				appendStringInfo(&s, ", \"connstr\": \"%s\"", msg_resp->connstr);
+				appendStringInfoChar(&s, '}');
+				break;
+			}
 
 		default:
 			appendStringInfo(&s, "{\"type\": \"unknown 0x%02x\"", msg->tag);
@@ -168,6 +168,13 @@ typedef struct
 	 *	- WL_EXIT_ON_PM_DEATH.
 	 */
 	WaitEventSet   *wes_read;
+
+	/*
+	 * Connection string the pageserver redirected us to with a "moved"
+	 * response, while handing off the shard. Used instead of the shard map
+	 * for the next connection attempt. Empty if not set.
+	 */
+	char			moved_connstr[MAX_PAGESERVER_CONNSTRING_SIZE];
 } PageServer;
 
 static uint32 local_request_counter;
@@ -178,6 +185,7 @@ static PageServer page_servers[MAX_SHARDS];
 static bool pageserver_flush(shardno_t shard_no);
 static void pageserver_disconnect(shardno_t shard_no);
 static void pageserver_disconnect_shard(shardno_t shard_no);
+static void pageserver_handle_moved(shardno_t shard_no, NeonMovedResponse *resp);
 
 static bool
 PagestoreShmemIsValid(void)
@@ -343,6 +351,8 @@ load_shard_map(shardno_t shard_no, char *connstr_p, shardno_t *num_shards_p, siz
 		{
 			if (page_servers[i].conn)
 				pageserver_disconnect(i);
+			/* The new shard map supersedes any handoff redirects */
+			page_servers[i].moved_connstr[0] = '\0';
 		}
 		pagestore_local_counter = end_update_counter;
 
@@ -431,7 +441,7 @@ pageserver_connect(shardno_t shard_no, int elevel)
 		const char *keywords[5];
 		const char *values[5];
 		char pid_str[16] = { 0 };
-		char endpoint_str[36] = { 0 };
+		char endpoint_str[64] = { 0 };
 		int			n_pgsql_params;
 		TimestampTz	now;
 		int64		us_since_last_attempt;
@@ -439,6 +449,17 @@ pageserver_connect(shardno_t shard_no, int elevel)
 		/* Make sure we start with a clean slate */
 		CLEANUP_AND_DISCONNECT(shard);
 
+		/*
+		 * If the pageserver redirected us during a shard handoff, try the new
+		 * pageserver once. If that connection is lost, we go back to the shard
+		 * map, which by then should have been updated as well.
+		 */
+		if (shard->moved_connstr[0] != '\0')
+		{
+			strlcpy(connstr, shard->moved_connstr, MAX_PAGESERVER_CONNSTRING_SIZE);
+			shard->moved_connstr[0] = '\0';
+		}
+
 		neon_shard_log(shard_no, DEBUG5, "Connection state: Disconnected");
 
 		now = GetCurrentTimestamp();
@@ -528,12 +549,17 @@ pageserver_connect(shardno_t shard_no, int elevel)
 					param_set = true;
 					break;
 			}
+			/*
+			 * Let the pageserver redirect us to another pageserver during a
+			 * shard handoff, see pageserver_handle_moved().
+			 */
 			if (param_set)
-			{
-				keywords[n_pgsql_params] = "options";
-				values[n_pgsql_params] = endpoint_str;
-				n_pgsql_params++;
-			}
+				strlcat(endpoint_str, " ", sizeof(endpoint_str));
+			strlcat(endpoint_str, "-c neon.accept_moved=on", sizeof(endpoint_str));
+
+			keywords[n_pgsql_params] = "options";
+			values[n_pgsql_params] = endpoint_str;
+			n_pgsql_params++;
 		}
 
 		keywords[n_pgsql_params] = NULL;
@@ -1012,6 +1038,29 @@ pageserver_disconnect_shard(shardno_t shard_no)
 	shard->state = PS_Disconnected;
 }
 
+/*
+ * The pageserver is handing off the shard to another pageserver, which has
+ * already caught up. Disconnect, and make the next connection attempt go to
+ * the new pageserver without backoff. The request that got this response and
+ * all other in-flight requests are retried, like after a lost connection.
+ */
+static void
+pageserver_handle_moved(shardno_t shard_no, NeonMovedResponse *resp)
+{
+	PageServer *shard = &page_servers[shard_no];
+
+	neon_shard_log(shard_no, LOG, "pageserver is handing off the shard, reconnecting to '%s'",
+				   resp->connstr);
+	strlcpy(shard->moved_connstr, resp->connstr, MAX_PAGESERVER_CONNSTRING_SIZE);
+	pfree(resp);
+
+	pageserver_disconnect(shard_no);
+
+	/* This is a planned move, not a failure: reconnect right away */
+	shard->delay_us = MIN_RECONNECT_INTERVAL_USEC;
+	shard->last_reconnect_time = 0;
+}
+
 static bool
 pageserver_send(shardno_t shard_no, NeonRequest *request)
 {
@@ -1139,6 +1188,12 @@ pageserver_receive(shardno_t shard_no)
 			neon_shard_log(shard_no, PageStoreTrace, "got response: %s", msg);
 			pfree(msg);
 		}
+
+		if (resp->tag == T_NeonMovedResponse)
+		{
+			pageserver_handle_moved(shard_no, (NeonMovedResponse *) resp);
+			resp = NULL;
+		}
 	}
 	else if (rc == -1 && shard->state == PS_Disconnected)
 	{
@@ -1225,6 +1280,12 @@ pageserver_try_receive(shardno_t shard_no)
 			neon_shard_log(shard_no, PageStoreTrace, "got response: %s", msg);
 			pfree(msg);
 		}
+
+		if (resp->tag == T_NeonMovedResponse)
+		{
+			pageserver_handle_moved(shard_no, (NeonMovedResponse *) resp);
+			resp = NULL;
+		}
 	}
 	else if (rc == -1)
 	{
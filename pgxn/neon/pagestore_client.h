@@ -41,6 +41,7 @@ typedef enum
 	T_NeonErrorResponse,
 	T_NeonDbSizeResponse,
 	T_NeonGetSlruSegmentResponse,
+	T_NeonMovedResponse,
 	/* future tags above this line */
 	T_NeonTestResponse = 199, /* only in cfg(feature = "testing") */
 } NeonMessageTag;
@@ -165,6 +166,18 @@ typedef struct
 												 * message */
 } NeonErrorResponse;
 
+/*
+ * The pageserver is handing off the shard to another pageserver, and asks us
+ * to send this and further requests to 'connstr' instead. Only sent if we
+ * asked for it with the "neon.accept_moved" startup option.
+ */
+typedef struct
+{
+	NeonResponse req;
+	char		connstr[FLEXIBLE_ARRAY_MEMBER]; /* null-terminated connection
+												 * string */
+} NeonMovedResponse;
+
 typedef struct
 {
 	NeonGetSlruSegmentRequest req;
@@ -86,6 +86,7 @@ static void UpdateDonorShmem(WalProposer *wp);
 static char *MembershipConfigurationToString(MembershipConfiguration *mconf);
 static void MembershipConfigurationCopy(MembershipConfiguration *src, MembershipConfiguration *dst);
 static void MembershipConfigurationFree(MembershipConfiguration *mconf);
+static bool IsWitness(WalProposer *wp, NNodeId node_id);
 
 WalProposer *
 WalProposerCreate(WalProposerConfig *config, walproposer_api api)
@@ -178,7 +179,7 @@ WalProposerCreate(WalProposerConfig *config, walproposer_api api)
 	}
 	wp->quorum = wp->n_safekeepers / 2 + 1;
 
-	if (wp->config->proto_version != 2 && wp->config->proto_version != 3)
+	if (wp->config->proto_version < 2 || wp->config->proto_version > 4)
 		wp_log(FATAL, "unsupported safekeeper protocol version %d", wp->config->proto_version);
 	if (wp->safekeepers_generation > INVALID_GENERATION && wp->config->proto_version < 3)
 		wp_log(FATAL, "enabling generations requires protocol version 3");
@@ -1124,12 +1125,14 @@ VotesCollectedMset(WalProposer *wp, MemberSet *mset, Safekeeper **msk, StringInf
 			 * Find the highest vote. NULL check is for the legacy case where
 			 * safekeeper might be not initialized with LSN at all and return
 			 * 0 LSN in the vote response; we still want to set donor to
-			 * something in this case.
+			 * something in this case. Witnesses don't store WAL, so they
+			 * can't be donors; see WitnessAheadOfDonor.
 			 */
-			if (GetLastLogTerm(sk) > wp->donorLastLogTerm ||
-				(GetLastLogTerm(sk) == wp->donorLastLogTerm &&
-				 sk->voteResponse.flushLsn > wp->propTermStartLsn) ||
-				wp->donor == NULL)
+			if (!IsWitness(wp, sk->greetResponse.nodeId) &&
+				(GetLastLogTerm(sk) > wp->donorLastLogTerm ||
+				 (GetLastLogTerm(sk) == wp->donorLastLogTerm &&
+				  sk->voteResponse.flushLsn > wp->propTermStartLsn) ||
+				 wp->donor == NULL))
 			{
 				wp->donorLastLogTerm = GetLastLogTerm(sk);
 				wp->propTermStartLsn = sk->voteResponse.flushLsn;
@@ -1147,6 +1150,35 @@ VotesCollectedMset(WalProposer *wp, MemberSet *mset, Safekeeper **msk, StringInf
 	return MsetHasQuorum(mset, n_votes);
 }
 
+/*
+ * Returns true if some witness voter has higher <last_log_term, flush_lsn>
+ * than the chosen donor. Witness might have acknowledged WAL which only
+ * some not yet voted member stores, so electing now could lose committed
+ * WAL; we must wait for more votes instead.
+ */
+static bool
+WitnessAheadOfDonor(WalProposer *wp)
+{
+	for (int i = 0; i < wp->n_safekeepers; i++)
+	{
+		Safekeeper *sk = &wp->safekeeper[i];
+
+		if (sk->state != SS_WAIT_ELECTED || !IsWitness(wp, sk->greetResponse.nodeId))
+			continue;
+		if (wp->donor == NULL ||
+			GetLastLogTerm(sk) > wp->donorLastLogTerm ||
+			(GetLastLogTerm(sk) == wp->donorLastLogTerm &&
+			 sk->voteResponse.flushLsn > wp->propTermStartLsn))
+		{
+			wp_log(LOG, "witness sk %lu has vote <%lu, %X/%X> ahead of the best storing voter, waiting for more votes",
+				   sk->greetResponse.nodeId, GetLastLogTerm(sk),
+				   LSN_FORMAT_ARGS(sk->voteResponse.flushLsn));
+			return true;
+		}
+	}
+	return false;
+}
+
 
 /*
  * Checks if enough votes has been collected to get elected and if that's the
@@ -1216,6 +1248,8 @@ VotesCollected(WalProposer *wp)
 		if (!VotesCollectedMset(wp, &wp->mconf.new_members, wp->new_members_safekeepers, &s))
 			goto res;
 	}
+	if (WitnessAheadOfDonor(wp))
+		goto res;
 	wp_log(LOG, "walproposer elected, %s", s.data);
 	collected = true;
 
@@ -1887,6 +1921,18 @@ ParsePageserverFeedbackMessage(WalProposer *wp, StringInfo reply_message, Pagese
 			ps_feedback->shard_number = pq_getmsgint(reply_message, sizeof(uint32));
 			psfeedback_log("%u", key, ps_feedback->shard_number);
 		}
+		else if (strcmp(key, "ps_ingest_throttled") == 0)
+		{
+			Assert(value_len == sizeof(uint32));
+			ps_feedback->ingest_throttled = pq_getmsgint(reply_message, sizeof(uint32)) != 0;
+			psfeedback_log("%d", key, ps_feedback->ingest_throttled);
+		}
+		else if (strcmp(key, "sk_wal_backpressure") == 0)
+		{
+			Assert(value_len == sizeof(uint32));
+			ps_feedback->sk_wal_backpressure = pq_getmsgint(reply_message, sizeof(uint32)) != 0;
+			psfeedback_log("%d", key, ps_feedback->sk_wal_backpressure);
+		}
 		else
 		{
 			/*
@@ -2038,7 +2084,7 @@ UpdateDonorShmem(WalProposer *wp)
 	 * about its position immediately after election before any feedbacks are
 	 * sent.
 	 */
-	if (wp->donor->state >= SS_WAIT_ELECTED)
+	if (wp->donor->state >= SS_WAIT_ELECTED && !IsWitness(wp, wp->donor->greetResponse.nodeId))
 	{
 		donor = wp->donor;
 		donor_lsn = wp->propTermStartLsn;
@@ -2053,7 +2099,8 @@ UpdateDonorShmem(WalProposer *wp)
 	{
 		Safekeeper *sk = &wp->safekeeper[i];
 
-		if (sk->state == SS_ACTIVE && sk->appendResponse.flushLsn > donor_lsn)
+		if (sk->state == SS_ACTIVE && sk->appendResponse.flushLsn > donor_lsn &&
+			!IsWitness(wp, sk->greetResponse.nodeId))
 		{
 			donor = sk;
 			donor_lsn = sk->appendResponse.flushLsn;
@@ -2157,7 +2204,7 @@ HandleSafekeeperResponse(WalProposer *wp, Safekeeper *fromsk)
 
 /* Serialize MembershipConfiguration into buf. */
 static void
-MembershipConfigurationSerialize(MembershipConfiguration *mconf, StringInfo buf)
+MembershipConfigurationSerialize(MembershipConfiguration *mconf, StringInfo buf, int proto_version)
 {
 	uint32		i;
 
@@ -2182,18 +2229,28 @@ MembershipConfigurationSerialize(MembershipConfiguration *mconf, StringInfo buf)
 		pq_send_ascii_string(buf, mconf->new_members.m[i].host);
 		pq_sendint16(buf, mconf->new_members.m[i].port);
 	}
+
+	if (proto_version >= 4)
+	{
+		pq_sendint32(buf, mconf->n_witnesses);
+		for (i = 0; i < mconf->n_witnesses; i++)
+			pq_sendint64(buf, mconf->witnesses[i]);
+	}
 }
 
 /* Serialize proposer -> acceptor message into buf using specified version */
 static void
 PAMessageSerialize(WalProposer *wp, ProposerAcceptorMessage *msg, StringInfo buf, int proto_version)
 {
-	/* both version are supported currently until we fully migrate to 3 */
-	Assert(proto_version == 3 || proto_version == 2);
+	/*
+	 * both version are supported currently until we fully migrate to 3; 4 is
+	 * 3 with witnesses in mconf
+	 */
+	Assert(proto_version >= 2 && proto_version <= 4);
 
 	resetStringInfo(buf);
 
-	if (proto_version == 3)
+	if (proto_version >= 3)
 	{
 		/*
 		 * v2 sends structs for some messages as is, so commonly send tag only
@@ -2209,7 +2266,7 @@ PAMessageSerialize(WalProposer *wp, ProposerAcceptorMessage *msg, StringInfo buf
 
 					pq_send_ascii_string(buf, m->tenant_id);
 					pq_send_ascii_string(buf, m->timeline_id);
-					MembershipConfigurationSerialize(&m->mconf, buf);
+					MembershipConfigurationSerialize(&m->mconf, buf, proto_version);
 					pq_sendint32(buf, m->pg_version);
 					pq_sendint64(buf, m->system_id);
 					pq_sendint32(buf, m->wal_seg_size);
@@ -2394,7 +2451,7 @@ AsyncRead(Safekeeper *sk, char **buf, int *buf_size)
 
 /* Deserialize membership configuration from buf to mconf. */
 static void
-MembershipConfigurationDeserialize(MembershipConfiguration *mconf, StringInfo buf)
+MembershipConfigurationDeserialize(MembershipConfiguration *mconf, StringInfo buf, int proto_version)
 {
 	uint32		i;
 
@@ -2421,6 +2478,15 @@ MembershipConfigurationDeserialize(MembershipConfiguration *mconf, StringInfo bu
 		strlcpy(mconf->new_members.m[i].host, buf_host, sizeof(mconf->new_members.m[i].host));
 		mconf->new_members.m[i].port = pq_getmsgint16(buf);
 	}
+	mconf->n_witnesses = 0;
+	mconf->witnesses = NULL;
+	if (proto_version >= 4)
+	{
+		mconf->n_witnesses = pq_getmsgint32(buf);
+		mconf->witnesses = palloc0(sizeof(NNodeId) * mconf->n_witnesses);
+		for (i = 0; i < mconf->n_witnesses; i++)
+			mconf->witnesses[i] = pq_getmsgint64(buf);
+	}
 }
 
 /*
@@ -2454,7 +2520,7 @@ AsyncReadMessage(Safekeeper *sk, AcceptorProposerMessage *anymsg)
 	s.maxlen = buf_size;
 	s.cursor = 0;
 
-	if (wp->config->proto_version == 3)
+	if (wp->config->proto_version >= 3)
 	{
 		tag = pq_getmsgbyte(&s);
 		if (tag != anymsg->tag)
@@ -2471,7 +2537,7 @@ AsyncReadMessage(Safekeeper *sk, AcceptorProposerMessage *anymsg)
 					AcceptorGreeting *msg = (AcceptorGreeting *) anymsg;
 
 					msg->nodeId = pq_getmsgint64(&s);
-					MembershipConfigurationDeserialize(&msg->mconf, &s);
+					MembershipConfigurationDeserialize(&msg->mconf, &s, wp->config->proto_version);
 					msg->term = pq_getmsgint64(&s);
 					pq_getmsgend(&s);
 					return true;
@@ -2992,6 +3058,13 @@ MembershipConfigurationToString(MembershipConfiguration *mconf)
 		appendStringInfo(&s, ", host = %s", mconf->new_members.m[i].host);
 		appendStringInfo(&s, ", port = %u }", mconf->new_members.m[i].port);
 	}
+	appendStringInfo(&s, "], witnesses = [");
+	for (i = 0; i < mconf->n_witnesses; i++)
+	{
+		if (i > 0)
+			appendStringInfoString(&s, ", ");
+		appendStringInfo(&s, "%lu", mconf->witnesses[i]);
+	}
 	appendStringInfoString(&s, "]}");
 	return s.data;
 }
@@ -3006,6 +3079,9 @@ MembershipConfigurationCopy(MembershipConfiguration *src, MembershipConfiguratio
 	dst->new_members.len = src->new_members.len;
 	dst->new_members.m = palloc0(sizeof(SafekeeperId) * dst->new_members.len);
 	memcpy(dst->new_members.m, src->new_members.m, sizeof(SafekeeperId) * dst->new_members.len);
+	dst->n_witnesses = src->n_witnesses;
+	dst->witnesses = palloc0(sizeof(NNodeId) * dst->n_witnesses);
+	memcpy(dst->witnesses, src->witnesses, sizeof(NNodeId) * dst->n_witnesses);
 }
 
 static void
@@ -3017,4 +3093,20 @@ MembershipConfigurationFree(MembershipConfiguration *mconf)
 	if (mconf->new_members.m)
 		pfree(mconf->new_members.m);
 	mconf->new_members.m = NULL;
+	if (mconf->witnesses)
+		pfree(mconf->witnesses);
+	mconf->witnesses = NULL;
+	mconf->n_witnesses = 0;
+}
+
+/* Is node_id a witness in the current configuration? */
+static bool
+IsWitness(WalProposer *wp, NNodeId node_id)
+{
+	for (uint32 i = 0; i < wp->mconf.n_witnesses; i++)
+	{
+		if (wp->mconf.witnesses[i] == node_id)
+			return true;
+	}
+	return false;
 }
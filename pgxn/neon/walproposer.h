@@ -171,6 +171,13 @@ typedef struct MembershipConfiguration
 	MemberSet	members;
 	/* Has 0 n_members in non joint conf. */
 	MemberSet	new_members;
+
+	/*
+	 * Members (of either set) which vote but don't store WAL; sent only in
+	 * protocol version 4.
+	 */
+	uint32		n_witnesses;
+	NNodeId    *witnesses;
 } MembershipConfiguration;
 
 /*
@@ -374,6 +381,10 @@ typedef struct PageserverFeedback
 	XLogRecPtr	remote_consistent_lsn;
 	TimestampTz replytime;
 	uint32		shard_number;
+	/* true if the pageserver is holding back WAL ingest due to tenant limits */
+	bool		ingest_throttled;
+	/* true if the safekeeper is close to its retained WAL quota */
+	bool		sk_wal_backpressure;
 } PageserverFeedback;
 
 /* BEGIN_HADRON */
@@ -418,6 +429,9 @@ typedef struct WalproposerShmemState
 	/* aggregated feedback with min LSNs across shards */
 	PageserverFeedback min_ps_feedback;
 
+	/* true if any active safekeeper asks to throttle WAL generation */
+	pg_atomic_uint32 sk_wal_backpressure;
+
 	/* BEGIN_HADRON */
 	/* The WAL rate limiter */
 	WalRateLimiter wal_rate_limiter;
@@ -240,7 +240,7 @@ nwp_register_gucs(void)
 	DefineCustomIntVariable(
 							"neon.safekeeper_proto_version",
 							"Version of compute <-> safekeeper protocol.",
-							"Used while migrating from 2 to 3.",
+							"Used while migrating from 2 to 3; 4 is 3 with witness safekeepers support.",
 							&safekeeper_proto_version,
 							3, 0, INT_MAX,
 							PGC_POSTMASTER,
@@ -434,12 +434,16 @@ backpressure_lag_impl(void)
 		}
 	}
 
+	/* Safekeeper retains WAL close to its quota */
+	state = GetWalpropShmemState();
+	if (state != NULL && pg_atomic_read_u32(&state->sk_wal_backpressure))
+		return 1;
+
 	/* BEGIN_HADRON */
 	if (databricks_max_wal_mb_per_second == -1) {
 		return 0;
 	}
 
-	state = GetWalpropShmemState();
 	if (state != NULL && !!pg_atomic_read_u32(&state->wal_rate_limiter.should_limit))
 	{
 		TimestampTz now = GetCurrentTimestamp();
@@ -502,6 +506,7 @@ WalproposerShmemInit(void)
 		pg_atomic_init_u64(&walprop_shared->mineLastElectedTerm, 0);
 		pg_atomic_init_u64(&walprop_shared->backpressureThrottlingTime, 0);
 		pg_atomic_init_u64(&walprop_shared->currentClusterSize, 0);
+		pg_atomic_init_u32(&walprop_shared->sk_wal_backpressure, 0);
 		/* BEGIN_HADRON */
 		pg_atomic_init_u32(&walprop_shared->wal_rate_limiter.should_limit, 0);
 		pg_atomic_init_u64(&walprop_shared->wal_rate_limiter.last_recorded_time_us, 0);
@@ -518,6 +523,7 @@ WalproposerShmemInit_SyncSafekeeper(void)
 	pg_atomic_init_u64(&walprop_shared->propEpochStartLsn, 0);
 	pg_atomic_init_u64(&walprop_shared->mineLastElectedTerm, 0);
 	pg_atomic_init_u64(&walprop_shared->backpressureThrottlingTime, 0);
+	pg_atomic_init_u32(&walprop_shared->sk_wal_backpressure, 0);
 	/* BEGIN_HADRON */
 	pg_atomic_init_u32(&walprop_shared->wal_rate_limiter.should_limit, 0);
 	pg_atomic_init_u64(&walprop_shared->wal_rate_limiter.last_recorded_time_us, 0);
@@ -670,6 +676,10 @@ record_pageserver_feedback(PageserverFeedback *ps_feedback)
 
 			if (min_feedback.remote_consistent_lsn == InvalidXLogRecPtr || feedback->remote_consistent_lsn < min_feedback.remote_consistent_lsn)
 				min_feedback.remote_consistent_lsn = feedback->remote_consistent_lsn;
+
+			/* ingest is throttled if it is throttled on any shard */
+			if (feedback->ingest_throttled)
+				min_feedback.ingest_throttled = true;
 		}
 	}
 	/* Copy min_feedback back to shmem */
@@ -1997,12 +2007,34 @@ CombineHotStanbyFeedbacks(HotStandbyFeedback *hs, WalProposer *wp)
 	}
 }
 
+/*
+ * Safekeepers retaining WAL close to their quota ask to throttle WAL
+ * generation. Do so while any active safekeeper asks.
+ */
+static void
+UpdateSafekeeperBackpressure(WalProposer *wp)
+{
+	bool		backpressure = false;
+
+	for (int i = 0; i < wp->n_safekeepers; i++)
+	{
+		Safekeeper *sk = &wp->safekeeper[i];
+
+		if (sk->state == SS_ACTIVE &&
+			sk->appendResponse.ps_feedback.present &&
+			sk->appendResponse.ps_feedback.sk_wal_backpressure)
+			backpressure = true;
+	}
+	pg_atomic_write_u32(&walprop_shared->sk_wal_backpressure, backpressure);
+}
+
 /*
  * Based on commitLsn and safekeeper responses including pageserver feedback,
  * 1) Propagate cluster size received from ps to ensure the limit.
  * 2) Propagate pageserver LSN positions to ensure backpressure limits.
  * 3) Advance walproposer slot to commitLsn (releasing WAL & waking up waiters).
  * 4) Propagate hot standby feedback.
+ * 5) Propagate safekeeper WAL quota backpressure.
  *
  * None of that is functional in sync-safekeepers.
  */
@@ -2015,8 +2047,14 @@ walprop_pg_process_safekeeper_feedback(WalProposer *wp, Safekeeper *sk)
 	if (wp->config->syncSafekeepers)
 		return;
 
-	/* handle fresh ps_feedback */
-	if (sk->appendResponse.ps_feedback.present)
+	UpdateSafekeeperBackpressure(wp);
+
+	/*
+	 * handle fresh ps_feedback; feedback without pageserver LSNs only carries
+	 * safekeeper flags
+	 */
+	if (sk->appendResponse.ps_feedback.present &&
+		sk->appendResponse.ps_feedback.last_received_lsn != InvalidXLogRecPtr)
 	{
 		PageserverFeedback min_feedback = record_pageserver_feedback(&sk->appendResponse.ps_feedback);
 
@@ -3,6 +3,7 @@
 
 use super::{ComputeCredentials, ComputeUserInfo};
 use crate::auth::backend::ComputeCredentialKeys;
+use crate::auth::backend::jwt::FetchAuthRules;
 use crate::auth::{self, AuthFlow};
 use crate::config::AuthenticationConfig;
 use crate::context::RequestContext;
@@ -54,3 +55,50 @@ pub(super) async fn authenticate(
         )),
     })
 }
+
+/// Like [`authenticate`], but also offer OAUTHBEARER, with the token checked
+/// against the endpoint's JWKS rules.
+pub(super) async fn authenticate_with_oauth(
+    ctx: &RequestContext,
+    creds: ComputeUserInfo,
+    client: &mut PqStream<Stream<impl AsyncRead + AsyncWrite + Unpin>>,
+    config: &'static AuthenticationConfig,
+    secret: AuthSecret,
+    api: &impl FetchAuthRules,
+) -> auth::Result<ComputeCredentials> {
+    let AuthSecret::Scram(secret) = secret;
+
+    let endpoint = creds.endpoint.clone();
+    let role = &creds.user;
+    let validate = |token: String| async move {
+        config
+            .jwks_cache
+            .check_jwt(ctx, endpoint, role, api, &token)
+            .await
+            .map_err(auth::AuthError::from)
+    };
+
+    let flow = auth::ScramOrOAuthBearer {
+        secret: &secret,
+        ctx,
+        validate,
+    };
+    let auth_outcome = tokio::time::timeout(
+        config.scram_protocol_timeout,
+        AuthFlow::new(client, flow).authenticate(),
+    )
+    .await
+    .inspect_err(|_| warn!("error processing sasl messages error = authentication timed out, execution time exceeded {} seconds", config.scram_protocol_timeout.as_secs()))
+    .map_err(auth::AuthError::user_timeout)?
+    .inspect_err(|error| warn!(?error, "error processing sasl messages"))?;
+
+    let keys = match auth_outcome {
+        sasl::Outcome::Success(keys) => keys,
+        sasl::Outcome::Failure(reason) => {
+            info!("auth backend failed with an error: {reason}");
+            return Err(auth::AuthError::password_failed(&*creds.user));
+        }
+    };
+
+    Ok(ComputeCredentials { info: creds, keys })
+}
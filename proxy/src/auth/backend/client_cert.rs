@@ -0,0 +1,227 @@
+//! Authentication with TLS client certificates.
+//!
+//! The proxy cannot verify client certificates during the TLS handshake,
+//! as the CAs to trust are configured per endpoint, and the endpoint is only known
+//! after the handshake. The handshake proves that the client owns the key
+//! of the certificate, and the chain is verified here against the CA bundle
+//! the control plane returns for the endpoint.
+//!
+//! A verified certificate authenticates the role equal to its common name
+//! or to one of its DNS, email or URI subject alternative names.
+
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::Context;
+use rustls::RootCertStore;
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, UnixTime};
+use rustls::server::WebPkiClientVerifier;
+use rustls::server::danger::ClientCertVerifier;
+use thiserror::Error;
+use x509_cert::Certificate;
+use x509_cert::der::asn1::{Ia5StringRef, PrintableStringRef, Utf8StringRef};
+use x509_cert::der::{Decode, oid};
+use x509_cert::ext::pkix::SubjectAltName;
+use x509_cert::ext::pkix::name::GeneralName;
+
+use crate::error::{ErrorKind, ReportableError, UserFacingError};
+use crate::tls::ClientCertChain;
+use crate::types::RoleName;
+
+/// The client CA configuration of an endpoint.
+pub struct ClientCa {
+    verifier: Arc<dyn ClientCertVerifier>,
+    /// Whether connections without a valid certificate are rejected,
+    /// rather than falling back to password authentication.
+    required: bool,
+}
+
+impl fmt::Debug for ClientCa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientCa")
+            .field("required", &self.required)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ClientCa {
+    /// Parse a PEM bundle of CA certificates.
+    pub(crate) fn parse(pem: &str, required: bool) -> anyhow::Result<Self> {
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut pem.as_bytes()) {
+            let cert = cert.context("could not parse client CA bundle")?;
+            roots.add(cert).context("invalid client CA certificate")?;
+        }
+
+        let verifier = WebPkiClientVerifier::builder_with_provider(
+            Arc::new(roots),
+            Arc::new(ring::default_provider()),
+        )
+        .build()
+        .context("could not build client certificate verifier")?;
+
+        Ok(Self { verifier, required })
+    }
+
+    pub(crate) fn is_required(&self) -> bool {
+        self.required
+    }
+
+    /// Check that the chain is signed by one of the CAs and names the role.
+    pub(crate) fn authenticate(
+        &self,
+        chain: Option<&ClientCertChain>,
+        role: &RoleName,
+    ) -> Result<(), ClientCertError> {
+        let Some((leaf, intermediates)) = chain.and_then(|chain| chain.0.split_first()) else {
+            return Err(ClientCertError::Missing);
+        };
+
+        self.verifier
+            .verify_client_cert(leaf, intermediates, UnixTime::now())
+            .map_err(ClientCertError::Untrusted)?;
+
+        if !identities(leaf)?.iter().any(|name| name == role.as_str()) {
+            return Err(ClientCertError::RoleMismatch(role.clone()));
+        }
+
+        Ok(())
+    }
+}
+
+/// The names a certificate can authenticate as: its common names and subject alternative names.
+fn identities(cert: &CertificateDer<'_>) -> Result<Vec<String>, ClientCertError> {
+    let cert = Certificate::from_der(cert)?;
+    let tbs = &cert.tbs_certificate;
+
+    let mut names = vec![];
+    for atv in tbs.subject.0.iter().flat_map(|rdn| rdn.0.iter()) {
+        if atv.oid != oid::db::rfc4519::CN {
+            continue;
+        }
+        let cn = if let Ok(s) = atv.value.decode_as::<Utf8StringRef<'_>>() {
+            s.as_str().to_owned()
+        } else if let Ok(s) = atv.value.decode_as::<PrintableStringRef<'_>>() {
+            s.as_str().to_owned()
+        } else {
+            atv.value
+                .decode_as::<Ia5StringRef<'_>>()?
+                .as_str()
+                .to_owned()
+        };
+        names.push(cn);
+    }
+
+    if let Some((_, SubjectAltName(sans))) = tbs.get::<SubjectAltName>()? {
+        for san in sans {
+            match san {
+                GeneralName::DnsName(name)
+                | GeneralName::Rfc822Name(name)
+                | GeneralName::UniformResourceIdentifier(name) => names.push(name.to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    Ok(names)
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum ClientCertError {
+    #[error("a client certificate is required to connect to this endpoint")]
+    Missing,
+
+    #[error("client certificate is not trusted: {0}")]
+    Untrusted(rustls::Error),
+
+    #[error("malformed client certificate")]
+    Malformed(#[from] x509_cert::der::Error),
+
+    #[error("client certificate is not valid for role '{0}'")]
+    RoleMismatch(RoleName),
+
+    /// The proxy has no credentials to send to compute for these sessions.
+    #[error("client certificate authentication is not set up for this endpoint")]
+    ComputeUntrusted,
+}
+
+impl ReportableError for ClientCertError {
+    fn get_error_kind(&self) -> ErrorKind {
+        ErrorKind::User
+    }
+}
+
+impl UserFacingError for ClientCertError {
+    fn to_string_client(&self) -> String {
+        self.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Pki {
+        ca_pem: String,
+        ca: rcgen::Certificate,
+        ca_key: rcgen::KeyPair,
+    }
+
+    impl Pki {
+        fn new() -> Self {
+            let ca_key = rcgen::KeyPair::generate().unwrap();
+            let mut params = rcgen::CertificateParams::default();
+            params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+            let ca = params.self_signed(&ca_key).unwrap();
+            Self {
+                ca_pem: ca.pem(),
+                ca,
+                ca_key,
+            }
+        }
+
+        fn client_cert(&self, common_name: &str, sans: &[&str]) -> ClientCertChain {
+            let key = rcgen::KeyPair::generate().unwrap();
+            let sans = sans.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            let mut params = rcgen::CertificateParams::new(sans).unwrap();
+            params.distinguished_name = rcgen::DistinguishedName::new();
+            params
+                .distinguished_name
+                .push(rcgen::DnType::CommonName, common_name);
+            params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+            let cert = params.signed_by(&key, &self.ca, &self.ca_key).unwrap();
+            ClientCertChain(Arc::new([cert.der().clone()]))
+        }
+    }
+
+    #[test]
+    fn maps_certificate_to_role() {
+        let pki = Pki::new();
+        let ca = ClientCa::parse(&pki.ca_pem, true).unwrap();
+
+        let chain = pki.client_cert("alice", &["reporting.example.com"]);
+        ca.authenticate(Some(&chain), &"alice".into()).unwrap();
+        ca.authenticate(Some(&chain), &"reporting.example.com".into())
+            .unwrap();
+
+        let err = ca.authenticate(Some(&chain), &"bob".into()).unwrap_err();
+        assert!(matches!(err, ClientCertError::RoleMismatch(_)));
+
+        let err = ca.authenticate(None, &"alice".into()).unwrap_err();
+        assert!(matches!(err, ClientCertError::Missing));
+    }
+
+    #[test]
+    fn rejects_other_cas() {
+        let trusted = Pki::new();
+        let other = Pki::new();
+        let ca = ClientCa::parse(&trusted.ca_pem, true).unwrap();
+
+        let chain = other.client_cert("alice", &[]);
+        let err = ca.authenticate(Some(&chain), &"alice".into()).unwrap_err();
+        assert!(matches!(err, ClientCertError::Untrusted(_)));
+
+        assert!(ClientCa::parse("not a certificate", false).is_err());
+    }
+}
@@ -217,6 +217,7 @@ async fn authenticate(
         NodeInfo {
             conn_info,
             aux: db_info.aux,
+            read_replicas: Vec::new(),
         },
         auth_info,
         user_info,
@@ -61,6 +61,8 @@ pub(crate) struct AuthRule {
     pub(crate) jwks_url: url::Url,
     pub(crate) audience: Option<String>,
     pub(crate) role_names: Vec<RoleNameInt>,
+    /// If set, the token must carry the role name in this claim.
+    pub(crate) role_claim: Option<String>,
 }
 
 pub struct JwkCache {
@@ -79,21 +81,17 @@ pub(crate) struct JwkCacheEntry {
 }
 
 impl JwkCacheEntry {
-    fn find_jwk_and_audience(
+    fn find_jwk_and_key_set(
         &self,
         key_id: &str,
         role_name: &RoleName,
-    ) -> Option<(&jose_jwk::Jwk, Option<&str>)> {
+    ) -> Option<(&jose_jwk::Jwk, &KeySet)> {
         self.key_sets
             .values()
             // make sure our requested role has access to the key set
             .filter(|key_set| key_set.role_names.iter().any(|role| **role == **role_name))
             // try and find the requested key-id in the key set
-            .find_map(|key_set| {
-                key_set
-                    .find_key(key_id)
-                    .map(|jwk| (jwk, key_set.audience.as_deref()))
-            })
+            .find_map(|key_set| key_set.find_key(key_id).map(|jwk| (jwk, key_set)))
     }
 }
 
@@ -101,6 +99,7 @@ struct KeySet {
     jwks: jose_jwk::JwkSet,
     audience: Option<String>,
     role_names: Vec<RoleNameInt>,
+    role_claim: Option<String>,
 }
 
 impl KeySet {
@@ -262,6 +261,7 @@ async fn renew_jwks<F: FetchAuthRules>(
                         jwks,
                         audience: rule.audience,
                         role_names: rule.role_names,
+                        role_claim: rule.role_claim,
                     },
                 );
             }
@@ -368,8 +368,8 @@ async fn check_jwt<F: FetchAuthRules>(
             .await?;
 
         // get the key from the JWKs if possible. If not, wait for the keys to update.
-        let (jwk, expected_audience) = loop {
-            match guard.find_jwk_and_audience(&kid, role_name) {
+        let (jwk, key_set) = loop {
+            match guard.find_jwk_and_key_set(&kid, role_name) {
                 Some(jwk) => break jwk,
                 None if guard.last_retrieved.elapsed() > MIN_RENEW => {
                     let _paused = ctx.latency_timer_pause(crate::metrics::Waiting::Compute);
@@ -399,7 +399,7 @@ async fn check_jwt<F: FetchAuthRules>(
 
         tracing::debug!(?payload, "JWT signature valid with claims");
 
-        if let Some(aud) = expected_audience
+        if let Some(aud) = &key_set.audience
             && payload.audience.0.iter().all(|s| s != aud)
         {
             return Err(JwtError::InvalidClaims(
@@ -431,6 +431,14 @@ async fn check_jwt<F: FetchAuthRules>(
             ));
         }
 
+        if let Some(claim) = &key_set.role_claim {
+            let claims =
+                serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(&payloadb)?;
+            if claims.get(claim).and_then(|v| v.as_str()) != Some(role_name.as_str()) {
+                return Err(JwtError::InvalidClaims(JwtClaimsError::InvalidJwtTokenRole));
+            }
+        }
+
         Ok(ComputeCredentialKeys::JwtPayload(payloadb))
     }
 }
@@ -768,6 +776,9 @@ pub enum JwtClaimsError {
 
     #[error("JWT token is not yet ready to use (nbf={0})")]
     JwtTokenNotYetReadyToUse(u64),
+
+    #[error("JWT token is not valid for this role")]
+    InvalidJwtTokenRole,
 }
 
 #[allow(dead_code, reason = "Debug use only")]
@@ -1048,12 +1059,14 @@ async fn check_jwt_happy_path() {
                 jwks_url: format!("http://{jwks_addr}/foo").parse().unwrap(),
                 audience: None,
                 role_names: roles.clone(),
+                role_claim: None,
             },
             AuthRule {
                 id: "bar".to_owned(),
                 jwks_url: format!("http://{jwks_addr}/bar").parse().unwrap(),
                 audience: None,
                 role_names: roles.clone(),
+                role_claim: None,
             },
         ];
 
@@ -1122,6 +1135,7 @@ async fn check_jwt_regression_cognito_issuer() {
             jwks_url: format!("http://{jwks_addr}/").parse().unwrap(),
             audience: None,
             role_names: vec![RoleNameInt::from(&role_name)],
+            role_claim: None,
         }];
 
         let fetch = Fetch(rules);
@@ -1163,6 +1177,7 @@ async fn check_jwt_invalid_signature() {
             jwks_url: format!("http://{jwks_addr}/").parse().unwrap(),
             audience: None,
             role_names: vec![RoleNameInt::from(&role)],
+            role_claim: None,
         }];
 
         let fetch = Fetch(rules);
@@ -1199,6 +1214,7 @@ async fn check_jwt_unknown_role() {
             jwks_url: format!("http://{jwks_addr}/").parse().unwrap(),
             audience: None,
             role_names: vec![RoleNameInt::from(&role)],
+            role_claim: None,
         }];
 
         let fetch = Fetch(rules);
@@ -1295,6 +1311,7 @@ struct Test {
             jwks_url: format!("http://{jwks_addr}/").parse().unwrap(),
             audience: Some("neon".to_string()),
             role_names: vec![RoleNameInt::from(&role)],
+            role_claim: None,
         }];
 
         let fetch = Fetch(rules);
@@ -1321,6 +1338,61 @@ struct Test {
         }
     }
 
+    #[tokio::test]
+    async fn check_jwt_role_claim() {
+        let (key, jwk) = new_ec_jwk("1".into());
+
+        let jwks = jose_jwk::JwkSet { keys: vec![jwk] };
+        let jwks_addr = jwks_server(move |path| match path {
+            "/" => Some(serde_json::to_vec(&jwks).unwrap()),
+            _ => None,
+        })
+        .await;
+
+        let role = RoleName::from("alice");
+        let rules = vec![AuthRule {
+            id: String::new(),
+            jwks_url: format!("http://{jwks_addr}/").parse().unwrap(),
+            audience: None,
+            role_names: vec![
+                RoleNameInt::from(&role),
+                RoleNameInt::from(&RoleName::from("bob")),
+            ],
+            role_claim: Some("preferred_username".to_owned()),
+        }];
+
+        let fetch = Fetch(rules);
+        let jwk_cache = JwkCache::default();
+
+        let ep = EndpointId::from("ep");
+
+        let ctx = RequestContext::test();
+        let jwt = new_custom_ec_jwt("1".into(), &key, json! {{"preferred_username": "alice"}});
+        jwk_cache
+            .check_jwt(&ctx, ep.clone(), &role, &fetch, &jwt)
+            .await
+            .unwrap();
+
+        for body in [
+            json! {{"preferred_username": "bob"}},
+            json! {{"preferred_username": ["alice"]}},
+            json! {{"sub": "alice"}},
+        ] {
+            let jwt = new_custom_ec_jwt("1".into(), &key, body);
+            let err = jwk_cache
+                .check_jwt(&ctx, ep.clone(), &role, &fetch, &jwt)
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    JwtError::InvalidClaims(JwtClaimsError::InvalidJwtTokenRole)
+                ),
+                "expected \"not valid for this role\", got {err:?}"
+            );
+        }
+    }
+
     #[tokio::test]
     async fn check_jwk_keycloak_regression() {
         let (rs, valid_jwk) = new_rsa_jwk(RS1, "rs1".into());
@@ -1359,6 +1431,7 @@ async fn check_jwk_keycloak_regression() {
             jwks_url: format!("http://{jwks_addr}/").parse().unwrap(),
             audience: None,
             role_names: vec![role],
+            role_claim: None,
         }];
 
         let fetch = Fetch(rules);
@@ -44,6 +44,7 @@ pub fn new(postgres_addr: SocketAddr, compute_ctl: ApiUrl) -> Self {
                     compute_id: "local".into(),
                     cold_start_info: ColdStartInfo::WarmCached,
                 },
+                read_replicas: Vec::new(),
             },
         }
     }
@@ -71,6 +72,7 @@ async fn fetch_auth_rules(
                 jwks_url: setting.jwks_url.clone(),
                 audience: setting.jwt_audience.clone(),
                 role_names: setting.role_names.clone(),
+                role_claim: setting.role_claim.clone(),
             });
         }
 
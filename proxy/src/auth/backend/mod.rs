@@ -1,4 +1,5 @@
 mod classic;
+pub(crate) mod client_cert;
 mod console_redirect;
 mod hacks;
 pub mod jwt;
@@ -6,8 +7,10 @@
 
 use std::sync::Arc;
 
+use client_cert::ClientCertError;
 pub use console_redirect::ConsoleRedirectBackend;
 pub(crate) use console_redirect::ConsoleRedirectError;
+use jwt::FetchAuthRules;
 use local::LocalBackend;
 use postgres_client::config::AuthKeys;
 use serde::{Deserialize, Serialize};
@@ -31,6 +34,7 @@
 use crate::proxy::wake_compute::WakeComputeBackend;
 use crate::rate_limiter::EndpointRateLimiter;
 use crate::stream::Stream;
+use crate::tls::ClientCertChain;
 use crate::types::{EndpointCacheKey, EndpointId, RoleName};
 use crate::{scram, stream};
 
@@ -74,6 +78,10 @@ fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                     .debug_tuple("ControlPlane::ProxyV1")
                     .field(&endpoint.url())
                     .finish(),
+                ControlPlaneClient::File(api) => fmt
+                    .debug_tuple("ControlPlane::File")
+                    .field(&api.path())
+                    .finish(),
                 #[cfg(any(test, feature = "testing"))]
                 ControlPlaneClient::PostgresMock(endpoint) => {
                     let url = endpoint.url();
@@ -171,6 +179,8 @@ pub(crate) fn endpoint_cache_key(&self) -> EndpointCacheKey {
 pub(crate) enum ComputeCredentialKeys {
     AuthKeys(AuthKeys),
     JwtPayload(Vec<u8>),
+    /// Authenticated by the proxy with a client certificate, compute must trust the proxy.
+    ClientCertificate,
 }
 
 impl TryFrom<ComputeUserInfoMaybeEndpoint> for ComputeUserInfo {
@@ -198,10 +208,11 @@ fn try_from(user_info: ComputeUserInfoMaybeEndpoint) -> Result<Self, Self::Error
 /// All authentication flows will emit an AuthenticationOk message if successful.
 async fn auth_quirks(
     ctx: &RequestContext,
-    api: &impl control_plane::ControlPlaneApi,
+    api: &(impl control_plane::ControlPlaneApi + FetchAuthRules),
     user_info: ComputeUserInfoMaybeEndpoint,
     client: &mut stream::PqStream<Stream<impl AsyncRead + AsyncWrite + Unpin>>,
     allow_cleartext: bool,
+    client_cert: Option<&ClientCertChain>,
     config: &'static AuthenticationConfig,
     endpoint_rate_limiter: Arc<EndpointRateLimiter>,
 ) -> auth::Result<ComputeCredentials> {
@@ -232,6 +243,26 @@ async fn auth_quirks(
 
     access_controls.connection_attempt_rate_limit(ctx, &info.endpoint, &endpoint_rate_limiter)?;
 
+    if let Some(ca) = &access_controls.client_ca {
+        let res = if access_controls.compute_trusts_proxy {
+            ca.authenticate(client_cert, &info.user)
+        } else {
+            Err(ClientCertError::ComputeUntrusted)
+        };
+        match res {
+            Ok(()) => {
+                ctx.set_auth_method(crate::context::AuthMethod::ClientCertificate);
+                client.write_message(BeMessage::AuthenticationOk);
+                return Ok(ComputeCredentials {
+                    info,
+                    keys: ComputeCredentialKeys::ClientCertificate,
+                });
+            }
+            Err(e) if ca.is_required() => return Err(e.into()),
+            Err(e) => debug!(error = %e, "client certificate rejected, falling back to password"),
+        }
+    }
+
     let role_access = api
         .get_role_access_control(ctx, &info.endpoint, &info.user)
         .await?;
@@ -246,6 +277,16 @@ async fn auth_quirks(
         AuthSecret::Scram(scram::ServerSecret::mock(rand::random()))
     };
 
+    // Clients that can do SASL may pick OAUTHBEARER instead of SCRAM.
+    // The token is passed on with pg_session_jwt, which needs compute to trust us.
+    if config.accept_oauthbearer
+        && access_controls.compute_trusts_proxy
+        && unauthenticated_password.is_none()
+        && !allow_cleartext
+    {
+        return classic::authenticate_with_oauth(ctx, info, client, config, secret, api).await;
+    }
+
     match authenticate_with_secret(
         ctx,
         secret,
@@ -319,6 +360,7 @@ pub(crate) async fn authenticate(
         ctx: &RequestContext,
         client: &mut stream::PqStream<Stream<impl AsyncRead + AsyncWrite + Unpin>>,
         allow_cleartext: bool,
+        client_cert: Option<&ClientCertChain>,
         config: &'static AuthenticationConfig,
         endpoint_rate_limiter: Arc<EndpointRateLimiter>,
     ) -> auth::Result<Backend<'a, ComputeCredentials>> {
@@ -336,6 +378,7 @@ pub(crate) async fn authenticate(
                     user_info.clone(),
                     client,
                     allow_cleartext,
+                    client_cert,
                     config,
                     endpoint_rate_limiter,
                 )
@@ -397,6 +440,9 @@ pub(crate) async fn get_endpoint_access_control(
                 allowed_vpce: Arc::new(vec![]),
                 flags: AccessBlockerFlags::default(),
                 rate_limits: EndpointRateLimitConfig::default(),
+                client_ca: None,
+                compute_trusts_proxy: false,
+                sql_firewall: None,
             }),
         }
     }
@@ -431,7 +477,7 @@ mod tests {
     use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 
     use super::auth_quirks;
-    use super::jwt::JwkCache;
+    use super::jwt::{FetchAuthRules, FetchAuthRulesError, JwkCache};
     use crate::auth::{ComputeUserInfoMaybeEndpoint, IpPattern};
     use crate::config::AuthenticationConfig;
     use crate::context::RequestContext;
@@ -445,10 +491,12 @@ mod tests {
     use crate::scram::threadpool::ThreadPool;
     use crate::stream::{PqStream, Stream};
 
+    #[derive(Clone)]
     struct Auth {
         ips: Vec<IpPattern>,
         vpc_endpoint_ids: Vec<String>,
         access_blocker_flags: AccessBlockerFlags,
+        compute_trusts_proxy: bool,
         secret: AuthSecret,
     }
 
@@ -475,6 +523,9 @@ async fn get_endpoint_access_control(
                 allowed_vpce: Arc::new(self.vpc_endpoint_ids.clone()),
                 flags: self.access_blocker_flags,
                 rate_limits: EndpointRateLimitConfig::default(),
+                client_ca: None,
+                compute_trusts_proxy: self.compute_trusts_proxy,
+                sql_firewall: None,
             })
         }
 
@@ -496,6 +547,16 @@ async fn wake_compute(
         }
     }
 
+    impl FetchAuthRules for Auth {
+        async fn fetch_auth_rules(
+            &self,
+            _ctx: &RequestContext,
+            _endpoint: crate::types::EndpointId,
+        ) -> Result<Vec<super::jwt::AuthRule>, FetchAuthRulesError> {
+            Ok(vec![])
+        }
+    }
+
     static CONFIG: Lazy<AuthenticationConfig> = Lazy::new(|| AuthenticationConfig {
         jwks_cache: JwkCache::default(),
         thread_pool: ThreadPool::new(1),
@@ -504,6 +565,19 @@ async fn wake_compute(
         is_vpc_acccess_proxy: false,
         is_auth_broker: false,
         accept_jwts: false,
+        accept_oauthbearer: false,
+        console_redirect_confirmation_timeout: std::time::Duration::from_secs(5),
+    });
+
+    static OAUTHBEARER_CONFIG: Lazy<AuthenticationConfig> = Lazy::new(|| AuthenticationConfig {
+        jwks_cache: JwkCache::default(),
+        thread_pool: ThreadPool::new(1),
+        scram_protocol_timeout: std::time::Duration::from_secs(5),
+        ip_allowlist_check_enabled: true,
+        is_vpc_acccess_proxy: false,
+        is_auth_broker: false,
+        accept_jwts: false,
+        accept_oauthbearer: true,
         console_redirect_confirmation_timeout: std::time::Duration::from_secs(5),
     });
 
@@ -526,6 +600,7 @@ async fn auth_quirks_scram() {
             ips: vec![],
             vpc_endpoint_ids: vec![],
             access_blocker_flags: AccessBlockerFlags::default(),
+            compute_trusts_proxy: true,
             secret: AuthSecret::Scram(ServerSecret::build("my-secret-password").await.unwrap()),
         };
 
@@ -586,6 +661,7 @@ async fn auth_quirks_scram() {
             user_info,
             &mut stream,
             false,
+            None,
             &CONFIG,
             endpoint_rate_limiter,
         )
@@ -608,6 +684,7 @@ async fn auth_quirks_cleartext() {
             ips: vec![],
             vpc_endpoint_ids: vec![],
             access_blocker_flags: AccessBlockerFlags::default(),
+            compute_trusts_proxy: true,
             secret: AuthSecret::Scram(ServerSecret::build("my-secret-password").await.unwrap()),
         };
 
@@ -643,6 +720,7 @@ async fn auth_quirks_cleartext() {
             user_info,
             &mut stream,
             true,
+            None,
             &CONFIG,
             endpoint_rate_limiter,
         )
@@ -662,6 +740,7 @@ async fn auth_quirks_password_hack() {
             ips: vec![],
             vpc_endpoint_ids: vec![],
             access_blocker_flags: AccessBlockerFlags::default(),
+            compute_trusts_proxy: true,
             secret: AuthSecret::Scram(ServerSecret::build("my-secret-password").await.unwrap()),
         };
 
@@ -698,6 +777,7 @@ async fn auth_quirks_password_hack() {
             user_info,
             &mut stream,
             true,
+            None,
             &CONFIG,
             endpoint_rate_limiter,
         )
@@ -708,4 +788,83 @@ async fn auth_quirks_password_hack() {
 
         handle.await.unwrap();
     }
+
+    #[tokio::test]
+    async fn auth_quirks_oauthbearer_bad_token() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let mut stream = PqStream::new_skip_handshake(Stream::from_raw(server));
+
+        let ctx = RequestContext::test();
+        let api = Auth {
+            ips: vec![],
+            vpc_endpoint_ids: vec![],
+            access_blocker_flags: AccessBlockerFlags::default(),
+            compute_trusts_proxy: true,
+            secret: AuthSecret::Scram(ServerSecret::build("my-secret-password").await.unwrap()),
+        };
+
+        let user_info = ComputeUserInfoMaybeEndpoint {
+            user: "conrad".into(),
+            endpoint_id: Some("endpoint".into()),
+            options: NeonOptions::default(),
+        };
+
+        let handle = tokio::spawn(async move {
+            let mut read = BytesMut::new();
+
+            // server should offer both scram and oauthbearer
+            match read_message(&mut client, &mut read).await {
+                PgMessage::AuthenticationSasl(a) => {
+                    let options: Vec<&str> = a.mechanisms().collect().unwrap();
+                    assert_eq!(options, ["SCRAM-SHA-256", "OAUTHBEARER"]);
+                }
+                _ => panic!("wrong message"),
+            }
+
+            // client sends its token
+            let mut write = BytesMut::new();
+            frontend::sasl_initial_response(
+                "OAUTHBEARER",
+                b"n,,\x01auth=Bearer not-a-jwt\x01\x01",
+                &mut write,
+            )
+            .unwrap();
+            client.write_all(&write).await.unwrap();
+
+            // server rejects the token
+            match read_message(&mut client, &mut read).await {
+                PgMessage::AuthenticationSaslContinue(a) => {
+                    assert_eq!(a.data(), br#"{"status":"invalid_token"}"#);
+                }
+                _ => panic!("wrong message"),
+            }
+
+            // client acknowledges the error
+            write.clear();
+            frontend::sasl_response(b"\x01", &mut write).unwrap();
+            client.write_all(&write).await.unwrap();
+        });
+
+        let endpoint_rate_limiter = Arc::new(EndpointRateLimiter::new_with_shards(
+            EndpointRateLimiter::DEFAULT,
+            64,
+        ));
+
+        let err = auth_quirks(
+            &ctx,
+            &api,
+            user_info,
+            &mut stream,
+            false,
+            None,
+            &OAUTHBEARER_CONFIG,
+            endpoint_rate_limiter,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(err, crate::auth::AuthError::Jwt(_)), "{err:?}");
+
+        handle.await.unwrap();
+    }
 }
@@ -456,6 +456,29 @@ fn parse_neon_options() -> anyhow::Result<()> {
         Ok(())
     }
 
+    #[test]
+    fn parse_target_session_attrs() -> anyhow::Result<()> {
+        let options = StartupMessageParams::new([
+            ("user", "john_doe"),
+            ("target_session_attrs", "read-only"),
+        ]);
+
+        let sni = Some("project.localhost");
+        let common_names = Some(["localhost".into()].into());
+        let ctx = RequestContext::test();
+        let user_info =
+            ComputeUserInfoMaybeEndpoint::parse(&ctx, &options, sni, common_names.as_ref())?;
+        assert_eq!(
+            user_info.options.get_cache_key("project"),
+            "project target_session_attrs:read-only"
+        );
+        assert!(!user_info.options.is_ephemeral());
+        // routing to replicas is handled by the proxy, not cplane.
+        assert!(user_info.options.to_deep_object().is_empty());
+
+        Ok(())
+    }
+
     #[test]
     fn test_check_peer_addr_is_in_list() {
         fn check(v: serde_json::Value) -> bool {
@@ -13,6 +13,7 @@
 use crate::intern::EndpointIdInt;
 use crate::pqproto::{BeAuthenticationSaslMessage, BeMessage};
 use crate::sasl;
+use crate::sasl::oauthbearer::{self, OAUTHBEARER};
 use crate::scram::threadpool::ThreadPool;
 use crate::scram::{self};
 use crate::stream::{PqStream, Stream};
@@ -37,6 +38,14 @@ fn first_message(&self, channel_binding: bool) -> BeMessage<'_> {
     }
 }
 
+/// Let the client choose between [SCRAM](crate::scram) and OAUTHBEARER in [`AuthFlow`].
+pub(crate) struct ScramOrOAuthBearer<'a, V> {
+    pub(crate) secret: &'a scram::ServerSecret,
+    pub(crate) ctx: &'a RequestContext,
+    /// Checks the bearer token and returns the keys to connect to compute with.
+    pub(crate) validate: V,
+}
+
 /// Use an ad hoc auth flow (for clients which don't support SNI) proposed in
 /// <https://github.com/neondatabase/cloud/issues/1620#issuecomment-1165332290>.
 pub(crate) struct PasswordHack;
@@ -143,25 +152,120 @@ pub(crate) async fn authenticate(self) -> super::Result<sasl::Outcome<scram::Scr
 
         // complete sasl handshake.
         sasl::authenticate(ctx, self.stream, |method| {
-            // Currently, the only supported SASL method is SCRAM.
-            match method {
-                SCRAM_SHA_256 => ctx.set_auth_method(crate::context::AuthMethod::ScramSha256),
-                SCRAM_SHA_256_PLUS => {
-                    ctx.set_auth_method(crate::context::AuthMethod::ScramSha256Plus);
-                }
-                method => return Err(sasl::Error::BadAuthMethod(method.into())),
-            }
-
-            // TODO: make this a metric instead
-            info!("client chooses {}", method);
-
-            Ok(scram::Exchange::new(secret, rand::random, channel_binding))
+            scram_exchange(ctx, secret, method, channel_binding)
         })
         .await
         .map_err(AuthError::Sasl)
     }
 }
 
+/// Start the [SCRAM](crate::scram) exchange for the method chosen by the client.
+fn scram_exchange<'a>(
+    ctx: &RequestContext,
+    secret: &'a scram::ServerSecret,
+    method: &str,
+    channel_binding: TlsServerEndPoint,
+) -> sasl::Result<scram::Exchange<'a>> {
+    // Currently, the only supported SASL method is SCRAM.
+    match method {
+        SCRAM_SHA_256 => ctx.set_auth_method(crate::context::AuthMethod::ScramSha256),
+        SCRAM_SHA_256_PLUS => {
+            ctx.set_auth_method(crate::context::AuthMethod::ScramSha256Plus);
+        }
+        method => return Err(sasl::Error::BadAuthMethod(method.into())),
+    }
+
+    // TODO: make this a metric instead
+    info!("client chooses {}", method);
+
+    Ok(scram::Exchange::new(secret, rand::random, channel_binding))
+}
+
+impl<S, V, Fut> AuthFlow<'_, S, ScramOrOAuthBearer<'_, V>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    V: FnOnce(String) -> Fut,
+    Fut: Future<Output = super::Result<ComputeCredentialKeys>>,
+{
+    /// Perform user authentication. Raise an error in case authentication failed.
+    pub(crate) async fn authenticate(self) -> super::Result<sasl::Outcome<ComputeCredentialKeys>> {
+        let ScramOrOAuthBearer {
+            secret,
+            ctx,
+            validate,
+        } = self.state;
+        let channel_binding = self.tls_server_end_point;
+
+        let methods: &[&str] = if channel_binding.supported() {
+            &[SCRAM_SHA_256_PLUS, SCRAM_SHA_256, OAUTHBEARER]
+        } else {
+            &[SCRAM_SHA_256, OAUTHBEARER]
+        };
+
+        // send sasl message.
+        {
+            // pause the timer while we communicate with the client
+            let _paused = ctx.latency_timer_pause(crate::metrics::Waiting::Client);
+
+            let sasl = BeMessage::AuthenticationSasl(BeAuthenticationSaslMessage::Methods(methods));
+            self.stream.write_message(sasl);
+            self.stream.flush().await?;
+        }
+
+        let (method, input) = sasl::read_first_message(ctx, self.stream).await?;
+
+        if method != OAUTHBEARER {
+            let exchange = scram_exchange(ctx, secret, &method, channel_binding)?;
+            let outcome = match sasl::exchange(ctx, self.stream, exchange, &input).await? {
+                sasl::Outcome::Success(client_key) => {
+                    let keys = crate::compute::ScramKeys {
+                        client_key: client_key.as_bytes(),
+                        server_key: secret.server_key.as_bytes(),
+                    };
+                    sasl::Outcome::Success(ComputeCredentialKeys::AuthKeys(
+                        postgres_client::config::AuthKeys::ScramSha256(keys),
+                    ))
+                }
+                sasl::Outcome::Failure(reason) => sasl::Outcome::Failure(reason),
+            };
+            return Ok(outcome);
+        }
+
+        ctx.set_auth_method(crate::context::AuthMethod::OAuthBearer);
+
+        let result = match oauthbearer::parse_initial_response(&input)? {
+            Some(token) => validate(token.to_owned()).await.map(Some),
+            // discovery request: the client has no token yet.
+            None => Ok(None),
+        };
+
+        if let Ok(Some(keys)) = result {
+            self.stream.write_message(BeMessage::AuthenticationOk);
+            return Ok(sasl::Outcome::Success(keys));
+        }
+
+        // The exchange can only fail after the client has acknowledged our error response.
+        {
+            // pause the timer while we communicate with the client
+            let _paused = ctx.latency_timer_pause(crate::metrics::Waiting::Client);
+
+            let sasl =
+                BeAuthenticationSaslMessage::Continue(oauthbearer::ERROR_RESPONSE.as_bytes());
+            self.stream
+                .write_message(BeMessage::AuthenticationSasl(sasl));
+            self.stream.flush().await?;
+
+            let msg = self.stream.read_password_message().await?;
+            if !oauthbearer::is_error_ack(msg) {
+                return Err(sasl::Error::BadClientMessage("bad error acknowledgement").into());
+            }
+        }
+
+        result?;
+        Ok(sasl::Outcome::Failure("missing bearer token"))
+    }
+}
+
 pub(crate) async fn validate_password_and_exchange(
     pool: &ThreadPool,
     endpoint: EndpointIdInt,
@@ -21,6 +21,7 @@
 use thiserror::Error;
 use tokio::time::error::Elapsed;
 
+use crate::auth::backend::client_cert::ClientCertError;
 use crate::auth::backend::jwt::JwtError;
 use crate::control_plane;
 use crate::error::{ReportableError, UserFacingError};
@@ -95,6 +96,9 @@ pub(crate) enum AuthError {
 
     #[error(transparent)]
     Jwt(#[from] JwtError),
+
+    #[error(transparent)]
+    ClientCert(#[from] ClientCertError),
 }
 
 impl AuthError {
@@ -150,6 +154,7 @@ fn to_string_client(&self) -> String {
             Self::UserTimeout(_) => self.to_string(),
             Self::ConfirmationTimeout(_) => self.to_string(),
             Self::Jwt(_) => self.to_string(),
+            Self::ClientCert(e) => e.to_string_client(),
         }
     }
 }
@@ -173,6 +178,7 @@ fn get_error_kind(&self) -> crate::error::ErrorKind {
             Self::UserTimeout(_) => crate::error::ErrorKind::User,
             Self::ConfirmationTimeout(_) => crate::error::ErrorKind::User,
             Self::Jwt(_) => crate::error::ErrorKind::User,
+            Self::ClientCert(e) => e.get_error_kind(),
         }
     }
 }
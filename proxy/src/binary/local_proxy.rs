@@ -104,6 +104,22 @@ struct SqlOverHttpArgs {
 
     #[clap(long, default_value_t = 10 * 1024 * 1024)] // 10 MiB
     sql_over_http_max_response_size_bytes: usize,
+
+    /// Maximum number of rows in a JSON response. Unlimited if not set.
+    #[clap(long)]
+    sql_over_http_max_response_rows: Option<usize>,
+
+    /// Limits for NDJSON responses. example: "max_rows=1000000,max_bytes=1073741824".
+    #[clap(long, default_value = config::FormatLimits::STREAMING_DEFAULT_OPTIONS)]
+    sql_over_http_ndjson_limits: config::FormatLimits,
+
+    /// Limits for Arrow IPC stream responses. example: "max_rows=1000000,max_bytes=1073741824".
+    #[clap(long, default_value = config::FormatLimits::STREAMING_DEFAULT_OPTIONS)]
+    sql_over_http_arrow_limits: config::FormatLimits,
+
+    /// Limits for CSV responses. example: "max_rows=1000000,max_bytes=1073741824".
+    #[clap(long, default_value = config::FormatLimits::STREAMING_DEFAULT_OPTIONS)]
+    sql_over_http_csv_limits: config::FormatLimits,
 }
 
 pub async fn run() -> anyhow::Result<()> {
@@ -200,7 +216,10 @@ pub async fn run() -> anyhow::Result<()> {
         auth_backend,
         http_listener,
         shutdown.clone(),
-        Arc::new(CancellationHandler::new(&config.connect_to_compute)),
+        Arc::new(CancellationHandler::new(
+            &config.connect_to_compute,
+            String::new(),
+        )),
         endpoint_rate_limiter,
     );
 
@@ -249,11 +268,21 @@ fn build_config(args: &LocalProxyCliArgs) -> anyhow::Result<&'static ProxyConfig
 
             max_conns_per_endpoint: args.sql_over_http.sql_over_http_pool_max_total_conns,
             max_total_conns: args.sql_over_http.sql_over_http_pool_max_total_conns,
+            // the session state, including prepared statements, is discarded on every request.
+            statement_cache_size: 0,
         },
         cancel_set: CancelSet::new(args.sql_over_http.sql_over_http_cancel_set_shards),
         client_conn_threshold: args.sql_over_http.sql_over_http_client_conn_threshold,
         max_request_size_bytes: args.sql_over_http.sql_over_http_max_request_size_bytes,
-        max_response_size_bytes: args.sql_over_http.sql_over_http_max_response_size_bytes,
+        response_limits: config::ResponseLimits {
+            json: config::FormatLimits {
+                max_rows: args.sql_over_http.sql_over_http_max_response_rows,
+                max_bytes: args.sql_over_http.sql_over_http_max_response_size_bytes,
+            },
+            ndjson: args.sql_over_http.sql_over_http_ndjson_limits,
+            arrow: args.sql_over_http.sql_over_http_arrow_limits,
+            csv: args.sql_over_http.sql_over_http_csv_limits,
+        },
     };
 
     let compute_config = ComputeConfig {
@@ -274,9 +303,13 @@ fn build_config(args: &LocalProxyCliArgs) -> anyhow::Result<&'static ProxyConfig
             is_vpc_acccess_proxy: false,
             is_auth_broker: false,
             accept_jwts: true,
+            accept_oauthbearer: false,
             console_redirect_confirmation_timeout: Duration::ZERO,
         },
         proxy_protocol_v2: config::ProxyProtocolV2::Rejected,
+        pooling_mode: config::PoolingMode::Session,
+        max_prepared_statements: 0,
+        read_replicas: None,
         handshake_timeout: Duration::from_secs(10),
         wake_compute_retry_config: RetryConfig::parse(RetryConfig::WAKE_COMPUTE_DEFAULT_VALUES)?,
         connect_compute_locks,
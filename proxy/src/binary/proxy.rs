@@ -10,7 +10,6 @@
 use anyhow::Context;
 use anyhow::{bail, ensure};
 use arc_swap::ArcSwapOption;
-#[cfg(any(test, feature = "testing"))]
 use camino::Utf8PathBuf;
 use futures::future::Either;
 use itertools::{Itertools, Position};
@@ -30,6 +29,7 @@
 use crate::auth::backend::local::LocalBackend;
 use crate::auth::backend::{ConsoleRedirectBackend, MaybeOwned};
 use crate::batch::BatchQueue;
+use crate::cancellation::peers::CancellationPeers;
 use crate::cancellation::{CancellationHandler, CancellationProcessor};
 #[cfg(any(test, feature = "testing"))]
 use crate::config::refresh_config_loop;
@@ -40,6 +40,7 @@
 use crate::context::parquet::ParquetUploadArgs;
 use crate::http::health_server::AppMetrics;
 use crate::metrics::Metrics;
+use crate::proxy::replicas::ReplicaBalancer;
 use crate::rate_limiter::{EndpointRateLimiter, RateBucketInfo, WakeComputeRateLimiter};
 use crate::redis::connection_with_credentials_provider::ConnectionWithCredentialsProvider;
 use crate::redis::kv_ops::RedisKVClient;
@@ -66,6 +67,9 @@ enum AuthBackendType {
     #[clap(alias("link"))]
     ConsoleRedirect,
 
+    /// Serve endpoints from a static config file, see `--control-plane-file`.
+    File,
+
     #[cfg(any(test, feature = "testing"))]
     Postgres,
 
@@ -116,6 +120,12 @@ struct ProxyCliArgs {
         env = "NEON_PROXY_TO_CONTROLPLANE_TOKEN"
     )]
     control_plane_token: Arc<str>,
+    /// Path of the TOML or JSON file describing endpoints (used for file auth backend)
+    #[clap(long, default_value = "./endpoints.toml")]
+    control_plane_file: Utf8PathBuf,
+    /// How often to check the control plane file for changes
+    #[clap(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    control_plane_file_poll_interval: Duration,
     /// if this is not local proxy, this toggles whether we accept jwt or passwords for http
     #[clap(long, default_value_t = false, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
     is_auth_broker: bool,
@@ -132,6 +142,12 @@ struct ProxyCliArgs {
     /// Allow writing TLS session keys to the given file pointed to by the environment variable `SSLKEYLOGFILE`.
     #[clap(long, alias = "allow-ssl-keylogfile")]
     allow_tls_keylogfile: bool,
+    /// Ask clients for a TLS certificate, to authenticate endpoints that have a client CA configured.
+    #[clap(long, default_value_t = false, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
+    accept_client_certificates: bool,
+    /// Offer OAUTHBEARER SASL authentication, validating bearer tokens against the endpoint's JWKS settings.
+    #[clap(long, default_value_t = false, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
+    accept_oauthbearer: bool,
     /// path to directory with TLS certificates for client postgres connections
     #[clap(long)]
     certs_dir: Option<PathBuf>,
@@ -170,6 +186,17 @@ struct ProxyCliArgs {
     /// Cancellation ops batch size for redis
     #[clap(long, default_value_t = 8)]
     cancellation_batch_size: usize,
+    /// Listen address for cancel key lookups from other proxy instances.
+    /// Enables query cancellation across instances without redis
+    #[clap(long)]
+    cancellation_peer_listen: Option<SocketAddr>,
+    /// Comma separated `host:port` of the other instances' `--cancellation-peer-listen`.
+    /// Hosts are resolved periodically, a DNS name can have a record for each instance
+    #[clap(long, value_delimiter = ',')]
+    cancellation_peers: Vec<String>,
+    /// Secret the proxy instances use to authenticate cancel key lookups
+    #[clap(long, default_value = "", env = "NEON_PROXY_CANCELLATION_PEER_SECRET")]
+    cancellation_peer_secret: Arc<str>,
     /// redis url for plain authentication
     #[clap(long, alias("redis-notifications"))]
     redis_plain: Option<String>,
@@ -236,6 +263,25 @@ struct ProxyCliArgs {
     #[clap(value_enum, long, default_value_t = ProxyProtocolV2::Rejected)]
     proxy_protocol_v2: ProxyProtocolV2,
 
+    /// How postgres client connections are mapped onto compute connections.
+    /// Transaction pooling shares compute connections between clients, using the sql-over-http pool limits.
+    #[clap(value_enum, long, default_value_t = config::PoolingMode::Session)]
+    pooling_mode: config::PoolingMode,
+
+    /// With transaction pooling, how many prepared statements to keep on each compute connection.
+    /// The least recently used one is closed to make room for more.
+    #[clap(long, default_value_t = 200, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    max_prepared_statements: usize,
+
+    /// Route read-only connections to the read replicas of an endpoint.
+    /// Clients opt in with `target_session_attrs`, or `Neon-Read-Only: true` over http.
+    #[clap(long, default_value_t = false, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
+    read_replica_routing: bool,
+
+    /// How long a read replica is avoided after a failed connection attempt.
+    #[clap(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    read_replica_cooldown: std::time::Duration,
+
     /// Time the proxy waits for the webauth session to be confirmed by the control plane.
     // TODO: rename to `console_redirect_confirmation_timeout`.
     #[clap(long, default_value = "2m", value_parser = humantime::parse_duration)]
@@ -286,6 +332,10 @@ struct SqlOverHttpArgs {
     #[clap(long, default_value_t = 128)]
     sql_over_http_pool_shards: usize,
 
+    /// How many prepared statements to cache on each pooled connection. 0 disables the cache
+    #[clap(long, default_value_t = 64)]
+    sql_over_http_statement_cache_size: usize,
+
     #[clap(long, default_value_t = 10000)]
     sql_over_http_client_conn_threshold: u64,
 
@@ -297,6 +347,22 @@ struct SqlOverHttpArgs {
 
     #[clap(long, default_value_t = 10 * 1024 * 1024)] // 10 MiB
     sql_over_http_max_response_size_bytes: usize,
+
+    /// Maximum number of rows in a JSON response. Unlimited if not set.
+    #[clap(long)]
+    sql_over_http_max_response_rows: Option<usize>,
+
+    /// Limits for NDJSON responses. example: "max_rows=1000000,max_bytes=1073741824".
+    #[clap(long, default_value = config::FormatLimits::STREAMING_DEFAULT_OPTIONS)]
+    sql_over_http_ndjson_limits: config::FormatLimits,
+
+    /// Limits for Arrow IPC stream responses. example: "max_rows=1000000,max_bytes=1073741824".
+    #[clap(long, default_value = config::FormatLimits::STREAMING_DEFAULT_OPTIONS)]
+    sql_over_http_arrow_limits: config::FormatLimits,
+
+    /// Limits for CSV responses. example: "max_rows=1000000,max_bytes=1073741824".
+    #[clap(long, default_value = config::FormatLimits::STREAMING_DEFAULT_OPTIONS)]
+    sql_over_http_csv_limits: config::FormatLimits,
 }
 
 #[derive(clap::Args, Clone, Debug)]
@@ -403,7 +469,28 @@ pub async fn run() -> anyhow::Result<()> {
 
     let cancellation_token = CancellationToken::new();
 
-    let cancellation_handler = Arc::new(CancellationHandler::new(&config.connect_to_compute));
+    let cancellation_handler = Arc::new(CancellationHandler::new(
+        &config.connect_to_compute,
+        args.region.clone(),
+    ));
+
+    let cancellation_peers = match args.cancellation_peer_listen {
+        Some(addr) => {
+            info!("Starting cancellation peer listener on {addr}");
+            let listener = TcpListener::bind(addr).await?.into_std()?;
+            let peers = Arc::new(CancellationPeers::new(
+                args.cancellation_peers.clone(),
+                args.cancellation_peer_secret.clone(),
+            )?);
+            cancellation_handler.init_peers(peers.clone());
+            Some((peers, listener))
+        }
+        None if !args.cancellation_peers.is_empty() => {
+            bail!("cancellation-peers requires cancellation-peer-listen to be set")
+        }
+        None => None,
+    };
+    let peer_cancellation = cancellation_peers.is_some();
 
     let endpoint_rate_limiter = Arc::new(EndpointRateLimiter::new_with_shards(
         RateBucketInfo::to_leaky_bucket(&args.endpoint_rps_limit)
@@ -517,38 +604,46 @@ pub async fn run() -> anyhow::Result<()> {
     ));
     maintenance_tasks.spawn(control_plane::mgmt::task_main(mgmt_listener));
 
+    if let Some((peers, listener)) = cancellation_peers {
+        maintenance_tasks.spawn(peers.clone().task_main(listener));
+        maintenance_tasks.spawn(peers.discovery_task());
+    }
+
     if let Some(metrics_config) = &config.metric_collection {
         // TODO: Add gc regardles of the metric collection being enabled.
         maintenance_tasks.spawn(usage_metrics::task_main(metrics_config));
     }
 
     if let Some(client) = redis_client {
-        // Try to connect to Redis 3 times with 1 + (0..0.1) second interval.
-        // This prevents immediate exit and pod restart,
-        // which can cause hammering of the redis in case of connection issues.
-        // cancellation key management
-        let mut redis_kv_client = RedisKVClient::new(client.clone());
-        for attempt in (0..3).with_position() {
-            match redis_kv_client.try_connect().await {
-                Ok(()) => {
-                    info!("Connected to Redis KV client");
-                    cancellation_handler.init_tx(BatchQueue::new(CancellationProcessor {
-                        client: redis_kv_client,
-                        batch_size: args.cancellation_batch_size,
-                    }));
-
-                    break;
-                }
-                Err(e) => {
-                    error!("Failed to connect to Redis KV client: {e}");
-                    if matches!(attempt, Position::Last(_)) {
-                        bail!(
-                            "Failed to connect to Redis KV client after {} attempts",
-                            attempt.into_inner()
-                        );
+        // cancel keys are stored in redis unless the peers share them.
+        if !peer_cancellation {
+            // Try to connect to Redis 3 times with 1 + (0..0.1) second interval.
+            // This prevents immediate exit and pod restart,
+            // which can cause hammering of the redis in case of connection issues.
+            // cancellation key management
+            let mut redis_kv_client = RedisKVClient::new(client.clone());
+            for attempt in (0..3).with_position() {
+                match redis_kv_client.try_connect().await {
+                    Ok(()) => {
+                        info!("Connected to Redis KV client");
+                        cancellation_handler.init_tx(BatchQueue::new(CancellationProcessor {
+                            client: redis_kv_client,
+                            batch_size: args.cancellation_batch_size,
+                        }));
+
+                        break;
+                    }
+                    Err(e) => {
+                        error!("Failed to connect to Redis KV client: {e}");
+                        if matches!(attempt, Position::Last(_)) {
+                            bail!(
+                                "Failed to connect to Redis KV client after {} attempts",
+                                attempt.into_inner()
+                            );
+                        }
+                        let jitter = thread_rng().gen_range(0..100);
+                        tokio::time::sleep(Duration::from_millis(1000 + jitter)).await;
                     }
-                    let jitter = thread_rng().gen_range(0..100);
-                    tokio::time::sleep(Duration::from_millis(1000 + jitter)).await;
                 }
             }
         }
@@ -599,6 +694,7 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
             cert_path,
             args.certs_dir.as_deref(),
             args.allow_tls_keylogfile,
+            args.accept_client_certificates,
         )?),
         (None, None) => None,
         _ => bail!("either both or neither tls-key and tls-cert must be specified"),
@@ -656,11 +752,20 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
             idle_timeout: args.sql_over_http.sql_over_http_idle_timeout,
             opt_in: args.sql_over_http.sql_over_http_pool_opt_in,
             max_total_conns: args.sql_over_http.sql_over_http_pool_max_total_conns,
+            statement_cache_size: args.sql_over_http.sql_over_http_statement_cache_size,
         },
         cancel_set: CancelSet::new(args.sql_over_http.sql_over_http_cancel_set_shards),
         client_conn_threshold: args.sql_over_http.sql_over_http_client_conn_threshold,
         max_request_size_bytes: args.sql_over_http.sql_over_http_max_request_size_bytes,
-        max_response_size_bytes: args.sql_over_http.sql_over_http_max_response_size_bytes,
+        response_limits: config::ResponseLimits {
+            json: config::FormatLimits {
+                max_rows: args.sql_over_http.sql_over_http_max_response_rows,
+                max_bytes: args.sql_over_http.sql_over_http_max_response_size_bytes,
+            },
+            ndjson: args.sql_over_http.sql_over_http_ndjson_limits,
+            arrow: args.sql_over_http.sql_over_http_arrow_limits,
+            csv: args.sql_over_http.sql_over_http_csv_limits,
+        },
     };
     let authentication_config = AuthenticationConfig {
         jwks_cache: JwkCache::default(),
@@ -670,6 +775,7 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
         is_vpc_acccess_proxy: args.is_private_access_proxy,
         is_auth_broker: args.is_auth_broker,
         accept_jwts: args.is_auth_broker,
+        accept_oauthbearer: args.accept_oauthbearer,
         console_redirect_confirmation_timeout: args.webauth_confirmation_timeout,
     };
 
@@ -685,6 +791,11 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
         http_config,
         authentication_config,
         proxy_protocol_v2: args.proxy_protocol_v2,
+        pooling_mode: args.pooling_mode,
+        max_prepared_statements: args.max_prepared_statements,
+        read_replicas: args
+            .read_replica_routing
+            .then(|| ReplicaBalancer::new(args.read_replica_cooldown)),
         handshake_timeout: args.handshake_timeout,
         wake_compute_retry_config: config::RetryConfig::parse(&args.wake_compute_retry)?,
         connect_compute_locks,
@@ -783,6 +894,19 @@ fn build_auth_backend(
             Ok(Either::Left(config))
         }
 
+        AuthBackendType::File => {
+            let api = control_plane::client::file::FileControlPlane::new(
+                args.control_plane_file.clone(),
+            )?;
+            tokio::spawn(api.clone().watch(args.control_plane_file_poll_interval));
+
+            let api = control_plane::client::ControlPlaneClient::File(api);
+            let auth_backend = auth::Backend::ControlPlane(MaybeOwned::Owned(api), ());
+            let config = Box::leak(Box::new(auth_backend));
+
+            Ok(Either::Left(config))
+        }
+
         AuthBackendType::ConsoleRedirect => {
             let wake_compute_cache_config: CacheOptions = args.wake_compute_cache.parse()?;
             let project_info_cache_config: ProjectInfoCacheOptions =
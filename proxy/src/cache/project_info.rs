@@ -401,6 +401,9 @@ async fn test_project_info_cache_settings() {
                 allowed_vpce: Arc::new(vec![]),
                 flags: AccessBlockerFlags::default(),
                 rate_limits: EndpointRateLimitConfig::default(),
+                client_ca: None,
+                compute_trusts_proxy: false,
+                sql_firewall: None,
             },
             RoleAccessControl {
                 secret: secret1.clone(),
@@ -417,6 +420,9 @@ async fn test_project_info_cache_settings() {
                 allowed_vpce: Arc::new(vec![]),
                 flags: AccessBlockerFlags::default(),
                 rate_limits: EndpointRateLimitConfig::default(),
+                client_ca: None,
+                compute_trusts_proxy: false,
+                sql_firewall: None,
             },
             RoleAccessControl {
                 secret: secret2.clone(),
@@ -449,6 +455,9 @@ async fn test_project_info_cache_settings() {
                 allowed_vpce: Arc::new(vec![]),
                 flags: AccessBlockerFlags::default(),
                 rate_limits: EndpointRateLimitConfig::default(),
+                client_ca: None,
+                compute_trusts_proxy: false,
+                sql_firewall: None,
             },
             RoleAccessControl {
                 secret: secret3.clone(),
@@ -575,6 +584,9 @@ async fn test_caching_project_info_errors() {
                 allowed_vpce: Arc::new(vec![]),
                 flags: AccessBlockerFlags::default(),
                 rate_limits: EndpointRateLimitConfig::default(),
+                client_ca: None,
+                compute_trusts_proxy: false,
+                sql_firewall: None,
             },
             RoleAccessControl {
                 secret: secret.clone(),
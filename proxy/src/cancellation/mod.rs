@@ -1,6 +1,8 @@
+pub mod peers;
+
 use std::convert::Infallible;
 use std::net::{IpAddr, SocketAddr};
-use std::pin::pin;
+use std::pin::{Pin, pin};
 use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 
@@ -10,8 +12,10 @@
 use postgres_client::tls::MakeTlsConnect;
 use redis::{Cmd, FromRedisValue, SetExpiry, SetOptions, Value};
 use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
 use thiserror::Error;
 use tokio::net::TcpStream;
+use tokio::sync::{oneshot, watch};
 use tokio::time::timeout;
 use tracing::{debug, error, info};
 
@@ -24,12 +28,14 @@
 use crate::error::ReportableError;
 use crate::ext::LockExt;
 use crate::metrics::{CancelChannelSizeGuard, CancellationRequest, Metrics, RedisMsgKind};
-use crate::pqproto::CancelKeyData;
+use crate::pqproto::{CancelKey, CancelKeyData, ProtocolVersion};
 use crate::rate_limiter::LeakyBucketRateLimiter;
 use crate::redis::keys::KeyPrefix;
 use crate::redis::kv_ops::{RedisKVClient, RedisKVClientError};
 use crate::util::run_until;
 
+use self::peers::CancellationPeers;
+
 type IpSubnetKey = IpNet;
 
 /// Initial period and TTL is shorter to clear keys of short-lived connections faster.
@@ -38,6 +44,50 @@
 /// `CANCEL_KEY_TTL_SLACK` is added to the periods to determine the actual TTL.
 const CANCEL_KEY_TTL_SLACK: Duration = Duration::from_secs(30);
 
+/// Protocol 3.2 clients accept cancel keys longer than the 8 bytes of 3.0.
+const LONG_CANCEL_KEYS_VERSION: ProtocolVersion = ProtocolVersion::new(3, 2);
+
+/// Format of the [`CancelKey::ext`] we give to protocol 3.2 clients:
+///
+/// ```text
+/// version     u8 = 1
+/// secret      [u8; 32], random
+/// region_len  u8
+/// region      [u8; region_len], region of the proxy that issued the key
+/// ```
+const CANCEL_KEY_EXT_V1: u8 = 1;
+const CANCEL_KEY_EXT_SECRET_LEN: usize = 32;
+/// The whole secret, including the 4 bytes in [`CancelKeyData`], is at most 256 bytes.
+const CANCEL_KEY_EXT_MAX_REGION_LEN: usize = 256 - 4 - 1 - CANCEL_KEY_EXT_SECRET_LEN - 1;
+
+fn new_cancel_key_ext(region: &str) -> Box<[u8]> {
+    // keys still work without routing info, so leave out regions that don't fit.
+    let region = if region.len() <= CANCEL_KEY_EXT_MAX_REGION_LEN {
+        region.as_bytes()
+    } else {
+        &[]
+    };
+
+    let mut ext = Vec::with_capacity(2 + CANCEL_KEY_EXT_SECRET_LEN + region.len());
+    ext.push(CANCEL_KEY_EXT_V1);
+    ext.extend_from_slice(&rand::random::<[u8; CANCEL_KEY_EXT_SECRET_LEN]>());
+    ext.push(region.len() as u8);
+    ext.extend_from_slice(region);
+    ext.into_boxed_slice()
+}
+
+/// The region encoded in a [`CancelKey::ext`], if there is one.
+fn cancel_key_ext_region(ext: &[u8]) -> Option<&str> {
+    let (&CANCEL_KEY_EXT_V1, rest) = ext.split_first()? else {
+        return None;
+    };
+    let (&len, region) = rest.get(CANCEL_KEY_EXT_SECRET_LEN..)?.split_first()?;
+    if region.len() != usize::from(len) {
+        return None;
+    }
+    std::str::from_utf8(region).ok().filter(|r| !r.is_empty())
+}
+
 // Message types for sending through mpsc channel
 pub enum CancelKeyOp {
     Store {
@@ -195,12 +245,16 @@ async fn apply(&mut self, batch: Vec<Self::Req>) -> Result<Vec<Self::Res>, Self:
 
 /// Enables serving `CancelRequest`s.
 ///
-/// If `CancellationPublisher` is available, cancel request will be used to publish the cancellation key to other proxy instances.
+/// Cancel keys are shared with other proxy instances through redis or, without redis, by asking the peer that issued them.
 pub struct CancellationHandler {
     compute_config: &'static ComputeConfig,
+    /// Encoded in the long cancel keys, to tell which region a session lives in.
+    region: String,
     // rate limiter of cancellation requests
     limiter: Arc<std::sync::Mutex<LeakyBucketRateLimiter<IpSubnetKey>>>,
     tx: OnceLock<BatchQueue<CancellationProcessor>>, // send messages to the redis KV client task
+    /// Used instead of redis, if set.
+    peers: OnceLock<Arc<CancellationPeers>>,
 }
 
 #[derive(Debug, Error)]
@@ -240,10 +294,12 @@ fn get_error_kind(&self) -> crate::error::ErrorKind {
 }
 
 impl CancellationHandler {
-    pub fn new(compute_config: &'static ComputeConfig) -> Self {
+    pub fn new(compute_config: &'static ComputeConfig, region: String) -> Self {
         Self {
             compute_config,
+            region,
             tx: OnceLock::new(),
+            peers: OnceLock::new(),
             limiter: Arc::new(std::sync::Mutex::new(
                 LeakyBucketRateLimiter::<IpSubnetKey>::new_with_shards(
                     LeakyBucketRateLimiter::<IpSubnetKey>::DEFAULT,
@@ -260,7 +316,14 @@ pub fn init_tx(&self, queue: BatchQueue<CancellationProcessor>) {
             .expect("cancellation queue should be registered once");
     }
 
-    pub(crate) fn get_key(self: Arc<Self>) -> Session {
+    pub fn init_peers(&self, peers: Arc<CancellationPeers>) {
+        self.peers
+            .set(peers)
+            .map_err(|_| {})
+            .expect("cancellation peers should be registered once");
+    }
+
+    pub(crate) fn get_key(self: Arc<Self>, version: ProtocolVersion) -> Session {
         // we intentionally generate a random "backend pid" and "secret key" here.
         // we use the corresponding u64 as an identifier for the
         // actual endpoint+pid+secret for postgres/pgbouncer.
@@ -268,7 +331,18 @@ pub(crate) fn get_key(self: Arc<Self>) -> Session {
         // if we forwarded the backend_pid from postgres to the client, there would be a lot
         // of overlap between our computes as most pids are small (~100).
 
-        let key: CancelKeyData = rand::random();
+        let data: CancelKeyData = match self.peers.get() {
+            // peers find the instance to ask from the key.
+            Some(peers) => peers.new_key(),
+            None => rand::random(),
+        };
+        let mut key = CancelKey::from(data);
+
+        // 64 bits can be brute-forced, so clients that support it get a longer secret.
+        // The session can then only be cancelled with the full key.
+        if version >= LONG_CANCEL_KEYS_VERSION {
+            key.ext = new_cancel_key_ext(&self.region);
+        }
 
         debug!("registered new query cancellation key {key}");
         Session {
@@ -281,9 +355,16 @@ pub(crate) fn get_key(self: Arc<Self>) -> Session {
     async fn get_cancel_key(
         &self,
         key: CancelKeyData,
-    ) -> Result<Option<CancelClosure>, CancelError> {
+    ) -> Result<Option<CancelKeyValue>, CancelError> {
         const TIMEOUT: Duration = Duration::from_secs(5);
 
+        if let Some(peers) = self.peers.get() {
+            return timeout(TIMEOUT, peers.get(key)).await.map_err(|_| {
+                tracing::warn!("timed out waiting for cancellation peers");
+                CancelError::InternalError
+            })?;
+        }
+
         let Some(tx) = self.tx.get() else {
             tracing::warn!("cancellation handler is not available");
             return Err(CancelError::InternalError);
@@ -342,13 +423,13 @@ async fn get_cancel_key(
             CancelError::InternalError
         })?;
 
-        let cancel_closure: CancelClosure =
+        let cancel_state: CancelKeyValue =
             serde_json::from_str(&cancel_state_str).map_err(|e| {
                 tracing::warn!("failed to deserialize cancel state: {e}");
                 CancelError::InternalError
             })?;
 
-        Ok(Some(cancel_closure))
+        Ok(Some(cancel_state))
     }
 
     /// Try to cancel a running query for the corresponding connection.
@@ -361,7 +442,7 @@ async fn get_cancel_key(
     /// This is not cancel safe
     pub(crate) async fn cancel_session<T: ControlPlaneApi>(
         &self,
-        key: CancelKeyData,
+        key: CancelKey,
         ctx: RequestContext,
         check_ip_allowed: bool,
         check_vpc_allowed: bool,
@@ -389,13 +470,43 @@ pub(crate) async fn cancel_session<T: ControlPlaneApi>(
             return Err(CancelError::RateLimit);
         }
 
-        let cancel_state = self.get_cancel_key(key).await.map_err(|e| {
+        if let Some(region) = cancel_key_ext_region(&key.ext)
+            && region != self.region
+        {
+            // the session is registered in that region's redis, not ours.
+            tracing::warn!(%region, "query cancellation key was issued in another region: {key}");
+            Metrics::get()
+                .proxy
+                .cancellation_requests_total
+                .inc(CancellationRequest {
+                    kind: crate::metrics::CancellationOutcome::NotFound,
+                });
+            return Err(CancelError::NotFound);
+        }
+
+        let cancel_state = self.get_cancel_key(key.data).await.map_err(|e| {
             tracing::warn!("failed to receive RedisOp response: {e}");
             CancelError::InternalError
         })?;
 
-        let Some(cancel_closure) = cancel_state else {
-            tracing::warn!("query cancellation key not found: {key}");
+        // sessions with a long key can only be cancelled with the full key.
+        let cancel_state = cancel_state.filter(|state| {
+            let ext = hex::encode(&key.ext);
+            let expected = state.key_ext.as_deref().unwrap_or_default();
+            expected.as_bytes().ct_eq(ext.as_bytes()).into()
+        });
+
+        let Some(CancelKeyValue {
+            closure: Some(cancel_closure),
+            ..
+        }) = cancel_state
+        else {
+            if cancel_state.is_some() {
+                // a transaction pooled session between transactions has nothing to cancel.
+                debug!("query cancellation key has no compute connection: {key}");
+            } else {
+                tracing::warn!("query cancellation key not found: {key}");
+            }
             Metrics::get()
                 .proxy
                 .cancellation_requests_total
@@ -449,6 +560,11 @@ pub(crate) fn new(
             user_info,
         }
     }
+    /// The backend process this closure cancels queries for.
+    pub(crate) fn process_id(&self) -> i32 {
+        self.cancel_token.process_id
+    }
+
     /// Cancels the query running on user's compute node.
     pub(crate) async fn try_cancel_query(
         &self,
@@ -468,15 +584,26 @@ pub(crate) async fn try_cancel_query(
     }
 }
 
+/// What is stored in redis for each cancel key.
+#[derive(Clone, Serialize, Deserialize)]
+struct CancelKeyValue {
+    /// Missing while a transaction pooled session holds no compute connection.
+    #[serde(flatten)]
+    closure: Option<CancelClosure>,
+    /// Hex encoded [`CancelKey::ext`] of the session, if it has a long key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_ext: Option<String>,
+}
+
 /// Helper for registering query cancellation tokens.
 pub(crate) struct Session {
     /// The user-facing key identifying this session.
-    key: CancelKeyData,
+    key: CancelKey,
     cancellation_handler: Arc<CancellationHandler>,
 }
 
 impl Session {
-    pub(crate) fn key(&self) -> &CancelKeyData {
+    pub(crate) fn key(&self) -> &CancelKey {
         &self.key
     }
 
@@ -487,22 +614,95 @@ pub(crate) fn key(&self) -> &CancelKeyData {
     pub(crate) async fn maintain_cancel_key(
         &self,
         session_id: uuid::Uuid,
-        cancel: tokio::sync::oneshot::Receiver<Infallible>,
+        cancel: oneshot::Receiver<Infallible>,
         cancel_closure: &CancelClosure,
         compute_config: &ComputeConfig,
     ) {
+        let mut cancel = pin!(cancel);
+
+        // the sender is dropped when the session ends.
+        let _ = self
+            .register_key(Some(cancel_closure.clone()), cancel.as_mut())
+            .await;
+
+        cancel_on_exit(session_id, cancel_closure, compute_config).await;
+    }
+
+    /// Like [`Self::maintain_cancel_key`], for transaction pooled sessions.
+    ///
+    /// `linked` holds the cancel closure of the compute connection the session
+    /// currently borrows, the key is registered again whenever it changes.
+    ///
+    /// This is not cancel safe
+    pub(crate) async fn maintain_pooled_cancel_key(
+        &self,
+        session_id: uuid::Uuid,
+        cancel: oneshot::Receiver<Infallible>,
+        mut linked: watch::Receiver<Option<CancelClosure>>,
+        compute_config: &ComputeConfig,
+    ) {
+        let mut cancel = pin!(cancel);
+
+        let mut closure = linked.borrow_and_update().clone();
+        loop {
+            let relinked = {
+                let relinked = pin!(async {
+                    tokio::select! {
+                        _ = cancel.as_mut() => false,
+                        res = linked.changed() => res.is_ok(),
+                    }
+                });
+                self.register_key(closure, relinked).await
+            };
+            if !relinked {
+                break;
+            }
+            closure = linked.borrow_and_update().clone();
+        }
+
+        // the session ended in the middle of a transaction.
+        let closure = linked.borrow().clone();
+        if let Some(closure) = closure {
+            cancel_on_exit(session_id, &closure, compute_config).await;
+        }
+    }
+
+    /// Make the session cancellable through `closure` until `cancel` resolves.
+    async fn register_key<R>(
+        &self,
+        closure: Option<CancelClosure>,
+        cancel: Pin<&mut impl Future<Output = R>>,
+    ) -> R {
+        let value = CancelKeyValue {
+            closure,
+            key_ext: (!self.key.ext.is_empty()).then(|| hex::encode(&self.key.ext)),
+        };
+
+        if let Some(peers) = self.cancellation_handler.peers.get() {
+            let _registered = peers.register(self.key.data, value);
+            cancel.await
+        } else {
+            self.maintain_redis_key(value, cancel).await
+        }
+    }
+
+    /// Store the cancel key in redis and keep refreshing it until `cancel` resolves.
+    async fn maintain_redis_key<R>(
+        &self,
+        value: CancelKeyValue,
+        mut cancel: Pin<&mut impl Future<Output = R>>,
+    ) -> R {
         let Some(tx) = self.cancellation_handler.tx.get() else {
             tracing::warn!("cancellation handler is not available");
             // don't exit, as we only want to exit if cancelled externally.
             std::future::pending().await
         };
+        let dest = value.closure.as_ref().map(|c| &c.cancel_token);
 
-        let closure_json = serde_json::to_string(&cancel_closure)
+        let closure_json = serde_json::to_string(&value)
             .expect("serialising to json string should not fail")
             .into_boxed_str();
 
-        let mut cancel = pin!(cancel);
-
         enum State {
             Init,
             Refresh,
@@ -514,12 +714,12 @@ enum State {
                 State::Init => {
                     tracing::debug!(
                         src=%self.key,
-                        dest=?cancel_closure.cancel_token,
+                        dest=?dest,
                         "registering cancellation key"
                     );
                     (
                         CancelKeyOp::Store {
-                            key: self.key,
+                            key: self.key.data,
                             value: closure_json.clone(),
                             expire: CANCEL_KEY_INITIAL_PERIOD + CANCEL_KEY_TTL_SLACK,
                         },
@@ -530,12 +730,12 @@ enum State {
                 State::Refresh => {
                     tracing::debug!(
                         src=%self.key,
-                        dest=?cancel_closure.cancel_token,
+                        dest=?dest,
                         "refreshing cancellation key"
                     );
                     (
                         CancelKeyOp::Refresh {
-                            key: self.key,
+                            key: self.key.data,
                             expire: CANCEL_KEY_REFRESH_PERIOD + CANCEL_KEY_TTL_SLACK,
                         },
                         CANCEL_KEY_REFRESH_PERIOD,
@@ -551,7 +751,7 @@ enum State {
                 Ok(Value::Okay) => {
                     tracing::debug!(
                         src=%self.key,
-                        dest=?cancel_closure.cancel_token,
+                        dest=?dest,
                         "registered cancellation key"
                     );
                     state = State::Refresh;
@@ -561,7 +761,7 @@ enum State {
                 Ok(Value::Int(1)) => {
                     tracing::debug!(
                         src=%self.key,
-                        dest=?cancel_closure.cancel_token,
+                        dest=?dest,
                         "refreshed cancellation key"
                     );
                 }
@@ -581,28 +781,136 @@ enum State {
                     wait_interval = Duration::from_millis(10);
                 }
 
-                Err(BatchQueueError::Cancelled(Err(_cancelled))) => break,
+                Err(BatchQueueError::Cancelled(cancelled)) => break cancelled,
             }
 
             // wait before continuing. break immediately if cancelled.
-            if run_until(tokio::time::sleep(wait_interval), cancel.as_mut())
-                .await
-                .is_err()
+            if let Err(cancelled) =
+                run_until(tokio::time::sleep(wait_interval), cancel.as_mut()).await
             {
-                break;
+                break cancelled;
             }
         }
+    }
+}
 
-        if let Err(err) = cancel_closure
-            .try_cancel_query(compute_config)
-            .boxed()
-            .await
-        {
-            tracing::warn!(
-                ?session_id,
-                ?err,
-                "could not cancel the query in the database"
-            );
-        }
+/// Cancel the query left running by a session that has ended.
+async fn cancel_on_exit(
+    session_id: uuid::Uuid,
+    cancel_closure: &CancelClosure,
+    compute_config: &ComputeConfig,
+) {
+    if let Err(err) = cancel_closure
+        .try_cancel_query(compute_config)
+        .boxed()
+        .await
+    {
+        tracing::warn!(
+            ?session_id,
+            ?err,
+            "could not cancel the query in the database"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel_key_ext_routing() {
+        let ext = new_cancel_key_ext("us-east-2");
+        assert_eq!(ext.len(), 2 + CANCEL_KEY_EXT_SECRET_LEN + "us-east-2".len());
+        assert_eq!(cancel_key_ext_region(&ext), Some("us-east-2"));
+
+        // the secret is random.
+        assert_ne!(ext, new_cancel_key_ext("us-east-2"));
+
+        // regions that don't fit are left out.
+        let ext = new_cancel_key_ext(&"a".repeat(CANCEL_KEY_EXT_MAX_REGION_LEN + 1));
+        assert_eq!(ext.len(), 2 + CANCEL_KEY_EXT_SECRET_LEN);
+        assert_eq!(cancel_key_ext_region(&ext), None);
+
+        // keys from protocol 3.0 clients, or in an unknown format.
+        assert_eq!(cancel_key_ext_region(&[]), None);
+        assert_eq!(cancel_key_ext_region(&[2; 40]), None);
+    }
+
+    #[tokio::test]
+    async fn pooled_cancel_key_follows_the_linked_connection() {
+        let compute_config = Box::leak(Box::new(ComputeConfig {
+            retry: crate::config::RetryConfig {
+                base_delay: Duration::from_secs(1),
+                max_retries: 0,
+                backoff_factor: 2.0,
+            },
+            tls: Arc::new(crate::tls::client_config::compute_client_config_with_certs(
+                std::iter::empty(),
+            )),
+            timeout: Duration::from_secs(1),
+        }));
+        let handler = Arc::new(CancellationHandler::new(compute_config, "local".into()));
+        handler.init_peers(Arc::new(
+            CancellationPeers::new(vec![], "secret".into()).unwrap(),
+        ));
+
+        let session = handler.clone().get_key(ProtocolVersion::new(3, 0));
+        let key = session.key().data;
+        let closure = |process_id| {
+            CancelClosure::new(
+                "127.0.0.1:5432".parse().unwrap(),
+                RawCancelToken {
+                    ssl_mode: postgres_client::config::SslMode::Disable,
+                    process_id,
+                    secret_key: 0,
+                },
+                "localhost".into(),
+                ComputeUserInfo {
+                    endpoint: "endpoint".into(),
+                    user: "user".into(),
+                    options: crate::proxy::NeonOptions::default(),
+                },
+            )
+        };
+        // wait for the session to register a closure for `expected`.
+        let registered = async |expected: Option<i32>| {
+            for _ in 0..100 {
+                let value = handler.get_cancel_key(key).await.unwrap();
+                let pid = value.map(|v| v.closure.map(|c| c.process_id()));
+                if pid == Some(expected) {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("cancel key should point at {expected:?}");
+        };
+
+        let (cancel_on_shutdown, cancel) = oneshot::channel();
+        let (linked, linked_cancel) = watch::channel(None);
+        let task = tokio::spawn(async move {
+            session
+                .maintain_pooled_cancel_key(
+                    uuid::Uuid::new_v4(),
+                    cancel,
+                    linked_cancel,
+                    compute_config,
+                )
+                .await;
+        });
+
+        // between transactions, there is nothing to cancel.
+        registered(None).await;
+
+        linked.send_replace(Some(closure(1)));
+        registered(Some(1)).await;
+        linked.send_replace(None);
+        registered(None).await;
+        linked.send_replace(Some(closure(2)));
+        registered(Some(2)).await;
+
+        linked.send_replace(None);
+        drop(cancel_on_shutdown);
+        task.await.unwrap();
+        assert!(handler.get_cancel_key(key).await.unwrap().is_none());
     }
 }
@@ -0,0 +1,460 @@
+//! Query cancellation across proxy instances without Redis.
+//!
+//! Every instance picks a random id at startup and puts it in the top bits of
+//! the cancel keys it hands out, keeping its sessions in memory. A cancel
+//! request for a key of another instance is forwarded to the peers with that
+//! id, which answer with the session's [`CancelClosure`](super::CancelClosure).
+//!
+//! Peers talk over an internal HTTP API, authenticated with a shared secret.
+//! They are given as `host:port` addresses that are resolved periodically, so
+//! a DNS name with a record for each instance discovers all of them.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use arc_swap::ArcSwap;
+use clashmap::ClashMap;
+use futures::future::join_all;
+use http_utils::endpoint::{self, request_span};
+use http_utils::error::ApiError;
+use http_utils::json::{json_request, json_response};
+use http_utils::{RouterBuilder, RouterService};
+use hyper0::header::AUTHORIZATION;
+use hyper0::{Body, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tracing::{debug, info, warn};
+
+use super::{CancelError, CancelKeyValue};
+use crate::http::ClientWithMiddleware;
+use crate::pqproto::{CancelKeyData, id_to_cancel_key};
+
+/// The instance id takes the top 16 bits of the cancel key.
+const INSTANCE_ID_SHIFT: u32 = 48;
+
+const PEER_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+const PEER_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+const PEER_RETRY_DURATION: Duration = Duration::from_secs(4);
+
+#[derive(Serialize, Deserialize)]
+struct InstanceInfo {
+    instance_id: u16,
+    /// Tells instances that picked the same id apart.
+    instance_uuid: uuid::Uuid,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CancelKeyRequest {
+    key: u64,
+}
+
+/// Resolved peer addresses.
+#[derive(Default)]
+struct PeerAddrs {
+    by_instance: HashMap<u16, Vec<SocketAddr>>,
+    /// Every peer but us, including those that did not answer.
+    all: Vec<SocketAddr>,
+}
+
+/// Stores the cancel keys of this instance and finds those of its peers.
+pub struct CancellationPeers {
+    instance: InstanceInfo,
+    /// `host:port` of the peers' internal listeners.
+    peers: Vec<String>,
+    secret: Arc<str>,
+    sessions: ClashMap<CancelKeyData, CancelKeyValue>,
+    addrs: ArcSwap<PeerAddrs>,
+    client: ClientWithMiddleware,
+}
+
+impl CancellationPeers {
+    pub fn new(peers: Vec<String>, secret: Arc<str>) -> anyhow::Result<Self> {
+        if secret.is_empty() {
+            bail!("a secret is required to authenticate cancellation peers");
+        }
+
+        Ok(Self {
+            instance: InstanceInfo {
+                instance_id: rand::random(),
+                instance_uuid: uuid::Uuid::new_v4(),
+            },
+            peers,
+            secret,
+            sessions: ClashMap::new(),
+            addrs: ArcSwap::default(),
+            client: crate::http::new_client_with_timeout(PEER_REQUEST_TIMEOUT, PEER_RETRY_DURATION),
+        })
+    }
+
+    /// A new random cancel key, owned by this instance.
+    pub(super) fn new_key(&self) -> CancelKeyData {
+        let id = u64::from(self.instance.instance_id) << INSTANCE_ID_SHIFT;
+        let random = rand::random::<u64>() & ((1 << INSTANCE_ID_SHIFT) - 1);
+        id_to_cancel_key(id | random)
+    }
+
+    /// Register a session of this instance until the returned guard is dropped.
+    pub(super) fn register(&self, key: CancelKeyData, value: CancelKeyValue) -> RegisteredKey<'_> {
+        self.sessions.insert(key, value);
+        RegisteredKey { peers: self, key }
+    }
+
+    /// Find a session, asking the instance that issued the key if it's not ours.
+    pub(super) async fn get(
+        &self,
+        key: CancelKeyData,
+    ) -> Result<Option<CancelKeyValue>, CancelError> {
+        let instance_id = key_instance_id(key);
+        if instance_id == self.instance.instance_id
+            && let Some(value) = self.sessions.get(&key)
+        {
+            return Ok(Some(value.clone()));
+        }
+
+        let addrs = self.addrs.load_full();
+        let addrs = match addrs.by_instance.get(&instance_id) {
+            Some(addrs) => addrs,
+            // our key, unless a peer picked the same id.
+            None if instance_id == self.instance.instance_id => return Ok(None),
+            // the instance might have started after the last refresh.
+            None => &addrs.all,
+        };
+
+        let mut failed = false;
+        for res in join_all(addrs.iter().map(|&addr| self.get_from_peer(addr, key))).await {
+            match res {
+                Ok(Some(value)) => return Ok(Some(value)),
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("failed to get cancel key from peer: {e}");
+                    failed = true;
+                }
+            }
+        }
+
+        if failed {
+            Err(CancelError::InternalError)
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn get_from_peer(
+        &self,
+        addr: SocketAddr,
+        key: CancelKeyData,
+    ) -> Result<Option<CancelKeyValue>, crate::http::Error> {
+        let key = CancelKeyRequest { key: key.0.get() };
+        let value = self
+            .client
+            .post(format!("http://{addr}/v1/cancel_key"))
+            .bearer_auth(&*self.secret)
+            .json(&key)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(value)
+    }
+
+    async fn get_instance(&self, addr: SocketAddr) -> Result<InstanceInfo, crate::http::Error> {
+        let info = self
+            .client
+            .get(format!("http://{addr}/v1/instance"))
+            .bearer_auth(&*self.secret)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(info)
+    }
+
+    /// Resolve the peers and ask each of them for its instance id.
+    async fn refresh(&self) {
+        let mut addrs = vec![];
+        for peer in &self.peers {
+            match tokio::net::lookup_host(peer.as_str()).await {
+                Ok(resolved) => addrs.extend(resolved),
+                Err(e) => warn!(peer, "could not resolve cancellation peer: {e}"),
+            }
+        }
+        addrs.sort_unstable();
+        addrs.dedup();
+
+        let instances = join_all(addrs.iter().map(|&addr| self.get_instance(addr))).await;
+
+        let mut peer_addrs = PeerAddrs::default();
+        for (addr, instance) in addrs.into_iter().zip(instances) {
+            match instance {
+                Ok(instance) if instance.instance_uuid == self.instance.instance_uuid => continue,
+                Ok(instance) => {
+                    peer_addrs
+                        .by_instance
+                        .entry(instance.instance_id)
+                        .or_default()
+                        .push(addr);
+                }
+                Err(e) => warn!(%addr, "could not reach cancellation peer: {e}"),
+            }
+            peer_addrs.all.push(addr);
+        }
+
+        debug!(
+            peers = peer_addrs.all.len(),
+            reachable = peer_addrs.by_instance.values().map(Vec::len).sum::<usize>(),
+            "refreshed cancellation peers"
+        );
+        self.addrs.store(Arc::new(peer_addrs));
+    }
+
+    /// Keep the peer addresses up to date.
+    pub async fn discovery_task(self: Arc<Self>) -> anyhow::Result<Infallible> {
+        let mut interval = tokio::time::interval(PEER_REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.refresh().await;
+        }
+    }
+
+    fn check_secret(&self, req: &Request<Body>) -> Result<(), ApiError> {
+        let token = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        if token.as_bytes().ct_eq(self.secret.as_bytes()).into() {
+            Ok(())
+        } else {
+            Err(ApiError::Unauthorized("invalid peer secret".to_owned()))
+        }
+    }
+
+    async fn instance_handler(&self, req: Request<Body>) -> Result<Response<Body>, ApiError> {
+        self.check_secret(&req)?;
+        json_response(StatusCode::OK, &self.instance)
+    }
+
+    async fn cancel_key_handler(&self, mut req: Request<Body>) -> Result<Response<Body>, ApiError> {
+        self.check_secret(&req)?;
+        let request: CancelKeyRequest = json_request(&mut req).await?;
+        let value = self
+            .sessions
+            .get(&id_to_cancel_key(request.key))
+            .as_deref()
+            .cloned();
+        json_response(StatusCode::OK, value)
+    }
+
+    fn make_router(self: Arc<Self>) -> RouterBuilder<Body, ApiError> {
+        let peers = self.clone();
+        endpoint::make_router()
+            .get("/v1/instance", move |r| {
+                let peers = self.clone();
+                request_span(r, move |r| async move { peers.instance_handler(r).await })
+            })
+            .post("/v1/cancel_key", move |r| {
+                let peers = peers.clone();
+                request_span(r, move |r| async move { peers.cancel_key_handler(r).await })
+            })
+    }
+
+    /// Serve the cancel keys of this instance to its peers.
+    pub async fn task_main(self: Arc<Self>, listener: TcpListener) -> anyhow::Result<Infallible> {
+        scopeguard::defer! {
+            info!("cancellation peer listener has shut down");
+        }
+
+        let service = || RouterService::new(self.make_router().build()?);
+
+        hyper0::Server::from_tcp(listener)?
+            .serve(service().map_err(|e| anyhow!(e))?)
+            .await?;
+
+        bail!("hyper server without shutdown handling cannot shutdown successfully");
+    }
+}
+
+/// The id of the instance that issued a cancel key.
+fn key_instance_id(key: CancelKeyData) -> u16 {
+    (key.0.get() >> INSTANCE_ID_SHIFT) as u16
+}
+
+/// Removes a session from [`CancellationPeers`] when it ends.
+pub(super) struct RegisteredKey<'a> {
+    peers: &'a CancellationPeers,
+    key: CancelKeyData,
+}
+
+impl Drop for RegisteredKey<'_> {
+    fn drop(&mut self) {
+        self.peers.sessions.remove(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn bind() -> (TcpListener, SocketAddr) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .into_std()
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        (listener, addr)
+    }
+
+    fn new_peers(addrs: &[SocketAddr], instance_id: u16) -> CancellationPeers {
+        let mut peers = CancellationPeers::new(
+            addrs.iter().map(SocketAddr::to_string).collect(),
+            "secret".into(),
+        )
+        .unwrap();
+        peers.instance.instance_id = instance_id;
+        // give up quickly on peers that don't answer
+        peers.client = crate::http::new_client_with_timeout(
+            Duration::from_millis(200),
+            Duration::from_millis(400),
+        );
+        peers
+    }
+
+    fn value(key_ext: &str) -> CancelKeyValue {
+        CancelKeyValue {
+            closure: None,
+            key_ext: Some(key_ext.to_owned()),
+        }
+    }
+
+    async fn get(peers: &CancellationPeers, key: CancelKeyData) -> Option<String> {
+        let value = peers.get(key).await.unwrap()?;
+        Some(value.key_ext.unwrap())
+    }
+
+    #[test]
+    fn keys_encode_the_instance() {
+        let peers = CancellationPeers::new(vec![], "secret".into()).unwrap();
+        let id = peers.instance.instance_id;
+        for _ in 0..100 {
+            assert_eq!(key_instance_id(peers.new_key()), id);
+        }
+        assert_ne!(peers.new_key(), peers.new_key());
+
+        assert_eq!(
+            key_instance_id(id_to_cancel_key(0x1234_5678_9abc_def0)),
+            0x1234
+        );
+
+        assert!(CancellationPeers::new(vec![], "".into()).is_err());
+    }
+
+    #[test]
+    fn checks_the_secret() {
+        let peers = CancellationPeers::new(vec![], "secret".into()).unwrap();
+        let request = |auth: Option<&str>| {
+            let mut req = Request::new(Body::empty());
+            if let Some(auth) = auth {
+                req.headers_mut()
+                    .insert(AUTHORIZATION, auth.parse().unwrap());
+            }
+            req
+        };
+
+        assert!(peers.check_secret(&request(Some("Bearer secret"))).is_ok());
+        for auth in [
+            None,
+            Some("Bearer secre"),
+            Some("Bearer secret2"),
+            Some("secret"),
+        ] {
+            assert!(peers.check_secret(&request(auth)).is_err(), "{auth:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn cancels_through_peers() {
+        let (listener_a, addr_a) = bind().await;
+        let (listener_b, addr_b) = bind().await;
+        // c is listening, but doesn't answer until later
+        let (listener_c, addr_c) = bind().await;
+        let addrs = [addr_a, addr_b, addr_c];
+        let a = Arc::new(new_peers(&addrs, 1));
+        let b = Arc::new(new_peers(&addrs, 2));
+        let c = Arc::new(new_peers(&addrs, 3));
+        tokio::spawn(a.clone().task_main(listener_a));
+        tokio::spawn(b.clone().task_main(listener_b));
+
+        a.refresh().await;
+        let peer_addrs = a.addrs.load_full();
+        assert_eq!(peer_addrs.by_instance, HashMap::from([(2, vec![addr_b])]));
+        let mut all = vec![addr_b, addr_c];
+        all.sort_unstable();
+        assert_eq!(peer_addrs.all, all);
+
+        // keys are forwarded to the instance that issued them
+        let key_b = b.new_key();
+        let registered = b.register(key_b, value("b"));
+        assert_eq!(get(&a, key_b).await.as_deref(), Some("b"));
+        assert_eq!(get(&b, key_b).await.as_deref(), Some("b"));
+        assert_eq!(get(&a, b.new_key()).await, None);
+        drop(registered);
+        assert_eq!(get(&a, key_b).await, None);
+
+        let key_a = a.new_key();
+        let _registered = a.register(key_a, value("a"));
+        assert_eq!(get(&a, key_a).await.as_deref(), Some("a"));
+        assert_eq!(get(&a, a.new_key()).await, None);
+
+        // keys of unknown instances are asked of all peers, failing if some don't answer
+        let key_c = c.new_key();
+        assert!(matches!(
+            a.get(key_c).await,
+            Err(CancelError::InternalError)
+        ));
+        tokio::spawn(c.clone().task_main(listener_c));
+        let _registered = c.register(key_c, value("c"));
+        assert_eq!(get(&a, key_c).await.as_deref(), Some("c"));
+        assert_eq!(get(&a, c.new_key()).await, None);
+    }
+
+    #[tokio::test]
+    async fn instance_id_collision() {
+        let (listener_a, addr_a) = bind().await;
+        let (listener_b, addr_b) = bind().await;
+        let addrs = [addr_a, addr_b];
+        let a = Arc::new(new_peers(&addrs, 7));
+        let b = Arc::new(new_peers(&addrs, 7));
+        tokio::spawn(a.clone().task_main(listener_a));
+        tokio::spawn(b.clone().task_main(listener_b));
+
+        // the instances tell each other apart by their uuid
+        a.refresh().await;
+        b.refresh().await;
+        assert_eq!(
+            a.addrs.load().by_instance,
+            HashMap::from([(7, vec![addr_b])])
+        );
+        assert_eq!(
+            b.addrs.load().by_instance,
+            HashMap::from([(7, vec![addr_a])])
+        );
+
+        let key_a = a.new_key();
+        let key_b = b.new_key();
+        let _registered_a = a.register(key_a, value("a"));
+        let _registered_b = b.register(key_b, value("b"));
+        for peers in [&a, &b] {
+            assert_eq!(get(peers, key_a).await.as_deref(), Some("a"));
+            assert_eq!(get(peers, key_b).await.as_deref(), Some("b"));
+            assert_eq!(get(peers, a.new_key()).await, None);
+        }
+    }
+}
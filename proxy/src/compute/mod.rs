@@ -11,6 +11,7 @@
 use postgres_client::tls::MakeTlsConnect;
 use postgres_client::{NoTls, RawCancelToken, RawConnection};
 use postgres_protocol::message::backend::NoticeResponseBody;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 use tokio::net::{TcpStream, lookup_host};
 use tracing::{debug, error, info, warn};
@@ -28,6 +29,8 @@
 use crate::metrics::{Metrics, NumDbConnectionsGuard};
 use crate::pqproto::StartupMessageParams;
 use crate::proxy::neon_option;
+use crate::proxy::replicas::NoReadReplicas;
+use crate::serverless::{create_random_jwk, resign_jwt};
 use crate::types::Host;
 
 pub const COULD_NOT_CONNECT: &str = "Couldn't connect to compute node";
@@ -86,6 +89,9 @@ pub(crate) enum ConnectionError {
 
     #[error("error acquiring resource permit: {0}")]
     TooManyConnectionAttempts(#[from] ApiLockError),
+
+    #[error("{0}")]
+    NoReadReplicas(#[from] NoReadReplicas),
 }
 
 impl UserFacingError for ConnectionError {
@@ -96,6 +102,7 @@ fn to_string_client(&self) -> String {
                 "Failed to acquire permit to connect to the database. Too many database connection attempts are currently ongoing.".to_owned()
             }
             ConnectionError::TlsError(_) => COULD_NOT_CONNECT.to_owned(),
+            ConnectionError::NoReadReplicas(err) => err.to_string_client(),
         }
     }
 }
@@ -106,6 +113,7 @@ fn get_error_kind(&self) -> crate::error::ErrorKind {
             ConnectionError::TlsError(_) => crate::error::ErrorKind::Compute,
             ConnectionError::WakeComputeError(e) => e.get_error_kind(),
             ConnectionError::TooManyConnectionAttempts(e) => e.get_error_kind(),
+            ConnectionError::NoReadReplicas(e) => e.get_error_kind(),
         }
     }
 }
@@ -127,6 +135,8 @@ pub(crate) struct AuthInfo {
     /// Some for sql-over-http, ws, tcp, and in most cases for console-redirect.
     /// Might be None for console-redirect, but that's only a consequence of testing environments ATM.
     auth: Option<Auth>,
+    /// The payload of the JWT the client authenticated with, passed on with pg_session_jwt.
+    jwt_session: Option<Vec<u8>>,
     server_params: StartupMessageParams,
 
     channel_binding: ChannelBinding,
@@ -152,6 +162,7 @@ pub(crate) fn for_console_redirect(db: &str, user: &str, pw: Option<&str>) -> Se
         server_params.insert("user", user);
         Self {
             auth: pw.map(|pw| Auth::Password(pw.as_bytes().to_owned())),
+            jwt_session: None,
             server_params,
             skip_db_user: true,
             // pg-sni-router is a mitm so this would fail.
@@ -160,13 +171,17 @@ pub(crate) fn for_console_redirect(db: &str, user: &str, pw: Option<&str>) -> Se
     }
 
     pub(crate) fn with_auth_keys(keys: ComputeCredentialKeys) -> Self {
+        let (auth, jwt_session) = match keys {
+            ComputeCredentialKeys::AuthKeys(AuthKeys::ScramSha256(auth_keys)) => {
+                (Some(Auth::Scram(Box::new(auth_keys))), None)
+            }
+            // compute trusts the proxy, which passes on who the client is.
+            ComputeCredentialKeys::JwtPayload(payload) => (None, Some(payload)),
+            ComputeCredentialKeys::ClientCertificate => (None, None),
+        };
         Self {
-            auth: match keys {
-                ComputeCredentialKeys::AuthKeys(AuthKeys::ScramSha256(auth_keys)) => {
-                    Some(Auth::Scram(Box::new(auth_keys)))
-                }
-                ComputeCredentialKeys::JwtPayload(_) => None,
-            },
+            auth,
+            jwt_session,
             server_params: StartupMessageParams::default(),
             skip_db_user: false,
             channel_binding: ChannelBinding::Prefer,
@@ -193,12 +208,55 @@ fn enrich(&self, mut config: postgres_client::Config) -> postgres_client::Config
             None => &mut config,
         };
         config.channel_binding(self.channel_binding);
+
+        let mut options = self.server_params.get("options").map(str::to_owned);
+        if let Some(payload) = &self.jwt_session {
+            // Like local_proxy, sign the claims with a key that only this session trusts.
+            let (key, jwk) = create_random_jwk();
+            let jwk = serde_json::to_string(&jwk).expect("serializing jwk to json should not fail");
+            let jwk = format!("-c pg_session_jwt.jwk={jwk}");
+            options = Some(match options {
+                Some(options) => format!("{options} {jwk}"),
+                None => jwk,
+            });
+
+            let token = resign_jwt(&key, payload, 1)
+                .expect("the payload was parsed when the JWT was validated");
+            // this is safe from query injections as the jwt format free of any escape characters.
+            config.init_query(format!(
+                "select auth.init(); select auth.jwt_session_init('{token}')"
+            ));
+        }
+
         for (k, v) in self.server_params.iter() {
-            config.set_param(k, v);
+            if k != "options" {
+                config.set_param(k, v);
+            }
+        }
+        if let Some(options) = &options {
+            config.set_param("options", options);
         }
         config
     }
 
+    /// Identifies the startup parameters sent to compute, so that pooled connections are
+    /// only shared by clients that would have opened them the same way.
+    pub(crate) fn startup_params_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        for (k, v) in self.server_params.iter().sorted() {
+            hasher.update(k);
+            hasher.update([0]);
+            hasher.update(v);
+            hasher.update([0]);
+        }
+        hex::encode(&hasher.finalize()[..16])
+    }
+
+    /// Whether the session carries the client's identity, so the connection can't be shared.
+    pub(crate) fn has_jwt_session(&self) -> bool {
+        self.jwt_session.is_some()
+    }
+
     /// Apply startup message params to the connection config.
     pub(crate) fn set_startup_params(
         &mut self,
@@ -222,6 +280,9 @@ pub(crate) fn set_startup_params(
                     self.server_params.insert(k, v);
                 }
 
+                // consumed by the proxy for read replica routing, postgres doesn't know it.
+                "target_session_attrs" => {}
+
                 // if we allow arbitrary params, then we forward them through.
                 // this is a flag for a period of backwards compatibility
                 k if arbitrary_params => {
@@ -446,4 +507,41 @@ fn test_filtered_options() {
         let params = "project = foo neon_endpoint_type:read_write   neon_lsn:0/2 neon_proxy_params_compat:true";
         assert_eq!(filtered_options(params).as_deref(), Some("project = foo"));
     }
+
+    #[test]
+    fn test_startup_params_hash() {
+        let hash = |params: &[(&str, &str)]| {
+            let mut info = AuthInfo::with_auth_keys(ComputeCredentialKeys::ClientCertificate);
+            let mut startup = StartupMessageParams::default();
+            for (k, v) in params {
+                startup.insert(k, v);
+            }
+            info.set_startup_params(&startup, false);
+            info.startup_params_hash()
+        };
+
+        let a = hash(&[("user", "alice"), ("application_name", "psql")]);
+        assert_eq!(a, hash(&[("application_name", "psql"), ("user", "alice")]));
+        assert_ne!(
+            a,
+            hash(&[("user", "alice"), ("application_name", "pgbench")])
+        );
+        assert_ne!(
+            a,
+            hash(&[
+                ("user", "alice"),
+                ("application_name", "psql"),
+                ("options", "-c search_path=app"),
+            ])
+        );
+        // not forwarded to compute.
+        assert_eq!(
+            a,
+            hash(&[
+                ("user", "alice"),
+                ("application_name", "psql"),
+                ("target_session_attrs", "read-write"),
+            ])
+        );
+    }
 }
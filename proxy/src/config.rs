@@ -18,6 +18,7 @@
 use crate::control_plane::messages::{EndpointJwksResponse, JwksSettings};
 use crate::ext::TaskExt;
 use crate::intern::RoleNameInt;
+use crate::proxy::replicas::ReplicaBalancer;
 use crate::rate_limiter::{RateLimitAlgorithm, RateLimiterConfig};
 use crate::scram::threadpool::ThreadPool;
 use crate::serverless::GlobalConnPoolOptions;
@@ -31,6 +32,11 @@ pub struct ProxyConfig {
     pub http_config: HttpConfig,
     pub authentication_config: AuthenticationConfig,
     pub proxy_protocol_v2: ProxyProtocolV2,
+    pub pooling_mode: PoolingMode,
+    /// With transaction pooling, how many prepared statements to keep on each compute connection.
+    pub max_prepared_statements: usize,
+    /// Set if read-only connections may be routed to read replicas.
+    pub read_replicas: Option<ReplicaBalancer>,
     pub handshake_timeout: Duration,
     pub wake_compute_retry_config: RetryConfig,
     pub connect_compute_locks: ApiLocks<Host>,
@@ -53,6 +59,15 @@ pub enum ProxyProtocolV2 {
     Rejected,
 }
 
+#[derive(Copy, Clone, Debug, ValueEnum, PartialEq)]
+pub enum PoolingMode {
+    /// Each client connection gets its own compute connection
+    Session,
+    /// Client connections share pooled compute connections, which are only
+    /// held for the duration of a transaction
+    Transaction,
+}
+
 #[derive(Debug)]
 pub struct MetricCollectionConfig {
     pub endpoint: reqwest::Url,
@@ -66,7 +81,79 @@ pub struct HttpConfig {
     pub cancel_set: CancelSet,
     pub client_conn_threshold: u64,
     pub max_request_size_bytes: usize,
-    pub max_response_size_bytes: usize,
+    pub response_limits: ResponseLimits,
+}
+
+/// Limits for SQL-over-HTTP responses, per output format.
+#[derive(Clone, Copy, Debug)]
+pub struct ResponseLimits {
+    pub json: FormatLimits,
+    pub ndjson: FormatLimits,
+    pub arrow: FormatLimits,
+    pub csv: FormatLimits,
+}
+
+impl ResponseLimits {
+    #[cfg(test)]
+    pub(crate) fn unlimited() -> Self {
+        let limits = FormatLimits {
+            max_rows: None,
+            max_bytes: usize::MAX,
+        };
+        Self {
+            json: limits,
+            ndjson: limits,
+            arrow: limits,
+            csv: limits,
+        }
+    }
+}
+
+/// Helper for cmdline response limit options parsing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FormatLimits {
+    /// Max number of rows in a single response. Unlimited if not set.
+    pub max_rows: Option<usize>,
+    /// Max size of the encoded response.
+    pub max_bytes: usize,
+}
+
+impl FormatLimits {
+    /// Default limits for the streaming formats.
+    pub const STREAMING_DEFAULT_OPTIONS: &'static str = "max_bytes=1073741824";
+
+    /// Parse response limits passed via cmdline.
+    /// Example: [`Self::STREAMING_DEFAULT_OPTIONS`].
+    fn parse(options: &str) -> anyhow::Result<Self> {
+        let mut max_rows = None;
+        let mut max_bytes = None;
+
+        for option in options.split(',') {
+            let (key, value) = option
+                .split_once('=')
+                .with_context(|| format!("bad key-value pair: {option}"))?;
+
+            match key {
+                "max_rows" => max_rows = Some(value.parse()?),
+                "max_bytes" => max_bytes = Some(value.parse()?),
+                unknown => bail!("unknown key: {unknown}"),
+            }
+        }
+
+        Ok(Self {
+            max_rows,
+            max_bytes: max_bytes.context("missing `max_bytes`")?,
+        })
+    }
+}
+
+impl FromStr for FormatLimits {
+    type Err = anyhow::Error;
+
+    fn from_str(options: &str) -> Result<Self, Self::Err> {
+        let error = || format!("failed to parse response limits '{options}'");
+        Self::parse(options).with_context(error)
+    }
 }
 
 pub struct AuthenticationConfig {
@@ -77,6 +164,8 @@ pub struct AuthenticationConfig {
     pub jwks_cache: JwkCache,
     pub is_auth_broker: bool,
     pub accept_jwts: bool,
+    /// Offer OAUTHBEARER to postgres clients, validating the token against the endpoint's JWKS rules.
+    pub accept_oauthbearer: bool,
     pub console_redirect_confirmation_timeout: tokio::time::Duration,
 }
 
@@ -448,6 +537,7 @@ fn parse_jwks_settings(jwks: compute_api::spec::JwksSettings) -> anyhow::Result<
                 .map(RoleName::from)
                 .map(|s| RoleNameInt::from(&s))
                 .collect(),
+            role_claim: None,
         })
     }
 
@@ -465,6 +555,7 @@ fn parse_jwks_settings(jwks: compute_api::spec::JwksSettings) -> anyhow::Result<
                 tls_config.cert_path.as_ref(),
                 None,
                 false,
+                false,
             )
         })
         .await
@@ -502,6 +593,28 @@ fn test_parse_cache_options() -> anyhow::Result<()> {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_format_limits() -> anyhow::Result<()> {
+        let FormatLimits {
+            max_rows,
+            max_bytes,
+        } = FormatLimits::STREAMING_DEFAULT_OPTIONS.parse()?;
+        assert_eq!(max_rows, None);
+        assert_eq!(max_bytes, 1024 * 1024 * 1024);
+
+        let FormatLimits {
+            max_rows,
+            max_bytes,
+        } = "max_rows=1000,max_bytes=4096".parse()?;
+        assert_eq!(max_rows, Some(1000));
+        assert_eq!(max_bytes, 4096);
+
+        assert!("max_rows=1000".parse::<FormatLimits>().is_err());
+        assert!("max_bytes=1,rows=2".parse::<FormatLimits>().is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_parse_lock_options() -> anyhow::Result<()> {
         let ConcurrencyLockOptions {
@@ -16,6 +16,7 @@
 use crate::pglb::passthrough::ProxyPassthrough;
 use crate::protocol2::{ConnectHeader, ConnectionInfo, read_proxy_protocol};
 use crate::proxy::connect_compute::{TcpMechanism, connect_to_compute};
+use crate::proxy::replicas::Router;
 use crate::proxy::{ErrorSource, finish_client_init};
 use crate::util::run_until_cancelled;
 
@@ -173,10 +174,9 @@ pub(crate) async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send>(
     let pause = ctx.latency_timer_pause(crate::metrics::Waiting::Client);
     let do_handshake = handshake(ctx, stream, tls, record_handshake_error);
 
-    let (mut stream, params) = match tokio::time::timeout(config.handshake_timeout, do_handshake)
-        .await??
-    {
-        HandshakeData::Startup(stream, params) => (stream, params),
+    let handshake_data = tokio::time::timeout(config.handshake_timeout, do_handshake).await??;
+    let (mut stream, params, version) = match handshake_data {
+        HandshakeData::Startup(stream, params, version) => (stream, params, version),
         HandshakeData::Cancel(cancel_key_data) => {
             // spawn a task to cancel the session, but don't wait for it
             cancellations.spawn({
@@ -218,6 +218,7 @@ pub(crate) async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send>(
         ctx,
         &TcpMechanism {
             locks: &config.connect_compute_locks,
+            router: Router::default(),
         },
         &node_info,
         config.wake_compute_retry_config,
@@ -231,9 +232,9 @@ pub(crate) async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send>(
         .or_else(|e| async { Err(stream.throw_error(e, Some(ctx)).await) })
         .await?;
 
-    let session = cancellation_handler.get_key();
+    let session = cancellation_handler.get_key(version);
 
-    finish_client_init(&pg_settings, *session.key(), &mut stream);
+    finish_client_init(&pg_settings, session.key(), &mut stream);
     let stream = stream.flush_and_into_inner().await?;
 
     let session_id = ctx.session_id();
@@ -255,6 +256,8 @@ pub(crate) async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send>(
 
         aux: node.aux,
         private_link_id: None,
+        pooling: None,
+        firewall: None,
 
         _cancel_on_shutdown: cancel_on_shutdown,
 
@@ -56,6 +56,7 @@ struct RequestContextInner {
     error_kind: Option<ErrorKind>,
     pub(crate) auth_method: Option<AuthMethod>,
     jwt_issuer: Option<String>,
+    sql_firewall_violation: Option<&'static str>,
     success: bool,
     pub(crate) cold_start_info: ColdStartInfo,
     pg_options: Option<StartupMessageParams>,
@@ -78,6 +79,8 @@ pub(crate) enum AuthMethod {
     ScramSha256Plus,
     Cleartext,
     Jwt,
+    ClientCertificate,
+    OAuthBearer,
 }
 
 impl Clone for RequestContext {
@@ -100,6 +103,7 @@ fn clone(&self) -> Self {
             error_kind: inner.error_kind,
             auth_method: inner.auth_method.clone(),
             jwt_issuer: inner.jwt_issuer.clone(),
+            sql_firewall_violation: inner.sql_firewall_violation,
             success: inner.success,
             cold_start_info: inner.cold_start_info,
             pg_options: inner.pg_options.clone(),
@@ -144,6 +148,7 @@ pub fn new(session_id: Uuid, conn_info: ConnectionInfo, protocol: Protocol) -> S
             error_kind: None,
             auth_method: None,
             jwt_issuer: None,
+            sql_firewall_violation: None,
             success: false,
             cold_start_info: ColdStartInfo::Unknown,
             pg_options: None,
@@ -267,6 +272,19 @@ pub(crate) fn set_jwt_issuer(&self, jwt_issuer: String) {
         this.jwt_issuer = Some(jwt_issuer);
     }
 
+    /// Record a query rejected by the SQL firewall. If the request was already logged,
+    /// as it is once a postgres session is established, the violation is logged in a new row.
+    pub(crate) fn set_sql_firewall_violation(&self, violation: &'static str) {
+        let mut this = self.0.try_lock().expect("should not deadlock");
+        this.sql_firewall_violation = Some(violation);
+        if this.sender.is_none()
+            && let Some(tx) = LOG_CHAN.get().and_then(|tx| tx.upgrade())
+            && let Err(e) = tx.send(RequestData::from(&*this))
+        {
+            error!("sql firewall violation channel send failed: {e}");
+        }
+    }
+
     pub fn has_private_peer_addr(&self) -> bool {
         self.0
             .try_lock()
@@ -90,6 +90,8 @@ pub(crate) struct RequestData {
     pg_options: Option<String>,
     auth_method: Option<&'static str>,
     jwt_issuer: Option<String>,
+    /// Rule of the SQL firewall that rejected a query, if any
+    sql_firewall_violation: Option<&'static str>,
 
     error: Option<&'static str>,
     /// Success is counted if we form a HTTP response with sql rows inside
@@ -144,8 +146,11 @@ fn from(value: &RequestContextInner) -> Self {
                 super::AuthMethod::ScramSha256Plus => "scram_sha_256_plus",
                 super::AuthMethod::Cleartext => "cleartext",
                 super::AuthMethod::Jwt => "jwt",
+                super::AuthMethod::ClientCertificate => "client_certificate",
+                super::AuthMethod::OAuthBearer => "oauthbearer",
             }),
             jwt_issuer: value.jwt_issuer.clone(),
+            sql_firewall_violation: value.sql_firewall_violation,
             protocol: value.protocol.as_str(),
             region: String::new(),
             error: value.error_kind.as_ref().map(|e| e.to_metric_label()),
@@ -540,6 +545,7 @@ fn generate_request_data(rng: &mut impl Rng) -> RequestData {
             pg_options: None,
             auth_method: None,
             jwt_issuer: None,
+            sql_firewall_violation: None,
             protocol: ["tcp", "ws", "http"][rng.gen_range(0..3)],
             region: String::new(),
             error: None,
@@ -622,15 +628,15 @@ async fn verify_parquet_no_compression() {
         assert_eq!(
             file_stats,
             [
-                (1313953, 3, 6000),
-                (1313942, 3, 6000),
-                (1314001, 3, 6000),
-                (1313958, 3, 6000),
-                (1314094, 3, 6000),
-                (1313931, 3, 6000),
-                (1313725, 3, 6000),
-                (1313960, 3, 6000),
-                (438318, 1, 2000)
+                (1314474, 3, 6000),
+                (1314463, 3, 6000),
+                (1314522, 3, 6000),
+                (1314479, 3, 6000),
+                (1314615, 3, 6000),
+                (1314452, 3, 6000),
+                (1314246, 3, 6000),
+                (1314481, 3, 6000),
+                (438514, 1, 2000)
             ]
         );
 
@@ -662,11 +668,11 @@ async fn verify_parquet_strong_compression() {
         assert_eq!(
             file_stats,
             [
-                (1205810, 5, 10000),
-                (1205534, 5, 10000),
-                (1205835, 5, 10000),
-                (1205820, 5, 10000),
-                (1206074, 5, 10000)
+                (1206741, 5, 10000),
+                (1206465, 5, 10000),
+                (1206766, 5, 10000),
+                (1206751, 5, 10000),
+                (1207005, 5, 10000)
             ]
         );
 
@@ -691,15 +697,15 @@ async fn verify_parquet_unreliable_upload() {
         assert_eq!(
             file_stats,
             [
-                (1313953, 3, 6000),
-                (1313942, 3, 6000),
-                (1314001, 3, 6000),
-                (1313958, 3, 6000),
-                (1314094, 3, 6000),
-                (1313931, 3, 6000),
-                (1313725, 3, 6000),
-                (1313960, 3, 6000),
-                (438318, 1, 2000)
+                (1314474, 3, 6000),
+                (1314463, 3, 6000),
+                (1314522, 3, 6000),
+                (1314479, 3, 6000),
+                (1314615, 3, 6000),
+                (1314452, 3, 6000),
+                (1314246, 3, 6000),
+                (1314481, 3, 6000),
+                (438514, 1, 2000)
             ]
         );
 
@@ -736,7 +742,7 @@ async fn verify_parquet_regular_upload() {
         // files are smaller than the size threshold, but they took too long to fill so were flushed early
         assert_eq!(
             file_stats,
-            [(658584, 2, 3001), (658298, 2, 3000), (658094, 2, 2999)]
+            [(658938, 2, 3001), (658652, 2, 3000), (658448, 2, 2999)]
         );
 
         tmpdir.close().unwrap();
@@ -16,6 +16,7 @@
 
 use super::super::messages::{ControlPlaneErrorMessage, GetEndpointAccessControl, WakeCompute};
 use crate::auth::backend::ComputeUserInfo;
+use crate::auth::backend::client_cert::ClientCa;
 use crate::auth::backend::jwt::AuthRule;
 use crate::context::RequestContext;
 use crate::control_plane::caches::ApiCaches;
@@ -26,11 +27,12 @@
 use crate::control_plane::messages::{ColdStartInfo, EndpointJwksResponse};
 use crate::control_plane::{
     AccessBlockerFlags, AuthInfo, AuthSecret, CachedNodeInfo, EndpointAccessControl, NodeInfo,
-    RoleAccessControl,
+    ReplicaInfo, RoleAccessControl,
 };
 use crate::metrics::Metrics;
 use crate::proxy::retry::CouldRetry;
 use crate::rate_limiter::WakeComputeRateLimiter;
+use crate::sql_firewall::SqlFirewall;
 use crate::types::{EndpointCacheKey, EndpointId, RoleName};
 use crate::{compute, http, scram};
 
@@ -83,6 +85,9 @@ async fn get_and_cache_auth_info<T>(
                     allowed_vpce: Arc::new(auth_info.allowed_vpc_endpoint_ids),
                     flags: auth_info.access_blocker_flags,
                     rate_limits: auth_info.rate_limits,
+                    client_ca: auth_info.client_ca,
+                    compute_trusts_proxy: auth_info.compute_trusts_proxy,
+                    sql_firewall: auth_info.sql_firewall,
                 };
                 let role_control = RoleAccessControl {
                     secret: auth_info.secret,
@@ -197,6 +202,27 @@ async fn do_get_auth_req(
                 .observe(allowed_vpc_endpoint_ids.len() as f64);
             let block_public_connections = body.block_public_connections.unwrap_or_default();
             let block_vpc_connections = body.block_vpc_connections.unwrap_or_default();
+            let client_ca = match body.client_ca_bundle {
+                Some(pem) => {
+                    let required = body.require_client_cert.unwrap_or_default();
+                    let ca = ClientCa::parse(&pem, required).map_err(|e| {
+                        warn!(error = ?e, "could not parse client CA bundle");
+                        GetAuthInfoError::BadClientCa
+                    })?;
+                    Some(Arc::new(ca))
+                }
+                None => None,
+            };
+            let sql_firewall = match body.sql_firewall {
+                Some(rules) => {
+                    let firewall = SqlFirewall::new(rules).map_err(|e| {
+                        warn!(error = ?e, "could not parse SQL firewall rules");
+                        GetAuthInfoError::BadSqlFirewall
+                    })?;
+                    Some(Arc::new(firewall))
+                }
+                None => None,
+            };
             Ok(AuthInfo {
                 secret,
                 allowed_ips,
@@ -208,6 +234,9 @@ async fn do_get_auth_req(
                     vpc_access_blocked: block_vpc_connections,
                 },
                 rate_limits: body.rate_limits,
+                client_ca,
+                compute_trusts_proxy: body.compute_trusts_proxy.unwrap_or_default(),
+                sql_firewall,
             })
         }
         .inspect_err(|e| tracing::debug!(error = ?e))
@@ -260,6 +289,7 @@ async fn do_get_endpoint_jwks(
                     jwks_url: jwks.jwks_url,
                     audience: jwks.jwt_audience,
                     role_names: jwks.role_names,
+                    role_claim: jwks.role_claim,
                 })
                 .collect();
 
@@ -304,29 +334,21 @@ async fn do_wake_compute(
             info!(duration = ?start.elapsed(), "received http response");
             let body = parse_body::<WakeCompute>(response.status(), response.bytes().await?)?;
 
-            let Some((host, port)) = parse_host_port(&body.address) else {
-                return Err(WakeComputeError::BadComputeAddress(body.address));
-            };
-
-            let host_addr = IpAddr::from_str(host).ok();
-
-            let ssl_mode = match &body.server_name {
-                Some(_) => SslMode::Require,
-                None => SslMode::Disable,
-            };
-            let host = match body.server_name {
-                Some(host) => host.into(),
-                None => host.into(),
-            };
+            let read_replicas = body
+                .read_replicas
+                .into_iter()
+                .map(|replica| {
+                    Ok(ReplicaInfo {
+                        conn_info: parse_connect_info(replica.address, replica.server_name)?,
+                        compute_id: replica.compute_id,
+                    })
+                })
+                .collect::<Result<_, WakeComputeError>>()?;
 
             let node = NodeInfo {
-                conn_info: compute::ConnectInfo {
-                    host_addr,
-                    host,
-                    port,
-                    ssl_mode,
-                },
+                conn_info: parse_connect_info(body.address, body.server_name)?,
                 aux: body.aux,
+                read_replicas,
             };
 
             Ok(node)
@@ -555,7 +577,34 @@ fn parse_body<T: for<'a> serde::Deserialize<'a>>(
     Err(ControlPlaneError::Message(body))
 }
 
-fn parse_host_port(input: &str) -> Option<(&str, u16)> {
+fn parse_connect_info(
+    address: Box<str>,
+    server_name: Option<String>,
+) -> Result<compute::ConnectInfo, WakeComputeError> {
+    let Some((host, port)) = parse_host_port(&address) else {
+        return Err(WakeComputeError::BadComputeAddress(address));
+    };
+
+    let host_addr = IpAddr::from_str(host).ok();
+
+    let ssl_mode = match &server_name {
+        Some(_) => SslMode::Require,
+        None => SslMode::Disable,
+    };
+    let host = match server_name {
+        Some(host) => host.into(),
+        None => host.into(),
+    };
+
+    Ok(compute::ConnectInfo {
+        host_addr,
+        host,
+        port,
+        ssl_mode,
+    })
+}
+
+pub(super) fn parse_host_port(input: &str) -> Option<(&str, u16)> {
     let (host, port) = input.rsplit_once(':')?;
     let ipv6_brackets: &[_] = &['[', ']'];
     Some((host.trim_matches(ipv6_brackets), port.parse().ok()?))
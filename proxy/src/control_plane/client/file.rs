@@ -0,0 +1,573 @@
+//! Static control plane backend which serves endpoints from a config file.
+//!
+//! Self-hosted deployments don't have a Neon control plane to ask for role
+//! secrets and compute addresses. Instead, the operator describes every
+//! endpoint in a TOML or JSON file, which the proxy re-reads whenever its
+//! modification time changes. A config that fails to parse is logged and
+//! ignored, so the proxy keeps serving the last good version.
+//!
+//! ```toml
+//! [[endpoints]]
+//! id = "ep-example-123456"
+//! project_id = "example-project"
+//! compute = "10.0.0.12:5432"
+//! allowed_ips = ["10.0.0.0/8"]
+//! # optional, authenticate roles with client certificates issued by these CAs
+//! client_ca_bundle = """
+//! -----BEGIN CERTIFICATE-----
+//! ...
+//! -----END CERTIFICATE-----
+//! """
+//! # required for client certificates and OAUTHBEARER: the compute lets the
+//! # proxy in without a password and has pg_session_jwt installed
+//! compute_trusts_proxy = true
+//!
+//! # optional, restrict the SQL clients may run
+//! [endpoints.sql_firewall]
+//! deny_ddl = true
+//! max_rows = 10000
+//!
+//! [[endpoints.read_replicas]]
+//! compute = "10.0.0.13:5432"
+//!
+//! [[endpoints.roles]]
+//! name = "alice"
+//! secret = "SCRAM-SHA-256$4096:...$...:..."
+//!
+//! [[endpoints.jwks]]
+//! id = "auth0"
+//! jwks_url = "https://example.auth0.com/.well-known/jwks.json"
+//! role_names = ["authenticated"]
+//! # optional, the claim that must carry the role name
+//! role_claim = "preferred_username"
+//! ```
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, bail};
+use arc_swap::ArcSwap;
+use camino::{Utf8Path, Utf8PathBuf};
+use postgres_client::config::SslMode;
+use serde::Deserialize;
+use smol_str::format_smolstr;
+use tracing::{error, info};
+
+use super::cplane_proxy_v1::parse_host_port;
+use crate::auth::IpPattern;
+use crate::auth::backend::ComputeUserInfo;
+use crate::auth::backend::client_cert::ClientCa;
+use crate::auth::backend::jwt::AuthRule;
+use crate::cache::Cached;
+use crate::compute::ConnectInfo;
+use crate::context::RequestContext;
+use crate::control_plane::errors::{
+    ControlPlaneError, GetAuthInfoError, GetEndpointJwksError, WakeComputeError,
+};
+use crate::control_plane::messages::{
+    ColdStartInfo, ControlPlaneErrorMessage, Details, EndpointRateLimitConfig, ErrorInfo,
+    MetricsAuxInfo, Reason, Status,
+};
+use crate::control_plane::{
+    AccessBlockerFlags, AuthSecret, CachedNodeInfo, EndpointAccessControl, NodeInfo, ReplicaInfo,
+    RoleAccessControl,
+};
+use crate::intern::RoleNameInt;
+use crate::scram;
+use crate::sql_firewall::{SqlFirewall, SqlFirewallRules};
+use crate::types::{BranchId, EndpointId, ProjectId, RoleName};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    #[serde(default)]
+    endpoints: Vec<EndpointSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EndpointSpec {
+    id: EndpointId,
+    /// Defaults to the endpoint id. Only used for metrics labels.
+    project_id: Option<ProjectId>,
+    /// Defaults to the endpoint id. Only used for metrics labels.
+    branch_id: Option<BranchId>,
+    /// Compute address in `host:port` form.
+    compute: String,
+    /// If set, connect to compute over TLS and verify its certificate against this name.
+    server_name: Option<String>,
+    #[serde(default)]
+    allowed_ips: Vec<String>,
+    #[serde(default)]
+    allowed_vpc_endpoint_ids: Vec<String>,
+    #[serde(default)]
+    block_public_connections: bool,
+    #[serde(default)]
+    block_vpc_connections: bool,
+    /// PEM bundle of the CAs that issue client certificates for this endpoint.
+    client_ca_bundle: Option<String>,
+    #[serde(default)]
+    require_client_cert: bool,
+    #[serde(default)]
+    compute_trusts_proxy: bool,
+    sql_firewall: Option<SqlFirewallRules>,
+    #[serde(default)]
+    read_replicas: Vec<ReplicaSpec>,
+    #[serde(default)]
+    roles: Vec<RoleSpec>,
+    #[serde(default)]
+    jwks: Vec<JwksSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReplicaSpec {
+    /// Replica address in `host:port` form.
+    compute: String,
+    server_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RoleSpec {
+    name: RoleName,
+    /// SCRAM secret as stored in `pg_authid.rolpassword`.
+    /// Roles without a secret can only authenticate with JWTs.
+    secret: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JwksSpec {
+    id: String,
+    jwks_url: url::Url,
+    audience: Option<String>,
+    role_names: Vec<RoleName>,
+    /// Claim that must carry the role name, e.g. `preferred_username`.
+    role_claim: Option<String>,
+}
+
+struct EndpointEntry {
+    access: EndpointAccessControl,
+    roles: HashMap<RoleName, RoleAccessControl>,
+    jwks: Vec<AuthRule>,
+    node: NodeInfo,
+}
+
+/// Parsed contents of the config file, keyed by normalized endpoint id.
+struct Endpoints {
+    endpoints: HashMap<EndpointId, EndpointEntry>,
+}
+
+impl Endpoints {
+    fn parse(path: &Utf8Path, bytes: &[u8]) -> anyhow::Result<Self> {
+        let config: FileConfig = match path.extension() {
+            Some("toml") => {
+                let s = std::str::from_utf8(bytes).context("config is not valid utf-8")?;
+                toml::from_str(s)?
+            }
+            _ => serde_json::from_slice(bytes)?,
+        };
+
+        let mut endpoints = HashMap::with_capacity(config.endpoints.len());
+        for spec in config.endpoints {
+            let id = spec.id.normalize();
+            let entry = EndpointEntry::from_spec(spec)
+                .with_context(|| format!("invalid config for endpoint {id}"))?;
+            if endpoints.insert(id.clone(), entry).is_some() {
+                bail!("endpoint {id} is configured more than once");
+            }
+        }
+
+        Ok(Self { endpoints })
+    }
+
+    fn get(&self, endpoint: &EndpointId) -> Option<&EndpointEntry> {
+        self.endpoints.get(&endpoint.normalize())
+    }
+}
+
+impl EndpointEntry {
+    fn from_spec(spec: EndpointSpec) -> anyhow::Result<Self> {
+        let allowed_ips = spec
+            .allowed_ips
+            .iter()
+            .map(|ip| IpPattern::from_str(ip).with_context(|| format!("invalid ip pattern {ip}")))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let client_ca = match &spec.client_ca_bundle {
+            Some(pem) => Some(Arc::new(
+                ClientCa::parse(pem, spec.require_client_cert)
+                    .context("invalid client CA bundle")?,
+            )),
+            None => None,
+        };
+
+        let sql_firewall = match spec.sql_firewall {
+            Some(rules) => Some(Arc::new(
+                SqlFirewall::new(rules).context("invalid SQL firewall rules")?,
+            )),
+            None => None,
+        };
+
+        let mut roles = HashMap::with_capacity(spec.roles.len());
+        for role in spec.roles {
+            let secret = match role.secret {
+                Some(secret) => Some(
+                    scram::ServerSecret::parse(&secret)
+                        .map(AuthSecret::Scram)
+                        .with_context(|| format!("role {} has a malformed secret", role.name))?,
+                ),
+                None => None,
+            };
+            if roles
+                .insert(role.name.clone(), RoleAccessControl { secret })
+                .is_some()
+            {
+                bail!("role {} is configured more than once", role.name);
+            }
+        }
+
+        let jwks = spec
+            .jwks
+            .into_iter()
+            .map(|rule| AuthRule {
+                id: rule.id,
+                jwks_url: rule.jwks_url,
+                audience: rule.audience,
+                role_names: rule.role_names.iter().map(RoleNameInt::from).collect(),
+                role_claim: rule.role_claim,
+            })
+            .collect();
+
+        let endpoint_id = spec.id.normalize();
+        let project_id = spec
+            .project_id
+            .unwrap_or_else(|| ProjectId::from(endpoint_id.as_str()));
+        let branch_id = spec
+            .branch_id
+            .unwrap_or_else(|| BranchId::from(endpoint_id.as_str()));
+        let read_replicas = spec
+            .read_replicas
+            .into_iter()
+            .enumerate()
+            .map(|(i, replica)| {
+                Ok(ReplicaInfo {
+                    conn_info: parse_connect_info(&replica.compute, replica.server_name)?,
+                    compute_id: format_smolstr!("{endpoint_id}-replica-{i}"),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let node = NodeInfo {
+            conn_info: parse_connect_info(&spec.compute, spec.server_name)?,
+            aux: MetricsAuxInfo {
+                endpoint_id: (&endpoint_id).into(),
+                project_id: (&project_id).into(),
+                branch_id: (&branch_id).into(),
+                compute_id: endpoint_id.as_str().into(),
+                cold_start_info: ColdStartInfo::Warm,
+            },
+            read_replicas,
+        };
+
+        Ok(Self {
+            access: EndpointAccessControl {
+                allowed_ips: Arc::new(allowed_ips),
+                allowed_vpce: Arc::new(spec.allowed_vpc_endpoint_ids),
+                flags: AccessBlockerFlags {
+                    public_access_blocked: spec.block_public_connections,
+                    vpc_access_blocked: spec.block_vpc_connections,
+                },
+                rate_limits: EndpointRateLimitConfig::default(),
+                client_ca,
+                compute_trusts_proxy: spec.compute_trusts_proxy,
+                sql_firewall,
+            },
+            roles,
+            jwks,
+            node,
+        })
+    }
+}
+
+fn parse_connect_info(address: &str, server_name: Option<String>) -> anyhow::Result<ConnectInfo> {
+    let Some((host, port)) = parse_host_port(address) else {
+        bail!("invalid compute address {address}");
+    };
+    let host_addr = IpAddr::from_str(host).ok();
+    let (host, ssl_mode) = match server_name {
+        Some(server_name) => (server_name.into(), SslMode::Require),
+        None => (host.into(), SslMode::Disable),
+    };
+
+    Ok(ConnectInfo {
+        host_addr,
+        host,
+        port,
+        ssl_mode,
+    })
+}
+
+fn endpoint_not_found(endpoint: &EndpointId) -> ControlPlaneError {
+    let message: Box<str> = format!("endpoint {endpoint} is not configured").into();
+    ControlPlaneError::Message(Box::new(ControlPlaneErrorMessage {
+        error: message.clone(),
+        http_status_code: http::StatusCode::NOT_FOUND,
+        status: Some(Status {
+            code: "NOT_FOUND".into(),
+            message,
+            details: Details {
+                error_info: Some(ErrorInfo {
+                    reason: Reason::EndpointNotFound,
+                }),
+                retry_info: None,
+                user_facing_message: None,
+            },
+        }),
+    }))
+}
+
+#[derive(Clone)]
+pub struct FileControlPlane {
+    path: Arc<Utf8PathBuf>,
+    endpoints: Arc<ArcSwap<Endpoints>>,
+}
+
+impl FileControlPlane {
+    /// Load the config file. Unlike later reloads, a broken config at startup is an error.
+    pub fn new(path: Utf8PathBuf) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(&path).with_context(|| format!("failed to read {path}"))?;
+        let endpoints =
+            Endpoints::parse(&path, &bytes).with_context(|| format!("failed to parse {path}"))?;
+        info!(%path, endpoints = endpoints.endpoints.len(), "loaded control plane config");
+
+        Ok(Self {
+            path: Arc::new(path),
+            endpoints: Arc::new(ArcSwap::from_pointee(endpoints)),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+
+    /// Poll the config file and reload it whenever its modification time or size changes.
+    pub async fn watch(self, interval: Duration) {
+        let mut last = file_version(&self.path).await;
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+
+            let version = file_version(&self.path).await;
+            if version.is_none() || version == last {
+                continue;
+            }
+            last = version;
+
+            match self.reload().await {
+                Ok(count) => {
+                    info!(path = %self.path, endpoints = count, "reloaded control plane config")
+                }
+                Err(e) => {
+                    error!(path = %self.path, "could not reload control plane config, keeping the previous one: {e:#}")
+                }
+            }
+        }
+    }
+
+    async fn reload(&self) -> anyhow::Result<usize> {
+        let bytes = tokio::fs::read(&*self.path).await?;
+        let endpoints = Endpoints::parse(&self.path, &bytes)?;
+        let count = endpoints.endpoints.len();
+        self.endpoints.store(Arc::new(endpoints));
+        Ok(count)
+    }
+}
+
+async fn file_version(path: &Utf8Path) -> Option<(SystemTime, u64)> {
+    let metadata = tokio::fs::metadata(path).await.ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+impl super::ControlPlaneApi for FileControlPlane {
+    async fn get_role_access_control(
+        &self,
+        _ctx: &RequestContext,
+        endpoint: &EndpointId,
+        role: &RoleName,
+    ) -> Result<RoleAccessControl, GetAuthInfoError> {
+        let endpoints = self.endpoints.load();
+        // Same as a 404 from the control plane: the user just has no secret.
+        let secret = endpoints
+            .get(endpoint)
+            .and_then(|ep| ep.roles.get(role))
+            .and_then(|role| role.secret.clone());
+        Ok(RoleAccessControl { secret })
+    }
+
+    async fn get_endpoint_access_control(
+        &self,
+        _ctx: &RequestContext,
+        endpoint: &EndpointId,
+        _role: &RoleName,
+    ) -> Result<EndpointAccessControl, GetAuthInfoError> {
+        let endpoints = self.endpoints.load();
+        match endpoints.get(endpoint) {
+            Some(ep) => Ok(ep.access.clone()),
+            None => Ok(EndpointAccessControl {
+                allowed_ips: Arc::new(vec![]),
+                allowed_vpce: Arc::new(vec![]),
+                flags: AccessBlockerFlags::default(),
+                rate_limits: EndpointRateLimitConfig::default(),
+                client_ca: None,
+                compute_trusts_proxy: false,
+                sql_firewall: None,
+            }),
+        }
+    }
+
+    async fn get_endpoint_jwks(
+        &self,
+        _ctx: &RequestContext,
+        endpoint: &EndpointId,
+    ) -> Result<Vec<AuthRule>, GetEndpointJwksError> {
+        let endpoints = self.endpoints.load();
+        match endpoints.get(endpoint) {
+            Some(ep) => Ok(ep.jwks.clone()),
+            None => Err(endpoint_not_found(endpoint).into()),
+        }
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn wake_compute(
+        &self,
+        _ctx: &RequestContext,
+        user_info: &ComputeUserInfo,
+    ) -> Result<CachedNodeInfo, WakeComputeError> {
+        let endpoints = self.endpoints.load();
+        match endpoints.get(&user_info.endpoint) {
+            Some(ep) => Ok(Cached::new_uncached(ep.node.clone())),
+            None => Err(endpoint_not_found(&user_info.endpoint).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "SCRAM-SHA-256$4096:c2FsdA==$AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+    fn toml_config(secret: &str) -> String {
+        format!(
+            r#"
+[[endpoints]]
+id = "ep-foo-123"
+compute = "10.0.0.1:5432"
+allowed_ips = ["10.0.0.0/8", "192.168.0.1"]
+
+[endpoints.sql_firewall]
+deny_ddl = true
+max_rows = 100
+
+[[endpoints.read_replicas]]
+compute = "10.0.0.2:5432"
+
+[[endpoints.roles]]
+name = "alice"
+secret = "{secret}"
+
+[[endpoints.roles]]
+name = "jwt_only"
+
+[[endpoints.jwks]]
+id = "first"
+jwks_url = "https://example.com/jwks.json"
+role_names = ["jwt_only"]
+
+[[endpoints]]
+id = "ep-bar-456"
+compute = "[2001:db8::1]:6432"
+server_name = "compute.example.com"
+"#
+        )
+    }
+
+    #[test]
+    fn parse_toml() {
+        let path = Utf8Path::new("endpoints.toml");
+        let endpoints = Endpoints::parse(path, toml_config(SECRET).as_bytes()).unwrap();
+        assert_eq!(endpoints.endpoints.len(), 2);
+
+        let foo = endpoints.get(&EndpointId::from("ep-foo-123")).unwrap();
+        assert_eq!(foo.access.allowed_ips.len(), 2);
+        let firewall = foo.access.sql_firewall.as_ref().unwrap();
+        assert_eq!(firewall.max_rows(), Some(100));
+        assert!(firewall.check("drop table users").is_err());
+        assert!(foo.roles[&RoleName::from("alice")].secret.is_some());
+        assert!(foo.roles[&RoleName::from("jwt_only")].secret.is_none());
+        assert_eq!(foo.jwks.len(), 1);
+        assert_eq!(foo.node.conn_info.port, 5432);
+        assert_eq!(foo.node.conn_info.ssl_mode, SslMode::Disable);
+        assert_eq!(foo.node.read_replicas.len(), 1);
+        assert_eq!(foo.node.read_replicas[0].compute_id, "ep-foo-123-replica-0");
+
+        // pooler endpoints route to the same compute
+        assert!(
+            endpoints
+                .get(&EndpointId::from("ep-foo-123-pooler"))
+                .is_some()
+        );
+        assert!(endpoints.get(&EndpointId::from("ep-baz-789")).is_none());
+
+        let bar = endpoints.get(&EndpointId::from("ep-bar-456")).unwrap();
+        assert_eq!(
+            bar.node.conn_info.host_addr,
+            Some("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(&*bar.node.conn_info.host, "compute.example.com");
+        assert!(bar.access.sql_firewall.is_none());
+        assert_eq!(bar.node.conn_info.ssl_mode, SslMode::Require);
+    }
+
+    #[test]
+    fn parse_json() {
+        let path = Utf8Path::new("endpoints.json");
+        let config = serde_json::json!({
+            "endpoints": [{
+                "id": "ep-foo-123",
+                "compute": "localhost:5432",
+                "roles": [{ "name": "alice", "secret": SECRET }],
+            }]
+        });
+        let endpoints = Endpoints::parse(path, config.to_string().as_bytes()).unwrap();
+        let foo = endpoints.get(&EndpointId::from("ep-foo-123")).unwrap();
+        assert!(foo.access.allowed_ips.is_empty());
+        assert_eq!(foo.node.conn_info.host_addr, None);
+    }
+
+    #[test]
+    fn reject_invalid_config() {
+        let path = Utf8Path::new("endpoints.toml");
+
+        let bad_secret = toml_config("md5abcdef");
+        assert!(Endpoints::parse(path, bad_secret.as_bytes()).is_err());
+
+        let bad_ip = toml_config(SECRET).replace("192.168.0.1", "not-an-ip");
+        assert!(Endpoints::parse(path, bad_ip.as_bytes()).is_err());
+
+        let bad_address = toml_config(SECRET).replace("10.0.0.1:5432", "10.0.0.1");
+        assert!(Endpoints::parse(path, bad_address.as_bytes()).is_err());
+
+        let duplicate = toml_config(SECRET).replace("ep-bar-456", "ep-foo-123-pooler");
+        assert!(Endpoints::parse(path, duplicate.as_bytes()).is_err());
+
+        let unknown_field = format!("{}\nunknown = 1\n", toml_config(SECRET));
+        assert!(Endpoints::parse(path, unknown_field.as_bytes()).is_err());
+    }
+}
@@ -131,6 +131,9 @@ async fn do_get_auth_info(
             account_id: None,
             access_blocker_flags: AccessBlockerFlags::default(),
             rate_limits: EndpointRateLimitConfig::default(),
+            client_ca: None,
+            compute_trusts_proxy: false,
+            sql_firewall: None,
         })
     }
 
@@ -161,6 +164,7 @@ async fn do_get_endpoint_jwks(
                     .map(RoleName::from)
                     .map(|s| RoleNameInt::from(&s))
                     .collect(),
+                role_claim: None,
             });
         }
 
@@ -196,6 +200,7 @@ async fn do_wake_compute(&self) -> Result<NodeInfo, WakeComputeError> {
                 compute_id: "compute".into(),
                 cold_start_info: crate::control_plane::messages::ColdStartInfo::Warm,
             },
+            read_replicas: Vec::new(),
         };
 
         Ok(node)
@@ -235,6 +240,9 @@ async fn get_endpoint_access_control(
             allowed_vpce: Arc::new(info.allowed_vpc_endpoint_ids),
             flags: info.access_blocker_flags,
             rate_limits: info.rate_limits,
+            client_ca: info.client_ca,
+            compute_trusts_proxy: info.compute_trusts_proxy,
+            sql_firewall: info.sql_firewall,
         })
     }
 
@@ -1,4 +1,5 @@
 pub mod cplane_proxy_v1;
+pub mod file;
 #[cfg(any(test, feature = "testing"))]
 pub mod mock;
 
@@ -27,6 +28,8 @@
 pub enum ControlPlaneClient {
     /// Proxy V1 control plane API
     ProxyV1(cplane_proxy_v1::NeonControlPlaneClient),
+    /// Endpoints served from a static config file.
+    File(file::FileControlPlane),
     /// Local mock control plane.
     #[cfg(any(test, feature = "testing"))]
     PostgresMock(mock::MockControlPlane),
@@ -45,6 +48,7 @@ async fn get_role_access_control(
     ) -> Result<RoleAccessControl, errors::GetAuthInfoError> {
         match self {
             Self::ProxyV1(api) => api.get_role_access_control(ctx, endpoint, role).await,
+            Self::File(api) => api.get_role_access_control(ctx, endpoint, role).await,
             #[cfg(any(test, feature = "testing"))]
             Self::PostgresMock(api) => api.get_role_access_control(ctx, endpoint, role).await,
             #[cfg(test)]
@@ -62,6 +66,7 @@ async fn get_endpoint_access_control(
     ) -> Result<EndpointAccessControl, errors::GetAuthInfoError> {
         match self {
             Self::ProxyV1(api) => api.get_endpoint_access_control(ctx, endpoint, role).await,
+            Self::File(api) => api.get_endpoint_access_control(ctx, endpoint, role).await,
             #[cfg(any(test, feature = "testing"))]
             Self::PostgresMock(api) => api.get_endpoint_access_control(ctx, endpoint, role).await,
             #[cfg(test)]
@@ -76,6 +81,7 @@ async fn get_endpoint_jwks(
     ) -> Result<Vec<AuthRule>, errors::GetEndpointJwksError> {
         match self {
             Self::ProxyV1(api) => api.get_endpoint_jwks(ctx, endpoint).await,
+            Self::File(api) => api.get_endpoint_jwks(ctx, endpoint).await,
             #[cfg(any(test, feature = "testing"))]
             Self::PostgresMock(api) => api.get_endpoint_jwks(ctx, endpoint).await,
             #[cfg(test)]
@@ -90,6 +96,7 @@ async fn wake_compute(
     ) -> Result<CachedNodeInfo, errors::WakeComputeError> {
         match self {
             Self::ProxyV1(api) => api.wake_compute(ctx, user_info).await,
+            Self::File(api) => api.wake_compute(ctx, user_info).await,
             #[cfg(any(test, feature = "testing"))]
             Self::PostgresMock(api) => api.wake_compute(ctx, user_info).await,
             #[cfg(test)]
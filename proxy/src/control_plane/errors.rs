@@ -104,6 +104,12 @@ pub(crate) enum GetAuthInfoError {
     #[error("Console responded with a malformed auth secret")]
     BadSecret,
 
+    #[error("Console responded with a malformed client CA bundle")]
+    BadClientCa,
+
+    #[error("Console responded with malformed SQL firewall rules")]
+    BadSqlFirewall,
+
     #[error(transparent)]
     ApiError(ControlPlaneError),
 }
@@ -120,6 +126,8 @@ fn to_string_client(&self) -> String {
         match self {
             // We absolutely should not leak any secrets!
             Self::BadSecret => REQUEST_FAILED.to_owned(),
+            Self::BadClientCa => REQUEST_FAILED.to_owned(),
+            Self::BadSqlFirewall => REQUEST_FAILED.to_owned(),
             // However, API might return a meaningful error.
             Self::ApiError(e) => e.to_string_client(),
         }
@@ -130,6 +138,8 @@ impl ReportableError for GetAuthInfoError {
     fn get_error_kind(&self) -> ErrorKind {
         match self {
             Self::BadSecret => ErrorKind::ControlPlane,
+            Self::BadClientCa => ErrorKind::ControlPlane,
+            Self::BadSqlFirewall => ErrorKind::ControlPlane,
             Self::ApiError(_) => ErrorKind::ControlPlane,
         }
     }
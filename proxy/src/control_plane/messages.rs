@@ -7,6 +7,7 @@
 use crate::auth::IpPattern;
 use crate::intern::{AccountIdInt, BranchIdInt, EndpointIdInt, ProjectIdInt, RoleNameInt};
 use crate::proxy::retry::CouldRetry;
+use crate::sql_firewall::SqlFirewallRules;
 
 /// Generic error response with human-readable description.
 /// Note that we can't always present it to user as is.
@@ -255,6 +256,17 @@ pub(crate) struct GetEndpointAccessControl {
 
     #[serde(default)]
     pub(crate) rate_limits: EndpointRateLimitConfig,
+
+    /// PEM bundle of the CAs that issue client certificates for this endpoint.
+    pub(crate) client_ca_bundle: Option<String>,
+    /// Reject connections without a valid client certificate.
+    pub(crate) require_client_cert: Option<bool>,
+    /// The compute accepts the proxy's connections without a password, and has
+    /// `pg_session_jwt` installed. Needed to log in with client certificates or OAUTHBEARER.
+    pub(crate) compute_trusts_proxy: Option<bool>,
+
+    /// SQL firewall rules for this endpoint.
+    pub(crate) sql_firewall: Option<SqlFirewallRules>,
 }
 
 #[derive(Copy, Clone, Deserialize, Default, Debug)]
@@ -282,6 +294,17 @@ pub(crate) struct WakeCompute {
     pub(crate) address: Box<str>,
     pub(crate) server_name: Option<String>,
     pub(crate) aux: MetricsAuxInfo,
+    /// Read replicas of the endpoint which are currently running.
+    #[serde(default)]
+    pub(crate) read_replicas: Vec<ReadReplica>,
+}
+
+/// A read replica compute of an endpoint, as reported by `/proxy_wake_compute`.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ReadReplica {
+    pub(crate) address: Box<str>,
+    pub(crate) server_name: Option<String>,
+    pub(crate) compute_id: SmolStr,
 }
 
 /// Async response which concludes the console redirect auth flow.
@@ -410,6 +433,8 @@ pub struct JwksSettings {
     pub _provider_name: String,
     pub jwt_audience: Option<String>,
     pub role_names: Vec<RoleNameInt>,
+    /// Claim that must carry the role name, if any.
+    pub role_claim: Option<String>,
 }
 
 #[cfg(test)]
@@ -507,6 +532,17 @@ fn parse_wake_compute() -> anyhow::Result<()> {
             "aux": dummy_aux(),
         });
         serde_json::from_str::<WakeCompute>(&json.to_string())?;
+
+        let json = json!({
+            "address": "0.0.0.0",
+            "aux": dummy_aux(),
+            "read_replicas": [
+                { "address": "10.0.0.1:5432", "compute_id": "compute-replica" },
+            ],
+        });
+        let wake = serde_json::from_str::<WakeCompute>(&json.to_string())?;
+        assert_eq!(wake.read_replicas.len(), 1);
+        assert_eq!(wake.read_replicas[0].compute_id, "compute-replica");
         Ok(())
     }
 
@@ -540,6 +576,28 @@ fn parse_get_role_secret() -> anyhow::Result<()> {
             "project_id": "project",
         });
         serde_json::from_str::<GetEndpointAccessControl>(&json.to_string())?;
+        let json = json!({
+            "role_secret": "secret",
+            "client_ca_bundle": "-----BEGIN CERTIFICATE-----\n...\n-----END CERTIFICATE-----\n",
+            "require_client_cert": true,
+            "compute_trusts_proxy": true,
+        });
+        let body = serde_json::from_str::<GetEndpointAccessControl>(&json.to_string())?;
+        assert!(body.client_ca_bundle.is_some());
+        assert_eq!(body.require_client_cert, Some(true));
+        assert_eq!(body.compute_trusts_proxy, Some(true));
+        let json = json!({
+            "role_secret": "secret",
+            "sql_firewall": {
+                "deny_ddl": true,
+                "allowed_functions": ["count", "now"],
+                "max_rows": 1000,
+            },
+        });
+        let body = serde_json::from_str::<GetEndpointAccessControl>(&json.to_string())?;
+        let rules = body.sql_firewall.unwrap();
+        assert!(rules.deny_ddl);
+        assert_eq!(rules.max_rows, Some(1000));
 
         Ok(())
     }
@@ -12,8 +12,10 @@
 use std::sync::Arc;
 
 use messages::EndpointRateLimitConfig;
+use smol_str::SmolStr;
 
 use crate::auth::backend::ComputeUserInfo;
+use crate::auth::backend::client_cert::ClientCa;
 use crate::auth::backend::jwt::AuthRule;
 use crate::auth::{AuthError, IpPattern, check_peer_addr_is_in_list};
 use crate::cache::{Cached, TimedLru};
@@ -23,6 +25,7 @@
 use crate::intern::{AccountIdInt, EndpointIdInt, ProjectIdInt};
 use crate::protocol2::ConnectionInfoExtra;
 use crate::rate_limiter::{EndpointRateLimiter, LeakyBucketConfig};
+use crate::sql_firewall::SqlFirewall;
 use crate::types::{EndpointCacheKey, EndpointId, RoleName};
 use crate::{compute, scram};
 
@@ -61,6 +64,12 @@ pub(crate) struct AuthInfo {
     pub(crate) access_blocker_flags: AccessBlockerFlags,
     /// The rate limits for this endpoint.
     pub(crate) rate_limits: EndpointRateLimitConfig,
+    /// CAs trusted to issue client certificates for this endpoint.
+    pub(crate) client_ca: Option<Arc<ClientCa>>,
+    /// The compute accepts the proxy's connections without credentials.
+    pub(crate) compute_trusts_proxy: bool,
+    /// SQL firewall rules for this endpoint.
+    pub(crate) sql_firewall: Option<Arc<SqlFirewall>>,
 }
 
 /// Info for establishing a connection to a compute node.
@@ -70,6 +79,16 @@ pub(crate) struct NodeInfo {
 
     /// Labels for proxy's metrics.
     pub(crate) aux: MetricsAuxInfo,
+
+    /// Read replicas of the endpoint, if any.
+    pub(crate) read_replicas: Vec<ReplicaInfo>,
+}
+
+/// Info for establishing a connection to a read replica of an endpoint.
+#[derive(Clone)]
+pub(crate) struct ReplicaInfo {
+    pub(crate) conn_info: compute::ConnectInfo,
+    pub(crate) compute_id: SmolStr,
 }
 
 impl NodeInfo {
@@ -104,6 +123,9 @@ pub struct EndpointAccessControl {
     pub flags: AccessBlockerFlags,
 
     pub rate_limits: EndpointRateLimitConfig,
+    pub client_ca: Option<Arc<ClientCa>>,
+    pub compute_trusts_proxy: bool,
+    pub sql_firewall: Option<Arc<SqlFirewall>>,
 }
 
 impl EndpointAccessControl {
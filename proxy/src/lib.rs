@@ -106,6 +106,7 @@
 mod scram;
 mod serverless;
 mod signals;
+mod sql_firewall;
 mod stream;
 mod tls;
 mod types;
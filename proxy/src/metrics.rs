@@ -92,6 +92,9 @@ pub struct ProxyMetrics {
     /// Number of opened connections to a database.
     pub http_pool_opened_connections: Gauge,
 
+    /// Number of SQL over HTTP queries by whether their prepared statement was cached.
+    pub http_statement_cache_lookups_total: CounterVec<StaticLabelSet<CacheOutcome>>,
+
     /// Number of allowed ips
     #[metric(metadata = Thresholds::with_buckets([0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 10.0, 20.0, 50.0, 100.0]))]
     pub allowed_ips_number: Histogram<10>,
@@ -10,7 +10,7 @@
 use crate::metrics::Metrics;
 use crate::pglb::TlsRequired;
 use crate::pqproto::{
-    BeMessage, CancelKeyData, FeStartupPacket, ProtocolVersion, StartupMessageParams,
+    BeMessage, CancelKey, FeStartupPacket, ProtocolVersion, StartupMessageParams,
 };
 use crate::stream::{PqStream, Stream, StreamUpgradeError};
 use crate::tls::PG_ALPN_PROTOCOL;
@@ -49,8 +49,9 @@ fn get_error_kind(&self) -> crate::error::ErrorKind {
 }
 
 pub(crate) enum HandshakeData<S> {
-    Startup(PqStream<Stream<S>>, StartupMessageParams),
-    Cancel(CancelKeyData),
+    /// The client wants to connect, using the negotiated protocol version.
+    Startup(PqStream<Stream<S>>, StartupMessageParams, ProtocolVersion),
+    Cancel(CancelKey),
 }
 
 /// Establish a (most probably, secure) connection with the client.
@@ -68,7 +69,8 @@ pub(crate) async fn handshake<S: AsyncRead + AsyncWrite + Unpin + Send>(
     let (mut tried_ssl, mut tried_gss) = (false, false);
 
     const PG_PROTOCOL_EARLIEST: ProtocolVersion = ProtocolVersion::new(3, 0);
-    const PG_PROTOCOL_LATEST: ProtocolVersion = ProtocolVersion::new(3, 0);
+    // 3.2 only changes the cancel key length.
+    const PG_PROTOCOL_LATEST: ProtocolVersion = ProtocolVersion::new(3, 2);
 
     let (mut stream, mut msg) = PqStream::parse_startup(Stream::from_raw(stream)).await?;
     loop {
@@ -189,7 +191,7 @@ pub(crate) async fn handshake<S: AsyncRead + AsyncWrite + Unpin + Send>(
                     session_type = "normal",
                     "successful handshake"
                 );
-                break Ok(HandshakeData::Startup(stream, params));
+                break Ok(HandshakeData::Startup(stream, params, version));
             }
             // downgrade protocol version
             FeStartupPacket::StartupMessage { params, version }
@@ -222,7 +224,11 @@ pub(crate) async fn handshake<S: AsyncRead + AsyncWrite + Unpin + Send>(
                     session_type = "normal",
                     "successful handshake; unsupported minor version requested"
                 );
-                break Ok(HandshakeData::Startup(stream, supported));
+                break Ok(HandshakeData::Startup(
+                    stream,
+                    supported,
+                    PG_PROTOCOL_LATEST,
+                ));
             }
             FeStartupPacket::StartupMessage { version, params } => {
                 warn!(
@@ -233,9 +239,9 @@ pub(crate) async fn handshake<S: AsyncRead + AsyncWrite + Unpin + Send>(
                 );
                 return Err(HandshakeError::ProtocolViolation);
             }
-            FeStartupPacket::CancelRequest(cancel_key_data) => {
+            FeStartupPacket::CancelRequest(cancel_key) => {
                 info!(session_type = "cancellation", "successful handshake");
-                break Ok(HandshakeData::Cancel(cancel_key_data));
+                break Ok(HandshakeData::Cancel(cancel_key));
             }
         }
     }
@@ -2,10 +2,14 @@
 pub mod handshake;
 pub mod inprocess;
 pub mod passthrough;
+pub(crate) mod sql_firewall;
+pub(crate) mod txn_pool;
 
 use std::sync::Arc;
 
 use futures::FutureExt;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 use smol_str::ToSmolStr;
 use thiserror::Error;
 use tokio::io::{AsyncRead, AsyncWrite};
@@ -14,17 +18,19 @@
 
 use crate::auth;
 use crate::cancellation::{self, CancellationHandler};
-use crate::config::{ProxyConfig, ProxyProtocolV2, TlsConfig};
+use crate::config::{PoolingMode, ProxyConfig, ProxyProtocolV2, TlsConfig};
 use crate::context::RequestContext;
 use crate::error::{ReportableError, UserFacingError};
 use crate::metrics::{Metrics, NumClientConnectionsGuard};
 pub use crate::pglb::copy_bidirectional::ErrorSource;
 use crate::pglb::handshake::{HandshakeData, HandshakeError, handshake};
 use crate::pglb::passthrough::ProxyPassthrough;
+use crate::pglb::txn_pool::TxnPool;
 use crate::protocol2::{ConnectHeader, ConnectionInfo, ConnectionInfoExtra, read_proxy_protocol};
 use crate::proxy::handle_client;
 use crate::rate_limiter::EndpointRateLimiter;
 use crate::stream::Stream;
+use crate::tls::ClientCertChain;
 use crate::util::run_until_cancelled;
 
 pub const ERR_INSECURE_CONNECTION: &str = "connection is insecure (try using `sslmode=require`)";
@@ -60,6 +66,20 @@ pub async fn task_main(
     let connections = tokio_util::task::task_tracker::TaskTracker::new();
     let cancellations = tokio_util::task::task_tracker::TaskTracker::new();
 
+    let txn_pool = match config.pooling_mode {
+        PoolingMode::Session => None,
+        PoolingMode::Transaction => {
+            let pool = TxnPool::new(&config.http_config);
+            {
+                let pool = Arc::clone(&pool);
+                tokio::spawn(async move {
+                    pool.gc_worker(StdRng::from_entropy()).await;
+                });
+            }
+            Some(pool)
+        }
+    };
+
     while let Some(accept_result) =
         run_until_cancelled(listener.accept(), &cancellation_token).await
     {
@@ -76,6 +96,7 @@ pub async fn task_main(
 
         debug!(protocol = "tcp", %session_id, "accepted new TCP connection");
         let endpoint_rate_limiter2 = endpoint_rate_limiter.clone();
+        let txn_pool = txn_pool.clone();
 
         connections.spawn(async move {
             let (socket, conn_info) = match config.proxy_protocol_v2 {
@@ -126,6 +147,7 @@ pub async fn task_main(
                 endpoint_rate_limiter2,
                 conn_gauge,
                 cancellations,
+                txn_pool.as_ref(),
             )
             .instrument(ctx.span())
             .boxed()
@@ -175,7 +197,11 @@ pub async fn task_main(
 
 pub(crate) enum ClientMode {
     Tcp,
-    Websockets { hostname: Option<String> },
+    Websockets {
+        hostname: Option<String>,
+        /// Certificate from the TLS handshake of the underlying HTTP connection.
+        client_cert: Option<ClientCertChain>,
+    },
 }
 
 /// Abstracts the logic of handling TCP vs WS clients
@@ -190,7 +216,14 @@ pub fn allow_cleartext(&self) -> bool {
     pub fn hostname<'a, S>(&'a self, s: &'a Stream<S>) -> Option<&'a str> {
         match self {
             ClientMode::Tcp => s.sni_hostname(),
-            ClientMode::Websockets { hostname } => hostname.as_deref(),
+            ClientMode::Websockets { hostname, .. } => hostname.as_deref(),
+        }
+    }
+
+    pub fn client_certificates<S>(&self, s: &Stream<S>) -> Option<ClientCertChain> {
+        match self {
+            ClientMode::Tcp => s.client_certificates(),
+            ClientMode::Websockets { client_cert, .. } => client_cert.clone(),
         }
     }
 
@@ -245,6 +278,7 @@ pub(crate) async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin + Send>(
     endpoint_rate_limiter: Arc<EndpointRateLimiter>,
     conn_gauge: NumClientConnectionsGuard<'static>,
     cancellations: tokio_util::task::task_tracker::TaskTracker,
+    txn_pool: Option<&Arc<TxnPool>>,
 ) -> Result<Option<ProxyPassthrough<S>>, ClientRequestError> {
     debug!(
         protocol = %ctx.protocol(),
@@ -262,10 +296,9 @@ pub(crate) async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin + Send>(
     let pause = ctx.latency_timer_pause(crate::metrics::Waiting::Client);
     let do_handshake = handshake(ctx, client, mode.handshake_tls(tls), record_handshake_error);
 
-    let (mut client, params) = match tokio::time::timeout(config.handshake_timeout, do_handshake)
-        .await??
-    {
-        HandshakeData::Startup(client, params) => (client, params),
+    let handshake_data = tokio::time::timeout(config.handshake_timeout, do_handshake).await??;
+    let (mut client, params, version) = match handshake_data {
+        HandshakeData::Startup(client, params, version) => (client, params, version),
         HandshakeData::Cancel(cancel_key_data) => {
             // spawn a task to cancel the session, but don't wait for it
             cancellations.spawn({
@@ -296,7 +329,7 @@ pub(crate) async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin + Send>(
 
     let common_names = tls.map(|tls| &tls.common_names);
 
-    let (node, cancel_on_shutdown) = handle_client(
+    let (node, pooling, firewall, cancel_on_shutdown) = handle_client(
         config,
         auth_backend,
         ctx,
@@ -306,6 +339,8 @@ pub(crate) async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin + Send>(
         endpoint_rate_limiter,
         common_names,
         &params,
+        version,
+        txn_pool,
     )
     .await?;
 
@@ -323,6 +358,8 @@ pub(crate) async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin + Send>(
 
         aux: node.aux,
         private_link_id,
+        pooling,
+        firewall,
 
         _cancel_on_shutdown: cancel_on_shutdown,
 
@@ -6,6 +6,8 @@
 use utils::measured_stream::MeasuredStream;
 
 use super::copy_bidirectional::ErrorSource;
+use super::sql_firewall::SqlFirewallFilter;
+use super::txn_pool::TxnPooling;
 use crate::compute::MaybeRustlsStream;
 use crate::control_plane::messages::MetricsAuxInfo;
 use crate::metrics::{
@@ -22,6 +24,7 @@ pub(crate) async fn proxy_pass(
     compute: impl AsyncRead + AsyncWrite + Unpin,
     aux: MetricsAuxInfo,
     private_link_id: Option<SmolStr>,
+    firewall: Option<SqlFirewallFilter>,
 ) -> Result<(), ErrorSource> {
     // we will report ingress at a later date
     let usage_tx = USAGE_METRICS.register(Ids {
@@ -55,11 +58,17 @@ pub(crate) async fn proxy_pass(
 
     // Starting from here we only proxy the client's traffic.
     debug!("performing the proxy pass...");
-    let _ = crate::pglb::copy_bidirectional::copy_bidirectional_client_compute(
-        &mut client,
-        &mut compute,
-    )
-    .await?;
+    match firewall {
+        None => {
+            let _ = crate::pglb::copy_bidirectional::copy_bidirectional_client_compute(
+                &mut client,
+                &mut compute,
+            )
+            .await?;
+        }
+        // the SQL firewall needs to see whole messages.
+        Some(firewall) => firewall.relay(&mut client, &mut compute).await?,
+    }
 
     Ok(())
 }
@@ -71,6 +80,11 @@ pub(crate) struct ProxyPassthrough<S> {
     pub(crate) aux: MetricsAuxInfo,
     pub(crate) private_link_id: Option<SmolStr>,
 
+    /// Set when the client should share pooled compute connections.
+    pub(crate) pooling: Option<TxnPooling>,
+    /// Set when the endpoint has SQL firewall rules.
+    pub(crate) firewall: Option<SqlFirewallFilter>,
+
     pub(crate) _cancel_on_shutdown: tokio::sync::oneshot::Sender<Infallible>,
 
     pub(crate) _req: NumConnectionRequestsGuard<'static>,
@@ -80,6 +94,29 @@ pub(crate) struct ProxyPassthrough<S> {
 
 impl<S: AsyncRead + AsyncWrite + Unpin> ProxyPassthrough<S> {
     pub(crate) async fn proxy_pass(self) -> Result<(), ErrorSource> {
-        proxy_pass(self.client, self.compute, self.aux, self.private_link_id).await
+        match self.pooling {
+            None => {
+                proxy_pass(
+                    self.client,
+                    self.compute,
+                    self.aux,
+                    self.private_link_id,
+                    self.firewall,
+                )
+                .await
+            }
+            Some(pooling) => {
+                pooling
+                    .proxy_pass(
+                        self.client,
+                        self.compute,
+                        self.aux,
+                        self._db_conn,
+                        self.private_link_id,
+                        self.firewall,
+                    )
+                    .await
+            }
+        }
     }
 }
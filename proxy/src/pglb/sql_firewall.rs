@@ -0,0 +1,334 @@
+//! Enforcement of the endpoint's SQL firewall on postgres protocol sessions.
+//!
+//! Queries sent with the simple or the extended query protocol are checked
+//! before they reach the compute. A rejected query is replaced by one the
+//! compute cannot parse, so the compute answers with an error and skips the rest
+//! of the pipeline exactly as it would for a bad query. We then swap that error
+//! for one that explains the violation.
+//!
+//! The rows of every statement are counted, and once a statement goes over
+//! the row limit the rest of its output is replaced by an error.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use sqlparser::parser::ParserError;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tracing::info;
+
+use super::copy_bidirectional::ErrorSource;
+use super::txn_pool::{CLIENT_FLUSH_BYTES, Message, MessageReader, split_cstr, write_message};
+use crate::context::RequestContext;
+use crate::error::UserFacingError;
+use crate::pqproto::ErrorCode;
+use crate::sql_firewall::{SqlFirewall, SqlFirewallError};
+
+/// Sent to the compute in place of a rejected query.
+const REJECTED_QUERY: &[u8] = b"neon_sql_firewall_rejected";
+
+const SQLSTATE_INSUFFICIENT_PRIVILEGE: ErrorCode = *b"42501";
+const SQLSTATE_PROGRAM_LIMIT_EXCEEDED: ErrorCode = *b"54000";
+
+/// Applies the SQL firewall to the messages of one client session.
+pub(crate) struct SqlFirewallFilter {
+    firewall: Arc<SqlFirewall>,
+    ctx: RequestContext,
+
+    /// Rejected queries waiting for the compute's error, with the number of syncs sent before each.
+    rejected: VecDeque<(u64, SqlFirewallError)>,
+    syncs_sent: u64,
+    syncs_received: u64,
+
+    /// Rows returned by the current statement.
+    rows: usize,
+    /// The current statement went over the row limit, drop its output until `ReadyForQuery`.
+    discarding: bool,
+}
+
+impl SqlFirewallFilter {
+    pub(crate) fn new(firewall: Arc<SqlFirewall>, ctx: RequestContext) -> Self {
+        Self {
+            firewall,
+            ctx,
+            rejected: VecDeque::new(),
+            syncs_sent: 0,
+            syncs_received: 0,
+            rows: 0,
+            discarding: false,
+        }
+    }
+
+    /// Check a message from the client, returning the message to send to the compute.
+    pub(super) fn client_message(&mut self, msg: Message) -> Message {
+        let syncs = self.syncs_sent;
+        match msg.tag {
+            b'Q' => {
+                self.syncs_sent += 1;
+                let query = split_cstr(&msg.body).map_or(&msg.body[..], |(query, _)| query);
+                match self.check(query) {
+                    Ok(()) => msg,
+                    Err(e) => {
+                        self.reject(syncs, e);
+                        rejected_query()
+                    }
+                }
+            }
+            b'P' => {
+                let Some((name, rest)) = split_cstr(&msg.body) else {
+                    return msg;
+                };
+                let Some((query, params)) = split_cstr(rest) else {
+                    return msg;
+                };
+                match self.check(query) {
+                    Ok(()) => msg,
+                    Err(e) => {
+                        self.reject(syncs, e);
+                        let mut body = vec![];
+                        for part in [name, b"\0", REJECTED_QUERY, b"\0", params] {
+                            body.extend_from_slice(part);
+                        }
+                        Message {
+                            tag: b'P',
+                            body: Bytes::from(body),
+                        }
+                    }
+                }
+            }
+            // the fast-path function call bypasses the function allowlist.
+            b'F' if !self.firewall.allows_function_calls() => {
+                self.syncs_sent += 1;
+                self.reject(syncs, SqlFirewallError::FunctionCall);
+                rejected_query()
+            }
+            b'F' | b'S' => {
+                self.syncs_sent += 1;
+                msg
+            }
+            _ => msg,
+        }
+    }
+
+    /// Check a message from the compute, returning the message to send to the client, if any.
+    pub(super) fn server_message(&mut self, msg: Message) -> Option<Message> {
+        match msg.tag {
+            // ReadyForQuery
+            b'Z' => {
+                self.syncs_received += 1;
+                // rejected messages the compute skipped because of an earlier error.
+                while let Some((syncs, _)) = self.rejected.front()
+                    && *syncs < self.syncs_received
+                {
+                    self.rejected.pop_front();
+                }
+                self.rows = 0;
+                self.discarding = false;
+                Some(msg)
+            }
+            _ if self.discarding => None,
+            // DataRow
+            b'D' => {
+                self.rows += 1;
+                match self.firewall.max_rows() {
+                    Some(max_rows) if self.rows > max_rows => {
+                        self.discarding = true;
+                        let e = SqlFirewallError::TooManyRows(max_rows);
+                        self.ctx.set_sql_firewall_violation(e.violation());
+                        Some(error_response(&e, SQLSTATE_PROGRAM_LIMIT_EXCEEDED))
+                    }
+                    _ => Some(msg),
+                }
+            }
+            // ErrorResponse
+            b'E' => {
+                self.rows = 0;
+                let is_rejection = msg
+                    .body
+                    .windows(REJECTED_QUERY.len())
+                    .any(|w| w == REJECTED_QUERY);
+                if is_rejection && let Some((_, e)) = self.rejected.pop_front() {
+                    return Some(error_response(&e, SQLSTATE_INSUFFICIENT_PRIVILEGE));
+                }
+                Some(msg)
+            }
+            // CommandComplete, EmptyQueryResponse
+            b'C' | b'I' => {
+                self.rows = 0;
+                Some(msg)
+            }
+            _ => Some(msg),
+        }
+    }
+
+    fn check(&self, query: &[u8]) -> Result<(), SqlFirewallError> {
+        let Ok(query) = std::str::from_utf8(query) else {
+            let e = ParserError::ParserError("query is not valid UTF-8".to_owned());
+            return Err(e.into());
+        };
+        self.firewall.check(query)
+    }
+
+    fn reject(&mut self, syncs: u64, e: SqlFirewallError) {
+        info!(error = %e, "query rejected by the SQL firewall");
+        self.ctx.set_sql_firewall_violation(e.violation());
+        self.rejected.push_back((syncs, e));
+    }
+
+    /// Forward messages between the client and the compute until either side hangs up.
+    pub(super) async fn relay(
+        mut self,
+        client: &mut (impl AsyncRead + AsyncWrite + Unpin),
+        compute: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    ) -> Result<(), ErrorSource> {
+        let mut client_reader = MessageReader::default();
+        let mut client_out = vec![];
+        let mut compute_reader = MessageReader::default();
+        let mut compute_out = vec![];
+
+        loop {
+            tokio::select! {
+                msg = client_reader.read(client) => {
+                    let Some(msg) = msg.map_err(ErrorSource::Client)? else {
+                        return Ok(());
+                    };
+                    let terminate = msg.tag == b'X';
+
+                    let msg = self.client_message(msg);
+                    write_message(&mut compute_out, msg.tag, &[&msg.body]);
+                    let buffered = client_reader.has_buffered();
+                    if terminate || !buffered || compute_out.len() >= CLIENT_FLUSH_BYTES {
+                        compute.write_all(&compute_out).await.map_err(ErrorSource::Compute)?;
+                        compute.flush().await.map_err(ErrorSource::Compute)?;
+                        compute_out.clear();
+                    }
+                    if terminate {
+                        return Ok(());
+                    }
+                }
+                msg = compute_reader.read(compute) => {
+                    let Some(msg) = msg.map_err(ErrorSource::Compute)? else {
+                        return Ok(());
+                    };
+
+                    if let Some(msg) = self.server_message(msg) {
+                        write_message(&mut client_out, msg.tag, &[&msg.body]);
+                    }
+                    let buffered = compute_reader.has_buffered();
+                    if !buffered || client_out.len() >= CLIENT_FLUSH_BYTES {
+                        client.write_all(&client_out).await.map_err(ErrorSource::Client)?;
+                        client.flush().await.map_err(ErrorSource::Client)?;
+                        client_out.clear();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A simple query the compute will fail to parse.
+fn rejected_query() -> Message {
+    let mut body = REJECTED_QUERY.to_vec();
+    body.push(0);
+    Message {
+        tag: b'Q',
+        body: Bytes::from(body),
+    }
+}
+
+fn error_response(e: &SqlFirewallError, code: ErrorCode) -> Message {
+    let msg = e.to_string_client();
+    let mut body = vec![];
+    for part in [b"SERROR\0C", &code[..], b"\0M", msg.as_bytes(), b"\0\0"] {
+        body.extend_from_slice(part);
+    }
+    Message {
+        tag: b'E',
+        body: Bytes::from(body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_firewall::SqlFirewallRules;
+
+    fn filter(rules: SqlFirewallRules) -> SqlFirewallFilter {
+        let firewall = SqlFirewall::new(rules).unwrap();
+        SqlFirewallFilter::new(Arc::new(firewall), RequestContext::test())
+    }
+
+    fn msg(tag: u8, body: &'static [u8]) -> Message {
+        Message {
+            tag,
+            body: Bytes::from_static(body),
+        }
+    }
+
+    fn compute_error(message: &[u8]) -> Message {
+        let body = [b"SERROR\0C42601\0M", message, b"\0\0"].concat();
+        Message {
+            tag: b'E',
+            body: Bytes::from(body),
+        }
+    }
+
+    #[test]
+    fn rejects_queries() {
+        let mut filter = filter(SqlFirewallRules {
+            deny_ddl: true,
+            ..SqlFirewallRules::default()
+        });
+
+        let allowed = filter.client_message(msg(b'Q', b"select 1\0"));
+        assert_eq!(&allowed.body[..], b"select 1\0");
+
+        let rejected = filter.client_message(msg(b'Q', b"drop table t\0"));
+        assert_eq!(&rejected.body[..], b"neon_sql_firewall_rejected\0");
+
+        // the extended protocol keeps the statement name and parameter types.
+        let rejected = filter.client_message(msg(b'P', b"s1\0drop table t\0\0\0"));
+        assert_eq!(&rejected.body[..], b"s1\0neon_sql_firewall_rejected\0\0\0");
+        filter.client_message(msg(b'S', b""));
+
+        // the answer to `select 1`.
+        assert!(filter.server_message(msg(b'Z', b"I")).is_some());
+
+        let syntax_error = b"syntax error at or near \"neon_sql_firewall_rejected\"";
+        let e = filter.server_message(compute_error(syntax_error)).unwrap();
+        assert!(e.body.starts_with(b"SERROR\0C42501\0"));
+        assert!(filter.server_message(msg(b'Z', b"I")).is_some());
+
+        // other errors are forwarded as they are.
+        let e = filter.server_message(compute_error(b"oops")).unwrap();
+        assert!(e.body.ends_with(b"oops\0\0"));
+
+        // the rejected Parse was answered too.
+        let e = filter.server_message(compute_error(syntax_error)).unwrap();
+        assert!(e.body.starts_with(b"SERROR\0C42501\0"));
+        assert!(filter.server_message(msg(b'Z', b"I")).is_some());
+        assert!(filter.rejected.is_empty());
+    }
+
+    #[test]
+    fn limits_rows() {
+        let mut filter = filter(SqlFirewallRules {
+            max_rows: Some(2),
+            ..SqlFirewallRules::default()
+        });
+
+        filter.client_message(msg(b'Q', b"select * from t\0"));
+        assert!(filter.server_message(msg(b'T', b"")).is_some());
+        assert!(filter.server_message(msg(b'D', b"")).is_some());
+        assert!(filter.server_message(msg(b'D', b"")).is_some());
+
+        let e = filter.server_message(msg(b'D', b"")).unwrap();
+        assert_eq!(e.tag, b'E');
+        assert!(e.body.starts_with(b"SERROR\0C54000\0"));
+        assert!(filter.server_message(msg(b'D', b"")).is_none());
+        assert!(filter.server_message(msg(b'C', b"SELECT 4\0")).is_none());
+
+        assert!(filter.server_message(msg(b'Z', b"I")).is_some());
+        assert!(filter.server_message(msg(b'D', b"")).is_some());
+    }
+}
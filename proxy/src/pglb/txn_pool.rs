@@ -0,0 +1,1261 @@
+//! Transaction-level connection pooling for postgres clients.
+//!
+//! Instead of pinning each client to one compute connection for its whole
+//! lifetime, client sessions borrow an authenticated compute connection from
+//! a per-endpoint pool whenever they send something, and give it back as soon
+//! as the compute reports an idle `ReadyForQuery` with nothing else in flight.
+//!
+//! Named prepared statements are tracked per client session and given a
+//! server-side name derived from their contents, so they can be prepared
+//! again on whichever connection the session borrows next. Each connection
+//! keeps up to `max_prepared_statements` of them, closing the least recently
+//! used one to make room, like pgbouncer does.
+//!
+//! Session state other than prepared statements (`SET`, temporary tables,
+//! `LISTEN`, advisory locks, SQL-level `PREPARE`) is not preserved between
+//! transactions. Cancel requests go to the connection the session holds at
+//! the time, if any.
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures::FutureExt;
+use hashlink::LruCache;
+use smol_str::{SmolStr, format_smolstr};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
+use tracing::{debug, info};
+use utils::measured_stream::MeasuredStream;
+
+use super::copy_bidirectional::ErrorSource;
+use super::sql_firewall::SqlFirewallFilter;
+use crate::auth;
+use crate::auth::backend::ComputeUserInfo;
+use crate::cancellation::CancelClosure;
+use crate::compute::{self, ComputeConnection, MaybeRustlsStream};
+use crate::config::ProxyConfig;
+use crate::context::RequestContext;
+use crate::control_plane::messages::MetricsAuxInfo;
+use crate::error::{ErrorKind, ReportableError, UserFacingError};
+use crate::metrics::{Direction, Metrics, NumDbConnectionsGuard};
+use crate::pqproto::{SQLSTATE_INTERNAL_ERROR, WriteBuf};
+use crate::proxy::connect_compute::{TcpMechanism, connect_to_compute};
+use crate::proxy::replicas::Router;
+use crate::serverless::conn_pool_lib::{
+    Client, ClientDataEnum, ClientInnerCommon, ClientInnerExt, ConnInfo, EndpointConnPool,
+    GlobalConnPool,
+};
+use crate::usage_metrics::{Ids, MetricCounterRecorder, USAGE_METRICS};
+
+/// Pool of idle compute connections, shared by all client sessions.
+///
+/// Connections are keyed by endpoint, role and database like the SQL over HTTP
+/// pools, with a hash of the forwarded startup parameters added to the options
+/// under [`STARTUP_PARAMS_KEY`].
+pub(crate) type TxnPool = GlobalConnPool<PooledCompute, EndpointConnPool<PooledCompute>>;
+
+/// Sets apart the connections of sessions which sent different startup parameters.
+pub(crate) const STARTUP_PARAMS_KEY: &str = "startup_params";
+
+/// Largest message we accept from either side, same as postgres' own limit.
+const MAX_MESSAGE_SIZE: usize = 0x3fff_ffff;
+
+/// Flush the responses to the client once this many bytes are buffered.
+pub(super) const CLIENT_FLUSH_BYTES: usize = 64 * 1024;
+
+#[derive(Clone)]
+pub(crate) struct ClientDataTcp();
+
+#[derive(Debug, Error)]
+pub(crate) enum TxnPoolError {
+    #[error("{0}")]
+    Connect(#[from] compute::ConnectionError),
+    #[error("{0}")]
+    Postgres(#[from] compute::PostgresError),
+}
+
+impl ReportableError for TxnPoolError {
+    fn get_error_kind(&self) -> ErrorKind {
+        match self {
+            TxnPoolError::Connect(e) => e.get_error_kind(),
+            TxnPoolError::Postgres(e) => e.get_error_kind(),
+        }
+    }
+}
+
+impl UserFacingError for TxnPoolError {
+    fn to_string_client(&self) -> String {
+        match self {
+            TxnPoolError::Connect(e) => e.to_string_client(),
+            TxnPoolError::Postgres(e) => e.to_string_client(),
+        }
+    }
+}
+
+/// Opens authenticated compute connections for a session when the pool has none to lend.
+trait Connect {
+    async fn connect(
+        &self,
+        ctx: &RequestContext,
+    ) -> Result<(ComputeConnection, compute::PostgresSettings), TxnPoolError>;
+}
+
+/// Opens more authenticated connections to the compute on behalf of one client session.
+pub(crate) struct ComputeConnector {
+    pub(crate) config: &'static ProxyConfig,
+    pub(crate) auth_info: compute::AuthInfo,
+    pub(crate) backend: auth::Backend<'static, ComputeUserInfo>,
+}
+
+impl ComputeConnector {
+    fn user_info(&self) -> &ComputeUserInfo {
+        match &self.backend {
+            auth::Backend::ControlPlane(_, user_info) => user_info,
+            auth::Backend::Local(_) => unreachable!("local proxy does not run tcp proxy service"),
+        }
+    }
+}
+
+impl Connect for ComputeConnector {
+    async fn connect(
+        &self,
+        ctx: &RequestContext,
+    ) -> Result<(ComputeConnection, compute::PostgresSettings), TxnPoolError> {
+        let mechanism = TcpMechanism {
+            locks: &self.config.connect_compute_locks,
+            router: Router::new(
+                self.config.read_replicas.as_ref(),
+                &self.user_info().options,
+            ),
+        };
+        let mut node = connect_to_compute(
+            ctx,
+            &mechanism,
+            &self.backend,
+            self.config.wake_compute_retry_config,
+            &self.config.connect_to_compute,
+        )
+        .await?;
+        let settings = self
+            .auth_info
+            .authenticate(ctx, &mut node, self.user_info())
+            .await?;
+        Ok((node, settings))
+    }
+}
+
+/// An authenticated compute connection which is not tied to a client session.
+pub(crate) struct PooledCompute {
+    stream: MaybeRustlsStream,
+    reader: MessageReader,
+    cancel_closure: CancelClosure,
+    /// Server-side names of the statements we have prepared on this connection,
+    /// least recently used first.
+    prepared: LruCache<SmolStr, ()>,
+    /// Set when the connection can no longer be used.
+    broken: bool,
+    last_used: Instant,
+    idle_timeout: Duration,
+    _guage: NumDbConnectionsGuard<'static>,
+}
+
+impl ClientInnerExt for PooledCompute {
+    fn is_closed(&self) -> bool {
+        // nothing polls idle connections, so treat the ones idle for too long as closed
+        // and let the pool gc them.
+        self.broken || self.last_used.elapsed() >= self.idle_timeout
+    }
+
+    fn get_process_id(&self) -> i32 {
+        self.cancel_closure.process_id()
+    }
+}
+
+impl PooledCompute {
+    fn new(
+        stream: MaybeRustlsStream,
+        guage: NumDbConnectionsGuard<'static>,
+        cancel_closure: CancelClosure,
+        idle_timeout: Duration,
+    ) -> Self {
+        PooledCompute {
+            stream,
+            reader: MessageReader::default(),
+            cancel_closure,
+            prepared: LruCache::new_unbounded(),
+            broken: false,
+            last_used: Instant::now(),
+            idle_timeout,
+            _guage: guage,
+        }
+    }
+
+    /// Checks whether the compute hung up on us while the connection was idle.
+    fn poll_closed(&mut self) -> bool {
+        let mut buf = [0; 1];
+        match self.stream.read(&mut buf).now_or_never() {
+            // nothing to read, as expected.
+            None => false,
+            // EOF, an error, or an unexpected message (usually the termination notice).
+            Some(_) => true,
+        }
+    }
+}
+
+/// Transaction-pooling settings for one client session.
+pub(crate) struct TxnPooling {
+    pub(crate) ctx: RequestContext,
+    pub(crate) pool: Arc<TxnPool>,
+    pub(crate) connector: ComputeConnector,
+    pub(crate) conn_info: ConnInfo,
+    /// Cancels queries on the connection the client authenticated with.
+    pub(crate) cancel_closure: CancelClosure,
+    /// Receives the cancel closure of the connection the session holds.
+    pub(crate) linked: watch::Sender<Option<CancelClosure>>,
+}
+
+impl TxnPooling {
+    /// Serve the client session, multiplexing it onto pooled compute connections.
+    #[tracing::instrument(skip_all)]
+    pub(crate) async fn proxy_pass(
+        self,
+        client: impl AsyncRead + AsyncWrite + Unpin,
+        compute: MaybeRustlsStream,
+        aux: MetricsAuxInfo,
+        db_conn: NumDbConnectionsGuard<'static>,
+        private_link_id: Option<SmolStr>,
+        firewall: Option<SqlFirewallFilter>,
+    ) -> Result<(), ErrorSource> {
+        let usage_tx = USAGE_METRICS.register(Ids {
+            endpoint_id: aux.endpoint_id,
+            branch_id: aux.branch_id,
+            private_link_id,
+        });
+
+        let metrics = &Metrics::get().proxy.io_bytes;
+        let m_sent = metrics.with_labels(Direction::Tx);
+        let m_recv = metrics.with_labels(Direction::Rx);
+        let client = MeasuredStream::new(
+            client,
+            |cnt| {
+                // Number of bytes the client sent to the compute node (inbound).
+                metrics.get_metric(m_recv).inc_by(cnt as u64);
+                usage_tx.record_ingress(cnt as u64);
+            },
+            |cnt| {
+                // Number of bytes we sent to the client (outbound).
+                metrics.get_metric(m_sent).inc_by(cnt as u64);
+                usage_tx.record_egress(cnt as u64);
+            },
+        );
+
+        let mut session = Session {
+            ctx: &self.ctx,
+            pool: &self.pool,
+            connector: &self.connector,
+            conn_info: &self.conn_info,
+            linked_cancel: &self.linked,
+            client,
+            client_reader: MessageReader::default(),
+            client_out: Vec::new(),
+            firewall,
+            server: None,
+            statements: HashMap::new(),
+            max_prepared: self.connector.config.max_prepared_statements,
+            link: LinkState::default(),
+        };
+
+        // the connection we authenticated with is idle, so it goes straight to the pool.
+        drop(session.wrap(compute, aux, db_conn, self.cancel_closure.clone()));
+
+        debug!("performing the transaction pooled proxy pass...");
+        let res = session.run().await;
+
+        if let Some(mut server) = session.server.take()
+            && (res.is_err() || !session.link.is_idle())
+        {
+            // the client went away in the middle of a transaction,
+            // or we don't know what state the connection is in.
+            server.inner().1.discard();
+        }
+
+        res
+    }
+}
+
+/// A frontend or backend message.
+pub(super) struct Message {
+    pub(super) tag: u8,
+    pub(super) body: Bytes,
+}
+
+/// Reads whole messages from a stream.
+///
+/// Unlike [`crate::pqproto::read_message`], this is cancel safe.
+#[derive(Default)]
+pub(super) struct MessageReader {
+    buf: BytesMut,
+}
+
+impl MessageReader {
+    pub(super) async fn read<S: AsyncRead + Unpin>(
+        &mut self,
+        stream: &mut S,
+    ) -> io::Result<Option<Message>> {
+        loop {
+            if let Some(msg) = self.try_parse()? {
+                return Ok(Some(msg));
+            }
+            if stream.read_buf(&mut self.buf).await? == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+
+    fn try_parse(&mut self) -> io::Result<Option<Message>> {
+        let Some(header) = self.buf.get(..5) else {
+            return Ok(None);
+        };
+        let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        if !(4..=MAX_MESSAGE_SIZE).contains(&len) {
+            return Err(io::Error::other(format!("invalid message length {len}")));
+        }
+        if self.buf.len() < len + 1 {
+            // don't trust the length for allocating more than a bit at a time.
+            self.buf
+                .reserve((len + 1 - self.buf.len()).min(CLIENT_FLUSH_BYTES));
+            return Ok(None);
+        }
+
+        let mut msg = self.buf.split_to(len + 1);
+        let tag = msg[0];
+        msg.advance(5);
+        Ok(Some(Message {
+            tag,
+            body: msg.freeze(),
+        }))
+    }
+
+    /// Whether there are more messages we can process without waiting for IO.
+    pub(super) fn has_buffered(&self) -> bool {
+        !self.buf.is_empty()
+    }
+}
+
+pub(super) fn write_message(buf: &mut Vec<u8>, tag: u8, parts: &[&[u8]]) {
+    let len: usize = parts.iter().map(|p| p.len()).sum();
+    buf.push(tag);
+    buf.extend_from_slice(&(len as u32 + 4).to_be_bytes());
+    for part in parts {
+        buf.extend_from_slice(part);
+    }
+}
+
+/// Splits off a null-terminated string from the start of a message body.
+pub(super) fn split_cstr(body: &[u8]) -> Option<(&[u8], &[u8])> {
+    let end = body.iter().position(|&b| b == 0)?;
+    Some((&body[..end], &body[end + 1..]))
+}
+
+/// A named prepared statement the client created.
+struct Statement {
+    /// The name we use for the statement on compute connections.
+    server_name: SmolStr,
+    /// The Parse message body following the statement name.
+    definition: Bytes,
+}
+
+impl Statement {
+    fn new(definition: Bytes) -> Self {
+        let mut hasher = DefaultHasher::new();
+        definition.hash(&mut hasher);
+        Statement {
+            server_name: format_smolstr!("neon_stmt_{:016x}", hasher.finish()),
+            definition,
+        }
+    }
+}
+
+/// A response to a Parse or Close message we are waiting for.
+struct Expected {
+    tag: u8,
+    /// Whether the client asked for this, or we sent it on the client's behalf.
+    forward: bool,
+    /// Number of syncs sent before the message, to notice when the compute skipped it.
+    syncs: u64,
+    /// The statement created by a Parse message.
+    statement: Option<SmolStr>,
+}
+
+/// Progress of the compute connection currently linked to the session.
+#[derive(Default)]
+struct LinkState {
+    /// Syncs and queries sent, we expect a `ReadyForQuery` for each.
+    syncs_sent: u64,
+    syncs_received: u64,
+    /// Extended protocol messages were sent after the last sync.
+    unsynced: bool,
+    /// Transaction status from the last `ReadyForQuery`.
+    status: u8,
+    expected: VecDeque<Expected>,
+}
+
+impl LinkState {
+    fn is_idle(&self) -> bool {
+        self.syncs_sent == self.syncs_received && !self.unsynced && matches!(self.status, 0 | b'I')
+    }
+}
+
+struct Session<'a, C, K> {
+    ctx: &'a RequestContext,
+    pool: &'a Arc<TxnPool>,
+    connector: &'a K,
+    conn_info: &'a ConnInfo,
+    linked_cancel: &'a watch::Sender<Option<CancelClosure>>,
+
+    client: C,
+    client_reader: MessageReader,
+    client_out: Vec<u8>,
+    firewall: Option<SqlFirewallFilter>,
+
+    server: Option<Client<PooledCompute>>,
+    statements: HashMap<Bytes, Statement>,
+    /// Most statements to keep prepared on a compute connection.
+    max_prepared: usize,
+    link: LinkState,
+}
+
+impl<C: AsyncRead + AsyncWrite + Unpin, K: Connect> Session<'_, C, K> {
+    async fn run(&mut self) -> Result<(), ErrorSource> {
+        enum Event {
+            Client(io::Result<Option<Message>>),
+            Server(io::Result<Option<Message>>),
+        }
+
+        loop {
+            let event = match &mut self.server {
+                None => Event::Client(self.client_reader.read(&mut self.client).await),
+                Some(server) => {
+                    let (server, _) = server.inner();
+                    tokio::select! {
+                        msg = self.client_reader.read(&mut self.client) => Event::Client(msg),
+                        msg = server.reader.read(&mut server.stream) => Event::Server(msg),
+                    }
+                }
+            };
+
+            match event {
+                Event::Client(msg) => match msg.map_err(ErrorSource::Client)? {
+                    // the client hung up or said goodbye.
+                    None | Some(Message { tag: b'X', .. }) => return Ok(()),
+                    Some(msg) => self.handle_client_message(msg).await?,
+                },
+                Event::Server(msg) => match msg.map_err(ErrorSource::Compute)? {
+                    None => {
+                        return Err(ErrorSource::Compute(io::ErrorKind::UnexpectedEof.into()));
+                    }
+                    Some(msg) => self.handle_server_message(msg).await?,
+                },
+            }
+        }
+    }
+
+    async fn handle_client_message(&mut self, msg: Message) -> Result<(), ErrorSource> {
+        if self.server.is_none() {
+            self.checkout().await?;
+        }
+
+        let msg = match &mut self.firewall {
+            Some(firewall) => firewall.client_message(msg),
+            None => msg,
+        };
+
+        let mut out = Vec::with_capacity(msg.body.len() + 5);
+        match msg.tag {
+            // simple query, function call and sync all end with a ReadyForQuery.
+            b'Q' | b'F' | b'S' => {
+                self.link.syncs_sent += 1;
+                self.link.unsynced = false;
+                write_message(&mut out, msg.tag, &[&msg.body]);
+            }
+            b'P' => self.parse(msg.body, &mut out),
+            b'B' => self.bind(msg.body, &mut out),
+            b'D' | b'C' => self.describe_or_close(msg.tag, msg.body, &mut out),
+            tag => {
+                // execute and flush are part of the extended protocol,
+                // copy messages don't change the state.
+                if matches!(tag, b'E' | b'H') {
+                    self.link.unsynced = true;
+                }
+                write_message(&mut out, tag, &[&msg.body]);
+            }
+        }
+
+        let server = self.linked();
+        server
+            .stream
+            .write_all(&out)
+            .await
+            .map_err(ErrorSource::Compute)?;
+        server.stream.flush().await.map_err(ErrorSource::Compute)
+    }
+
+    async fn handle_server_message(&mut self, msg: Message) -> Result<(), ErrorSource> {
+        let tag = msg.tag;
+        match tag {
+            // ParseComplete, CloseComplete
+            b'1' | b'3' => {
+                let forward = match self.link.expected.pop_front() {
+                    Some(expected) if expected.tag == msg.tag => expected.forward,
+                    _ => {
+                        return Err(ErrorSource::Compute(io::Error::other(
+                            "unexpected response from compute",
+                        )));
+                    }
+                };
+                if forward {
+                    self.forward(msg);
+                }
+            }
+            // ReadyForQuery
+            b'Z' => {
+                self.link.syncs_received += 1;
+                self.link.status = msg.body.first().copied().unwrap_or(b'I');
+
+                // anything sent before this sync that didn't get a response was skipped
+                // because of an error.
+                let mut skipped = vec![];
+                while let Some(expected) = self.link.expected.front()
+                    && expected.syncs < self.link.syncs_received
+                {
+                    skipped.extend(self.link.expected.pop_front().and_then(|e| e.statement));
+                }
+                let server = self.linked();
+                for statement in skipped {
+                    server.prepared.remove(&statement);
+                }
+
+                self.forward(msg);
+            }
+            _ => self.forward(msg),
+        }
+
+        let idle = tag == b'Z' && self.link.is_idle();
+        let buffered = self.linked().reader.has_buffered();
+        if idle || !buffered || self.client_out.len() >= CLIENT_FLUSH_BYTES {
+            self.client
+                .write_all(&self.client_out)
+                .await
+                .map_err(ErrorSource::Client)?;
+            self.client.flush().await.map_err(ErrorSource::Client)?;
+            self.client_out.clear();
+        }
+
+        if idle {
+            self.release();
+        }
+        Ok(())
+    }
+
+    /// Queue a message for the client.
+    fn forward(&mut self, msg: Message) {
+        let msg = match &mut self.firewall {
+            Some(firewall) => firewall.server_message(msg),
+            None => Some(msg),
+        };
+        if let Some(msg) = msg {
+            write_message(&mut self.client_out, msg.tag, &[&msg.body]);
+        }
+    }
+
+    fn parse(&mut self, body: Bytes, out: &mut Vec<u8>) {
+        self.link.unsynced = true;
+
+        let Some((name, definition)) = split_cstr(&body).filter(|(name, _)| !name.is_empty())
+        else {
+            // unnamed statements don't outlive the transaction, and we let
+            // the compute complain about malformed messages.
+            write_message(out, b'P', &[&body]);
+            self.expect(b'P', true, None);
+            return;
+        };
+
+        let statement = Statement::new(body.slice_ref(definition));
+        self.prepare(&statement, true, out);
+        self.statements.insert(body.slice_ref(name), statement);
+    }
+
+    fn bind(&mut self, body: Bytes, out: &mut Vec<u8>) {
+        self.link.unsynced = true;
+        let Some((portal, rest)) = split_cstr(&body) else {
+            write_message(out, b'B', &[&body]);
+            return;
+        };
+        let Some((name, rest)) = split_cstr(rest) else {
+            write_message(out, b'B', &[&body]);
+            return;
+        };
+
+        match self.prepared_name(name, out) {
+            Some(server_name) => write_message(
+                out,
+                b'B',
+                &[portal, b"\0", server_name.as_bytes(), b"\0", rest],
+            ),
+            None => write_message(out, b'B', &[&body]),
+        }
+    }
+
+    fn describe_or_close(&mut self, tag: u8, body: Bytes, out: &mut Vec<u8>) {
+        self.link.unsynced = true;
+
+        let statement = match body.split_first() {
+            Some((b'S', name)) => split_cstr(name).map(|(name, _)| name),
+            _ => None,
+        };
+        let server_name = match statement {
+            Some(name) if tag == b'C' => self.statements.remove(name).map(|s| {
+                self.linked().prepared.remove(&s.server_name);
+                s.server_name
+            }),
+            Some(name) => self.prepared_name(name, out),
+            None => None,
+        };
+
+        match server_name {
+            Some(server_name) => write_message(out, tag, &[b"S", server_name.as_bytes(), b"\0"]),
+            None => write_message(out, tag, &[&body]),
+        }
+        if tag == b'C' {
+            self.expect(b'C', true, None);
+        }
+    }
+
+    /// Makes sure the client's statement exists on the linked connection,
+    /// and returns the server-side name for it.
+    fn prepared_name(&mut self, name: &[u8], out: &mut Vec<u8>) -> Option<SmolStr> {
+        if name.is_empty() {
+            return None;
+        }
+        let statement = self.statements.remove(name)?;
+        if self.linked().prepared.get(&statement.server_name).is_none() {
+            self.prepare(&statement, false, out);
+        }
+        let server_name = statement.server_name.clone();
+        self.statements
+            .insert(Bytes::copy_from_slice(name), statement);
+        Some(server_name)
+    }
+
+    /// Prepares the statement on the linked connection.
+    fn prepare(&mut self, statement: &Statement, forward: bool, out: &mut Vec<u8>) {
+        let name = statement.server_name.as_bytes();
+
+        // The statement might still exist if an earlier Close was skipped, and
+        // re-preparing it is cheaper than keeping track of that.
+        write_message(out, b'C', &[b"S", name, b"\0"]);
+        self.expect(b'C', false, None);
+
+        write_message(out, b'P', &[name, b"\0", &statement.definition]);
+        self.expect(b'P', forward, Some(statement.server_name.clone()));
+
+        let max_prepared = self.max_prepared;
+        let prepared = &mut self.linked().prepared;
+        prepared.insert(statement.server_name.clone(), ());
+
+        // make room by closing the statements which haven't been used for the longest.
+        let mut evicted = vec![];
+        while prepared.len() > max_prepared
+            && let Some((name, ())) = prepared.remove_lru()
+        {
+            evicted.push(name);
+        }
+        for name in evicted {
+            write_message(out, b'C', &[b"S", name.as_bytes(), b"\0"]);
+            self.expect(b'C', false, None);
+        }
+    }
+
+    fn expect(&mut self, request: u8, forward: bool, statement: Option<SmolStr>) {
+        let tag = match request {
+            b'P' => b'1',
+            _ => b'3',
+        };
+        self.link.expected.push_back(Expected {
+            tag,
+            forward,
+            syncs: self.link.syncs_sent,
+            statement,
+        });
+    }
+
+    fn linked(&mut self) -> &mut PooledCompute {
+        self.server
+            .as_mut()
+            .expect("a compute connection should be linked")
+            .inner()
+            .0
+    }
+
+    /// Borrow an idle connection from the pool, or open a new one.
+    async fn checkout(&mut self) -> Result<(), ErrorSource> {
+        // `get` can only fail for connections which track the session id, ours don't.
+        while let Ok(Some(mut client)) = self.pool.get(self.ctx, self.conn_info) {
+            let (conn, mut discard) = client.inner();
+            if conn.poll_closed() {
+                info!("pool: cached connection '{}' is closed", self.conn_info);
+                conn.broken = true;
+                discard.discard();
+                continue;
+            }
+            self.link(client);
+            return Ok(());
+        }
+
+        match self.connector.connect(self.ctx).await {
+            Ok((node, settings)) => {
+                let client = self.wrap(node.stream, node.aux, node.guage, settings.cancel_closure);
+                self.link(client);
+                Ok(())
+            }
+            Err(e) => {
+                self.ctx.set_error_kind(e.get_error_kind());
+                let mut buf = WriteBuf::new();
+                buf.write_error(&e.to_string_client(), SQLSTATE_INTERNAL_ERROR);
+                let _ = self.client.write_all_buf(&mut buf).await;
+                let _ = self.client.flush().await;
+                Err(ErrorSource::Compute(io::Error::other(e)))
+            }
+        }
+    }
+
+    fn wrap(
+        &self,
+        stream: MaybeRustlsStream,
+        aux: MetricsAuxInfo,
+        guage: NumDbConnectionsGuard<'static>,
+        cancel_closure: CancelClosure,
+    ) -> Client<PooledCompute> {
+        let idle_timeout = self.pool.get_idle_timeout();
+        let conn = PooledCompute::new(stream, guage, cancel_closure, idle_timeout);
+        let pool = match self.conn_info.endpoint_cache_key() {
+            Some(endpoint) => Arc::downgrade(&self.pool.get_or_create_endpoint_pool(&endpoint)),
+            None => Weak::new(),
+        };
+        let inner = ClientInnerCommon {
+            inner: conn,
+            aux,
+            conn_id: uuid::Uuid::new_v4(),
+            data: ClientDataEnum::Tcp(ClientDataTcp()),
+        };
+        Client::new(inner, self.conn_info.clone(), pool)
+    }
+
+    fn link(&mut self, mut client: Client<PooledCompute>) {
+        let (conn, _) = client.inner();
+        self.linked_cancel
+            .send_replace(Some(conn.cancel_closure.clone()));
+        self.link = LinkState::default();
+        self.server = Some(client);
+    }
+
+    /// Give the linked connection back to the pool.
+    fn release(&mut self) {
+        if let Some(mut client) = self.server.take() {
+            // the connection is about to serve other sessions.
+            self.linked_cancel.send_replace(None);
+            let (conn, _) = client.inner();
+            conn.last_used = Instant::now();
+            debug!(pid = conn.get_process_id(), "releasing compute connection");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn read_split_messages() {
+        let mut data = vec![];
+        write_message(&mut data, b'Q', &[b"select 1\0"]);
+        write_message(&mut data, b'S', &[]);
+
+        // deliver the messages one byte at a time.
+        let (mut stream, mut tx) = tokio::io::duplex(1);
+        tokio::spawn(async move { tx.write_all(&data).await.unwrap() });
+
+        let mut reader = MessageReader::default();
+        let msg = reader.read(&mut stream).await.unwrap().unwrap();
+        assert_eq!(msg.tag, b'Q');
+        assert_eq!(&msg.body[..], b"select 1\0");
+        let msg = reader.read(&mut stream).await.unwrap().unwrap();
+        assert_eq!(msg.tag, b'S');
+        assert!(msg.body.is_empty());
+        assert!(reader.read(&mut stream).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn statement_names() {
+        let a = Statement::new(Bytes::from_static(b"select $1\0\0\0"));
+        let b = Statement::new(Bytes::from_static(b"select $1\0\0\0"));
+        let c = Statement::new(Bytes::from_static(b"select $2\0\0\0"));
+        assert_eq!(a.server_name, b.server_name);
+        assert_ne!(a.server_name, c.server_name);
+        assert!(a.server_name.starts_with("neon_stmt_"));
+    }
+
+    /// Hands out the given compute connections, in order.
+    struct MockConnector(std::sync::Mutex<VecDeque<ComputeConnection>>);
+
+    impl Connect for MockConnector {
+        async fn connect(
+            &self,
+            _ctx: &RequestContext,
+        ) -> Result<(ComputeConnection, compute::PostgresSettings), TxnPoolError> {
+            let node = self
+                .0
+                .lock()
+                .unwrap()
+                .pop_front()
+                .expect("the test should provide enough compute connections");
+            let settings = compute::PostgresSettings {
+                params: HashMap::new(),
+                cancel_closure: cancel_closure(node.socket_addr.port().into()),
+                delayed_notice: vec![],
+            };
+            Ok((node, settings))
+        }
+    }
+
+    fn cancel_closure(process_id: i32) -> CancelClosure {
+        CancelClosure::new(
+            "127.0.0.1:5432".parse().unwrap(),
+            postgres_client::RawCancelToken {
+                ssl_mode: postgres_client::config::SslMode::Disable,
+                process_id,
+                secret_key: 0,
+            },
+            "localhost".into(),
+            user_info(),
+        )
+    }
+
+    fn user_info() -> ComputeUserInfo {
+        ComputeUserInfo {
+            endpoint: "endpoint".into(),
+            user: "user".into(),
+            options: crate::proxy::NeonOptions::default(),
+        }
+    }
+
+    /// The compute's end of a pooled connection.
+    struct MockCompute {
+        stream: tokio::net::TcpStream,
+        reader: MessageReader,
+    }
+
+    impl MockCompute {
+        /// Returns the connection for the proxy, its process id is the compute's port.
+        async fn new() -> (ComputeConnection, MockCompute) {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let socket_addr = listener.local_addr().unwrap();
+            let (stream, accepted) = tokio::join!(
+                tokio::net::TcpStream::connect(socket_addr),
+                listener.accept()
+            );
+            let node = ComputeConnection {
+                stream: MaybeRustlsStream::Raw(stream.unwrap()),
+                aux: MetricsAuxInfo {
+                    endpoint_id: (&crate::types::EndpointId::from("endpoint")).into(),
+                    project_id: (&crate::types::ProjectId::from("project")).into(),
+                    branch_id: (&crate::types::BranchId::from("branch")).into(),
+                    compute_id: "compute".into(),
+                    cold_start_info: crate::control_plane::messages::ColdStartInfo::Warm,
+                },
+                hostname: "localhost".into(),
+                ssl_mode: postgres_client::config::SslMode::Disable,
+                socket_addr,
+                guage: Metrics::get()
+                    .proxy
+                    .db_connections
+                    .guard(crate::metrics::Protocol::Tcp),
+            };
+            let compute = MockCompute {
+                stream: accepted.unwrap().0,
+                reader: MessageReader::default(),
+            };
+            (node, compute)
+        }
+
+        /// Reads the messages the proxy sent, up to and including a sync.
+        async fn receive(&mut self) -> Vec<Message> {
+            let mut msgs = vec![];
+            loop {
+                let msg = self.reader.read(&mut self.stream).await.unwrap().unwrap();
+                let tag = msg.tag;
+                msgs.push(msg);
+                if matches!(tag, b'S' | b'Q') {
+                    return msgs;
+                }
+            }
+        }
+
+        async fn send(&mut self, msgs: &[(u8, &[u8])]) {
+            let mut buf = vec![];
+            for (tag, body) in msgs {
+                write_message(&mut buf, *tag, &[body]);
+            }
+            self.stream.write_all(&buf).await.unwrap();
+        }
+    }
+
+    /// The client's end of the session.
+    struct MockClient {
+        stream: tokio::io::DuplexStream,
+        reader: MessageReader,
+    }
+
+    impl MockClient {
+        async fn send(&mut self, msgs: &[(u8, &[u8])]) {
+            let mut buf = vec![];
+            for (tag, body) in msgs {
+                write_message(&mut buf, *tag, &[body]);
+            }
+            self.stream.write_all(&buf).await.unwrap();
+        }
+
+        /// Reads the responses, up to and including a ReadyForQuery.
+        async fn receive(&mut self) -> Vec<u8> {
+            let mut tags = vec![];
+            loop {
+                let msg = self.reader.read(&mut self.stream).await.unwrap().unwrap();
+                tags.push(msg.tag);
+                if msg.tag == b'Z' {
+                    return tags;
+                }
+            }
+        }
+    }
+
+    fn tags(msgs: &[Message]) -> Vec<u8> {
+        msgs.iter().map(|m| m.tag).collect()
+    }
+
+    /// Runs `test` against a session served by the given compute connections.
+    async fn run_session<F>(
+        nodes: Vec<ComputeConnection>,
+        max_prepared: usize,
+        test: impl FnOnce(
+            MockClient,
+            Arc<TxnPool>,
+            ConnInfo,
+            watch::Receiver<Option<CancelClosure>>,
+        ) -> F,
+    ) where
+        F: Future<Output = ()>,
+    {
+        let config = Box::leak(Box::new(crate::config::HttpConfig {
+            accept_websockets: false,
+            pool_options: crate::serverless::GlobalConnPoolOptions {
+                max_conns_per_endpoint: 10,
+                gc_epoch: Duration::from_secs(60),
+                pool_shards: 2,
+                idle_timeout: Duration::from_secs(60),
+                opt_in: false,
+                max_total_conns: 10,
+                statement_cache_size: 0,
+            },
+            cancel_set: crate::serverless::cancel_set::CancelSet::new(0),
+            client_conn_threshold: u64::MAX,
+            max_request_size_bytes: usize::MAX,
+            response_limits: crate::config::ResponseLimits::unlimited(),
+        }));
+        let pool = TxnPool::new(config);
+        let conn_info = ConnInfo {
+            user_info: user_info(),
+            dbname: "db".into(),
+        };
+        let connector = MockConnector(std::sync::Mutex::new(nodes.into()));
+        let (linked, linked_rx) = watch::channel(None);
+        let ctx = RequestContext::test();
+
+        let (client, proxy) = tokio::io::duplex(CLIENT_FLUSH_BYTES);
+        let mut session = Session {
+            ctx: &ctx,
+            pool: &pool,
+            connector: &connector,
+            conn_info: &conn_info,
+            linked_cancel: &linked,
+            client: proxy,
+            client_reader: MessageReader::default(),
+            client_out: Vec::new(),
+            firewall: None,
+            server: None,
+            statements: HashMap::new(),
+            max_prepared,
+            link: LinkState::default(),
+        };
+        let client = MockClient {
+            stream: client,
+            reader: MessageReader::default(),
+        };
+
+        // the session ends when the test drops the client.
+        let (res, ()) = tokio::join!(
+            session.run(),
+            test(client, Arc::clone(&pool), conn_info.clone(), linked_rx)
+        );
+        assert!(res.is_ok());
+    }
+
+    /// Parse message for a named statement without parameter types.
+    fn parse(name: &str, query: &str) -> Vec<u8> {
+        [name.as_bytes(), b"\0", query.as_bytes(), b"\0\0\0"].concat()
+    }
+
+    /// Bind message for the unnamed portal, without parameters.
+    fn bind(name: &str) -> Vec<u8> {
+        [b"\0", name.as_bytes(), b"\0\0\0\0\0\0\0"].concat()
+    }
+
+    fn server_name(query: &str) -> SmolStr {
+        Statement::new(Bytes::from([query.as_bytes(), b"\0\0\0"].concat())).server_name
+    }
+
+    fn close_statement(name: &str) -> Vec<u8> {
+        [b"S", name.as_bytes(), b"\0"].concat()
+    }
+
+    #[tokio::test]
+    async fn release_at_idle_ready_for_query() {
+        let (node, mut compute) = MockCompute::new().await;
+        let pid = i32::from(node.socket_addr.port());
+
+        run_session(vec![node], 10, |mut client, pool, _, linked| async move {
+            client.send(&[(b'Q', b"begin\0")]).await;
+            assert_eq!(tags(&compute.receive().await), b"Q");
+            compute.send(&[(b'C', b"BEGIN\0"), (b'Z', b"T")]).await;
+            assert_eq!(client.receive().await, b"CZ");
+
+            // held for the rest of the transaction, and cancel requests go to it.
+            assert_eq!(pool.get_global_connections_count(), 0);
+            let linked_pid = linked.borrow().as_ref().map(|c| c.process_id());
+            assert_eq!(linked_pid, Some(pid));
+
+            client.send(&[(b'Q', b"commit\0")]).await;
+            assert_eq!(tags(&compute.receive().await), b"Q");
+            compute.send(&[(b'C', b"COMMIT\0"), (b'Z', b"I")]).await;
+            assert_eq!(client.receive().await, b"CZ");
+
+            // idle, so it's back in the pool.
+            assert_eq!(pool.get_global_connections_count(), 1);
+            assert!(linked.borrow().is_none());
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn reprepare_on_another_connection() {
+        let (node1, mut compute1) = MockCompute::new().await;
+        let (node2, mut compute2) = MockCompute::new().await;
+        let stmt = server_name("select 1");
+
+        run_session(
+            vec![node1, node2],
+            10,
+            |mut client, pool, conn_info, _| async move {
+                client
+                    .send(&[(b'P', &parse("s1", "select 1")), (b'S', b"")])
+                    .await;
+                let msgs = compute1.receive().await;
+                assert_eq!(tags(&msgs), b"CPS");
+                assert_eq!(&msgs[0].body[..], close_statement(&stmt));
+                assert_eq!(&msgs[1].body[..], parse(&stmt, "select 1"));
+                compute1
+                    .send(&[(b'3', b""), (b'1', b""), (b'Z', b"I")])
+                    .await;
+                // the close we sent on our own is not forwarded.
+                assert_eq!(client.receive().await, b"1Z");
+
+                // keep the first connection busy, so the session gets the second one.
+                let ctx = RequestContext::test();
+                let busy = pool.get(&ctx, &conn_info).unwrap();
+                assert!(busy.is_some());
+
+                client
+                    .send(&[(b'B', &bind("s1")), (b'E', b"\0\0\0\0\0"), (b'S', b"")])
+                    .await;
+                let msgs = compute2.receive().await;
+                assert_eq!(tags(&msgs), b"CPBES");
+                assert_eq!(&msgs[1].body[..], parse(&stmt, "select 1"));
+                assert_eq!(&msgs[2].body[..], bind(&stmt));
+                compute2
+                    .send(&[
+                        (b'3', b""),
+                        (b'1', b""),
+                        (b'2', b""),
+                        (b'C', b"SELECT 1\0"),
+                        (b'Z', b"I"),
+                    ])
+                    .await;
+                // the client already saw the statement being parsed.
+                assert_eq!(client.receive().await, b"2CZ");
+            },
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn skip_parse_responses_after_error() {
+        let (node, mut compute) = MockCompute::new().await;
+        let stmt1 = server_name("select 1");
+        let stmt2 = server_name("selec 2");
+
+        run_session(vec![node], 10, |mut client, _, _, _| async move {
+            client
+                .send(&[
+                    (b'P', &parse("s1", "select 1")),
+                    (b'P', &parse("s2", "selec 2")),
+                    (b'B', &bind("s2")),
+                    (b'S', b""),
+                ])
+                .await;
+            assert_eq!(tags(&compute.receive().await), b"CPCPBS");
+            // parsing the second statement fails, so the rest up to the sync is skipped.
+            compute
+                .send(&[
+                    (b'3', b""),
+                    (b'1', b""),
+                    (b'3', b""),
+                    (b'E', b"SERROR\0C42601\0Msyntax error\0\0"),
+                    (b'Z', b"I"),
+                ])
+                .await;
+            assert_eq!(client.receive().await, b"1EZ");
+
+            // only the statement that failed is prepared again.
+            client
+                .send(&[(b'B', &bind("s1")), (b'B', &bind("s2")), (b'S', b"")])
+                .await;
+            let msgs = compute.receive().await;
+            assert_eq!(tags(&msgs), b"BCPBS");
+            assert_eq!(&msgs[0].body[..], bind(&stmt1));
+            assert_eq!(&msgs[2].body[..], parse(&stmt2, "selec 2"));
+            compute
+                .send(&[
+                    (b'2', b""),
+                    (b'3', b""),
+                    (b'E', b"SERROR\0C42601\0Msyntax error\0\0"),
+                    (b'Z', b"I"),
+                ])
+                .await;
+            assert_eq!(client.receive().await, b"2EZ");
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn close_statements() {
+        let (node, mut compute) = MockCompute::new().await;
+        let stmt = server_name("select 1");
+
+        run_session(vec![node], 10, |mut client, _, _, _| async move {
+            client
+                .send(&[(b'P', &parse("s1", "select 1")), (b'S', b"")])
+                .await;
+            assert_eq!(tags(&compute.receive().await), b"CPS");
+            compute
+                .send(&[(b'3', b""), (b'1', b""), (b'Z', b"I")])
+                .await;
+            assert_eq!(client.receive().await, b"1Z");
+
+            // the client's close is forwarded, with the server-side name.
+            client
+                .send(&[(b'C', &close_statement("s1")), (b'S', b"")])
+                .await;
+            let msgs = compute.receive().await;
+            assert_eq!(tags(&msgs), b"CS");
+            assert_eq!(&msgs[0].body[..], close_statement(&stmt));
+            compute.send(&[(b'3', b""), (b'Z', b"I")]).await;
+            assert_eq!(client.receive().await, b"3Z");
+
+            // the statement is gone, compute gets to complain about it.
+            client.send(&[(b'B', &bind("s1")), (b'S', b"")]).await;
+            let msgs = compute.receive().await;
+            assert_eq!(tags(&msgs), b"BS");
+            assert_eq!(&msgs[0].body[..], bind("s1"));
+            compute
+                .send(&[
+                    (
+                        b'E',
+                        b"SERROR\0C26000\0Mprepared statement does not exist\0\0",
+                    ),
+                    (b'Z', b"I"),
+                ])
+                .await;
+            assert_eq!(client.receive().await, b"EZ");
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn evict_least_recently_used_statements() {
+        let (node, mut compute) = MockCompute::new().await;
+        let stmt1 = server_name("select 1");
+        let stmt2 = server_name("select 2");
+
+        run_session(vec![node], 2, |mut client, _, _, _| async move {
+            client
+                .send(&[
+                    (b'P', &parse("s1", "select 1")),
+                    (b'P', &parse("s2", "select 2")),
+                    (b'P', &parse("s3", "select 3")),
+                    (b'S', b""),
+                ])
+                .await;
+            let msgs = compute.receive().await;
+            assert_eq!(tags(&msgs), b"CPCPCPCS");
+            assert_eq!(&msgs[6].body[..], close_statement(&stmt1));
+            compute
+                .send(&[
+                    (b'3', b""),
+                    (b'1', b""),
+                    (b'3', b""),
+                    (b'1', b""),
+                    (b'3', b""),
+                    (b'1', b""),
+                    (b'3', b""),
+                    (b'Z', b"I"),
+                ])
+                .await;
+            assert_eq!(client.receive().await, b"111Z");
+
+            // the evicted statement is prepared again, evicting the next one.
+            client.send(&[(b'B', &bind("s1")), (b'S', b"")]).await;
+            let msgs = compute.receive().await;
+            assert_eq!(tags(&msgs), b"CPCBS");
+            assert_eq!(&msgs[2].body[..], close_statement(&stmt2));
+            assert_eq!(&msgs[3].body[..], bind(&stmt1));
+            compute
+                .send(&[
+                    (b'3', b""),
+                    (b'1', b""),
+                    (b'3', b""),
+                    (b'2', b""),
+                    (b'Z', b"I"),
+                ])
+                .await;
+            assert_eq!(client.receive().await, b"2Z");
+        })
+        .await;
+    }
+}
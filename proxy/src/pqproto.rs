@@ -58,6 +58,8 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 const RESERVED_INVALID_MAJOR_VERSION: u16 = 1234;
 /// <https://github.com/postgres/postgres/blob/ca481d3c9ab7bf69ff0c8d71ad3951d407f6a33c/src/include/libpq/pqcomm.h#L132>
 const CANCEL_REQUEST_CODE: ProtocolVersion = ProtocolVersion::new(1234, 5678);
+/// <https://www.postgresql.org/docs/18/protocol-message-formats.html#PROTOCOL-MESSAGE-FORMATS-BACKENDKEYDATA>
+const MAX_CANCEL_SECRET_LENGTH: usize = 256;
 /// <https://github.com/postgres/postgres/blob/ca481d3c9ab7bf69ff0c8d71ad3951d407f6a33c/src/include/libpq/pqcomm.h#L166>
 const NEGOTIATE_SSL_CODE: ProtocolVersion = ProtocolVersion::new(1234, 5679);
 /// <https://github.com/postgres/postgres/blob/ca481d3c9ab7bf69ff0c8d71ad3951d407f6a33c/src/include/libpq/pqcomm.h#L167>
@@ -154,15 +156,26 @@ pub async fn read_startup<S>(stream: &mut S) -> io::Result<FeStartupPacket>
     match header.version {
         // <https://www.postgresql.org/docs/current/protocol-message-formats.html#PROTOCOL-MESSAGE-FORMATS-CANCELREQUEST>
         CANCEL_REQUEST_CODE => {
-            if len != 8 {
+            if len < 8 {
                 return Err(io::Error::other(
                     "CancelRequest message is malformed, backend PID / secret key missing",
                 ));
             }
+            // since protocol 3.2, the secret key is variable length.
+            if len > 4 + MAX_CANCEL_SECRET_LENGTH {
+                return Err(io::Error::other(
+                    "CancelRequest message is malformed, secret key is too long",
+                ));
+            }
 
-            Ok(FeStartupPacket::CancelRequest(
-                read!(stream => CancelKeyData),
-            ))
+            let data = read!(stream => CancelKeyData);
+            let mut ext = vec![0; len - 8];
+            stream.read_exact(&mut ext).await?;
+
+            Ok(FeStartupPacket::CancelRequest(CancelKey {
+                data,
+                ext: ext.into_boxed_slice(),
+            }))
         }
         // <https://www.postgresql.org/docs/current/protocol-message-formats.html#PROTOCOL-MESSAGE-FORMATS-SSLREQUEST>
         NEGOTIATE_SSL_CODE => {
@@ -369,7 +382,7 @@ pub fn write_error(&mut self, msg: &str, error_code: ErrorCode) {
 
 #[derive(Debug)]
 pub enum FeStartupPacket {
-    CancelRequest(CancelKeyData),
+    CancelRequest(CancelKey),
     SslRequest {
         direct: Option<[u8; 8]>,
     },
@@ -464,11 +477,47 @@ fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> CancelKeyData {
     }
 }
 
+/// A cancel key as sent in `BackendKeyData` and `CancelRequest`.
+///
+/// Protocol 3.0 secrets are 4 bytes long, protocol 3.2 secrets can be up to 256 bytes.
+/// The PID and the first 4 bytes of the secret are always the [`CancelKeyData`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct CancelKey {
+    pub data: CancelKeyData,
+    /// The rest of the secret. Empty for protocol 3.0 keys.
+    pub ext: Box<[u8]>,
+}
+
+impl From<CancelKeyData> for CancelKey {
+    fn from(data: CancelKeyData) -> Self {
+        Self {
+            data,
+            ext: Box::default(),
+        }
+    }
+}
+
+impl fmt::Display for CancelKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.data, f)
+    }
+}
+
+impl fmt::Debug for CancelKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the secret should not end up in logs.
+        f.debug_struct("CancelKey")
+            .field("data", &self.data)
+            .field("ext_len", &self.ext.len())
+            .finish()
+    }
+}
+
 pub enum BeMessage<'a> {
     AuthenticationOk,
     AuthenticationSasl(BeAuthenticationSaslMessage<'a>),
     AuthenticationCleartextPassword,
-    BackendKeyData(CancelKeyData),
+    BackendKeyData(&'a CancelKey),
     ParameterStatus {
         name: &'a [u8],
         value: &'a [u8],
@@ -529,8 +578,11 @@ pub fn write_message(self, buf: &mut WriteBuf) {
             }
 
             // <https://www.postgresql.org/docs/current/protocol-message-formats.html#PROTOCOL-MESSAGE-FORMATS-BACKENDKEYDATA>
-            BeMessage::BackendKeyData(key_data) => {
-                buf.write_raw(8, b'K', |buf| buf.put_slice(key_data.as_bytes()));
+            BeMessage::BackendKeyData(key) => {
+                buf.write_raw(8 + key.ext.len(), b'K', |buf| {
+                    buf.put_slice(key.data.as_bytes());
+                    buf.put_slice(&key.ext);
+                });
             }
 
             // <https://www.postgresql.org/docs/current/protocol-message-formats.html#PROTOCOL-MESSAGE-FORMATS-NOTICERESPONSE>
@@ -593,7 +645,7 @@ mod tests {
     use zerocopy::IntoBytes;
 
     use super::ProtocolVersion;
-    use crate::pqproto::{FeStartupPacket, read_message, read_startup};
+    use crate::pqproto::{FeStartupPacket, id_to_cancel_key, read_message, read_startup};
 
     #[tokio::test]
     async fn reject_large_startup() {
@@ -663,6 +715,48 @@ async fn read_ssl_message() {
         };
     }
 
+    #[tokio::test]
+    async fn read_cancel_message() {
+        // protocol 3.0 keys are 8 bytes.
+        let mut payload = vec![];
+        payload.extend_from_slice(&16_u32.to_be_bytes());
+        payload.extend_from_slice(ProtocolVersion::new(1234, 5678).as_bytes());
+        payload.extend_from_slice(&0x1234_5678_9abc_def0_u64.to_be_bytes());
+
+        let startup = read_startup(&mut Cursor::new(&payload)).await.unwrap();
+        let FeStartupPacket::CancelRequest(key) = startup else {
+            panic!("unexpected startup message: {startup:?}");
+        };
+        assert_eq!(key.data, id_to_cancel_key(0x1234_5678_9abc_def0));
+        assert!(key.ext.is_empty());
+
+        // protocol 3.2 keys are longer.
+        let mut payload = vec![];
+        payload.extend_from_slice(&48_u32.to_be_bytes());
+        payload.extend_from_slice(ProtocolVersion::new(1234, 5678).as_bytes());
+        payload.extend_from_slice(&0x1234_5678_9abc_def0_u64.to_be_bytes());
+        payload.extend_from_slice(&[7; 32]);
+
+        let startup = read_startup(&mut Cursor::new(&payload)).await.unwrap();
+        let FeStartupPacket::CancelRequest(key) = startup else {
+            panic!("unexpected startup message: {startup:?}");
+        };
+        assert_eq!(key.data, id_to_cancel_key(0x1234_5678_9abc_def0));
+        assert_eq!(&*key.ext, &[7; 32]);
+
+        // secrets can be at most 256 bytes.
+        let mut payload = vec![];
+        payload.extend_from_slice(&269_u32.to_be_bytes());
+        payload.extend_from_slice(ProtocolVersion::new(1234, 5678).as_bytes());
+        payload.resize(269, 0);
+
+        let err = read_startup(&mut Cursor::new(&payload)).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "CancelRequest message is malformed, secret key is too long"
+        );
+    }
+
     #[tokio::test]
     async fn read_tls_message() {
         // sample client hello taken from <https://tls13.xargs.org/#client-hello>
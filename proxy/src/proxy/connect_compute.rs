@@ -12,6 +12,7 @@
 use crate::metrics::{
     ConnectOutcome, ConnectionFailureKind, Metrics, RetriesMetricGroup, RetryType,
 };
+use crate::proxy::replicas::Router;
 use crate::proxy::retry::{CouldRetry, ShouldRetryWakeCompute, retry_after, should_retry};
 use crate::proxy::wake_compute::{WakeComputeBackend, wake_compute};
 use crate::types::Host;
@@ -51,6 +52,7 @@ async fn connect_once(
 pub(crate) struct TcpMechanism {
     /// connect_to_compute concurrency lock
     pub(crate) locks: &'static ApiLocks<Host>,
+    pub(crate) router: Router,
 }
 
 #[async_trait]
@@ -69,8 +71,11 @@ async fn connect_once(
         node_info: &control_plane::CachedNodeInfo,
         config: &ComputeConfig,
     ) -> Result<ComputeConnection, Self::Error> {
-        let permit = self.locks.get_permit(&node_info.conn_info.host).await?;
-        permit.release_result(node_info.connect(ctx, config).await)
+        let route = self.router.route(node_info)?;
+        let permit = self.locks.get_permit(&route.conn_info.host).await?;
+        let res = route.connect(ctx, config).await;
+        self.router.report(&route, res.is_ok());
+        permit.release_result(res)
     }
 }
 
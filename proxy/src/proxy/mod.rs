@@ -2,6 +2,7 @@
 mod tests;
 
 pub(crate) mod connect_compute;
+pub(crate) mod replicas;
 pub(crate) mod retry;
 pub(crate) mod wake_compute;
 
@@ -15,7 +16,7 @@
 use serde::{Deserialize, Serialize};
 use smol_str::{SmolStr, format_smolstr};
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch};
 use tracing::Instrument;
 
 use crate::cache::Cache;
@@ -25,11 +26,15 @@
 use crate::context::RequestContext;
 use crate::control_plane::client::ControlPlaneClient;
 pub use crate::pglb::copy_bidirectional::{ErrorSource, copy_bidirectional_client_compute};
+use crate::pglb::sql_firewall::SqlFirewallFilter;
+use crate::pglb::txn_pool::{ComputeConnector, STARTUP_PARAMS_KEY, TxnPool, TxnPooling};
 use crate::pglb::{ClientMode, ClientRequestError};
-use crate::pqproto::{BeMessage, CancelKeyData, StartupMessageParams};
+use crate::pqproto::{BeMessage, CancelKey, ProtocolVersion, StartupMessageParams};
 use crate::proxy::connect_compute::{TcpMechanism, connect_to_compute};
+use crate::proxy::replicas::Router;
 use crate::proxy::retry::ShouldRetryWakeCompute;
 use crate::rate_limiter::EndpointRateLimiter;
+use crate::serverless::conn_pool_lib::ConnInfo;
 use crate::stream::{PqStream, Stream};
 use crate::types::EndpointCacheKey;
 use crate::{auth, compute};
@@ -45,8 +50,19 @@ pub(crate) async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send>(
     endpoint_rate_limiter: Arc<EndpointRateLimiter>,
     common_names: Option<&HashSet<String>>,
     params: &StartupMessageParams,
-) -> Result<(ComputeConnection, oneshot::Sender<Infallible>), ClientRequestError> {
+    version: ProtocolVersion,
+    txn_pool: Option<&Arc<TxnPool>>,
+) -> Result<
+    (
+        ComputeConnection,
+        Option<TxnPooling>,
+        Option<SqlFirewallFilter>,
+        oneshot::Sender<Infallible>,
+    ),
+    ClientRequestError,
+> {
     let hostname = mode.hostname(client.get_ref());
+    let client_cert = mode.client_certificates(client.get_ref());
     // Extract credentials which we're going to use for auth.
     let result = auth_backend
         .as_ref()
@@ -64,6 +80,7 @@ pub(crate) async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send>(
             ctx,
             client,
             mode.allow_cleartext(),
+            client_cert.as_ref(),
             &config.authentication_config,
             endpoint_rate_limiter,
         )
@@ -94,9 +111,23 @@ pub(crate) async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send>(
     let mut attempt = 0;
     let connect = TcpMechanism {
         locks: &config.connect_compute_locks,
+        router: Router::new(config.read_replicas.as_ref(), &creds.info.options),
     };
     let backend = auth::Backend::ControlPlane(cplane, creds.info);
 
+    // access control was cached during authentication.
+    let firewall = match backend.get_endpoint_access_control(ctx).await {
+        Ok(access_control) => access_control.sql_firewall,
+        Err(e) => Err(client.throw_error(e, Some(ctx)).await)?,
+    };
+    if let Some(firewall) = &firewall
+        && let Err(e) = firewall.check_startup_params(params)
+    {
+        ctx.set_sql_firewall_violation(e.violation());
+        return Err(client.throw_error(e, Some(ctx)).await)?;
+    }
+    let firewall = firewall.map(|firewall| SqlFirewallFilter::new(firewall, ctx.clone()));
+
     // NOTE: This is messy, but should hopefully be detangled with PGLB.
     // We wanted to separate the concerns of **connect** to compute (a PGLB operation),
     // from **authenticate** to compute (a NeonKeeper operation).
@@ -143,30 +174,79 @@ pub(crate) async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send>(
         }
     };
 
-    let session = cancellation_handler.get_key();
+    let session = cancellation_handler.get_key(version);
 
-    finish_client_init(&pg_settings, *session.key(), client);
+    finish_client_init(&pg_settings, session.key(), client);
 
     let session_id = ctx.session_id();
     let (cancel_on_shutdown, cancel) = oneshot::channel();
-    tokio::spawn(async move {
-        session
-            .maintain_cancel_key(
-                session_id,
-                cancel,
-                &pg_settings.cancel_closure,
-                &config.connect_to_compute,
-            )
-            .await;
-    });
-
-    Ok((node, cancel_on_shutdown))
+
+    // the session state of a JWT login belongs to this client, so the connection can't be shared.
+    let pooling = if let Some(pool) = txn_pool
+        && !auth_info.has_jwt_session()
+    {
+        let auth::Backend::ControlPlane(_, user_info) = &backend else {
+            unreachable!("ensured above");
+        };
+        let dbname = params
+            .get("database")
+            .unwrap_or(user_info.user.as_str())
+            .into();
+        // pooled connections are opened with this client's startup parameters,
+        // so they can only be shared with clients that sent the same ones.
+        let mut user_info = user_info.clone();
+        user_info
+            .options
+            .insert(STARTUP_PARAMS_KEY, &auth_info.startup_params_hash());
+        let conn_info = ConnInfo { user_info, dbname };
+
+        // With transaction pooling the client's queries run on whichever compute
+        // connection is free, so cancellations go to the one it holds at the time.
+        let (linked, linked_cancel) = watch::channel(None);
+        tokio::spawn(async move {
+            session
+                .maintain_pooled_cancel_key(
+                    session_id,
+                    cancel,
+                    linked_cancel,
+                    &config.connect_to_compute,
+                )
+                .await;
+        });
+
+        Some(TxnPooling {
+            ctx: ctx.clone(),
+            pool: Arc::clone(pool),
+            connector: ComputeConnector {
+                config,
+                auth_info,
+                backend,
+            },
+            conn_info,
+            cancel_closure: pg_settings.cancel_closure,
+            linked,
+        })
+    } else {
+        tokio::spawn(async move {
+            session
+                .maintain_cancel_key(
+                    session_id,
+                    cancel,
+                    &pg_settings.cancel_closure,
+                    &config.connect_to_compute,
+                )
+                .await;
+        });
+        None
+    };
+
+    Ok((node, pooling, firewall, cancel_on_shutdown))
 }
 
 /// Finish client connection initialization: confirm auth success, send params, etc.
 pub(crate) fn finish_client_init(
     settings: &compute::PostgresSettings,
-    cancel_key_data: CancelKeyData,
+    cancel_key: &CancelKey,
     client: &mut PqStream<impl AsyncRead + AsyncWrite + Unpin>,
 ) {
     // Forward all deferred notices to the client.
@@ -184,7 +264,7 @@ pub(crate) fn finish_client_init(
         });
     }
 
-    client.write_message(BeMessage::BackendKeyData(cancel_key_data));
+    client.write_message(BeMessage::BackendKeyData(cancel_key));
     client.write_message(BeMessage::ReadyForQuery);
 }
 
@@ -197,6 +277,10 @@ impl NeonOptions {
     /// `PARAMS_COMPAT` allows opting in to forwarding all startup parameters from client to compute.
     pub const PARAMS_COMPAT: &'static str = "proxy_params_compat";
 
+    /// `TARGET_SESSION_ATTRS` selects between the primary compute and read replicas.
+    /// Also taken from the `target_session_attrs` startup parameter.
+    pub const TARGET_SESSION_ATTRS: &'static str = "target_session_attrs";
+
     // cplane options:
 
     /// `LSN` allows provisioning an ephemeral compute with time-travel to the provided LSN.
@@ -209,10 +293,22 @@ impl NeonOptions {
     const ENDPOINT_TYPE: &'static str = "endpoint_type";
 
     pub(crate) fn parse_params(params: &StartupMessageParams) -> Self {
-        params
+        let mut options: Self = params
             .options_raw()
             .map(Self::parse_from_iter)
-            .unwrap_or_default()
+            .unwrap_or_default();
+        if let Some(attrs) = params.get(Self::TARGET_SESSION_ATTRS)
+            && options.get(Self::TARGET_SESSION_ATTRS).is_none()
+        {
+            options.insert(Self::TARGET_SESSION_ATTRS, attrs);
+        }
+        options
+    }
+
+    pub(crate) fn insert(&mut self, key: &str, value: &str) {
+        self.0.retain(|(k, _)| k != key);
+        self.0.push((key.into(), value.into()));
+        self.0.sort();
     }
 
     pub(crate) fn parse_options_raw(options: &str) -> Self {
@@ -230,6 +326,7 @@ pub(crate) fn is_ephemeral(&self) -> bool {
         self.0.iter().any(|(k, _)| match &**k {
             // This is not a cplane option, we know it does not create ephemeral computes.
             Self::PARAMS_COMPAT => false,
+            Self::TARGET_SESSION_ATTRS => false,
             Self::LSN => true,
             Self::TIMESTAMP => true,
             Self::ENDPOINT_TYPE => true,
@@ -262,6 +359,8 @@ pub(crate) fn get_cache_key(&self, prefix: &str) -> EndpointCacheKey {
     pub(crate) fn to_deep_object(&self) -> Vec<(SmolStr, SmolStr)> {
         self.0
             .iter()
+            // replica routing happens in the proxy, cplane would treat it as an unknown option.
+            .filter(|(k, _)| k != Self::TARGET_SESSION_ATTRS)
             .map(|(k, v)| (format_smolstr!("options[{}]", k), v.clone()))
             .collect()
     }
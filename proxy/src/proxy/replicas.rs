@@ -0,0 +1,313 @@
+//! Routing of read-only connections to the read replicas of an endpoint.
+//!
+//! Clients ask for a replica with `target_session_attrs`, either as a startup
+//! parameter or as the `neon_target_session_attrs` option. `read-only` and
+//! `standby` require a replica, `prefer-standby` falls back to the primary
+//! compute when no replica is available. Everything else goes to the primary.
+//!
+//! Replicas are picked round-robin. A replica we failed to connect to is
+//! skipped until its cooldown expires, unless it is the only option left.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use clashmap::ClashMap;
+use smol_str::SmolStr;
+use thiserror::Error;
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::compute::{self, ComputeConnection};
+use crate::config::ComputeConfig;
+use crate::context::RequestContext;
+use crate::control_plane::messages::MetricsAuxInfo;
+use crate::control_plane::{NodeInfo, ReplicaInfo};
+use crate::error::{ErrorKind, ReportableError, UserFacingError};
+use crate::proxy::NeonOptions;
+use crate::proxy::retry::{CouldRetry, ShouldRetryWakeCompute};
+
+/// Which computes of an endpoint a connection may be routed to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum ComputeTarget {
+    #[default]
+    Primary,
+    Replica,
+    PreferReplica,
+}
+
+impl ComputeTarget {
+    pub(crate) fn from_options(options: &NeonOptions) -> Self {
+        match options.get(NeonOptions::TARGET_SESSION_ATTRS).as_deref() {
+            Some("read-only" | "standby") => Self::Replica,
+            Some("prefer-standby") => Self::PreferReplica,
+            _ => Self::Primary,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("endpoint has no read replicas")]
+pub(crate) struct NoReadReplicas;
+
+impl ReportableError for NoReadReplicas {
+    fn get_error_kind(&self) -> ErrorKind {
+        ErrorKind::User
+    }
+}
+
+impl UserFacingError for NoReadReplicas {
+    fn to_string_client(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl CouldRetry for NoReadReplicas {
+    fn could_retry(&self) -> bool {
+        false
+    }
+}
+
+impl ShouldRetryWakeCompute for NoReadReplicas {
+    fn should_retry_wake_compute(&self) -> bool {
+        // replicas might have been started since the node info was cached.
+        true
+    }
+}
+
+/// Health-aware balancer over the read replicas of all endpoints.
+pub struct ReplicaBalancer {
+    cooldown: Duration,
+    next: AtomicUsize,
+    /// Replicas that recently failed, keyed by compute id, with the end of their cooldown.
+    unhealthy: ClashMap<SmolStr, Instant>,
+}
+
+impl ReplicaBalancer {
+    pub fn new(cooldown: Duration) -> Self {
+        Self {
+            cooldown,
+            next: AtomicUsize::new(0),
+            unhealthy: ClashMap::new(),
+        }
+    }
+
+    fn pick<'a>(&self, replicas: &'a [ReplicaInfo]) -> Option<&'a ReplicaInfo> {
+        if replicas.is_empty() {
+            return None;
+        }
+
+        let now = Instant::now();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let healthy = (0..replicas.len())
+            .map(|i| &replicas[(start + i) % replicas.len()])
+            .find(|replica| {
+                self.unhealthy
+                    .remove_if(&replica.compute_id, |_, until| *until <= now)
+                    .is_some()
+                    || !self.unhealthy.contains_key(&replica.compute_id)
+            });
+
+        healthy.or_else(|| {
+            // every replica is cooling down, try the one that failed the longest time ago.
+            replicas.iter().min_by_key(|replica| {
+                self.unhealthy
+                    .get(&replica.compute_id)
+                    .map(|until| *until)
+                    .unwrap_or(now)
+            })
+        })
+    }
+
+    fn is_healthy(&self, replica: &ReplicaInfo) -> bool {
+        self.unhealthy
+            .get(&replica.compute_id)
+            .is_none_or(|until| *until <= Instant::now())
+    }
+
+    fn report(&self, compute_id: &SmolStr, success: bool) {
+        if success {
+            self.unhealthy.remove(compute_id);
+        } else {
+            self.unhealthy
+                .insert(compute_id.clone(), Instant::now() + self.cooldown);
+        }
+    }
+}
+
+/// Decides which compute of an endpoint a connection goes to.
+/// The default router always picks the primary compute.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Router {
+    balancer: Option<&'static ReplicaBalancer>,
+    target: ComputeTarget,
+}
+
+impl Router {
+    pub(crate) fn new(balancer: Option<&'static ReplicaBalancer>, options: &NeonOptions) -> Self {
+        Self {
+            balancer,
+            target: ComputeTarget::from_options(options),
+        }
+    }
+
+    pub(crate) fn route<'a>(&self, node: &'a NodeInfo) -> Result<Route<'a>, NoReadReplicas> {
+        let Some(balancer) = self.balancer else {
+            return Ok(Route::primary(node));
+        };
+
+        let replica = match self.target {
+            ComputeTarget::Primary => None,
+            ComputeTarget::Replica => {
+                Some(balancer.pick(&node.read_replicas).ok_or(NoReadReplicas)?)
+            }
+            ComputeTarget::PreferReplica => balancer
+                .pick(&node.read_replicas)
+                .filter(|replica| balancer.is_healthy(replica)),
+        };
+
+        let Some(replica) = replica else {
+            return Ok(Route::primary(node));
+        };
+
+        debug!(compute_id = %replica.compute_id, "routing connection to read replica");
+        let mut aux = node.aux.clone();
+        aux.compute_id = replica.compute_id.clone();
+        Ok(Route {
+            conn_info: &replica.conn_info,
+            aux,
+            replica: true,
+        })
+    }
+
+    /// Record the outcome of connecting to the route, so that failing replicas are avoided.
+    pub(crate) fn report(&self, route: &Route<'_>, success: bool) {
+        if route.replica
+            && let Some(balancer) = self.balancer
+        {
+            balancer.report(&route.aux.compute_id, success);
+        }
+    }
+}
+
+/// The compute a connection was routed to.
+pub(crate) struct Route<'a> {
+    pub(crate) conn_info: &'a compute::ConnectInfo,
+    pub(crate) aux: MetricsAuxInfo,
+    replica: bool,
+}
+
+impl<'a> Route<'a> {
+    fn primary(node: &'a NodeInfo) -> Self {
+        Self {
+            conn_info: &node.conn_info,
+            aux: node.aux.clone(),
+            replica: false,
+        }
+    }
+
+    pub(crate) async fn connect(
+        &self,
+        ctx: &RequestContext,
+        config: &ComputeConfig,
+    ) -> Result<ComputeConnection, compute::ConnectionError> {
+        self.conn_info.connect(ctx, &self.aux, config).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use postgres_client::config::SslMode;
+
+    use super::*;
+    use crate::control_plane::messages::ColdStartInfo;
+    use crate::types::{BranchId, EndpointId, ProjectId};
+
+    fn conn_info(host: &str) -> compute::ConnectInfo {
+        compute::ConnectInfo {
+            host_addr: None,
+            host: host.into(),
+            port: 5432,
+            ssl_mode: SslMode::Disable,
+        }
+    }
+
+    fn node_info(replicas: usize) -> NodeInfo {
+        NodeInfo {
+            conn_info: conn_info("primary"),
+            aux: MetricsAuxInfo {
+                endpoint_id: (&EndpointId::from("endpoint")).into(),
+                project_id: (&ProjectId::from("project")).into(),
+                branch_id: (&BranchId::from("branch")).into(),
+                compute_id: "primary".into(),
+                cold_start_info: ColdStartInfo::Warm,
+            },
+            read_replicas: (0..replicas)
+                .map(|i| ReplicaInfo {
+                    conn_info: conn_info(&format!("replica-{i}")),
+                    compute_id: format!("replica-{i}").into(),
+                })
+                .collect(),
+        }
+    }
+
+    fn router(balancer: &'static ReplicaBalancer, attrs: &str) -> Router {
+        let options = NeonOptions::parse_options_raw(&format!("neon_target_session_attrs:{attrs}"));
+        Router::new(Some(balancer), &options)
+    }
+
+    #[test]
+    fn routes_by_target_session_attrs() {
+        let balancer = Box::leak(Box::new(ReplicaBalancer::new(Duration::from_secs(30))));
+        let node = node_info(2);
+
+        let route = router(balancer, "read-write").route(&node).unwrap();
+        assert_eq!(route.aux.compute_id, "primary");
+
+        let first = router(balancer, "read-only").route(&node).unwrap();
+        let second = router(balancer, "read-only").route(&node).unwrap();
+        assert!(first.aux.compute_id.starts_with("replica-"));
+        assert!(second.aux.compute_id.starts_with("replica-"));
+        assert_ne!(first.aux.compute_id, second.aux.compute_id);
+
+        // routing is opt-in.
+        let options = NeonOptions::parse_options_raw("neon_target_session_attrs:read-only");
+        let route = Router::new(None, &options).route(&node).unwrap();
+        assert_eq!(route.aux.compute_id, "primary");
+
+        let no_replicas = node_info(0);
+        assert!(router(balancer, "read-only").route(&no_replicas).is_err());
+        let route = router(balancer, "prefer-standby")
+            .route(&no_replicas)
+            .unwrap();
+        assert_eq!(route.aux.compute_id, "primary");
+    }
+
+    #[test]
+    fn avoids_failed_replicas() {
+        let balancer = Box::leak(Box::new(ReplicaBalancer::new(Duration::from_secs(30))));
+        let node = node_info(2);
+        let read_only = router(balancer, "read-only");
+
+        let failed = read_only.route(&node).unwrap();
+        read_only.report(&failed, false);
+        for _ in 0..4 {
+            let route = read_only.route(&node).unwrap();
+            assert_ne!(route.aux.compute_id, failed.aux.compute_id);
+        }
+
+        // with every replica failing, we still try one instead of erroring.
+        let other = read_only.route(&node).unwrap();
+        read_only.report(&other, false);
+        assert!(read_only.route(&node).is_ok());
+
+        // prefer-standby falls back to the primary instead.
+        let prefer = router(balancer, "prefer-standby");
+        assert_eq!(prefer.route(&node).unwrap().aux.compute_id, "primary");
+
+        read_only.report(&failed, true);
+        assert_eq!(
+            read_only.route(&node).unwrap().aux.compute_id,
+            failed.aux.compute_id
+        );
+    }
+}
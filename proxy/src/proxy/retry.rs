@@ -102,6 +102,7 @@ fn could_retry(&self) -> bool {
             compute::ConnectionError::TlsError(err) => err.could_retry(),
             compute::ConnectionError::WakeComputeError(err) => err.could_retry(),
             compute::ConnectionError::TooManyConnectionAttempts(_) => false,
+            compute::ConnectionError::NoReadReplicas(err) => err.could_retry(),
         }
     }
 }
@@ -47,7 +47,7 @@ async fn proxy_mitm(
         .await
         .unwrap()
         {
-            HandshakeData::Startup(stream, params) => (stream, params),
+            HandshakeData::Startup(stream, params, _) => (stream, params),
             HandshakeData::Cancel(_) => panic!("cancellation not supported"),
         };
 
@@ -9,29 +9,39 @@
 use anyhow::{Context, bail};
 use async_trait::async_trait;
 use http::StatusCode;
+use once_cell::sync::Lazy;
 use postgres_client::config::SslMode;
 use postgres_client::tls::{MakeTlsConnect, NoTls};
 use rstest::rstest;
 use rustls::crypto::ring;
 use rustls::pki_types;
-use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
 use tracing_test::traced_test;
 
 use super::retry::CouldRetry;
+use crate::auth::backend::client_cert::{ClientCa, ClientCertError};
+use crate::auth::backend::jwt::JwkCache;
 use crate::auth::backend::{ComputeUserInfo, MaybeOwned};
-use crate::config::{ComputeConfig, RetryConfig, TlsConfig};
+use crate::config::{AuthenticationConfig, ComputeConfig, RetryConfig, TlsConfig};
 use crate::context::RequestContext;
 use crate::control_plane::client::{ControlPlaneClient, TestControlPlaneClient};
-use crate::control_plane::messages::{ControlPlaneErrorMessage, Details, MetricsAuxInfo, Status};
+use crate::control_plane::messages::{
+    ControlPlaneErrorMessage, Details, EndpointRateLimitConfig, MetricsAuxInfo, Status,
+};
 use crate::control_plane::{self, CachedNodeInfo, NodeInfo, NodeInfoCache};
 use crate::error::{ErrorKind, ReportableError};
 use crate::pglb::ERR_INSECURE_CONNECTION;
 use crate::pglb::handshake::{HandshakeData, handshake};
-use crate::pqproto::BeMessage;
+use crate::pqproto::{
+    BeMessage, FeStartupPacket, StartupMessageParams, read_message, read_startup,
+};
 use crate::proxy::NeonOptions;
 use crate::proxy::connect_compute::{ConnectMechanism, connect_to_compute};
 use crate::proxy::retry::{ShouldRetryWakeCompute, retry_after};
+use crate::rate_limiter::EndpointRateLimiter;
+use crate::scram::threadpool::ThreadPool;
 use crate::stream::{PqStream, Stream};
+use crate::tls::ClientCertChain;
 use crate::tls::client_config::compute_client_config_with_certs;
 use crate::tls::server_config::CertResolver;
 use crate::types::{BranchId, EndpointId, ProjectId};
@@ -178,7 +188,7 @@ async fn dummy_proxy(
     auth: impl TestAuth + Send,
 ) -> anyhow::Result<()> {
     let mut stream = match handshake(&RequestContext::test(), client, tls.as_ref(), false).await? {
-        HandshakeData::Startup(stream, _) => stream,
+        HandshakeData::Startup(stream, _, _) => stream,
         HandshakeData::Cancel(_) => bail!("cancellation not supported"),
     };
 
@@ -576,6 +586,7 @@ fn helper_create_uncached_node_info() -> NodeInfo {
             compute_id: "compute".into(),
             cold_start_info: crate::control_plane::messages::ColdStartInfo::Warm,
         },
+        read_replicas: Vec::new(),
     }
 }
 
@@ -857,3 +868,239 @@ async fn retry_cold_wake_skips_invalidation() {
         .unwrap();
     mechanism.verify();
 }
+
+/// A compute that lets the proxy in without credentials, like one with `trust` auth.
+/// Returns the startup parameters and the queries it received before the proxy hung up.
+async fn trusting_compute(
+    listener: tokio::net::TcpListener,
+) -> anyhow::Result<(StartupMessageParams, Vec<String>)> {
+    let (mut stream, _) = listener.accept().await?;
+    let params = match read_startup(&mut stream).await? {
+        FeStartupPacket::StartupMessage { params, .. } => params,
+        packet => bail!("unexpected startup packet {packet:?}"),
+    };
+
+    // AuthenticationOk, BackendKeyData, ReadyForQuery
+    stream.write_all(b"R\0\0\0\x08\0\0\0\0").await?;
+    stream.write_all(b"K\0\0\0\x0c\0\0\0\x01\0\0\0\x02").await?;
+    stream.write_all(b"Z\0\0\0\x05I").await?;
+
+    let mut queries = vec![];
+    let mut buf = vec![];
+    loop {
+        match read_message(&mut stream, &mut buf, 1024 * 1024).await {
+            Ok((b'Q', query)) => {
+                let query = query.strip_suffix(b"\0").context("unterminated query")?;
+                queries.push(String::from_utf8(query.to_vec())?);
+                // CommandComplete, ReadyForQuery
+                stream.write_all(b"C\0\0\0\x0dSELECT 1\0").await?;
+                stream.write_all(b"Z\0\0\0\x05I").await?;
+            }
+            Ok((tag, _)) => bail!("unexpected message {}", tag as char),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok((params, queries))
+}
+
+/// Authenticate to a [`trusting_compute`] the way the proxy would after authenticating the client.
+async fn login_to_compute(
+    ctx: &RequestContext,
+    creds: auth::backend::ComputeCredentials,
+) -> anyhow::Result<(StartupMessageParams, Vec<String>)> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    let compute = tokio::spawn(trusting_compute(listener));
+
+    let conn_info = compute::ConnectInfo {
+        host_addr: Some([127, 0, 0, 1].into()),
+        host: "localhost".into(),
+        port,
+        ssl_mode: SslMode::Disable,
+    };
+    let aux = helper_create_uncached_node_info().aux;
+    let mut conn = conn_info.connect(ctx, &aux, &config()).await?;
+
+    let mut auth_info = compute::AuthInfo::with_auth_keys(creds.keys);
+    let params = StartupMessageParams::new([("user", &*creds.info.user), ("database", "db")]);
+    auth_info.set_startup_params(&params, false);
+    auth_info.authenticate(ctx, &mut conn, &creds.info).await?;
+    drop(conn);
+
+    compute.await?
+}
+
+/// A control plane for an endpoint that accepts client certificates.
+#[derive(Clone)]
+struct ClientCaControlPlane(control_plane::EndpointAccessControl);
+
+impl TestControlPlaneClient for ClientCaControlPlane {
+    fn wake_compute(&self) -> Result<CachedNodeInfo, control_plane::errors::WakeComputeError> {
+        unimplemented!("not used in tests")
+    }
+
+    fn get_access_control(
+        &self,
+    ) -> Result<control_plane::EndpointAccessControl, control_plane::errors::GetAuthInfoError> {
+        Ok(self.0.clone())
+    }
+
+    fn dyn_clone(&self) -> Box<dyn TestControlPlaneClient> {
+        Box::new(self.clone())
+    }
+}
+
+static AUTH_CONFIG: Lazy<AuthenticationConfig> = Lazy::new(|| AuthenticationConfig {
+    jwks_cache: JwkCache::default(),
+    thread_pool: ThreadPool::new(1),
+    scram_protocol_timeout: Duration::from_secs(5),
+    ip_allowlist_check_enabled: true,
+    is_vpc_acccess_proxy: false,
+    is_auth_broker: false,
+    accept_jwts: false,
+    accept_oauthbearer: false,
+    console_redirect_confirmation_timeout: Duration::from_secs(5),
+});
+
+/// Generate a CA and a client certificate for `common_name` signed by it.
+fn generate_client_cert(common_name: &str) -> anyhow::Result<(String, ClientCertChain)> {
+    let ca_key = rcgen::KeyPair::generate()?;
+    let mut params = rcgen::CertificateParams::default();
+    params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    let ca = params.self_signed(&ca_key)?;
+
+    let key = rcgen::KeyPair::generate()?;
+    let mut params = rcgen::CertificateParams::default();
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, common_name);
+    params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+    let cert = params.signed_by(&key, &ca, &ca_key)?;
+
+    Ok((ca.pem(), ClientCertChain(Arc::new([cert.der().clone()]))))
+}
+
+#[tokio::test]
+async fn client_cert_auth_reaches_compute() -> anyhow::Result<()> {
+    let (ca_pem, chain) = generate_client_cert("alice")?;
+    let client_ca = Arc::new(ClientCa::parse(&ca_pem, true)?);
+
+    for compute_trusts_proxy in [true, false] {
+        let ctx = RequestContext::test();
+        let (mut client, server) = tokio::io::duplex(1024);
+        let mut stream = PqStream::new_skip_handshake(Stream::from_raw(server));
+
+        let access_control = control_plane::EndpointAccessControl {
+            allowed_ips: Arc::new(vec![]),
+            allowed_vpce: Arc::new(vec![]),
+            flags: control_plane::AccessBlockerFlags::default(),
+            rate_limits: EndpointRateLimitConfig::default(),
+            client_ca: Some(client_ca.clone()),
+            compute_trusts_proxy,
+            sql_firewall: None,
+        };
+        let user_info = auth::ComputeUserInfoMaybeEndpoint {
+            user: "alice".into(),
+            endpoint_id: Some("endpoint".into()),
+            options: NeonOptions::parse_options_raw(""),
+        };
+        let backend = auth::Backend::ControlPlane(
+            MaybeOwned::Owned(ControlPlaneClient::Test(Box::new(ClientCaControlPlane(
+                access_control,
+            )))),
+            user_info,
+        );
+        let endpoint_rate_limiter = Arc::new(EndpointRateLimiter::new_with_shards(
+            EndpointRateLimiter::DEFAULT,
+            64,
+        ));
+
+        let res = backend
+            .authenticate(
+                &ctx,
+                &mut stream,
+                false,
+                Some(&chain),
+                &AUTH_CONFIG,
+                endpoint_rate_limiter,
+            )
+            .await;
+        stream.flush().await?;
+        drop(stream);
+
+        let mut sent = vec![];
+        client.read_to_end(&mut sent).await?;
+
+        if !compute_trusts_proxy {
+            // the compute would not let the proxy in, so the client must not be told it's in.
+            let err = res.err().context("client should be rejected")?;
+            assert!(matches!(
+                err,
+                auth::AuthError::ClientCert(ClientCertError::ComputeUntrusted)
+            ));
+            assert!(sent.is_empty());
+            continue;
+        }
+
+        // AuthenticationOk
+        assert_eq!(sent, b"R\0\0\0\x08\0\0\0\0");
+        let auth::Backend::ControlPlane(_, creds) = res? else {
+            unreachable!("control plane backend");
+        };
+        let (params, queries) = login_to_compute(&ctx, creds).await?;
+        assert_eq!(params.get("user"), Some("alice"));
+        assert!(queries.is_empty());
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn jwt_session_reaches_compute() -> anyhow::Result<()> {
+    use base64::Engine as _;
+    use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    let ctx = RequestContext::test();
+    let creds = auth::backend::ComputeCredentials {
+        info: ComputeUserInfo {
+            endpoint: "endpoint".into(),
+            user: "alice".into(),
+            options: NeonOptions::parse_options_raw(""),
+        },
+        keys: auth::backend::ComputeCredentialKeys::JwtPayload(br#"{"sub":"alice"}"#.to_vec()),
+    };
+    let (params, queries) = login_to_compute(&ctx, creds).await?;
+    assert_eq!(params.get("user"), Some("alice"));
+
+    // the session key is passed on with the startup options
+    let options = params.get("options").context("missing options")?;
+    let jwk = options
+        .strip_prefix("-c pg_session_jwt.jwk=")
+        .context("missing session jwk")?;
+    let jose_jwk::Key::Okp(jwk) = serde_json::from_str(jwk)? else {
+        bail!("session jwk should be ed25519");
+    };
+    let key = VerifyingKey::try_from(&jwk.x[..])?;
+
+    // and the claims, signed with it, once connected.
+    let [query] = &queries[..] else {
+        bail!("expected a single init query, got {queries:?}");
+    };
+    let token = query
+        .strip_prefix("select auth.init(); select auth.jwt_session_init('")
+        .and_then(|q| q.strip_suffix("')"))
+        .context("unexpected init query")?;
+    let (message, signature) = token.rsplit_once('.').context("malformed token")?;
+    let signature = Signature::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(signature)?)?;
+    key.verify(message.as_bytes(), &signature)?;
+
+    let (_, payload) = message.split_once('.').context("malformed token")?;
+    let payload: serde_json::Value =
+        serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(payload)?)?;
+    assert_eq!(payload, serde_json::json!({"sub": "alice", "jti": 1}));
+
+    Ok(())
+}
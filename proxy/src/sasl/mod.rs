@@ -8,13 +8,14 @@
 
 mod channel_binding;
 mod messages;
+pub(crate) mod oauthbearer;
 mod stream;
 
 use std::io;
 
 pub(crate) use channel_binding::ChannelBinding;
 pub(crate) use messages::FirstMessage;
-pub(crate) use stream::{Outcome, authenticate};
+pub(crate) use stream::{Outcome, authenticate, exchange, read_first_message};
 use thiserror::Error;
 
 use crate::error::{ReportableError, UserFacingError};
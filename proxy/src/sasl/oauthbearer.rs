@@ -0,0 +1,121 @@
+//! Messages of the OAUTHBEARER mechanism.
+//!
+//! RFC: <https://datatracker.ietf.org/doc/html/rfc7628>.
+//!
+//! Reference implementation:
+//! * <https://github.com/postgres/postgres/blob/REL_18_0/src/backend/libpq/auth-oauth.c>
+
+use super::Error;
+
+/// Name of the mechanism.
+pub(crate) const OAUTHBEARER: &str = "OAUTHBEARER";
+
+/// Separator of the key-value pairs in the client's initial response.
+const KVSEP: char = '\x01';
+
+/// Reply sent when the token is missing or rejected. The client must answer with
+/// a single [`KVSEP`], after which the exchange fails.
+///
+/// Unlike postgres we don't advertise an issuer for discovery:
+/// the trusted issuers depend on the role, which we only know from the JWKS settings.
+pub(crate) const ERROR_RESPONSE: &str = r#"{"status":"invalid_token"}"#;
+
+/// Parse the client's initial response and return the bearer token.
+/// `None` means the client asked for the server's error response to begin discovery.
+pub(crate) fn parse_initial_response(input: &str) -> super::Result<Option<&str>> {
+    // gs2-header: channel binding is not supported by OAUTHBEARER.
+    let (cbind_flag, rest) = input
+        .split_once(',')
+        .ok_or(Error::BadClientMessage("missing gs2 header"))?;
+    match cbind_flag {
+        "n" | "y" => {}
+        flag if flag.starts_with("p=") => {
+            return Err(Error::BadClientMessage("channel binding is not supported"));
+        }
+        _ => return Err(Error::BadClientMessage("bad channel binding flag")),
+    }
+
+    // authzid is not supported, the role comes from the startup message.
+    let (authzid, rest) = rest
+        .split_once(',')
+        .ok_or(Error::BadClientMessage("missing gs2 header"))?;
+    if !authzid.is_empty() {
+        return Err(Error::BadClientMessage("authzid is not supported"));
+    }
+
+    let kvpairs = rest
+        .strip_prefix(KVSEP)
+        .and_then(|rest| rest.strip_suffix("\x01\x01"))
+        .ok_or(Error::BadClientMessage("malformed key-value pairs"))?;
+
+    let mut auth = None;
+    for kv in kvpairs.split(KVSEP) {
+        let (key, value) = kv
+            .split_once('=')
+            .ok_or(Error::BadClientMessage("malformed key-value pair"))?;
+        if key == "auth" {
+            if auth.is_some() {
+                return Err(Error::BadClientMessage("duplicate auth value"));
+            }
+            auth = Some(value);
+        }
+        // other keys (host, port, extensions) are ignored, as in postgres.
+    }
+
+    let auth = auth.ok_or(Error::BadClientMessage("missing auth value"))?;
+    if auth.is_empty() {
+        return Ok(None);
+    }
+
+    // RFC 6750: the scheme is case-insensitive.
+    let token = auth
+        .split_once(' ')
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer"))
+        .map(|(_, token)| token.trim_start_matches(' '))
+        .filter(|token| !token.is_empty())
+        .ok_or(Error::BadClientMessage("malformed bearer token"))?;
+
+    Ok(Some(token))
+}
+
+/// Check the client's acknowledgement of [`ERROR_RESPONSE`].
+pub(crate) fn is_error_ack(input: &[u8]) -> bool {
+    input == [KVSEP as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_bearer_token() {
+        let token = parse_initial_response("n,,\x01auth=Bearer abc.def.ghi\x01\x01").unwrap();
+        assert_eq!(token, Some("abc.def.ghi"));
+
+        let token = parse_initial_response("y,,\x01host=localhost\x01auth=bearer  abc\x01\x01");
+        assert_eq!(token.unwrap(), Some("abc"));
+
+        // discovery request.
+        let token = parse_initial_response("n,,\x01auth=\x01\x01").unwrap();
+        assert_eq!(token, None);
+    }
+
+    #[test]
+    fn reject_malformed_messages() {
+        for input in [
+            "",
+            "p=tls-server-end-point,,\x01auth=Bearer abc\x01\x01",
+            "n,a=alice,\x01auth=Bearer abc\x01\x01",
+            "n,,\x01auth=Bearer abc\x01",
+            "n,,\x01host=localhost\x01\x01",
+            "n,,\x01auth=Basic abc\x01\x01",
+            "n,,\x01auth=Bearer \x01\x01",
+            "n,,\x01auth=Bearer abc\x01auth=Bearer def\x01\x01",
+        ] {
+            assert!(parse_initial_response(input).is_err(), "{input:?}");
+        }
+
+        assert!(is_error_ack(b"\x01"));
+        assert!(!is_error_ack(b""));
+    }
+}
@@ -30,19 +30,43 @@ pub async fn authenticate<S, F, M>(
     F: FnOnce(&str) -> super::Result<M>,
     M: Mechanism,
 {
-    let (mut mechanism, mut input) = {
-        // pause the timer while we communicate with the client
-        let _paused = ctx.latency_timer_pause(crate::metrics::Waiting::Client);
+    let (method, input) = read_first_message(ctx, stream).await?;
+    exchange(ctx, stream, mechanism(&method)?, &input).await
+}
 
-        // Initial client message contains the chosen auth method's name.
-        let msg = stream.read_password_message().await?;
+/// Read the mechanism chosen by the client and its initial response.
+pub(crate) async fn read_first_message<S>(
+    ctx: &RequestContext,
+    stream: &mut PqStream<S>,
+) -> super::Result<(String, String)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // pause the timer while we communicate with the client
+    let _paused = ctx.latency_timer_pause(crate::metrics::Waiting::Client);
 
-        let sasl = super::FirstMessage::parse(msg)
-            .ok_or(super::Error::BadClientMessage("bad sasl message"))?;
+    // Initial client message contains the chosen auth method's name.
+    let msg = stream.read_password_message().await?;
 
-        (mechanism(sasl.method)?, sasl.message)
-    };
+    let sasl = super::FirstMessage::parse(msg)
+        .ok_or(super::Error::BadClientMessage("bad sasl message"))?;
 
+    Ok((sasl.method.to_owned(), sasl.message.to_owned()))
+}
+
+/// Continue the exchange with the client's initial response.
+pub(crate) async fn exchange<S, M>(
+    ctx: &RequestContext,
+    stream: &mut PqStream<S>,
+    mut mechanism: M,
+    input: &str,
+) -> super::Result<Outcome<M::Output>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    M: Mechanism,
+{
+    // later inputs borrow from the stream.
+    let mut input = input;
     loop {
         match mechanism.exchange(input) {
             Ok(Step::Continue(moved_mechanism, reply)) => {
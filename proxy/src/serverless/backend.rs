@@ -20,6 +20,7 @@
 use super::conn_pool_lib::{Client, ConnInfo, EndpointConnPool, GlobalConnPool};
 use super::http_conn_pool::{self, HttpConnPool, Send, poll_http2_client};
 use super::local_conn_pool::{self, EXT_NAME, EXT_SCHEMA, EXT_VERSION, LocalConnPool};
+use crate::auth::backend::client_cert::ClientCertError;
 use crate::auth::backend::local::StaticAuthRules;
 use crate::auth::backend::{ComputeCredentialKeys, ComputeCredentials, ComputeUserInfo};
 use crate::auth::{self, AuthError};
@@ -35,8 +36,11 @@
 use crate::error::{ErrorKind, ReportableError, UserFacingError};
 use crate::intern::EndpointIdInt;
 use crate::proxy::connect_compute::ConnectMechanism;
+use crate::proxy::replicas::{NoReadReplicas, Router};
 use crate::proxy::retry::{CouldRetry, ShouldRetryWakeCompute};
 use crate::rate_limiter::EndpointRateLimiter;
+use crate::sql_firewall::SqlFirewall;
+use crate::tls::ClientCertChain;
 use crate::types::{EndpointId, Host, LOCAL_PROXY_SUFFIX};
 
 pub(crate) struct PoolingBackend {
@@ -155,6 +159,54 @@ pub(crate) async fn authenticate_with_jwt(
         }
     }
 
+    pub(crate) async fn authenticate_with_client_cert(
+        &self,
+        ctx: &RequestContext,
+        user_info: &ComputeUserInfo,
+        client_cert: &ClientCertChain,
+    ) -> Result<ComputeCredentials, AuthError> {
+        ctx.set_auth_method(crate::context::AuthMethod::ClientCertificate);
+
+        let backend = self.auth_backend.as_ref().map(|()| user_info.clone());
+        let access_control = backend.get_endpoint_access_control(ctx).await?;
+        access_control.check(
+            ctx,
+            self.config.authentication_config.ip_allowlist_check_enabled,
+            self.config.authentication_config.is_vpc_acccess_proxy,
+        )?;
+
+        access_control.connection_attempt_rate_limit(
+            ctx,
+            &user_info.endpoint,
+            &self.endpoint_rate_limiter,
+        )?;
+
+        let Some(ca) = &access_control.client_ca else {
+            return Err(AuthError::bad_auth_method("client certificate"));
+        };
+        if !access_control.compute_trusts_proxy {
+            return Err(ClientCertError::ComputeUntrusted.into());
+        }
+        ca.authenticate(Some(client_cert), &user_info.user)?;
+        info!("user successfully authenticated");
+
+        Ok(ComputeCredentials {
+            info: user_info.clone(),
+            keys: ComputeCredentialKeys::ClientCertificate,
+        })
+    }
+
+    /// The SQL firewall of the endpoint, if it has one.
+    pub(crate) async fn sql_firewall(
+        &self,
+        ctx: &RequestContext,
+        user_info: &ComputeUserInfo,
+    ) -> Result<Option<Arc<SqlFirewall>>, GetAuthInfoError> {
+        let backend = self.auth_backend.as_ref().map(|()| user_info.clone());
+        let access_control = backend.get_endpoint_access_control(ctx).await?;
+        Ok(access_control.sql_firewall)
+    }
+
     // Wake up the destination if needed. Code here is a bit involved because
     // we reuse the code from the usual proxy and we need to prepare few structures
     // that this code expects.
@@ -184,6 +236,7 @@ pub(crate) async fn connect_to_compute(
         let conn_id = uuid::Uuid::new_v4();
         tracing::Span::current().record("conn_id", display(conn_id));
         info!(%conn_id, "pool: opening a new connection '{conn_info}'");
+        let router = Router::new(self.config.read_replicas.as_ref(), &keys.info.options);
         let backend = self.auth_backend.as_ref().map(|()| keys.info);
         crate::proxy::connect_compute::connect_to_compute(
             ctx,
@@ -192,6 +245,7 @@ pub(crate) async fn connect_to_compute(
                 conn_info,
                 pool: self.pool.clone(),
                 locks: &self.config.connect_compute_locks,
+                router,
                 keys: keys.keys,
             },
             &backend,
@@ -363,7 +417,7 @@ pub(crate) async fn connect_to_local_postgres(
     }
 }
 
-fn create_random_jwk() -> (SigningKey, jose_jwk::Key) {
+pub(crate) fn create_random_jwk() -> (SigningKey, jose_jwk::Key) {
     let key = SigningKey::generate(&mut OsRng);
 
     let jwk = jose_jwk::Key::Okp(jose_jwk::Okp {
@@ -396,6 +450,8 @@ pub(crate) enum HttpConnError {
     WakeCompute(#[from] WakeComputeError),
     #[error("error acquiring resource permit: {0}")]
     TooManyConnectionAttempts(#[from] ApiLockError),
+    #[error("{0}")]
+    NoReadReplicas(#[from] NoReadReplicas),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -426,6 +482,7 @@ fn get_error_kind(&self) -> ErrorKind {
             HttpConnError::AuthError(a) => a.get_error_kind(),
             HttpConnError::WakeCompute(w) => w.get_error_kind(),
             HttpConnError::TooManyConnectionAttempts(w) => w.get_error_kind(),
+            HttpConnError::NoReadReplicas(e) => e.get_error_kind(),
         }
     }
 }
@@ -444,6 +501,7 @@ fn to_string_client(&self) -> String {
             HttpConnError::TooManyConnectionAttempts(_) => {
                 "Failed to acquire permit to connect to the database. Too many database connection attempts are currently ongoing.".to_owned()
             }
+            HttpConnError::NoReadReplicas(e) => e.to_string_client(),
         }
     }
 }
@@ -460,6 +518,7 @@ fn could_retry(&self) -> bool {
             HttpConnError::AuthError(_) => false,
             HttpConnError::WakeCompute(_) => false,
             HttpConnError::TooManyConnectionAttempts(_) => false,
+            HttpConnError::NoReadReplicas(e) => e.could_retry(),
         }
     }
 }
@@ -514,6 +573,7 @@ struct TokioMechanism {
 
     /// connect_to_compute concurrency lock
     locks: &'static ApiLocks<Host>,
+    router: Router,
 }
 
 #[async_trait]
@@ -528,9 +588,10 @@ async fn connect_once(
         node_info: &CachedNodeInfo,
         compute_config: &ComputeConfig,
     ) -> Result<Self::Connection, Self::ConnectError> {
-        let permit = self.locks.get_permit(&node_info.conn_info.host).await?;
+        let route = self.router.route(node_info)?;
+        let permit = self.locks.get_permit(&route.conn_info.host).await?;
 
-        let mut config = node_info.conn_info.to_postgres_client_config();
+        let mut config = route.conn_info.to_postgres_client_config();
         let config = config
             .user(&self.conn_info.user_info.user)
             .dbname(&self.conn_info.dbname)
@@ -543,13 +604,15 @@ async fn connect_once(
         let pause = ctx.latency_timer_pause(crate::metrics::Waiting::Compute);
         let res = config.connect(compute_config).await;
         drop(pause);
-        let (client, connection) = permit.release_result(res)?;
+        // postgres rejecting the connection still means the compute is reachable.
+        let reachable = !matches!(&res, Err(e) if e.as_db_error().is_none());
+        self.router.report(&route, reachable);
+        let (mut client, connection) = permit.release_result(res)?;
+        client.set_statement_cache_size(self.pool.config.pool_options.statement_cache_size);
 
         tracing::Span::current().record("pid", tracing::field::display(client.get_process_id()));
-        tracing::Span::current().record(
-            "compute_id",
-            tracing::field::display(&node_info.aux.compute_id),
-        );
+        tracing::Span::current()
+            .record("compute_id", tracing::field::display(&route.aux.compute_id));
 
         if let Some(query_id) = ctx.get_testodrome_id() {
             info!("latency={}, query_id={}", ctx.get_proxy_latency(), query_id);
@@ -562,7 +625,7 @@ async fn connect_once(
             client,
             connection,
             self.conn_id,
-            node_info.aux.clone(),
+            route.aux,
         ))
     }
 }
@@ -27,6 +27,7 @@
 use crate::context::RequestContext;
 use crate::control_plane::messages::MetricsAuxInfo;
 use crate::metrics::Metrics;
+use crate::tls::ClientCertChain;
 
 type TlsStream = <ComputeConfig as MakeTlsConnect<TcpStream>>::Stream;
 
@@ -40,6 +41,7 @@ pub(crate) struct ConnInfoWithAuth {
 pub(crate) enum AuthData {
     Password(SmallVec<[u8; 16]>),
     Jwt(String),
+    ClientCertificate(ClientCertChain),
 }
 
 impl fmt::Display for ConnInfo {
@@ -245,11 +247,12 @@ async fn test_pool() {
                 idle_timeout: Duration::from_secs(1),
                 opt_in: false,
                 max_total_conns: 3,
+                statement_cache_size: 0,
             },
             cancel_set: CancelSet::new(0),
             client_conn_threshold: u64::MAX,
             max_request_size_bytes: usize::MAX,
-            max_response_size_bytes: usize::MAX,
+            response_limits: crate::config::ResponseLimits::unlimited(),
         }));
         let pool = GlobalConnPool::new(config);
         let conn_info = ConnInfo {
@@ -20,6 +20,7 @@
 use crate::context::RequestContext;
 use crate::control_plane::messages::{ColdStartInfo, MetricsAuxInfo};
 use crate::metrics::{HttpEndpointPoolsGuard, Metrics};
+use crate::pglb::txn_pool::ClientDataTcp;
 use crate::protocol2::ConnectionInfoExtra;
 use crate::types::{DbName, EndpointCacheKey, RoleName};
 use crate::usage_metrics::{Ids, MetricCounter, USAGE_METRICS};
@@ -52,6 +53,7 @@ pub(crate) enum ClientDataEnum {
     Remote(ClientDataRemote),
     Local(ClientDataLocal),
     Http(ClientDataHttp),
+    Tcp(ClientDataTcp),
 }
 
 #[derive(Clone)]
@@ -72,6 +74,7 @@ fn drop(&mut self) {
                 local_data.cancel();
             }
             ClientDataEnum::Http(_http_data) => (),
+            ClientDataEnum::Tcp(_tcp_data) => (),
         }
     }
 }
@@ -389,6 +392,9 @@ pub struct GlobalConnPoolOptions {
 
     // Total number of connections in the pool.
     pub max_total_conns: usize,
+
+    // Number of prepared statements cached on each connection.
+    pub statement_cache_size: usize,
 }
 
 impl<C, P> GlobalConnPool<C, P>
@@ -538,6 +544,7 @@ pub(crate) fn get(
                     data.session().send(ctx.session_id())?;
                 }
                 ClientDataEnum::Http(_) => (),
+                ClientDataEnum::Tcp(_) => (),
             }
 
             ctx.set_cold_start_info(ColdStartInfo::HttpPoolHit);
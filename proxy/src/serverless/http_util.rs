@@ -21,6 +21,7 @@
 use crate::metrics::{Metrics, SniGroup, SniKind};
 use crate::pqproto::StartupMessageParams;
 use crate::proxy::NeonOptions;
+use crate::tls::ClientCertChain;
 use crate::types::{DbName, EndpointId, RoleName};
 
 // Common header names used across serverless modules
@@ -33,6 +34,15 @@
     HeaderName::from_static("neon-batch-isolation-level");
 pub(super) static TXN_READ_ONLY: HeaderName = HeaderName::from_static("neon-batch-read-only");
 pub(super) static TXN_DEFERRABLE: HeaderName = HeaderName::from_static("neon-batch-deferrable");
+pub(super) static READ_ONLY: HeaderName = HeaderName::from_static("neon-read-only");
+
+// Trailers sent at the end of streaming SQL-over-HTTP responses
+pub(super) static QUERY_STATUS: HeaderName = HeaderName::from_static("neon-query-status");
+pub(super) static QUERY_COMMAND: HeaderName = HeaderName::from_static("neon-query-command");
+pub(super) static QUERY_ROW_COUNT: HeaderName = HeaderName::from_static("neon-query-row-count");
+pub(super) static QUERY_ERROR_CODE: HeaderName = HeaderName::from_static("neon-query-error-code");
+pub(super) static QUERY_ERROR_MESSAGE: HeaderName =
+    HeaderName::from_static("neon-query-error-message");
 
 pub(crate) fn uuid_to_header_value(id: Uuid) -> HeaderValue {
     let mut uuid = [0; uuid::fmt::Hyphenated::LENGTH];
@@ -144,6 +154,7 @@ pub(crate) fn get_conn_info(
     ctx: &RequestContext,
     connection_string: Option<&str>,
     headers: &HeaderMap,
+    client_cert: Option<&ClientCertChain>,
 ) -> Result<ConnInfoWithAuth, ConnInfoError> {
     let connection_url = match connection_string {
         Some(connection_string) => Url::parse(connection_string)?,
@@ -199,6 +210,8 @@ pub(crate) fn get_conn_info(
             std::borrow::Cow::Borrowed(b) => b.into(),
             std::borrow::Cow::Owned(b) => b.into(),
         })
+    } else if let Some(client_cert) = client_cert {
+        AuthData::ClientCertificate(client_cert.clone())
     } else if config.accept_jwts {
         return Err(ConnInfoError::MissingCredentials(Credentials::BearerJwt));
     } else {
@@ -217,16 +230,16 @@ pub(crate) fn get_conn_info(
 
     let pairs = connection_url.query_pairs();
 
-    let mut options = Option::None;
-
     let mut params = StartupMessageParams::default();
     params.insert("user", &username);
     params.insert("database", &dbname);
     for (key, value) in pairs {
         params.insert(&key, &value);
-        if key == "options" {
-            options = Some(NeonOptions::parse_options_raw(&value));
-        }
+    }
+
+    let mut options = NeonOptions::parse_params(&params);
+    if headers.get(&READ_ONLY).is_some_and(|h| h == "true") {
+        options.insert(NeonOptions::TARGET_SESSION_ATTRS, "read-only");
     }
 
     // check the URL that was used, for metrics
@@ -265,7 +278,7 @@ pub(crate) fn get_conn_info(
     let user_info = ComputeUserInfo {
         endpoint,
         user: username,
-        options: options.unwrap_or_default(),
+        options,
     };
 
     let conn_info = ConnInfo { user_info, dbname };
@@ -1,6 +1,6 @@
 use json::{ListSer, ObjectSer, ValueSer};
-use postgres_client::Row;
 use postgres_client::types::{Kind, Type};
+use postgres_client::{Column, Row};
 use serde_json::Value;
 
 //
@@ -127,6 +127,26 @@ pub(crate) fn pg_text_row_to_json(
     Ok(())
 }
 
+//
+// Convert postgres column descriptions to a JSON list of fields
+//
+pub(crate) fn pg_columns_to_json(output: ValueSer, columns: &[Column]) {
+    json::value_as_list!(|output| {
+        for c in columns {
+            let json_field = output.entry();
+            json::value_as_object!(|json_field| {
+                json_field.entry("name", c.name());
+                json_field.entry("dataTypeID", c.type_().oid());
+                json_field.entry("tableID", c.table_oid());
+                json_field.entry("columnID", c.column_id());
+                json_field.entry("dataTypeSize", c.type_size());
+                json_field.entry("dataTypeModifier", c.type_modifier());
+                json_field.entry("format", "text");
+            });
+        }
+    });
+}
+
 //
 // Convert postgres text-encoded value to JSON value
 //
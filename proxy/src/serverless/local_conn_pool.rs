@@ -127,6 +127,7 @@ pub(crate) fn get(
                     data.session().send(ctx.session_id())?;
                 }
                 ClientDataEnum::Http(_) => (),
+                ClientDataEnum::Tcp(_) => (),
             }
 
             ctx.set_cold_start_info(ColdStartInfo::HttpPoolHit);
@@ -316,7 +317,11 @@ fn upsert_json_object(
     serde_json::to_string(&payload)
 }
 
-fn resign_jwt(sk: &SigningKey, payload: &[u8], jti: u64) -> Result<String, HttpConnError> {
+pub(crate) fn resign_jwt(
+    sk: &SigningKey,
+    payload: &[u8],
+    jti: u64,
+) -> Result<String, HttpConnError> {
     let mut buffer = itoa::Buffer::new();
 
     // encode the jti integer to a json rawvalue
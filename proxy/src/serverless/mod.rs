@@ -5,12 +5,13 @@
 mod backend;
 pub mod cancel_set;
 mod conn_pool;
-mod conn_pool_lib;
+pub(crate) mod conn_pool_lib;
 mod error;
 mod http_conn_pool;
 mod http_util;
 mod json;
 mod local_conn_pool;
+mod response_format;
 mod sql_over_http;
 mod websocket;
 
@@ -22,6 +23,7 @@
 use arc_swap::ArcSwapOption;
 use async_trait::async_trait;
 use atomic_take::AtomicTake;
+pub(crate) use backend::create_random_jwk;
 use bytes::Bytes;
 pub use conn_pool_lib::GlobalConnPoolOptions;
 use futures::TryFutureExt;
@@ -34,6 +36,7 @@
 use hyper::body::Incoming;
 use hyper_util::rt::TokioExecutor;
 use hyper_util::server::conn::auto::Builder;
+pub(crate) use local_conn_pool::resign_jwt;
 use rand::SeedableRng;
 use rand::rngs::StdRng;
 use tokio::io::{AsyncRead, AsyncWrite};
@@ -53,6 +56,7 @@
 use crate::rate_limiter::EndpointRateLimiter;
 use crate::serverless::backend::PoolingBackend;
 use crate::serverless::http_util::{api_error_into_response, json_response};
+use crate::tls::ClientCertChain;
 use crate::util::run_until_cancelled;
 
 pub(crate) const SERVERLESS_DRIVER_SNI: &str = "api";
@@ -174,7 +178,7 @@ pub async fn task_main(
                     peer_addr,
                 ))
                 .await;
-                let Some((conn, conn_info)) = startup_result else {
+                let Some((conn, conn_info, client_cert)) = startup_result else {
                     return;
                 };
 
@@ -188,6 +192,7 @@ pub async fn task_main(
                     conn_token,
                     conn,
                     conn_info,
+                    client_cert,
                     session_id,
                 ))
                 .await;
@@ -207,19 +212,23 @@ impl<T: AsyncRead + AsyncWrite + Send + 'static> AsyncReadWrite for T {}
 
 #[async_trait]
 trait MaybeTlsAcceptor: Send + Sync + 'static {
-    async fn accept(&self, conn: TcpStream) -> std::io::Result<AsyncRW>;
+    /// Returns the stream and the certificate chain the client presented, if any.
+    async fn accept(&self, conn: TcpStream) -> std::io::Result<(AsyncRW, Option<ClientCertChain>)>;
 }
 
 #[async_trait]
 impl MaybeTlsAcceptor for &'static ArcSwapOption<crate::config::TlsConfig> {
-    async fn accept(&self, conn: TcpStream) -> std::io::Result<AsyncRW> {
+    async fn accept(&self, conn: TcpStream) -> std::io::Result<(AsyncRW, Option<ClientCertChain>)> {
         match &*self.load() {
-            Some(config) => Ok(Box::pin(
-                TlsAcceptor::from(config.http_config.clone())
+            Some(config) => {
+                let tls = TlsAcceptor::from(config.http_config.clone())
                     .accept(conn)
-                    .await?,
-            )),
-            None => Ok(Box::pin(conn)),
+                    .await?;
+                let client_cert = ClientCertChain::from_connection(tls.get_ref().1);
+                let conn: AsyncRW = Box::pin(tls);
+                Ok((conn, client_cert))
+            }
+            None => Ok((Box::pin(conn), None)),
         }
     }
 }
@@ -233,7 +242,7 @@ async fn connection_startup(
     session_id: uuid::Uuid,
     conn: TcpStream,
     peer_addr: SocketAddr,
-) -> Option<(AsyncRW, ConnectionInfo)> {
+) -> Option<(AsyncRW, ConnectionInfo, Option<ClientCertChain>)> {
     // handle PROXY protocol
     let (conn, conn_info) = match config.proxy_protocol_v2 {
         ProxyProtocolV2::Required => {
@@ -268,10 +277,10 @@ async fn connection_startup(
     info!(?session_id, %conn_info, "accepted new TCP connection");
 
     // try upgrade to TLS, but with a timeout.
-    let conn = match timeout(config.handshake_timeout, tls_acceptor.accept(conn)).await {
-        Ok(Ok(conn)) => {
+    let accepted = match timeout(config.handshake_timeout, tls_acceptor.accept(conn)).await {
+        Ok(Ok(accepted)) => {
             info!(?session_id, %conn_info, "accepted new TLS connection");
-            conn
+            accepted
         }
         // The handshake failed
         Ok(Err(e)) => {
@@ -290,8 +299,9 @@ async fn connection_startup(
             return None;
         }
     };
+    let (conn, client_cert) = accepted;
 
-    Some((conn, conn_info))
+    Some((conn, conn_info, client_cert))
 }
 
 /// Handles HTTP connection
@@ -309,6 +319,7 @@ async fn connection_handler(
     cancellation_token: CancellationToken,
     conn: AsyncRW,
     conn_info: ConnectionInfo,
+    client_cert: Option<ClientCertChain>,
     session_id: uuid::Uuid,
 ) {
     let session_id = AtomicTake::new(session_id);
@@ -321,7 +332,12 @@ async fn connection_handler(
     let server = Builder::new(TokioExecutor::new());
     let conn = server.serve_connection_with_upgrades(
         hyper_util::rt::TokioIo::new(conn),
-        hyper::service::service_fn(move |req: hyper::Request<Incoming>| {
+        hyper::service::service_fn(move |mut req: hyper::Request<Incoming>| {
+            // The client certificate authenticates every request on the connection.
+            if let Some(client_cert) = &client_cert {
+                req.extensions_mut().insert(client_cert.clone());
+            }
+
             // First HTTP request shares the same session ID
             let mut session_id = session_id.take().unwrap_or_else(uuid::Uuid::new_v4);
 
@@ -432,6 +448,7 @@ async fn request_handler(
 
         let (response, websocket) = framed_websockets::upgrade::upgrade(&mut request)
             .map_err(|e| ApiError::BadRequest(e.into()))?;
+        let client_cert = request.extensions().get::<ClientCertChain>().cloned();
 
         let cancellations = cancellations.clone();
         ws_connections.spawn(
@@ -444,6 +461,7 @@ async fn request_handler(
                     cancellation_handler,
                     endpoint_rate_limiter,
                     host,
+                    client_cert,
                     cancellations,
                 )
                 .await
@@ -0,0 +1,521 @@
+//! Output formats for SQL-over-HTTP responses.
+//!
+//! By default the result of a query is returned as a single JSON document,
+//! which is buffered in full before it is sent. Clients can ask for one of the
+//! streaming formats with the `Accept` header instead. Rows are then encoded
+//! and flushed as they arrive from postgres:
+//!
+//! * `application/x-ndjson`: one JSON value per line. The first line is
+//!   `{"fields":[...]}`, every row is a JSON array, and the last line is either
+//!   `{"command":...,"rowCount":...,"rowAsArray":true}` or `{"error":{...}}`.
+//! * `application/vnd.apache.arrow.stream`: an Arrow IPC stream. Booleans,
+//!   integers and floats are sent as the matching arrow types, everything else
+//!   as text, exactly as postgres formats it.
+//! * `text/csv`: a header line with the column names, then one line per row.
+//!   NULL is an empty field, the empty string is `""`.
+//!
+//! Once the first byte is sent the status code can't change anymore, so all
+//! streaming responses end with [`QUERY_STATUS`](super::http_util::QUERY_STATUS)
+//! trailers. For Arrow and CSV this is the only way to learn about an error
+//! that happened mid-stream.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow_array::builder::{
+    BooleanBuilder, Float32Builder, Float64Builder, Int16Builder, Int32Builder, Int64Builder,
+    StringBuilder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use bytes::Bytes;
+use http::HeaderMap;
+use http::header::ACCEPT;
+use postgres_client::types::Type;
+use postgres_client::{Column, Row};
+
+use super::json::{JsonConversionError, pg_columns_to_json, pg_text_row_to_json};
+use crate::config::{FormatLimits, ResponseLimits};
+
+/// Max number of rows in a single arrow record batch.
+const ARROW_BATCH_ROWS: usize = 4096;
+/// Max size of the postgres text values in a single arrow record batch.
+const ARROW_BATCH_BYTES: usize = 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ResponseFormat {
+    Json,
+    Ndjson,
+    Arrow,
+    Csv,
+}
+
+impl ResponseFormat {
+    /// Pick the format with the highest quality in the `Accept` header.
+    /// Falls back to JSON if the client doesn't accept any of our formats,
+    /// as clients have never been required to send a matching header.
+    pub(crate) fn negotiate(headers: &HeaderMap) -> Self {
+        let mut best = None;
+        let mut best_q = 0.0;
+
+        let media_ranges = headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+
+        for media_range in media_ranges {
+            let mut params = media_range.split(';');
+            let media_type = params.next().unwrap_or_default().trim();
+            let q = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            let Some(format) = Self::from_media_type(media_type) else {
+                continue;
+            };
+            if q > best_q {
+                best = Some(format);
+                best_q = q;
+            }
+        }
+
+        best.unwrap_or(Self::Json)
+    }
+
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        const MEDIA_TYPES: &[(&str, ResponseFormat)] = &[
+            ("application/json", ResponseFormat::Json),
+            ("application/x-ndjson", ResponseFormat::Ndjson),
+            ("application/jsonl", ResponseFormat::Ndjson),
+            ("application/vnd.apache.arrow.stream", ResponseFormat::Arrow),
+            ("text/csv", ResponseFormat::Csv),
+            ("application/*", ResponseFormat::Json),
+            ("*/*", ResponseFormat::Json),
+        ];
+
+        MEDIA_TYPES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(media_type))
+            .map(|&(_, format)| format)
+    }
+
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Ndjson => "application/x-ndjson",
+            Self::Arrow => "application/vnd.apache.arrow.stream",
+            Self::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub(crate) fn limits(self, limits: &ResponseLimits) -> FormatLimits {
+        match self {
+            Self::Json => limits.json,
+            Self::Ndjson => limits.ndjson,
+            Self::Arrow => limits.arrow,
+            Self::Csv => limits.csv,
+        }
+    }
+
+    pub(crate) fn is_streaming(self) -> bool {
+        self != Self::Json
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum EncodeError {
+    #[error("{0}")]
+    Conversion(#[from] JsonConversionError),
+    #[error("arrow error: {0}")]
+    Arrow(#[from] ArrowError),
+}
+
+/// Incrementally encodes the result of a single query in one of the streaming formats.
+pub(crate) struct RowEncoder {
+    kind: EncoderKind,
+    raw_output: bool,
+    buf: Vec<u8>,
+}
+
+enum EncoderKind {
+    Ndjson,
+    Csv,
+    /// Created once the columns are known.
+    Arrow(Option<Box<ArrowEncoder>>),
+}
+
+impl RowEncoder {
+    pub(crate) fn new(format: ResponseFormat, raw_output: bool) -> Self {
+        let kind = match format {
+            ResponseFormat::Ndjson => EncoderKind::Ndjson,
+            ResponseFormat::Csv => EncoderKind::Csv,
+            ResponseFormat::Arrow => EncoderKind::Arrow(None),
+            ResponseFormat::Json => unreachable!("json responses are not streamed"),
+        };
+        Self {
+            kind,
+            raw_output,
+            buf: vec![],
+        }
+    }
+
+    pub(crate) fn begin(&mut self, columns: &[Column]) -> Result<(), EncodeError> {
+        match &mut self.kind {
+            EncoderKind::Ndjson => {
+                let obj = json::ValueSer::new(&mut self.buf);
+                json::value_as_object!(|obj| pg_columns_to_json(obj.key("fields"), columns));
+                self.buf.push(b'\n');
+            }
+            EncoderKind::Csv => {
+                for (i, column) in columns.iter().enumerate() {
+                    if i > 0 {
+                        self.buf.push(b',');
+                    }
+                    write_csv_field(&mut self.buf, column.name());
+                }
+                self.buf.extend_from_slice(b"\r\n");
+            }
+            EncoderKind::Arrow(arrow) => {
+                *arrow = Some(Box::new(ArrowEncoder::new(columns, self.raw_output)?));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn row(&mut self, row: &Row) -> Result<(), EncodeError> {
+        match &mut self.kind {
+            EncoderKind::Ndjson => {
+                pg_text_row_to_json(
+                    json::ValueSer::new(&mut self.buf),
+                    row,
+                    self.raw_output,
+                    true,
+                )?;
+                self.buf.push(b'\n');
+            }
+            EncoderKind::Csv => {
+                for i in 0..row.len() {
+                    if i > 0 {
+                        self.buf.push(b',');
+                    }
+                    let value = row.as_text(i).map_err(JsonConversionError::AsTextError)?;
+                    if let Some(value) = value {
+                        write_csv_field(&mut self.buf, value);
+                    }
+                }
+                self.buf.extend_from_slice(b"\r\n");
+            }
+            EncoderKind::Arrow(arrow) => {
+                let arrow = arrow.as_mut().expect("begin should be called first");
+                arrow.append(row)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(
+        &mut self,
+        command: &str,
+        row_count: Option<i64>,
+    ) -> Result<(), EncodeError> {
+        match &mut self.kind {
+            EncoderKind::Ndjson => {
+                let obj = json::ValueSer::new(&mut self.buf);
+                json::value_as_object!(|obj| {
+                    obj.entry("command", command);
+                    obj.entry("rowCount", row_count);
+                    obj.entry("rowAsArray", true);
+                });
+                self.buf.push(b'\n');
+            }
+            EncoderKind::Csv => {}
+            EncoderKind::Arrow(arrow) => {
+                let arrow = arrow.as_mut().expect("begin should be called first");
+                arrow.finish()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Report an error in the body, if the format allows it.
+    pub(crate) fn error(&mut self, body: impl serde::Serialize) {
+        match &mut self.kind {
+            EncoderKind::Ndjson => {
+                self.buf.extend_from_slice(b"{\"error\":");
+                serde_json::to_writer(&mut self.buf, &body)
+                    .expect("serializing the error should not fail");
+                self.buf.extend_from_slice(b"}\n");
+            }
+            // Leave the CSV and Arrow streams truncated, the trailers tell the rest.
+            EncoderKind::Csv | EncoderKind::Arrow(_) => {}
+        }
+    }
+
+    /// The size of the encoded data which wasn't taken yet, including
+    /// an estimate for data that isn't encoded yet.
+    pub(crate) fn len(&self) -> usize {
+        match &self.kind {
+            EncoderKind::Arrow(Some(arrow)) => arrow.encoded().len() + arrow.batch_bytes,
+            _ => self.buf.len(),
+        }
+    }
+
+    /// The size of the encoded data which is ready to be taken.
+    pub(crate) fn ready(&self) -> usize {
+        match &self.kind {
+            EncoderKind::Arrow(Some(arrow)) => arrow.encoded().len(),
+            _ => self.buf.len(),
+        }
+    }
+
+    /// Take the encoded data which is ready to be sent.
+    pub(crate) fn take(&mut self) -> Bytes {
+        match &mut self.kind {
+            EncoderKind::Arrow(Some(arrow)) => Bytes::from(arrow.take_encoded()),
+            _ => Bytes::from(std::mem::take(&mut self.buf)),
+        }
+    }
+}
+
+/// Write a CSV field, quoting it the same way as postgres `COPY ... CSV` does.
+fn write_csv_field(buf: &mut Vec<u8>, value: &str) {
+    let needs_quotes = value.is_empty() || value.contains([',', '"', '\n', '\r']);
+    if !needs_quotes {
+        buf.extend_from_slice(value.as_bytes());
+        return;
+    }
+
+    buf.push(b'"');
+    for part in value.split_inclusive('"') {
+        buf.extend_from_slice(part.as_bytes());
+        if part.ends_with('"') {
+            buf.push(b'"');
+        }
+    }
+    buf.push(b'"');
+}
+
+struct ArrowEncoder {
+    schema: SchemaRef,
+    columns: Vec<ColumnBuilder>,
+    writer: StreamWriter<Vec<u8>>,
+    batch_rows: usize,
+    batch_bytes: usize,
+}
+
+impl ArrowEncoder {
+    fn new(columns: &[Column], raw_output: bool) -> Result<Self, ArrowError> {
+        let (fields, columns): (Vec<_>, Vec<_>) = columns
+            .iter()
+            .map(|column| {
+                let builder = ColumnBuilder::new(column.type_(), raw_output);
+                let field = Field::new(column.name(), builder.data_type(), true).with_metadata(
+                    HashMap::from([("dataTypeID".to_owned(), column.type_().oid().to_string())]),
+                );
+                (field, builder)
+            })
+            .unzip();
+
+        let schema = Arc::new(Schema::new(fields));
+        // writes the schema message.
+        let writer = StreamWriter::try_new(vec![], &schema)?;
+
+        Ok(Self {
+            schema,
+            columns,
+            writer,
+            batch_rows: 0,
+            batch_bytes: 0,
+        })
+    }
+
+    fn append(&mut self, row: &Row) -> Result<(), EncodeError> {
+        for (i, column) in self.columns.iter_mut().enumerate() {
+            let value = row.as_text(i).map_err(JsonConversionError::AsTextError)?;
+            self.batch_bytes += value.map_or(0, str::len);
+            column.append(value)?;
+        }
+        self.batch_rows += 1;
+
+        if self.batch_rows >= ARROW_BATCH_ROWS || self.batch_bytes >= ARROW_BATCH_BYTES {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ArrowError> {
+        if self.batch_rows == 0 {
+            return Ok(());
+        }
+
+        let columns = self.columns.iter_mut().map(ColumnBuilder::finish).collect();
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+        self.writer.write(&batch)?;
+
+        self.batch_rows = 0;
+        self.batch_bytes = 0;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ArrowError> {
+        self.flush()?;
+        // writes the end-of-stream marker.
+        self.writer.finish()
+    }
+
+    fn encoded(&self) -> &[u8] {
+        self.writer.get_ref()
+    }
+
+    fn take_encoded(&mut self) -> Vec<u8> {
+        std::mem::take(self.writer.get_mut())
+    }
+}
+
+enum ColumnBuilder {
+    Bool(BooleanBuilder),
+    Int16(Int16Builder),
+    Int32(Int32Builder),
+    Int64(Int64Builder),
+    Float32(Float32Builder),
+    Float64(Float64Builder),
+    Text(StringBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(pg_type: &Type, raw_output: bool) -> Self {
+        if raw_output {
+            return Self::Text(StringBuilder::new());
+        }
+
+        match *pg_type {
+            Type::BOOL => Self::Bool(BooleanBuilder::new()),
+            Type::INT2 => Self::Int16(Int16Builder::new()),
+            Type::INT4 => Self::Int32(Int32Builder::new()),
+            Type::INT8 => Self::Int64(Int64Builder::new()),
+            Type::FLOAT4 => Self::Float32(Float32Builder::new()),
+            Type::FLOAT8 => Self::Float64(Float64Builder::new()),
+            // numeric is sent as text to not lose any precision.
+            _ => Self::Text(StringBuilder::new()),
+        }
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            Self::Bool(_) => DataType::Boolean,
+            Self::Int16(_) => DataType::Int16,
+            Self::Int32(_) => DataType::Int32,
+            Self::Int64(_) => DataType::Int64,
+            Self::Float32(_) => DataType::Float32,
+            Self::Float64(_) => DataType::Float64,
+            Self::Text(_) => DataType::Utf8,
+        }
+    }
+
+    fn append(&mut self, value: Option<&str>) -> Result<(), JsonConversionError> {
+        match self {
+            Self::Bool(b) => b.append_option(value.map(|v| v == "t")),
+            Self::Int16(b) => b.append_option(value.map(str::parse).transpose()?),
+            Self::Int32(b) => b.append_option(value.map(str::parse).transpose()?),
+            Self::Int64(b) => b.append_option(value.map(str::parse).transpose()?),
+            // rust accepts the "NaN", "Infinity" and "-Infinity" that postgres sends.
+            Self::Float32(b) => b.append_option(value.map(str::parse).transpose()?),
+            Self::Float64(b) => b.append_option(value.map(str::parse).transpose()?),
+            Self::Text(b) => b.append_option(value),
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Self::Bool(b) => Arc::new(b.finish()),
+            Self::Int16(b) => Arc::new(b.finish()),
+            Self::Int32(b) => Arc::new(b.finish()),
+            Self::Int64(b) => Arc::new(b.finish()),
+            Self::Float32(b) => Arc::new(b.finish()),
+            Self::Float64(b) => Arc::new(b.finish()),
+            Self::Text(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::Array;
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn negotiate(accept: &[&'static str]) -> ResponseFormat {
+        let mut headers = HeaderMap::new();
+        for value in accept {
+            headers.append(ACCEPT, HeaderValue::from_static(value));
+        }
+        ResponseFormat::negotiate(&headers)
+    }
+
+    #[test]
+    fn negotiate_format() {
+        assert_eq!(negotiate(&[]), ResponseFormat::Json);
+        assert_eq!(negotiate(&["*/*"]), ResponseFormat::Json);
+        assert_eq!(negotiate(&["text/html"]), ResponseFormat::Json);
+        assert_eq!(negotiate(&["application/x-ndjson"]), ResponseFormat::Ndjson);
+        assert_eq!(negotiate(&["Text/CSV"]), ResponseFormat::Csv);
+        assert_eq!(
+            negotiate(&["application/vnd.apache.arrow.stream, application/json;q=0.5"]),
+            ResponseFormat::Arrow
+        );
+        assert_eq!(
+            negotiate(&["text/csv;q=0.5", "application/jsonl;q=0.9"]),
+            ResponseFormat::Ndjson
+        );
+        // the first of equally preferred formats wins
+        assert_eq!(
+            negotiate(&["application/json, text/csv"]),
+            ResponseFormat::Json
+        );
+        assert_eq!(
+            negotiate(&["text/csv;q=0, */*;q=0.1"]),
+            ResponseFormat::Json
+        );
+    }
+
+    #[test]
+    fn csv_quoting() {
+        let field = |value| {
+            let mut buf = vec![];
+            write_csv_field(&mut buf, value);
+            String::from_utf8(buf).unwrap()
+        };
+
+        assert_eq!(field("plain"), "plain");
+        assert_eq!(field(""), r#""""#);
+        assert_eq!(field("a,b"), r#""a,b""#);
+        assert_eq!(field(r#"say "hi""#), r#""say ""hi""""#);
+        assert_eq!(field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn arrow_column_types() {
+        let mut int = ColumnBuilder::new(&Type::INT8, false);
+        assert_eq!(int.data_type(), DataType::Int64);
+        int.append(Some("42")).unwrap();
+        int.append(None).unwrap();
+        assert!(int.append(Some("4.2")).is_err());
+        assert_eq!(int.finish().len(), 2);
+
+        let mut float = ColumnBuilder::new(&Type::FLOAT8, false);
+        float.append(Some("NaN")).unwrap();
+        float.append(Some("-Infinity")).unwrap();
+        assert_eq!(float.finish().null_count(), 0);
+
+        let raw = ColumnBuilder::new(&Type::INT8, true);
+        assert_eq!(raw.data_type(), DataType::Utf8);
+
+        let numeric = ColumnBuilder::new(&Type::NUMERIC, false);
+        assert_eq!(numeric.data_type(), DataType::Utf8);
+    }
+}
@@ -1,15 +1,17 @@
-use std::pin::pin;
+use std::pin::{Pin, pin};
 use std::sync::Arc;
+use std::task::{Context, Poll};
 
 use bytes::Bytes;
 use futures::future::{Either, select, try_join};
 use futures::{StreamExt, TryFutureExt};
+use http::HeaderMap;
 use http::Method;
-use http::header::AUTHORIZATION;
+use http::header::{ACCEPT, AUTHORIZATION};
 use http_body_util::combinators::BoxBody;
 use http_body_util::{BodyExt, Full};
 use http_utils::error::ApiError;
-use hyper::body::Incoming;
+use hyper::body::{Body, Frame, Incoming};
 use hyper::http::{HeaderName, HeaderValue};
 use hyper::{Request, Response, StatusCode, header};
 use indexmap::IndexMap;
@@ -17,9 +19,10 @@
 use postgres_client::{GenericClient, IsolationLevel, NoTls, ReadyForQueryStatus, Transaction};
 use serde_json::Value;
 use serde_json::value::RawValue;
+use tokio::sync::{mpsc, oneshot};
 use tokio::time::{self, Instant};
 use tokio_util::sync::CancellationToken;
-use tracing::{Level, debug, error, info};
+use tracing::{Instrument, Level, debug, error, info};
 use typed_json::json;
 
 use super::backend::{LocalProxyConnError, PoolingBackend};
@@ -27,17 +30,21 @@
 use super::conn_pool_lib::{self, ConnInfo};
 use super::error::{ConnInfoError, HttpCodeError, ReadPayloadError};
 use super::http_util::{
-    ALLOW_POOL, ARRAY_MODE, CONN_STRING, NEON_REQUEST_ID, RAW_TEXT_OUTPUT, TXN_DEFERRABLE,
+    ALLOW_POOL, ARRAY_MODE, CONN_STRING, NEON_REQUEST_ID, QUERY_COMMAND, QUERY_ERROR_CODE,
+    QUERY_ERROR_MESSAGE, QUERY_ROW_COUNT, QUERY_STATUS, RAW_TEXT_OUTPUT, TXN_DEFERRABLE,
     TXN_ISOLATION_LEVEL, TXN_READ_ONLY, get_conn_info, json_response, uuid_to_header_value,
 };
-use super::json::{JsonConversionError, json_to_pg_text, pg_text_row_to_json};
+use super::json::{JsonConversionError, json_to_pg_text, pg_columns_to_json, pg_text_row_to_json};
+use super::response_format::{EncodeError, ResponseFormat, RowEncoder};
 use crate::auth::backend::ComputeCredentialKeys;
-use crate::config::{HttpConfig, ProxyConfig};
+use crate::config::{FormatLimits, HttpConfig, ProxyConfig};
 use crate::context::RequestContext;
 use crate::error::{ErrorKind, ReportableError, UserFacingError};
 use crate::http::read_body_with_limit;
-use crate::metrics::{HttpDirection, Metrics};
+use crate::metrics::{CacheOutcome, HttpDirection, Metrics};
 use crate::serverless::backend::HttpConnError;
+use crate::sql_firewall::{SqlFirewall, SqlFirewallError};
+use crate::tls::ClientCertChain;
 use crate::usage_metrics::{MetricCounter, MetricCounterRecorder};
 use crate::util::run_until_cancelled;
 
@@ -64,6 +71,19 @@ enum Payload {
     Batch(BatchQueryData),
 }
 
+impl Payload {
+    /// Check every query against the SQL firewall before running any of them.
+    fn check(&self, firewall: &SqlFirewall) -> Result<(), SqlFirewallError> {
+        match self {
+            Payload::Single(stmt) => firewall.check(&stmt.query),
+            Payload::Batch(batch) => batch
+                .queries
+                .iter()
+                .try_for_each(|stmt| firewall.check(&stmt.query)),
+        }
+    }
+}
+
 static HEADER_VALUE_TRUE: HeaderValue = HeaderValue::from_static("true");
 
 fn bytes_to_pg_text<'de, D>(deserializer: D) -> Result<Vec<Option<String>>, D::Error>
@@ -154,43 +174,11 @@ pub(crate) async fn handle(
         Err(e) => {
             let error_kind = e.get_error_kind();
             ctx.set_error_kind(error_kind);
-
-            let mut message = e.to_string_client();
-            let db_error = match &e {
-                SqlOverHttpError::ConnectCompute(HttpConnError::PostgresConnectionError(e))
-                | SqlOverHttpError::Postgres(e) => e.as_db_error(),
-                _ => None,
-            };
-            fn get<'a, T: Default>(db: Option<&'a DbError>, x: impl FnOnce(&'a DbError) -> T) -> T {
-                db.map(x).unwrap_or_default()
-            }
-
-            if let Some(db_error) = db_error {
-                db_error.message().clone_into(&mut message);
+            if let SqlOverHttpError::SqlFirewall(e) = &e {
+                ctx.set_sql_firewall_violation(e.violation());
             }
 
-            let position = db_error.and_then(|db| db.position());
-            let (position, internal_position, internal_query) = match position {
-                Some(ErrorPosition::Original(position)) => (Some(position.to_string()), None, None),
-                Some(ErrorPosition::Internal { position, query }) => {
-                    (None, Some(position.to_string()), Some(query.clone()))
-                }
-                None => (None, None, None),
-            };
-
-            let code = get(db_error, |db| db.code().code());
-            let severity = get(db_error, |db| db.severity());
-            let detail = get(db_error, |db| db.detail());
-            let hint = get(db_error, |db| db.hint());
-            let where_ = get(db_error, |db| db.where_());
-            let table = get(db_error, |db| db.table());
-            let column = get(db_error, |db| db.column());
-            let schema = get(db_error, |db| db.schema());
-            let datatype = get(db_error, |db| db.datatype());
-            let constraint = get(db_error, |db| db.constraint());
-            let file = get(db_error, |db| db.file());
-            let line = get(db_error, |db| db.line().map(|l| l.to_string()));
-            let routine = get(db_error, |db| db.routine());
+            let (message, body) = error_body(&e);
 
             match &e {
                 SqlOverHttpError::Postgres(e)
@@ -222,28 +210,7 @@ fn get<'a, T: Default>(db: Option<&'a DbError>, x: impl FnOnce(&'a DbError) -> T
                 }
             }
 
-            json_response(
-                e.get_http_status_code(),
-                json!({
-                    "message": message,
-                    "code": code,
-                    "detail": detail,
-                    "hint": hint,
-                    "position": position,
-                    "internalPosition": internal_position,
-                    "internalQuery": internal_query,
-                    "severity": severity,
-                    "where": where_,
-                    "table": table,
-                    "column": column,
-                    "schema": schema,
-                    "dataType": datatype,
-                    "constraint": constraint,
-                    "file": file,
-                    "line": line,
-                    "routine": routine,
-                }),
-            )?
+            json_response(e.get_http_status_code(), body)?
         }
     };
 
@@ -253,6 +220,72 @@ fn get<'a, T: Default>(db: Option<&'a DbError>, x: impl FnOnce(&'a DbError) -> T
     Ok(response)
 }
 
+fn db_error(e: &SqlOverHttpError) -> Option<&DbError> {
+    match e {
+        SqlOverHttpError::ConnectCompute(HttpConnError::PostgresConnectionError(e))
+        | SqlOverHttpError::Postgres(e) => e.as_db_error(),
+        _ => None,
+    }
+}
+
+/// The message and the JSON body we report to the user for a failed request.
+fn error_body(e: &SqlOverHttpError) -> (String, impl serde::Serialize) {
+    let mut message = e.to_string_client();
+    let db_error = db_error(e);
+    fn get<'a, T: Default>(db: Option<&'a DbError>, x: impl FnOnce(&'a DbError) -> T) -> T {
+        db.map(x).unwrap_or_default()
+    }
+
+    if let Some(db_error) = db_error {
+        db_error.message().clone_into(&mut message);
+    }
+
+    let position = db_error.and_then(|db| db.position());
+    let (position, internal_position, internal_query) = match position {
+        Some(ErrorPosition::Original(position)) => (Some(position.to_string()), None, None),
+        Some(ErrorPosition::Internal { position, query }) => {
+            (None, Some(position.to_string()), Some(query.clone()))
+        }
+        None => (None, None, None),
+    };
+
+    let code = get(db_error, |db| db.code().code());
+    let severity = get(db_error, |db| db.severity());
+    let detail = get(db_error, |db| db.detail());
+    let hint = get(db_error, |db| db.hint());
+    let where_ = get(db_error, |db| db.where_());
+    let table = get(db_error, |db| db.table());
+    let column = get(db_error, |db| db.column());
+    let schema = get(db_error, |db| db.schema());
+    let datatype = get(db_error, |db| db.datatype());
+    let constraint = get(db_error, |db| db.constraint());
+    let file = get(db_error, |db| db.file());
+    let line = get(db_error, |db| db.line().map(|l| l.to_string()));
+    let routine = get(db_error, |db| db.routine());
+
+    let body = json!({
+        "message": message.clone(),
+        "code": code,
+        "detail": detail,
+        "hint": hint,
+        "position": position,
+        "internalPosition": internal_position,
+        "internalQuery": internal_query,
+        "severity": severity,
+        "where": where_,
+        "table": table,
+        "column": column,
+        "schema": schema,
+        "dataType": datatype,
+        "constraint": constraint,
+        "file": file,
+        "line": line,
+        "routine": routine,
+    });
+
+    (message, body)
+}
+
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum SqlOverHttpError {
     #[error("{0}")]
@@ -263,6 +296,10 @@ pub(crate) enum SqlOverHttpError {
     ConnInfo(#[from] ConnInfoError),
     #[error("response is too large (max is {0} bytes)")]
     ResponseTooLarge(usize),
+    #[error("response has too many rows (max is {0})")]
+    TooManyRows(usize),
+    #[error("batch queries can only be returned as JSON")]
+    StreamingBatch,
     #[error("invalid isolation level")]
     InvalidIsolationLevel,
     /// for queries our customers choose to run
@@ -274,7 +311,11 @@ pub(crate) enum SqlOverHttpError {
     #[error("{0}")]
     JsonConversion(#[from] JsonConversionError),
     #[error("{0}")]
+    Encode(#[from] EncodeError),
+    #[error("{0}")]
     Cancelled(SqlOverHttpCancel),
+    #[error("{0}")]
+    SqlFirewall(#[from] SqlFirewallError),
 }
 
 impl ReportableError for SqlOverHttpError {
@@ -284,6 +325,8 @@ fn get_error_kind(&self) -> ErrorKind {
             SqlOverHttpError::ConnectCompute(e) => e.get_error_kind(),
             SqlOverHttpError::ConnInfo(e) => e.get_error_kind(),
             SqlOverHttpError::ResponseTooLarge(_) => ErrorKind::User,
+            SqlOverHttpError::TooManyRows(_) => ErrorKind::User,
+            SqlOverHttpError::StreamingBatch => ErrorKind::User,
             SqlOverHttpError::InvalidIsolationLevel => ErrorKind::User,
             // customer initiated SQL errors.
             SqlOverHttpError::Postgres(p) => {
@@ -303,7 +346,10 @@ fn get_error_kind(&self) -> ErrorKind {
             }
             // postgres returned a bad row format that we couldn't parse.
             SqlOverHttpError::JsonConversion(_) => ErrorKind::Postgres,
+            SqlOverHttpError::Encode(EncodeError::Conversion(_)) => ErrorKind::Postgres,
+            SqlOverHttpError::Encode(EncodeError::Arrow(_)) => ErrorKind::Service,
             SqlOverHttpError::Cancelled(c) => c.get_error_kind(),
+            SqlOverHttpError::SqlFirewall(e) => e.get_error_kind(),
         }
     }
 }
@@ -315,11 +361,15 @@ fn to_string_client(&self) -> String {
             SqlOverHttpError::ConnectCompute(c) => c.to_string_client(),
             SqlOverHttpError::ConnInfo(c) => c.to_string_client(),
             SqlOverHttpError::ResponseTooLarge(_) => self.to_string(),
+            SqlOverHttpError::TooManyRows(_) => self.to_string(),
+            SqlOverHttpError::StreamingBatch => self.to_string(),
             SqlOverHttpError::InvalidIsolationLevel => self.to_string(),
             SqlOverHttpError::Postgres(p) => p.to_string(),
             SqlOverHttpError::InternalPostgres(p) => p.to_string(),
             SqlOverHttpError::JsonConversion(_) => "could not parse postgres response".to_string(),
+            SqlOverHttpError::Encode(_) => "could not encode postgres response".to_string(),
             SqlOverHttpError::Cancelled(_) => self.to_string(),
+            SqlOverHttpError::SqlFirewall(e) => e.to_string_client(),
         }
     }
 }
@@ -334,11 +384,19 @@ fn get_http_status_code(&self) -> StatusCode {
             },
             SqlOverHttpError::ConnInfo(_) => StatusCode::BAD_REQUEST,
             SqlOverHttpError::ResponseTooLarge(_) => StatusCode::INSUFFICIENT_STORAGE,
+            SqlOverHttpError::TooManyRows(_) => StatusCode::INSUFFICIENT_STORAGE,
+            SqlOverHttpError::StreamingBatch => StatusCode::NOT_ACCEPTABLE,
             SqlOverHttpError::InvalidIsolationLevel => StatusCode::BAD_REQUEST,
             SqlOverHttpError::Postgres(_) => StatusCode::BAD_REQUEST,
             SqlOverHttpError::InternalPostgres(_) => StatusCode::INTERNAL_SERVER_ERROR,
             SqlOverHttpError::JsonConversion(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            SqlOverHttpError::Encode(_) => StatusCode::INTERNAL_SERVER_ERROR,
             SqlOverHttpError::Cancelled(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            SqlOverHttpError::SqlFirewall(SqlFirewallError::Parse(_)) => StatusCode::BAD_REQUEST,
+            SqlOverHttpError::SqlFirewall(SqlFirewallError::TooManyRows(_)) => {
+                StatusCode::INSUFFICIENT_STORAGE
+            }
+            SqlOverHttpError::SqlFirewall(_) => StatusCode::FORBIDDEN,
         }
     }
 }
@@ -433,7 +491,14 @@ async fn handle_inner(
         "handling interactive connection from client"
     );
 
-    let conn_info = get_conn_info(&config.authentication_config, ctx, None, request.headers())?;
+    let client_cert = request.extensions().get::<ClientCertChain>();
+    let conn_info = get_conn_info(
+        &config.authentication_config,
+        ctx,
+        None,
+        request.headers(),
+        client_cert,
+    )?;
     info!(
         user = conn_info.conn_info.user_info.user.as_str(),
         "credentials"
@@ -478,6 +543,7 @@ async fn handle_db_inner(
         || headers.get(&ALLOW_POOL) == Some(&HEADER_VALUE_TRUE);
 
     let parsed_headers = HttpHeaders::try_parse(headers)?;
+    let format = ResponseFormat::negotiate(headers);
 
     let mut request_len = 0;
     let fetch_and_process_request = Box::pin(
@@ -513,8 +579,17 @@ async fn handle_db_inner(
                     .authenticate_with_jwt(ctx, &conn_info.user_info, jwt)
                     .await
                     .map_err(HttpConnError::AuthError)?,
+                AuthData::ClientCertificate(client_cert) => backend
+                    .authenticate_with_client_cert(ctx, &conn_info.user_info, &client_cert)
+                    .await
+                    .map_err(HttpConnError::AuthError)?,
             };
 
+            let firewall = backend
+                .sql_firewall(ctx, &conn_info.user_info)
+                .await
+                .map_err(HttpConnError::from)?;
+
             let client = match keys.keys {
                 ComputeCredentialKeys::JwtPayload(payload)
                     if backend.auth_backend.is_local_proxy() =>
@@ -543,12 +618,12 @@ async fn handle_db_inner(
             // not strictly necessary to mark success here,
             // but it's just insurance for if we forget it somewhere else
             ctx.success();
-            Ok::<_, SqlOverHttpError>(client)
+            Ok::<_, SqlOverHttpError>((client, firewall))
         }
         .map_err(SqlOverHttpError::from),
     );
 
-    let (payload, mut client) = match run_until_cancelled(
+    let (payload, (mut client, firewall)) = match run_until_cancelled(
         // Run both operations in parallel
         try_join(
             pin!(fetch_and_process_request),
@@ -562,6 +637,44 @@ async fn handle_db_inner(
         None => return Err(SqlOverHttpError::Cancelled(SqlOverHttpCancel::Connect)),
     };
 
+    let max_rows = match &firewall {
+        Some(firewall) => {
+            payload.check(firewall)?;
+            firewall.max_rows()
+        }
+        None => None,
+    };
+
+    if format.is_streaming() {
+        let Payload::Single(stmt) = payload else {
+            return Err(SqlOverHttpError::StreamingBatch);
+        };
+
+        let metrics = client.metrics(ctx);
+        metrics.record_ingress(request_len as u64);
+
+        let body = stream_query(
+            &config.http_config,
+            ctx,
+            cancel,
+            client,
+            metrics,
+            stmt,
+            parsed_headers,
+            format,
+            max_rows,
+        )
+        .await?;
+
+        let response = Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, format.content_type())
+            .header(header::TRAILER, QUERY_TRAILERS)
+            .body(body.boxed())
+            .expect("building response payload should not fail");
+        return Ok(response);
+    }
+
     let mut response = Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/json");
@@ -569,8 +682,14 @@ async fn handle_db_inner(
     // Now execute the query and return the result.
     let json_output = match payload {
         Payload::Single(stmt) => {
-            stmt.process(&config.http_config, cancel, &mut client, parsed_headers)
-                .await?
+            stmt.process(
+                &config.http_config,
+                cancel,
+                &mut client,
+                parsed_headers,
+                max_rows,
+            )
+            .await?
         }
         Payload::Batch(statements) => {
             if parsed_headers.txn_read_only {
@@ -587,7 +706,13 @@ async fn handle_db_inner(
             }
 
             statements
-                .process(&config.http_config, cancel, &mut client, parsed_headers)
+                .process(
+                    &config.http_config,
+                    cancel,
+                    &mut client,
+                    parsed_headers,
+                    max_rows,
+                )
                 .await?
         }
     };
@@ -619,6 +744,7 @@ async fn handle_db_inner(
 }
 
 static HEADERS_TO_FORWARD: &[&HeaderName] = &[
+    &ACCEPT,
     &AUTHORIZATION,
     &CONN_STRING,
     &RAW_TEXT_OUTPUT,
@@ -680,6 +806,7 @@ async fn process(
         cancel: CancellationToken,
         client: &mut Client,
         parsed_headers: HttpHeaders,
+        max_rows: Option<usize>,
     ) -> Result<String, SqlOverHttpError> {
         let (inner, mut discard) = client.inner();
         let cancel_token = inner.cancel_token();
@@ -692,7 +819,8 @@ async fn process(
                 &mut *inner,
                 self,
                 json::ValueSer::new(&mut json_buf),
-                parsed_headers
+                parsed_headers,
+                max_rows,
             )),
             pin!(cancel.cancelled()),
         )
@@ -710,13 +838,7 @@ async fn process(
                     Ok(Ok(status)) => Ok(status),
                     // query failed or was cancelled.
                     Ok(Err(error)) => {
-                        let db_error = match &error {
-                            SqlOverHttpError::ConnectCompute(
-                                HttpConnError::PostgresConnectionError(e),
-                            )
-                            | SqlOverHttpError::Postgres(e) => e.as_db_error(),
-                            _ => None,
-                        };
+                        let db_error = db_error(&error);
 
                         // if errored for some other reason, it might not be safe to return
                         if !db_error.is_some_and(|e| *e.code() == SqlState::QUERY_CANCELED) {
@@ -757,6 +879,7 @@ async fn process(
         cancel: CancellationToken,
         client: &mut Client,
         parsed_headers: HttpHeaders,
+        max_rows: Option<usize>,
     ) -> Result<String, SqlOverHttpError> {
         info!("starting transaction");
         let (inner, mut discard) = client.inner();
@@ -788,6 +911,7 @@ async fn process(
             &mut transaction,
             self,
             parsed_headers,
+            max_rows,
         )
         .await
         {
@@ -840,6 +964,7 @@ async fn query_batch(
     transaction: &mut Transaction<'_>,
     queries: BatchQueryData,
     parsed_headers: HttpHeaders,
+    max_rows: Option<usize>,
     results: &mut json::ListSer<'_>,
 ) -> Result<(), SqlOverHttpError> {
     for stmt in queries.queries {
@@ -849,6 +974,7 @@ async fn query_batch(
             stmt,
             results.entry(),
             parsed_headers,
+            max_rows,
         ));
         let cancelled = pin!(cancel.cancelled());
         let res = select(query, cancelled).await;
@@ -873,11 +999,12 @@ async fn query_batch_to_json(
     tx: &mut Transaction<'_>,
     queries: BatchQueryData,
     headers: HttpHeaders,
+    max_rows: Option<usize>,
 ) -> Result<String, SqlOverHttpError> {
     let json_output = json::value_to_string!(|obj| json::value_as_object!(|obj| {
         let results = obj.key("results");
         json::value_as_list!(|results| {
-            query_batch(config, cancel, tx, queries, headers, results).await?;
+            query_batch(config, cancel, tx, queries, headers, max_rows, results).await?;
         });
     }));
 
@@ -890,6 +1017,7 @@ async fn query_to_json<T: GenericClient>(
     data: QueryData,
     output: json::ValueSer<'_>,
     parsed_headers: HttpHeaders,
+    max_rows: Option<usize>,
 ) -> Result<ReadyForQueryStatus, SqlOverHttpError> {
     let query_start = Instant::now();
 
@@ -899,21 +1027,9 @@ async fn query_to_json<T: GenericClient>(
         .await
         .map_err(SqlOverHttpError::Postgres)?;
     let query_acknowledged = Instant::now();
+    record_statement_cache(row_stream.statement_cache_hit);
 
-    let mut json_fields = output.key("fields").list();
-    for c in row_stream.statement.columns() {
-        let json_field = json_fields.entry();
-        json::value_as_object!(|json_field| {
-            json_field.entry("name", c.name());
-            json_field.entry("dataTypeID", c.type_().oid());
-            json_field.entry("tableID", c.table_oid());
-            json_field.entry("columnID", c.column_id());
-            json_field.entry("dataTypeSize", c.type_size());
-            json_field.entry("dataTypeModifier", c.type_modifier());
-            json_field.entry("format", "text");
-        });
-    }
-    json_fields.finish();
+    pg_columns_to_json(output.key("fields"), row_stream.statement.columns());
 
     let array_mode = data.array_mode.unwrap_or(parsed_headers.default_array_mode);
     let raw_output = parsed_headers.raw_output;
@@ -921,17 +1037,22 @@ async fn query_to_json<T: GenericClient>(
     // Manually drain the stream into a vector to leave row_stream hanging
     // around to get a command tag. Also check that the response is not too
     // big.
+    let limits = config.response_limits.json;
     let mut rows = 0;
     let mut json_rows = output.key("rows").list();
     while let Some(row) = row_stream.next().await {
         let row = row.map_err(SqlOverHttpError::Postgres)?;
 
-        // we don't have a streaming response support yet so this is to prevent OOM
+        // the JSON response is buffered, so this is to prevent OOM
         // from a malicious query (eg a cross join)
-        if json_rows.as_buffer().len() > config.max_response_size_bytes {
-            return Err(SqlOverHttpError::ResponseTooLarge(
-                config.max_response_size_bytes,
-            ));
+        if json_rows.as_buffer().len() > limits.max_bytes {
+            return Err(SqlOverHttpError::ResponseTooLarge(limits.max_bytes));
+        }
+        if let Some(max_rows) = limits.max_rows.filter(|&max_rows| rows >= max_rows) {
+            return Err(SqlOverHttpError::TooManyRows(max_rows));
+        }
+        if let Some(max_rows) = max_rows.filter(|&max_rows| rows >= max_rows) {
+            return Err(SqlFirewallError::TooManyRows(max_rows).into());
         }
 
         pg_text_row_to_json(json_rows.entry(), &row, raw_output, array_mode)?;
@@ -951,16 +1072,7 @@ async fn query_to_json<T: GenericClient>(
 
     // grab the command tag and number of rows affected
     let command_tag = row_stream.command_tag.unwrap_or_default();
-    let mut command_tag_split = command_tag.split(' ');
-    let command_tag_name = command_tag_split.next().unwrap_or_default();
-    let command_tag_count = if command_tag_name == "INSERT" {
-        // INSERT returns OID first and then number of rows
-        command_tag_split.nth(1)
-    } else {
-        // other commands return number of rows (if any)
-        command_tag_split.next()
-    }
-    .and_then(|s| s.parse::<i64>().ok());
+    let (command_tag_name, command_tag_count) = parse_command_tag(&command_tag);
 
     info!(
         rows,
@@ -979,6 +1091,295 @@ async fn query_to_json<T: GenericClient>(
     Ok(ready)
 }
 
+/// Splits a command tag into the command name and the number of rows affected.
+fn parse_command_tag(command_tag: &str) -> (&str, Option<i64>) {
+    let mut command_tag_split = command_tag.split(' ');
+    let command_tag_name = command_tag_split.next().unwrap_or_default();
+    let command_tag_count = if command_tag_name == "INSERT" {
+        // INSERT returns OID first and then number of rows
+        command_tag_split.nth(1)
+    } else {
+        // other commands return number of rows (if any)
+        command_tag_split.next()
+    }
+    .and_then(|s| s.parse::<i64>().ok());
+    (command_tag_name, command_tag_count)
+}
+
+/// Counts whether the prepared statement of a query was found in the connection's statement cache.
+fn record_statement_cache(hit: Option<bool>) {
+    let outcome = match hit {
+        Some(true) => CacheOutcome::Hit,
+        Some(false) => CacheOutcome::Miss,
+        // the cache is disabled.
+        None => return,
+    };
+    Metrics::get()
+        .proxy
+        .http_statement_cache_lookups_total
+        .inc(outcome);
+}
+
+/// The trailers announced for streamed responses.
+const QUERY_TRAILERS: &str = "neon-query-status, neon-query-command, neon-query-row-count, \
+    neon-query-error-code, neon-query-error-message";
+
+/// Encoded data is sent to the client in chunks of roughly this size.
+const STREAM_CHUNK_BYTES: usize = 64 * 1024;
+
+/// How many chunks can be in flight before we stop reading rows from postgres.
+const STREAM_CHANNEL_CAPACITY: usize = 4;
+
+/// A response body fed by the task streaming the query results.
+struct ChannelBody {
+    rx: mpsc::Receiver<Frame<Bytes>>,
+}
+
+impl Body for ChannelBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+        self.rx.poll_recv(cx).map(|frame| frame.map(Ok))
+    }
+}
+
+/// Runs the query in a background task which owns the connection, and
+/// returns the body the results are streamed into.
+///
+/// Errors that happen before the first row description is known are returned
+/// as a regular error response. Any later errors are reported in the trailers.
+#[allow(clippy::too_many_arguments)]
+async fn stream_query(
+    config: &'static HttpConfig,
+    ctx: &RequestContext,
+    cancel: CancellationToken,
+    client: Client,
+    metrics: Arc<MetricCounter>,
+    data: QueryData,
+    parsed_headers: HttpHeaders,
+    format: ResponseFormat,
+    max_rows: Option<usize>,
+) -> Result<ChannelBody, SqlOverHttpError> {
+    let (tx, rx) = mpsc::channel(STREAM_CHANNEL_CAPACITY);
+    let (started_tx, started_rx) = oneshot::channel();
+
+    let stream = ResultStream {
+        ctx: ctx.clone(),
+        tx,
+        started: Some(started_tx),
+        encoder: RowEncoder::new(format, parsed_headers.raw_output),
+        limits: format.limits(&config.response_limits),
+        max_rows,
+        sent: 0,
+    };
+    tokio::spawn(stream.run(cancel, client, metrics, data).in_current_span());
+
+    match started_rx.await {
+        Ok(Ok(())) => Ok(ChannelBody { rx }),
+        Ok(Err(e)) => Err(e),
+        // the task went away without telling us how the query went.
+        Err(_) => Err(SqlOverHttpError::Cancelled(SqlOverHttpCancel::Postgres)),
+    }
+}
+
+struct ResultStream {
+    /// Records SQL firewall violations once the request has been logged.
+    ctx: RequestContext,
+    tx: mpsc::Sender<Frame<Bytes>>,
+    /// Notifies the handler once the response is ready to be sent.
+    started: Option<oneshot::Sender<Result<(), SqlOverHttpError>>>,
+    encoder: RowEncoder,
+    limits: FormatLimits,
+    /// Row limit of the SQL firewall.
+    max_rows: Option<usize>,
+    /// Number of body bytes handed over to the client so far.
+    sent: usize,
+}
+
+impl ResultStream {
+    async fn run(
+        mut self,
+        cancel: CancellationToken,
+        mut client: Client,
+        metrics: Arc<MetricCounter>,
+        data: QueryData,
+    ) {
+        let (inner, mut discard) = client.inner();
+        let cancel_token = inner.cancel_token();
+
+        let res = match select(
+            pin!(self.stream_rows(&mut *inner, data)),
+            pin!(cancel.cancelled()),
+        )
+        .await
+        {
+            Either::Left((res, _not_yet_cancelled)) => res,
+            Either::Right((_cancelled, _query)) => {
+                tracing::info!("cancelling query");
+                if let Err(err) = cancel_token.cancel_query(NoTls).await {
+                    tracing::warn!(?err, "could not cancel query");
+                }
+                Err(SqlOverHttpError::Cancelled(SqlOverHttpCancel::Postgres))
+            }
+        };
+
+        match res {
+            Ok(status) => discard.check_idle(status),
+            Err(e) => {
+                // we might have stopped in the middle of the results,
+                // the connection is not safe to reuse.
+                discard.discard();
+                self.fail(e).await;
+            }
+        }
+
+        // count the egress bytes - we miss the TLS and header overhead but oh well...
+        metrics.record_egress(self.sent as u64);
+        Metrics::get()
+            .proxy
+            .http_conn_content_length_bytes
+            .observe(HttpDirection::Response, self.sent as f64);
+    }
+
+    async fn stream_rows(
+        &mut self,
+        client: &mut postgres_client::Client,
+        data: QueryData,
+    ) -> Result<ReadyForQueryStatus, SqlOverHttpError> {
+        let query_start = Instant::now();
+
+        let mut row_stream = client
+            .query_raw_txt(&data.query, data.params)
+            .await
+            .map_err(SqlOverHttpError::Postgres)?;
+        let query_acknowledged = Instant::now();
+        record_statement_cache(row_stream.statement_cache_hit);
+
+        self.encoder.begin(row_stream.statement.columns())?;
+        if let Some(started) = self.started.take() {
+            // the handler only goes away if the request was cancelled.
+            let _ = started.send(Ok(()));
+        }
+
+        let mut rows = 0;
+        while let Some(row) = row_stream.next().await {
+            let row = row.map_err(SqlOverHttpError::Postgres)?;
+
+            if self.sent + self.encoder.len() > self.limits.max_bytes {
+                return Err(SqlOverHttpError::ResponseTooLarge(self.limits.max_bytes));
+            }
+            if let Some(max_rows) = self.limits.max_rows.filter(|&max_rows| rows >= max_rows) {
+                return Err(SqlOverHttpError::TooManyRows(max_rows));
+            }
+            if let Some(max_rows) = self.max_rows.filter(|&max_rows| rows >= max_rows) {
+                return Err(SqlFirewallError::TooManyRows(max_rows).into());
+            }
+
+            self.encoder.row(&row)?;
+            rows += 1;
+
+            if self.encoder.ready() >= STREAM_CHUNK_BYTES {
+                self.flush().await?;
+            }
+
+            // see query_to_json
+            tokio::task::consume_budget().await;
+        }
+
+        let query_resp_end = Instant::now();
+        let ready = row_stream.status;
+
+        let command_tag = row_stream.command_tag.unwrap_or_default();
+        let (command_tag_name, command_tag_count) = parse_command_tag(&command_tag);
+
+        info!(
+            rows,
+            ?ready,
+            command_tag,
+            acknowledgement = ?(query_acknowledged - query_start),
+            response = ?(query_resp_end - query_start),
+            "finished streaming query"
+        );
+
+        self.encoder.finish(command_tag_name, command_tag_count)?;
+        self.flush().await?;
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert(QUERY_STATUS.clone(), HeaderValue::from_static("ok"));
+        if let Ok(command) = HeaderValue::from_str(command_tag_name) {
+            trailers.insert(QUERY_COMMAND.clone(), command);
+        }
+        if let Some(count) = command_tag_count {
+            trailers.insert(QUERY_ROW_COUNT.clone(), HeaderValue::from(count));
+        }
+        self.send(Frame::trailers(trailers)).await?;
+
+        Ok(ready)
+    }
+
+    /// Reports the error either as the response to the request, or if the
+    /// response has already started, at the end of the body.
+    async fn fail(&mut self, e: SqlOverHttpError) {
+        if let Some(started) = self.started.take() {
+            let _ = started.send(Err(e));
+            return;
+        }
+
+        let error_kind = e.get_error_kind();
+        tracing::info!(
+            kind=error_kind.to_metric_label(),
+            error=%e,
+            "query failed while streaming the response"
+        );
+        if let SqlOverHttpError::SqlFirewall(e) = &e {
+            self.ctx.set_sql_firewall_violation(e.violation());
+        }
+
+        let (message, body) = error_body(&e);
+        self.encoder.error(body);
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert(QUERY_STATUS.clone(), HeaderValue::from_static("error"));
+        if let Some(code) = db_error(&e).and_then(|db| HeaderValue::from_str(db.code().code()).ok())
+        {
+            trailers.insert(QUERY_ERROR_CODE.clone(), code);
+        }
+        // header values can only contain visible ascii characters.
+        let message: String = message
+            .chars()
+            .map(|c| if c.is_ascii_graphic() { c } else { ' ' })
+            .collect();
+        if let Ok(message) = HeaderValue::from_str(&message) {
+            trailers.insert(QUERY_ERROR_MESSAGE.clone(), message);
+        }
+
+        // the client might be gone already, nothing to do about that.
+        if self.flush().await.is_ok() {
+            let _ = self.send(Frame::trailers(trailers)).await;
+        }
+    }
+
+    async fn flush(&mut self) -> Result<(), SqlOverHttpError> {
+        let chunk = self.encoder.take();
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        self.sent += chunk.len();
+        self.send(Frame::data(chunk)).await
+    }
+
+    async fn send(&mut self, frame: Frame<Bytes>) -> Result<(), SqlOverHttpError> {
+        self.tx
+            .send(frame)
+            .await
+            .map_err(|_| SqlOverHttpError::Cancelled(SqlOverHttpCancel::Postgres))
+    }
+}
+
 enum Client {
     Remote(conn_pool_lib::Client<postgres_client::Client>),
     Local(conn_pool_lib::Client<postgres_client::Client>),
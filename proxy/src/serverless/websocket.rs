@@ -20,6 +20,7 @@
 use crate::pglb::{ClientMode, handle_connection};
 use crate::proxy::ErrorSource;
 use crate::rate_limiter::EndpointRateLimiter;
+use crate::tls::ClientCertChain;
 
 pin_project! {
     /// This is a wrapper around a [`WebSocketStream`] that
@@ -133,6 +134,7 @@ pub(crate) async fn serve_websocket(
     cancellation_handler: Arc<CancellationHandler>,
     endpoint_rate_limiter: Arc<EndpointRateLimiter>,
     hostname: Option<String>,
+    client_cert: Option<ClientCertChain>,
     cancellations: tokio_util::task::task_tracker::TaskTracker,
 ) -> anyhow::Result<()> {
     let websocket = websocket.await?;
@@ -149,10 +151,15 @@ pub(crate) async fn serve_websocket(
         &ctx,
         cancellation_handler,
         WebSocketRw::new(websocket),
-        ClientMode::Websockets { hostname },
+        ClientMode::Websockets {
+            hostname,
+            client_cert,
+        },
         endpoint_rate_limiter,
         conn_gauge,
         cancellations,
+        // transaction pooling is only offered to tcp clients.
+        None,
     ))
     .await;
 
@@ -0,0 +1,465 @@
+//! Per-endpoint SQL firewall.
+//!
+//! The control plane can attach rules to an endpoint that restrict the SQL
+//! its clients may run: deny DDL or specific statement kinds, allow only
+//! listed functions or statements, and cap the number of rows returned.
+//! Queries are parsed with the postgres dialect of `sqlparser`,
+//! a query that cannot be parsed is rejected.
+//!
+//! Rules are enforced on SQL over HTTP and on postgres protocol queries.
+
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+
+use anyhow::Context;
+use serde::Deserialize;
+use sqlparser::ast::{
+    Expr, Function, FunctionArg, FunctionArgExpr, FunctionArguments, ObjectName, Statement,
+    TableFactor, Value, Visit, Visitor,
+};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::{Parser, ParserError};
+use sqlparser::tokenizer::{Token, Tokenizer};
+use thiserror::Error;
+
+use crate::error::{ErrorKind, ReportableError, UserFacingError};
+use crate::pqproto::StartupMessageParams;
+
+/// Statement kinds denied by [`SqlFirewallRules::deny_ddl`].
+const DDL_STATEMENTS: &[&str] = &["create", "alter", "drop", "truncate", "comment"];
+
+/// Settings that change how postgres splits a query into tokens. Queries are
+/// parsed as if they had their default values, so a client that changes them
+/// could hide statements from the firewall: with `standard_conforming_strings`
+/// off, `select 'x\' , 'y; drop table t; --'` drops the table.
+const LEXER_SETTINGS: &[&str] = &["standard_conforming_strings", "backslash_quote"];
+
+/// SQL firewall rules of an endpoint, as returned by the control plane.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub(crate) struct SqlFirewallRules {
+    /// Deny `CREATE`, `ALTER`, `DROP`, `TRUNCATE`, `COMMENT` and `SELECT INTO`.
+    #[serde(default)]
+    pub(crate) deny_ddl: bool,
+    /// Statement kinds to deny, named by their leading keyword, e.g. `delete` or `grant`.
+    #[serde(default)]
+    pub(crate) deny_statements: Vec<String>,
+    /// If set, only these functions may be called. Schema-qualified calls
+    /// must be listed with their schema, e.g. `pg_catalog.now`.
+    pub(crate) allowed_functions: Option<Vec<String>>,
+    /// If set, only these statements may run. Statements are compared after
+    /// parsing, so whitespace, keyword case and comments do not matter.
+    pub(crate) allowed_statements: Option<Vec<String>>,
+    /// Maximum number of rows a single statement may return.
+    pub(crate) max_rows: Option<usize>,
+}
+
+/// Compiled [`SqlFirewallRules`].
+#[derive(Debug)]
+pub struct SqlFirewall {
+    denied: HashSet<String>,
+    allowed_functions: Option<HashSet<String>>,
+    allowed_statements: Option<HashSet<String>>,
+    max_rows: Option<usize>,
+}
+
+impl SqlFirewall {
+    pub(crate) fn new(rules: SqlFirewallRules) -> anyhow::Result<Self> {
+        let mut denied: HashSet<String> = rules
+            .deny_statements
+            .iter()
+            .map(|kind| kind.to_lowercase())
+            .collect();
+        if rules.deny_ddl {
+            denied.extend(DDL_STATEMENTS.iter().map(|kind| kind.to_string()));
+        }
+
+        let allowed_functions = rules.allowed_functions.map(|functions| {
+            functions
+                .iter()
+                .map(|name| name.to_lowercase())
+                .collect::<HashSet<_>>()
+        });
+
+        let allowed_statements = rules
+            .allowed_statements
+            .map(|statements| {
+                let mut allowed = HashSet::new();
+                for sql in &statements {
+                    let parsed = Parser::parse_sql(&PostgreSqlDialect {}, sql)
+                        .with_context(|| format!("invalid allowed statement {sql:?}"))?;
+                    allowed.extend(parsed.iter().map(Statement::to_string));
+                }
+                anyhow::Ok(allowed)
+            })
+            .transpose()?;
+
+        Ok(Self {
+            denied,
+            allowed_functions,
+            allowed_statements,
+            max_rows: rules.max_rows,
+        })
+    }
+
+    /// Check a query, which may contain several statements, against the rules.
+    pub(crate) fn check(&self, sql: &str) -> Result<(), SqlFirewallError> {
+        // Any mention of a lexer setting is rejected. This covers `SET`, `RESET`,
+        // `ALTER ROLE ... SET` and everything else that could change them.
+        for token in Tokenizer::new(&PostgreSqlDialect {}, sql)
+            .tokenize()
+            .map_err(ParserError::from)?
+        {
+            let text = match token {
+                Token::Word(word) => word.value,
+                token => token.to_string(),
+            }
+            .to_lowercase();
+            if let Some(setting) = LEXER_SETTINGS.iter().find(|s| text.contains(*s)) {
+                return Err(SqlFirewallError::DeniedSetting((*setting).to_owned()));
+            }
+        }
+
+        let statements = Parser::parse_sql(&PostgreSqlDialect {}, sql)?;
+
+        if let Some(allowed) = &self.allowed_statements
+            && statements
+                .iter()
+                .any(|statement| !allowed.contains(&statement.to_string()))
+        {
+            return Err(SqlFirewallError::StatementNotAllowed);
+        }
+
+        match statements.visit(&mut Checker(self)) {
+            ControlFlow::Continue(()) => Ok(()),
+            ControlFlow::Break(e) => Err(e),
+        }
+    }
+
+    /// Check the startup parameters of a postgres protocol session, which can
+    /// set the lexer settings as well, e.g. with `options=-c name=value`.
+    pub(crate) fn check_startup_params(
+        &self,
+        params: &StartupMessageParams,
+    ) -> Result<(), SqlFirewallError> {
+        for (name, value) in params.iter() {
+            // postgres accepts dashes in place of underscores in `options`.
+            let text = format!("{name}={value}").to_lowercase().replace('-', "_");
+            if let Some(setting) = LEXER_SETTINGS.iter().find(|s| text.contains(*s)) {
+                return Err(SqlFirewallError::DeniedSetting((*setting).to_owned()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether function calls that bypass SQL, like the fast-path function call
+    /// of the postgres protocol, are allowed.
+    pub(crate) fn allows_function_calls(&self) -> bool {
+        self.allowed_functions.is_none() && self.allowed_statements.is_none()
+    }
+
+    pub(crate) fn max_rows(&self) -> Option<usize> {
+        self.max_rows
+    }
+
+    fn check_function(&self, name: &ObjectName) -> ControlFlow<SqlFirewallError> {
+        let Some(allowed) = &self.allowed_functions else {
+            return ControlFlow::Continue(());
+        };
+
+        let name = function_name(name);
+        if allowed.contains(&name) {
+            ControlFlow::Continue(())
+        } else {
+            ControlFlow::Break(SqlFirewallError::DeniedFunction(name))
+        }
+    }
+}
+
+struct Checker<'a>(&'a SqlFirewall);
+
+impl Visitor for Checker<'_> {
+    type Break = SqlFirewallError;
+
+    // Also called for statements nested in `EXPLAIN`, `PREPARE` and data-modifying CTEs.
+    fn pre_visit_statement(&mut self, statement: &Statement) -> ControlFlow<Self::Break> {
+        let kind = statement_kind(statement);
+        if self.0.denied.contains(&kind) {
+            return ControlFlow::Break(SqlFirewallError::DeniedStatement(kind));
+        }
+
+        if let Statement::Call(function) = statement {
+            return self.0.check_function(&function.name);
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<Self::Break> {
+        match expr {
+            Expr::Function(function) => {
+                check_set_config(function)?;
+                self.0.check_function(&function.name)
+            }
+            _ => ControlFlow::Continue(()),
+        }
+    }
+
+    fn pre_visit_table_factor(&mut self, table_factor: &TableFactor) -> ControlFlow<Self::Break> {
+        match table_factor {
+            // postgres set-returning functions are parsed as tables with arguments.
+            TableFactor::Table {
+                name,
+                args: Some(_),
+                ..
+            }
+            | TableFactor::Function { name, .. } => self.0.check_function(name),
+            _ => ControlFlow::Continue(()),
+        }
+    }
+}
+
+/// `set_config` may only be called with a plain string literal as the setting
+/// name, so that [`SqlFirewall::check`] sees which setting it changes.
+fn check_set_config(function: &Function) -> ControlFlow<SqlFirewallError> {
+    if !matches!(
+        function_name(&function.name).as_str(),
+        "set_config" | "pg_catalog.set_config"
+    ) {
+        return ControlFlow::Continue(());
+    }
+
+    match &function.args {
+        FunctionArguments::List(list)
+            if matches!(
+                list.args.first(),
+                Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+                    Value::SingleQuotedString(_)
+                ))))
+            ) =>
+        {
+            ControlFlow::Continue(())
+        }
+        _ => ControlFlow::Break(SqlFirewallError::DeniedSetting(
+            "with a computed name".to_owned(),
+        )),
+    }
+}
+
+/// The kind of a statement: its leading keyword, lowercased.
+fn statement_kind(statement: &Statement) -> String {
+    match statement {
+        // `SELECT INTO` creates a table.
+        Statement::Query(query) => match query.body.as_select() {
+            Some(select) if select.into.is_some() => "create".to_owned(),
+            _ => "select".to_owned(),
+        },
+        Statement::Insert(_) => "insert".to_owned(),
+        Statement::Update { .. } => "update".to_owned(),
+        Statement::Delete(_) => "delete".to_owned(),
+        Statement::Copy { .. } => "copy".to_owned(),
+        Statement::Merge { .. } => "merge".to_owned(),
+        statement => statement
+            .to_string()
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_lowercase(),
+    }
+}
+
+/// Qualified function name. Unquoted identifiers are case-insensitive.
+fn function_name(name: &ObjectName) -> String {
+    let parts: Vec<String> = name
+        .0
+        .iter()
+        .map(|ident| match ident.quote_style {
+            Some(_) => ident.value.clone(),
+            None => ident.value.to_lowercase(),
+        })
+        .collect();
+    parts.join(".")
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum SqlFirewallError {
+    #[error("query could not be parsed by the SQL firewall: {0}")]
+    Parse(#[from] ParserError),
+
+    #[error("{} statements are not allowed on this endpoint", .0.to_uppercase())]
+    DeniedStatement(String),
+
+    #[error("function {0} is not allowed on this endpoint")]
+    DeniedFunction(String),
+
+    #[error("statement is not in the list of statements allowed on this endpoint")]
+    StatementNotAllowed,
+
+    #[error("function calls are not allowed on this endpoint")]
+    FunctionCall,
+
+    #[error("setting {0} is not allowed on this endpoint")]
+    DeniedSetting(String),
+
+    #[error("query returned more than the maximum of {0} rows allowed on this endpoint")]
+    TooManyRows(usize),
+}
+
+impl SqlFirewallError {
+    /// The violation, as recorded in the request log.
+    pub(crate) fn violation(&self) -> &'static str {
+        match self {
+            SqlFirewallError::Parse(_) => "parse_error",
+            SqlFirewallError::DeniedStatement(_) => "denied_statement",
+            SqlFirewallError::DeniedFunction(_) => "denied_function",
+            SqlFirewallError::StatementNotAllowed => "statement_not_allowed",
+            SqlFirewallError::FunctionCall => "function_call",
+            SqlFirewallError::DeniedSetting(_) => "denied_setting",
+            SqlFirewallError::TooManyRows(_) => "row_limit",
+        }
+    }
+}
+
+impl ReportableError for SqlFirewallError {
+    fn get_error_kind(&self) -> ErrorKind {
+        ErrorKind::User
+    }
+}
+
+impl UserFacingError for SqlFirewallError {
+    fn to_string_client(&self) -> String {
+        self.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn firewall(rules: serde_json::Value) -> SqlFirewall {
+        SqlFirewall::new(serde_json::from_value(rules).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn deny_statements() {
+        let fw = firewall(serde_json::json!({ "deny_ddl": true, "deny_statements": ["DELETE"] }));
+
+        fw.check("select * from t; insert into t values (1)")
+            .unwrap();
+        fw.check("update t set x = 1").unwrap();
+
+        for sql in [
+            "create table t (x int)",
+            "ALTER TABLE t ADD COLUMN y int",
+            "drop table t",
+            "truncate t",
+            "select 1 into t2",
+            "delete from t",
+            "select 1; delete from t",
+            "explain analyze delete from t",
+            "with d as (update t set x = 1 returning *) select * from d; delete from t",
+        ] {
+            let err = fw.check(sql).unwrap_err();
+            assert!(matches!(err, SqlFirewallError::DeniedStatement(_)), "{sql}");
+        }
+
+        // sqlparser does not support every postgres statement, these are rejected too.
+        for sql in [
+            "selec 1",
+            "with d as (delete from t returning *) select * from d",
+        ] {
+            let err = fw.check(sql).unwrap_err();
+            assert!(matches!(err, SqlFirewallError::Parse(_)), "{sql}");
+        }
+    }
+
+    #[test]
+    fn allowed_functions() {
+        let fw = firewall(serde_json::json!({ "allowed_functions": ["count", "pg_catalog.now"] }));
+        assert!(!fw.allows_function_calls());
+
+        fw.check("select count(*) from t").unwrap();
+        fw.check("SELECT COUNT(*), pg_catalog.now()").unwrap();
+
+        for sql in [
+            "select now()",
+            "select pg_sleep(10)",
+            "select * from generate_series(1, 10)",
+            "select * from t where x = (select max(x) from t)",
+            "call do_things()",
+        ] {
+            let err = fw.check(sql).unwrap_err();
+            assert!(matches!(err, SqlFirewallError::DeniedFunction(_)), "{sql}");
+        }
+    }
+
+    #[test]
+    fn allowed_statements() {
+        let fw = firewall(serde_json::json!({
+            "allowed_statements": ["SELECT * FROM users WHERE id = $1"],
+            "max_rows": 10,
+        }));
+        assert_eq!(fw.max_rows(), Some(10));
+
+        fw.check("select *\n  from users -- by id\n  where id = $1")
+            .unwrap();
+
+        for sql in [
+            "select * from users",
+            "select * from users where id = $1; drop table users",
+        ] {
+            let err = fw.check(sql).unwrap_err();
+            assert!(
+                matches!(err, SqlFirewallError::StatementNotAllowed),
+                "{sql}"
+            );
+        }
+
+        let rules = serde_json::from_value(serde_json::json!({ "allowed_statements": ["selec"] }));
+        assert!(SqlFirewall::new(rules.unwrap()).is_err());
+    }
+
+    #[test]
+    fn lexer_settings() {
+        let fw = firewall(serde_json::json!({ "deny_ddl": true }));
+
+        // with standard_conforming_strings off, postgres would run the drop.
+        let err = fw
+            .check("SET standard_conforming_strings = off")
+            .unwrap_err();
+        assert!(matches!(err, SqlFirewallError::DeniedSetting(_)));
+        fw.check(r"select 'x\' , 'y; drop table t; --'").unwrap();
+
+        for sql in [
+            "set local standard_conforming_strings to off",
+            "SET \"Standard_Conforming_Strings\" = off",
+            "set backslash_quote = on",
+            "select set_config('standard_conforming_strings', 'off', false)",
+            "select pg_catalog.set_config('backslash_quote', 'on', false)",
+            "select set_config('standard_' || 'conforming_strings', 'off', false)",
+            "select set_config(name, 'off', false) from pg_settings",
+            "alter role current_user set standard_conforming_strings = off",
+        ] {
+            let err = fw.check(sql).unwrap_err();
+            assert!(matches!(err, SqlFirewallError::DeniedSetting(_)), "{sql}");
+        }
+
+        fw.check("set search_path = public").unwrap();
+        fw.check("select set_config('search_path', 'public', false)")
+            .unwrap();
+
+        for options in [
+            "-c standard_conforming_strings=off",
+            "--standard-conforming-strings=off",
+        ] {
+            let params = StartupMessageParams::new([("user", "alice"), ("options", options)]);
+            let err = fw.check_startup_params(&params).unwrap_err();
+            assert!(
+                matches!(err, SqlFirewallError::DeniedSetting(_)),
+                "{options}"
+            );
+        }
+        let params = StartupMessageParams::new([("user", "alice"), ("backslash_quote", "on")]);
+        assert!(fw.check_startup_params(&params).is_err());
+        let params = StartupMessageParams::new([("user", "alice"), ("options", "-c geqo=off")]);
+        fw.check_startup_params(&params).unwrap();
+    }
+}
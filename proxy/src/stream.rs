@@ -13,7 +13,7 @@
     BeMessage, FE_PASSWORD_MESSAGE, FeStartupPacket, SQLSTATE_INTERNAL_ERROR, WriteBuf,
     read_message, read_startup,
 };
-use crate::tls::TlsServerEndPoint;
+use crate::tls::{ClientCertChain, TlsServerEndPoint};
 
 /// Stream wrapper which implements libpq's protocol.
 ///
@@ -262,6 +262,14 @@ pub fn sni_hostname(&self) -> Option<&str> {
         }
     }
 
+    /// Return the certificate chain the client presented during the TLS handshake.
+    pub(crate) fn client_certificates(&self) -> Option<ClientCertChain> {
+        match self {
+            Stream::Raw { .. } => None,
+            Stream::Tls { tls, .. } => ClientCertChain::from_connection(tls.get_ref().1),
+        }
+    }
+
     pub(crate) fn tls_server_end_point(&self) -> TlsServerEndPoint {
         match self {
             Stream::Raw { .. } => TlsServerEndPoint::Undefined,
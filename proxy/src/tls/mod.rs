@@ -2,6 +2,8 @@
 pub mod postgres_rustls;
 pub mod server_config;
 
+use std::sync::Arc;
+
 use anyhow::Context;
 use base64::Engine as _;
 use base64::prelude::BASE64_STANDARD;
@@ -13,6 +15,21 @@
 /// <https://github.com/postgres/postgres/blob/ca481d3c9ab7bf69ff0c8d71ad3951d407f6a33c/src/include/libpq/pqcomm.h#L159>
 pub const PG_ALPN_PROTOCOL: &[u8] = b"postgresql";
 
+/// Certificate chain presented by a client during the TLS handshake, leaf first.
+///
+/// The handshake only proves that the client owns the key of the leaf certificate.
+/// The chain must be verified against the CA bundle of the endpoint before it can be trusted,
+/// see `auth::backend::client_cert`.
+#[derive(Clone, Debug)]
+pub struct ClientCertChain(pub Arc<[CertificateDer<'static>]>);
+
+impl ClientCertChain {
+    pub(crate) fn from_connection(conn: &rustls::ServerConnection) -> Option<Self> {
+        let certs = conn.peer_certificates()?;
+        (!certs.is_empty()).then(|| Self(certs.iter().map(|c| c.clone().into_owned()).collect()))
+    }
+}
+
 /// Channel binding parameter
 ///
 /// <https://www.rfc-editor.org/rfc/rfc5929#section-4>
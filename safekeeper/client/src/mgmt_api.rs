@@ -9,10 +9,12 @@
 use reqwest::{IntoUrl, Method, Response, StatusCode};
 use safekeeper_api::models::{
     self, PullTimelineRequest, PullTimelineResponse, SafekeeperStatus, SafekeeperUtilization,
-    TimelineCreateRequest,
+    SnapshotStart, TenantWalQuotaStatus, TimelineCreateRequest, TimelineWalVerifyResponse,
+    WalVerifySource,
 };
 use utils::id::{NodeId, TenantId, TimelineId};
 use utils::logging::SecretString;
+use utils::lsn::Lsn;
 
 #[derive(Debug, Clone)]
 pub struct Client {
@@ -157,6 +159,20 @@ pub async fn bump_timeline_term(
         resp.json().await.map_err(Error::ReceiveBody)
     }
 
+    pub async fn timeline_wal_archive(
+        &self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        req: &models::TimelineWalArchiveRequest,
+    ) -> Result<models::TimelineWalArchiveResponse> {
+        let uri = format!(
+            "{}/v1/tenant/{}/timeline/{}/wal_archive",
+            self.mgmt_api_endpoint, tenant_id, timeline_id
+        );
+        let resp = self.put(&uri, req).await?;
+        resp.json().await.map_err(Error::ReceiveBody)
+    }
+
     pub async fn timeline_status(
         &self,
         tenant_id: TenantId,
@@ -174,14 +190,69 @@ pub async fn snapshot(
         tenant_id: TenantId,
         timeline_id: TimelineId,
         stream_to: NodeId,
+        start: Option<SnapshotStart>,
     ) -> Result<reqwest::Response> {
-        let uri = format!(
+        let mut uri = format!(
             "{}/v1/tenant/{}/timeline/{}/snapshot/{}",
             self.mgmt_api_endpoint, tenant_id, timeline_id, stream_to.0
         );
+        if let Some(start) = start {
+            uri.push_str(&format!("?from_lsn={}&from_term={}", start.lsn, start.term));
+        }
         self.get(&uri).await
     }
 
+    pub async fn timeline_digest(
+        &self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        from_lsn: Lsn,
+        until_lsn: Lsn,
+    ) -> Result<Response> {
+        let uri = format!(
+            "{}/v1/tenant/{}/timeline/{}/digest?from_lsn={}&until_lsn={}",
+            self.mgmt_api_endpoint, tenant_id, timeline_id, from_lsn, until_lsn
+        );
+        self.get(&uri).await
+    }
+
+    /// Download raw WAL of `[from_lsn, until_lsn)`, up to flush_lsn if
+    /// `until_lsn` is not specified. The WAL is in the response body.
+    pub async fn timeline_wal(
+        &self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        from_lsn: Lsn,
+        until_lsn: Option<Lsn>,
+    ) -> Result<reqwest::Response> {
+        let mut uri = format!(
+            "{}/v1/tenant/{}/timeline/{}/wal?from_lsn={}",
+            self.mgmt_api_endpoint, tenant_id, timeline_id, from_lsn
+        );
+        if let Some(until_lsn) = until_lsn {
+            uri.push_str(&format!("&until_lsn={until_lsn}"));
+        }
+        self.get(&uri).await
+    }
+
+    pub async fn timeline_verify_wal(
+        &self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        source: WalVerifySource,
+    ) -> Result<TimelineWalVerifyResponse> {
+        let source = match source {
+            WalVerifySource::Local => "local",
+            WalVerifySource::Remote => "remote",
+        };
+        let uri = format!(
+            "{}/v1/tenant/{}/timeline/{}/verify_wal?source={}",
+            self.mgmt_api_endpoint, tenant_id, timeline_id, source
+        );
+        let resp = self.get(&uri).await?;
+        resp.json().await.map_err(Error::ReceiveBody)
+    }
+
     pub async fn status(&self) -> Result<SafekeeperStatus> {
         let uri = format!("{}/v1/status", self.mgmt_api_endpoint);
         let resp = self.get(&uri).await?;
@@ -194,6 +265,15 @@ pub async fn utilization(&self) -> Result<SafekeeperUtilization> {
         resp.json().await.map_err(Error::ReceiveBody)
     }
 
+    pub async fn tenant_wal_quota(&self, tenant_id: TenantId) -> Result<TenantWalQuotaStatus> {
+        let uri = format!(
+            "{}/v1/tenant/{}/wal_quota",
+            self.mgmt_api_endpoint, tenant_id
+        );
+        let resp = self.get(&uri).await?;
+        resp.json().await.map_err(Error::ReceiveBody)
+    }
+
     async fn post<B: serde::Serialize, U: IntoUrl>(
         &self,
         uri: U,
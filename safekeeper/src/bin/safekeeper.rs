@@ -15,7 +15,7 @@
 use futures::{FutureExt, StreamExt};
 use http_utils::tls_certs::ReloadingCertificateResolver;
 use metrics::set_build_info_metric;
-use remote_storage::RemoteStorageConfig;
+use remote_storage::{GenericRemoteStorage, RemoteStorageConfig};
 use safekeeper::defaults::{
     DEFAULT_CONTROL_FILE_SAVE_INTERVAL, DEFAULT_EVICTION_MIN_RESIDENT,
     DEFAULT_GLOBAL_DISK_CHECK_INTERVAL, DEFAULT_HEARTBEAT_TIMEOUT, DEFAULT_HTTP_LISTEN_ADDR,
@@ -23,13 +23,17 @@
     DEFAULT_MAX_REELECT_OFFLOADER_LAG_BYTES, DEFAULT_MAX_TIMELINE_DISK_USAGE_BYTES,
     DEFAULT_PARTIAL_BACKUP_CONCURRENCY, DEFAULT_PARTIAL_BACKUP_TIMEOUT, DEFAULT_PG_LISTEN_ADDR,
     DEFAULT_SSL_CERT_FILE, DEFAULT_SSL_CERT_RELOAD_PERIOD, DEFAULT_SSL_KEY_FILE,
+    DEFAULT_TENANT_WAL_QUOTA_BYTES, DEFAULT_TIMELINE_WAL_QUOTA_BYTES,
+    DEFAULT_WAL_QUOTA_BACKPRESSURE_RATIO,
 };
 use safekeeper::hadron;
 use safekeeper::wal_backup::WalBackup;
+use safekeeper::wal_verify::{self, SegmentSource, VerifyEnd};
 use safekeeper::{
     BACKGROUND_RUNTIME, BROKER_RUNTIME, GlobalTimelines, HTTP_RUNTIME, SafeKeeperConf,
     WAL_SERVICE_RUNTIME, broker, control_file, http, wal_service,
 };
+use safekeeper_api::models::WalVerifySource;
 use sd_notify::NotifyState;
 use storage_broker::{DEFAULT_ENDPOINT, Uri};
 use tokio::runtime::Handle;
@@ -37,7 +41,7 @@
 use tokio::task::JoinError;
 use tracing::*;
 use utils::auth::{JwtAuth, Scope, SwappableJwtAuth};
-use utils::id::NodeId;
+use utils::id::{NodeId, TenantTimelineId};
 use utils::logging::{self, LogFormat, SecretString};
 use utils::metrics_collector::{METRICS_COLLECTION_INTERVAL, METRICS_COLLECTOR};
 use utils::sentry_init::init_sentry;
@@ -122,6 +126,14 @@ struct Args {
     /// Dump control file at path specified by this argument and exit.
     #[arg(long)]
     dump_control_file: Option<Utf8PathBuf>,
+    /// Decode and check WAL of the timeline in the directory specified by
+    /// this argument, print the result and exit. Fails if WAL is corrupted.
+    #[arg(long)]
+    verify_wal: Option<Utf8PathBuf>,
+    /// Where --verify-wal reads WAL from: local or remote. The latter uses
+    /// --remote-storage.
+    #[arg(long, default_value = "local")]
+    verify_wal_source: WalVerifySource,
     /// Broker endpoint for storage nodes coordination in the form
     /// http[s]://host:port. In case of https schema TLS is connection is
     /// established; plaintext otherwise.
@@ -145,6 +157,12 @@ struct Args {
     /// structure on the file system.
     #[arg(long, value_parser = parse_remote_storage, verbatim_doc_comment)]
     remote_storage: Option<RemoteStorageConfig>,
+    /// Remote storage configuration for WAL archive export, in the same format
+    /// as --remote-storage. Timelines with archive export enabled upload WAL to
+    ///   [prefix_in_bucket/]<tenant_id>/<timeline_id>/<segment_file>
+    /// in the standard PostgreSQL archive layout, usable by restore_command.
+    #[arg(long, value_parser = parse_remote_storage, verbatim_doc_comment)]
+    wal_archive_storage: Option<RemoteStorageConfig>,
     /// Safekeeper won't be elected for WAL offloading if it is lagging for more than this value in bytes
     #[arg(long, default_value_t = DEFAULT_MAX_OFFLOADER_LAG_BYTES)]
     max_offloader_lag: u64,
@@ -257,6 +275,26 @@ struct Args {
     #[arg(long, default_value_t = true)]
     force_metric_collection_on_scrape: bool,
 
+    /// If set, recompress closed WAL segments on local disk with zstd of the
+    /// given level, 1 to 22. Compressed segments are transparently
+    /// decompressed on read.
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..=22))]
+    wal_compression_level: Option<i32>,
+
+    /// Max WAL retained on local disk by a single timeline, in bytes. Above
+    /// wal-quota-backpressure-ratio of it the compute is asked to throttle,
+    /// above it appends are rejected. 0 disables the quota.
+    #[arg(long, default_value_t = DEFAULT_TIMELINE_WAL_QUOTA_BYTES)]
+    timeline_wal_quota_bytes: u64,
+    /// Max WAL retained on local disk by all timelines of a tenant, in bytes.
+    /// 0 disables the quota.
+    #[arg(long, default_value_t = DEFAULT_TENANT_WAL_QUOTA_BYTES)]
+    tenant_wal_quota_bytes: u64,
+    /// Fraction of a retained WAL quota above which the compute is asked to
+    /// throttle WAL generation.
+    #[arg(long, default_value_t = DEFAULT_WAL_QUOTA_BACKPRESSURE_RATIO)]
+    wal_quota_backpressure_ratio: f64,
+
     /// Run in development mode (disables security checks)
     #[arg(long, help = "Run in development mode (disables security checks)")]
     dev: bool,
@@ -318,6 +356,15 @@ async fn main() -> anyhow::Result<()> {
         return Ok(());
     }
 
+    if let Some(timeline_dir) = &args.verify_wal {
+        return verify_wal(
+            timeline_dir,
+            args.verify_wal_source,
+            args.remote_storage.as_ref(),
+        )
+        .await;
+    }
+
     // important to keep the order of:
     // 1. init logging
     // 2. tracing panic hook
@@ -427,6 +474,7 @@ async fn main() -> anyhow::Result<()> {
         heartbeat_timeout: args.heartbeat_timeout,
         peer_recovery_enabled: args.peer_recovery,
         remote_storage: args.remote_storage,
+        wal_archive_storage: args.wal_archive_storage,
         max_offloader_lag_bytes: args.max_offloader_lag,
         /* BEGIN_HADRON */
         max_reelect_offloader_lag_bytes: args.max_reelect_offloader_lag_bytes,
@@ -456,6 +504,10 @@ async fn main() -> anyhow::Result<()> {
         use_https_safekeeper_api: args.use_https_safekeeper_api,
         enable_tls_wal_service_api: args.enable_tls_wal_service_api,
         force_metric_collection_on_scrape: args.force_metric_collection_on_scrape,
+        wal_compression_level: args.wal_compression_level,
+        timeline_wal_quota_bytes: args.timeline_wal_quota_bytes,
+        tenant_wal_quota_bytes: args.tenant_wal_quota_bytes,
+        wal_quota_backpressure_ratio: args.wal_quota_backpressure_ratio,
         /* BEGIN_HADRON */
         advertise_pg_addr_tenant_only: None,
         enable_pull_timeline_on_startup: args.enable_pull_timeline_on_startup,
@@ -782,6 +834,45 @@ async fn start_safekeeper(conf: Arc<SafeKeeperConf>) -> Result<()> {
     std::process::exit(0);
 }
 
+/// Verify WAL of a timeline of a stopped safekeeper: local WAL until it
+/// ends, remote WAL until backup_lsn.
+async fn verify_wal(
+    timeline_dir: &Utf8Path,
+    source: WalVerifySource,
+    remote_storage: Option<&RemoteStorageConfig>,
+) -> Result<()> {
+    let state = control_file::FileStorage::load_control_file(
+        timeline_dir.join(control_file::CONTROL_FILE_NAME),
+    )?;
+    let ttid = TenantTimelineId::new(state.tenant_id, state.timeline_id);
+    let response = match source {
+        WalVerifySource::Local => {
+            let source = SegmentSource::Local(timeline_dir);
+            wal_verify::verify_wal(
+                &ttid,
+                &state,
+                &source,
+                state.commit_lsn,
+                None,
+                VerifyEnd::EndOfWal,
+            )
+            .await?
+        }
+        WalVerifySource::Remote => {
+            let config = remote_storage.context("--remote-storage is required")?;
+            let storage = GenericRemoteStorage::from_config(config).await?;
+            let source = SegmentSource::Remote(&storage);
+            let end = VerifyEnd::Lsn(state.backup_lsn);
+            wal_verify::verify_wal(&ttid, &state, &source, state.backup_lsn, None, end).await?
+        }
+    };
+    println!("{}", serde_json::to_string(&response)?);
+    if let Some(error) = response.error {
+        bail!("WAL is corrupted at {}: {}", error.lsn, error.msg);
+    }
+    Ok(())
+}
+
 /// Determine safekeeper id.
 fn set_id(workdir: &Utf8Path, given_id: Option<NodeId>) -> Result<NodeId> {
     let id_file_path = workdir.join(ID_FILE_NAME);
@@ -15,13 +15,16 @@
 use utils::bin_ser::LeSer;
 use utils::crashsafe::durable_rename;
 
-use crate::control_file_upgrade::{downgrade_v10_to_v9, upgrade_control_file};
+use crate::control_file_upgrade::{
+    downgrade_v10_to_v9, downgrade_v11_to_v10, downgrade_v12_to_v11, upgrade_control_file,
+};
 use crate::metrics::PERSIST_CONTROL_FILE_SECONDS;
 use crate::metrics::WAL_DISK_IO_ERRORS;
 use crate::state::{EvictionState, TimelinePersistentState};
+use crate::wal_archive;
 
 pub const SK_MAGIC: u32 = 0xcafeceefu32;
-pub const SK_FORMAT_VERSION: u32 = 10;
+pub const SK_FORMAT_VERSION: u32 = 12;
 
 // contains persistent metadata for safekeeper
 pub const CONTROL_FILE_NAME: &str = "safekeeper.control";
@@ -170,13 +173,28 @@ pub(crate) fn write_to_buf(&self) -> Result<Vec<u8>> {
         let mut buf: Vec<u8> = Vec::new();
         WriteBytesExt::write_u32::<LittleEndian>(&mut buf, SK_MAGIC)?;
 
-        if self.mconf.generation == INVALID_GENERATION {
+        let wal_archive_unused = self.wal_archive == wal_archive::State::default();
+        let no_witnesses = self.mconf.witnesses.is_empty();
+        if self.mconf.generation == INVALID_GENERATION && wal_archive_unused && no_witnesses {
             // Temp hack for forward compatibility test: in case of none
             // configuration save cfile in previous v9 format.
             const PREV_FORMAT_VERSION: u32 = 9;
             let prev = downgrade_v10_to_v9(self);
             WriteBytesExt::write_u32::<LittleEndian>(&mut buf, PREV_FORMAT_VERSION)?;
             prev.ser_into(&mut buf)?;
+        } else if wal_archive_unused && no_witnesses {
+            // Similarly, keep writing v10 until WAL archive export is enabled
+            // on the timeline, so that the previous release can read it.
+            const PREV_FORMAT_VERSION: u32 = 10;
+            let prev = downgrade_v11_to_v10(self);
+            WriteBytesExt::write_u32::<LittleEndian>(&mut buf, PREV_FORMAT_VERSION)?;
+            prev.ser_into(&mut buf)?;
+        } else if no_witnesses {
+            // And v11 until the configuration gets witnesses.
+            const PREV_FORMAT_VERSION: u32 = 11;
+            let prev = downgrade_v12_to_v11(self);
+            WriteBytesExt::write_u32::<LittleEndian>(&mut buf, PREV_FORMAT_VERSION)?;
+            prev.ser_into(&mut buf)?;
         } else {
             // otherwise, we write the current format version
             WriteBytesExt::write_u32::<LittleEndian>(&mut buf, SK_FORMAT_VERSION)?;
@@ -241,8 +259,11 @@ fn last_persist_at(&self) -> Instant {
 
 #[cfg(test)]
 mod test {
-    use safekeeper_api::membership::{Configuration, MemberSet, SafekeeperGeneration};
+    use safekeeper_api::membership::{
+        Configuration, MemberSet, SafekeeperGeneration, SafekeeperId,
+    };
     use tokio::fs;
+    use utils::id::NodeId;
     use utils::lsn::Lsn;
 
     use super::*;
@@ -257,6 +278,7 @@ async fn test_read_write_safekeeper_state() -> anyhow::Result<()> {
             generation: SafekeeperGeneration::new(42),
             members: MemberSet::empty(),
             new_members: None,
+            witnesses: Vec::new(),
         };
         let mut storage = FileStorage::create_new(tempdir.path(), state.clone(), NO_SYNC).await?;
 
@@ -270,6 +292,48 @@ async fn test_read_write_safekeeper_state() -> anyhow::Result<()> {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_read_write_wal_archive_state() -> anyhow::Result<()> {
+        let tempdir = camino_tempfile::tempdir()?;
+        let mut state = TimelinePersistentState::empty();
+        let mut storage = FileStorage::create_new(tempdir.path(), state.clone(), NO_SYNC).await?;
+
+        // Enabling WAL archive switches the file to the current format version.
+        state.wal_archive = wal_archive::State {
+            enabled: true,
+            archived_lsn: Lsn(0x2000000),
+        };
+        storage.persist(&state).await?;
+
+        let loaded_state = FileStorage::load_control_file_from_dir(tempdir.path())?;
+        assert_eq!(loaded_state, state);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_write_witnesses() -> anyhow::Result<()> {
+        let tempdir = camino_tempfile::tempdir()?;
+        let mut state = TimelinePersistentState::empty();
+        let members = (1..=3)
+            .map(|id| SafekeeperId {
+                id: NodeId(id),
+                host: format!("sk-{id}"),
+                pg_port: 5432,
+            })
+            .collect();
+        state.mconf = Configuration::new(MemberSet::new(members)?);
+        let mut storage = FileStorage::create_new(tempdir.path(), state.clone(), NO_SYNC).await?;
+
+        // Adding a witness switches the file to the current format version.
+        state.mconf.generation = state.mconf.generation.next();
+        state.mconf.witnesses = vec![NodeId(3)];
+        storage.persist(&state).await?;
+
+        let loaded_state = FileStorage::load_control_file_from_dir(tempdir.path())?;
+        assert_eq!(loaded_state, state);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_safekeeper_state_checksum_mismatch() -> anyhow::Result<()> {
         let tempdir = camino_tempfile::tempdir()?;
@@ -4,7 +4,9 @@
 use anyhow::{Result, bail};
 use postgres_versioninfo::PgVersionId;
 use pq_proto::SystemId;
-use safekeeper_api::membership::{Configuration, INVALID_GENERATION};
+use safekeeper_api::membership::{
+    Configuration, INVALID_GENERATION, MemberSet, SafekeeperGeneration,
+};
 use safekeeper_api::{ServerInfo, Term};
 use serde::{Deserialize, Serialize};
 use tracing::*;
@@ -14,7 +16,7 @@
 
 use crate::safekeeper::{AcceptorState, PgUuid, TermHistory, TermLsn};
 use crate::state::{EvictionState, TimelinePersistentState};
-use crate::wal_backup_partial;
+use crate::{wal_archive, wal_backup_partial};
 
 /// Persistent consensus state of the acceptor.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -318,6 +320,137 @@ pub struct TimelinePersistentStateV9 {
     pub eviction_state: EvictionState,
 }
 
+/// Membership configuration as stored in control file versions 10 and 11,
+/// i.e. before witnesses were added.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ConfigurationV11 {
+    pub generation: SafekeeperGeneration,
+    pub members: MemberSet,
+    pub new_members: Option<MemberSet>,
+}
+
+impl From<ConfigurationV11> for Configuration {
+    fn from(c: ConfigurationV11) -> Self {
+        Configuration {
+            generation: c.generation,
+            members: c.members,
+            new_members: c.new_members,
+            witnesses: Vec::new(),
+        }
+    }
+}
+
+impl From<&Configuration> for ConfigurationV11 {
+    fn from(c: &Configuration) -> Self {
+        assert!(c.witnesses.is_empty());
+        ConfigurationV11 {
+            generation: c.generation,
+            members: c.members.clone(),
+            new_members: c.new_members.clone(),
+        }
+    }
+}
+
+/// Note: SafekeeperStateVn is old name for TimelinePersistentStateVn.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TimelinePersistentStateV10 {
+    #[serde(with = "hex")]
+    pub tenant_id: TenantId,
+    #[serde(with = "hex")]
+    pub timeline_id: TimelineId,
+    /// Membership configuration.
+    pub mconf: ConfigurationV11,
+    /// persistent acceptor state
+    pub acceptor_state: AcceptorState,
+    /// information about server
+    pub server: ServerInfo,
+    /// Unique id of the last *elected* proposer we dealt with. Not needed
+    /// for correctness, exists for monitoring purposes.
+    #[serde(with = "hex")]
+    pub proposer_uuid: PgUuid,
+    /// Since which LSN this timeline generally starts. Safekeeper might have
+    /// joined later.
+    pub timeline_start_lsn: Lsn,
+    /// Since which LSN safekeeper has (had) WAL for this timeline.
+    /// All WAL segments next to one containing local_start_lsn are
+    /// filled with data from the beginning.
+    pub local_start_lsn: Lsn,
+    /// Part of WAL acknowledged by quorum *and available locally*. Always points
+    /// to record boundary.
+    pub commit_lsn: Lsn,
+    /// LSN that points to the end of the last backed up segment. Useful to
+    /// persist to avoid finding out offloading progress on boot.
+    pub backup_lsn: Lsn,
+    /// Minimal LSN which may be needed for recovery of some safekeeper (end_lsn
+    /// of last record streamed to everyone). Persisting it helps skipping
+    /// recovery in walproposer, generally we compute it from peers. In
+    /// walproposer proto called 'truncate_lsn'. Updates are currently drived
+    /// only by walproposer.
+    pub peer_horizon_lsn: Lsn,
+    /// LSN of the oldest known checkpoint made by pageserver and successfully
+    /// pushed to s3. We don't remove WAL beyond it. Persisted only for
+    /// informational purposes, we receive it from pageserver (or broker).
+    pub remote_consistent_lsn: Lsn,
+    /// Holds names of partial segments uploaded to remote storage. Used to
+    /// clean up old objects without leaving garbage in remote storage.
+    pub partial_backup: wal_backup_partial::State,
+    /// Eviction state of the timeline. If it's Offloaded, we should download
+    /// WAL files from remote storage to serve the timeline.
+    pub eviction_state: EvictionState,
+    pub creation_ts: std::time::SystemTime,
+}
+
+/// Note: SafekeeperStateVn is old name for TimelinePersistentStateVn.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TimelinePersistentStateV11 {
+    #[serde(with = "hex")]
+    pub tenant_id: TenantId,
+    #[serde(with = "hex")]
+    pub timeline_id: TimelineId,
+    /// Membership configuration.
+    pub mconf: ConfigurationV11,
+    /// persistent acceptor state
+    pub acceptor_state: AcceptorState,
+    /// information about server
+    pub server: ServerInfo,
+    /// Unique id of the last *elected* proposer we dealt with. Not needed
+    /// for correctness, exists for monitoring purposes.
+    #[serde(with = "hex")]
+    pub proposer_uuid: PgUuid,
+    /// Since which LSN this timeline generally starts. Safekeeper might have
+    /// joined later.
+    pub timeline_start_lsn: Lsn,
+    /// Since which LSN safekeeper has (had) WAL for this timeline.
+    /// All WAL segments next to one containing local_start_lsn are
+    /// filled with data from the beginning.
+    pub local_start_lsn: Lsn,
+    /// Part of WAL acknowledged by quorum *and available locally*. Always points
+    /// to record boundary.
+    pub commit_lsn: Lsn,
+    /// LSN that points to the end of the last backed up segment. Useful to
+    /// persist to avoid finding out offloading progress on boot.
+    pub backup_lsn: Lsn,
+    /// Minimal LSN which may be needed for recovery of some safekeeper (end_lsn
+    /// of last record streamed to everyone). Persisting it helps skipping
+    /// recovery in walproposer, generally we compute it from peers. In
+    /// walproposer proto called 'truncate_lsn'. Updates are currently drived
+    /// only by walproposer.
+    pub peer_horizon_lsn: Lsn,
+    /// LSN of the oldest known checkpoint made by pageserver and successfully
+    /// pushed to s3. We don't remove WAL beyond it. Persisted only for
+    /// informational purposes, we receive it from pageserver (or broker).
+    pub remote_consistent_lsn: Lsn,
+    /// Holds names of partial segments uploaded to remote storage. Used to
+    /// clean up old objects without leaving garbage in remote storage.
+    pub partial_backup: wal_backup_partial::State,
+    /// Eviction state of the timeline. If it's Offloaded, we should download
+    /// WAL files from remote storage to serve the timeline.
+    pub eviction_state: EvictionState,
+    pub creation_ts: std::time::SystemTime,
+    /// WAL archive export state.
+    pub wal_archive: wal_archive::State,
+}
+
 pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersistentState> {
     // migrate to storing full term history
     if version == 1 {
@@ -350,6 +483,7 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersiste
             partial_backup: wal_backup_partial::State::default(),
             eviction_state: EvictionState::Present,
             creation_ts: std::time::SystemTime::UNIX_EPOCH,
+            wal_archive: wal_archive::State::default(),
         });
     // migrate to hexing some ids
     } else if version == 2 {
@@ -376,6 +510,7 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersiste
             partial_backup: wal_backup_partial::State::default(),
             eviction_state: EvictionState::Present,
             creation_ts: std::time::SystemTime::UNIX_EPOCH,
+            wal_archive: wal_archive::State::default(),
         });
     // migrate to moving tenant_id/timeline_id to the top and adding some lsns
     } else if version == 3 {
@@ -402,6 +537,7 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersiste
             partial_backup: wal_backup_partial::State::default(),
             eviction_state: EvictionState::Present,
             creation_ts: std::time::SystemTime::UNIX_EPOCH,
+            wal_archive: wal_archive::State::default(),
         });
     // migrate to having timeline_start_lsn
     } else if version == 4 {
@@ -428,6 +564,7 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersiste
             partial_backup: wal_backup_partial::State::default(),
             eviction_state: EvictionState::Present,
             creation_ts: std::time::SystemTime::UNIX_EPOCH,
+            wal_archive: wal_archive::State::default(),
         });
     } else if version == 5 {
         info!("reading safekeeper control file version {}", version);
@@ -474,6 +611,7 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersiste
             partial_backup: wal_backup_partial::State::default(),
             eviction_state: EvictionState::Present,
             creation_ts: std::time::SystemTime::UNIX_EPOCH,
+            wal_archive: wal_archive::State::default(),
         });
     } else if version == 8 {
         let oldstate = SafeKeeperStateV8::des(&buf[..buf.len()])?;
@@ -494,6 +632,7 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersiste
             partial_backup: oldstate.partial_backup,
             eviction_state: EvictionState::Present,
             creation_ts: std::time::SystemTime::UNIX_EPOCH,
+            wal_archive: wal_archive::State::default(),
         });
     } else if version == 9 {
         let oldstate = TimelinePersistentStateV9::des(&buf[..buf.len()])?;
@@ -513,6 +652,47 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersiste
             partial_backup: oldstate.partial_backup,
             eviction_state: oldstate.eviction_state,
             creation_ts: std::time::SystemTime::UNIX_EPOCH,
+            wal_archive: wal_archive::State::default(),
+        });
+    } else if version == 10 {
+        let oldstate = TimelinePersistentStateV10::des(&buf[..buf.len()])?;
+        return Ok(TimelinePersistentState {
+            tenant_id: oldstate.tenant_id,
+            timeline_id: oldstate.timeline_id,
+            mconf: oldstate.mconf.into(),
+            acceptor_state: oldstate.acceptor_state,
+            server: oldstate.server,
+            proposer_uuid: oldstate.proposer_uuid,
+            timeline_start_lsn: oldstate.timeline_start_lsn,
+            local_start_lsn: oldstate.local_start_lsn,
+            commit_lsn: oldstate.commit_lsn,
+            backup_lsn: oldstate.backup_lsn,
+            peer_horizon_lsn: oldstate.peer_horizon_lsn,
+            remote_consistent_lsn: oldstate.remote_consistent_lsn,
+            partial_backup: oldstate.partial_backup,
+            eviction_state: oldstate.eviction_state,
+            creation_ts: oldstate.creation_ts,
+            wal_archive: wal_archive::State::default(),
+        });
+    } else if version == 11 {
+        let oldstate = TimelinePersistentStateV11::des(&buf[..buf.len()])?;
+        return Ok(TimelinePersistentState {
+            tenant_id: oldstate.tenant_id,
+            timeline_id: oldstate.timeline_id,
+            mconf: oldstate.mconf.into(),
+            acceptor_state: oldstate.acceptor_state,
+            server: oldstate.server,
+            proposer_uuid: oldstate.proposer_uuid,
+            timeline_start_lsn: oldstate.timeline_start_lsn,
+            local_start_lsn: oldstate.local_start_lsn,
+            commit_lsn: oldstate.commit_lsn,
+            backup_lsn: oldstate.backup_lsn,
+            peer_horizon_lsn: oldstate.peer_horizon_lsn,
+            remote_consistent_lsn: oldstate.remote_consistent_lsn,
+            partial_backup: oldstate.partial_backup,
+            eviction_state: oldstate.eviction_state,
+            creation_ts: oldstate.creation_ts,
+            wal_archive: oldstate.wal_archive,
         });
     }
 
@@ -544,6 +724,51 @@ pub fn downgrade_v10_to_v9(state: &TimelinePersistentState) -> TimelinePersisten
     }
 }
 
+// Keeps control files readable by the previous release as long as WAL archive
+// export was never used on the timeline.
+pub fn downgrade_v11_to_v10(state: &TimelinePersistentState) -> TimelinePersistentStateV10 {
+    assert!(state.wal_archive == wal_archive::State::default());
+    TimelinePersistentStateV10 {
+        tenant_id: state.tenant_id,
+        timeline_id: state.timeline_id,
+        mconf: ConfigurationV11::from(&state.mconf),
+        acceptor_state: state.acceptor_state.clone(),
+        server: state.server.clone(),
+        proposer_uuid: state.proposer_uuid,
+        timeline_start_lsn: state.timeline_start_lsn,
+        local_start_lsn: state.local_start_lsn,
+        commit_lsn: state.commit_lsn,
+        backup_lsn: state.backup_lsn,
+        peer_horizon_lsn: state.peer_horizon_lsn,
+        remote_consistent_lsn: state.remote_consistent_lsn,
+        partial_backup: state.partial_backup.clone(),
+        eviction_state: state.eviction_state,
+        creation_ts: state.creation_ts,
+    }
+}
+
+// Similarly, timelines without witnesses keep the v11 format.
+pub fn downgrade_v12_to_v11(state: &TimelinePersistentState) -> TimelinePersistentStateV11 {
+    TimelinePersistentStateV11 {
+        tenant_id: state.tenant_id,
+        timeline_id: state.timeline_id,
+        mconf: ConfigurationV11::from(&state.mconf),
+        acceptor_state: state.acceptor_state.clone(),
+        server: state.server.clone(),
+        proposer_uuid: state.proposer_uuid,
+        timeline_start_lsn: state.timeline_start_lsn,
+        local_start_lsn: state.local_start_lsn,
+        commit_lsn: state.commit_lsn,
+        backup_lsn: state.backup_lsn,
+        peer_horizon_lsn: state.peer_horizon_lsn,
+        remote_consistent_lsn: state.remote_consistent_lsn,
+        partial_backup: state.partial_backup.clone(),
+        eviction_state: state.eviction_state,
+        creation_ts: state.creation_ts,
+        wal_archive: state.wal_archive.clone(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
@@ -7,6 +7,7 @@
 use std::sync::Arc;
 
 use anyhow::{Result, bail};
+use bytes::Bytes;
 use camino::{Utf8Path, Utf8PathBuf};
 use chrono::{DateTime, Utc};
 use postgres_ffi::v14::xlog_utils::{IsPartialXLogFileName, IsXLogFileName};
@@ -14,12 +15,15 @@
 use safekeeper_api::models::WalSenderState;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use tracing::error;
 use utils::id::{NodeId, TenantId, TenantTimelineId, TimelineId};
 use utils::lsn::Lsn;
 
 use crate::safekeeper::TermHistory;
 use crate::state::{TimelineMemState, TimelinePersistentState};
 use crate::timeline::{WalResidentTimeline, get_timeline_dir};
+use crate::wal_compression::is_compressed_segment_file_name;
 use crate::{GlobalTimelines, SafeKeeperConf, timeline_manager};
 
 /// Various filters that influence the resulting JSON output.
@@ -319,7 +323,10 @@ fn get_wal_last_modified(path: &Utf8Path) -> Result<Option<DateTime<Utc>>> {
         let entry = entry?;
         /* Ignore files that are not XLOG segments */
         let fname = entry.file_name();
-        if !IsXLogFileName(&fname) && !IsPartialXLogFileName(&fname) {
+        if !IsXLogFileName(&fname)
+            && !IsPartialXLogFileName(&fname)
+            && !is_compressed_segment_file_name(&fname)
+        {
             continue;
         }
 
@@ -388,3 +395,50 @@ pub async fn calculate_digest(
     let digest = hex::encode(digest);
     Ok(TimelineDigest { sha256: digest })
 }
+
+/// Streams raw WAL bytes of `[from_lsn, until_lsn)` to `tx`, reading local
+/// segments (including the .partial one) and falling back to remote storage
+/// for offloaded ones. The range must be checked by the caller to be within
+/// the timeline's flushed WAL.
+pub async fn stream_wal_range(
+    tli: WalResidentTimeline,
+    from_lsn: Lsn,
+    until_lsn: Lsn,
+    tx: mpsc::Sender<Result<Bytes>>,
+) {
+    if let Err(e) = stream_wal_range_guts(&tli, from_lsn, until_lsn, &tx).await {
+        // The client gets a truncated body, see stream_snapshot.
+        tx.send(Err(anyhow::anyhow!("WAL download failed")))
+            .await
+            .ok();
+        error!(
+            "WAL download of {}..{} for {} failed: {:#}",
+            from_lsn, until_lsn, tli.ttid, e
+        );
+    }
+}
+
+async fn stream_wal_range_guts(
+    tli: &WalResidentTimeline,
+    from_lsn: Lsn,
+    until_lsn: Lsn,
+    tx: &mpsc::Sender<Result<Bytes>>,
+) -> Result<()> {
+    let mut wal_reader = tli.get_walreader(from_lsn).await?;
+
+    let mut bytes_left = (until_lsn.0 - from_lsn.0) as usize;
+    while bytes_left > 0 {
+        let mut buf = vec![0u8; std::cmp::min(MAX_SEND_SIZE, bytes_left)];
+        let bytes_read = wal_reader.read(&mut buf).await?;
+        if bytes_read == 0 {
+            bail!("wal_reader.read returned 0 bytes");
+        }
+        buf.truncate(bytes_read);
+        bytes_left -= bytes_read;
+        if tx.send(Ok(Bytes::from(buf))).await.is_err() {
+            // Client went away.
+            return Ok(());
+        }
+    }
+    Ok(())
+}
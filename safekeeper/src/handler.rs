@@ -58,9 +58,17 @@ enum SafekeeperPostgresCommand {
     },
     StartReplication {
         start_lsn: Lsn,
+        /// Postgres timeline requested by the client, if specified.
+        timeline: Option<u32>,
         term: Option<Term>,
     },
     IdentifySystem,
+    TimelineHistory {
+        timeline: u32,
+    },
+    Show {
+        name: String,
+    },
     TimelineStatus,
 }
 
@@ -109,7 +117,7 @@ fn parse_cmd(cmd: &str) -> anyhow::Result<SafekeeperPostgresCommand> {
     } else if cmd.starts_with("START_REPLICATION") {
         let re = Regex::new(
             // We follow postgres START_REPLICATION LOGICAL options to pass term.
-            r"START_REPLICATION(?: SLOT [^ ]+)?(?: PHYSICAL)? ([[:xdigit:]]+/[[:xdigit:]]+)(?: \(term='(\d+)'\))?",
+            r"START_REPLICATION(?: SLOT [^ ]+)?(?: PHYSICAL)? ([[:xdigit:]]+/[[:xdigit:]]+)(?: TIMELINE (\d+))?(?: \(term='(\d+)'\))?",
         )
         .unwrap();
         let caps = re
@@ -117,14 +125,34 @@ fn parse_cmd(cmd: &str) -> anyhow::Result<SafekeeperPostgresCommand> {
             .context(format!("failed to parse START_REPLICATION command {cmd}"))?;
         let start_lsn =
             Lsn::from_str(&caps[1]).context("parse start LSN from START_REPLICATION command")?;
-        let term = if let Some(m) = caps.get(2) {
+        let timeline = if let Some(m) = caps.get(2) {
+            Some(m.as_str().parse::<u32>().context("invalid timeline")?)
+        } else {
+            None
+        };
+        let term = if let Some(m) = caps.get(3) {
             Some(m.as_str().parse::<u64>().context("invalid term")?)
         } else {
             None
         };
-        Ok(SafekeeperPostgresCommand::StartReplication { start_lsn, term })
+        Ok(SafekeeperPostgresCommand::StartReplication {
+            start_lsn,
+            timeline,
+            term,
+        })
     } else if cmd.starts_with("IDENTIFY_SYSTEM") {
         Ok(SafekeeperPostgresCommand::IdentifySystem)
+    } else if cmd.starts_with("TIMELINE_HISTORY") {
+        let re = Regex::new(r"TIMELINE_HISTORY (\d+)").unwrap();
+        let caps = re
+            .captures(cmd)
+            .context(format!("failed to parse TIMELINE_HISTORY command {cmd}"))?;
+        let timeline = caps[1].parse::<u32>().context("invalid timeline")?;
+        Ok(SafekeeperPostgresCommand::TimelineHistory { timeline })
+    } else if let Some(name) = cmd.strip_prefix("SHOW ") {
+        // pg_receivewal asks for a couple of settings before streaming.
+        let name = name.trim().trim_end_matches(';').to_lowercase();
+        Ok(SafekeeperPostgresCommand::Show { name })
     } else if cmd.starts_with("TIMELINE_STATUS") {
         Ok(SafekeeperPostgresCommand::TimelineStatus)
     } else {
@@ -138,6 +166,8 @@ fn cmd_to_string(cmd: &SafekeeperPostgresCommand) -> &str {
         SafekeeperPostgresCommand::StartReplication { .. } => "START_REPLICATION",
         SafekeeperPostgresCommand::TimelineStatus => "TIMELINE_STATUS",
         SafekeeperPostgresCommand::IdentifySystem => "IDENTIFY_SYSTEM",
+        SafekeeperPostgresCommand::TimelineHistory { .. } => "TIMELINE_HISTORY",
+        SafekeeperPostgresCommand::Show { .. } => "SHOW",
     }
 }
 
@@ -202,13 +232,14 @@ fn startup(
                 }
 
                 match self.protocol() {
-                    PostgresClientProtocol::Vanilla => {
+                    PostgresClientProtocol::Vanilla
+                    | PostgresClientProtocol::ChangeEvents { .. } => {
                         if shard_count.is_some()
                             || shard_number.is_some()
                             || shard_stripe_size.is_some()
                         {
                             return Err(QueryError::Other(anyhow::anyhow!(
-                                "Shard params specified for vanilla protocol"
+                                "Shard params specified for unsharded protocol"
                             )));
                         }
                     }
@@ -339,12 +370,27 @@ fn process_query(
                         .instrument(info_span!("WAL receiver"))
                         .await
                 }
-                SafekeeperPostgresCommand::StartReplication { start_lsn, term } => {
+                SafekeeperPostgresCommand::StartReplication {
+                    start_lsn,
+                    timeline,
+                    term,
+                } => {
+                    if let Some(timeline) = timeline {
+                        if timeline != PG_TLI {
+                            return Err(QueryError::Other(anyhow::anyhow!(
+                                "requested timeline {timeline} is not in this server's history, only timeline {PG_TLI} is available"
+                            )));
+                        }
+                    }
                     self.handle_start_replication(pgb, start_lsn, term)
                         .instrument(info_span!("WAL sender"))
                         .await
                 }
                 SafekeeperPostgresCommand::IdentifySystem => self.handle_identify_system(pgb).await,
+                SafekeeperPostgresCommand::TimelineHistory { timeline } => {
+                    self.handle_timeline_history(pgb, timeline).await
+                }
+                SafekeeperPostgresCommand::Show { name } => self.handle_show(pgb, &name).await,
                 SafekeeperPostgresCommand::TimelineStatus => self.handle_timeline_status(pgb).await,
             }
         })
@@ -491,6 +537,56 @@ async fn handle_identify_system<IO: AsyncRead + AsyncWrite + Unpin>(
         Ok(())
     }
 
+    ///
+    /// Handle TIMELINE_HISTORY replication command
+    ///
+    /// Safekeepers always stream WAL of a single Postgres timeline without any
+    /// switches, so there are no history files at all. Reply like Postgres does
+    /// for a timeline without one, e.g. timeline 1 of a fresh cluster.
+    async fn handle_timeline_history<IO: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        _pgb: &mut PostgresBackend<IO>,
+        timeline: u32,
+    ) -> Result<(), QueryError> {
+        Err(QueryError::Other(anyhow::anyhow!(
+            "could not open file \"pg_wal/{timeline:08X}.history\": No such file or directory"
+        )))
+    }
+
+    ///
+    /// Handle SHOW command for the few settings replication clients query
+    ///
+    async fn handle_show<IO: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        pgb: &mut PostgresBackend<IO>,
+        name: &str,
+    ) -> Result<(), QueryError> {
+        let value = match name {
+            "wal_segment_size" => {
+                let tli = self
+                    .global_timelines
+                    .get(self.ttid)
+                    .map_err(|e| QueryError::Other(e.into()))?;
+                let wal_seg_size = tli.get_state().await.1.server.wal_seg_size;
+                format_wal_segment_size(wal_seg_size)
+            }
+            // Directories created by the client for received WAL are private.
+            "data_directory_mode" => "0700".to_string(),
+            _ => {
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "unrecognized configuration parameter \"{name}\""
+                )));
+            }
+        };
+
+        pgb.write_message_noflush(&BeMessage::RowDescription(&[RowDescriptor::text_col(
+            name.as_bytes(),
+        )]))?
+        .write_message_noflush(&BeMessage::DataRow(&[Some(value.as_bytes())]))?
+        .write_message_noflush(&BeMessage::CommandComplete(b"SHOW"))?;
+        Ok(())
+    }
+
     /// Returns true if current connection is a replication connection, originating
     /// from a walproposer recovery function. This connection gets a special handling:
     /// safekeeper must stream all local WAL till the flush_lsn, whether committed or not.
@@ -506,8 +602,24 @@ pub fn is_walproposer_recovery(&self) -> bool {
     }
 }
 
+/// Formats WAL segment size the way Postgres shows it, e.g. `16MB`.
+fn format_wal_segment_size(wal_seg_size: u32) -> String {
+    const KB: u32 = 1024;
+    const MB: u32 = 1024 * KB;
+    const GB: u32 = 1024 * MB;
+    if wal_seg_size % GB == 0 {
+        format!("{}GB", wal_seg_size / GB)
+    } else if wal_seg_size % MB == 0 {
+        format!("{}MB", wal_seg_size / MB)
+    } else {
+        format!("{}kB", wal_seg_size / KB)
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use utils::lsn::Lsn;
+
     use super::SafekeeperPostgresCommand;
 
     /// Test parsing of START_WAL_PUSH command
@@ -540,4 +652,49 @@ fn test_start_wal_push_parse() {
             _ => panic!("unexpected command"),
         }
     }
+
+    /// Test parsing of commands issued by vanilla standbys and pg_receivewal
+    #[test]
+    fn test_physical_replication_parse() {
+        let cmd = "START_REPLICATION SLOT standby PHYSICAL 0/3000000 TIMELINE 1";
+        match super::parse_cmd(cmd).expect("failed to parse") {
+            SafekeeperPostgresCommand::StartReplication {
+                start_lsn,
+                timeline,
+                term,
+            } => {
+                assert_eq!(start_lsn, Lsn(0x3000000));
+                assert_eq!(timeline, Some(1));
+                assert_eq!(term, None);
+            }
+            _ => panic!("unexpected command"),
+        }
+
+        let cmd = "START_REPLICATION 1/2 (term='5')";
+        match super::parse_cmd(cmd).expect("failed to parse") {
+            SafekeeperPostgresCommand::StartReplication {
+                start_lsn,
+                timeline,
+                term,
+            } => {
+                assert_eq!(start_lsn, Lsn(0x1_0000_0002));
+                assert_eq!(timeline, None);
+                assert_eq!(term, Some(5));
+            }
+            _ => panic!("unexpected command"),
+        }
+
+        match super::parse_cmd("TIMELINE_HISTORY 2").expect("failed to parse") {
+            SafekeeperPostgresCommand::TimelineHistory { timeline } => assert_eq!(timeline, 2),
+            _ => panic!("unexpected command"),
+        }
+
+        match super::parse_cmd("SHOW wal_segment_size").expect("failed to parse") {
+            SafekeeperPostgresCommand::Show { name } => assert_eq!(name, "wal_segment_size"),
+            _ => panic!("unexpected command"),
+        }
+
+        assert_eq!(super::format_wal_segment_size(16 * 1024 * 1024), "16MB");
+        assert_eq!(super::format_wal_segment_size(1024 * 1024 * 1024), "1GB");
+    }
 }
@@ -17,11 +17,12 @@
 use pem::Pem;
 use postgres_ffi::WAL_SEGMENT_SIZE;
 use safekeeper_api::models::{
-    AcceptorStateStatus, PullTimelineRequest, SafekeeperStatus, SkTimelineInfo, TenantDeleteResult,
-    TermSwitchApiEntry, TimelineCopyRequest, TimelineCreateRequest, TimelineDeleteResult,
-    TimelineStatus, TimelineTermBumpRequest,
+    AcceptorStateStatus, PullTimelineRequest, SafekeeperStatus, SkTimelineInfo, SnapshotStart,
+    TenantDeleteResult, TermSwitchApiEntry, TimelineCopyRequest, TimelineCreateRequest,
+    TimelineDeleteResult, TimelineStatus, TimelineTermBumpRequest, TimelineWalArchiveRequest,
+    TimelineWalArchiveResponse, WalVerifySource,
 };
-use safekeeper_api::{ServerInfo, membership, models};
+use safekeeper_api::{ServerInfo, Term, membership, models};
 use storage_broker::proto::{SafekeeperTimelineInfo, TenantTimelineId as ProtoTenantTimelineId};
 use tokio::sync::mpsc;
 use tokio::task;
@@ -38,6 +39,7 @@
 use crate::timelines_global_map::DeleteOrExclude;
 use crate::{
     GlobalTimelines, SafeKeeperConf, copy_timeline, debug_dump, patch_control_file, pull_timeline,
+    wal_quota, wal_verify,
 };
 use serde_json::json;
 
@@ -93,6 +95,15 @@ async fn tenant_delete_handler(mut request: Request<Body>) -> Result<Response<Bo
     json_response(StatusCode::OK, response_body)
 }
 
+/// Retained WAL quotas of the tenant and their usage on this safekeeper.
+async fn tenant_wal_quota_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let tenant_id = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+    let conf = get_conf(&request);
+    let status = wal_quota::RETAINED_WAL.tenant_status(conf, &tenant_id);
+    json_response(StatusCode::OK, status)
+}
+
 async fn timeline_create_handler(mut request: Request<Body>) -> Result<Response<Body>, ApiError> {
     let request_data: TimelineCreateRequest = json_request(&mut request).await?;
 
@@ -198,6 +209,7 @@ async fn timeline_status_handler(request: Request<Body>) -> Result<Response<Body
 
     let conf = get_conf(&request);
     // Note: we report in memory values which can be lost.
+    let witness = state.mconf.is_witness(conf.my_id);
     let status = TimelineStatus {
         tenant_id: ttid.tenant_id,
         timeline_id: ttid.timeline_id,
@@ -214,6 +226,7 @@ async fn timeline_status_handler(request: Request<Body>) -> Result<Response<Body
         peers: tli.get_peers(conf).await,
         walsenders: tli.get_walsenders().get_all_public(),
         walreceivers: tli.get_walreceivers().get_all(),
+        witness,
     };
     json_response(StatusCode::OK, status)
 }
@@ -278,10 +291,31 @@ async fn timeline_snapshot_handler(request: Request<Body>) -> Result<Response<Bo
     );
     check_permission(&request, Some(ttid.tenant_id))?;
 
+    let from_lsn: Option<Lsn> = parse_query_param(&request, "from_lsn")?;
+    let from_term: Option<Term> = parse_query_param(&request, "from_term")?;
+    let start = match (from_lsn, from_term) {
+        (Some(lsn), Some(term)) => Some(SnapshotStart { lsn, term }),
+        (None, None) => None,
+        _ => {
+            return Err(ApiError::BadRequest(anyhow::anyhow!(
+                "from_lsn and from_term must be specified together"
+            )));
+        }
+    };
+
     let global_timelines = get_global_timelines(&request);
     let tli = global_timelines.get(ttid).map_err(ApiError::from)?;
     let storage = global_timelines.get_wal_backup().get_storage();
 
+    // Refuse upfront if the receiver's WAL diverges from ours, so that it can
+    // fall back to the full snapshot; start_snapshot checks this again under
+    // the lock.
+    if let Some(start) = &start {
+        tli.check_snapshot_start(start)
+            .await
+            .map_err(|e| ApiError::Conflict(format!("{e:#}")))?;
+    }
+
     // To stream the body use wrap_stream which wants Stream of Result<Bytes>,
     // so create the chan and write to it in another task.
     let (tx, rx) = mpsc::channel(1);
@@ -291,6 +325,7 @@ async fn timeline_snapshot_handler(request: Request<Body>) -> Result<Response<Bo
         tli,
         conf.my_id,
         destination,
+        start,
         tx,
         storage,
     ));
@@ -507,6 +542,102 @@ async fn timeline_digest_handler(request: Request<Body>) -> Result<Response<Body
     json_response(StatusCode::OK, response)
 }
 
+/// Download raw WAL bytes of `[from_lsn, until_lsn)`, `until_lsn` defaulting
+/// to flush_lsn. Segments which are not on local disk are read from remote
+/// storage.
+async fn timeline_wal_download_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let ttid = TenantTimelineId::new(
+        parse_request_param(&request, "tenant_id")?,
+        parse_request_param(&request, "timeline_id")?,
+    );
+    check_permission(&request, Some(ttid.tenant_id))?;
+
+    let global_timelines = get_global_timelines(&request);
+    let from_lsn: Lsn = parse_query_param(&request, "from_lsn")?.ok_or(ApiError::BadRequest(
+        anyhow::anyhow!("from_lsn is required"),
+    ))?;
+    let until_lsn: Option<Lsn> = parse_query_param(&request, "until_lsn")?;
+
+    let tli = global_timelines.get(ttid).map_err(ApiError::from)?;
+    let tli = tli
+        .wal_residence_guard()
+        .await
+        .map_err(ApiError::InternalServerError)?;
+
+    // Bytes past flush_lsn in the .partial segment are garbage, never serve them.
+    let (_, persisted_state) = tli.get_state().await;
+    let flush_lsn = tli.get_flush_lsn().await;
+    let until_lsn = until_lsn.unwrap_or(flush_lsn);
+    if from_lsn > until_lsn {
+        return Err(ApiError::BadRequest(anyhow::anyhow!(
+            "from_lsn {from_lsn} is greater than until_lsn {until_lsn}"
+        )));
+    }
+    if until_lsn > flush_lsn {
+        return Err(ApiError::BadRequest(anyhow::anyhow!(
+            "until_lsn {until_lsn} is beyond flush_lsn {flush_lsn}"
+        )));
+    }
+    if from_lsn < persisted_state.timeline_start_lsn {
+        return Err(ApiError::BadRequest(anyhow::anyhow!(
+            "from_lsn {from_lsn} is before the start of the timeline {}",
+            persisted_state.timeline_start_lsn
+        )));
+    }
+    if from_lsn < persisted_state.local_start_lsn && tli.wal_backup.get_storage().is_none() {
+        return Err(ApiError::BadRequest(anyhow::anyhow!(
+            "WAL before {} is not available locally and remote storage is not configured",
+            persisted_state.local_start_lsn
+        )));
+    }
+
+    // Stream the body from another task, as in timeline_snapshot_handler.
+    let (tx, rx) = mpsc::channel(1);
+    task::spawn(debug_dump::stream_wal_range(tli, from_lsn, until_lsn, tx));
+
+    let body = Body::wrap_stream(ReceiverStream::new(rx));
+    let response = Response::builder()
+        .status(200)
+        .header(hyper::header::CONTENT_TYPE, "application/octet-stream")
+        .header(hyper::header::CONTENT_LENGTH, until_lsn.0 - from_lsn.0)
+        .body(body)
+        .unwrap();
+
+    Ok(response)
+}
+
+/// Decode WAL of the timeline, locally or in remote storage, and report the
+/// first corrupted position, if any.
+async fn timeline_verify_wal_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let ttid = TenantTimelineId::new(
+        parse_request_param(&request, "tenant_id")?,
+        parse_request_param(&request, "timeline_id")?,
+    );
+    check_permission(&request, Some(ttid.tenant_id))?;
+
+    let global_timelines = get_global_timelines(&request);
+    let source: Option<WalVerifySource> = parse_query_param(&request, "source")?;
+    let source = source.unwrap_or(WalVerifySource::Local);
+    let from_lsn: Option<Lsn> = parse_query_param(&request, "from_lsn")?;
+    let until_lsn: Option<Lsn> = parse_query_param(&request, "until_lsn")?;
+
+    let tli = global_timelines.get(ttid).map_err(ApiError::from)?;
+    if source == WalVerifySource::Remote && tli.wal_backup.get_storage().is_none() {
+        return Err(ApiError::BadRequest(anyhow::anyhow!(
+            "remote storage is not configured"
+        )));
+    }
+    let tli = tli
+        .wal_residence_guard()
+        .await
+        .map_err(ApiError::InternalServerError)?;
+
+    let response = wal_verify::verify_timeline(&tli, source, from_lsn, until_lsn)
+        .await
+        .map_err(ApiError::InternalServerError)?;
+    json_response(StatusCode::OK, response)
+}
+
 /// Unevict timeline and remove uploaded partial segment(s) from the remote storage.
 /// Successfull response returns list of segments existed before the deletion.
 /// Aimed for one-off usage not normally needed.
@@ -550,6 +681,42 @@ async fn timeline_term_bump_handler(
     json_response(StatusCode::OK, response)
 }
 
+/// Enable or disable export of the timeline WAL to the WAL archive storage.
+async fn timeline_wal_archive_handler(
+    mut request: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    let ttid = TenantTimelineId::new(
+        parse_request_param(&request, "tenant_id")?,
+        parse_request_param(&request, "timeline_id")?,
+    );
+    check_permission(&request, Some(ttid.tenant_id))?;
+
+    let request_data: TimelineWalArchiveRequest = json_request(&mut request).await?;
+    if request_data.enabled && get_conf(&request).wal_archive_storage.is_none() {
+        return Err(ApiError::BadRequest(anyhow::anyhow!(
+            "WAL archive storage is not configured"
+        )));
+    }
+
+    let global_timelines = get_global_timelines(&request);
+    let tli = global_timelines.get(ttid).map_err(ApiError::from)?;
+    let state = tli
+        .map_control_file(|cf| {
+            cf.wal_archive.enabled = request_data.enabled;
+            Ok(cf.wal_archive.clone())
+        })
+        .await
+        .map_err(ApiError::InternalServerError)?;
+
+    json_response(
+        StatusCode::OK,
+        TimelineWalArchiveResponse {
+            enabled: state.enabled,
+            archived_lsn: state.archived_lsn,
+        },
+    )
+}
+
 /// Used only in tests to hand craft required data.
 async fn record_safekeeper_info(mut request: Request<Body>) -> Result<Response<Body>, ApiError> {
     let ttid = TenantTimelineId::new(
@@ -755,6 +922,9 @@ pub fn make_router(
         .delete("/v1/tenant/:tenant_id", |r| {
             request_span(r, tenant_delete_handler)
         })
+        .get("/v1/tenant/:tenant_id/wal_quota", |r| {
+            request_span(r, tenant_wal_quota_handler)
+        })
         // Will be used in the future instead of implicit timeline creation
         .post("/v1/tenant/timeline", |r| {
             request_span(r, timeline_create_handler)
@@ -797,6 +967,13 @@ pub fn make_router(
         .get("/v1/tenant/:tenant_id/timeline/:timeline_id/digest", |r| {
             request_span(r, timeline_digest_handler)
         })
+        .get(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/verify_wal",
+            |r| request_span(r, timeline_verify_wal_handler),
+        )
+        .get("/v1/tenant/:tenant_id/timeline/:timeline_id/wal", |r| {
+            request_span(r, timeline_wal_download_handler)
+        })
         .post(
             "/v1/tenant/:tenant_id/timeline/:timeline_id/backup_partial_reset",
             |r| request_span(r, timeline_backup_partial_reset),
@@ -805,6 +982,10 @@ pub fn make_router(
             "/v1/tenant/:tenant_id/timeline/:timeline_id/term_bump",
             |r| request_span(r, timeline_term_bump_handler),
         )
+        .put(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/wal_archive",
+            |r| request_span(r, timeline_wal_archive_handler),
+        )
         .post("/v1/record_safekeeper_info/:tenant_id/:timeline_id", |r| {
             request_span(r, record_safekeeper_info)
         })
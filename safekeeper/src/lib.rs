@@ -32,6 +32,7 @@
 pub mod recovery;
 pub mod remove_wal;
 pub mod safekeeper;
+pub mod send_change_events;
 pub mod send_interpreted_wal;
 pub mod send_wal;
 pub mod state;
@@ -40,11 +41,15 @@
 pub mod timeline_guard;
 pub mod timeline_manager;
 pub mod timelines_set;
+pub mod wal_archive;
 pub mod wal_backup;
 pub mod wal_backup_partial;
+pub mod wal_compression;
+pub mod wal_quota;
 pub mod wal_reader_stream;
 pub mod wal_service;
 pub mod wal_storage;
+pub mod wal_verify;
 
 #[cfg(any(test, feature = "benchmarking"))]
 pub mod test_utils;
@@ -88,6 +93,11 @@ pub mod defaults {
     // Global disk watcher defaults
     pub const DEFAULT_GLOBAL_DISK_CHECK_INTERVAL: &str = "60s";
     pub const DEFAULT_MAX_GLOBAL_DISK_USAGE_RATIO: f64 = 0.0;
+
+    // Retained WAL quotas are disabled by default.
+    pub const DEFAULT_TIMELINE_WAL_QUOTA_BYTES: u64 = 0;
+    pub const DEFAULT_TENANT_WAL_QUOTA_BYTES: u64 = 0;
+    pub const DEFAULT_WAL_QUOTA_BACKPRESSURE_RATIO: f64 = 0.8;
 }
 
 #[derive(Debug, Clone)]
@@ -117,6 +127,9 @@ pub struct SafeKeeperConf {
     pub heartbeat_timeout: Duration,
     pub peer_recovery_enabled: bool,
     pub remote_storage: Option<RemoteStorageConfig>,
+    /// Remote storage for timelines exporting WAL in the standard PostgreSQL
+    /// archive layout, see [`wal_archive`].
+    pub wal_archive_storage: Option<RemoteStorageConfig>,
     pub max_offloader_lag_bytes: u64,
     /* BEGIN_HADRON */
     pub max_reelect_offloader_lag_bytes: u64,
@@ -151,6 +164,17 @@ pub struct SafeKeeperConf {
     pub use_https_safekeeper_api: bool,
     pub enable_tls_wal_service_api: bool,
     pub force_metric_collection_on_scrape: bool,
+    /// If set, closed WAL segments are recompressed on local disk with zstd
+    /// of this level; see [`wal_compression`].
+    pub wal_compression_level: Option<i32>,
+    /// Max WAL retained on disk by a single timeline, see [`wal_quota`].
+    /// 0 disables the quota.
+    pub timeline_wal_quota_bytes: u64,
+    /// Max WAL retained on disk by all timelines of a tenant. 0 disables the
+    /// quota.
+    pub tenant_wal_quota_bytes: u64,
+    /// Fraction of a quota above which the walproposer is asked to throttle.
+    pub wal_quota_backpressure_ratio: f64,
 }
 
 impl SafeKeeperConf {
@@ -165,6 +189,7 @@ pub fn dummy() -> Self {
             advertise_pg_addr: None,
             availability_zone: None,
             remote_storage: None,
+            wal_archive_storage: None,
             my_id: NodeId(0),
             broker_endpoint: storage_broker::DEFAULT_ENDPOINT
                 .parse()
@@ -203,6 +228,10 @@ pub fn dummy() -> Self {
             use_https_safekeeper_api: false,
             enable_tls_wal_service_api: false,
             force_metric_collection_on_scrape: true,
+            wal_compression_level: None,
+            timeline_wal_quota_bytes: defaults::DEFAULT_TIMELINE_WAL_QUOTA_BYTES,
+            tenant_wal_quota_bytes: defaults::DEFAULT_TENANT_WAL_QUOTA_BYTES,
+            wal_quota_backpressure_ratio: defaults::DEFAULT_WAL_QUOTA_BACKPRESSURE_RATIO,
             /* BEGIN_HADRON */
             advertise_pg_addr_tenant_only: None,
             enable_pull_timeline_on_startup: false,
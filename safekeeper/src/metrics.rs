@@ -189,6 +189,20 @@
     )
     .expect("Failed to register safekeeper_removed_wal_segments_total counter")
 });
+pub static WAL_COMPRESSED_SEGMENTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "safekeeper_wal_compressed_segments_total",
+        "Number of WAL segments compressed on the disk"
+    )
+    .expect("Failed to register safekeeper_wal_compressed_segments_total counter")
+});
+pub static WAL_COMPRESSED_BYTES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "safekeeper_wal_compressed_bytes_total",
+        "Size of WAL segments after compression on the disk"
+    )
+    .expect("Failed to register safekeeper_wal_compressed_bytes_total counter")
+});
 pub static BACKED_UP_SEGMENTS: Lazy<IntCounter> = Lazy::new(|| {
     register_int_counter!(
         "safekeeper_backed_up_segments_total",
@@ -253,6 +267,21 @@
     )
     .expect("Failed to register safekeeper_partial_backup_uploaded_bytes_total counter")
 });
+pub static WAL_ARCHIVE_UPLOADS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "safekeeper_wal_archive_uploads_total",
+        "Number of segment uploads to the WAL archive",
+        &["result"]
+    )
+    .expect("Failed to register safekeeper_wal_archive_uploads_total counter")
+});
+pub static WAL_ARCHIVE_UPLOADED_BYTES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "safekeeper_wal_archive_uploaded_bytes_total",
+        "Number of bytes uploaded to the WAL archive"
+    )
+    .expect("Failed to register safekeeper_wal_archive_uploaded_bytes_total counter")
+});
 pub static MANAGER_ITERATIONS_TOTAL: Lazy<IntCounter> = Lazy::new(|| {
     register_int_counter!(
         "safekeeper_manager_iterations_total",
@@ -356,6 +385,29 @@ pub(crate) enum EvictionEvent {
     )
     .expect("Failed to register metric")
 });
+pub static WAL_QUOTA_TIMELINES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "safekeeper_wal_quota_timelines",
+        "Number of timelines by retained WAL quota state",
+        &["state"]
+    )
+    .expect("Failed to register safekeeper_wal_quota_timelines gauge vec")
+});
+pub static WAL_QUOTA_REJECTED_APPENDS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "safekeeper_wal_quota_rejected_appends_total",
+        "Number of appends rejected because a retained WAL quota was exceeded",
+        &["quota"]
+    )
+    .expect("Failed to register safekeeper_wal_quota_rejected_appends_total counter vec")
+});
+pub static CHANGE_EVENTS_SENT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "safekeeper_change_events_sent_total",
+        "Number of row-level change events sent to change data capture subscribers"
+    )
+    .expect("Failed to register safekeeper_change_events_sent_total counter")
+});
 
 pub const LABEL_UNKNOWN: &str = "unknown";
 
@@ -1,20 +1,24 @@
-use std::cmp::min;
+use std::cmp::{max, min};
+use std::ffi::OsStr;
 use std::io::{self, ErrorKind};
 use std::ops::RangeInclusive;
 use std::sync::Arc;
 
 use anyhow::{Context, Result, anyhow, bail};
 use bytes::Bytes;
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use chrono::{DateTime, Utc};
 use futures::{SinkExt, StreamExt, TryStreamExt};
 use http::StatusCode;
 use http_utils::error::ApiError;
+use postgres_ffi::v14::xlog_utils::{IsXLogFileName, XLogFromFileName, XLogSegNoOffsetToRecPtr};
 use postgres_ffi::{PG_TLI, XLogFileName, XLogSegNo};
 use remote_storage::GenericRemoteStorage;
 use reqwest::Certificate;
 use safekeeper_api::Term;
-use safekeeper_api::models::{PullTimelineRequest, PullTimelineResponse, TimelineStatus};
+use safekeeper_api::models::{
+    PullTimelineRequest, PullTimelineResponse, SnapshotStart, TimelineStatus,
+};
 use safekeeper_client::mgmt_api;
 use safekeeper_client::mgmt_api::Client;
 use serde::Deserialize;
@@ -33,10 +37,15 @@
 use utils::lsn::Lsn;
 use utils::pausable_failpoint;
 
-use crate::control_file::CONTROL_FILE_NAME;
+use crate::control_file::{CONTROL_FILE_NAME, FileStorage};
+use crate::debug_dump::{TimelineDigest, TimelineDigestRequest};
+use crate::safekeeper::{TermHistory, TermLsn};
 use crate::state::{EvictionState, TimelinePersistentState};
 use crate::timeline::{Timeline, TimelineError, WalResidentTimeline};
 use crate::timelines_global_map::{create_temp_timeline_dir, validate_temp_timeline};
+use crate::wal_compression::{
+    compressed_segment_path, is_compressed_segment_file_name, open_compressed_segment,
+};
 use crate::wal_storage::{open_wal_file, wal_file_paths};
 use crate::{GlobalTimelines, debug_dump, wal_backup};
 
@@ -46,6 +55,7 @@ pub async fn stream_snapshot(
     tli: Arc<Timeline>,
     source: NodeId,
     destination: NodeId,
+    start: Option<SnapshotStart>,
     tx: mpsc::Sender<Result<Bytes>>,
     storage: Option<Arc<GenericRemoteStorage>>,
 ) {
@@ -62,12 +72,23 @@ pub async fn stream_snapshot(
                         resident_tli,
                         source,
                         destination,
+                        start,
                         tx.clone(),
                         storage,
                     )
                     .await
                 }
                 None => {
+                    if start.is_some() {
+                        // Receiver's WAL would be mixed with the offloaded
+                        // timeline; let it retry and get the conflict.
+                        tx.send(Err(anyhow!(
+                            "timeline was offloaded, can't resume snapshot"
+                        )))
+                        .await
+                        .ok();
+                        return;
+                    }
                     if let Some(storage) = storage {
                         stream_snapshot_offloaded_guts(
                             tli,
@@ -113,7 +134,7 @@ fn drop(&mut self) {
         let tli = self.tli.clone();
         task::spawn(async move {
             let mut shared_state = tli.write_shared_state().await;
-            shared_state.wal_removal_on_hold = false;
+            shared_state.wal_removal_holds -= 1;
         });
     }
 }
@@ -167,13 +188,14 @@ pub async fn stream_snapshot_resident_guts(
     tli: WalResidentTimeline,
     source: NodeId,
     destination: NodeId,
+    start: Option<SnapshotStart>,
     tx: mpsc::Sender<Result<Bytes>>,
     storage: Option<Arc<GenericRemoteStorage>>,
 ) -> Result<()> {
     let mut ar = prepare_tar_stream(tx);
 
     let bctx = tli
-        .start_snapshot(&mut ar, source, destination, storage)
+        .start_snapshot(&mut ar, source, destination, start, storage)
         .await?;
     pausable_failpoint!("sk-snapshot-after-list-pausable");
 
@@ -189,20 +211,33 @@ pub async fn stream_snapshot_resident_guts(
             bctx.flush_lsn,
         );
         for segno in from_to_segno.clone() {
+            let mut wal_file_name = XLogFileName(PG_TLI, segno, bctx.wal_seg_size);
             let Some((mut sf, is_partial)) =
                 open_wal_file(&tli_dir, segno, bctx.wal_seg_size).await?
             else {
-                // File is not found
+                // Send compressed segment decompressed, so that the receiver
+                // doesn't need to know about it.
                 let (wal_file_path, _wal_file_partial_path) =
                     wal_file_paths(&tli_dir, segno, bctx.wal_seg_size);
-                tracing::warn!("couldn't find WAL segment file {wal_file_path}");
-                bail!("couldn't find WAL segment file {wal_file_path}")
+                let compressed_path = compressed_segment_path(&wal_file_path);
+                let Some(reader) = open_compressed_segment(&compressed_path, 0).await? else {
+                    // File is not found
+                    tracing::warn!("couldn't find WAL segment file {wal_file_path}");
+                    bail!("couldn't find WAL segment file {wal_file_path}")
+                };
+                let mut header = Header::new_gnu();
+                header.set_size(bctx.wal_seg_size as u64);
+                header.set_mode(0o600);
+                ar.append_data(&mut header, &wal_file_name, reader).await?;
+                continue;
             };
-            let mut wal_file_name = XLogFileName(PG_TLI, segno, bctx.wal_seg_size);
             if is_partial {
                 wal_file_name.push_str(".partial");
             }
             ar.append_file(&wal_file_name, &mut sf).await?;
+            fail::fail_point!("sk-snapshot-after-segment", |_| {
+                Err(anyhow!("failpoint: sk-snapshot-after-segment"))
+            });
         }
     } else {
         info!("Not including any segments into the snapshot");
@@ -218,6 +253,19 @@ pub async fn stream_snapshot_resident_guts(
 }
 
 impl Timeline {
+    /// Check that the receiver's WAL before `start` matches ours and can be
+    /// continued with an incremental snapshot.
+    pub async fn check_snapshot_start(&self, start: &SnapshotStart) -> Result<()> {
+        let shared_state = self.read_shared_state().await;
+        if !matches!(
+            shared_state.sk.state().eviction_state,
+            EvictionState::Present
+        ) {
+            bail!("timeline is offloaded, incremental snapshot is not possible");
+        }
+        check_snapshot_start(shared_state.sk.state(), shared_state.sk.flush_lsn(), start)
+    }
+
     /// Simple snapshot for an offloaded timeline: we will only upload a renamed partial segment and
     /// pass a modified control file into the provided tar stream (nothing with data segments on disk, since
     /// we are offloaded and there aren't any)
@@ -302,16 +350,23 @@ impl WalResidentTimeline {
     /// is not needed, but we likely don't want that as there might be no
     /// compute which could perform the recovery.
     ///
+    /// If `start` is given, the receiver already has WAL before it, so
+    /// segments preceding the one containing `start.lsn` are skipped.
+    ///
     /// When returned SnapshotContext is dropped WAL hold is removed.
     async fn start_snapshot<W: AsyncWrite + Unpin + Send>(
         &self,
         ar: &mut tokio_tar::Builder<W>,
         source: NodeId,
         destination: NodeId,
+        start: Option<SnapshotStart>,
         storage: Option<Arc<GenericRemoteStorage>>,
     ) -> Result<SnapshotContext> {
         let mut shared_state = self.write_shared_state().await;
         let wal_seg_size = shared_state.get_wal_seg_size();
+        if let Some(start) = &start {
+            check_snapshot_start(shared_state.sk.state(), shared_state.sk.flush_lsn(), start)?;
+        }
 
         let mut control_store = TimelinePersistentState::clone(shared_state.sk.state());
         // Modify the partial segment of the in-memory copy for the control file to
@@ -351,8 +406,8 @@ async fn start_snapshot<W: AsyncWrite + Unpin + Send>(
         //
         // We know that WAL wasn't removed up to this point because it cannot be
         // removed further than `backup_lsn`. Since we're holding shared_state
-        // lock and setting `wal_removal_on_hold` later, it guarantees that WAL
-        // won't be removed until we're done.
+        // lock and incrementing `wal_removal_holds` later, it guarantees that
+        // WAL won't be removed until we're done.
         let timeline_state = shared_state.sk.state();
         let from_lsn = min(
             timeline_state.remote_consistent_lsn,
@@ -368,9 +423,13 @@ async fn start_snapshot<W: AsyncWrite + Unpin + Send>(
             remote_consistent_lsn=%timeline_state.remote_consistent_lsn,
             backup_lsn=%timeline_state.backup_lsn,
             %flush_lsn,
+            ?start,
             "{msg}"
         );
-        let from_segno = from_lsn.segment_number(wal_seg_size);
+        let mut from_segno = from_lsn.segment_number(wal_seg_size);
+        if let Some(start) = &start {
+            from_segno = max(from_segno, start.lsn.segment_number(wal_seg_size));
+        }
         let term = shared_state.sk.state().acceptor_state.term;
         let last_log_term = shared_state.sk.last_log_term();
         let upto_segno = flush_lsn.segment_number(wal_seg_size);
@@ -386,13 +445,7 @@ async fn start_snapshot<W: AsyncWrite + Unpin + Send>(
         }
 
         // Prevent WAL removal while we're streaming data.
-        //
-        // Since this a flag, not a counter just bail out if already set; we
-        // shouldn't need concurrent snapshotting.
-        if shared_state.wal_removal_on_hold {
-            bail!("wal_removal_on_hold is already true");
-        }
-        shared_state.wal_removal_on_hold = true;
+        shared_state.wal_removal_holds += 1;
 
         // Drop shared_state to release the lock, before calling wal_residence_guard().
         drop(shared_state);
@@ -435,6 +488,43 @@ pub async fn finish_snapshot(&self, bctx: &SnapshotContext) -> Result<()> {
     }
 }
 
+/// Term of the last WAL record before `lsn` according to `th`, or None if
+/// `lsn` is not past the beginning of the history.
+fn term_before(th: &TermHistory, lsn: Lsn) -> Option<Term> {
+    th.0.iter()
+        .take_while(|e| e.lsn < lsn)
+        .last()
+        .map(|e| e.term)
+}
+
+/// WAL histories which have a record written in the same term at the same
+/// position are identical up to it, so it is enough to compare the term of
+/// the last receiver's record with ours.
+fn check_snapshot_start(
+    state: &TimelinePersistentState,
+    flush_lsn: Lsn,
+    start: &SnapshotStart,
+) -> Result<()> {
+    if start.lsn > flush_lsn {
+        bail!(
+            "snapshot start {} is beyond flush_lsn {}",
+            start.lsn,
+            flush_lsn
+        );
+    }
+    let th = state.acceptor_state.term_history.up_to(flush_lsn);
+    let term = term_before(&th, start.lsn);
+    if term != Some(start.term) {
+        bail!(
+            "WAL before {} was written in term {:?}, but receiver has term {}",
+            start.lsn,
+            term,
+            start.term
+        );
+    }
+    Ok(())
+}
+
 /// Response for debug dump request.
 #[derive(Debug, Deserialize)]
 pub struct DebugDumpResponse {
@@ -457,12 +547,17 @@ pub async fn handle_request(
         request.tenant_id,
         request.timeline_id,
     ));
-    if existing_tli.is_ok() {
-        info!("Timeline {} already exists", request.timeline_id);
-        return Ok(PullTimelineResponse {
-            safekeeper_host: None,
-        });
-    }
+    // In incremental mode existing timeline is caught up from the donor.
+    let local_tli = match existing_tli {
+        Ok(tli) if request.incremental.unwrap_or(false) => Some(tli),
+        Ok(_) => {
+            info!("Timeline {} already exists", request.timeline_id);
+            return Ok(PullTimelineResponse {
+                safekeeper_host: None,
+            });
+        }
+        Err(_) => None,
+    };
 
     let mut http_client = reqwest::Client::builder();
     for ssl_ca_cert in ssl_ca_certs {
@@ -571,9 +666,10 @@ pub async fn handle_request(
         }
     }
 
-    // Find the most advanced safekeeper
+    // Find the most advanced safekeeper; witnesses don't have WAL to pull.
     let (status, i) = statuses
         .into_iter()
+        .filter(|(status, _)| !status.witness)
         .max_by_key(|(status, _)| {
             (
                 status.acceptor_state.epoch,
@@ -587,7 +683,11 @@ pub async fn handle_request(
                 status.commit_lsn,
             )
         })
-        .unwrap();
+        .ok_or_else(|| {
+            ApiError::InternalServerError(anyhow::anyhow!(
+                "no donors: all responding safekeepers are witnesses"
+            ))
+        })?;
     let safekeeper_host = http_hosts[i].clone();
 
     assert!(status.tenant_id == request.tenant_id);
@@ -602,6 +702,7 @@ pub async fn handle_request(
         http_client,
         global_timelines,
         check_tombstone,
+        local_tli,
     )
     .await
     {
@@ -628,34 +729,293 @@ async fn pull_timeline(
     http_client: reqwest::Client,
     global_timelines: Arc<GlobalTimelines>,
     check_tombstone: bool,
+    local_tli: Option<Arc<Timeline>>,
 ) -> Result<PullTimelineResponse> {
     let ttid = TenantTimelineId::new(status.tenant_id, status.timeline_id);
     info!(
-        "pulling timeline {} from safekeeper {}, commit_lsn={}, flush_lsn={}, term={}, epoch={}",
+        "pulling timeline {} from safekeeper {}, commit_lsn={}, flush_lsn={}, term={}, epoch={}, incremental={}",
         ttid,
         host,
         status.commit_lsn,
         status.flush_lsn,
         status.acceptor_state.term,
-        status.acceptor_state.epoch
+        status.acceptor_state.epoch,
+        local_tli.is_some(),
     );
 
     let conf = &global_timelines.get_global_config();
 
     let (_tmp_dir, tli_dir_path) = create_temp_timeline_dir(conf, ttid).await?;
     let client = Client::new(http_client, host.clone(), sk_auth_token.clone());
-    // Request stream with basebackup archive.
-    let bb_resp = client
-        .snapshot(status.tenant_id, status.timeline_id, conf.my_id)
-        .await?;
 
+    // If we already have the timeline, reuse its WAL which is the same as
+    // donor's one.
+    let mut start = None;
+    if let Some(tli) = &local_tli {
+        match prepare_incremental_pull(tli, &status, &client, &tli_dir_path).await? {
+            IncrementalPull::UpToDate => {
+                info!("local timeline {} is not behind the donor", ttid);
+                return Ok(PullTimelineResponse {
+                    safekeeper_host: None,
+                });
+            }
+            IncrementalPull::From(from) => start = Some(from),
+            IncrementalPull::Full => {}
+        }
+    }
+
+    download_snapshot(&client, ttid, conf.my_id, &tli_dir_path, start).await?;
+
+    // fsync temp timeline directory to remember its contents.
+    fsync_async_opt(&tli_dir_path, !conf.no_sync).await?;
+
+    // Let's create timeline from temp directory and verify that it's correct
+    let (commit_lsn, flush_lsn) = validate_temp_timeline(conf, ttid, &tli_dir_path).await?;
+    info!(
+        "finished downloading timeline {}, commit_lsn={}, flush_lsn={}",
+        ttid, commit_lsn, flush_lsn
+    );
+    assert!(status.commit_lsn <= status.flush_lsn);
+
+    // Finally, load the timeline.
+    let _tli = match local_tli {
+        Some(tli) => {
+            global_timelines
+                .replace_timeline(tli, &tli_dir_path)
+                .await?
+        }
+        None => {
+            global_timelines
+                .load_temp_timeline(ttid, &tli_dir_path, check_tombstone)
+                .await?
+        }
+    };
+
+    Ok(PullTimelineResponse {
+        safekeeper_host: Some(host),
+    })
+}
+
+/// Result of comparing local timeline with the donor.
+enum IncrementalPull {
+    /// Local timeline is not behind the donor, nothing to pull.
+    UpToDate,
+    /// Local WAL before this point is the same as donor's and has been linked
+    /// into the temp timeline directory.
+    From(SnapshotStart),
+    /// Nothing can be reused, pull everything.
+    Full,
+}
+
+/// Find how much of local WAL can be reused when pulling from the donor, and
+/// hard link these segments into `tli_dir_path`.
+async fn prepare_incremental_pull(
+    tli: &Arc<Timeline>,
+    status: &TimelineStatus,
+    client: &Client,
+    tli_dir_path: &Utf8Path,
+) -> Result<IncrementalPull> {
+    let Some(tli) = tli.try_wal_residence_guard().await? else {
+        info!("local timeline is offloaded, pulling it fully");
+        return Ok(IncrementalPull::Full);
+    };
+
+    let (local_th, local_flush_lsn, local_last_log_term, timeline_start_lsn, wal_seg_size) = {
+        let shared_state = tli.read_shared_state().await;
+        let state = shared_state.sk.state();
+        (
+            state.acceptor_state.term_history.clone(),
+            shared_state.sk.flush_lsn(),
+            shared_state.sk.last_log_term(),
+            state.timeline_start_lsn,
+            shared_state.get_wal_seg_size(),
+        )
+    };
+    if (local_last_log_term, local_flush_lsn) >= (status.acceptor_state.epoch, status.flush_lsn) {
+        return Ok(IncrementalPull::UpToDate);
+    }
+
+    let donor_th = TermHistory(
+        status
+            .acceptor_state
+            .term_history
+            .iter()
+            .map(|e| TermLsn {
+                term: e.term,
+                lsn: e.lsn,
+            })
+            .collect(),
+    )
+    .up_to(status.flush_lsn);
+    let Some(common_point) = TermHistory::find_highest_common_point(
+        &donor_th,
+        &local_th.up_to(local_flush_lsn),
+        local_flush_lsn,
+    ) else {
+        info!("local WAL has nothing in common with the donor, pulling it fully");
+        return Ok(IncrementalPull::Full);
+    };
+    // Donor will send the segment with the common point and all after it.
+    // Also don't reuse segments after donor's commit_lsn: the pulled timeline
+    // looks for the end of WAL starting there, so these must be raw segments
+    // while local ones might be compressed.
+    let common_lsn = min(common_point.lsn, status.commit_lsn);
+    let start_segno = common_lsn.segment_number(wal_seg_size);
+    let start_lsn = common_lsn.segment_lsn(wal_seg_size);
+    let Some(term) = term_before(&donor_th, start_lsn) else {
+        info!("no complete local segments to reuse, pulling timeline fully");
+        return Ok(IncrementalPull::Full);
+    };
+
+    // Term history guarantees that WAL is the same, but compare the digest
+    // of the last reused segment anyway to catch corruption.
+    let digest_from = max(
+        start_lsn.saturating_sub(wal_seg_size as u64),
+        timeline_start_lsn,
+    );
+    let local_digest = debug_dump::calculate_digest(
+        &tli,
+        TimelineDigestRequest {
+            from_lsn: digest_from,
+            until_lsn: start_lsn,
+        },
+    )
+    .await;
+    let donor_digest = async {
+        client
+            .timeline_digest(status.tenant_id, status.timeline_id, digest_from, start_lsn)
+            .await?
+            .json::<TimelineDigest>()
+            .await
+            .map_err(mgmt_api::Error::ReceiveBody)
+    }
+    .await;
+    match (local_digest, donor_digest) {
+        (Ok(local), Ok(donor)) if local.sha256 == donor.sha256 => {}
+        (Ok(local), Ok(donor)) => {
+            warn!(
+                "WAL digest mismatch in [{}, {}): local {}, donor {}, pulling timeline fully",
+                digest_from, start_lsn, local.sha256, donor.sha256
+            );
+            return Ok(IncrementalPull::Full);
+        }
+        (local, donor) => {
+            warn!(
+                "failed to compare WAL digests in [{}, {}), pulling timeline fully: local {:?}, donor {:?}",
+                digest_from,
+                start_lsn,
+                local.err(),
+                donor.err()
+            );
+            return Ok(IncrementalPull::Full);
+        }
+    }
+
+    // Segments might be concurrently removed or compressed, hence also try
+    // compressed version if the raw one is gone.
+    let local_dir = tli.get_timeline_dir();
+    let mut n_linked = 0;
+    let mut entries = tokio::fs::read_dir(&local_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let fname = entry.file_name();
+        if !IsXLogFileName(&fname) && !is_compressed_segment_file_name(&fname) {
+            continue;
+        }
+        let (segno, _) = XLogFromFileName(&fname, wal_seg_size)?;
+        if segno >= start_segno {
+            continue;
+        }
+        let src = Utf8PathBuf::from_path_buf(entry.path()).expect("non-Unicode path");
+        let res =
+            match tokio::fs::hard_link(&src, tli_dir_path.join(src.file_name().unwrap())).await {
+                Err(e) if e.kind() == ErrorKind::NotFound && IsXLogFileName(&fname) => {
+                    let compressed = compressed_segment_path(&src);
+                    tokio::fs::hard_link(
+                        &compressed,
+                        tli_dir_path.join(compressed.file_name().unwrap()),
+                    )
+                    .await
+                }
+                res => res,
+            };
+        match res {
+            Ok(()) => n_linked += 1,
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if n_linked == 0 {
+        info!("no local segments to reuse, pulling timeline fully");
+        return Ok(IncrementalPull::Full);
+    }
+
+    let start = SnapshotStart {
+        lsn: start_lsn,
+        term,
+    };
+    info!(
+        "reusing {} local segments, pulling WAL from {:?}",
+        n_linked, start
+    );
+    Ok(IncrementalPull::From(start))
+}
+
+/// How many times snapshot download is attempted before giving up.
+const MAX_SNAPSHOT_ATTEMPTS: u64 = 5;
+
+/// Download snapshot from the donor into `tli_dir_path`, starting at `start`
+/// if given. If the transfer breaks, it is resumed from the first segment
+/// which wasn't fully received.
+async fn download_snapshot(
+    client: &Client,
+    ttid: TenantTimelineId,
+    my_id: NodeId,
+    tli_dir_path: &Utf8Path,
+    mut start: Option<SnapshotStart>,
+) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let res = match client
+            .snapshot(ttid.tenant_id, ttid.timeline_id, my_id, start)
+            .await
+        {
+            Ok(resp) => receive_snapshot(resp, tli_dir_path).await,
+            Err(mgmt_api::Error::ApiError(status, msg)) if status == StatusCode::CONFLICT => {
+                // Donor's history doesn't match WAL we have, start from
+                // scratch.
+                clear_dir(tli_dir_path).await?;
+                start = None;
+                Err(anyhow!("donor refused snapshot: {msg}"))
+            }
+            Err(e) => Err(e.into()),
+        };
+        let e = match res {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if attempt >= MAX_SNAPSHOT_ATTEMPTS {
+            return Err(e.context(format!("snapshot failed after {attempt} attempts")));
+        }
+        if let Some(resume) = snapshot_resume_point(tli_dir_path).await? {
+            start = Some(resume);
+        }
+        warn!(
+            "snapshot attempt {} failed: {:#}, retrying from {:?}",
+            attempt, e, start
+        );
+        sleep(std::time::Duration::from_secs(attempt)).await;
+    }
+}
+
+/// Extract snapshot tar archive on the fly to the disk. We don't use simple
+/// unpack() to fsync files.
+async fn receive_snapshot(resp: reqwest::Response, tli_dir_path: &Utf8Path) -> Result<()> {
     // Make Stream of Bytes from it...
-    let bb_stream = bb_resp.bytes_stream().map_err(std::io::Error::other);
+    let bb_stream = resp.bytes_stream().map_err(std::io::Error::other);
     // and turn it into StreamReader implementing AsyncRead.
     let bb_reader = tokio_util::io::StreamReader::new(bb_stream);
 
-    // Extract it on the fly to the disk. We don't use simple unpack() to fsync
-    // files.
     let mut entries = Archive::new(bb_reader).entries()?;
     while let Some(base_tar_entry) = entries.next().await {
         let mut entry = base_tar_entry?;
@@ -666,6 +1026,16 @@ async fn pull_timeline(
                 let utf8_file_path =
                     Utf8PathBuf::from_path_buf(file_path).expect("non-Unicode path");
                 let dst_path = tli_dir_path.join(utf8_file_path);
+                // A previous attempt might have left partial version of the
+                // segment which is complete now.
+                if IsXLogFileName(OsStr::new(dst_path.file_name().unwrap())) {
+                    let partial_path = Utf8PathBuf::from(format!("{dst_path}.partial"));
+                    if let Err(e) = tokio::fs::remove_file(&partial_path).await {
+                        if e.kind() != ErrorKind::NotFound {
+                            return Err(e.into());
+                        }
+                    }
+                }
                 let mut f = OpenOptions::new()
                     .create(true)
                     .truncate(true)
@@ -685,24 +1055,54 @@ async fn pull_timeline(
             }
         }
     }
+    Ok(())
+}
 
-    // fsync temp timeline directory to remember its contents.
-    fsync_async_opt(&tli_dir_path, !conf.no_sync).await?;
+/// Find where to resume interrupted snapshot download: after the last
+/// complete segment in the directory, in the history of the received control
+/// file. None if nothing useful has been received.
+async fn snapshot_resume_point(tli_dir_path: &Utf8Path) -> Result<Option<SnapshotStart>> {
+    let control_path = tli_dir_path.join(CONTROL_FILE_NAME);
+    if !tokio::fs::try_exists(&control_path).await? {
+        return Ok(None);
+    }
+    let state = FileStorage::load_control_file(&control_path)?;
+    let wal_seg_size = state.server.wal_seg_size as usize;
+    if wal_seg_size == 0 {
+        return Ok(None);
+    }
 
-    // Let's create timeline from temp directory and verify that it's correct
-    let (commit_lsn, flush_lsn) = validate_temp_timeline(conf, ttid, &tli_dir_path).await?;
-    info!(
-        "finished downloading timeline {}, commit_lsn={}, flush_lsn={}",
-        ttid, commit_lsn, flush_lsn
-    );
-    assert!(status.commit_lsn <= status.flush_lsn);
+    let mut last_segno = None;
+    let mut entries = tokio::fs::read_dir(tli_dir_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let fname = entry.file_name();
+        let complete = if IsXLogFileName(&fname) {
+            // Transfer might have been interrupted in the middle of the file.
+            entry.metadata().await?.len() == wal_seg_size as u64
+        } else {
+            is_compressed_segment_file_name(&fname)
+        };
+        if complete {
+            let (segno, _) = XLogFromFileName(&fname, wal_seg_size)?;
+            last_segno = max(last_segno, Some(segno));
+        }
+    }
+    let Some(last_segno) = last_segno else {
+        return Ok(None);
+    };
 
-    // Finally, load the timeline.
-    let _tli = global_timelines
-        .load_temp_timeline(ttid, &tli_dir_path, check_tombstone)
-        .await?;
+    let lsn = Lsn(XLogSegNoOffsetToRecPtr(last_segno + 1, 0, wal_seg_size));
+    Ok(
+        term_before(&state.acceptor_state.term_history, lsn)
+            .map(|term| SnapshotStart { lsn, term }),
+    )
+}
 
-    Ok(PullTimelineResponse {
-        safekeeper_host: Some(host),
-    })
+/// Remove everything received so far.
+async fn clear_dir(dir: &Utf8Path) -> Result<()> {
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        tokio::fs::remove_file(entry.path()).await?;
+    }
+    Ok(())
 }
@@ -465,13 +465,19 @@ async fn network_write<IO: AsyncRead + AsyncWrite + Unpin>(
 
     // storing append_response to inject PageserverFeedback into it
     let mut last_append_response = None;
+    // storing last PageserverFeedback to send it along with the WAL quota
+    // backpressure flag
+    let mut last_pageserver_feedback = None;
 
     loop {
         // trying to read either AcceptorProposerMessage or PageserverFeedback
         let msg = tokio::select! {
             reply = reply_rx.recv() => {
-                if let Some(msg) = reply {
-                    if let AcceptorProposerMessage::AppendResponse(append_response) = &msg {
+                if let Some(mut msg) = reply {
+                    if let AcceptorProposerMessage::AppendResponse(append_response) = &mut msg {
+                        if append_response.wal_backpressure {
+                            append_response.pageserver_feedback = last_pageserver_feedback;
+                        }
                         last_append_response = Some(append_response.clone());
                     }
                     Some(msg)
@@ -483,6 +489,7 @@ async fn network_write<IO: AsyncRead + AsyncWrite + Unpin>(
             feedback = pageserver_feedback_rx.recv() =>
                 match (feedback, &last_append_response) {
                     (Ok(feedback), Some(append_response)) => {
+                        last_pageserver_feedback = Some(feedback);
                         // clone AppendResponse and inject PageserverFeedback into it
                         let mut append_response = append_response.clone();
                         append_response.pageserver_feedback = Some(feedback);
@@ -66,9 +66,11 @@ pub async fn recovery_main(tli: WalResidentTimeline, conf: SafeKeeperConf) {
 /// Thus we don't try to predict it here.
 async fn recovery_needed(
     tli: &WalResidentTimeline,
+    my_id: NodeId,
     heartbeat_timeout: Duration,
 ) -> RecoveryNeededInfo {
     let ss = tli.read_shared_state().await;
+    let mconf = &ss.sk.state().mconf;
     let term = ss.sk.state().acceptor_state.term;
     let last_log_term = ss.sk.last_log_term();
     let flush_lsn = ss.sk.flush_lsn();
@@ -89,9 +91,13 @@ async fn recovery_needed(
     let num_streaming_computes = tli.get_walreceivers().get_num_streaming();
     let donors = if num_streaming_computes > 0 {
         vec![] // If there is a streaming compute, don't try to recover to not intervene.
+    } else if mconf.is_witness(my_id) {
+        vec![] // Witness doesn't store WAL, nothing to recover.
     } else {
         peers
             .iter()
+            // Witnesses don't store WAL and can't be donors.
+            .filter(|candidate| !mconf.is_witness(candidate.sk_id))
             .filter_map(|candidate| {
                 // Are we interested in this candidate?
                 let candidate_tl = TermLsn {
@@ -199,7 +205,7 @@ fn from(p: &PeerInfo) -> Self {
 async fn recovery_main_loop(tli: WalResidentTimeline, conf: SafeKeeperConf) {
     let check_duration = Duration::from_millis(CHECK_INTERVAL_MS);
     loop {
-        let recovery_needed_info = recovery_needed(&tli, conf.heartbeat_timeout).await;
+        let recovery_needed_info = recovery_needed(&tli, conf.my_id, conf.heartbeat_timeout).await;
         match recovery_needed_info.donors.first() {
             Some(donor) => {
                 info!(
@@ -492,7 +498,8 @@ async fn network_io(
             }
             ReplicationMessage::PrimaryKeepAlive(_) => {
                 // keepalive means nothing is being streamed for a while. Check whether we need to stop.
-                let recovery_needed_info = recovery_needed(&tli, conf.heartbeat_timeout).await;
+                let recovery_needed_info =
+                    recovery_needed(&tli, conf.my_id, conf.heartbeat_timeout).await;
                 // do current donors still contain one we currently connected to?
                 if !recovery_needed_info
                     .donors
@@ -30,6 +30,8 @@
 
 pub const SK_PROTO_VERSION_2: u32 = 2;
 pub const SK_PROTO_VERSION_3: u32 = 3;
+/// Same as 3, but membership configuration additionally carries witnesses.
+pub const SK_PROTO_VERSION_4: u32 = 4;
 pub const UNKNOWN_SERVER_VERSION: PgVersionId = PgVersionId::UNKNOWN;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
@@ -347,6 +349,9 @@ pub struct AppendResponse {
     pub commit_lsn: Lsn,
     pub hs_feedback: HotStandbyFeedback,
     pub pageserver_feedback: Option<PageserverFeedback>,
+    // Asks the compute to throttle WAL generation because we are close to a
+    // retained WAL quota. Sent within pageserver feedback.
+    pub wal_backpressure: bool,
 }
 
 impl AppendResponse {
@@ -358,8 +363,21 @@ fn term_only(generation: Generation, term: Term) -> AppendResponse {
             commit_lsn: Lsn(0),
             hs_feedback: HotStandbyFeedback::empty(),
             pageserver_feedback: None,
+            wal_backpressure: false,
         }
     }
+
+    /// Pageserver feedback to send, with our backpressure flag merged in.
+    fn feedback(&self) -> Option<PageserverFeedback> {
+        if !self.wal_backpressure {
+            return self.pageserver_feedback;
+        }
+        let mut feedback = self
+            .pageserver_feedback
+            .unwrap_or_else(PageserverFeedback::empty);
+        feedback.sk_wal_backpressure = true;
+        Some(feedback)
+    }
 }
 
 /// Proposer -> Acceptor messages
@@ -425,7 +443,7 @@ fn get_cstr(buf: &mut Bytes) -> Result<String> {
     }
 
     /// Read membership::Configuration from Bytes.
-    fn get_mconf(buf: &mut Bytes) -> Result<membership::Configuration> {
+    fn get_mconf(buf: &mut Bytes, proto_version: u32) -> Result<membership::Configuration> {
         let generation = Generation::new(buf.get_u32_f().with_context(|| "reading generation")?);
         let members_len = buf.get_u32_f().with_context(|| "reading members_len")?;
         // Main member set must have at least someone in valid configuration.
@@ -451,12 +469,8 @@ fn get_mconf(buf: &mut Bytes) -> Result<membership::Configuration> {
         }
         let new_members_len = buf.get_u32_f().with_context(|| "reading new_members_len")?;
         // Non joint conf.
-        if new_members_len == 0 {
-            Ok(membership::Configuration {
-                generation,
-                members,
-                new_members: None,
-            })
+        let new_members = if new_members_len == 0 {
+            None
         } else {
             let mut new_members = MemberSet::empty();
             for i in 0..new_members_len {
@@ -475,17 +489,31 @@ fn get_mconf(buf: &mut Bytes) -> Result<membership::Configuration> {
                 };
                 new_members.add(sk)?;
             }
-            Ok(membership::Configuration {
-                generation,
-                members,
-                new_members: Some(new_members),
-            })
+            Some(new_members)
+        };
+        let mut witnesses = Vec::new();
+        if proto_version >= SK_PROTO_VERSION_4 {
+            let witnesses_len = buf.get_u32_f().with_context(|| "reading witnesses_len")?;
+            for i in 0..witnesses_len {
+                let id = buf
+                    .get_u64_f()
+                    .with_context(|| format!("reading witness {i} node_id"))?;
+                witnesses.push(NodeId(id));
+            }
         }
+        let mconf = membership::Configuration {
+            generation,
+            members,
+            new_members,
+            witnesses,
+        };
+        mconf.validate()?;
+        Ok(mconf)
     }
 
     /// Parse proposer message.
     pub fn parse(mut msg_bytes: Bytes, proto_version: u32) -> Result<ProposerAcceptorMessage> {
-        if proto_version == SK_PROTO_VERSION_3 {
+        if proto_version == SK_PROTO_VERSION_3 || proto_version == SK_PROTO_VERSION_4 {
             if msg_bytes.is_empty() {
                 bail!("ProposerAcceptorMessage is not complete: missing tag");
             }
@@ -500,7 +528,7 @@ pub fn parse(mut msg_bytes: Bytes, proto_version: u32) -> Result<ProposerAccepto
                     let timeline_id_str =
                         Self::get_cstr(&mut msg_bytes).with_context(|| "reading timeline_id")?;
                     let timeline_id = TimelineId::from_str(&timeline_id_str)?;
-                    let mconf = Self::get_mconf(&mut msg_bytes)?;
+                    let mconf = Self::get_mconf(&mut msg_bytes, proto_version)?;
                     let pg_version = msg_bytes
                         .get_u32_f()
                         .with_context(|| "reading pg_version")?;
@@ -615,11 +643,7 @@ pub fn parse(mut msg_bytes: Bytes, proto_version: u32) -> Result<ProposerAccepto
                     let g = ProposerGreeting {
                         tenant_id: msgv2.tenant_id,
                         timeline_id: msgv2.timeline_id,
-                        mconf: membership::Configuration {
-                            generation: INVALID_GENERATION,
-                            members: MemberSet::empty(),
-                            new_members: None,
-                        },
+                        mconf: membership::Configuration::empty(),
                         pg_version: msgv2.pg_version,
                         system_id: msgv2.system_id,
                         wal_seg_size: msgv2.wal_seg_size,
@@ -756,7 +780,11 @@ fn put_cstr(buf: &mut BytesMut, s: &str) {
     }
 
     /// Serialize membership::Configuration into buf.
-    fn serialize_mconf(buf: &mut BytesMut, mconf: &membership::Configuration) {
+    fn serialize_mconf(
+        buf: &mut BytesMut,
+        mconf: &membership::Configuration,
+        proto_version: u32,
+    ) -> Result<()> {
         buf.put_u32(mconf.generation.into_inner());
         buf.put_u32(mconf.members.m.len() as u32);
         for sk in &mconf.members.m {
@@ -774,16 +802,30 @@ fn serialize_mconf(buf: &mut BytesMut, mconf: &membership::Configuration) {
         } else {
             buf.put_u32(0);
         }
+        if proto_version >= SK_PROTO_VERSION_4 {
+            buf.put_u32(mconf.witnesses.len() as u32);
+            for id in &mconf.witnesses {
+                buf.put_u64(id.0);
+            }
+        } else if !mconf.witnesses.is_empty() {
+            // Walproposer unaware of witnesses could pick one as a donor.
+            bail!(
+                "configuration {} has witnesses, which requires protocol version {}",
+                mconf,
+                SK_PROTO_VERSION_4
+            );
+        }
+        Ok(())
     }
 
     /// Serialize acceptor -> proposer message.
     pub fn serialize(&self, buf: &mut BytesMut, proto_version: u32) -> Result<()> {
-        if proto_version == SK_PROTO_VERSION_3 {
+        if proto_version == SK_PROTO_VERSION_3 || proto_version == SK_PROTO_VERSION_4 {
             match self {
                 AcceptorProposerMessage::Greeting(msg) => {
                     buf.put_u8(b'g');
                     buf.put_u64(msg.node_id.0);
-                    Self::serialize_mconf(buf, &msg.mconf);
+                    Self::serialize_mconf(buf, &msg.mconf, proto_version)?;
                     buf.put_u64(msg.term)
                 }
                 AcceptorProposerMessage::VoteResponse(msg) => {
@@ -811,8 +853,8 @@ pub fn serialize(&self, buf: &mut BytesMut, proto_version: u32) -> Result<()> {
 
                     // AsyncReadMessage in walproposer.c will not try to decode pageserver_feedback
                     // if it is not present.
-                    if let Some(ref msg) = msg.pageserver_feedback {
-                        msg.serialize(buf);
+                    if let Some(feedback) = msg.feedback() {
+                        feedback.serialize(buf);
                     }
                 }
             }
@@ -853,8 +895,8 @@ pub fn serialize(&self, buf: &mut BytesMut, proto_version: u32) -> Result<()> {
 
                     // AsyncReadMessage in walproposer.c will not try to decode pageserver_feedback
                     // if it is not present.
-                    if let Some(ref msg) = msg.pageserver_feedback {
-                        msg.serialize(buf);
+                    if let Some(feedback) = msg.feedback() {
+                        feedback.serialize(buf);
                     }
                 }
             }
@@ -1027,7 +1069,9 @@ async fn handle_greeting(
         }
 
         // Switch into conf given by proposer conf if it is higher.
-        self.state.membership_switch(msg.mconf.clone()).await?;
+        self.state
+            .membership_switch(msg.mconf.clone(), self.node_id)
+            .await?;
 
         let apg = AcceptorGreeting {
             node_id: self.node_id,
@@ -1091,6 +1135,7 @@ fn append_response(&self) -> AppendResponse {
             // will be filled by the upper code to avoid bothering safekeeper
             hs_feedback: HotStandbyFeedback::empty(),
             pageserver_feedback: None,
+            wal_backpressure: false,
         };
         trace!("formed AppendResponse {:?}", ar);
         ar
@@ -1754,6 +1799,7 @@ fn test_sk_state_bincode_serde_roundtrip() {
                 }])
                 .expect("duplicate member"),
                 new_members: None,
+                witnesses: Vec::new(),
             },
             acceptor_state: AcceptorState {
                 term: 42,
@@ -1781,6 +1827,7 @@ fn test_sk_state_bincode_serde_roundtrip() {
             partial_backup: crate::wal_backup_partial::State::default(),
             eviction_state: EvictionState::Present,
             creation_ts: UNIX_EPOCH,
+            wal_archive: crate::wal_archive::State::default(),
         };
 
         let ser = state.ser().unwrap();
@@ -1789,4 +1836,40 @@ fn test_sk_state_bincode_serde_roundtrip() {
 
         assert_eq!(deser, state);
     }
+
+    #[test]
+    fn test_mconf_witnesses_wire() {
+        let mut mconf = Configuration::new(
+            MemberSet::new(
+                (1..=3)
+                    .map(|id| SafekeeperId {
+                        id: NodeId(id),
+                        host: format!("sk-{id}.org"),
+                        pg_port: 5432,
+                    })
+                    .collect(),
+            )
+            .unwrap(),
+        );
+        mconf.witnesses = vec![NodeId(3)];
+        let greeting = AcceptorProposerMessage::Greeting(AcceptorGreeting {
+            node_id: NodeId(1),
+            mconf: mconf.clone(),
+            term: 42,
+        });
+
+        // Walproposer speaking v3 doesn't know about witnesses.
+        let mut buf = BytesMut::new();
+        greeting
+            .serialize(&mut buf, SK_PROTO_VERSION_3)
+            .expect_err("witnesses must not be sent in v3");
+
+        let mut buf = BytesMut::new();
+        greeting.serialize(&mut buf, SK_PROTO_VERSION_4).unwrap();
+        let mut bytes = buf.freeze();
+        bytes.advance(1 + 8); // tag and node_id
+        let parsed = ProposerAcceptorMessage::get_mconf(&mut bytes, SK_PROTO_VERSION_4).unwrap();
+        assert_eq!(parsed, mconf);
+        assert_eq!(bytes.get_u64(), 42);
+    }
 }
@@ -0,0 +1,284 @@
+//! Streaming of row-level change events decoded from WAL to change data
+//! capture subscribers, see [`wal_decoder::change_events`].
+//!
+//! Subscribers connect with the change events protocol and START_REPLICATION
+//! at a record boundary, typically `next_record_lsn` of the last batch they
+//! processed. Committed WAL is streamed regardless of whether a compute is
+//! running, so the stream survives compute suspends.
+
+use std::time::Duration;
+
+use anyhow::{Context, anyhow};
+use futures::StreamExt;
+use postgres_backend::{CopyStreamHandlerEnd, PostgresBackend};
+use postgres_ffi::waldecoder::WalStreamDecoder;
+use postgres_ffi::{PgMajorVersion, get_current_timestamp};
+use pq_proto::{BeMessage, ChangeEventsBody, WalSndKeepAlive};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::MissedTickBehavior;
+use utils::lsn::Lsn;
+use utils::postgres_client::Compression;
+use wal_decoder::change_events::{ChangeEvent, ChangeEvents, RelationOids};
+
+use crate::metrics::CHANGE_EVENTS_SENT;
+use crate::send_wal::EndWatchView;
+use crate::wal_reader_stream::{StreamingWalReader, WalBytes};
+
+pub(crate) struct ChangeEventSender<'a, IO> {
+    pub(crate) compression: Option<Compression>,
+    pub(crate) appname: Option<String>,
+    pub(crate) pg_version: PgMajorVersion,
+    pub(crate) start_lsn: Lsn,
+
+    pub(crate) pgb: &'a mut PostgresBackend<IO>,
+    pub(crate) end_watch_view: EndWatchView,
+    pub(crate) wal_stream: StreamingWalReader,
+}
+
+impl<IO: AsyncRead + AsyncWrite + Unpin> ChangeEventSender<'_, IO> {
+    /// Decode WAL into change events and send them over the network, one
+    /// batch per chunk of WAL read. Also sends keep-alives if nothing was
+    /// sent for a while.
+    pub(crate) async fn run(mut self) -> Result<(), CopyStreamHandlerEnd> {
+        let mut keepalive_ticker = tokio::time::interval(Duration::from_secs(1));
+        keepalive_ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        keepalive_ticker.reset();
+
+        let mut wal_decoder = WalStreamDecoder::new(self.start_lsn, self.pg_version);
+        let mut relations = RelationOids::default();
+
+        loop {
+            tokio::select! {
+                wal = self.wal_stream.next() => {
+                    let WalBytes {
+                        wal,
+                        wal_end_lsn,
+                        available_wal_end_lsn,
+                        ..
+                    } = match wal.and_then(|wor| wor.get_wal()) {
+                        Some(wal) => wal?,
+                        None => {
+                            return Err(CopyStreamHandlerEnd::Other(anyhow!(
+                                "WAL stream for {:?} closed unexpectedly",
+                                self.appname
+                            )));
+                        }
+                    };
+
+                    wal_decoder.feed_bytes(&wal);
+
+                    let mut events = Vec::new();
+                    let mut next_record_lsn = None;
+                    while let Some((lsn, recdata)) = wal_decoder
+                        .poll_decode()
+                        .map_err(|e| anyhow!("failed to decode WAL: {e:?}"))?
+                    {
+                        ChangeEvent::decode(
+                            recdata,
+                            lsn,
+                            self.pg_version,
+                            &mut relations,
+                            &mut events,
+                        )
+                            .with_context(|| format!("failed to decode change events at {lsn}"))?;
+                        next_record_lsn = Some(lsn);
+                    }
+
+                    // Nothing to report until a record is complete.
+                    let Some(next_record_lsn) = next_record_lsn else {
+                        continue;
+                    };
+
+                    CHANGE_EVENTS_SENT.inc_by(events.len() as u64);
+                    let buf = ChangeEvents {
+                        events,
+                        next_record_lsn,
+                    }
+                    .to_wire(self.compression)
+                    .await
+                    .with_context(|| "failed to serialize change events")?;
+
+                    keepalive_ticker.reset();
+
+                    self.pgb
+                        .write_message(&BeMessage::ChangeEvents(ChangeEventsBody {
+                            streaming_lsn: wal_end_lsn.0,
+                            commit_lsn: available_wal_end_lsn.0,
+                            data: &buf,
+                        }))
+                        .await?;
+                }
+                _ = keepalive_ticker.tick() => {
+                    self.pgb
+                        .write_message(&BeMessage::KeepAlive(WalSndKeepAlive {
+                            wal_end: self.end_watch_view.get().0,
+                            timestamp: get_current_timestamp(),
+                            request_reply: true,
+                        }))
+                        .await?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use async_compression::tokio::bufread::ZstdDecoder;
+    use bytes::{Buf, Bytes};
+    use postgres_backend::{AuthType, PostgresBackend};
+    use postgres_ffi::{MAX_SEND_SIZE, PgMajorVersion, WAL_SEGMENT_SIZE};
+    use tokio::io::{AsyncReadExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+    use utils::id::{NodeId, TenantTimelineId};
+    use utils::lsn::Lsn;
+    use utils::postgres_client::Compression;
+    use wal_decoder::change_events::{Change, ChangeEvent, ChangeEvents};
+
+    use super::ChangeEventSender;
+    use crate::test_utils::Env;
+    use crate::wal_reader_stream::StreamingWalReader;
+
+    /// Read CopyData messages of the change events protocol from the client
+    /// side of the connection until a batch ending at `end_lsn`.
+    async fn receive_events(
+        client: &mut TcpStream,
+        compression: Option<Compression>,
+        end_lsn: Lsn,
+    ) -> Vec<ChangeEvent> {
+        let mut events = Vec::new();
+        let mut keepalives = 0;
+        loop {
+            let tag = client.read_u8().await.unwrap();
+            let len = client.read_u32().await.unwrap() as usize;
+            let mut body = vec![0; len - 4];
+            client.read_exact(&mut body).await.unwrap();
+            assert_eq!(tag, b'd', "expected CopyData");
+            let mut body = Bytes::from(body);
+            match body.get_u8() {
+                b'e' => {
+                    let streaming_lsn = Lsn(body.get_u64());
+                    let commit_lsn = Lsn(body.get_u64());
+                    assert!(streaming_lsn <= commit_lsn);
+                    let batch = ChangeEvents::from_wire(&body, compression).await.unwrap();
+                    assert!(batch.next_record_lsn <= streaming_lsn);
+                    events.extend(batch.events);
+                    if batch.next_record_lsn == end_lsn {
+                        return events;
+                    }
+                }
+                b'k' => {
+                    keepalives += 1;
+                    assert!(
+                        keepalives < 30,
+                        "no change events after {keepalives} keepalives"
+                    );
+                }
+                tag => panic!("unexpected message {tag}"),
+            }
+        }
+    }
+
+    /// Commit the beginning of pgbench initialization WAL shared with
+    /// pageserver tests to a timeline and stream it as change events over a
+    /// real connection.
+    #[tokio::test]
+    async fn test_change_events_stream() {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        const PGBENCH_ACCOUNTS: u32 = 16390;
+        const PGBENCH_ACCOUNTS_RELFILENODE: u32 = 16396;
+        let start_lsn = Lsn::from_hex("14AEC08").unwrap();
+        // End of the last complete record before 0/1500000.
+        let end_lsn = Lsn::from_hex("14FF190").unwrap();
+
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../pageserver/test_data/sk_wal_segment_from_pgbench/000000010000000000000001.zst"
+        );
+        let compressed = tokio::fs::read(path).await.unwrap();
+        let mut segment = Vec::new();
+        ZstdDecoder::new(BufReader::new(&compressed[..]))
+            .read_to_end(&mut segment)
+            .await
+            .unwrap();
+        let wal = &segment
+            [start_lsn.segment_offset(WAL_SEGMENT_SIZE)..end_lsn.segment_offset(WAL_SEGMENT_SIZE)];
+
+        let mut env = Env::new(false).unwrap();
+        env.pg_version = PgMajorVersion::PG15;
+        let tli = env
+            .make_timeline(NodeId(1), TenantTimelineId::generate(), start_lsn)
+            .await
+            .unwrap();
+        let end_watch = Env::write_raw_wal(tli.clone(), start_lsn, wal)
+            .await
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let compression = Some(Compression::Zstd { level: 1 });
+        let resident_tli = tli.wal_residence_guard().await.unwrap();
+        let sender = tokio::spawn(async move {
+            let mut pgb = PostgresBackend::new(socket, AuthType::Trust, None).unwrap();
+            let end_watch_view = end_watch.view();
+            let end_pos = end_watch.get();
+            let wal_stream = StreamingWalReader::new(
+                resident_tli,
+                None,
+                start_lsn,
+                end_pos,
+                end_watch,
+                MAX_SEND_SIZE,
+            );
+            ChangeEventSender {
+                compression,
+                appname: None,
+                pg_version: PgMajorVersion::PG15,
+                start_lsn,
+                pgb: &mut pgb,
+                end_watch_view,
+                wal_stream,
+            }
+            .run()
+            .await
+        });
+
+        let events = receive_events(&mut client, compression, end_lsn).await;
+        // Committed table creations, the truncation of the tables and the
+        // first rows of pgbench_accounts in the uncommitted transaction.
+        assert_eq!(events.len(), 1159);
+        let commits = events
+            .iter()
+            .filter(|event| matches!(event.change, Change::Commit { .. }))
+            .count();
+        assert_eq!(commits, 4);
+        let mut inserts = HashMap::new();
+        for event in &events {
+            if let Change::Insert { rel, tuple } = &event.change {
+                assert!(event.lsn <= end_lsn);
+                *inserts.entry((rel.reloid, rel.relnode)).or_insert(0) += 1;
+                if rel.reloid == PGBENCH_ACCOUNTS {
+                    assert_eq!(event.xid, 1028);
+                    assert!(tuple.is_some());
+                }
+            }
+        }
+        assert_eq!(
+            inserts[&(PGBENCH_ACCOUNTS, PGBENCH_ACCOUNTS_RELFILENODE)],
+            976
+        );
+
+        // The stream stays open waiting for more WAL until the subscriber
+        // goes away.
+        assert!(!sender.is_finished());
+        drop(client);
+        assert!(sender.await.unwrap().is_err());
+    }
+}
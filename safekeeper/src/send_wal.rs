@@ -34,6 +34,7 @@
 use crate::metrics::{RECEIVED_PS_FEEDBACKS, WAL_READERS};
 use crate::receive_wal::WalReceivers;
 use crate::safekeeper::TermLsn;
+use crate::send_change_events::ChangeEventSender;
 use crate::send_interpreted_wal::{
     Batch, InterpretedWalReader, InterpretedWalReaderHandle, InterpretedWalSender,
 };
@@ -450,6 +451,13 @@ pub async fn handle_start_replication<IO: AsyncRead + AsyncWrite + Unpin + Send>
             .global_timelines
             .get(self.ttid)
             .map_err(|e| QueryError::Other(e.into()))?;
+        if tli.is_witness().await {
+            return Err(QueryError::Other(anyhow::anyhow!(
+                "safekeeper {} is a witness of timeline {} and doesn't store WAL",
+                self.conf.my_id,
+                self.ttid
+            )));
+        }
         let residence_guard = tli.wal_residence_guard().await?;
 
         if let Err(end) = self
@@ -476,15 +484,17 @@ pub async fn handle_start_replication_guts<IO: AsyncRead + AsyncWrite + Unpin +
 
         // Use a guard object to remove our entry from the timeline when we are done.
         let ws_guard = match self.protocol() {
-            PostgresClientProtocol::Vanilla => Arc::new(tli.get_walsenders().register(
-                WalSenderState::Vanilla(VanillaWalSenderInternalState {
-                    ttid: self.ttid,
-                    addr: *pgb.get_peer_addr(),
-                    conn_id: self.conn_id,
-                    appname: self.appname.clone(),
-                    feedback: ReplicationFeedback::Pageserver(PageserverFeedback::empty()),
-                }),
-            )),
+            PostgresClientProtocol::Vanilla | PostgresClientProtocol::ChangeEvents { .. } => {
+                Arc::new(tli.get_walsenders().register(WalSenderState::Vanilla(
+                    VanillaWalSenderInternalState {
+                        ttid: self.ttid,
+                        addr: *pgb.get_peer_addr(),
+                        conn_id: self.conn_id,
+                        appname: self.appname.clone(),
+                        feedback: ReplicationFeedback::Pageserver(PageserverFeedback::empty()),
+                    },
+                )))
+            }
             PostgresClientProtocol::Interpreted { .. } => Arc::new(tli.get_walsenders().register(
                 WalSenderState::Interpreted(InterpretedWalSenderInternalState {
                     public_state: safekeeper_api::models::InterpretedWalSenderState {
@@ -529,11 +539,13 @@ pub async fn handle_start_replication_guts<IO: AsyncRead + AsyncWrite + Unpin +
             self.protocol(),
         );
 
+        // Check that we can stream from start_pos before switching to copy, so
+        // that e.g. a request for a segment we don't have gets a plain error.
+        let wal_reader = tli.get_walreader(start_pos).await?;
+
         // switch to copy
         pgb.write_message(&BeMessage::CopyBothResponse).await?;
 
-        let wal_reader = tli.get_walreader(start_pos).await?;
-
         // Split to concurrently receive and send data; replies are generally
         // not synchronized with sends, so this avoids deadlocks.
         let reader = pgb.split().context("START_REPLICATION split")?;
@@ -556,6 +568,31 @@ pub async fn handle_start_replication_guts<IO: AsyncRead + AsyncWrite + Unpin +
 
                 FutureExt::boxed(sender.run())
             }
+            PostgresClientProtocol::ChangeEvents { compression } => {
+                let pg_version =
+                    PgMajorVersion::try_from(tli.tli.get_state().await.1.server.pg_version)
+                        .unwrap();
+                let end_watch_view = end_watch.view();
+                let wal_stream = StreamingWalReader::new(
+                    tli.wal_residence_guard().await?,
+                    term,
+                    start_pos,
+                    end_pos,
+                    end_watch,
+                    MAX_SEND_SIZE,
+                );
+                let sender = ChangeEventSender {
+                    compression,
+                    appname: appname.clone(),
+                    pg_version,
+                    start_lsn: start_pos,
+                    pgb,
+                    end_watch_view,
+                    wal_stream,
+                };
+
+                FutureExt::boxed(sender.run())
+            }
             PostgresClientProtocol::Interpreted {
                 format,
                 compression,
@@ -1038,10 +1075,20 @@ async fn handle_feedback(&mut self, msg: &Bytes) -> anyhow::Result<()> {
 
 #[cfg(test)]
 mod tests {
+    use std::ffi::CStr;
+    use std::str::FromStr;
+
+    use postgres_ffi::v17::bindings::{XLOG_PAGE_MAGIC, XLogLongPageHeaderData};
+    use postgres_ffi::v17::wal_generator::{LogicalMessageGenerator, WalGenerator};
+    use postgres_ffi::{PG_TLI, WAL_SEGMENT_SIZE, XLogFileName};
+    use remote_storage::{GenericRemoteStorage, RemoteStorageConfig, RemoteStorageKind};
     use safekeeper_api::models::FullTransactionId;
-    use utils::id::{TenantId, TenantTimelineId, TimelineId};
+    use tokio_util::sync::CancellationToken;
+    use utils::id::{NodeId, TenantId, TenantTimelineId, TimelineId};
 
     use super::*;
+    use crate::test_utils::Env;
+    use crate::wal_backup::remote_timeline_path;
 
     fn mock_ttid() -> TenantTimelineId {
         TenantTimelineId {
@@ -1100,4 +1147,173 @@ fn test_hs_feedback() {
         wss.update_reply_feedback();
         assert_eq!(wss.agg_standby_feedback.hs_feedback.xmin, 42);
     }
+
+    /// Reads WAL in [start, end) like START_REPLICATION does.
+    async fn read_wal(tli: &WalResidentTimeline, start: Lsn, end: Lsn) -> anyhow::Result<Vec<u8>> {
+        let mut reader = tli.get_walreader(start).await?;
+        let mut wal = vec![0; (end.0 - start.0) as usize];
+        let mut pos = 0;
+        while pos < wal.len() {
+            pos += reader.read(&mut wal[pos..]).await?;
+        }
+        Ok(wal)
+    }
+
+    /// WAL written by [`Env::write_wal`] with the same arguments.
+    fn generate_wal(start_lsn: Lsn, msg_size: usize, msg_count: usize, prefix: &CStr) -> Vec<u8> {
+        let message = vec![0; msg_size - prefix.to_bytes_with_nul().len()];
+        WalGenerator::new(LogicalMessageGenerator::new(prefix, &message), start_lsn)
+            .take(msg_count)
+            .flat_map(|(_, record)| record)
+            .collect()
+    }
+
+    fn assert_segment_header(wal: &[u8], segment_start: Lsn) {
+        let hdr = XLogLongPageHeaderData::from_bytes(&mut &wal[..]).unwrap();
+        assert_eq!(hdr.std.xlp_magic, XLOG_PAGE_MAGIC as u16);
+        assert_eq!(hdr.std.xlp_pageaddr, segment_start.0);
+        assert_eq!(hdr.xlp_seg_size, WAL_SEGMENT_SIZE as u32);
+    }
+
+    // Vanilla replication clients like pg_receivewal stream whole segments, so
+    // START_REPLICATION may point at any segment boundary, including the start
+    // of the first segment, before the timeline starts.
+    #[tokio::test]
+    async fn test_start_replication_at_segment_boundaries() {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        const SIZE: usize = 32 * 1024;
+        const MSG_COUNT: usize = 1200;
+        const PREFIX: &CStr = c"neon-file:";
+
+        let start_lsn = Lsn::from_str("0/149FD18").unwrap();
+        let env = Env::new(false).unwrap();
+        let tli = env
+            .make_timeline(NodeId(1), TenantTimelineId::generate(), start_lsn)
+            .await
+            .unwrap();
+        let resident_tli = tli.wal_residence_guard().await.unwrap();
+        let end_pos = Env::write_wal(tli, start_lsn, SIZE, MSG_COUNT, PREFIX, None)
+            .await
+            .unwrap()
+            .get();
+        assert!(end_pos > Lsn(3 * WAL_SEGMENT_SIZE as u64));
+        let wal = generate_wal(start_lsn, SIZE, MSG_COUNT, PREFIX);
+
+        // The first segment begins with a made up page header, the timeline's
+        // WAL follows.
+        let segment_start = start_lsn.segment_lsn(WAL_SEGMENT_SIZE);
+        let got = read_wal(&resident_tli, segment_start, end_pos)
+            .await
+            .unwrap();
+        assert_segment_header(&got, segment_start);
+        let tl_start_off = start_lsn.segment_offset(WAL_SEGMENT_SIZE);
+        assert_eq!(got[tl_start_off..], wal[..got.len() - tl_start_off]);
+
+        for segno in 2..=3 {
+            let segment_start = Lsn(segno * WAL_SEGMENT_SIZE as u64);
+            let got = read_wal(&resident_tli, segment_start, end_pos)
+                .await
+                .unwrap();
+            let off = (segment_start.0 - start_lsn.0) as usize;
+            assert_eq!(got, wal[off..off + got.len()]);
+        }
+
+        // Segments before the timeline start are reported like Postgres does
+        // for removed ones.
+        let err = read_wal(&resident_tli, Lsn(0), end_pos).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "requested WAL segment 000000010000000000000000 has already been removed"
+        );
+    }
+
+    // A safekeeper which joined the timeline later has WAL only since
+    // local_start_lsn, the rest of the segment comes from remote storage.
+    #[tokio::test]
+    async fn test_start_replication_before_local_start() {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        const SIZE: usize = 8 * 1024;
+        const MSG_COUNT: usize = 100;
+        const PREFIX: &CStr = c"neon-file:";
+
+        let timeline_start_lsn = Lsn::from_str("0/1000028").unwrap();
+        let local_start_lsn = Lsn::from_str("0/149FD18").unwrap();
+        let segment_start = Lsn::from_str("0/1000000").unwrap();
+
+        let mut env = Env::new(false).unwrap();
+        let remote_fs_dir = env.tempdir.path().join("remote");
+        std::fs::create_dir_all(&remote_fs_dir).unwrap();
+        let remote_storage = RemoteStorageConfig {
+            storage: RemoteStorageKind::LocalFs {
+                local_path: remote_fs_dir,
+            },
+            timeout: RemoteStorageConfig::DEFAULT_TIMEOUT,
+            small_timeout: RemoteStorageConfig::DEFAULT_SMALL_TIMEOUT,
+        };
+        env.remote_storage = Some(remote_storage.clone());
+
+        let ttid = TenantTimelineId::generate();
+        let tli = env
+            .make_timeline(NodeId(1), ttid, local_start_lsn)
+            .await
+            .unwrap();
+        let resident_tli = tli.wal_residence_guard().await.unwrap();
+        // Stay within the first segment, so that WAL backup doesn't upload it.
+        let end_pos = Env::write_wal(tli.clone(), local_start_lsn, SIZE, MSG_COUNT, PREFIX, None)
+            .await
+            .unwrap()
+            .get();
+        assert!(end_pos < Lsn(2 * WAL_SEGMENT_SIZE as u64));
+        let wal = generate_wal(local_start_lsn, SIZE, MSG_COUNT, PREFIX);
+
+        // Pretend the timeline started before this safekeeper got its WAL.
+        {
+            let mut shared = tli.write_shared_state().await;
+            let mut pstate = shared.sk.state_mut().start_change();
+            pstate.timeline_start_lsn = timeline_start_lsn;
+            shared.sk.state_mut().finish_change(&pstate).await.unwrap();
+        }
+
+        let err = read_wal(&resident_tli, segment_start, end_pos)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "requested WAL segment 000000010000000000000001 has already been removed"
+        );
+
+        // Once the segment is offloaded, WAL before local_start_lsn is read
+        // from remote storage.
+        let local_start_off = local_start_lsn.segment_offset(WAL_SEGMENT_SIZE);
+        let mut segment = vec![0xAB; local_start_off];
+        segment.extend_from_slice(&wal);
+        segment.resize(WAL_SEGMENT_SIZE, 0);
+        let segment = Bytes::from(segment);
+        let storage = GenericRemoteStorage::from_config(&remote_storage)
+            .await
+            .unwrap();
+        let remote_path =
+            remote_timeline_path(&ttid)
+                .unwrap()
+                .join(XLogFileName(PG_TLI, 1, WAL_SEGMENT_SIZE));
+        storage
+            .upload_storage_object(
+                futures::stream::once(futures::future::ready(Ok(segment.clone()))),
+                segment.len(),
+                &remote_path,
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+        let got = read_wal(&resident_tli, segment_start, end_pos)
+            .await
+            .unwrap();
+        assert_segment_header(&got, segment_start);
+        let tl_start_off = timeline_start_lsn.segment_offset(WAL_SEGMENT_SIZE);
+        assert_eq!(got[tl_start_off..], segment[tl_start_off..got.len()]);
+        assert_eq!(got[local_start_off..], wal[..got.len() - local_start_off]);
+    }
 }
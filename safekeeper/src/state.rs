@@ -13,12 +13,13 @@
 use safekeeper_api::{INITIAL_TERM, ServerInfo, Term};
 use serde::{Deserialize, Serialize};
 use tracing::info;
-use utils::id::{TenantId, TenantTimelineId, TimelineId};
+use utils::id::{NodeId, TenantId, TenantTimelineId, TimelineId};
 use utils::lsn::Lsn;
 
 use crate::control_file;
 use crate::safekeeper::{AcceptorState, PgUuid, TermHistory, TermLsn, UNKNOWN_SERVER_VERSION};
 use crate::timeline::TimelineError;
+use crate::wal_archive;
 use crate::wal_backup_partial::{self};
 
 /// Persistent information stored on safekeeper node about timeline.
@@ -69,6 +70,8 @@ pub struct TimelinePersistentState {
     /// WAL files from remote storage to serve the timeline.
     pub eviction_state: EvictionState,
     pub creation_ts: SystemTime,
+    /// Whether and how far WAL of the timeline is exported to the WAL archive.
+    pub wal_archive: wal_archive::State,
 }
 
 /// State of the local WAL files. Used to track current timeline state,
@@ -147,6 +150,7 @@ pub fn new(
             partial_backup: wal_backup_partial::State::default(),
             eviction_state: EvictionState::Present,
             creation_ts: SystemTime::now(),
+            wal_archive: wal_archive::State::default(),
         })
     }
 
@@ -266,7 +270,14 @@ pub async fn term_bump(&mut self, to: Option<Term>) -> Result<TimelineTermBumpRe
 
     /// Switch into membership configuration `to` if it is higher than the
     /// current one.
-    pub async fn membership_switch(&mut self, to: Configuration) -> Result<MembershipSwitchResult> {
+    /// Switch to the given configuration if it is newer. `my_id` is id of
+    /// this safekeeper: switch must not change its witness role, as WAL
+    /// storage mode is fixed when the timeline is loaded.
+    pub async fn membership_switch(
+        &mut self,
+        to: Configuration,
+        my_id: NodeId,
+    ) -> Result<MembershipSwitchResult> {
         let before = self.mconf.clone();
         // Is switch allowed?
         if to.generation <= self.mconf.generation {
@@ -274,6 +285,13 @@ pub async fn membership_switch(&mut self, to: Configuration) -> Result<Membershi
                 "ignoring request to switch membership conf to {}, current conf {}",
                 to, self.mconf
             );
+        } else if to.is_witness(my_id) != self.mconf.is_witness(my_id) {
+            bail!(
+                "switch to membership conf {} from {} changes witness role of sk {}",
+                to,
+                self.mconf,
+                my_id
+            );
         } else {
             let mut state = self.start_change();
             state.mconf = to.clone();
@@ -1,8 +1,12 @@
 use std::ffi::CStr;
 use std::sync::Arc;
 
+use bytes::Bytes;
 use camino_tempfile::Utf8TempDir;
 use postgres_ffi::v17::wal_generator::{LogicalMessageGenerator, WalGenerator};
+use postgres_ffi::{MAX_SEND_SIZE, PgMajorVersion};
+use postgres_versioninfo::PgVersionId;
+use remote_storage::RemoteStorageConfig;
 use safekeeper_api::membership::SafekeeperGeneration as Generation;
 use tokio::fs::create_dir_all;
 use utils::id::{NodeId, TenantTimelineId};
@@ -27,6 +31,10 @@ pub struct Env {
     pub fsync: bool,
     /// Benchmark directory. Deleted when dropped.
     pub tempdir: Utf8TempDir,
+    /// Postgres version of timelines, determines the WAL format.
+    pub pg_version: PgMajorVersion,
+    /// Remote storage of Safekeepers, none by default.
+    pub remote_storage: Option<RemoteStorageConfig>,
 }
 
 impl Env {
@@ -34,7 +42,12 @@ impl Env {
     /// enable fsyncing.
     pub fn new(fsync: bool) -> anyhow::Result<Self> {
         let tempdir = camino_tempfile::tempdir()?;
-        Ok(Self { fsync, tempdir })
+        Ok(Self {
+            fsync,
+            tempdir,
+            pg_version: PgMajorVersion::PG17,
+            remote_storage: None,
+        })
     }
 
     /// Constructs a Safekeeper config for the given node ID.
@@ -43,6 +56,7 @@ fn make_conf(&self, node_id: NodeId) -> SafeKeeperConf {
         conf.my_id = node_id;
         conf.no_sync = !self.fsync;
         conf.workdir = self.tempdir.path().join(format!("safekeeper-{node_id}"));
+        conf.remote_storage = self.remote_storage.clone();
         conf
     }
 
@@ -66,8 +80,10 @@ pub async fn make_safekeeper(
         let mut pstate = TimelinePersistentState::empty();
         pstate.tenant_id = ttid.tenant_id;
         pstate.timeline_id = ttid.timeline_id;
+        pstate.server.pg_version = PgVersionId::from(self.pg_version);
 
-        let wal = wal_storage::PhysicalStorage::new(&ttid, &timeline_dir, &pstate, conf.no_sync)?;
+        let wal =
+            wal_storage::PhysicalStorage::new(&ttid, &timeline_dir, &pstate, false, conf.no_sync)?;
         let ctrl =
             control_file::FileStorage::create_new(&timeline_dir, pstate, conf.no_sync).await?;
         let state = TimelineState::new(ctrl);
@@ -178,4 +194,62 @@ pub(crate) async fn write_wal(
 
         Ok(end_watch)
     }
+
+    /// Writes and commits WAL which was produced elsewhere, e.g. by Postgres.
+    /// The WAL must start and end at record boundaries.
+    #[allow(dead_code)]
+    pub(crate) async fn write_raw_wal(
+        tli: Arc<Timeline>,
+        start_lsn: Lsn,
+        wal: &[u8],
+    ) -> anyhow::Result<EndWatch> {
+        let (msg_tx, msg_rx) = tokio::sync::mpsc::channel(receive_wal::MSG_QUEUE_SIZE);
+        let (reply_tx, mut reply_rx) = tokio::sync::mpsc::channel(receive_wal::REPLY_QUEUE_SIZE);
+
+        let end_watch = EndWatch::Commit(tli.get_commit_lsn_watch_rx());
+
+        WalAcceptor::spawn(tli.wal_residence_guard().await?, msg_rx, reply_tx, Some(0));
+
+        let end_lsn = start_lsn + wal.len() as u64;
+        let append = |begin_lsn: Lsn, wal_data: Bytes| {
+            ProposerAcceptorMessage::AppendRequest(AppendRequest {
+                h: AppendRequestHeader {
+                    generation: Generation::new(0),
+                    term: 1,
+                    begin_lsn,
+                    end_lsn: begin_lsn + wal_data.len() as u64,
+                    // Safekeeper caps it at the end of the last flushed
+                    // record.
+                    commit_lsn: end_lsn,
+                    truncate_lsn: Lsn(0),
+                },
+                wal_data,
+            })
+        };
+
+        let mut lsn = start_lsn;
+        for chunk in wal.chunks(MAX_SEND_SIZE) {
+            msg_tx
+                .send(append(lsn, Bytes::copy_from_slice(chunk)))
+                .await?;
+            lsn += chunk.len() as u64;
+            // Chunks may end mid-record, so only drain responses here.
+            while reply_rx.try_recv().is_ok() {}
+        }
+        while let Some(reply) = reply_rx.recv().await {
+            if let AcceptorProposerMessage::AppendResponse(resp) = reply {
+                if resp.flush_lsn >= end_lsn {
+                    break;
+                }
+            }
+        }
+
+        // Commit everything once it is flushed.
+        msg_tx.send(append(end_lsn, Bytes::new())).await?;
+        tli.get_commit_lsn_watch_rx()
+            .wait_for(|commit_lsn| *commit_lsn >= end_lsn)
+            .await?;
+
+        Ok(end_watch)
+    }
 }
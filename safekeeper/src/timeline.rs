@@ -27,7 +27,8 @@
 use utils::sync::gate::Gate;
 
 use crate::metrics::{
-    FullTimelineInfo, MISC_OPERATION_SECONDS, WAL_STORAGE_LIMIT_ERRORS, WalStorageMetrics,
+    FullTimelineInfo, MISC_OPERATION_SECONDS, WAL_QUOTA_REJECTED_APPENDS, WAL_STORAGE_LIMIT_ERRORS,
+    WalStorageMetrics,
 };
 
 use crate::hadron::GLOBAL_DISK_LIMIT_EXCEEDED;
@@ -42,6 +43,7 @@
 use crate::wal_backup;
 use crate::wal_backup::{WalBackup, remote_timeline_path};
 use crate::wal_backup_partial::PartialRemoteSegment;
+use crate::wal_quota::{AtomicWalQuotaState, WalQuotaState};
 use crate::wal_storage::{Storage as wal_storage_iface, WalReader};
 use crate::{SafeKeeperConf, control_file, debug_dump, timeline_manager, wal_storage};
 
@@ -193,8 +195,9 @@ pub async fn term_bump(&mut self, to: Option<Term>) -> Result<TimelineTermBumpRe
     pub async fn membership_switch(
         &mut self,
         to: Configuration,
+        my_id: NodeId,
     ) -> Result<TimelineMembershipSwitchResponse> {
-        let result = self.state_mut().membership_switch(to).await?;
+        let result = self.state_mut().membership_switch(to, my_id).await?;
 
         Ok(TimelineMembershipSwitchResponse {
             previous_conf: result.previous_conf,
@@ -304,9 +307,9 @@ pub struct SharedState {
     pub(crate) sk: StateSK,
     /// In memory list containing state of peers sent in latest messages from them.
     pub(crate) peers_info: PeersInfo,
-    // True value hinders old WAL removal; this is used by snapshotting. We
-    // could make it a counter, but there is no need to.
-    pub(crate) wal_removal_on_hold: bool,
+    // Non zero value hinders old WAL removal; this is used by snapshotting
+    // and WAL verification, which may run concurrently.
+    pub(crate) wal_removal_holds: u32,
 }
 
 impl SharedState {
@@ -315,7 +318,7 @@ pub fn new(sk: StateSK) -> Self {
         Self {
             sk,
             peers_info: PeersInfo(vec![]),
-            wal_removal_on_hold: false,
+            wal_removal_holds: 0,
         }
     }
 
@@ -333,6 +336,7 @@ pub fn restore(conf: &SafeKeeperConf, ttid: &TenantTimelineId) -> Result<Self> {
                     ttid,
                     &timeline_dir,
                     &control_store,
+                    control_store.mconf.is_witness(conf.my_id),
                     conf.no_sync,
                 )?;
                 StateSK::Loaded(SafeKeeper::new(
@@ -372,10 +376,15 @@ fn get_safekeeper_info(
             commit_lsn: self.sk.state().inmem.commit_lsn.0,
             remote_consistent_lsn: self.sk.state().inmem.remote_consistent_lsn.0,
             peer_horizon_lsn: self.sk.state().inmem.peer_horizon_lsn.0,
-            safekeeper_connstr: conf
-                .advertise_pg_addr
-                .to_owned()
-                .unwrap_or(conf.listen_pg_addr.clone()),
+            // Witness has no WAL to serve, so don't advertise it to
+            // pageservers; they skip safekeepers with empty connstr.
+            safekeeper_connstr: if self.sk.state().mconf.is_witness(conf.my_id) {
+                String::new()
+            } else {
+                conf.advertise_pg_addr
+                    .to_owned()
+                    .unwrap_or(conf.listen_pg_addr.clone())
+            },
             http_connstr: conf.listen_http_addr.to_owned(),
             https_connstr: conf.listen_https_addr.to_owned(),
             backup_lsn: self.sk.state().inmem.backup_lsn.0,
@@ -481,6 +490,7 @@ pub struct Timeline {
     pub(crate) broker_active: AtomicBool,
     pub(crate) wal_backup_active: AtomicBool,
     pub(crate) last_removed_segno: AtomicU64,
+    pub(crate) wal_quota_state: AtomicWalQuotaState,
     pub(crate) mgr_status: AtomicStatus,
 }
 
@@ -525,6 +535,7 @@ pub fn new(
             broker_active: AtomicBool::new(false),
             wal_backup_active: AtomicBool::new(false),
             last_removed_segno: AtomicU64::new(0),
+            wal_quota_state: AtomicWalQuotaState::new(),
             mgr_status: AtomicStatus::new(),
             wal_backup,
         })
@@ -607,6 +618,13 @@ pub async fn close(&self) {
         self.gate.close().await;
     }
 
+    /// Close WAL storage of the shut down timeline before its directory is
+    /// replaced. `Self::close` must have been already called.
+    pub async fn close_wal_store(&self) {
+        assert!(self.gate.close_complete());
+        self.write_shared_state().await.sk.close_wal_store();
+    }
+
     /// Delete timeline from disk completely, by removing timeline directory.
     ///
     /// Also deletes WAL in s3. Might fail if e.g. s3 is unavailable, but
@@ -911,7 +929,14 @@ pub async fn membership_switch(
         to: Configuration,
     ) -> Result<TimelineMembershipSwitchResponse> {
         let mut state = self.write_shared_state().await;
-        state.sk.membership_switch(to).await
+        state.sk.membership_switch(to, self.conf.my_id).await
+    }
+
+    /// Returns true if this safekeeper is a witness of the timeline, i.e. it
+    /// votes but doesn't store WAL.
+    pub async fn is_witness(&self) -> bool {
+        let state = self.read_shared_state().await;
+        state.sk.state().mconf.is_witness(self.conf.my_id)
     }
 
     /// Guts of [`Self::wal_residence_guard`] and [`Self::try_wal_residence_guard`]
@@ -1092,6 +1117,43 @@ fn hadron_check_disk_usage(
     }
     // END HADRON
 
+    /// Reject appends carrying WAL while the timeline is over a retained WAL
+    /// quota. Other messages still pass, so elections and commit_lsn
+    /// propagation keep working.
+    fn check_wal_quota(&self, msg: &ProposerAcceptorMessage) -> Result<()> {
+        let (ProposerAcceptorMessage::AppendRequest(req)
+        | ProposerAcceptorMessage::NoFlushAppendRequest(req)) = msg
+        else {
+            return Ok(());
+        };
+        if req.wal_data.is_empty() {
+            return Ok(());
+        }
+        match self.wal_quota_state.load() {
+            WalQuotaState::TimelineExceeded => {
+                WAL_QUOTA_REJECTED_APPENDS
+                    .with_label_values(&["timeline"])
+                    .inc();
+                bail!(
+                    "timeline {} retains more WAL than its quota of {} bytes, rejecting appends until pageservers catch up",
+                    self.ttid,
+                    self.conf.timeline_wal_quota_bytes
+                );
+            }
+            WalQuotaState::TenantExceeded => {
+                WAL_QUOTA_REJECTED_APPENDS
+                    .with_label_values(&["tenant"])
+                    .inc();
+                bail!(
+                    "tenant {} retains more WAL than its quota of {} bytes, rejecting appends until pageservers catch up",
+                    self.ttid.tenant_id,
+                    self.conf.tenant_wal_quota_bytes
+                );
+            }
+            WalQuotaState::Ok | WalQuotaState::Backpressure => Ok(()),
+        }
+    }
+
     /// Pass arrived message to the safekeeper.
     pub async fn process_msg(
         &self,
@@ -1111,11 +1173,16 @@ pub async fn process_msg(
             // safekeeper connections every second until it can successfully propose WAL to the SK again.
             self.hadron_check_disk_usage(&mut shared_state)?;
             // END HADRON
+            // Like the disk usage check above, this terminates the WalAcceptor
+            // and makes the compute reconnect.
+            self.check_wal_quota(msg)?;
             rmsg = shared_state.sk.safekeeper().process_msg(msg).await?;
 
-            // if this is AppendResponse, fill in proper hot standby feedback.
+            // if this is AppendResponse, fill in proper hot standby feedback
+            // and ask the compute to throttle if we are close to a WAL quota.
             if let Some(AcceptorProposerMessage::AppendResponse(ref mut resp)) = rmsg {
                 resp.hs_feedback = self.walsenders.get_hotstandby().hs_feedback;
+                resp.wal_backpressure = self.wal_quota_state.load() != WalQuotaState::Ok;
             }
         }
         Ok(rmsg)
@@ -1239,6 +1306,7 @@ pub(crate) async fn switch_to_present(&self) -> anyhow::Result<()> {
             &self.ttid,
             &self.timeline_dir,
             shared.sk.state(),
+            shared.sk.state().mconf.is_witness(self.conf.my_id),
             self.conf.no_sync,
         )?;
 
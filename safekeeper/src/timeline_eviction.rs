@@ -38,6 +38,7 @@ pub(crate) fn ready_for_eviction(
         self.backup_task.is_none()
             && self.recovery_task.is_none()
             && self.wal_removal_task.is_none()
+            && self.wal_compression_task.is_none()
             && self.partial_backup_task.is_none()
             && next_event.is_none()
             && self.access_service.is_empty()
@@ -49,9 +50,10 @@ pub(crate) fn ready_for_eviction(
             // advancing remote_consistent_lsn) which happens only after WAL is
             // committed, true means all this is done.
             //
-            // This also works for the first segment despite last_removed_segno
-            // being 0 on init because this 0 triggers run of wal_removal_task
-            // on success of which manager updates the horizon.
+            // On init last_removed_segno is seeded from the first segment on
+            // disk, and if it is still behind, removal horizon is ahead of it
+            // which triggers run of wal_removal_task on success of which
+            // manager updates the horizon.
             //
             // **Note** pull_timeline functionality assumes that evicted timelines always have
             // a partial segment: if we ever change this condition, must also update that code.
@@ -20,12 +20,14 @@
 use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 use tracing::{Instrument, debug, info, info_span, instrument, warn};
+use utils::id::NodeId;
 use utils::lsn::Lsn;
 
 use crate::SafeKeeperConf;
 use crate::control_file::{FileStorage, Storage};
 use crate::metrics::{
-    MANAGER_ACTIVE_CHANGES, MANAGER_ITERATIONS_TOTAL, MISC_OPERATION_SECONDS, NUM_EVICTED_TIMELINES,
+    MANAGER_ACTIVE_CHANGES, MANAGER_ITERATIONS_TOTAL, MISC_OPERATION_SECONDS,
+    NUM_EVICTED_TIMELINES, WAL_QUOTA_TIMELINES,
 };
 use crate::rate_limit::{RateLimiter, rand_duration};
 use crate::recovery::recovery_main;
@@ -35,8 +37,12 @@
 use crate::timeline::{ManagerTimeline, ReadGuardSharedState, StateSK, WalResidentTimeline};
 use crate::timeline_guard::{AccessService, GuardId, ResidenceGuard};
 use crate::timelines_set::{TimelineSetGuard, TimelinesSet};
+use crate::wal_archive;
 use crate::wal_backup::{self, WalBackup, WalBackupTaskHandle};
 use crate::wal_backup_partial::{self, PartialBackup, PartialRemoteSegment};
+use crate::wal_compression;
+use crate::wal_quota::{self, WalQuotaState};
+use crate::wal_storage;
 
 pub(crate) struct StateSnapshot {
     // inmem values
@@ -52,12 +58,16 @@ pub(crate) struct StateSnapshot {
     // latest state
     pub(crate) flush_lsn: Lsn,
     pub(crate) last_log_term: Term,
+    pub(crate) timeline_start_lsn: Lsn,
 
     // misc
     pub(crate) cfile_last_persist_at: std::time::Instant,
     pub(crate) inmem_flush_pending: bool,
     pub(crate) wal_removal_on_hold: bool,
+    pub(crate) wal_archive_enabled: bool,
     pub(crate) peers: Vec<PeerInfo>,
+    /// Witness members of the current configuration.
+    pub(crate) witnesses: Vec<NodeId>,
 }
 
 impl StateSnapshot {
@@ -73,10 +83,13 @@ fn new(read_guard: ReadGuardSharedState, heartbeat_timeout: Duration) -> Self {
             cfile_backup_lsn: state.backup_lsn,
             flush_lsn: read_guard.sk.flush_lsn(),
             last_log_term: read_guard.sk.last_log_term(),
+            timeline_start_lsn: state.timeline_start_lsn,
             cfile_last_persist_at: state.pers.last_persist_at(),
             inmem_flush_pending: Self::has_unflushed_inmem_state(state),
-            wal_removal_on_hold: read_guard.wal_removal_on_hold,
+            wal_removal_on_hold: read_guard.wal_removal_holds > 0,
+            wal_archive_enabled: state.wal_archive.enabled,
             peers: read_guard.get_peers(heartbeat_timeout),
+            witnesses: state.mconf.witnesses.clone(),
         }
     }
 
@@ -207,18 +220,24 @@ pub(crate) struct Manager {
     pub(crate) num_computes_rx: tokio::sync::watch::Receiver<usize>,
     pub(crate) tli_broker_active: TimelineSetGuard,
     pub(crate) last_removed_segno: XLogSegNo,
+    pub(crate) last_compressed_segno: XLogSegNo,
     pub(crate) is_offloaded: bool,
 
     // background tasks
     pub(crate) backup_task: Option<WalBackupTaskHandle>,
     pub(crate) recovery_task: Option<JoinHandle<()>>,
     pub(crate) wal_removal_task: Option<JoinHandle<anyhow::Result<u64>>>,
+    pub(crate) wal_compression_task: Option<JoinHandle<anyhow::Result<u64>>>,
 
     // partial backup
     pub(crate) partial_backup_task:
         Option<(JoinHandle<Option<PartialRemoteSegment>>, CancellationToken)>,
     pub(crate) partial_backup_uploaded: Option<PartialRemoteSegment>,
 
+    // WAL archive export
+    pub(crate) wal_archive_task: Option<(JoinHandle<Option<Lsn>>, CancellationToken)>,
+    pub(crate) wal_archive_uploaded: Option<Lsn>,
+
     // misc
     pub(crate) access_service: AccessService,
     pub(crate) global_rate_limiter: RateLimiter,
@@ -270,6 +289,10 @@ pub async fn main_task(
         }
     }
 
+    WAL_QUOTA_TIMELINES
+        .with_label_values(&[wal_quota::state_label(WalQuotaState::Ok)])
+        .inc();
+
     // If timeline is evicted, reflect that in the metric.
     if mgr.is_offloaded {
         NUM_EVICTED_TIMELINES.inc();
@@ -296,9 +319,15 @@ pub async fn main_task(
             mgr.set_status(Status::UpdateWalRemoval);
             mgr.update_wal_removal(&state_snapshot).await;
 
+            mgr.set_status(Status::UpdateWalCompression);
+            mgr.update_wal_compression(&state_snapshot);
+
             mgr.set_status(Status::UpdatePartialBackup);
             mgr.update_partial_backup(&state_snapshot).await;
 
+            mgr.set_status(Status::UpdateWalArchive);
+            mgr.update_wal_archive(&state_snapshot).await;
+
             let now = Instant::now();
             if mgr.evict_not_before > now {
                 // we should wait until evict_not_before
@@ -330,6 +359,9 @@ pub async fn main_task(
             }
         }
 
+        mgr.set_status(Status::UpdateWalQuota);
+        mgr.update_wal_quota(&state_snapshot);
+
         mgr.set_status(Status::Wait);
         // wait until something changes. tx channels are stored under Arc, so they will not be
         // dropped until the manager task is finished.
@@ -356,11 +388,21 @@ pub async fn main_task(
                 mgr.wal_removal_task = None;
                 mgr.update_wal_removal_end(res);
             }
+            res = await_task_finish(mgr.wal_compression_task.as_mut()) => {
+                // WAL compression task finished
+                mgr.wal_compression_task = None;
+                mgr.update_wal_compression_end(res);
+            }
             res = await_task_finish(mgr.partial_backup_task.as_mut().map(|(handle, _)| handle)) => {
                 // partial backup task finished
                 mgr.partial_backup_task = None;
                 mgr.update_partial_backup_end(res);
             }
+            res = await_task_finish(mgr.wal_archive_task.as_mut().map(|(handle, _)| handle)) => {
+                // WAL archive task finished
+                mgr.wal_archive_task = None;
+                mgr.update_wal_archive_end(res);
+            }
 
             msg = manager_rx.recv() => {
                 mgr.set_status(Status::HandleMessage);
@@ -398,19 +440,65 @@ pub async fn main_task(
         }
     }
 
+    if let Some((handle, cancel)) = &mut mgr.wal_archive_task {
+        cancel.cancel();
+        if let Err(e) = handle.await {
+            warn!("WAL archive task failed: {:?}", e);
+        }
+    }
+
     if let Some(wal_removal_task) = &mut mgr.wal_removal_task {
         let res = wal_removal_task.await;
         mgr.update_wal_removal_end(res);
     }
 
+    if let Some(wal_compression_task) = &mut mgr.wal_compression_task {
+        let res = wal_compression_task.await;
+        mgr.update_wal_compression_end(res);
+    }
+
     // If timeline is deleted while evicted decrement the gauge.
     if mgr.tli.is_cancelled() && mgr.is_offloaded {
         NUM_EVICTED_TIMELINES.dec();
     }
 
+    let wal_quota_state = mgr.tli.wal_quota_state.load();
+    WAL_QUOTA_TIMELINES
+        .with_label_values(&[wal_quota::state_label(wal_quota_state)])
+        .dec();
+    wal_quota::RETAINED_WAL.remove(&mgr.tli.ttid);
+
     mgr.set_status(Status::Finished);
 }
 
+/// WAL below the first segment on disk was removed before restart, so start
+/// from there instead of 0: otherwise the WAL quota would account all WAL
+/// since timeline start until the first removal task finishes. Evicted
+/// timelines have no local WAL, everything before the uploaded partial
+/// segment is removed.
+async fn initial_last_removed_segno(
+    tli: &ManagerTimeline,
+    wal_seg_size: usize,
+    partial_backup_uploaded: &Option<PartialRemoteSegment>,
+) -> XLogSegNo {
+    match wal_storage::first_segment_on_disk(tli.timeline_dir(), wal_seg_size).await {
+        Ok(Some(first_segno)) => first_segno.saturating_sub(1),
+        Ok(None) => partial_backup_uploaded
+            .as_ref()
+            .map(|partial| {
+                partial
+                    .flush_lsn
+                    .segment_number(wal_seg_size)
+                    .saturating_sub(1)
+            })
+            .unwrap_or(0),
+        Err(e) => {
+            warn!("failed to find first WAL segment on disk: {:?}", e);
+            0
+        }
+    }
+}
+
 impl Manager {
     async fn new(
         tli: ManagerTimeline,
@@ -421,20 +509,29 @@ async fn new(
         wal_backup: Arc<WalBackup>,
     ) -> Manager {
         let (is_offloaded, partial_backup_uploaded) = tli.bootstrap_mgr().await;
+        let wal_seg_size = tli.get_wal_seg_size().await;
+        let last_removed_segno =
+            initial_last_removed_segno(&tli, wal_seg_size, &partial_backup_uploaded).await;
+        tli.last_removed_segno
+            .store(last_removed_segno, std::sync::atomic::Ordering::Relaxed);
         Manager {
-            wal_seg_size: tli.get_wal_seg_size().await,
+            wal_seg_size,
             walsenders: tli.get_walsenders().clone(),
             wal_backup,
             state_version_rx: tli.get_state_version_rx(),
             num_computes_rx: tli.get_walreceivers().get_num_rx(),
             tli_broker_active: broker_active_set.guard(tli.clone()),
-            last_removed_segno: 0,
+            last_removed_segno,
+            last_compressed_segno: 0,
             is_offloaded,
             backup_task: None,
             recovery_task: None,
             wal_removal_task: None,
+            wal_compression_task: None,
             partial_backup_task: None,
             partial_backup_uploaded,
+            wal_archive_task: None,
+            wal_archive_uploaded: None,
             access_service: AccessService::new(manager_tx),
             tli,
             global_rate_limiter,
@@ -555,8 +652,12 @@ async fn update_control_file_save(
 
     /// Spawns WAL removal task if needed.
     async fn update_wal_removal(&mut self, state: &StateSnapshot) {
-        if self.wal_removal_task.is_some() || state.wal_removal_on_hold {
-            // WAL removal is already in progress or hold off
+        if self.wal_removal_task.is_some()
+            || self.wal_compression_task.is_some()
+            || state.wal_removal_on_hold
+        {
+            // WAL removal is already in progress, hold off or segments are
+            // being compressed
             return;
         }
 
@@ -626,6 +727,100 @@ fn update_wal_removal_end(&mut self, res: Result<anyhow::Result<u64>, JoinError>
             .store(new_last_removed_segno, std::sync::atomic::Ordering::Relaxed);
     }
 
+    /// Reports WAL retained on disk by the timeline to [`wal_quota`] and
+    /// publishes the resulting quota state for appends. Evicted timelines
+    /// don't retain any WAL locally.
+    fn update_wal_quota(&mut self, state: &StateSnapshot) {
+        let retained_bytes = if self.is_offloaded {
+            0
+        } else {
+            wal_quota::retained_wal_bytes(
+                self.last_removed_segno,
+                state.timeline_start_lsn,
+                state.flush_lsn,
+                self.wal_seg_size,
+            )
+        };
+
+        let new_state = wal_quota::RETAINED_WAL.update(&self.conf, &self.tli.ttid, retained_bytes);
+        let old_state = self.tli.wal_quota_state.load();
+        if new_state != old_state {
+            info!(
+                "WAL quota state changed from {:?} to {:?}, retaining {} bytes of WAL",
+                old_state, new_state, retained_bytes
+            );
+            self.set_wal_quota_state(new_state);
+        }
+    }
+
+    fn set_wal_quota_state(&self, new_state: WalQuotaState) {
+        let old_state = self.tli.wal_quota_state.load();
+        WAL_QUOTA_TIMELINES
+            .with_label_values(&[wal_quota::state_label(old_state)])
+            .dec();
+        WAL_QUOTA_TIMELINES
+            .with_label_values(&[wal_quota::state_label(new_state)])
+            .inc();
+        self.tli.wal_quota_state.store(new_state);
+    }
+
+    /// Spawns WAL compression task if enabled and there are new segments to
+    /// compress. Only segments entirely below the persisted commit_lsn are
+    /// compressed: they are never written or truncated again, and startup
+    /// doesn't look at them.
+    fn update_wal_compression(&mut self, state: &StateSnapshot) {
+        let Some(level) = self.conf.wal_compression_level else {
+            return;
+        };
+        if self.wal_compression_task.is_some() || self.wal_removal_task.is_some() {
+            return;
+        }
+
+        let Some(compress_up_to_segno) = state
+            .cfile_commit_lsn
+            .segment_number(self.wal_seg_size)
+            .checked_sub(1)
+        else {
+            return;
+        };
+        if compress_up_to_segno <= self.last_compressed_segno {
+            return;
+        }
+
+        let Ok(timeline_gate_guard) = self.tli.gate.enter() else {
+            tracing::info!("Timeline shutdown, not spawning WAL compression task");
+            return;
+        };
+        let timeline_dir = self.tli.timeline_dir().to_owned();
+        let wal_seg_size = self.wal_seg_size;
+        let no_sync = self.conf.no_sync;
+        self.wal_compression_task = Some(tokio::spawn(
+            async move {
+                let _timeline_gate_guard = timeline_gate_guard;
+
+                wal_compression::compress_segments_up_to(
+                    &timeline_dir,
+                    wal_seg_size,
+                    compress_up_to_segno,
+                    level,
+                    no_sync,
+                )
+                .await?;
+                Ok(compress_up_to_segno)
+            }
+            .instrument(info_span!("WAL compression", ttid=%self.tli.ttid)),
+        ));
+    }
+
+    /// Update the state after WAL compression task finished.
+    fn update_wal_compression_end(&mut self, res: Result<anyhow::Result<u64>, JoinError>) {
+        match res {
+            Ok(Ok(segno)) => self.last_compressed_segno = segno,
+            Err(e) => warn!("WAL compression task failed: {:?}", e),
+            Ok(Err(e)) => warn!("WAL compression task failed: {:?}", e),
+        }
+    }
+
     /// Spawns partial WAL backup task if needed.
     async fn update_partial_backup(&mut self, state: &StateSnapshot) {
         // check if WAL backup is enabled and should be started
@@ -672,6 +867,59 @@ fn update_partial_backup_end(&mut self, res: Result<Option<PartialRemoteSegment>
         }
     }
 
+    /// Spawns WAL archive task if needed, or stops it if this safekeeper is no
+    /// longer the offloader.
+    async fn update_wal_archive(&mut self, state: &StateSnapshot) {
+        let Some(storage) = self.wal_backup.get_archive_storage() else {
+            return;
+        };
+
+        let should_run = state.wal_archive_enabled && wal_backup::is_elected_offloader(self, state);
+
+        if let Some((handle, cancel)) = &mut self.wal_archive_task {
+            if !should_run {
+                info!("stopping WAL archive task");
+                cancel.cancel();
+                if let Err(e) = handle.await {
+                    warn!("WAL archive task panicked: {:?}", e);
+                }
+                self.wal_archive_task = None;
+            }
+            return;
+        }
+
+        if !should_run || !wal_archive::needs_uploading(state, self.wal_archive_uploaded) {
+            return;
+        }
+
+        let Ok(resident) = self.wal_resident_timeline() else {
+            // Shutting down
+            return;
+        };
+
+        let cancel = CancellationToken::new();
+        let handle = tokio::spawn(wal_archive::main_task(
+            resident,
+            self.conf.clone(),
+            self.global_rate_limiter.clone(),
+            cancel.clone(),
+            storage,
+        ));
+        self.wal_archive_task = Some((handle, cancel));
+    }
+
+    /// Update the state after WAL archive task finished.
+    fn update_wal_archive_end(&mut self, res: Result<Option<Lsn>, JoinError>) {
+        match res {
+            Ok(uploaded) => {
+                self.wal_archive_uploaded = uploaded;
+            }
+            Err(e) => {
+                warn!("WAL archive task panicked: {:?}", e);
+            }
+        }
+    }
+
     /// Reset partial backup state and remove its remote storage data. Since it
     /// might concurrently uploading something, cancel the task first.
     async fn backup_partial_reset(&mut self) -> anyhow::Result<Vec<String>> {
@@ -810,7 +1058,10 @@ pub enum Status {
     UpdateBackup,
     UpdateControlFile,
     UpdateWalRemoval,
+    UpdateWalCompression,
     UpdatePartialBackup,
+    UpdateWalArchive,
+    UpdateWalQuota,
     EvictTimeline,
     Wait,
     HandleMessage,
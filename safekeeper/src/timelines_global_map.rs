@@ -8,7 +8,7 @@
 use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result, bail};
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use camino_tempfile::Utf8TempDir;
 use safekeeper_api::membership::Configuration;
 use safekeeper_api::models::{SafekeeperUtilization, TimelineDeleteResult};
@@ -19,6 +19,7 @@
 use utils::id::{TenantId, TenantTimelineId, TimelineId};
 use utils::lsn::Lsn;
 
+use crate::control_file::CONTROL_FILE_NAME;
 use crate::defaults::DEFAULT_EVICTION_CONCURRENCY;
 use crate::http::routes::DeleteOrExcludeError;
 use crate::rate_limit::RateLimiter;
@@ -145,6 +146,10 @@ pub async fn init(&self) -> Result<()> {
             // named as a valid tenant_id.
             state.conf.workdir.clone()
         };
+        // Temp timeline directories are only used within a single run, e.g. by
+        // pull_timeline; anything left there is from before a crash.
+        delete_dir(&tenants_dir.join(TEMP_DIR_NAME)).await?;
+
         let mut tenant_count = 0;
         for tenants_dir_entry in std::fs::read_dir(&tenants_dir)
             .with_context(|| format!("failed to list tenants dir {tenants_dir}"))?
@@ -187,6 +192,7 @@ async fn load_tenant_timelines(&self, tenant_id: TenantId) -> Result<()> {
         };
 
         let timelines_dir = get_tenant_dir(&conf, &tenant_id);
+        recover_replaced_timelines(&timelines_dir, !conf.no_sync).await?;
         for timelines_dir_entry in std::fs::read_dir(&timelines_dir)
             .with_context(|| format!("failed to list timelines dir {timelines_dir}"))?
         {
@@ -336,14 +342,173 @@ pub async fn load_temp_timeline(
         };
 
         // Do the actual move and reflect the result in the map.
-        match GlobalTimelines::install_temp_timeline(
+        let res = GlobalTimelines::install_temp_timeline(
             ttid,
             tmp_path,
             conf.clone(),
             wal_backup.clone(),
         )
+        .await;
+        self.finish_creation(
+            ttid,
+            res,
+            &conf,
+            broker_active_set,
+            partial_backup_rate_limiter,
+            wal_backup,
+        )
         .await
+    }
+
+    /// Replace existing timeline `tli` with the one pulled into `tmp_path`
+    /// by incremental pull_timeline. The timeline is shut down and loaded
+    /// from the new directory. If the pulled state is not ahead of the local
+    /// one, e.g. because timeline advanced during the pull, the old timeline
+    /// is loaded back and error is returned.
+    pub(crate) async fn replace_timeline(
+        &self,
+        tli: Arc<Timeline>,
+        tmp_path: &Utf8PathBuf,
+    ) -> Result<Arc<Timeline>> {
+        let ttid = tli.ttid;
+        let (conf, broker_active_set, partial_backup_rate_limiter, wal_backup) = {
+            let mut state = self.state.lock().unwrap();
+            match state.timelines.get(&ttid) {
+                Some(GlobalMapTimeline::Timeline(t)) if Arc::ptr_eq(t, &tli) => {}
+                _ => bail!("timeline {ttid} was changed concurrently with the pull"),
+            }
+            if tli.is_cancelled() {
+                bail!(TimelineError::Cancelled(ttid));
+            }
+            state
+                .timelines
+                .insert(ttid, GlobalMapTimeline::CreationInProgress);
+            state.get_dependencies()
+        };
+
+        tli.cancel().await;
+        tli.close().await;
+        info!("timeline {ttid} shut down for replacement");
+
+        let res = match GlobalTimelines::check_replacement(&tli, &conf, tmp_path).await {
+            Ok(()) => {
+                GlobalTimelines::install_replacement(
+                    &tli,
+                    tmp_path,
+                    conf.clone(),
+                    wal_backup.clone(),
+                )
+                .await
+            }
+            Err(e) => {
+                warn!("not replacing timeline {ttid}, loading it back: {e:#}");
+                let res = Timeline::load_timeline(conf.clone(), ttid, wal_backup.clone());
+                self.finish_creation(
+                    ttid,
+                    res,
+                    &conf,
+                    broker_active_set,
+                    partial_backup_rate_limiter,
+                    wal_backup,
+                )
+                .await?;
+                return Err(e);
+            }
+        };
+        self.finish_creation(
+            ttid,
+            res,
+            &conf,
+            broker_active_set,
+            partial_backup_rate_limiter,
+            wal_backup,
+        )
+        .await
+    }
+
+    /// Check that the pulled state in `tmp_path` doesn't lose anything shut
+    /// down `tli` has: votes, membership generation or WAL.
+    async fn check_replacement(
+        tli: &Timeline,
+        conf: &SafeKeeperConf,
+        tmp_path: &Utf8PathBuf,
+    ) -> Result<()> {
+        let (term, generation, last_log_term, flush_lsn) = {
+            let shared_state = tli.read_shared_state().await;
+            let state = shared_state.sk.state();
+            (
+                state.acceptor_state.term,
+                state.mconf.generation,
+                shared_state.sk.last_log_term(),
+                shared_state.sk.flush_lsn(),
+            )
+        };
+        let new_state =
+            control_file::FileStorage::load_control_file(tmp_path.join(CONTROL_FILE_NAME))?;
+        let (_, new_flush_lsn) = validate_temp_timeline(conf, tli.ttid, tmp_path).await?;
+        let new_last_log_term = new_state.acceptor_state.get_last_log_term(new_flush_lsn);
+        if new_state.acceptor_state.term < term
+            || new_state.mconf.generation < generation
+            || (new_last_log_term, new_flush_lsn) < (last_log_term, flush_lsn)
         {
+            bail!(
+                "pulled state term={}, generation={}, last_log_term={}, flush_lsn={} is behind local term={}, generation={}, last_log_term={}, flush_lsn={}",
+                new_state.acceptor_state.term,
+                new_state.mconf.generation,
+                new_last_log_term,
+                new_flush_lsn,
+                term,
+                generation,
+                last_log_term,
+                flush_lsn
+            );
+        }
+        Ok(())
+    }
+
+    /// Main part of replace_timeline: swap the directory of shut down `tli`
+    /// with `tmp_path` and load it.
+    async fn install_replacement(
+        tli: &Timeline,
+        tmp_path: &Utf8PathBuf,
+        conf: Arc<SafeKeeperConf>,
+        wal_backup: Arc<WalBackup>,
+    ) -> Result<Arc<Timeline>> {
+        let timeline_path = get_timeline_dir(conf.as_ref(), &tli.ttid);
+        info!(
+            "replacing timeline {} directory {} with {}",
+            tli.ttid, timeline_path, tmp_path
+        );
+
+        tli.close_wal_store().await;
+        // Move the old directory aside first, so that at any point on disk
+        // there is either the old or the new timeline, and the old one is not
+        // deleted until the new one is in place. A crash in between is
+        // finished or rolled back by recover_replaced_timelines on startup.
+        let old_path = replaced_timeline_dir(&timeline_path);
+        durable_rename(&timeline_path, &old_path, !conf.no_sync).await?;
+        if let Err(e) = durable_rename(tmp_path, &timeline_path, !conf.no_sync).await {
+            durable_rename(&old_path, &timeline_path, !conf.no_sync).await?;
+            return Err(e.into());
+        }
+        delete_dir(&old_path).await?;
+
+        Timeline::load_timeline(conf, tli.ttid, wal_backup)
+    }
+
+    /// Put freshly loaded timeline into the map in place of the
+    /// CreationInProgress marker and start it, or remove the marker if
+    /// loading failed.
+    async fn finish_creation(
+        &self,
+        ttid: TenantTimelineId,
+        res: Result<Arc<Timeline>>,
+        conf: &SafeKeeperConf,
+        broker_active_set: Arc<TimelinesSet>,
+        partial_backup_rate_limiter: RateLimiter,
+        wal_backup: Arc<WalBackup>,
+    ) -> Result<Arc<Timeline>> {
+        match res {
             Ok(timeline) => {
                 let mut timeline_shared_state = timeline.write_shared_state().await;
                 let mut state = self.state.lock().unwrap();
@@ -358,7 +523,7 @@ pub async fn load_temp_timeline(
                 drop(state);
                 timeline.bootstrap(
                     &mut timeline_shared_state,
-                    &conf,
+                    conf,
                     broker_active_set,
                     partial_backup_rate_limiter,
                     wal_backup,
@@ -647,6 +812,49 @@ pub enum DeleteOrExclude {
     Exclude(membership::Configuration),
 }
 
+/// Directory in workdir holding temp timeline directories.
+const TEMP_DIR_NAME: &str = "tmp";
+
+/// Suffix of a timeline directory moved aside while it is being replaced,
+/// see [`GlobalTimelines::replace_timeline`].
+const REPLACED_TIMELINE_SUFFIX: &str = ".old";
+
+fn replaced_timeline_dir(timeline_path: &Utf8Path) -> Utf8PathBuf {
+    Utf8PathBuf::from(format!("{timeline_path}{REPLACED_TIMELINE_SUFFIX}"))
+}
+
+/// Finish or roll back timeline replacements interrupted by a crash: if the
+/// new directory was moved in, drop the old one, otherwise move the old one
+/// back.
+async fn recover_replaced_timelines(tenant_dir: &Utf8Path, do_fsync: bool) -> Result<()> {
+    let mut replaced = Vec::new();
+    for entry in std::fs::read_dir(tenant_dir)
+        .with_context(|| format!("failed to list timelines dir {tenant_dir}"))?
+    {
+        let file_name = entry?.file_name();
+        if let Some(timeline_id) = file_name
+            .to_str()
+            .and_then(|name| name.strip_suffix(REPLACED_TIMELINE_SUFFIX))
+            .and_then(|name| TimelineId::from_str(name).ok())
+        {
+            replaced.push(timeline_id);
+        }
+    }
+
+    for timeline_id in replaced {
+        let timeline_path = tenant_dir.join(timeline_id.to_string());
+        let old_path = replaced_timeline_dir(&timeline_path);
+        if fs::try_exists(&timeline_path).await? {
+            info!("finishing replacement of timeline directory {timeline_path}");
+            delete_dir(&old_path).await?;
+        } else {
+            info!("rolling back replacement of timeline directory {timeline_path}");
+            durable_rename(&old_path, &timeline_path, do_fsync).await?;
+        }
+    }
+    Ok(())
+}
+
 /// Create temp directory for a new timeline. It needs to be located on the same
 /// filesystem as the rest of the timelines. It will be automatically deleted when
 /// Utf8TempDir goes out of scope.
@@ -654,7 +862,7 @@ pub async fn create_temp_timeline_dir(
     conf: &SafeKeeperConf,
     ttid: TenantTimelineId,
 ) -> Result<(Utf8TempDir, Utf8PathBuf)> {
-    let temp_base = conf.workdir.join("tmp");
+    let temp_base = conf.workdir.join(TEMP_DIR_NAME);
 
     tokio::fs::create_dir_all(&temp_base).await?;
 
@@ -681,10 +889,47 @@ pub async fn validate_temp_timeline(
         bail!("wal_seg_size is not set");
     }
 
-    let wal_store = wal_storage::PhysicalStorage::new(&ttid, path, &control_store, conf.no_sync)?;
+    let wal_store = wal_storage::PhysicalStorage::new(
+        &ttid,
+        path,
+        &control_store,
+        control_store.mconf.is_witness(conf.my_id),
+        conf.no_sync,
+    )?;
 
     let commit_lsn = control_store.commit_lsn;
     let flush_lsn = wal_store.flush_lsn();
 
     Ok((commit_lsn, flush_lsn))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_recover_replaced_timelines() {
+        let tenant_dir = camino_tempfile::tempdir().unwrap();
+        let tenant_dir = tenant_dir.path();
+
+        // Crashed after the new directory was moved in: the old one goes.
+        let finished = tenant_dir.join(TimelineId::generate().to_string());
+        let finished_old = replaced_timeline_dir(&finished);
+        fs::create_dir(&finished).await.unwrap();
+        fs::write(finished.join("new"), b"").await.unwrap();
+        fs::create_dir(&finished_old).await.unwrap();
+
+        // Crashed before that: the old one is moved back.
+        let rolled_back = tenant_dir.join(TimelineId::generate().to_string());
+        let rolled_back_old = replaced_timeline_dir(&rolled_back);
+        fs::create_dir(&rolled_back_old).await.unwrap();
+        fs::write(rolled_back_old.join("old"), b"").await.unwrap();
+
+        recover_replaced_timelines(tenant_dir, false).await.unwrap();
+
+        assert!(finished.join("new").exists());
+        assert!(!finished_old.exists());
+        assert!(rolled_back.join("old").exists());
+        assert!(!rolled_back_old.exists());
+    }
+}
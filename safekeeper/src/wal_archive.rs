@@ -0,0 +1,308 @@
+//! Export of timeline WAL into a standard PostgreSQL WAL archive.
+//!
+//! WAL backup (see [`crate::wal_backup`] and [`crate::wal_backup_partial`])
+//! keeps WAL in a layout private to Neon. When enabled for a timeline, the
+//! elected offloader additionally uploads committed WAL to the archive remote
+//! storage (`--wal-archive-storage`) under `<tenant_id>/<timeline_id>/`, in the
+//! layout `archive_command` of vanilla Postgres would produce, so that it can
+//! be used by `restore_command` directly:
+//! - complete segments are named by timeline and segment number, e.g.
+//!   `000000010000000000000002`;
+//! - the segment containing the end of committed WAL is uploaded as
+//!   `000000010000000000000003.partial`, zero padded to the full segment size,
+//!   and is overwritten as it grows;
+//! - no `.history` files are written: safekeepers only serve timeline 1, which
+//!   by Postgres convention never has one.
+//!
+//! Only committed WAL is archived. It is the same on all safekeepers, so
+//! uploads are idempotent and offloader re-election is harmless.
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use postgres_ffi::{PG_TLI, XLogFileName, XLogSegNo};
+use remote_storage::{GenericRemoteStorage, RemotePath};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, instrument, warn};
+use utils::lsn::Lsn;
+
+use crate::SafeKeeperConf;
+use crate::metrics::{WAL_ARCHIVE_UPLOADED_BYTES, WAL_ARCHIVE_UPLOADS};
+use crate::rate_limit::{RateLimiter, rand_duration};
+use crate::timeline::WalResidentTimeline;
+use crate::timeline_manager::StateSnapshot;
+
+/// Delay before retrying after a failed upload.
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+// NB: this structure is a part of a control_file, you can't change it without
+// changing the control file format version.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct State {
+    /// Whether WAL of the timeline should be exported to the archive.
+    pub enabled: bool,
+    /// End of the last complete segment uploaded to the archive, or
+    /// [`Lsn::INVALID`] if nothing was archived yet.
+    pub archived_lsn: Lsn,
+}
+
+/// Returns true if archive is enabled and committed WAL is not fully uploaded
+/// yet, including the last partial segment.
+pub(crate) fn needs_uploading(state: &StateSnapshot, uploaded: Option<Lsn>) -> bool {
+    state.wal_archive_enabled
+        && state.commit_lsn != Lsn::INVALID
+        && uploaded != Some(state.commit_lsn)
+}
+
+struct WalArchive {
+    wal_seg_size: usize,
+    tli: WalResidentTimeline,
+    remote_timeline_path: RemotePath,
+    storage: Arc<GenericRemoteStorage>,
+    cancel: CancellationToken,
+    state: State,
+}
+
+impl WalArchive {
+    async fn new(
+        tli: WalResidentTimeline,
+        storage: Arc<GenericRemoteStorage>,
+        cancel: CancellationToken,
+    ) -> WalArchive {
+        let (_, persistent_state) = tli.get_state().await;
+        let wal_seg_size = tli.get_wal_seg_size().await;
+
+        let mut state = persistent_state.wal_archive;
+        // Start with the first segment of the timeline. It is padded by
+        // WalReader, so it is complete even if timeline starts in the middle.
+        let timeline_start = persistent_state
+            .timeline_start_lsn
+            .segment_lsn(wal_seg_size);
+        if state.archived_lsn < timeline_start {
+            state.archived_lsn = timeline_start;
+        }
+
+        WalArchive {
+            wal_seg_size,
+            remote_timeline_path: tli.remote_path.clone(),
+            tli,
+            storage,
+            cancel,
+            state,
+        }
+    }
+
+    fn segno(&self, lsn: Lsn) -> XLogSegNo {
+        lsn.segment_number(self.wal_seg_size)
+    }
+
+    fn segment_name(&self, segno: XLogSegNo) -> String {
+        XLogFileName(PG_TLI, segno, self.wal_seg_size)
+    }
+
+    /// Read WAL of segment `segno` up to `end_lsn`, leaving the rest of the
+    /// segment zeroed.
+    async fn read_segment(&self, segno: XLogSegNo, end_lsn: Lsn) -> anyhow::Result<Vec<u8>> {
+        let start_lsn = Lsn(segno * self.wal_seg_size as u64);
+        let len = (end_lsn.0 - start_lsn.0) as usize;
+        assert!(len <= self.wal_seg_size);
+
+        let mut buf = vec![0u8; self.wal_seg_size];
+        let mut reader = self.tli.get_walreader(start_lsn).await?;
+        let mut pos = 0;
+        while pos < len {
+            let read = reader.read(&mut buf[pos..len]).await?;
+            if read == 0 {
+                anyhow::bail!("unexpected end of WAL at {}", start_lsn + pos as u64);
+            }
+            pos += read;
+        }
+        Ok(buf)
+    }
+
+    async fn upload(&self, name: &str, buf: Vec<u8>) -> anyhow::Result<()> {
+        let size = buf.len();
+        let remote_path = self.remote_timeline_path.join(name);
+        let body = futures::stream::once(futures::future::ready(Ok(Bytes::from(buf))));
+
+        let res = self
+            .storage
+            .upload(body, size, &remote_path, None, &self.cancel)
+            .await;
+        if res.is_ok() {
+            WAL_ARCHIVE_UPLOADS.with_label_values(&["ok"]).inc();
+            WAL_ARCHIVE_UPLOADED_BYTES.inc_by(size as u64);
+        } else {
+            WAL_ARCHIVE_UPLOADS.with_label_values(&["error"]).inc();
+        }
+        res
+    }
+
+    /// Upload all complete segments below `commit_lsn`, persisting progress
+    /// after each of them.
+    async fn archive_complete_segments(&mut self, commit_lsn: Lsn) -> anyhow::Result<()> {
+        while self.segno(self.state.archived_lsn) < self.segno(commit_lsn) {
+            let segno = self.segno(self.state.archived_lsn);
+            let end_lsn = Lsn((segno + 1) * self.wal_seg_size as u64);
+            let name = self.segment_name(segno);
+
+            let buf = self.read_segment(segno, end_lsn).await?;
+            self.upload(&name, buf).await?;
+            debug!("archived {}", name);
+
+            self.tli
+                .map_control_file(|cf| {
+                    cf.wal_archive.archived_lsn = end_lsn;
+                    Ok(())
+                })
+                .await?;
+            self.state.archived_lsn = end_lsn;
+        }
+        Ok(())
+    }
+
+    /// Upload the segment with the end of committed WAL as `.partial`.
+    async fn archive_partial_segment(&self, commit_lsn: Lsn) -> anyhow::Result<()> {
+        let segno = self.segno(commit_lsn);
+        let name = format!("{}.partial", self.segment_name(segno));
+
+        let buf = self.read_segment(segno, commit_lsn).await?;
+        self.upload(&name, buf).await?;
+        debug!("archived {} up to commit_lsn {}", name, commit_lsn);
+        Ok(())
+    }
+}
+
+/// Main task for WAL archive export. It uploads complete segments as soon as
+/// they are committed, and the partial segment after `partial_backup_timeout`
+/// passes without the segment being completed.
+///
+/// When everything up to commit_lsn is archived, the task returns that LSN.
+/// Returns None if cancelled.
+#[instrument(name = "wal_archive", skip_all, fields(ttid = %tli.ttid))]
+pub async fn main_task(
+    tli: WalResidentTimeline,
+    conf: SafeKeeperConf,
+    limiter: RateLimiter,
+    cancel: CancellationToken,
+    storage: Arc<GenericRemoteStorage>,
+) -> Option<Lsn> {
+    debug!("started");
+    let await_duration = conf.partial_backup_timeout;
+    let mut first_iteration = true;
+
+    let mut commit_lsn_rx = tli.get_commit_lsn_watch_rx();
+    let mut archive = WalArchive::new(tli, storage, cancel.clone()).await;
+
+    loop {
+        // limit concurrent uploads, they hold a segment in memory
+        let upload_permit = tokio::select! {
+            acq = limiter.acquire_partial_backup() => acq,
+            _ = archive.tli.cancel.cancelled() => {
+                info!("timeline canceled");
+                return None;
+            }
+            _ = cancel.cancelled() => {
+                info!("task canceled");
+                return None;
+            }
+        };
+
+        let commit_lsn = *commit_lsn_rx.borrow();
+        let res = archive.archive_complete_segments(commit_lsn).await;
+        drop(upload_permit);
+        if let Err(e) = res {
+            warn!("failed to archive segments up to {}: {:#}", commit_lsn, e);
+            if !sleep_before_retry(&archive, &cancel).await {
+                return None;
+            }
+            continue;
+        }
+
+        if commit_lsn == archive.state.archived_lsn {
+            // commit_lsn is exactly at the segment boundary, nothing is partial
+            return Some(commit_lsn);
+        }
+
+        // smoothing the load after restart, by sleeping for a random time.
+        let await_duration = if first_iteration {
+            first_iteration = false;
+            rand_duration(&await_duration)
+        } else {
+            await_duration
+        };
+
+        // Wait before uploading the partial segment, unless it gets completed
+        // in the meantime and can be archived as a whole.
+        let pending_segno = archive.segno(commit_lsn);
+        let timeout = tokio::time::sleep(await_duration);
+        tokio::pin!(timeout);
+        let timeout_expired = loop {
+            tokio::select! {
+                _ = archive.tli.cancel.cancelled() => {
+                    info!("timeline canceled");
+                    return None;
+                }
+                _ = cancel.cancelled() => {
+                    info!("task canceled");
+                    return None;
+                }
+                _ = commit_lsn_rx.changed() => {
+                    if archive.segno(*commit_lsn_rx.borrow()) != pending_segno {
+                        break false;
+                    }
+                }
+                _ = &mut timeout => break true,
+            }
+        };
+        if !timeout_expired {
+            continue;
+        }
+
+        let _upload_permit = tokio::select! {
+            acq = limiter.acquire_partial_backup() => acq,
+            _ = archive.tli.cancel.cancelled() => {
+                info!("timeline canceled");
+                return None;
+            }
+            _ = cancel.cancelled() => {
+                info!("task canceled");
+                return None;
+            }
+        };
+
+        let commit_lsn = *commit_lsn_rx.borrow();
+        if archive.segno(commit_lsn) != pending_segno {
+            continue;
+        }
+        if let Err(e) = archive.archive_partial_segment(commit_lsn).await {
+            warn!(
+                "failed to archive partial segment up to {}: {:#}",
+                commit_lsn, e
+            );
+            if !sleep_before_retry(&archive, &cancel).await {
+                return None;
+            }
+            continue;
+        }
+        if *commit_lsn_rx.borrow() == commit_lsn {
+            return Some(commit_lsn);
+        }
+    }
+}
+
+/// Returns false if cancelled while sleeping.
+async fn sleep_before_retry(archive: &WalArchive, cancel: &CancellationToken) -> bool {
+    tokio::select! {
+        _ = archive.tli.cancel.cancelled() => {
+            info!("timeline canceled");
+            false
+        }
+        _ = cancel.cancelled() => {
+            info!("task canceled");
+            false
+        }
+        _ = tokio::time::sleep(RETRY_DELAY) => true,
+    }
+}
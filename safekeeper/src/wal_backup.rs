@@ -31,6 +31,7 @@
 };
 use crate::timeline::WalResidentTimeline;
 use crate::timeline_manager::{Manager, StateSnapshot};
+use crate::wal_compression::{compressed_segment_path, open_compressed_segment};
 use crate::{SafeKeeperConf, WAL_BACKUP_RUNTIME};
 
 const UPLOAD_FAILURE_RETRY_MIN_MS: u64 = 10;
@@ -121,6 +122,13 @@ pub(crate) async fn update_task(
     }
 }
 
+/// Returns true if this safekeeper is currently elected to offload WAL of the
+/// timeline.
+pub(crate) fn is_elected_offloader(mgr: &Manager, state: &StateSnapshot) -> bool {
+    let (offloader, _) = hadron_determine_offloader(mgr, state);
+    Some(mgr.conf.my_id) == offloader
+}
+
 async fn shut_down_task(entry: &mut Option<WalBackupTaskHandle>) {
     if let Some(wb_handle) = entry.take() {
         // Tell the task to shutdown. Error means task exited earlier, that's ok.
@@ -152,8 +160,15 @@ fn hadron_determine_offloader(mgr: &Manager, state: &StateSnapshot) -> (Option<N
     let mut offloader: Option<NodeId>;
     let mut election_dbg_str: String;
     let caughtup_peers_count: usize;
+    // Witnesses don't store WAL, so they can't offload it.
+    let peers: Vec<_> = state
+        .peers
+        .iter()
+        .filter(|p| !state.witnesses.contains(&p.sk_id))
+        .cloned()
+        .collect();
     (offloader, election_dbg_str, caughtup_peers_count) =
-        determine_offloader(&state.peers, state.backup_lsn, mgr.tli.ttid, &mgr.conf);
+        determine_offloader(&peers, state.backup_lsn, mgr.tli.ttid, &mgr.conf);
 
     if offloader.is_none()
         || caughtup_peers_count <= 1
@@ -182,8 +197,7 @@ fn hadron_determine_offloader(mgr: &Manager, state: &StateSnapshot) -> (Option<N
     );
     BACKUP_REELECT_LEADER_COUNT.inc();
     // Remove the current offloader if lag is too high.
-    let new_peers: Vec<_> = state
-        .peers
+    let new_peers: Vec<_> = peers
         .iter()
         .filter(|p| p.sk_id != offloader_sk_id)
         .cloned()
@@ -250,13 +264,23 @@ fn determine_offloader(
 
 pub struct WalBackup {
     storage: Option<Arc<GenericRemoteStorage>>,
+    /// Storage for WAL archive export, see [`crate::wal_archive`].
+    archive_storage: Option<Arc<GenericRemoteStorage>>,
 }
 
 impl WalBackup {
     /// Create a new WalBackup instance.
     pub async fn new(conf: &SafeKeeperConf) -> Result<Self> {
+        let archive_storage = match conf.wal_archive_storage.as_ref() {
+            Some(config) => Some(Arc::new(GenericRemoteStorage::from_config(config).await?)),
+            None => None,
+        };
+
         if !conf.wal_backup_enabled {
-            return Ok(Self { storage: None });
+            return Ok(Self {
+                storage: None,
+                archive_storage,
+            });
         }
 
         match conf.remote_storage.as_ref() {
@@ -264,15 +288,23 @@ pub async fn new(conf: &SafeKeeperConf) -> Result<Self> {
                 let storage = GenericRemoteStorage::from_config(config).await?;
                 Ok(Self {
                     storage: Some(Arc::new(storage)),
+                    archive_storage,
                 })
             }
-            None => Ok(Self { storage: None }),
+            None => Ok(Self {
+                storage: None,
+                archive_storage,
+            }),
         }
     }
 
     pub fn get_storage(&self) -> Option<Arc<GenericRemoteStorage>> {
         self.storage.clone()
     }
+
+    pub fn get_archive_storage(&self) -> Option<Arc<GenericRemoteStorage>> {
+        self.archive_storage.clone()
+    }
 }
 
 struct WalBackupTask {
@@ -556,9 +588,21 @@ async fn backup_object(
     target_file: &RemotePath,
     size: usize,
 ) -> Result<()> {
-    let file = File::open(&source_file)
-        .await
-        .with_context(|| format!("Failed to open file {source_file:?} for wal backup"))?;
+    let res = File::open(&source_file).await;
+    let file: Pin<Box<dyn tokio::io::AsyncRead + Send + Sync>> = match res {
+        Ok(file) => Box::pin(file),
+        // Segment might have been compressed on the disk; upload it raw.
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            open_compressed_segment(&compressed_segment_path(source_file), 0)
+                .await?
+                .ok_or(e)
+                .with_context(|| format!("Failed to open file {source_file:?} for wal backup"))?
+        }
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to open file {source_file:?} for wal backup"));
+        }
+    };
 
     let file = tokio_util::io::ReaderStream::with_capacity(file, BUFFER_SIZE);
 
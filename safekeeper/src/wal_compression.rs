@@ -0,0 +1,343 @@
+//! On-disk compression of closed WAL segments.
+//!
+//! When `--wal-compression-level` is set, the timeline manager recompresses
+//! segments lying entirely below the persisted commit_lsn -- they will never
+//! be written or truncated again -- into `<segment>.zst` files and removes the
+//! raw ones. Files are in the zstd seekable format: the segment is split into
+//! independently compressed frames of 256 KiB, followed by a seek
+//! table in a skippable frame, so reading from the middle of a segment
+//! decompresses only the frames from that position on.
+//!
+//! Readers of local WAL ([`crate::wal_storage::WalReader`], WAL backup and
+//! pull_timeline snapshots) fall back to the compressed file when the raw one
+//! is absent and always see the uncompressed content; in particular, remote
+//! storage and other safekeepers get raw segments.
+use std::ffi::OsStr;
+use std::io::{ErrorKind, SeekFrom};
+use std::pin::Pin;
+
+use anyhow::{Context, Result, bail, ensure};
+use async_compression::Level;
+use async_compression::tokio::write::{ZstdDecoder, ZstdEncoder};
+use bytes::Bytes;
+use camino::{Utf8Path, Utf8PathBuf};
+use postgres_ffi::XLogSegNo;
+use postgres_ffi::v14::xlog_utils::{IsXLogFileName, XLogFromFileName};
+use tokio::fs::{self, File, remove_file};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::StreamReader;
+use tracing::*;
+use utils::crashsafe::durable_rename;
+
+use crate::metrics::{WAL_COMPRESSED_BYTES, WAL_COMPRESSED_SEGMENTS};
+use crate::wal_storage::wal_file_paths;
+
+/// Suffix of compressed segment file names.
+pub const COMPRESSED_SEGMENT_SUFFIX: &str = ".zst";
+
+/// Size of uncompressed data in one frame.
+const FRAME_SIZE: usize = 256 * 1024;
+
+/// Seekable format constants, see
+/// <https://github.com/facebook/zstd/blob/dev/contrib/seekable_format/zstd_seekable_compression_format.md>
+const SKIPPABLE_FRAME_MAGIC: u32 = 0x184D2A5E;
+const SEEKABLE_MAGIC: u32 = 0x8F92EAB1;
+const SKIPPABLE_FRAME_HEADER_SIZE: usize = 8;
+const SEEK_TABLE_FOOTER_SIZE: usize = 9;
+const SEEK_TABLE_ENTRY_SIZE: usize = 8;
+/// Seek table descriptor flag indicating entries have checksums.
+const CHECKSUM_FLAG: u8 = 0x80;
+
+/// Name of the temp file compressed segment is baked in.
+const TMP_FILE_NAME: &str = "walcompresstmp";
+
+#[derive(Debug, Clone, Copy)]
+struct FrameEntry {
+    compressed_size: u32,
+    decompressed_size: u32,
+}
+
+/// Path of the compressed file of WAL segment at `wal_file_path`.
+pub fn compressed_segment_path(wal_file_path: &Utf8Path) -> Utf8PathBuf {
+    Utf8PathBuf::from(format!("{wal_file_path}{COMPRESSED_SEGMENT_SUFFIX}"))
+}
+
+/// Returns true if `fname` is a name of compressed WAL segment.
+pub fn is_compressed_segment_file_name(fname: &OsStr) -> bool {
+    fname
+        .to_str()
+        .and_then(|f| f.strip_suffix(COMPRESSED_SEGMENT_SUFFIX))
+        .is_some_and(|base| IsXLogFileName(OsStr::new(base)))
+}
+
+/// Compress all complete raw segments in `timeline_dir` with segno <=
+/// `segno_up_to`. Caller must ensure these segments are not written anymore.
+pub async fn compress_segments_up_to(
+    timeline_dir: &Utf8Path,
+    wal_seg_size: usize,
+    segno_up_to: XLogSegNo,
+    level: i32,
+    no_sync: bool,
+) -> Result<()> {
+    let mut segnos = Vec::new();
+    let mut entries = fs::read_dir(timeline_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let fname = entry.file_name();
+        if !IsXLogFileName(&fname) {
+            continue;
+        }
+        let (segno, _) = XLogFromFileName(&fname, wal_seg_size)?;
+        if segno <= segno_up_to {
+            segnos.push(segno);
+        }
+    }
+    segnos.sort_unstable();
+
+    for &segno in &segnos {
+        compress_segment(timeline_dir, segno, wal_seg_size, level, no_sync).await?;
+    }
+    if let (Some(first), Some(last)) = (segnos.first(), segnos.last()) {
+        info!(
+            "compressed {} WAL segments [{}; {}]",
+            segnos.len(),
+            first,
+            last
+        );
+    }
+    Ok(())
+}
+
+/// Compress complete segment `segno` and remove the raw file.
+async fn compress_segment(
+    timeline_dir: &Utf8Path,
+    segno: XLogSegNo,
+    wal_seg_size: usize,
+    level: i32,
+    no_sync: bool,
+) -> Result<()> {
+    let (wal_file_path, _) = wal_file_paths(timeline_dir, segno, wal_seg_size);
+    let raw = match fs::read(&wal_file_path).await {
+        Ok(raw) => raw,
+        // removed concurrently, nothing to do
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {wal_file_path}")),
+    };
+    ensure!(
+        raw.len() == wal_seg_size,
+        "unexpected size {} of segment {wal_file_path}",
+        raw.len()
+    );
+
+    // Compressing a segment takes a while at higher levels, don't hog the
+    // runtime's workers with it.
+    let compressed = tokio::task::spawn_blocking(move || encode_segment(&raw, level))
+        .await
+        .context("WAL segment compression task panicked")??;
+
+    let tmp_path = timeline_dir.join(TMP_FILE_NAME);
+    let compressed_path = compressed_segment_path(&wal_file_path);
+    fs::write(&tmp_path, &compressed).await?;
+    durable_rename(&tmp_path, &compressed_path, !no_sync).await?;
+    // Readers prefer the raw file, so it's fine to crash before this; the
+    // segment will be compressed once again.
+    remove_file(&wal_file_path).await?;
+
+    WAL_COMPRESSED_SEGMENTS.inc();
+    WAL_COMPRESSED_BYTES.inc_by(compressed.len() as u64);
+    debug!("compressed {} to {} bytes", wal_file_path, compressed.len());
+    Ok(())
+}
+
+/// Compress `raw` into frames of the seekable format, followed by the seek
+/// table. CPU bound, run it on a blocking thread.
+fn encode_segment(raw: &[u8], level: i32) -> Result<Vec<u8>> {
+    let mut compressed = Vec::new();
+    let mut frames = Vec::with_capacity(raw.len().div_ceil(FRAME_SIZE));
+    for chunk in raw.chunks(FRAME_SIZE) {
+        // Writing into a Vec never blocks, so driving the async encoder
+        // in place is fine.
+        let frame = futures::executor::block_on(async {
+            let mut encoder = ZstdEncoder::with_quality(Vec::new(), Level::Precise(level));
+            encoder.write_all(chunk).await?;
+            encoder.shutdown().await?;
+            anyhow::Ok(encoder.into_inner())
+        })?;
+        frames.push(FrameEntry {
+            compressed_size: frame.len() as u32,
+            decompressed_size: chunk.len() as u32,
+        });
+        compressed.extend_from_slice(&frame);
+    }
+    write_seek_table(&mut compressed, &frames);
+    Ok(compressed)
+}
+
+fn write_seek_table(buf: &mut Vec<u8>, frames: &[FrameEntry]) {
+    let table_size = frames.len() * SEEK_TABLE_ENTRY_SIZE + SEEK_TABLE_FOOTER_SIZE;
+    buf.extend_from_slice(&SKIPPABLE_FRAME_MAGIC.to_le_bytes());
+    buf.extend_from_slice(&(table_size as u32).to_le_bytes());
+    for frame in frames {
+        buf.extend_from_slice(&frame.compressed_size.to_le_bytes());
+        buf.extend_from_slice(&frame.decompressed_size.to_le_bytes());
+    }
+    buf.extend_from_slice(&(frames.len() as u32).to_le_bytes());
+    // descriptor: no checksums
+    buf.push(0);
+    buf.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+}
+
+async fn read_seek_table(file: &mut File, path: &Utf8Path) -> Result<Vec<FrameEntry>> {
+    let file_size = file.metadata().await?.len() as usize;
+    ensure!(
+        file_size >= SKIPPABLE_FRAME_HEADER_SIZE + SEEK_TABLE_FOOTER_SIZE,
+        "{path} is too short"
+    );
+
+    let mut footer = [0u8; SEEK_TABLE_FOOTER_SIZE];
+    file.seek(SeekFrom::End(-(SEEK_TABLE_FOOTER_SIZE as i64)))
+        .await?;
+    file.read_exact(&mut footer).await?;
+    let n_frames = u32::from_le_bytes(footer[0..4].try_into().unwrap()) as usize;
+    let descriptor = footer[4];
+    let magic = u32::from_le_bytes(footer[5..9].try_into().unwrap());
+    if magic != SEEKABLE_MAGIC {
+        bail!("{path} has no seek table");
+    }
+    let entry_size = if descriptor & CHECKSUM_FLAG != 0 {
+        SEEK_TABLE_ENTRY_SIZE + 4
+    } else {
+        SEEK_TABLE_ENTRY_SIZE
+    };
+
+    let table_size = n_frames * entry_size + SEEK_TABLE_FOOTER_SIZE;
+    ensure!(
+        file_size >= SKIPPABLE_FRAME_HEADER_SIZE + table_size,
+        "{path} has corrupted seek table"
+    );
+    let mut table = vec![0u8; SKIPPABLE_FRAME_HEADER_SIZE + table_size];
+    file.seek(SeekFrom::End(-(table.len() as i64))).await?;
+    file.read_exact(&mut table).await?;
+    let frame_magic = u32::from_le_bytes(table[0..4].try_into().unwrap());
+    let frame_size = u32::from_le_bytes(table[4..8].try_into().unwrap()) as usize;
+    ensure!(
+        frame_magic == SKIPPABLE_FRAME_MAGIC && frame_size == table_size,
+        "{path} has corrupted seek table"
+    );
+
+    Ok(
+        table[SKIPPABLE_FRAME_HEADER_SIZE..SKIPPABLE_FRAME_HEADER_SIZE + n_frames * entry_size]
+            .chunks_exact(entry_size)
+            .map(|e| FrameEntry {
+                compressed_size: u32::from_le_bytes(e[0..4].try_into().unwrap()),
+                decompressed_size: u32::from_le_bytes(e[4..8].try_into().unwrap()),
+            })
+            .collect(),
+    )
+}
+
+async fn decompress_frame(frame: &[u8], decompressed_size: usize) -> std::io::Result<Bytes> {
+    let mut decoder = ZstdDecoder::new(Vec::with_capacity(decompressed_size));
+    decoder.write_all(frame).await?;
+    decoder.shutdown().await?;
+    let data = decoder.into_inner();
+    if data.len() != decompressed_size {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "decompressed frame has size {}, expected {}",
+                data.len(),
+                decompressed_size
+            ),
+        ));
+    }
+    Ok(Bytes::from(data))
+}
+
+/// Open compressed segment at `path` for reading uncompressed content from
+/// `offset`. Returns None if the file doesn't exist.
+pub async fn open_compressed_segment(
+    path: &Utf8Path,
+    offset: u64,
+) -> Result<Option<Pin<Box<dyn AsyncRead + Send + Sync>>>> {
+    let mut file = match File::open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to open {path}")),
+    };
+    let frames = read_seek_table(&mut file, path).await?;
+
+    // Find the frame containing offset.
+    let mut compressed_pos = 0u64;
+    let mut decompressed_pos = 0u64;
+    let mut first_frame = frames.len();
+    for (i, frame) in frames.iter().enumerate() {
+        if decompressed_pos + frame.decompressed_size as u64 > offset {
+            first_frame = i;
+            break;
+        }
+        compressed_pos += frame.compressed_size as u64;
+        decompressed_pos += frame.decompressed_size as u64;
+    }
+    ensure!(
+        offset >= decompressed_pos && (first_frame < frames.len() || offset == decompressed_pos),
+        "offset {offset} is beyond the end of {path}"
+    );
+    let skip = (offset - decompressed_pos) as usize;
+    file.seek(SeekFrom::Start(compressed_pos)).await?;
+
+    let frames = frames.into_iter().skip(first_frame);
+    let stream = futures::stream::try_unfold(
+        (file, frames, skip),
+        |(mut file, mut frames, skip)| async move {
+            let Some(frame) = frames.next() else {
+                return Ok(None);
+            };
+            let mut buf = vec![0u8; frame.compressed_size as usize];
+            file.read_exact(&mut buf).await?;
+            let data = decompress_frame(&buf, frame.decompressed_size as usize).await?;
+            Ok(Some((data.slice(skip..), (file, frames, 0))))
+        },
+    );
+    Ok(Some(Box::pin(StreamReader::new(stream))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_compress_and_read() {
+        let dir = camino_tempfile::tempdir().unwrap();
+        let wal_seg_size = 8 * FRAME_SIZE;
+        let segment: Vec<u8> = (0..wal_seg_size).map(|i| (i % 251) as u8).collect();
+        let (wal_file_path, _) = wal_file_paths(dir.path(), 1, wal_seg_size);
+        fs::write(&wal_file_path, &segment).await.unwrap();
+
+        compress_segments_up_to(dir.path(), wal_seg_size, 1, 1, true)
+            .await
+            .unwrap();
+        assert!(!fs::try_exists(&wal_file_path).await.unwrap());
+        let compressed_path = compressed_segment_path(&wal_file_path);
+        assert!(is_compressed_segment_file_name(OsStr::new(
+            compressed_path.file_name().unwrap()
+        )));
+
+        for offset in [0, 1, FRAME_SIZE, 3 * FRAME_SIZE + 17, wal_seg_size] {
+            let mut reader = open_compressed_segment(&compressed_path, offset as u64)
+                .await
+                .unwrap()
+                .unwrap();
+            let mut read = Vec::new();
+            reader.read_to_end(&mut read).await.unwrap();
+            assert_eq!(read, segment[offset..], "offset {offset}");
+        }
+
+        let missing = compressed_segment_path(&dir.path().join("missing"));
+        assert!(
+            open_compressed_segment(&missing, 0)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,291 @@
+//! Per-timeline and per-tenant quotas on WAL retained on local disk.
+//!
+//! A compute producing WAL faster than pageservers consume it makes the
+//! safekeeper retain more and more WAL, eventually filling the disk shared by
+//! all timelines on the node. To prevent that, timeline managers periodically
+//! report WAL retained by their timelines here and get back the timeline's
+//! [`WalQuotaState`]. Above `wal_quota_backpressure_ratio` of a quota the
+//! walproposer is asked to throttle through AppendResponse feedback; above
+//! the quota itself appends carrying WAL are rejected until WAL removal
+//! catches up.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use once_cell::sync::Lazy;
+use postgres_ffi::XLogSegNo;
+pub use safekeeper_api::models::WalQuotaState;
+use safekeeper_api::models::{TenantWalQuotaStatus, TimelineWalQuotaStatus};
+use utils::id::{TenantId, TenantTimelineId, TimelineId};
+use utils::lsn::Lsn;
+
+use crate::SafeKeeperConf;
+
+/// WAL retained by timelines on this safekeeper, reported by timeline
+/// managers.
+pub static RETAINED_WAL: Lazy<RetainedWal> = Lazy::new(RetainedWal::default);
+
+#[derive(Default)]
+pub struct RetainedWal {
+    tenants: Mutex<HashMap<TenantId, HashMap<TimelineId, u64>>>,
+}
+
+impl RetainedWal {
+    /// Record WAL retained by the timeline and return its quota state.
+    pub fn update(
+        &self,
+        conf: &SafeKeeperConf,
+        ttid: &TenantTimelineId,
+        retained_bytes: u64,
+    ) -> WalQuotaState {
+        let mut tenants = self.tenants.lock().unwrap();
+        let timelines = tenants.entry(ttid.tenant_id).or_default();
+        timelines.insert(ttid.timeline_id, retained_bytes);
+        let tenant_bytes = timelines.values().sum();
+        quota_state(conf, retained_bytes, tenant_bytes)
+    }
+
+    /// Forget the timeline, e.g. on deletion or eviction.
+    pub fn remove(&self, ttid: &TenantTimelineId) {
+        let mut tenants = self.tenants.lock().unwrap();
+        if let Some(timelines) = tenants.get_mut(&ttid.tenant_id) {
+            timelines.remove(&ttid.timeline_id);
+            if timelines.is_empty() {
+                tenants.remove(&ttid.tenant_id);
+            }
+        }
+    }
+
+    /// Quotas of the tenant and their current usage.
+    pub fn tenant_status(
+        &self,
+        conf: &SafeKeeperConf,
+        tenant_id: &TenantId,
+    ) -> TenantWalQuotaStatus {
+        let tenants = self.tenants.lock().unwrap();
+        let mut timelines: Vec<(TimelineId, u64)> = tenants
+            .get(tenant_id)
+            .map(|t| t.iter().map(|(id, bytes)| (*id, *bytes)).collect())
+            .unwrap_or_default();
+        drop(tenants);
+        timelines.sort_by_key(|(id, _)| *id);
+
+        let tenant_bytes = timelines.iter().map(|(_, bytes)| bytes).sum();
+        TenantWalQuotaStatus {
+            timeline_quota_bytes: Some(conf.timeline_wal_quota_bytes).filter(|q| *q > 0),
+            tenant_quota_bytes: Some(conf.tenant_wal_quota_bytes).filter(|q| *q > 0),
+            backpressure_ratio: conf.wal_quota_backpressure_ratio,
+            retained_wal_bytes: tenant_bytes,
+            timelines: timelines
+                .into_iter()
+                .map(|(timeline_id, bytes)| TimelineWalQuotaStatus {
+                    timeline_id,
+                    retained_wal_bytes: bytes,
+                    state: quota_state(conf, bytes, tenant_bytes),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Quota state of a timeline retaining `timeline_bytes` of WAL in a tenant
+/// retaining `tenant_bytes`. Quotas set to 0 are disabled.
+fn quota_state(conf: &SafeKeeperConf, timeline_bytes: u64, tenant_bytes: u64) -> WalQuotaState {
+    let timeline_quota = conf.timeline_wal_quota_bytes;
+    let tenant_quota = conf.tenant_wal_quota_bytes;
+    let above = |used: u64, quota: u64, ratio: f64| quota > 0 && used as f64 > quota as f64 * ratio;
+
+    if above(timeline_bytes, timeline_quota, 1.0) {
+        WalQuotaState::TimelineExceeded
+    } else if above(tenant_bytes, tenant_quota, 1.0) {
+        WalQuotaState::TenantExceeded
+    } else if above(
+        timeline_bytes,
+        timeline_quota,
+        conf.wal_quota_backpressure_ratio,
+    ) || above(
+        tenant_bytes,
+        tenant_quota,
+        conf.wal_quota_backpressure_ratio,
+    ) {
+        WalQuotaState::Backpressure
+    } else {
+        WalQuotaState::Ok
+    }
+}
+
+/// Label of the state in metrics.
+pub fn state_label(state: WalQuotaState) -> &'static str {
+    match state {
+        WalQuotaState::Ok => "ok",
+        WalQuotaState::Backpressure => "backpressure",
+        WalQuotaState::TimelineExceeded => "timeline_exceeded",
+        WalQuotaState::TenantExceeded => "tenant_exceeded",
+    }
+}
+
+/// [`WalQuotaState`] of a timeline, set by the manager and read on appends.
+pub struct AtomicWalQuotaState(AtomicU8);
+
+impl AtomicWalQuotaState {
+    pub fn new() -> Self {
+        Self(AtomicU8::new(WalQuotaState::Ok as u8))
+    }
+
+    pub fn load(&self) -> WalQuotaState {
+        match self.0.load(Ordering::Relaxed) {
+            0 => WalQuotaState::Ok,
+            1 => WalQuotaState::Backpressure,
+            2 => WalQuotaState::TimelineExceeded,
+            _ => WalQuotaState::TenantExceeded,
+        }
+    }
+
+    pub fn store(&self, state: WalQuotaState) {
+        self.0.store(state as u8, Ordering::Relaxed);
+    }
+}
+
+impl Default for AtomicWalQuotaState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// WAL retained on disk by a resident timeline: all segments from the first
+/// not removed one up to the current one, compressed segments are accounted
+/// as full.
+pub fn retained_wal_bytes(
+    last_removed_segno: XLogSegNo,
+    timeline_start_lsn: Lsn,
+    flush_lsn: Lsn,
+    wal_seg_size: usize,
+) -> u64 {
+    let first_segno = std::cmp::max(
+        last_removed_segno + 1,
+        timeline_start_lsn.segment_number(wal_seg_size),
+    );
+    let flush_segno = flush_lsn.segment_number(wal_seg_size);
+    (flush_segno + 1).saturating_sub(first_segno) * wal_seg_size as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1 << 20;
+
+    fn conf(timeline_quota: u64, tenant_quota: u64) -> SafeKeeperConf {
+        SafeKeeperConf {
+            timeline_wal_quota_bytes: timeline_quota,
+            tenant_wal_quota_bytes: tenant_quota,
+            wal_quota_backpressure_ratio: 0.5,
+            ..SafeKeeperConf::dummy()
+        }
+    }
+
+    #[test]
+    fn test_quota_state() {
+        let c = conf(100 * MB, 0);
+        assert_eq!(quota_state(&c, 10 * MB, 1000 * MB), WalQuotaState::Ok);
+        assert_eq!(
+            quota_state(&c, 60 * MB, 60 * MB),
+            WalQuotaState::Backpressure
+        );
+        assert_eq!(
+            quota_state(&c, 101 * MB, 101 * MB),
+            WalQuotaState::TimelineExceeded
+        );
+
+        let c = conf(0, 0);
+        assert_eq!(quota_state(&c, u64::MAX, u64::MAX), WalQuotaState::Ok);
+    }
+
+    #[test]
+    fn test_tenant_quota() {
+        let c = conf(100 * MB, 150 * MB);
+        let retained = RetainedWal::default();
+        let tenant_id = TenantId::generate();
+        let tli1 = TenantTimelineId::new(tenant_id, TimelineId::generate());
+        let tli2 = TenantTimelineId::new(tenant_id, TimelineId::generate());
+        let other = TenantTimelineId::generate();
+
+        assert_eq!(retained.update(&c, &tli1, 40 * MB), WalQuotaState::Ok);
+        assert_eq!(
+            retained.update(&c, &other, 90 * MB),
+            WalQuotaState::Backpressure
+        );
+        assert_eq!(
+            retained.update(&c, &tli2, 40 * MB),
+            WalQuotaState::Backpressure
+        );
+        assert_eq!(
+            retained.update(&c, &tli2, 120 * MB),
+            WalQuotaState::TimelineExceeded
+        );
+        assert_eq!(
+            retained.update(&c, &tli1, 40 * MB),
+            WalQuotaState::TenantExceeded
+        );
+
+        let status = retained.tenant_status(&c, &tenant_id);
+        assert_eq!(status.retained_wal_bytes, 160 * MB);
+        assert_eq!(status.timelines.len(), 2);
+
+        retained.remove(&tli2);
+        assert_eq!(retained.update(&c, &tli1, 40 * MB), WalQuotaState::Ok);
+        retained.remove(&tli1);
+        assert!(retained.tenant_status(&c, &tenant_id).timelines.is_empty());
+    }
+
+    /// After restart WAL removed before it must not be accounted until the
+    /// first WAL removal task finishes.
+    #[tokio::test]
+    async fn test_retained_wal_after_restart() {
+        let dir = camino_tempfile::tempdir().unwrap();
+        let wal_seg_size = 16 * MB as usize;
+        let seg_lsn = |segno: u64| Lsn(segno * wal_seg_size as u64);
+
+        // Segments up to 9 were removed before restart, 10 is compressed, 11
+        // is raw and 12 is the current partial one.
+        let (path10, _) = crate::wal_storage::wal_file_paths(dir.path(), 10, wal_seg_size);
+        let (path11, _) = crate::wal_storage::wal_file_paths(dir.path(), 11, wal_seg_size);
+        let (_, partial12) = crate::wal_storage::wal_file_paths(dir.path(), 12, wal_seg_size);
+        let compressed10 = crate::wal_compression::compressed_segment_path(&path10);
+        for path in [&compressed10, &path11, &partial12] {
+            tokio::fs::write(path, b"").await.unwrap();
+        }
+        tokio::fs::write(dir.path().join("safekeeper.control"), b"")
+            .await
+            .unwrap();
+
+        let first_segno = crate::wal_storage::first_segment_on_disk(dir.path(), wal_seg_size)
+            .await
+            .unwrap();
+        assert_eq!(first_segno, Some(10));
+        let last_removed_segno = first_segno.unwrap() - 1;
+        assert_eq!(
+            retained_wal_bytes(
+                last_removed_segno,
+                seg_lsn(1),
+                seg_lsn(12) + 100,
+                wal_seg_size
+            ),
+            3 * wal_seg_size as u64
+        );
+        // Without seeding all WAL since timeline start would be accounted.
+        assert_eq!(
+            retained_wal_bytes(0, seg_lsn(1), seg_lsn(12) + 100, wal_seg_size),
+            12 * wal_seg_size as u64
+        );
+
+        let empty = camino_tempfile::tempdir().unwrap();
+        assert_eq!(
+            crate::wal_storage::first_segment_on_disk(empty.path(), wal_seg_size)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+}
@@ -6,6 +6,12 @@
 //! - 000000010000000000000002.partial
 //!
 //! Note that last file has `.partial` suffix, that's different from postgres.
+//!
+//! Witness safekeepers don't store WAL at all: they only track record
+//! boundaries of the received stream and durably remember the flush position
+//! in the `witness_flush_lsn` file.
+//!
+//! Closed segments might be recompressed, see [`crate::wal_compression`].
 
 use std::cmp::{max, min};
 use std::future::Future;
@@ -21,12 +27,12 @@
 use postgres_ffi::{PG_TLI, XLogFileName, XLogSegNo, dispatch_pgversion};
 use postgres_versioninfo::{PgMajorVersion, PgVersionId};
 use pq_proto::SystemId;
-use remote_storage::RemotePath;
+use remote_storage::{DownloadError, RemotePath};
 use std::sync::Arc;
 use tokio::fs::{self, File, OpenOptions, remove_file};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tracing::*;
-use utils::crashsafe::durable_rename;
+use utils::crashsafe::{durable_rename, fsync_async};
 use utils::id::TenantTimelineId;
 use utils::lsn::Lsn;
 
@@ -36,6 +42,9 @@
 };
 use crate::state::TimelinePersistentState;
 use crate::wal_backup::{WalBackup, read_object, remote_timeline_path};
+use crate::wal_compression::{
+    compressed_segment_path, is_compressed_segment_file_name, open_compressed_segment,
+};
 
 pub trait Storage {
     // Last written LSN.
@@ -160,26 +169,51 @@ pub struct PhysicalStorage {
     ///
     /// [`write_lsn`]: Self::write_lsn
     pending_wal_truncation: bool,
+
+    /// True if this safekeeper is a witness of the timeline: WAL is received
+    /// and decoded, but not written to disk; only the flush position is
+    /// persisted.
+    witness: bool,
+
+    /// Cached open `witness_flush_lsn` file, used only in witness mode.
+    witness_file: Option<File>,
 }
 
+/// Name of the file where witness keeps the position of acknowledged WAL.
+pub const WITNESS_FLUSH_LSN_FILE_NAME: &str = "witness_flush_lsn";
+const WITNESS_FLUSH_LSN_FILE_SIZE: usize = 8 + 4;
+
 impl PhysicalStorage {
     /// Create new storage. If commit_lsn is not zero, flush_lsn is tried to be restored from
     /// the disk. Otherwise, all LSNs are set to zero.
+    ///
+    /// If `witness` is true, no WAL is stored and flush_lsn is restored from
+    /// the `witness_flush_lsn` file instead.
     pub fn new(
         ttid: &TenantTimelineId,
         timeline_dir: &Utf8Path,
         state: &TimelinePersistentState,
+        witness: bool,
         no_sync: bool,
     ) -> Result<PhysicalStorage> {
         let wal_seg_size = state.server.wal_seg_size as usize;
 
+        // Witness has no WAL to scan; take the persisted flush position, which
+        // is always a record boundary. It might be missing or behind
+        // commit_lsn (e.g. right after pull_timeline), commit_lsn is fine then.
+        //
         // Find out where stored WAL ends, starting at commit_lsn which is a
         // known recent record boundary (unless we don't have WAL at all).
         //
         // NB: find_end_of_wal MUST be backwards compatible with the previously
         // written WAL. If find_end_of_wal fails to read any WAL written by an
         // older version of the code, we could lose data forever.
-        let write_lsn = if state.commit_lsn == Lsn(0) {
+        let write_lsn = if witness {
+            max(
+                read_witness_flush_lsn(timeline_dir)?.unwrap_or(Lsn(0)),
+                state.commit_lsn,
+            )
+        } else if state.commit_lsn == Lsn(0) {
             Lsn(0)
         } else {
             let version = PgMajorVersion::try_from(state.server.pg_version).unwrap();
@@ -236,6 +270,8 @@ pub fn new(
             ),
             file: None,
             pending_wal_truncation: true,
+            witness,
+            witness_file: None,
         })
     }
 
@@ -267,6 +303,40 @@ async fn fdatasync_file(&mut self, file: &File) -> Result<()> {
         Ok(())
     }
 
+    /// Durably store witness flush position. The file is overwritten in place:
+    /// it is smaller than a sector, and the checksum guards from torn writes.
+    async fn persist_witness_flush_lsn(&mut self, lsn: Lsn) -> Result<()> {
+        let mut buf = Vec::with_capacity(WITNESS_FLUSH_LSN_FILE_SIZE);
+        buf.extend_from_slice(&lsn.0.to_le_bytes());
+        let checksum = crc32c::crc32c(&buf);
+        buf.extend_from_slice(&checksum.to_le_bytes());
+
+        let (mut file, created) = match self.witness_file.take() {
+            Some(file) => (file, false),
+            None => {
+                let path = self.timeline_dir.join(WITNESS_FLUSH_LSN_FILE_NAME);
+                let created = !fs::try_exists(&path).await?;
+                let file = OpenOptions::new()
+                    .create(true)
+                    .truncate(false)
+                    .write(true)
+                    .open(&path)
+                    .await
+                    .with_context(|| format!("failed to open {path}"))?;
+                (file, created)
+            }
+        };
+        file.seek(SeekFrom::Start(0)).await?;
+        file.write_all(&buf).await?;
+        file.flush().await?;
+        self.fdatasync_file(&file).await?;
+        if created && !self.no_sync {
+            fsync_async(&self.timeline_dir).await?;
+        }
+        self.witness_file = Some(file);
+        Ok(())
+    }
+
     /// Open or create WAL segment file. Caller must call seek to the wanted position.
     /// Returns `file` and `is_partial`.
     async fn open_or_create(&mut self, segno: XLogSegNo) -> Result<(File, bool)> {
@@ -420,6 +490,11 @@ async fn initialize_first_segment(&mut self, init_lsn: Lsn) -> Result<()> {
             .with_label_values(&["initialize_first_segment"])
             .start_timer();
 
+        if self.witness {
+            // nothing to initialize, witness doesn't store WAL
+            return Ok(());
+        }
+
         let segno = init_lsn.segment_number(self.wal_seg_size);
         let (mut file, _) = self.open_or_create(segno).await?;
         let major_pg_version = PgMajorVersion::try_from(self.pg_version).unwrap();
@@ -459,10 +534,15 @@ async fn write_wal(&mut self, startpos: Lsn, buf: &[u8]) -> Result<()> {
             );
         }
 
-        let write_seconds = time_io_closure(self.write_exact(startpos, buf)).await?;
-        // WAL is written, updating write metrics
-        self.metrics.observe_write_seconds(write_seconds);
-        self.metrics.observe_write_bytes(buf.len());
+        if self.witness {
+            // Only advance the position; decoder below still tracks records.
+            self.write_lsn = startpos + buf.len() as u64;
+        } else {
+            let write_seconds = time_io_closure(self.write_exact(startpos, buf)).await?;
+            // WAL is written, updating write metrics
+            self.metrics.observe_write_seconds(write_seconds);
+            self.metrics.observe_write_bytes(buf.len());
+        }
 
         // Figure out the last record's end LSN and update `write_record_lsn`
         // (if we got a whole record). The write may also have closed and
@@ -498,7 +578,14 @@ async fn flush_wal(&mut self) -> Result<()> {
             return Ok(());
         }
 
-        if let Some(unflushed_file) = self.file.take() {
+        if self.witness {
+            let write_record_lsn = self.write_record_lsn;
+            self.persist_witness_flush_lsn(write_record_lsn)
+                .await
+                /* BEGIN_HADRON */
+                .inspect_err(|_| WAL_DISK_IO_ERRORS.inc())?;
+            /* END_HADRON */
+        } else if let Some(unflushed_file) = self.file.take() {
             self.fdatasync_file(&unflushed_file)
                 .await
                 /* BEGIN_HADRON */
@@ -567,6 +654,13 @@ async fn truncate_wal(&mut self, end_pos: Lsn) -> Result<()> {
         self.write_record_lsn = end_pos;
         self.flush_record_lsn = end_pos;
 
+        if self.witness {
+            self.persist_witness_flush_lsn(end_pos).await?;
+            self.pending_wal_truncation = false;
+            info!("truncated witness flush position to {}", end_pos);
+            return Ok(());
+        }
+
         // Close previously opened file, if any
         if let Some(unflushed_file) = self.file.take() {
             self.fdatasync_file(&unflushed_file).await?;
@@ -608,6 +702,7 @@ fn remove_up_to(&self, segno_up_to: XLogSegNo) -> BoxFuture<'static, anyhow::Res
     fn close(&mut self) {
         // close happens in destructor
         let _open_file = self.file.take();
+        let _witness_file = self.witness_file.take();
     }
 
     fn get_metrics(&self) -> WalStorageMetrics {
@@ -615,6 +710,25 @@ fn get_metrics(&self) -> WalStorageMetrics {
     }
 }
 
+/// Read flush position persisted by witness, if any.
+fn read_witness_flush_lsn(timeline_dir: &Utf8Path) -> Result<Option<Lsn>> {
+    let path = timeline_dir.join(WITNESS_FLUSH_LSN_FILE_NAME);
+    let buf = match std::fs::read(&path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {path}")),
+    };
+    if buf.len() != WITNESS_FLUSH_LSN_FILE_SIZE {
+        bail!("{path} has unexpected size {}", buf.len());
+    }
+    let (lsn_buf, checksum_buf) = buf.split_at(8);
+    let checksum = u32::from_le_bytes(checksum_buf.try_into().unwrap());
+    if crc32c::crc32c(lsn_buf) != checksum {
+        bail!("{path} checksum mismatch");
+    }
+    Ok(Some(Lsn(u64::from_le_bytes(lsn_buf.try_into().unwrap()))))
+}
+
 /// Remove all WAL segments in timeline_dir that match the given predicate.
 async fn remove_segments_from_disk(
     timeline_dir: &Utf8Path,
@@ -634,7 +748,10 @@ async fn remove_segments_from_disk(
         let entry_path = entry.path();
         let fname = entry_path.file_name().unwrap();
         /* Ignore files that are not XLOG segments */
-        if !IsXLogFileName(fname) && !IsPartialXLogFileName(fname) {
+        if !IsXLogFileName(fname)
+            && !IsPartialXLogFileName(fname)
+            && !is_compressed_segment_file_name(fname)
+        {
             continue;
         }
         let (segno, _) = XLogFromFileName(fname, wal_seg_size)?;
@@ -656,6 +773,29 @@ async fn remove_segments_from_disk(
     Ok(())
 }
 
+/// Find the first WAL segment (raw, partial or compressed) present in
+/// timeline_dir, if any. WAL below it has been removed.
+pub async fn first_segment_on_disk(
+    timeline_dir: &Utf8Path,
+    wal_seg_size: usize,
+) -> Result<Option<XLogSegNo>> {
+    let mut first_segno = None;
+    let mut entries = fs::read_dir(timeline_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let entry_path = entry.path();
+        let fname = entry_path.file_name().unwrap();
+        if !IsXLogFileName(fname)
+            && !IsPartialXLogFileName(fname)
+            && !is_compressed_segment_file_name(fname)
+        {
+            continue;
+        }
+        let (segno, _) = XLogFromFileName(fname, wal_seg_size)?;
+        first_segno = Some(first_segno.map_or(segno, |first| min(first, segno)));
+    }
+    Ok(first_segno)
+}
+
 pub struct WalReader {
     remote_path: RemotePath,
     timeline_dir: Utf8PathBuf,
@@ -694,22 +834,22 @@ pub fn new(
             warn!("timeline_start_lsn uninitialized before initializing wal reader");
         }
 
-        if start_pos
-            < state
-                .timeline_start_lsn
-                .segment_lsn(state.server.wal_seg_size as usize)
-        {
-            bail!(
-                "Requested streaming from {}, which is before the start of the timeline {}, and also doesn't start at the first segment of that timeline",
-                start_pos,
-                state.timeline_start_lsn
+        let wal_seg_size = state.server.wal_seg_size as usize;
+        if start_pos < state.timeline_start_lsn.segment_lsn(wal_seg_size) {
+            info!(
+                "requested streaming from {}, which is before the first segment of the timeline starting at {}",
+                start_pos, state.timeline_start_lsn
             );
+            return Err(segment_removed_error(
+                start_pos.segment_number(wal_seg_size),
+                wal_seg_size,
+            ));
         }
 
         Ok(Self {
             remote_path: remote_timeline_path(ttid)?,
             timeline_dir,
-            wal_seg_size: state.server.wal_seg_size as usize,
+            wal_seg_size,
             pos: start_pos,
             wal_segment: None,
             wal_backup,
@@ -812,21 +952,37 @@ async fn open_segment(&self) -> Result<Pin<Box<dyn AsyncRead + Send + Sync>>> {
             if let Some((mut file, _)) = res {
                 file.seek(SeekFrom::Start(xlogoff as u64)).await?;
                 return Ok(Box::pin(file));
-            } else {
-                // NotFound is expected, fall through to remote read
             }
+            let (wal_file_path, _) = wal_file_paths(&self.timeline_dir, segno, self.wal_seg_size);
+            let compressed_path = compressed_segment_path(&wal_file_path);
+            if let Some(reader) = open_compressed_segment(&compressed_path, xlogoff as u64).await? {
+                return Ok(reader);
+            }
+            // NotFound is expected, fall through to remote read
         }
 
         // Try to open remote file, if remote reads are enabled
         if let Some(storage) = self.wal_backup.get_storage() {
             let remote_wal_file_path = self.remote_path.join(&wal_file_name);
-            return read_object(&storage, &remote_wal_file_path, xlogoff as u64).await;
+            match read_object(&storage, &remote_wal_file_path, xlogoff as u64).await {
+                Err(e) if matches!(e.downcast_ref(), Some(DownloadError::NotFound)) => {}
+                res => return res,
+            }
         }
 
-        bail!("WAL segment is not found")
+        Err(segment_removed_error(segno, self.wal_seg_size))
     }
 }
 
+/// Error for WAL which is not available here. Worded as in Postgres walsender,
+/// so that vanilla replication clients report it sensibly.
+fn segment_removed_error(segno: XLogSegNo, wal_seg_size: usize) -> anyhow::Error {
+    anyhow::anyhow!(
+        "requested WAL segment {} has already been removed",
+        XLogFileName(PG_TLI, segno, wal_seg_size)
+    )
+}
+
 /// Helper function for opening WAL segment `segno` in `dir`. Returns file and
 /// whether it is .partial.
 pub(crate) async fn open_wal_file(
@@ -0,0 +1,456 @@
+//! WAL integrity verification.
+//!
+//! Walks WAL segments of a timeline, locally or in remote storage, decoding
+//! every record with [`WalStreamDecoder`], which checks page headers and
+//! record CRCs, and checks that valid WAL is continuous from its start up to
+//! the end known from the control file. Reports the first bad position.
+//! Unlike the digest, this tells whether and where WAL is broken.
+//!
+//! Used by the verify_wal HTTP endpoint on a running safekeeper and by the
+//! offline `--verify-wal` mode of the binary.
+
+use std::cmp::{max, min};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use postgres_ffi::v14::bindings::{XLogLongPageHeaderData, XLogPageHeaderData};
+use postgres_ffi::v14::xlog_utils::{
+    IsPartialXLogFileName, IsXLogFileName, XLogFromFileName, XLogSegNoOffsetToRecPtr,
+};
+use postgres_ffi::waldecoder::WalStreamDecoder;
+use postgres_ffi::{
+    PG_TLI, XLOG_SIZE_OF_XLOG_LONG_PHD, XLOG_SIZE_OF_XLOG_SHORT_PHD, XLogFileName, XLogSegNo,
+    dispatch_pgversion, pg_constants,
+};
+use postgres_versioninfo::PgMajorVersion;
+use remote_storage::{DownloadError, GenericRemoteStorage};
+use safekeeper_api::models::{TimelineWalVerifyResponse, WalVerifyError, WalVerifySource};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::info;
+use utils::id::TenantTimelineId;
+use utils::lsn::Lsn;
+
+use crate::state::TimelinePersistentState;
+use crate::timeline::{Timeline, WalResidentTimeline};
+use crate::wal_backup::{read_object, remote_timeline_path};
+use crate::wal_compression::{
+    compressed_segment_path, is_compressed_segment_file_name, open_compressed_segment,
+};
+use crate::wal_storage::{open_wal_file, wal_file_paths};
+
+/// Up to where WAL must be valid.
+#[derive(Debug, Clone, Copy)]
+pub enum VerifyEnd {
+    /// Up to this LSN, which is a record boundary, e.g. flush_lsn.
+    RecordBoundary(Lsn),
+    /// Up to this LSN, a record may cross it.
+    Lsn(Lsn),
+    /// Until WAL ends, which must not be before commit_lsn.
+    EndOfWal,
+}
+
+/// Where to read WAL segments from.
+pub enum SegmentSource<'a> {
+    Local(&'a Utf8Path),
+    Remote(&'a GenericRemoteStorage),
+}
+
+impl SegmentSource<'_> {
+    fn kind(&self) -> WalVerifySource {
+        match self {
+            SegmentSource::Local(_) => WalVerifySource::Local,
+            SegmentSource::Remote(_) => WalVerifySource::Remote,
+        }
+    }
+}
+
+/// Verify WAL of a running timeline. By default local WAL is verified up to
+/// flush_lsn and remote WAL up to backup_lsn.
+pub async fn verify_timeline(
+    tli: &WalResidentTimeline,
+    source: WalVerifySource,
+    from_lsn: Option<Lsn>,
+    until_lsn: Option<Lsn>,
+) -> Result<TimelineWalVerifyResponse> {
+    // Segments must not be removed between looking up the oldest local one
+    // and reading it.
+    let _hold = match source {
+        WalVerifySource::Local => Some(WalRemovalHold::new(tli).await),
+        WalVerifySource::Remote => None,
+    };
+    let (mem_state, state) = tli.get_state().await;
+    let flush_lsn = tli.get_flush_lsn().await;
+    let timeline_dir = tli.get_timeline_dir();
+    let storage = tli.wal_backup.get_storage();
+
+    let (source, known_end, end) = match source {
+        WalVerifySource::Local => {
+            let end = match until_lsn {
+                Some(lsn) if lsn < flush_lsn => VerifyEnd::Lsn(lsn),
+                _ => VerifyEnd::RecordBoundary(flush_lsn),
+            };
+            (SegmentSource::Local(&timeline_dir), flush_lsn, end)
+        }
+        WalVerifySource::Remote => {
+            let storage = storage
+                .as_deref()
+                .context("remote storage is not configured")?;
+            let backup_lsn = max(state.backup_lsn, mem_state.backup_lsn);
+            let until = until_lsn.map_or(backup_lsn, |lsn| min(lsn, backup_lsn));
+            (
+                SegmentSource::Remote(storage),
+                backup_lsn,
+                VerifyEnd::Lsn(until),
+            )
+        }
+    };
+    verify_wal(&tli.ttid, &state, &source, known_end, from_lsn, end).await
+}
+
+/// Prevents removal of local WAL segments while they are being verified.
+struct WalRemovalHold(Arc<Timeline>);
+
+impl WalRemovalHold {
+    async fn new(tli: &WalResidentTimeline) -> Self {
+        tli.write_shared_state().await.wal_removal_holds += 1;
+        WalRemovalHold(tli.tli.clone())
+    }
+}
+
+impl Drop for WalRemovalHold {
+    fn drop(&mut self) {
+        let tli = self.0.clone();
+        tokio::spawn(async move {
+            tli.write_shared_state().await.wal_removal_holds -= 1;
+        });
+    }
+}
+
+/// Verify WAL of the timeline with control file `state`, starting at the
+/// first available segment at or after `from_lsn`.
+pub async fn verify_wal(
+    ttid: &TenantTimelineId,
+    state: &TimelinePersistentState,
+    source: &SegmentSource<'_>,
+    known_end: Lsn,
+    from_lsn: Option<Lsn>,
+    end: VerifyEnd,
+) -> Result<TimelineWalVerifyResponse> {
+    let wal_seg_size = state.server.wal_seg_size as usize;
+    let pg_version = PgMajorVersion::try_from(state.server.pg_version)?;
+    let commit_lsn = state.commit_lsn;
+
+    // timeline_start_lsn and local_start_lsn are record boundaries. Past them
+    // verification starts at a segment start, skipping the tail of the record
+    // continued from the previous segment.
+    let mut start = state.timeline_start_lsn;
+    if let SegmentSource::Local(timeline_dir) = source {
+        start = max(start, state.local_start_lsn);
+        if let Some(segno) = first_local_segno(timeline_dir, wal_seg_size).await? {
+            start = max(start, Lsn(XLogSegNoOffsetToRecPtr(segno, 0, wal_seg_size)));
+        }
+    }
+    if let Some(from_lsn) = from_lsn {
+        start = max(start, from_lsn.segment_lsn(wal_seg_size));
+    }
+    let start_is_boundary = start == state.timeline_start_lsn || start == state.local_start_lsn;
+    let until = match end {
+        VerifyEnd::RecordBoundary(lsn) | VerifyEnd::Lsn(lsn) => lsn,
+        VerifyEnd::EndOfWal => Lsn::MAX,
+    };
+    info!(
+        "verifying {:?} WAL of {} from {} until {:?}",
+        source.kind(),
+        ttid,
+        start,
+        end
+    );
+
+    let mut verifier = Verifier {
+        pg_version,
+        decoder: start_is_boundary.then(|| WalStreamDecoder::new(start, pg_version)),
+        start_lsn: start,
+        end_lsn: start,
+        records: 0,
+        record_ends_at_commit: start == commit_lsn,
+    };
+    let mut error = None;
+    let mut segno = start.segment_number(wal_seg_size);
+    let mut pos = start;
+    while pos < until {
+        let seg_start = Lsn(XLogSegNoOffsetToRecPtr(segno, 0, wal_seg_size));
+        let seg_end = seg_start + wal_seg_size as u64;
+        let read_until = min(seg_end, until);
+        let len = (read_until.0 - pos.0) as usize;
+        let offset = pos.segment_offset(wal_seg_size);
+
+        let data = match read_segment(ttid, source, segno, wal_seg_size, offset, len).await? {
+            Some(data) if data.len() == len => data,
+            Some(data) => {
+                // WAL ends in this segment, decode what is there first.
+                let truncated_at = pos + data.len() as u64;
+                error = verifier.feed(seg_start, &data, commit_lsn).err();
+                if error.is_none() && !matches!(end, VerifyEnd::EndOfWal) {
+                    error = Some(verify_error(truncated_at, "WAL segment is truncated"));
+                }
+                break;
+            }
+            None => {
+                if !matches!(end, VerifyEnd::EndOfWal) {
+                    let name = XLogFileName(PG_TLI, segno, wal_seg_size);
+                    error = Some(verify_error(pos, &format!("WAL segment {name} is missing")));
+                }
+                break;
+            }
+        };
+        if let Err(e) = verifier.feed(seg_start, &data, commit_lsn) {
+            error = Some(e);
+            break;
+        }
+        pos = read_until;
+        segno += 1;
+    }
+
+    // Decoding stopped at the first invalid byte. For EndOfWal that is
+    // where WAL ends, otherwise it is corruption.
+    if let VerifyEnd::EndOfWal = end {
+        if verifier.end_lsn < commit_lsn {
+            let at = error.map_or(verifier.end_lsn, |e| e.lsn);
+            error = Some(verify_error(
+                at,
+                &format!("valid WAL ends before commit_lsn {commit_lsn}"),
+            ));
+        } else {
+            error = None;
+        }
+    }
+    match end {
+        VerifyEnd::RecordBoundary(lsn) if error.is_none() && verifier.end_lsn != lsn => {
+            error = Some(verify_error(
+                verifier.end_lsn,
+                &format!("last record doesn't end at {lsn}"),
+            ));
+        }
+        _ => {}
+    }
+    if error.is_none()
+        && verifier.start_lsn <= commit_lsn
+        && commit_lsn <= verifier.end_lsn
+        && !verifier.record_ends_at_commit
+    {
+        error = Some(verify_error(
+            commit_lsn,
+            "commit_lsn is not at a record boundary",
+        ));
+    }
+
+    let known_end = match end {
+        VerifyEnd::EndOfWal => verifier.end_lsn,
+        _ => known_end,
+    };
+    Ok(TimelineWalVerifyResponse {
+        source: source.kind(),
+        commit_lsn,
+        flush_lsn: known_end,
+        start_lsn: verifier.start_lsn,
+        end_lsn: verifier.end_lsn,
+        records: verifier.records,
+        error,
+    })
+}
+
+fn verify_error(lsn: Lsn, msg: &str) -> WalVerifyError {
+    WalVerifyError {
+        lsn,
+        msg: msg.to_owned(),
+    }
+}
+
+struct Verifier {
+    pg_version: PgMajorVersion,
+    /// None until the first record boundary is found.
+    decoder: Option<WalStreamDecoder>,
+    start_lsn: Lsn,
+    end_lsn: Lsn,
+    records: u64,
+    record_ends_at_commit: bool,
+}
+
+impl Verifier {
+    /// Feed WAL of segment starting at `seg_start` and decode all complete
+    /// records. `data` starts at the decoder position, or at the segment
+    /// start if we don't have the decoder yet.
+    fn feed(&mut self, seg_start: Lsn, data: &[u8], commit_lsn: Lsn) -> Result<(), WalVerifyError> {
+        let decoder = match self.decoder.as_mut() {
+            Some(decoder) => {
+                decoder.feed_bytes(data);
+                decoder
+            }
+            None => {
+                let Some(skip) = skip_contrecord(seg_start, data, self.pg_version)? else {
+                    // The whole segment is a continuation, try the next one.
+                    return Ok(());
+                };
+                let start = seg_start + skip as u64;
+                self.start_lsn = start;
+                self.end_lsn = start;
+                self.record_ends_at_commit = start == commit_lsn;
+                let mut decoder = WalStreamDecoder::new(start, self.pg_version);
+                decoder.feed_bytes(&data[skip..]);
+                self.decoder.insert(decoder)
+            }
+        };
+        loop {
+            match decoder.poll_decode() {
+                Ok(Some((end_lsn, _))) => {
+                    self.records += 1;
+                    self.end_lsn = end_lsn;
+                    if end_lsn == commit_lsn {
+                        self.record_ends_at_commit = true;
+                    }
+                }
+                Ok(None) => return Ok(()),
+                Err(e) => return Err(verify_error(e.lsn, &e.msg)),
+            }
+        }
+    }
+}
+
+/// Find the first record starting in the segment at `seg_start` with
+/// contents `data`, skipping the tail of a record continued from the
+/// previous segment. Returns its offset, or None if no record starts in the
+/// segment.
+fn skip_contrecord(
+    seg_start: Lsn,
+    data: &[u8],
+    pg_version: PgMajorVersion,
+) -> Result<Option<usize>, WalVerifyError> {
+    let magic = dispatch_pgversion!(pg_version, pgv::bindings::XLOG_PAGE_MAGIC as u16);
+    let check_header = |hdr: &XLogPageHeaderData, lsn: Lsn, rem_len: Option<u32>| {
+        if hdr.xlp_magic != magic {
+            return Err(verify_error(
+                lsn,
+                &format!("invalid xlog page header: xlp_magic={}", hdr.xlp_magic),
+            ));
+        }
+        if hdr.xlp_pageaddr != lsn.0 {
+            return Err(verify_error(
+                lsn,
+                &format!(
+                    "invalid xlog page header: xlp_pageaddr={}",
+                    hdr.xlp_pageaddr
+                ),
+            ));
+        }
+        if let Some(rem_len) = rem_len {
+            if hdr.xlp_info & pg_constants::XLP_FIRST_IS_CONTRECORD == 0
+                || hdr.xlp_rem_len != rem_len
+            {
+                return Err(verify_error(
+                    lsn,
+                    &format!(
+                        "invalid xlog page header: xlp_rem_len={}, expected continuation of {}",
+                        hdr.xlp_rem_len, rem_len
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    };
+
+    let mut buf = data;
+    let hdr = XLogLongPageHeaderData::from_bytes(&mut buf).map_err(|e| {
+        verify_error(
+            seg_start,
+            &format!("long header deserialization failed {e}"),
+        )
+    })?;
+    check_header(&hdr.std, seg_start, None)?;
+    if hdr.std.xlp_info & pg_constants::XLP_FIRST_IS_CONTRECORD == 0 {
+        return Ok(Some(0));
+    }
+
+    let mut rem = hdr.std.xlp_rem_len as u64;
+    let mut pos = seg_start + XLOG_SIZE_OF_XLOG_LONG_PHD as u64;
+    loop {
+        let n = min(rem, pos.remaining_in_block());
+        pos += n;
+        rem -= n;
+        if rem == 0 {
+            break;
+        }
+        let offset = (pos.0 - seg_start.0) as usize;
+        if offset >= data.len() {
+            return Ok(None);
+        }
+        let hdr = XLogPageHeaderData::from_bytes(&mut &data[offset..])
+            .map_err(|e| verify_error(pos, &format!("header deserialization failed {e}")))?;
+        check_header(&hdr, pos, Some(rem as u32))?;
+        pos += XLOG_SIZE_OF_XLOG_SHORT_PHD as u64;
+    }
+    let offset = (pos.align().0 - seg_start.0) as usize;
+    Ok((offset < data.len()).then_some(offset))
+}
+
+/// Smallest segment number among WAL segments in `timeline_dir`.
+async fn first_local_segno(
+    timeline_dir: &Utf8Path,
+    wal_seg_size: usize,
+) -> Result<Option<XLogSegNo>> {
+    let mut first = None;
+    let mut entries = tokio::fs::read_dir(timeline_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let fname = entry.file_name();
+        if !IsXLogFileName(&fname)
+            && !IsPartialXLogFileName(&fname)
+            && !is_compressed_segment_file_name(&fname)
+        {
+            continue;
+        }
+        let (segno, _) = XLogFromFileName(&fname, wal_seg_size)?;
+        first = Some(first.map_or(segno, |first: XLogSegNo| first.min(segno)));
+    }
+    Ok(first)
+}
+
+/// Read `len` bytes of segment `segno` from `offset`. Returns None if the
+/// segment doesn't exist, and less than `len` bytes if it is shorter.
+async fn read_segment(
+    ttid: &TenantTimelineId,
+    source: &SegmentSource<'_>,
+    segno: XLogSegNo,
+    wal_seg_size: usize,
+    offset: usize,
+    len: usize,
+) -> Result<Option<Vec<u8>>> {
+    let reader: std::pin::Pin<Box<dyn AsyncRead + Send + Sync>> = match source {
+        SegmentSource::Local(timeline_dir) => {
+            if let Some((mut file, _)) = open_wal_file(timeline_dir, segno, wal_seg_size).await? {
+                use tokio::io::AsyncSeekExt;
+                file.seek(std::io::SeekFrom::Start(offset as u64)).await?;
+                Box::pin(file)
+            } else {
+                let (wal_file_path, _) = wal_file_paths(timeline_dir, segno, wal_seg_size);
+                let compressed_path = compressed_segment_path(&wal_file_path);
+                match open_compressed_segment(&compressed_path, offset as u64).await? {
+                    Some(reader) => reader,
+                    None => return Ok(None),
+                }
+            }
+        }
+        SegmentSource::Remote(storage) => {
+            let path = remote_timeline_path(ttid)?.join(XLogFileName(PG_TLI, segno, wal_seg_size));
+            match read_object(storage, &path, offset as u64).await {
+                Ok(reader) => reader,
+                Err(e) if matches!(e.downcast_ref(), Some(DownloadError::NotFound)) => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.context(format!("read remote segment {path}"))),
+            }
+        }
+    };
+    let mut data = Vec::with_capacity(len);
+    reader.take(len as u64).read_to_end(&mut data).await?;
+    Ok(Some(data))
+}
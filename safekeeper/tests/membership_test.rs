@@ -0,0 +1,87 @@
+use rand::Rng;
+use safekeeper_api::membership::SafekeeperGeneration;
+use tracing::{info, warn};
+use utils::id::NodeId;
+
+use crate::walproposer_sim::log::init_logger;
+use crate::walproposer_sim::simulation::{
+    Schedule, TestAction, TestConfig, generate_membership_schedule, generate_network_opts,
+};
+use crate::walproposer_sim::simulation_logs::validate_events;
+
+pub mod walproposer_sim;
+
+// Migrate the timeline twice while writing WAL, including moving it back to
+// a safekeeper it was excluded from.
+#[test]
+fn test_membership_change() -> anyhow::Result<()> {
+    let clock = init_logger();
+    let mut config = TestConfig::new(Some(clock));
+    config.membership_change = Some(2);
+    let test = config.start(1337);
+
+    let mut schedule: Schedule = (0..100)
+        .map(|i| (i * 200, TestAction::WriteTx(3)))
+        .collect();
+    schedule.push((2_000, TestAction::MigrateSafekeepers(vec![2, 3, 4])));
+    schedule.push((8_000, TestAction::MigrateSafekeepers(vec![0, 3, 4])));
+    schedule.sort_by_key(|(time, _)| *time);
+
+    test.run_schedule(&schedule)?;
+    validate_events(test.world.take_events());
+    test.check_committed_wal()?;
+
+    let controller = test.controller.as_ref().unwrap();
+    assert_eq!(controller.migrations_done(), 2);
+
+    let has_timeline = |idx: usize| {
+        test.servers[idx]
+            .disk
+            .timelines
+            .lock()
+            .contains_key(&test.ttid)
+    };
+    assert!(!has_timeline(1));
+    assert!(!has_timeline(2));
+
+    // 1 is the initial generation, each migration adds joint and new ones
+    let tli = test.servers[0].disk.timelines.lock()[&test.ttid].clone();
+    let mconf = tli.state.lock().mconf.clone();
+    assert_eq!(mconf.generation, SafekeeperGeneration::new(5));
+    let members = mconf.members.m.iter().map(|sk| sk.id).collect::<Vec<_>>();
+    let expected = [0, 3, 4]
+        .map(|idx| NodeId(test.servers[idx].id as u64))
+        .to_vec();
+    assert_eq!(members, expected);
+    info!("committed WAL up to {}", test.committed_wal.end_lsn());
+
+    test.world.deallocate();
+    Ok(())
+}
+
+// Generates 100 random seeds and runs a schedule with migrations, network
+// faults and crashes for each of them, checking that committed WAL is never
+// lost. If you see this test fail, please report the last seed to the
+// @safekeeper team.
+#[test]
+fn test_random_membership_schedules() -> anyhow::Result<()> {
+    let clock = init_logger();
+    let mut config = TestConfig::new(Some(clock));
+    config.membership_change = Some(2);
+
+    for _ in 0..100 {
+        let seed: u64 = rand::thread_rng().r#gen();
+        config.network = generate_network_opts(seed);
+
+        let test = config.start(seed);
+        warn!("Running test with seed {}", seed);
+
+        let schedule = generate_membership_schedule(seed, test.servers.len());
+        test.run_schedule(&schedule).unwrap();
+        validate_events(test.world.take_events());
+        test.check_committed_wal().unwrap();
+        test.world.deallocate();
+    }
+
+    Ok(())
+}
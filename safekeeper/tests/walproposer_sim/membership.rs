@@ -0,0 +1,521 @@
+//! Simulated storage controller driving safekeeper membership changes, see
+//! rfcs/035-safekeeper-dynamic-membership-change.md. It creates the timeline
+//! with generations enabled and then migrates it between safekeeper sets via
+//! joint configurations, pulling the timeline to new members and excluding
+//! the old ones. Requests are sent over the simulated network, so they are
+//! subject to the same faults as walproposer traffic.
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use desim::executor::{self, ExternalHandle, PollSome};
+use desim::node_os::NodeOs;
+use desim::proto::{AnyMessage, NetEvent, NodeEvent};
+use desim::world::Node;
+use parking_lot::Mutex;
+use safekeeper::state::TimelinePersistentState;
+use safekeeper_api::membership::{
+    Configuration, INITIAL_GENERATION, MemberSet, SafekeeperGeneration, SafekeeperId,
+};
+use safekeeper_api::{ServerInfo, Term};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, info_span, warn};
+use utils::bin_ser::LeSer;
+use utils::id::{NodeId, TenantTimelineId};
+use utils::lsn::Lsn;
+
+/// Prefix of controller requests sent to the safekeeper.
+pub const CONTROL_PREFIX: &[u8] = b"CONTROL ";
+
+/// How long to wait for a safekeeper to answer a request.
+const RESPONSE_TIMEOUT: i64 = 5000;
+/// Pause between reconciliation attempts.
+const RETRY_INTERVAL: i64 = 500;
+
+/// Requests of the controller to safekeepers, a subset of the safekeeper
+/// http API.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ControlRequest {
+    TimelineCreate {
+        ttid: TenantTimelineId,
+        server_info: ServerInfo,
+        mconf: Configuration,
+    },
+    MembershipSwitch {
+        ttid: TenantTimelineId,
+        mconf: Configuration,
+    },
+    /// Get the state and WAL of the timeline, like the snapshot used by
+    /// pull_timeline.
+    Snapshot { ttid: TenantTimelineId },
+    /// Create the timeline from the snapshot, unless it already exists.
+    PullTimeline {
+        ttid: TenantTimelineId,
+        snapshot: Box<TimelineSnapshot>,
+    },
+    Exclude {
+        ttid: TenantTimelineId,
+        mconf: Configuration,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TimelineSnapshot {
+    pub state: TimelinePersistentState,
+    pub flush_lsn: Lsn,
+    /// WAL of [wal_start, flush_lsn).
+    pub wal_start: Lsn,
+    pub wal: Vec<u8>,
+}
+
+impl std::fmt::Debug for TimelineSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // don't print the WAL itself
+        f.debug_struct("TimelineSnapshot")
+            .field("mconf", &self.state.mconf)
+            .field("term", &self.state.acceptor_state.term)
+            .field("commit_lsn", &self.state.commit_lsn)
+            .field("flush_lsn", &self.flush_lsn)
+            .field("wal_start", &self.wal_start)
+            .finish()
+    }
+}
+
+impl TimelineSnapshot {
+    /// Position used to choose the most advanced donor, same as in
+    /// pull_timeline.
+    fn position(&self) -> (Term, Term, Lsn, Lsn) {
+        (
+            self.state.acceptor_state.get_last_log_term(self.flush_lsn),
+            self.state.acceptor_state.term,
+            self.flush_lsn,
+            self.state.commit_lsn,
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ControlResponse {
+    Ok,
+    MembershipSwitch {
+        generation: SafekeeperGeneration,
+        last_log_term: Term,
+        flush_lsn: Lsn,
+    },
+    Snapshot(Box<TimelineSnapshot>),
+    Error(String),
+}
+
+/// Safekeepers the compute is configured with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComputeConfig {
+    pub generation: SafekeeperGeneration,
+    pub safekeepers: Vec<NodeId>,
+}
+
+#[derive(Debug, Clone)]
+enum MigrationState {
+    /// Configuration `generation` with members `sk_set` is applied.
+    Idle,
+    /// Joint configuration `generation` from `sk_set` to `new_sk_set`.
+    Joint { new_sk_set: Vec<NodeId> },
+    /// Configuration `generation` with members `sk_set` is chosen but not yet
+    /// applied; `excluded` are removed after that.
+    Finalize { excluded: Vec<NodeId> },
+}
+
+/// Persistent state of the controller, survives its restarts.
+#[derive(Debug, Clone)]
+struct ControllerDb {
+    created: bool,
+    generation: SafekeeperGeneration,
+    sk_set: Vec<NodeId>,
+    migration: MigrationState,
+    pending: VecDeque<Vec<NodeId>>,
+    to_exclude: Vec<(NodeId, Configuration)>,
+    compute: Option<ComputeConfig>,
+    migrations_done: usize,
+}
+
+/// Simulated storage controller node.
+pub struct Controller {
+    pub node: Arc<Node>,
+    ttid: TenantTimelineId,
+    server_info: ServerInfo,
+    initial_config: ComputeConfig,
+    db: Arc<Mutex<ControllerDb>>,
+    thread: Cell<ExternalHandle>,
+}
+
+impl Controller {
+    /// Create and start a controller managing `ttid` at the specified Node,
+    /// with the timeline initially placed on `sk_set`.
+    pub fn new(
+        node: Arc<Node>,
+        ttid: TenantTimelineId,
+        server_info: ServerInfo,
+        sk_set: Vec<NodeId>,
+    ) -> Self {
+        let initial_config = ComputeConfig {
+            generation: INITIAL_GENERATION,
+            safekeepers: sk_set.clone(),
+        };
+        let db = Arc::new(Mutex::new(ControllerDb {
+            created: false,
+            generation: INITIAL_GENERATION,
+            sk_set,
+            migration: MigrationState::Idle,
+            pending: VecDeque::new(),
+            to_exclude: Vec::new(),
+            compute: None,
+            migrations_done: 0,
+        }));
+        let thread = Cell::new(Controller::launch(
+            node.clone(),
+            ttid,
+            server_info.clone(),
+            db.clone(),
+        ));
+
+        Self {
+            node,
+            ttid,
+            server_info,
+            initial_config,
+            db,
+            thread,
+        }
+    }
+
+    fn launch(
+        node: Arc<Node>,
+        ttid: TenantTimelineId,
+        server_info: ServerInfo,
+        db: Arc<Mutex<ControllerDb>>,
+    ) -> ExternalHandle {
+        node.launch(move |os| run_controller(os, ttid, server_info, db))
+    }
+
+    /// Restart the controller, interrupting the operation in progress.
+    pub fn restart(&self) {
+        let new_thread = Controller::launch(
+            self.node.clone(),
+            self.ttid,
+            self.server_info.clone(),
+            self.db.clone(),
+        );
+        let old_thread = self.thread.replace(new_thread);
+        old_thread.crash_stop();
+    }
+
+    /// Queue migration of the timeline to `new_sk_set`.
+    pub fn migrate(&self, new_sk_set: Vec<NodeId>) {
+        self.db.lock().pending.push_back(new_sk_set);
+        self.node
+            .node_events()
+            .send(NodeEvent::Internal(AnyMessage::Just32(0)));
+    }
+
+    /// Latest configuration the compute was notified about. Until the
+    /// timeline is created, compute uses the initial member set.
+    pub fn compute_config(&self) -> ComputeConfig {
+        self.db
+            .lock()
+            .compute
+            .clone()
+            .unwrap_or_else(|| self.initial_config.clone())
+    }
+
+    /// Number of finished migrations.
+    pub fn migrations_done(&self) -> usize {
+        self.db.lock().migrations_done
+    }
+
+    /// Whether all queued migrations are finished.
+    pub fn is_idle(&self) -> bool {
+        let db = self.db.lock();
+        db.created && db.pending.is_empty() && matches!(db.migration, MigrationState::Idle)
+    }
+}
+
+fn run_controller(
+    os: NodeOs,
+    ttid: TenantTimelineId,
+    server_info: ServerInfo,
+    db: Arc<Mutex<ControllerDb>>,
+) {
+    let _enter = info_span!("controller", id = os.id()).entered();
+    debug!("started controller");
+
+    let node_events = os.node_events();
+    let chans: Vec<Box<dyn PollSome>> = vec![Box::new(node_events.clone())];
+    loop {
+        while reconcile(&os, ttid, &server_info, &db) {}
+
+        // wait for a new migration or retry later
+        if executor::epoll_chans(&chans, RETRY_INTERVAL).is_some() {
+            while let Some(event) = node_events.try_recv() {
+                match event {
+                    NodeEvent::Internal(AnyMessage::Just32(0)) => {}
+                    other => warn!("unexpected event {:?}", other),
+                }
+            }
+        }
+    }
+}
+
+/// Make one step towards the desired state. Returns true if the step
+/// succeeded and there might be more work to do.
+fn reconcile(
+    os: &NodeOs,
+    ttid: TenantTimelineId,
+    server_info: &ServerInfo,
+    db: &Mutex<ControllerDb>,
+) -> bool {
+    // Never hold the lock across network calls, the test reads it.
+    let state = db.lock().clone();
+
+    if !state.created {
+        let mconf = Configuration::new(member_set(&state.sk_set));
+        for sk in &state.sk_set {
+            let req = ControlRequest::TimelineCreate {
+                ttid,
+                server_info: server_info.clone(),
+                mconf: mconf.clone(),
+            };
+            if !matches!(call(os, *sk, &req), Some(ControlResponse::Ok)) {
+                debug!("failed to create timeline on sk {}", sk);
+                return false;
+            }
+        }
+        info!("created timeline with {}", mconf);
+        let mut db = db.lock();
+        db.created = true;
+        db.compute = Some(ComputeConfig {
+            generation: mconf.generation,
+            safekeepers: state.sk_set,
+        });
+        return true;
+    }
+
+    match state.migration {
+        MigrationState::Idle => {
+            retry_exclude(os, ttid, db);
+
+            let mut db = db.lock();
+            let Some(new_sk_set) = db.pending.pop_front() else {
+                return false;
+            };
+            info!(
+                "starting migration from {:?} to {:?}",
+                db.sk_set, new_sk_set
+            );
+            // safekeepers joining the set again must keep the timeline
+            db.to_exclude.retain(|(sk, _)| !new_sk_set.contains(sk));
+            db.generation = db.generation.next();
+            db.migration = MigrationState::Joint { new_sk_set };
+            true
+        }
+        MigrationState::Joint { new_sk_set } => {
+            let joint = Configuration {
+                generation: state.generation,
+                members: member_set(&state.sk_set),
+                new_members: Some(member_set(&new_sk_set)),
+                witnesses: Vec::new(),
+            };
+            let mut all = state.sk_set.clone();
+            all.extend(new_sk_set.iter().filter(|sk| !state.sk_set.contains(sk)));
+            db.lock().compute = Some(ComputeConfig {
+                generation: joint.generation,
+                safekeepers: all,
+            });
+
+            // Once a quorum of the current set is in the joint conf, WAL can
+            // be committed only with the new set participating. Everything
+            // committed before is at or below the highest position.
+            let Some(sync_position) = switch_quorum(os, ttid, &state.sk_set, &joint, None) else {
+                debug!("failed to switch current set to {}", joint);
+                return false;
+            };
+            for sk in new_sk_set.iter().filter(|sk| !state.sk_set.contains(sk)) {
+                if !pull_timeline(os, ttid, *sk, &state.sk_set) {
+                    debug!("failed to pull timeline to sk {}", sk);
+                    return false;
+                }
+            }
+            if switch_quorum(os, ttid, &new_sk_set, &joint, Some(sync_position)).is_none() {
+                debug!(
+                    "new set hasn't reached sync position {:?} yet",
+                    sync_position
+                );
+                return false;
+            }
+
+            let mut db = db.lock();
+            let excluded = db
+                .sk_set
+                .iter()
+                .filter(|sk| !new_sk_set.contains(sk))
+                .copied()
+                .collect();
+            db.generation = db.generation.next();
+            db.sk_set = new_sk_set;
+            db.migration = MigrationState::Finalize { excluded };
+            true
+        }
+        MigrationState::Finalize { excluded } => {
+            let mconf = Configuration {
+                generation: state.generation,
+                members: member_set(&state.sk_set),
+                new_members: None,
+                witnesses: Vec::new(),
+            };
+            if switch_quorum(os, ttid, &state.sk_set, &mconf, None).is_none() {
+                debug!("failed to switch new set to {}", mconf);
+                return false;
+            }
+            info!("finished migration to {}", mconf);
+
+            let mut db = db.lock();
+            db.to_exclude
+                .extend(excluded.into_iter().map(|sk| (sk, mconf.clone())));
+            db.compute = Some(ComputeConfig {
+                generation: mconf.generation,
+                safekeepers: state.sk_set,
+            });
+            db.migration = MigrationState::Idle;
+            db.migrations_done += 1;
+            true
+        }
+    }
+}
+
+/// Try to remove the timeline from safekeepers which are no longer members.
+fn retry_exclude(os: &NodeOs, ttid: TenantTimelineId, db: &Mutex<ControllerDb>) {
+    let to_exclude = db.lock().to_exclude.clone();
+    for (sk, mconf) in to_exclude {
+        let req = ControlRequest::Exclude {
+            ttid,
+            mconf: mconf.clone(),
+        };
+        match call(os, sk, &req) {
+            Some(ControlResponse::Ok) => {}
+            Some(ControlResponse::Error(e)) => {
+                // the safekeeper is already in a newer configuration
+                warn!("exclude of sk {} failed: {}", sk, e);
+            }
+            _ => continue,
+        }
+        db.lock()
+            .to_exclude
+            .retain(|(id, conf)| !(*id == sk && conf.generation == mconf.generation));
+    }
+}
+
+/// Switch safekeepers to `mconf`. Returns the highest (last_log_term,
+/// flush_lsn) position if a quorum of them switched and, if `min_position`
+/// is specified, reached it.
+fn switch_quorum(
+    os: &NodeOs,
+    ttid: TenantTimelineId,
+    sks: &[NodeId],
+    mconf: &Configuration,
+    min_position: Option<(Term, Lsn)>,
+) -> Option<(Term, Lsn)> {
+    let mut switched = 0;
+    let mut max_position = None;
+    for sk in sks {
+        let req = ControlRequest::MembershipSwitch {
+            ttid,
+            mconf: mconf.clone(),
+        };
+        if let Some(ControlResponse::MembershipSwitch {
+            generation,
+            last_log_term,
+            flush_lsn,
+        }) = call(os, *sk, &req)
+        {
+            let position = (last_log_term, flush_lsn);
+            if generation == mconf.generation && min_position.is_none_or(|min| position >= min) {
+                switched += 1;
+                max_position = max_position.max(Some(position));
+            }
+        }
+    }
+
+    if switched >= sks.len() / 2 + 1 {
+        max_position
+    } else {
+        None
+    }
+}
+
+/// Copy the timeline to `sk` from the most advanced of `donors`.
+fn pull_timeline(os: &NodeOs, ttid: TenantTimelineId, sk: NodeId, donors: &[NodeId]) -> bool {
+    let snapshots = donors
+        .iter()
+        .filter_map(
+            |donor| match call(os, *donor, &ControlRequest::Snapshot { ttid }) {
+                Some(ControlResponse::Snapshot(snapshot)) => Some(snapshot),
+                _ => None,
+            },
+        )
+        .collect::<Vec<_>>();
+
+    // like pull_timeline, require all but one donor to be available
+    if snapshots.len() < donors.len().saturating_sub(1).max(1) {
+        return false;
+    }
+    let snapshot = snapshots
+        .into_iter()
+        .max_by_key(|s| s.position())
+        .expect("at least one snapshot");
+    debug!(
+        "pulling timeline to sk {} at flush_lsn {}",
+        sk, snapshot.flush_lsn
+    );
+
+    let req = ControlRequest::PullTimeline { ttid, snapshot };
+    matches!(call(os, sk, &req), Some(ControlResponse::Ok))
+}
+
+/// Send a request to the safekeeper and wait for the response.
+fn call(os: &NodeOs, sk: NodeId, req: &ControlRequest) -> Option<ControlResponse> {
+    let mut buf = CONTROL_PREFIX.to_vec();
+    req.ser_into(&mut buf).expect("failed to serialize request");
+
+    let tcp = os.open_tcp(sk.0 as u32);
+    tcp.send(AnyMessage::Bytes(buf.into()));
+
+    let chans: Vec<Box<dyn PollSome>> = vec![Box::new(tcp.recv_chan())];
+    let res = match executor::epoll_chans(&chans, RESPONSE_TIMEOUT) {
+        Some(_) => match tcp.recv_chan().must_recv() {
+            NetEvent::Message(AnyMessage::Bytes(b)) => {
+                Some(ControlResponse::des(&b).expect("failed to deserialize response"))
+            }
+            NetEvent::Message(_) => unreachable!(),
+            NetEvent::Closed => None,
+        },
+        None => None,
+    };
+    tcp.close();
+
+    debug!("request {:?} to sk {} returned {:?}", req, sk, res);
+    res
+}
+
+/// Member set of the simulated safekeepers, which listen on port equal to
+/// the node id.
+fn member_set(sks: &[NodeId]) -> MemberSet {
+    MemberSet::new(
+        sks.iter()
+            .map(|id| SafekeeperId {
+                id: *id,
+                host: "node".to_owned(),
+                pg_port: id.0 as u16,
+            })
+            .collect(),
+    )
+    .expect("safekeeper ids are unique")
+}
@@ -1,5 +1,6 @@
 pub mod block_storage;
 pub mod log;
+pub mod membership;
 pub mod safekeeper;
 pub mod safekeeper_disk;
 pub mod simulation;
@@ -24,9 +24,11 @@
 use safekeeper_api::ServerInfo;
 use safekeeper_api::membership::Configuration;
 use tracing::{debug, info_span, warn};
+use utils::bin_ser::LeSer;
 use utils::id::{NodeId, TenantId, TenantTimelineId, TimelineId};
 use utils::lsn::Lsn;
 
+use super::membership::{CONTROL_PREFIX, ControlRequest, ControlResponse, TimelineSnapshot};
 use super::safekeeper_disk::{DiskStateStorage, DiskWALStorage, SafekeeperDisk, TimelineDisk};
 
 struct SharedState {
@@ -85,25 +87,54 @@ fn new(disk: Arc<SafekeeperDisk>, conf: SafeKeeperConf) -> Result<Self> {
         })
     }
 
-    fn create(&mut self, ttid: TenantTimelineId, server_info: ServerInfo) -> Result<()> {
+    fn create(
+        &mut self,
+        ttid: TenantTimelineId,
+        mconf: Configuration,
+        server_info: ServerInfo,
+    ) -> Result<()> {
         if self.timelines.contains_key(&ttid) {
             bail!("timeline {} already exists", ttid);
         }
 
-        debug!("creating new timeline {}", ttid);
+        debug!("creating new timeline {} with {}", ttid, mconf);
 
         let commit_lsn = Lsn::INVALID;
         let local_start_lsn = Lsn::INVALID;
 
-        let state = TimelinePersistentState::new(
-            &ttid,
-            Configuration::empty(),
-            server_info,
-            commit_lsn,
-            local_start_lsn,
-        )?;
+        let state =
+            TimelinePersistentState::new(&ttid, mconf, server_info, commit_lsn, local_start_lsn)?;
+
+        self.load(ttid, state, None)
+    }
 
+    /// Create the timeline from the snapshot of another safekeeper.
+    fn restore(&mut self, ttid: TenantTimelineId, snapshot: TimelineSnapshot) -> Result<()> {
+        if self.timelines.contains_key(&ttid) {
+            bail!("timeline {} already exists", ttid);
+        }
+
+        debug!("restoring timeline {} from {:?}", ttid, snapshot);
+
+        self.load(
+            ttid,
+            snapshot.state,
+            Some((snapshot.wal_start, &snapshot.wal[..])),
+        )
+    }
+
+    /// Put the state and WAL to disk and start the timeline.
+    fn load(
+        &mut self,
+        ttid: TenantTimelineId,
+        state: TimelinePersistentState,
+        wal: Option<(Lsn, &[u8])>,
+    ) -> Result<()> {
+        self.disk.excluded.lock().remove(&ttid);
         let disk_timeline = self.disk.put_state(&ttid, state);
+        if let Some((wal_start, wal)) = wal {
+            disk_timeline.wal.lock().write(wal_start.0, wal);
+        }
         let control_store = DiskStateStorage::new(disk_timeline.clone());
         let wal_store = DiskWALStorage::new(disk_timeline.clone(), &control_store)?;
 
@@ -123,6 +154,18 @@ fn create(&mut self, ttid: TenantTimelineId, server_info: ServerInfo) -> Result<
         Ok(())
     }
 
+    /// Remove the timeline from memory and disk after the safekeeper was excluded from it.
+    fn exclude(&mut self, ttid: &TenantTimelineId) {
+        debug!("deleting excluded timeline {}", ttid);
+        self.timelines.remove(ttid);
+        self.disk.timelines.lock().remove(ttid);
+        self.disk.excluded.lock().insert(*ttid);
+    }
+
+    fn was_excluded(&self, ttid: &TenantTimelineId) -> bool {
+        self.disk.excluded.lock().contains(ttid)
+    }
+
     fn get(&mut self, ttid: &TenantTimelineId) -> &mut SharedState {
         self.timelines.get_mut(ttid).expect("timeline must exist")
     }
@@ -139,6 +182,8 @@ struct ConnState {
     greeting: bool,
     ttid: TenantTimelineId,
     flush_pending: bool,
+    // set by START_WAL_PUSH, false if walproposer uses generations
+    allow_timeline_creation: bool,
 
     runtime: tokio::runtime::Runtime,
 }
@@ -158,6 +203,7 @@ pub fn run_server(os: NodeOs, disk: Arc<SafekeeperDisk>) -> Result<()> {
         broker_keepalive_interval: Duration::from_secs(0),
         heartbeat_timeout: Duration::from_secs(0),
         remote_storage: None,
+        wal_archive_storage: None,
         max_offloader_lag_bytes: 0,
         /* BEGIN_HADRON */
         max_reelect_offloader_lag_bytes: 0,
@@ -191,6 +237,10 @@ pub fn run_server(os: NodeOs, disk: Arc<SafekeeperDisk>) -> Result<()> {
         use_https_safekeeper_api: false,
         enable_tls_wal_service_api: false,
         force_metric_collection_on_scrape: true,
+        wal_compression_level: None,
+        timeline_wal_quota_bytes: 0,
+        tenant_wal_quota_bytes: 0,
+        wal_quota_backpressure_ratio: 0.8,
         /* BEGIN_HADRON */
         enable_pull_timeline_on_startup: false,
         advertise_pg_addr_tenant_only: None,
@@ -242,6 +292,7 @@ pub fn run_server(os: NodeOs, disk: Arc<SafekeeperDisk>) -> Result<()> {
                             greeting: false,
                             ttid: TenantTimelineId::empty(),
                             flush_pending: false,
+                            allow_timeline_creation: true,
                             runtime: tokio::runtime::Builder::new_current_thread().build()?,
                         },
                     );
@@ -267,10 +318,11 @@ pub fn run_server(os: NodeOs, disk: Arc<SafekeeperDisk>) -> Result<()> {
                     if res.is_err() {
                         let e = res.unwrap_err();
                         let estr = e.to_string();
-                        if !estr.contains("finished processing START_REPLICATION") {
+                        if !EXPECTED_ERRORS.iter().any(|msg| estr.contains(msg)) {
                             warn!("conn {:?} error: {:?}", connection_id, e);
                             panic!("unexpected error at safekeeper: {e:#}");
                         }
+                        debug!("closing conn {:?}: {:#}", connection_id, e);
                         conns.remove(&connection_id);
                         break;
                     }
@@ -283,6 +335,9 @@ pub fn run_server(os: NodeOs, disk: Arc<SafekeeperDisk>) -> Result<()> {
             next_event = conn.tcp.recv_chan().try_recv();
         }
 
+        // timeline might have been excluded from this safekeeper
+        conns.retain(|_, conn| !conn.greeting || global.has_tli(&conn.ttid));
+
         conns.retain(|_, conn| {
             let res = conn.flush(&mut global);
             if res.is_err() {
@@ -293,8 +348,20 @@ pub fn run_server(os: NodeOs, disk: Arc<SafekeeperDisk>) -> Result<()> {
     }
 }
 
+/// Errors which close the connection instead of failing the test.
+const EXPECTED_ERRORS: &[&str] = &[
+    "finished processing START_REPLICATION",
+    "finished processing control request",
+    // membership changes, walproposer reconnects and learns the new conf
+    "due to generation mismatch",
+    "timeline creation is not allowed",
+    "was excluded from this safekeeper",
+];
+
 impl ConnState {
-    /// Process a message from the network. It can be START_REPLICATION request or a valid ProposerAcceptorMessage message.
+    /// Process a message from the network. It can be START_REPLICATION,
+    /// START_WAL_PUSH, controller request or a valid ProposerAcceptorMessage
+    /// message.
     fn process_any(&mut self, any: AnyMessage, global: &mut GlobalMap) -> Result<()> {
         if let AnyMessage::Bytes(copy_data) = any {
             let repl_prefix = b"START_REPLICATION ";
@@ -303,6 +370,20 @@ fn process_any(&mut self, any: AnyMessage, global: &mut GlobalMap) -> Result<()>
                 bail!("finished processing START_REPLICATION")
             }
 
+            let wal_push_prefix = b"START_WAL_PUSH";
+            if !self.greeting && copy_data.starts_with(wal_push_prefix) {
+                let query = String::from_utf8(copy_data.to_vec())?;
+                self.allow_timeline_creation = !query.contains("allow_timeline_creation 'false'");
+                return Ok(());
+            }
+
+            if !self.greeting && copy_data.starts_with(CONTROL_PREFIX) {
+                let req = ControlRequest::des(&copy_data[CONTROL_PREFIX.len()..])?;
+                let resp = self.process_control(req, global)?;
+                self.tcp.send(AnyMessage::Bytes(resp.ser()?.into()));
+                bail!("finished processing control request")
+            }
+
             let msg = ProposerAcceptorMessage::parse(copy_data, SK_PROTO_VERSION_3)?;
             debug!("got msg: {:?}", msg);
             self.process(msg, global)
@@ -327,6 +408,11 @@ fn process_start_replication(
         let end_lsn = parts.next().unwrap().parse::<u64>()?;
 
         let ttid = TenantTimelineId::new(tenant_id, timeline_id);
+        // walproposer might still be recovering WAL from a safekeeper which has just been
+        // excluded from the configuration
+        if !global.has_tli(&ttid) && global.was_excluded(&ttid) {
+            bail!("timeline {} was excluded from this safekeeper", ttid);
+        }
         let shared_state = global.get(&ttid);
 
         // read bytes from start_lsn to end_lsn
@@ -349,8 +435,88 @@ fn init_timeline(
         if global.has_tli(&ttid) {
             return Ok(());
         }
+        if !self.allow_timeline_creation {
+            bail!(
+                "timeline {} not found, timeline creation is not allowed",
+                ttid
+            );
+        }
+
+        global.create(ttid, Configuration::empty(), server_info)
+    }
 
-        global.create(ttid, server_info)
+    /// Process a request of the storage controller.
+    fn process_control(
+        &mut self,
+        req: ControlRequest,
+        global: &mut GlobalMap,
+    ) -> Result<ControlResponse> {
+        debug!("got control request: {:?}", req);
+        let resp = match req {
+            ControlRequest::TimelineCreate {
+                ttid,
+                server_info,
+                mconf,
+            } => {
+                if !global.has_tli(&ttid) {
+                    global.create(ttid, mconf, server_info)?;
+                }
+                ControlResponse::Ok
+            }
+            ControlRequest::MembershipSwitch { ttid, mconf } => {
+                if !global.has_tli(&ttid) {
+                    return Ok(ControlResponse::Error(format!("timeline {ttid} not found")));
+                }
+                let my_id = global.conf.my_id;
+                let sk = &mut global.get(&ttid).sk;
+                let res = self
+                    .runtime
+                    .block_on(sk.state.membership_switch(mconf, my_id))?;
+                ControlResponse::MembershipSwitch {
+                    generation: res.current_conf.generation,
+                    last_log_term: sk.get_last_log_term(),
+                    flush_lsn: sk.flush_lsn(),
+                }
+            }
+            ControlRequest::Snapshot { ttid } => {
+                if !global.has_tli(&ttid) {
+                    return Ok(ControlResponse::Error(format!("timeline {ttid} not found")));
+                }
+                let shared_state = global.get(&ttid);
+                let flush_lsn = shared_state.sk.flush_lsn();
+                let state = shared_state.disk.state.lock().clone();
+                let wal_start = state.local_start_lsn.min(flush_lsn);
+                let mut wal = vec![0; (flush_lsn.0 - wal_start.0) as usize];
+                shared_state.disk.wal.lock().read(wal_start.0, &mut wal);
+                ControlResponse::Snapshot(Box::new(TimelineSnapshot {
+                    state,
+                    flush_lsn,
+                    wal_start,
+                    wal,
+                }))
+            }
+            ControlRequest::PullTimeline { ttid, snapshot } => {
+                if !global.has_tli(&ttid) {
+                    global.restore(ttid, *snapshot)?;
+                }
+                ControlResponse::Ok
+            }
+            ControlRequest::Exclude { ttid, mconf } => {
+                if global.has_tli(&ttid) {
+                    let sk_generation = global.get(&ttid).sk.state.mconf.generation;
+                    if sk_generation > mconf.generation {
+                        return Ok(ControlResponse::Error(format!(
+                            "refused to exclude timeline {ttid} with generation {}, sk generation is {sk_generation}",
+                            mconf.generation
+                        )));
+                    }
+                    global.exclude(&ttid);
+                }
+                ControlResponse::Ok
+            }
+        };
+        debug!("sending control response: {:?}", resp);
+        Ok(resp)
     }
 
     /// Process a ProposerAcceptorMessage.
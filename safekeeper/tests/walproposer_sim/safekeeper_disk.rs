@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::Deref;
 use std::sync::Arc;
 use std::time::Instant;
@@ -21,6 +21,8 @@
 /// All safekeeper state that is usually saved to disk.
 pub struct SafekeeperDisk {
     pub timelines: Mutex<HashMap<TenantTimelineId, Arc<TimelineDisk>>>,
+    /// Timelines deleted because the safekeeper was excluded from their configuration.
+    pub excluded: Mutex<HashSet<TenantTimelineId>>,
 }
 
 impl Default for SafekeeperDisk {
@@ -33,6 +35,7 @@ impl SafekeeperDisk {
     pub fn new() -> Self {
         SafekeeperDisk {
             timelines: Mutex::new(HashMap::new()),
+            excluded: Mutex::new(HashSet::new()),
         }
     }
 
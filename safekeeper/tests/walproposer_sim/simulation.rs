@@ -7,16 +7,21 @@
 use desim::options::{Delay, NetworkOptions};
 use desim::proto::{AnyMessage, NodeEvent};
 use desim::world::{Node, World};
+use postgres_ffi::WAL_SEGMENT_SIZE;
 use rand::{Rng, SeedableRng};
+use safekeeper_api::membership::INVALID_GENERATION;
+use safekeeper_api::{PgVersionId, ServerInfo};
 use tracing::{debug, info_span, warn};
-use utils::id::TenantTimelineId;
+use utils::id::{NodeId, TenantTimelineId};
 use utils::lsn::Lsn;
+use walproposer::bindings::PG_VERSION_NUM;
 use walproposer::walproposer::{Config, Wrapper};
 
 use super::log::SimClock;
+use super::membership::{ComputeConfig, Controller};
 use super::safekeeper_disk::SafekeeperDisk;
 use super::walproposer_api;
-use super::walproposer_disk::DiskWalProposer;
+use super::walproposer_disk::{CommittedWal, DiskWalProposer};
 use crate::walproposer_sim::safekeeper::run_server;
 use crate::walproposer_sim::walproposer_api::SimulationApi;
 
@@ -70,6 +75,7 @@ impl WalProposer {
     fn start(
         os: NodeOs,
         disk: Arc<DiskWalProposer>,
+        committed_wal: Arc<CommittedWal>,
         ttid: TenantTimelineId,
         addrs: Vec<String>,
         lsn: Option<Lsn>,
@@ -96,6 +102,7 @@ fn start(
             os,
             config: config.clone(),
             disk,
+            committed_wal,
             redo_start_lsn: lsn,
         };
         let api = SimulationApi::new(args);
@@ -104,14 +111,19 @@ fn start(
     }
 
     /// Start walproposer in a sync_safekeepers mode.
-    pub fn launch_sync(ttid: TenantTimelineId, addrs: Vec<String>, node: Arc<Node>) -> Self {
+    pub fn launch_sync(
+        ttid: TenantTimelineId,
+        addrs: Vec<String>,
+        node: Arc<Node>,
+        committed_wal: Arc<CommittedWal>,
+    ) -> Self {
         debug!("sync_safekeepers started at node {}", node.id);
         let disk = DiskWalProposer::new();
         let disk_wp = disk.clone();
 
         // start the client thread
         let handle = node.launch(move |os| {
-            WalProposer::start(os, disk_wp, ttid, addrs, None);
+            WalProposer::start(os, disk_wp, committed_wal, ttid, addrs, None);
         });
 
         Self {
@@ -128,6 +140,7 @@ pub fn launch_walproposer(
         addrs: Vec<String>,
         node: Arc<Node>,
         lsn: Lsn,
+        committed_wal: Arc<CommittedWal>,
     ) -> Self {
         debug!("walproposer started at node {}", node.id);
         let disk = DiskWalProposer::new();
@@ -136,7 +149,7 @@ pub fn launch_walproposer(
 
         // start the client thread
         let handle = node.launch(move |os| {
-            WalProposer::start(os, disk_wp, ttid, addrs, Some(lsn));
+            WalProposer::start(os, disk_wp, committed_wal, ttid, addrs, Some(lsn));
         });
 
         Self {
@@ -178,6 +191,11 @@ pub struct TestConfig {
     pub network: NetworkOptions,
     pub timeout: u64,
     pub clock: Option<SimClock>,
+    /// If set, the timeline is created with generations enabled by a
+    /// simulated storage controller, which can migrate it to other
+    /// safekeepers. This many spare safekeepers are started in addition to
+    /// the 3 initial members.
+    pub membership_change: Option<usize>,
 }
 
 impl TestConfig {
@@ -199,6 +217,7 @@ pub fn new(clock: Option<SimClock>) -> Self {
             },
             timeout: 1_000 * 10,
             clock,
+            membership_change: None,
         }
     }
 
@@ -210,23 +229,38 @@ pub fn start(&self, seed: u64) -> Test {
             clock.set_clock(world.clock());
         }
 
-        let servers = [
-            SafekeeperNode::new(world.new_node()),
-            SafekeeperNode::new(world.new_node()),
-            SafekeeperNode::new(world.new_node()),
-        ];
+        // Walproposer treats node id 0 as invalid, so with generations
+        // enabled it is taken by the controller.
+        let controller_node = self.membership_change.map(|_| world.new_node());
+
+        let n_safekeepers = 3 + self.membership_change.unwrap_or(0);
+        let servers = (0..n_safekeepers)
+            .map(|_| SafekeeperNode::new(world.new_node()))
+            .collect::<Vec<_>>();
 
         let server_ids = [servers[0].id, servers[1].id, servers[2].id];
         let safekeepers_addrs = server_ids.map(|id| format!("node:{id}")).to_vec();
 
         let ttid = TenantTimelineId::generate();
 
+        let controller = controller_node.map(|node| {
+            let server_info = ServerInfo {
+                pg_version: PgVersionId::from_full_pg_version(PG_VERSION_NUM),
+                system_id: 0,
+                wal_seg_size: WAL_SEGMENT_SIZE as u32,
+            };
+            let sk_set = server_ids.map(|id| NodeId(id as u64)).to_vec();
+            Controller::new(node, ttid, server_info, sk_set)
+        });
+
         Test {
             world,
             servers,
             sk_list: safekeepers_addrs,
             ttid,
             timeout: self.timeout,
+            controller,
+            committed_wal: CommittedWal::new(),
         }
     }
 }
@@ -234,10 +268,12 @@ pub fn start(&self, seed: u64) -> Test {
 /// Holds simulation state.
 pub struct Test {
     pub world: Arc<World>,
-    pub servers: [SafekeeperNode; 3],
+    pub servers: Vec<SafekeeperNode>,
     pub sk_list: Vec<String>,
     pub ttid: TenantTimelineId,
     pub timeout: u64,
+    pub controller: Option<Controller>,
+    pub committed_wal: Arc<CommittedWal>,
 }
 
 impl Test {
@@ -261,9 +297,39 @@ pub fn sync_safekeepers(&self) -> anyhow::Result<Lsn> {
         Ok(lsn)
     }
 
+    /// Configuration of safekeepers in compute, None if generations are
+    /// disabled.
+    pub fn compute_config(&self) -> Option<ComputeConfig> {
+        self.controller.as_ref().map(|c| c.compute_config())
+    }
+
+    /// List of safekeepers to start walproposer with.
+    pub fn safekeepers_list(&self) -> Vec<String> {
+        match self.compute_config() {
+            Some(config) => config
+                .safekeepers
+                .iter()
+                .enumerate()
+                .map(|(i, id)| {
+                    if i == 0 {
+                        format!("g#{}:node:{}", config.generation, id)
+                    } else {
+                        format!("node:{id}")
+                    }
+                })
+                .collect(),
+            None => self.sk_list.clone(),
+        }
+    }
+
     /// Spawn a new sync_safekeepers thread.
     pub fn launch_sync_safekeepers(&self) -> WalProposer {
-        WalProposer::launch_sync(self.ttid, self.sk_list.clone(), self.world.new_node())
+        WalProposer::launch_sync(
+            self.ttid,
+            self.safekeepers_list(),
+            self.world.new_node(),
+            self.committed_wal.clone(),
+        )
     }
 
     /// Spawn a new walproposer thread.
@@ -275,7 +341,13 @@ pub fn launch_walproposer(&self, lsn: Lsn) -> WalProposer {
             lsn
         };
 
-        WalProposer::launch_walproposer(self.ttid, self.sk_list.clone(), self.world.new_node(), lsn)
+        WalProposer::launch_walproposer(
+            self.ttid,
+            self.safekeepers_list(),
+            self.world.new_node(),
+            lsn,
+            self.committed_wal.clone(),
+        )
     }
 
     /// Execute the simulation for the specified duration.
@@ -299,6 +371,7 @@ pub fn run_schedule(&self, schedule: &Schedule) -> anyhow::Result<()> {
             }
         }
 
+        let mut compute_config = self.compute_config();
         let mut wp = self.launch_sync_safekeepers();
 
         let mut skipped_tx = 0;
@@ -307,6 +380,21 @@ pub fn run_schedule(&self, schedule: &Schedule) -> anyhow::Result<()> {
         let mut schedule_ptr = 0;
 
         loop {
+            if self.controller.is_some() {
+                let new_config = self.compute_config();
+                if new_config != compute_config {
+                    // like compute_ctl, restart compute with the new safekeepers
+                    debug!("restarting walproposer with {:?}", new_config);
+                    compute_config = new_config;
+                    wp.stop();
+                    wp = self.launch_sync_safekeepers();
+                } else if !wp.sync_safekeepers && wp.thread.is_finished() {
+                    // walproposer exits to adopt a new membership configuration
+                    debug!("walproposer exited: {:?}", wp.thread.result());
+                    wp = self.launch_sync_safekeepers();
+                }
+            }
+
             if wp.sync_safekeepers && wp.thread.is_finished() {
                 let res = wp.thread.result();
                 if res.0 != 0 {
@@ -349,6 +437,18 @@ pub fn run_schedule(&self, schedule: &Schedule) -> anyhow::Result<()> {
                         wp.stop();
                         wp = self.launch_sync_safekeepers();
                     }
+                    TestAction::MigrateSafekeepers(ids) => {
+                        let new_sk_set = ids
+                            .iter()
+                            .map(|id| NodeId(self.servers[*id].id as u64))
+                            .collect::<Vec<_>>();
+                        debug!("migrating timeline to {:?}", new_sk_set);
+                        self.controller().migrate(new_sk_set);
+                    }
+                    TestAction::RestartController => {
+                        debug!("restarting controller");
+                        self.controller().restart();
+                    }
                 }
                 schedule_ptr += 1;
             }
@@ -360,11 +460,15 @@ pub fn run_schedule(&self, schedule: &Schedule) -> anyhow::Result<()> {
 
             // poll until the next event
             if wp.thread.is_finished() {
-                while self.world.step() && self.world.now() < next_event_time {}
+                while self.world.step()
+                    && self.world.now() < next_event_time
+                    && self.compute_config() == compute_config
+                {}
             } else {
                 while self.world.step()
                     && self.world.now() < next_event_time
                     && !wp.thread.is_finished()
+                    && self.compute_config() == compute_config
                 {}
             }
         }
@@ -378,6 +482,63 @@ pub fn run_schedule(&self, schedule: &Schedule) -> anyhow::Result<()> {
 
         Ok(())
     }
+
+    fn controller(&self) -> &Controller {
+        self.controller
+            .as_ref()
+            .expect("membership changes are not enabled")
+    }
+
+    /// Check that committed WAL never changed and that it is stored on a
+    /// quorum of members of the latest membership configuration.
+    pub fn check_committed_wal(&self) -> anyhow::Result<()> {
+        let violations = self.committed_wal.violations();
+        if !violations.is_empty() {
+            anyhow::bail!("committed WAL has changed: {:?}", violations);
+        }
+        if self.committed_wal.end_lsn() == Lsn(0) {
+            return Ok(());
+        }
+
+        let timelines = self
+            .servers
+            .iter()
+            .filter_map(|sk| {
+                let tli = sk.disk.timelines.lock().get(&self.ttid).cloned()?;
+                Some((sk.id, tli))
+            })
+            .collect::<Vec<_>>();
+        let mconf = timelines
+            .iter()
+            .map(|(_, tli)| tli.state.lock().mconf.clone())
+            .max_by_key(|mconf| mconf.generation);
+        // without generations all safekeepers are members
+        let members = match mconf {
+            Some(mconf) if mconf.generation != INVALID_GENERATION => mconf
+                .members
+                .m
+                .iter()
+                .map(|sk| sk.id.0 as u32)
+                .collect::<Vec<_>>(),
+            _ => self.servers.iter().map(|sk| sk.id).collect(),
+        };
+
+        let holders = timelines
+            .iter()
+            .filter(|(id, tli)| {
+                members.contains(id) && self.committed_wal.is_contained_in(&tli.wal.lock())
+            })
+            .count();
+        if holders < members.len() / 2 + 1 {
+            anyhow::bail!(
+                "committed WAL up to {} is stored on {} of members {:?}",
+                self.committed_wal.end_lsn(),
+                holders,
+                members
+            );
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -385,6 +546,9 @@ pub enum TestAction {
     WriteTx(usize),
     RestartSafekeeper(usize),
     RestartWalProposer,
+    /// Migrate the timeline to safekeepers with the given indexes.
+    MigrateSafekeepers(Vec<usize>),
+    RestartController,
 }
 
 pub type Schedule = Vec<(u64, TestAction)>;
@@ -410,6 +574,33 @@ pub fn generate_schedule(seed: u64) -> Schedule {
     schedule
 }
 
+/// Like [`generate_schedule`], but also migrates the timeline between random
+/// sets of 3 out of `n_safekeepers` and restarts the controller.
+pub fn generate_membership_schedule(seed: u64, n_safekeepers: usize) -> Schedule {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    let mut schedule = Vec::new();
+    let mut time = 0;
+
+    let cnt = rng.gen_range(1..100);
+
+    for _ in 0..cnt {
+        time += rng.gen_range(0..500);
+        let action = match rng.gen_range(0..6) {
+            0 | 1 => TestAction::WriteTx(rng.gen_range(1..10)),
+            2 => TestAction::RestartSafekeeper(rng.gen_range(0..n_safekeepers)),
+            3 => TestAction::RestartWalProposer,
+            4 => TestAction::MigrateSafekeepers(
+                rand::seq::index::sample(&mut rng, n_safekeepers, 3).into_vec(),
+            ),
+            5 => TestAction::RestartController,
+            _ => unreachable!(),
+        };
+        schedule.push((time, action));
+    }
+
+    schedule
+}
+
 pub fn generate_network_opts(seed: u64) -> NetworkOptions {
     let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
 
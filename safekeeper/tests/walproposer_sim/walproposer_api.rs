@@ -16,7 +16,7 @@
 };
 use walproposer::walproposer::{ApiImpl, Config};
 
-use super::walproposer_disk::DiskWalProposer;
+use super::walproposer_disk::{CommittedWal, DiskWalProposer};
 
 /// Special state for each wp->sk connection.
 struct SafekeeperConn {
@@ -188,6 +188,7 @@ pub struct SimulationApi {
     os: NodeOs,
     safekeepers: RefCell<Vec<SafekeeperConn>>,
     disk: Arc<DiskWalProposer>,
+    committed_wal: Arc<CommittedWal>,
     redo_start_lsn: Option<Lsn>,
     last_logged_commit_lsn: u64,
     shmem: UnsafeCell<walproposer::bindings::WalproposerShmemState>,
@@ -199,6 +200,7 @@ pub struct Args {
     pub os: NodeOs,
     pub config: Config,
     pub disk: Arc<DiskWalProposer>,
+    pub committed_wal: Arc<CommittedWal>,
     pub redo_start_lsn: Option<Lsn>,
 }
 
@@ -209,7 +211,13 @@ pub fn new(args: Args) -> Self {
             .config
             .safekeepers_list
             .iter()
-            .map(|s| {
+            .enumerate()
+            .map(|(i, s)| {
+                // the list can start with the generation, "g#<generation>:"
+                let s = match s.split_once(':') {
+                    Some((generation, rest)) if i == 0 && generation.starts_with("g#") => rest,
+                    _ => s,
+                };
                 SafekeeperConn::new(
                     s.split(':').next().unwrap().to_string(),
                     s.split(':').nth(1).unwrap().to_string(),
@@ -221,6 +229,7 @@ pub fn new(args: Args) -> Self {
             os: args.os,
             safekeepers: RefCell::new(sk_conns),
             disk: args.disk,
+            committed_wal: args.committed_wal,
             redo_start_lsn: args.redo_start_lsn,
             last_logged_commit_lsn: 0,
             shmem: UnsafeCell::new(walproposer::api_bindings::empty_shmem()),
@@ -229,6 +238,36 @@ pub fn new(args: Args) -> Self {
         }
     }
 
+    /// Download WAL of [startpos, endpos) from the safekeeper and write it to
+    /// disk. Returns the LSN up to which WAL was downloaded.
+    fn download_wal(&self, node_id: NodeId, mut startpos: u64, endpos: u64) -> u64 {
+        let replication_prompt = format!(
+            "START_REPLICATION {} {} {} {}",
+            self.config.ttid.tenant_id, self.config.ttid.timeline_id, startpos, endpos,
+        );
+
+        let conn = self.os.open_tcp(node_id);
+        conn.send(desim::proto::AnyMessage::Bytes(replication_prompt.into()));
+
+        let chan = conn.recv_chan();
+        while startpos < endpos {
+            let event = chan.recv();
+            match event {
+                NetEvent::Closed => {
+                    debug!("connection closed in recovery");
+                    break;
+                }
+                NetEvent::Message(AnyMessage::Bytes(b)) => {
+                    debug!("got recovery bytes from safekeeper");
+                    self.disk.lock().write(startpos, &b);
+                    startpos += b.len() as u64;
+                }
+                NetEvent::Message(_) => unreachable!(),
+            }
+        }
+        startpos
+    }
+
     /// Get SafekeeperConn for the given Safekeeper.
     fn get_conn(&self, sk: &mut walproposer::bindings::Safekeeper) -> RefMut<'_, SafekeeperConn> {
         let sk_port = unsafe { CStr::from_ptr(sk.port).to_str().unwrap() };
@@ -290,7 +329,13 @@ fn conn_connect_poll(
 
     fn conn_send_query(&self, sk: &mut walproposer::bindings::Safekeeper, query: &str) -> bool {
         debug!("conn_send_query: {}", query);
-        self.get_conn(sk).is_start_wal_push = true;
+        let mut conn = self.get_conn(sk);
+        conn.is_start_wal_push = true;
+        if let Some(socket) = conn.socket.as_mut() {
+            socket.send(desim::proto::AnyMessage::Bytes(Bytes::copy_from_slice(
+                query.as_bytes(),
+            )));
+        }
         true
     }
 
@@ -375,10 +420,30 @@ fn wal_reader_allocate(&self, _: &mut walproposer::bindings::Safekeeper) -> Neon
 
     fn wal_read(
         &self,
-        _sk: &mut walproposer::bindings::Safekeeper,
+        sk: &mut walproposer::bindings::Safekeeper,
         buf: &mut [u8],
         startpos: u64,
     ) -> NeonWALReadResult {
+        let wal_start = self.disk.lock().wal_start();
+        if let Some(wal_start) = wal_start {
+            let endpos = std::cmp::min(startpos + buf.len() as u64, wal_start.0);
+            if startpos < endpos {
+                // Lagging safekeeper, e.g. just added to the configuration,
+                // needs WAL which compute doesn't have locally. Like
+                // neon_walreader, read it from the donor safekeeper.
+                let donor = unsafe { (*sk.wp).donor };
+                if !donor.is_null() {
+                    let node_id = self.get_conn(unsafe { &mut *donor }).node_id;
+                    debug!(
+                        "wal_read: downloading WAL [{}, {}) from {}",
+                        startpos, endpos, node_id
+                    );
+                    if self.download_wal(node_id, startpos, endpos) != endpos {
+                        executor::exit(1, "failed to download WAL from donor".to_owned());
+                    }
+                }
+            }
+        }
         self.disk.lock().read(startpos, buf);
         walproposer::bindings::NeonWALReadResult_NEON_WALREAD_SUCCESS
     }
@@ -523,6 +588,14 @@ fn log_internal(&self, _wp: &mut walproposer::bindings::WalProposer, level: Leve
                 // Voting bug when safekeeper disconnects after voting
                 executor::exit(1, msg.to_owned());
             }
+            if msg.contains("restarting to adopt mconf generation") {
+                // Safekeeper has a newer membership configuration
+                executor::exit(1, msg.to_owned());
+            }
+            if msg.contains("sent response with generation") {
+                // Safekeeper switched membership configuration while streaming
+                executor::exit(1, msg.to_owned());
+            }
             panic!("unknown FATAL error from walproposer: {msg}");
         }
     }
@@ -592,6 +665,11 @@ fn process_safekeeper_feedback(
         debug!("process_safekeeper_feedback, commit_lsn={}", wp.commitLsn);
         if wp.commitLsn > self.last_logged_commit_lsn {
             self.os.log_event(format!("commit_lsn;{}", wp.commitLsn));
+            self.committed_wal.record(
+                &self.disk.lock(),
+                Lsn(self.last_logged_commit_lsn),
+                Lsn(wp.commitLsn),
+            );
             self.last_logged_commit_lsn = wp.commitLsn;
         }
     }
@@ -617,31 +695,8 @@ fn recovery_download(
 
         debug!("recovery_download from {} to {}", startpos, endpos,);
 
-        let replication_prompt = format!(
-            "START_REPLICATION {} {} {} {}",
-            self.config.ttid.tenant_id, self.config.ttid.timeline_id, startpos, endpos,
-        );
-        let async_conn = self.get_conn(sk);
-
-        let conn = self.os.open_tcp(async_conn.node_id);
-        conn.send(desim::proto::AnyMessage::Bytes(replication_prompt.into()));
-
-        let chan = conn.recv_chan();
-        while startpos < endpos {
-            let event = chan.recv();
-            match event {
-                NetEvent::Closed => {
-                    debug!("connection closed in recovery");
-                    break;
-                }
-                NetEvent::Message(AnyMessage::Bytes(b)) => {
-                    debug!("got recovery bytes from safekeeper");
-                    self.disk.lock().write(startpos, &b);
-                    startpos += b.len() as u64;
-                }
-                NetEvent::Message(_) => unreachable!(),
-            }
-        }
+        let node_id = self.get_conn(sk).node_id;
+        startpos = self.download_wal(node_id, startpos, endpos);
 
         debug!("recovery finished at {}", startpos);
 
@@ -3,6 +3,7 @@
 
 use parking_lot::{Mutex, MutexGuard};
 use postgres_ffi::v16::wal_generator::{LogicalMessageGenerator, WalGenerator};
+use tracing::warn;
 use utils::lsn::Lsn;
 
 use super::block_storage::BlockStorage;
@@ -18,6 +19,8 @@ pub fn new() -> Arc<DiskWalProposer> {
             state: Mutex::new(State {
                 internal_available_lsn: Lsn(0),
                 prev_lsn: Lsn(0),
+                wal_start: None,
+                wal_end: Lsn(0),
                 disk: BlockStorage::new(),
                 wal_generator: WalGenerator::new(LogicalMessageGenerator::new(c"", &[]), Lsn(0)),
             }),
@@ -34,6 +37,9 @@ pub struct State {
     internal_available_lsn: Lsn,
     // needed for WAL generation
     prev_lsn: Lsn,
+    // WAL is available locally in [wal_start, wal_end)
+    wal_start: Option<Lsn>,
+    wal_end: Lsn,
     // actual WAL storage
     disk: BlockStorage,
     // WAL record generator
@@ -48,10 +54,22 @@ pub fn read(&self, pos: u64, buf: &mut [u8]) {
 
     pub fn write(&mut self, pos: u64, buf: &[u8]) {
         self.disk.write(pos, buf);
+        let end = Lsn(pos + buf.len() as u64);
+        self.wal_start = Some(self.wal_start.map_or(Lsn(pos), |start| start.min(Lsn(pos))));
+        self.wal_end = self.wal_end.max(end);
+    }
+
+    /// Start of WAL available locally, None if nothing was written yet.
+    /// Compute doesn't have WAL before the basebackup LSN, unless it was
+    /// downloaded from safekeepers.
+    pub fn wal_start(&self) -> Option<Lsn> {
+        self.wal_start
     }
 
     /// Update the internal available LSN to the given value.
     pub fn reset_to(&mut self, lsn: Lsn) {
+        self.wal_start = Some(lsn);
+        self.wal_end = lsn;
         self.internal_available_lsn = lsn;
         self.prev_lsn = Lsn(0); // Safekeeper doesn't care if this is omitted
         self.wal_generator.lsn = self.internal_available_lsn;
@@ -66,8 +84,102 @@ pub fn flush_rec_ptr(&self) -> Lsn {
     /// Inserts a logical record in the WAL at the current LSN.
     pub fn insert_logical_message(&mut self, prefix: &CStr, msg: &[u8]) {
         let (_, record) = self.wal_generator.append_logical_message(prefix, msg);
-        self.disk.write(self.internal_available_lsn.into(), &record);
+        self.write(self.internal_available_lsn.into(), &record);
         self.prev_lsn = self.internal_available_lsn;
         self.internal_available_lsn += record.len() as u64;
     }
 }
+
+/// WAL committed by walproposers during the simulation. Committed WAL must
+/// never change, so each walproposer compares the part it commits with the
+/// part committed by the previous ones.
+#[derive(Default)]
+pub struct CommittedWal {
+    state: Mutex<CommittedWalState>,
+}
+
+#[derive(Default)]
+struct CommittedWalState {
+    // sorted non-overlapping [start, end) ranges of recorded WAL
+    ranges: Vec<(Lsn, Lsn)>,
+    wal: BlockStorage,
+    violations: Vec<String>,
+}
+
+impl CommittedWal {
+    pub fn new() -> Arc<CommittedWal> {
+        Arc::new(CommittedWal::default())
+    }
+
+    /// Record WAL of [from, to) committed by the walproposer owning `disk`.
+    /// Only the part available locally is recorded.
+    pub fn record(&self, disk: &State, from: Lsn, to: Lsn) {
+        let Some(wal_start) = disk.wal_start() else {
+            return;
+        };
+        let from = from.max(wal_start);
+        let to = to.min(disk.wal_end);
+        if from >= to {
+            return;
+        }
+
+        let mut wal = vec![0; (to.0 - from.0) as usize];
+        disk.read(from.0, &mut wal);
+
+        let mut state = self.state.lock();
+        let overlaps = state
+            .ranges
+            .iter()
+            .map(|&(start, end)| (start.max(from), end.min(to)))
+            .filter(|(start, end)| start < end)
+            .collect::<Vec<_>>();
+        for (start, end) in overlaps {
+            let mut recorded = vec![0; (end.0 - start.0) as usize];
+            state.wal.read(start.0, &mut recorded);
+            let offset = (start.0 - from.0) as usize;
+            if recorded[..] != wal[offset..offset + recorded.len()] {
+                let violation = format!("committed WAL at [{start}, {end}) has changed");
+                warn!("{}", violation);
+                state.violations.push(violation);
+            }
+        }
+
+        state.wal.write(from.0, &wal);
+        state.ranges.push((from, to));
+        state.ranges.sort();
+        let mut merged: Vec<(Lsn, Lsn)> = Vec::new();
+        for (start, end) in state.ranges.drain(..) {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        state.ranges = merged;
+    }
+
+    /// Detected changes of committed WAL.
+    pub fn violations(&self) -> Vec<String> {
+        self.state.lock().violations.clone()
+    }
+
+    /// Check that `wal` contains all recorded committed WAL.
+    pub fn is_contained_in(&self, wal: &BlockStorage) -> bool {
+        let state = self.state.lock();
+        state.ranges.iter().all(|&(start, end)| {
+            let mut expected = vec![0; (end.0 - start.0) as usize];
+            let mut actual = vec![0; (end.0 - start.0) as usize];
+            state.wal.read(start.0, &mut expected);
+            wal.read(start.0, &mut actual);
+            expected == actual
+        })
+    }
+
+    /// End of the recorded committed WAL.
+    pub fn end_lsn(&self) -> Lsn {
+        self.state
+            .lock()
+            .ranges
+            .last()
+            .map_or(Lsn(0), |&(_, end)| end)
+    }
+}
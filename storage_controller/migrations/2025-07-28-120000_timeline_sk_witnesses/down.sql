@@ -0,0 +1 @@
+ALTER TABLE timelines DROP sk_witnesses;
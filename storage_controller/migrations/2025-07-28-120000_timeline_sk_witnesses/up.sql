@@ -0,0 +1 @@
+ALTER TABLE timelines ADD sk_witnesses BIGINT[] NOT NULL DEFAULT '{}';
@@ -217,6 +217,12 @@ struct Cli {
     #[arg(long, default_value = "3", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
     timeline_safekeeper_count: usize,
 
+    /// Number of safekeepers out of `timeline_safekeeper_count` to make
+    /// witnesses when creating a timeline: they vote but don't store WAL.
+    /// Must leave WAL storing safekeepers in every majority.
+    #[arg(long, default_value = "0")]
+    timeline_safekeeper_witness_count: usize,
+
     /// When set, actively checks and initiates heatmap downloads/uploads during reconciliation.
     /// This speed up migrations by avoiding the default wait for the heatmap download interval.
     /// Primarily useful for testing to reduce test execution time.
@@ -404,6 +410,13 @@ async fn async_main() -> anyhow::Result<()> {
         }
     }
 
+    // Every majority must include a safekeeper storing WAL.
+    if args.timeline_safekeeper_witness_count >= args.timeline_safekeeper_count / 2 + 1 {
+        anyhow::bail!(
+            "`--timeline-safekeeper-witness-count` must be less than the majority of `--timeline-safekeeper-count`"
+        );
+    }
+
     let ssl_ca_certs = match args.ssl_ca_file.as_ref() {
         Some(ssl_ca_file) => {
             tracing::info!("Using ssl root CA file: {ssl_ca_file:?}");
@@ -471,6 +484,7 @@ async fn async_main() -> anyhow::Result<()> {
         timelines_onto_safekeepers: args.timelines_onto_safekeepers,
         use_local_compute_notifications: args.use_local_compute_notifications,
         timeline_safekeeper_count: args.timeline_safekeeper_count,
+        timeline_safekeeper_witness_count: args.timeline_safekeeper_witness_count,
         posthog_config: posthog_config.clone(),
         kick_secondary_downloads: args.kick_secondary_downloads,
         shard_split_request_timeout: args
@@ -108,6 +108,14 @@ pub(crate) fn base_url(&self) -> String {
         }
     }
 
+    /// Connection string that computes use to reach the page service of this node.
+    pub(crate) fn page_service_connstr(&self) -> String {
+        format!(
+            "postgresql://no_user@{}:{}",
+            self.listen_pg_addr, self.listen_pg_port
+        )
+    }
+
     pub(crate) fn get_id(&self) -> NodeId {
         self.id
     }
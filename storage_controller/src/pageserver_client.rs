@@ -3,9 +3,10 @@
 use pageserver_api::models::detach_ancestor::AncestorDetached;
 use pageserver_api::models::{
     DetachBehavior, LocationConfig, LocationConfigListResponse, LsnLease, PageserverUtilization,
-    SecondaryProgress, TenantScanRemoteStorageResponse, TenantShardSplitRequest,
-    TenantShardSplitResponse, TenantWaitLsnRequest, TimelineArchivalConfigRequest,
-    TimelineCreateRequest, TimelineInfo, TopTenantShardsRequest, TopTenantShardsResponse,
+    SecondaryProgress, TenantScanRemoteStorageResponse, TenantShardHandoffRequest,
+    TenantShardHandoffResponse, TenantShardSplitRequest, TenantShardSplitResponse,
+    TenantWaitLsnRequest, TimelineArchivalConfigRequest, TimelineCreateRequest, TimelineInfo,
+    TopTenantShardsRequest, TopTenantShardsResponse,
 };
 use pageserver_api::shard::TenantShardId;
 use pageserver_client::BlockUnblock;
@@ -426,6 +427,21 @@ pub(crate) async fn wait_lsn(
         )
     }
 
+    pub(crate) async fn tenant_shard_handoff(
+        &self,
+        tenant_shard_id: TenantShardId,
+        request: TenantShardHandoffRequest,
+    ) -> Result<TenantShardHandoffResponse> {
+        measured_request!(
+            "tenant_shard_handoff",
+            crate::metrics::Method::Put,
+            &self.node_id_label,
+            self.inner
+                .tenant_shard_handoff(tenant_shard_id, request)
+                .await
+        )
+    }
+
     pub(crate) async fn activate_post_import(
         &self,
         tenant_shard_id: TenantShardId,
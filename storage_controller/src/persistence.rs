@@ -1473,6 +1473,7 @@ pub(crate) async fn update_timeline_membership(
         new_generation: SafekeeperGeneration,
         sk_set: &[NodeId],
         new_sk_set: Option<&[NodeId]>,
+        sk_witnesses: &[NodeId],
     ) -> DatabaseResult<()> {
         use crate::schema::timelines::dsl;
 
@@ -1491,6 +1492,10 @@ pub(crate) async fn update_timeline_membership(
                         dsl::sk_set.eq(sk_set.iter().map(|id| id.0 as i64).collect::<Vec<_>>()),
                         dsl::new_sk_set.eq(new_sk_set
                             .map(|set| set.iter().map(|id| id.0 as i64).collect::<Vec<_>>())),
+                        dsl::sk_witnesses.eq(sk_witnesses
+                            .iter()
+                            .map(|id| id.0 as i64)
+                            .collect::<Vec<_>>()),
                     ))
                     .execute(conn)
                     .await?;
@@ -2462,6 +2467,8 @@ pub(crate) struct TimelinePersistence {
     pub(crate) new_sk_set: Option<Vec<i64>>,
     pub(crate) cplane_notified_generation: i32,
     pub(crate) deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Members of the sets which are witnesses, i.e. don't store WAL.
+    pub(crate) sk_witnesses: Vec<i64>,
 }
 
 /// This is separate from [TimelinePersistence] only because postgres allows NULLs
@@ -2480,6 +2487,7 @@ pub(crate) struct TimelineFromDb {
     pub(crate) new_sk_set: Option<Vec<Option<i64>>>,
     pub(crate) cplane_notified_generation: i32,
     pub(crate) deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub(crate) sk_witnesses: Vec<Option<i64>>,
 }
 
 impl TimelineFromDb {
@@ -2490,6 +2498,7 @@ fn into_persistence(self) -> TimelinePersistence {
         let new_sk_set = self
             .new_sk_set
             .map(|s| s.into_iter().flatten().collect::<Vec<_>>());
+        let sk_witnesses = self.sk_witnesses.into_iter().flatten().collect::<Vec<_>>();
         TimelinePersistence {
             tenant_id: self.tenant_id,
             timeline_id: self.timeline_id,
@@ -2499,6 +2508,7 @@ fn into_persistence(self) -> TimelinePersistence {
             new_sk_set,
             cplane_notified_generation: self.cplane_notified_generation,
             deleted_at: self.deleted_at,
+            sk_witnesses,
         }
     }
 }
@@ -6,7 +6,8 @@
 use json_structural_diff::JsonDiff;
 use pageserver_api::controller_api::{AvailabilityZone, MigrationConfig, PlacementPolicy};
 use pageserver_api::models::{
-    LocationConfig, LocationConfigMode, LocationConfigSecondary, TenantConfig, TenantWaitLsnRequest,
+    LocationConfig, LocationConfigMode, LocationConfigSecondary, TenantConfig,
+    TenantShardHandoffRequest, TenantWaitLsnRequest,
 };
 use pageserver_api::shard::{ShardIdentity, TenantShardId};
 use pageserver_client::mgmt_api;
@@ -121,6 +122,15 @@ pub(crate) fn secondary_download_request_timeout(self, value: Duration) -> Self
         }
     }
 
+    pub(crate) fn handoff_timeout(self, value: Duration) -> Self {
+        Self {
+            config: ReconcilerConfig {
+                handoff_timeout: Some(value),
+                ..self.config
+            },
+        }
+    }
+
     pub(crate) fn tenant_creation_hint(self, hint: bool) -> Self {
         Self {
             config: ReconcilerConfig {
@@ -155,6 +165,11 @@ pub(crate) struct ReconcilerConfig {
     // the pagserver will hold our poll.
     secondary_download_request_timeout: Option<Duration>,
 
+    // During live migrations, wait at most this long for page service
+    // connections to leave the origin after handing them off to the
+    // destination, before detaching the origin.
+    handoff_timeout: Option<Duration>,
+
     // A hint indicating whether this reconciliation is done on the
     // creation of a new tenant. This only informs logging behaviour.
     tenant_creation_hint: bool,
@@ -169,6 +184,7 @@ pub(crate) fn new(priority: ReconcilerPriority) -> Self {
             priority,
             secondary_warmup_timeout: None,
             secondary_download_request_timeout: None,
+            handoff_timeout: None,
             tenant_creation_hint: false,
         }
     }
@@ -185,6 +201,11 @@ pub(crate) fn get_secondary_download_request_timeout(&self) -> Duration {
             .unwrap_or(SECONDARY_DOWNLOAD_REQUEST_TIMEOUT_DEFAULT)
     }
 
+    pub(crate) fn get_handoff_timeout(&self) -> Duration {
+        const HANDOFF_TIMEOUT_DEFAULT: Duration = Duration::from_secs(10);
+        self.handoff_timeout.unwrap_or(HANDOFF_TIMEOUT_DEFAULT)
+    }
+
     pub(crate) fn tenant_creation_hint(&self) -> bool {
         self.tenant_creation_hint
     }
@@ -710,13 +731,29 @@ fn build_location_config(
             };
         }
 
+        // The destination has caught up: redirect page service clients of the origin to it right away,
+        // rather than waiting for the compute notification to propagate and for the origin to be detached.
+        tracing::info!("🔁 Handing off page service traffic to pageserver {dest_ps}");
+        let handoff_started = self.handoff(&origin_ps, Some(&dest_ps)).await.is_some();
+
         tracing::info!("🔁 Notifying compute to use pageserver {dest_ps}");
 
         // During a live migration it is unhelpful to proceed if we couldn't notify compute: if we detach
         // the origin without notifying compute, we will render the tenant unavailable.
-        self.compute_notify_blocking(&origin_ps).await?;
+        if let Err(e) = self.compute_notify_blocking(&origin_ps).await {
+            if handoff_started {
+                // The destination is still attached, but we are not moving on: let the origin serve
+                // its clients again until the next reconciliation.
+                self.handoff(&origin_ps, None).await;
+            }
+            return Err(e);
+        }
         pausable_failpoint!("reconciler-live-migrate-post-notify");
 
+        if handoff_started {
+            self.await_handoff(&origin_ps, &dest_ps).await?;
+        }
+
         // Downgrade the origin to secondary.  If the tenant's policy is PlacementPolicy::Attached(0), then
         // this location will be deleted in the general case reconciliation that runs after this.
         let origin_secondary_conf = build_location_config(
@@ -762,6 +799,81 @@ fn build_location_config(
         Ok(())
     }
 
+    /// Starts (`Some(dest_ps)`) or cancels (`None`) handing off page service clients of `origin_ps`
+    /// to `dest_ps`. Returns the number of page service connections still using the origin.
+    ///
+    /// This is best-effort: if the origin doesn't support handoffs or the request fails, clients
+    /// are moved by the compute notification and by detaching the origin instead.
+    async fn handoff(&self, origin_ps: &Node, dest_ps: Option<&Node>) -> Option<usize> {
+        let tenant_shard_id = self.tenant_shard_id;
+        let connstr = dest_ps.map(|node| node.page_service_connstr());
+        match origin_ps
+            .with_client_retries(
+                |client| {
+                    let connstr = connstr.clone();
+                    async move {
+                        client
+                            .tenant_shard_handoff(
+                                tenant_shard_id,
+                                TenantShardHandoffRequest { connstr },
+                            )
+                            .await
+                    }
+                },
+                &self.http_client,
+                &self.service_config.pageserver_jwt_token,
+                1,
+                3,
+                Duration::from_secs(10),
+                &self.cancel,
+            )
+            .await
+        {
+            Some(Ok(response)) => Some(response.page_service_connections),
+            Some(Err(e)) => {
+                tracing::warn!("Failed to update page service handoff on node {origin_ps}: {e}");
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Waits for page service connections to leave `origin_ps` after a handoff, up to the
+    /// configured handoff timeout. Clients which are idle or don't understand the redirect
+    /// will be disconnected when the origin is detached.
+    async fn await_handoff(&self, origin_ps: &Node, dest_ps: &Node) -> Result<(), ReconcileError> {
+        let started_at = Instant::now();
+        let timeout = self.reconciler_config.get_handoff_timeout();
+        loop {
+            match self.handoff(origin_ps, Some(dest_ps)).await {
+                Some(0) => {
+                    tracing::info!(
+                        "Page service handoff to {dest_ps} complete in {}ms",
+                        started_at.elapsed().as_millis()
+                    );
+                    return Ok(());
+                }
+                Some(remaining) if started_at.elapsed() < timeout => {
+                    tracing::info!(
+                        "🕑 Waiting for {remaining} page service connections to leave {origin_ps}"
+                    );
+                }
+                Some(remaining) => {
+                    tracing::info!(
+                        "Proceeding with {remaining} page service connections left on {origin_ps} after handoff timeout"
+                    );
+                    return Ok(());
+                }
+                None => return Ok(()),
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_millis(500)) => {},
+                _ = self.cancel.cancelled() => return Err(ReconcileError::Cancel),
+            }
+        }
+    }
+
     /// Returns true if the observed state of the attached location was refreshed
     /// and false otherwise.
     async fn maybe_refresh_observed(&mut self) -> Result<bool, ReconcileError> {
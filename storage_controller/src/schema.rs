@@ -118,6 +118,7 @@ pub mod sql_types {
         new_sk_set -> Nullable<Array<Nullable<Int8>>>,
         cplane_notified_generation -> Int4,
         deleted_at -> Nullable<Timestamptz>,
+        sk_witnesses -> Array<Nullable<Int8>>,
     }
 }
 
@@ -479,6 +479,10 @@ pub struct Config {
     /// Safekeepers will be choosen from different availability zones.
     pub timeline_safekeeper_count: usize,
 
+    /// Number of safekeepers out of `timeline_safekeeper_count` which are
+    /// made witnesses when creating a timeline.
+    pub timeline_safekeeper_witness_count: usize,
+
     /// PostHog integration config
     pub posthog_config: Option<PostHogConfig>,
 
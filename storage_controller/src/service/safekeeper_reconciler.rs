@@ -365,6 +365,7 @@ async fn reconcile_one(
                     tenant_id: req.tenant_id,
                     timeline_id,
                     ignore_tombstone: Some(false),
+                    incremental: None,
                 };
                 success = self
                     .reconcile_inner(
@@ -48,6 +48,15 @@ fn make_member_set(safekeepers: &[Safekeeper]) -> Result<MemberSet, anyhow::Erro
         MemberSet::new(members)
     }
 
+    /// Persisted witnesses of a timeline which are members of one of `msets`.
+    fn witnesses_in(sk_witnesses: &[i64], msets: &[&MemberSet]) -> Vec<NodeId> {
+        sk_witnesses
+            .iter()
+            .map(|&id| NodeId(id as u64))
+            .filter(|&id| msets.iter().any(|mset| mset.contains(id)))
+            .collect()
+    }
+
     fn get_safekeepers(&self, ids: &[i64]) -> Result<Vec<Safekeeper>, ApiError> {
         let safekeepers = {
             let locked = self.inner.read().unwrap();
@@ -81,7 +90,9 @@ pub(super) async fn tenant_timeline_create_safekeepers_quorum(
         let safekeepers = self.get_safekeepers(&timeline_persistence.sk_set)?;
 
         let mset = Self::make_member_set(&safekeepers).map_err(ApiError::InternalServerError)?;
-        let mconf = safekeeper_api::membership::Configuration::new(mset);
+        let mut mconf = safekeeper_api::membership::Configuration::new(mset);
+        mconf.witnesses = Self::witnesses_in(&timeline_persistence.sk_witnesses, &[&mconf.members]);
+        mconf.validate().map_err(ApiError::InternalServerError)?;
 
         let req = safekeeper_api::models::TimelineCreateRequest {
             commit_lsn: None,
@@ -301,6 +312,13 @@ pub(super) async fn tenant_timeline_create_safekeepers(
             Vec::new()
         };
         let sks_persistence = sks.iter().map(|sk| sk.id.0 as i64).collect::<Vec<_>>();
+        // Witnesses don't need disk space for WAL, so the role goes to the
+        // most loaded of the chosen safekeepers, which are sorted by load.
+        let witness_count = self
+            .config
+            .timeline_safekeeper_witness_count
+            .min(sks_persistence.len() / 2);
+        let sk_witnesses = sks_persistence[sks_persistence.len() - witness_count..].to_vec();
         // Add timeline to db
         let mut timeline_persist = TimelinePersistence {
             tenant_id: tenant_id.to_string(),
@@ -311,6 +329,7 @@ pub(super) async fn tenant_timeline_create_safekeepers(
             new_sk_set: None,
             cplane_notified_generation: 0,
             deleted_at: None,
+            sk_witnesses,
         };
         let inserted = self
             .persistence
@@ -460,6 +479,7 @@ pub(crate) async fn timeline_import(&self, req: TimelineImportRequest) -> Result
             new_sk_set: None,
             cplane_notified_generation: 1,
             deleted_at: None,
+            sk_witnesses: Vec::new(),
         };
         let inserted = self.persistence.insert_timeline(persistence).await?;
         if inserted {
@@ -990,6 +1010,7 @@ async fn tenant_timeline_pull_from_peers(
             timeline_id,
             http_hosts,
             ignore_tombstone: Some(true),
+            incremental: None,
         };
 
         const SK_PULL_TIMELINE_RECONCILE_TIMEOUT: Duration = Duration::from_secs(30);
@@ -1154,6 +1175,11 @@ pub(crate) async fn tenant_timeline_safekeeper_migrate(
             .iter()
             .map(|&id| NodeId(id as u64))
             .collect::<Vec<_>>();
+        let cur_witnesses = timeline
+            .sk_witnesses
+            .iter()
+            .map(|&id| NodeId(id as u64))
+            .collect::<Vec<_>>();
 
         // Validate that we are not migrating to a decomissioned safekeeper.
         for sk in new_safekeepers.iter() {
@@ -1167,9 +1193,31 @@ pub(crate) async fn tenant_timeline_safekeeper_migrate(
             }
         }
 
+        let cur_safekeepers = self.get_safekeepers(&timeline.sk_set)?;
+        let cur_sk_member_set =
+            Self::make_member_set(&cur_safekeepers).map_err(ApiError::InternalServerError)?;
+
+        // Witnesses keep their role while they stay members; safekeepers
+        // joining the timeline store WAL.
+        let joint_witnesses = Self::witnesses_in(
+            &timeline.sk_witnesses,
+            &[&cur_sk_member_set, &new_sk_member_set],
+        );
+        // Validate witness placement before persisting anything; the
+        // generation doesn't matter here.
+        membership::Configuration {
+            generation: SafekeeperGeneration::new(timeline.generation as u32),
+            members: cur_sk_member_set.clone(),
+            new_members: Some(new_sk_member_set.clone()),
+            witnesses: joint_witnesses.clone(),
+        }
+        .validate()
+        .map_err(ApiError::BadRequest)?;
+
         tracing::info!(
             ?cur_sk_set,
             ?new_sk_set,
+            ?joint_witnesses,
             "Migrating timeline to new safekeeper set",
         );
 
@@ -1204,18 +1252,16 @@ pub(crate) async fn tenant_timeline_safekeeper_migrate(
                     generation,
                     &cur_sk_set,
                     Some(&new_sk_set),
+                    &cur_witnesses,
                 )
                 .await?;
         }
 
-        let cur_safekeepers = self.get_safekeepers(&timeline.sk_set)?;
-        let cur_sk_member_set =
-            Self::make_member_set(&cur_safekeepers).map_err(ApiError::InternalServerError)?;
-
         let joint_config = membership::Configuration {
             generation,
             members: cur_sk_member_set,
             new_members: Some(new_sk_member_set.clone()),
+            witnesses: joint_witnesses,
         };
 
         // 4. Call PUT configuration on safekeepers from the current set,
@@ -1293,14 +1339,23 @@ pub(crate) async fn tenant_timeline_safekeeper_migrate(
 
         let generation = generation.next();
 
+        let new_witnesses = Self::witnesses_in(&timeline.sk_witnesses, &[&new_sk_member_set]);
         let new_conf = membership::Configuration {
             generation,
             members: new_sk_member_set,
             new_members: None,
+            witnesses: new_witnesses.clone(),
         };
 
         self.persistence
-            .update_timeline_membership(tenant_id, timeline_id, generation, &new_sk_set, None)
+            .update_timeline_membership(
+                tenant_id,
+                timeline_id,
+                generation,
+                &new_sk_set,
+                None,
+                &new_witnesses,
+            )
             .await?;
 
         // TODO(diko): at this point we have already updated the timeline in the database,
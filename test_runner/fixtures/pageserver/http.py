@@ -487,6 +487,16 @@ class PageserverHttpClient(requests.Session, MetricsGetter):
         self.verbose_error(res)
         return res.json()
 
+    def tenant_shard_handoff(
+        self, tenant_id: TenantId | TenantShardId, connstr: str | None
+    ) -> dict[str, Any]:
+        res = self.put(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/handoff",
+            json={"connstr": connstr},
+        )
+        self.verbose_error(res)
+        return res.json()
+
     def set_tenant_config(self, tenant_id: TenantId | TenantShardId, config: dict[str, Any]):
         """
         Only use this via storage_controller.pageserver_api().
@@ -375,6 +375,54 @@ class SafekeeperHttpClient(requests.Session, MetricsGetter):
         res.raise_for_status()
         return TermBumpResponse.from_json(res.json())
 
+    def timeline_wal_archive(
+        self, tenant_id: TenantId, timeline_id: TimelineId, enabled: bool
+    ) -> dict[str, Any]:
+        res = self.put(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/wal_archive",
+            json={"enabled": enabled},
+        )
+        res.raise_for_status()
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_verify_wal(
+        self, tenant_id: TenantId, timeline_id: TimelineId, source: str = "local"
+    ) -> dict[str, Any]:
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/verify_wal",
+            params={"source": source},
+        )
+        res.raise_for_status()
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_wal_download(
+        self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        from_lsn: Lsn,
+        until_lsn: Lsn | None = None,
+    ) -> bytes:
+        params = {"from_lsn": str(from_lsn)}
+        if until_lsn is not None:
+            params["until_lsn"] = str(until_lsn)
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/wal",
+            params=params,
+        )
+        res.raise_for_status()
+        return res.content
+
+    def tenant_wal_quota(self, tenant_id: TenantId) -> dict[str, Any]:
+        res = self.get(f"http://localhost:{self.port}/v1/tenant/{tenant_id}/wal_quota")
+        res.raise_for_status()
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
     def record_safekeeper_info(self, tenant_id: TenantId, timeline_id: TimelineId, body):
         res = self.post(
             f"http://localhost:{self.port}/v1/record_safekeeper_info/{tenant_id}/{timeline_id}",
@@ -178,6 +178,14 @@ def test_fully_custom_config(positive_env: NeonEnv):
             "refill_amount": 1000,
             "max": 1000,
         },
+        "wal_ingest_throttle": {
+            "task_kinds": ["WalReceiverConnectionHandler"],
+            "initial": 0,
+            "refill_interval": "1s",
+            "refill_amount": 100000000,
+            "max": 100000000,
+        },
+        "wal_ingest_max_unflushed_bytes": 1073741824,
         "walreceiver_connect_timeout": "13m",
         "image_layer_creation_check_threshold": 1,
         "lsn_lease_length": "1m",
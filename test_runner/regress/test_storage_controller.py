@@ -3648,6 +3648,104 @@ def test_storage_controller_proxy_during_migration(
         raise
 
 
+def test_storage_controller_live_migration_handoff(neon_env_builder: NeonEnvBuilder):
+    """
+    While both locations are attached during a live migration, the origin can hand off its
+    page service clients to the destination: computes get a "moved" response and should
+    carry on reading from the destination, before they are notified of the migration.
+    """
+    neon_env_builder.num_pageservers = 2
+    neon_env_builder.enable_pageserver_remote_storage(s3_storage())
+    env = neon_env_builder.init_configs()
+    env.start()
+
+    tenant_id = env.initial_tenant
+    timeline_id = env.initial_timeline
+    env.create_tenant(tenant_id, timeline_id)
+
+    workload = Workload(env, tenant_id, timeline_id)
+    workload.init()
+    workload.write_rows(64)
+
+    origin_pageserver = env.get_tenant_pageserver(tenant_id)
+    assert origin_pageserver is not None
+    dest_pageserver = [p for p in env.pageservers if p.id != origin_pageserver.id][0]
+    dest_connstr = f"postgresql://no_user@localhost:{dest_pageserver.service_port.pg}"
+
+    # Stop the migration while both locations are attached, but before the compute is notified.
+    migration_failpoint = MigrationFailpoints.PRE_AWAIT_LSN
+    env.storage_controller.configure_failpoints((migration_failpoint.value, "pause"))
+
+    # Read through a single connection, so that it has to follow the moved response rather
+    # than picking up the new location on reconnect.
+    endpoint = workload.endpoint()
+    stop_reads = threading.Event()
+    reads_done = 0
+
+    def read_load():
+        nonlocal reads_done
+        with endpoint.cursor() as cur:
+            while not stop_reads.is_set():
+                endpoint.clear_buffers(cursor=cur)
+                cur.execute(f"SELECT COUNT(*) FROM {workload.table}")
+                assert cur.fetchall() == [(workload.expect_rows,)]
+                reads_done += 1
+
+    def reads_progress(since: int):
+        assert reads_done > since
+
+    try:
+        with concurrent.futures.ThreadPoolExecutor(max_workers=2) as executor:
+            migrate_fut = executor.submit(
+                env.storage_controller.tenant_shard_migrate,
+                TenantShardId(tenant_id, 0, 0),
+                dest_pageserver.id,
+            )
+
+            def has_hit_migration_failpoint():
+                assert env.storage_controller.log_contains(
+                    f"at failpoint {migration_failpoint.value}"
+                )
+
+            wait_until(has_hit_migration_failpoint)
+
+            reads_fut = executor.submit(read_load)
+            wait_until(lambda: reads_progress(0))
+
+            origin_client = origin_pageserver.http_client()
+            response = origin_client.tenant_shard_handoff(tenant_id, dest_connstr)
+            assert response["page_service_connections"] > 0
+
+            def reads_moved():
+                moved = origin_client.get_metric_value(
+                    "pageserver_moved_pagestream_responses_total"
+                )
+                assert moved is not None and moved > 0
+                response = dest_pageserver.http_client().tenant_shard_handoff(tenant_id, None)
+                assert response["page_service_connections"] > 0
+
+            wait_until(reads_moved)
+
+            # Reads carry on against the destination, without the compute being reconfigured.
+            moved_at = reads_done
+            wait_until(lambda: reads_progress(moved_at))
+            assert not reads_fut.done()
+
+            stop_reads.set()
+            reads_fut.result()
+
+            env.storage_controller.configure_failpoints((migration_failpoint.value, "off"))
+            migrate_fut.result()
+    except:
+        # Always disable 'pause' failpoints, even on failure, to avoid hanging in shutdown
+        stop_reads.set()
+        env.storage_controller.configure_failpoints((migration_failpoint.value, "off"))
+        raise
+
+    assert env.get_tenant_pageserver(tenant_id).id == dest_pageserver.id
+    workload.validate()
+
+
 @run_only_on_default_postgres("this is like a 'unit test' against storcon db")
 def test_safekeeper_deployment_time_update(neon_env_builder: NeonEnvBuilder):
     env = neon_env_builder.init_configs()
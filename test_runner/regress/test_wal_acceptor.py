@@ -1,6 +1,7 @@
 from __future__ import annotations
 
 import filecmp
+import json
 import logging
 import os
 import random
@@ -21,6 +22,7 @@ import psycopg2.errors
 import psycopg2.extras
 import pytest
 import requests
+import zstandard
 from fixtures.common_types import Lsn, TenantId, TimelineId
 from fixtures.log_helper import log
 from fixtures.metrics import parse_metrics
@@ -865,6 +867,67 @@ def test_start_replication_term(neon_env_builder: NeonEnvBuilder):
         assert "failed to acquire term 3" in str(excinfo.value)
 
 
+# Test that vanilla pg_receivewal can follow a timeline from a safekeeper, starting
+# at a segment boundary and using the physical replication commands it issues.
+def test_pg_receivewal(neon_env_builder: NeonEnvBuilder, pg_bin: PgBin, test_output_dir: Path):
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.initial_timeline
+
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1, 100000), 'payload'")
+    flush_lsn = Lsn(endpoint.safe_psql("SELECT pg_current_wal_flush_lsn()")[0][0])
+
+    sk = env.safekeepers[0]
+    conn_opts = {
+        "host": "127.0.0.1",
+        "options": f"-c timeline_id={timeline_id} tenant_id={tenant_id}",
+    }
+    connector = PgProtocol(**conn_opts)
+    # Like Postgres, which has no history file for the initial timeline.
+    with pytest.raises(
+        psycopg2.Error,
+        match='could not open file "pg_wal/00000001.history": No such file or directory',
+    ):
+        connector.safe_psql("TIMELINE_HISTORY 1", port=sk.port.pg)
+    assert connector.safe_psql("SHOW wal_segment_size", port=sk.port.pg) == [("16MB",)]
+
+    wal_dir = test_output_dir / "received_wal"
+    wal_dir.mkdir()
+    connstr = (
+        f"host=127.0.0.1 port={sk.port.pg} "
+        f"options='-c timeline_id={timeline_id} tenant_id={tenant_id}'"
+    )
+    pg_bin.run_capture(
+        [
+            "pg_receivewal",
+            "--dbname",
+            connstr,
+            "--directory",
+            str(wal_dir),
+            "--endpos",
+            str(flush_lsn),
+            "--no-loop",
+            "--no-password",
+        ]
+    )
+
+    received = sorted(f.name for f in wal_dir.iterdir())
+    log.info(f"received WAL files: {received}")
+    assert len(received) > 0
+    # Postgres names segments with the timeline first, and we only serve timeline 1.
+    assert all(name.startswith("00000001") for name in received)
+    # Streaming starts at a segment boundary, which may precede the timeline start;
+    # every segment must still begin with the page header for its address.
+    wal_seg_size = 16 * 1024 * 1024
+    for name in received:
+        with open(wal_dir / name, "rb") as f:
+            header = f.read(16)
+        xlp_pageaddr = int.from_bytes(header[8:16], "little")
+        assert xlp_pageaddr == (int(name[8:16], 16) << 32) + int(name[16:24], 16) * wal_seg_size
+
+
 # Test auth on all ports: WAL service (postgres protocol), WAL service tenant only and http.
 def test_sk_auth(neon_env_builder: NeonEnvBuilder):
     neon_env_builder.auth_enabled = True
@@ -1793,12 +1856,8 @@ def test_pull_timeline_gc(neon_env_builder: NeonEnvBuilder):
 # to fetch the log up to <last_log_term, flush_lsn>. This is unsafe if term
 # changes during the procedure (unless timeline is locked all the time but we
 # don't want that): recepient might end up with mix of WAL from different
-# histories. Thus the schedule above is expected to fail. Later we'd allow
-# pull_timeline to only initialize timeline to any valid state (up to
-# commit_lsn), holding switch to fully new configuration until it recovers
-# enough, so it won't be affected by term change anymore.
-#
-# Expected to fail while term check is not implemented.
+# histories. Thus the snapshot in the schedule above is aborted; the recipient
+# retries it and must end up with the donor's WAL of the new term.
 def test_pull_timeline_term_change(neon_env_builder: NeonEnvBuilder):
     neon_env_builder.auth_enabled = True
     neon_env_builder.num_safekeepers = 3
@@ -1843,8 +1902,20 @@ def test_pull_timeline_term_change(neon_env_builder: NeonEnvBuilder):
     assert term_after > term_before, f"term_after={term_after}, term_before={term_before}"
 
     src_http.configure_failpoints(("sk-snapshot-after-list-pausable", "off"))
-    with pytest.raises(requests.exceptions.HTTPError):
-        pt_handle.join()
+    pt_handle.join()
+    assert dst_sk.log_contains("snapshot attempt 1 failed")
+
+    ep.stop()
+    timeline_start_lsn = src_sk.get_timeline_start_lsn(tenant_id, timeline_id)
+    dst_status = dst_sk.http_client().timeline_status(tenant_id, timeline_id)
+    assert dst_status.term == term_after
+    digests = [
+        sk.http_client().timeline_digest(
+            tenant_id, timeline_id, timeline_start_lsn, dst_status.flush_lsn
+        )
+        for sk in [src_sk, dst_sk]
+    ]
+    assert digests[0] == digests[1], f"digest on src is {digests[0]} but on dst is {digests[1]}"
 
 
 def test_pull_timeline_while_evicted(neon_env_builder: NeonEnvBuilder):
@@ -2907,3 +2978,339 @@ def test_global_disk_usage_limit(neon_env_builder: NeonEnvBuilder):
         with conn.cursor() as cur:
             cur.execute("select count(*) from t2")
             assert cur.fetchone() == (3000,)
+
+
+# Test that enabled WAL archive export uploads complete segments and the
+# partial one in the standard archive layout.
+def test_wal_archive_export(neon_env_builder: NeonEnvBuilder, test_output_dir: Path):
+    neon_env_builder.num_safekeepers = 1
+    env = neon_env_builder.init_start()
+    sk = env.safekeepers[0]
+
+    archive_dir = test_output_dir / "wal_archive"
+    archive_dir.mkdir()
+    sk.stop()
+    sk.start(
+        extra_opts=[
+            f"--wal-archive-storage={{local_path='{archive_dir}'}}",
+            "--partial-backup-timeout=1s",
+        ]
+    )
+
+    tenant_id, timeline_id = env.create_tenant()
+    http_cli = sk.http_client()
+    res = http_cli.timeline_wal_archive(tenant_id, timeline_id, True)
+    assert res["enabled"]
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("create table t(key int, value text)")
+    endpoint.safe_psql("insert into t select generate_series(1, 180000), 'payload'")
+    lsn = Lsn(endpoint.safe_psql("select pg_current_wal_flush_lsn()")[0][0])
+    assert lsn > Lsn("0/2000000")
+
+    timeline_archive = archive_dir / str(tenant_id) / str(timeline_id)
+
+    def archived():
+        assert (timeline_archive / "000000010000000000000001").exists()
+        partials = list(timeline_archive.glob("*.partial"))
+        assert len(partials) == 1
+        assert partials[0].stat().st_size == 16 * 1024 * 1024
+
+    wait_until(archived)
+
+    res = http_cli.timeline_wal_archive(tenant_id, timeline_id, False)
+    assert not res["enabled"]
+    assert Lsn(res["archived_lsn"]) >= Lsn("0/2000000")
+
+
+def test_wal_compression(neon_env_builder: NeonEnvBuilder, test_output_dir: Path):
+    """
+    Check that closed segments are compressed on safekeeper disk in the zstd
+    seekable format, and are transparently decompressed when WAL is read.
+    """
+    neon_env_builder.num_safekeepers = 1
+    env = neon_env_builder.init_start()
+    sk = env.safekeepers[0]
+
+    archive_dir = test_output_dir / "wal_archive"
+    archive_dir.mkdir()
+    sk.stop()
+    sk.start(
+        extra_opts=[
+            "--wal-compression-level=1",
+            f"--wal-archive-storage={{local_path='{archive_dir}'}}",
+        ]
+    )
+
+    tenant_id, timeline_id = env.create_tenant()
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("create table t(key int, value text)")
+    endpoint.safe_psql("insert into t select generate_series(1, 540000), 'payload'")
+
+    segment = "000000010000000000000002"
+    tli_dir = sk.timeline_dir(tenant_id, timeline_id)
+
+    def compressed():
+        segments = sk.list_segments(tenant_id, timeline_id)
+        assert f"{segment}.zst" in segments
+        assert segment not in segments
+
+    wait_until(compressed)
+
+    with open(tli_dir / f"{segment}.zst", "rb") as f:
+        reader = zstandard.ZstdDecompressor().stream_reader(f, read_across_frames=True)
+        decompressed = reader.read()
+    assert len(decompressed) == 16 * 1024 * 1024
+
+    # WAL archive export reads WAL through the safekeeper WAL reader.
+    sk.http_client().timeline_wal_archive(tenant_id, timeline_id, True)
+    archived = archive_dir / str(tenant_id) / str(timeline_id) / segment
+
+    def is_archived():
+        assert archived.exists()
+
+    wait_until(is_archived)
+    assert archived.read_bytes() == decompressed
+
+
+def test_pull_timeline_incremental(neon_env_builder: NeonEnvBuilder):
+    """
+    Check that incremental pull_timeline onto a safekeeper which missed some
+    WAL reuses the segments it already has, and that the transfer is resumed
+    if it breaks.
+    """
+    neon_env_builder.num_safekeepers = 3
+    env = neon_env_builder.init_start()
+    tenant_id, timeline_id = env.create_tenant()
+    (src_sk, dst_sk) = (env.safekeepers[0], env.safekeepers[2])
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("create table t(key int, value text)")
+    endpoint.safe_psql("insert into t select generate_series(1, 180000), 'payload'")
+
+    first_segment = dst_sk.timeline_dir(tenant_id, timeline_id) / "000000010000000000000001"
+
+    def segment_closed():
+        assert dst_sk.get_flush_lsn(tenant_id, timeline_id) > Lsn("0/2000000")
+
+    wait_until(segment_closed)
+    inode_before = first_segment.stat().st_ino
+
+    log.info("continue without the last safekeeper")
+    endpoint.stop()
+    dst_sk.stop()
+    endpoint.active_safekeepers = [1, 2]
+    endpoint.start()
+    endpoint.safe_psql("insert into t select generate_series(1, 360000), 'payload'")
+    endpoint.stop()
+
+    dst_sk.start()
+    src_sk.http_client().configure_failpoints(("sk-snapshot-after-segment", "1*return"))
+    res = dst_sk.pull_timeline([src_sk], tenant_id, timeline_id, incremental=True)
+    assert res["safekeeper_host"] is not None
+    assert dst_sk.log_contains("snapshot attempt 1 failed")
+    # The segment dst already had is reused, not transferred again.
+    assert first_segment.stat().st_ino == inode_before
+
+    src_flush_lsn = src_sk.get_flush_lsn(tenant_id, timeline_id)
+    dst_flush_lsn = dst_sk.get_flush_lsn(tenant_id, timeline_id)
+    assert dst_flush_lsn >= src_flush_lsn
+    timeline_start_lsn = src_sk.get_timeline_start_lsn(tenant_id, timeline_id)
+    digests = [
+        sk.http_client().timeline_digest(tenant_id, timeline_id, timeline_start_lsn, dst_flush_lsn)
+        for sk in [src_sk, dst_sk]
+    ]
+    assert digests[0] == digests[1], f"digest on src is {digests[0]} but on dst is {digests[1]}"
+
+    # Now dst is up to date, so there is nothing to pull.
+    res = dst_sk.pull_timeline([src_sk], tenant_id, timeline_id, incremental=True)
+    assert res["safekeeper_host"] is None
+
+
+def test_wal_quota(neon_env_builder: NeonEnvBuilder):
+    """
+    Check retained WAL quotas: close to the quota the safekeeper asks the
+    compute to throttle, above it appends are rejected, and writes resume once
+    the quota is lifted.
+    """
+    neon_env_builder.num_safekeepers = 1
+    neon_env_builder.enable_safekeeper_remote_storage(s3_storage())
+    # Backpressure starts after two segments of retained WAL.
+    neon_env_builder.safekeeper_extra_opts = [
+        f"--timeline-wal-quota-bytes={4 * 16 * 1024 * 1024}",
+        "--wal-quota-backpressure-ratio=0.5",
+    ]
+    env = neon_env_builder.init_start()
+    tenant_id, timeline_id = env.create_tenant()
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("create table t(key int, value text)")
+    sk = env.safekeepers[0]
+    http_cli = sk.http_client()
+
+    # Keep WAL on disk by not backing it up.
+    http_cli.configure_failpoints([("backup-lsn-range-pausable", "pause")])
+
+    def run_insert():
+        with closing(endpoint.connect()) as conn:
+            with conn.cursor() as cur:
+                cur.execute("insert into t select generate_series(1, 1000000), 'payload'")
+
+    bg_thread = threading.Thread(target=run_insert)
+    bg_thread.start()
+
+    def throttled():
+        quota = http_cli.tenant_wal_quota(tenant_id)
+        assert quota["timelines"][0]["state"] == "backpressure"
+        assert endpoint.safe_psql("select backpressure_throttling_time()")[0][0] > 0
+
+    wait_until(throttled)
+    metrics = http_cli.get_metrics()
+    assert metrics.query_one("safekeeper_wal_quota_timelines", {"state": "backpressure"}).value == 1
+    time.sleep(2)
+    assert bg_thread.is_alive(), "insert finished while compute should be throttled"
+
+    log.info("shrink the quota below retained WAL to get appends rejected")
+    sk.stop().start(extra_opts=["--timeline-wal-quota-bytes=1"])
+
+    def rejected():
+        # Backends are still throttled, but a WAL switch isn't and makes the
+        # compute send WAL.
+        endpoint.safe_psql("select pg_switch_wal()")
+        assert endpoint.log_contains("retains more WAL than its quota") is not None
+        quota = sk.http_client().tenant_wal_quota(tenant_id)
+        assert quota["timeline_quota_bytes"] == 1
+        assert quota["timelines"][0]["state"] == "timeline_exceeded"
+
+    wait_until(rejected)
+    metrics = sk.http_client().get_metrics()
+    assert (
+        metrics.query_one("safekeeper_wal_quota_rejected_appends_total", {"quota": "timeline"}).value
+        > 0
+    )
+    assert bg_thread.is_alive(), "insert finished while appends are rejected"
+
+    log.info("lift the quota")
+    sk.stop().start(extra_opts=[])
+    bg_thread.join(timeout=120)
+    assert not bg_thread.is_alive(), "insert did not complete after lifting the quota"
+    assert endpoint.safe_psql("select count(*) from t")[0][0] == 1000000
+
+
+def test_verify_wal(neon_env_builder: NeonEnvBuilder):
+    """
+    Check that WAL verification, through the HTTP API and the offline CLI,
+    accepts valid WAL and reports the position of a corrupted record.
+    """
+    neon_env_builder.num_safekeepers = 1
+    env = neon_env_builder.init_start()
+    tenant_id, timeline_id = env.create_tenant()
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("create table t(key int, value text)")
+    endpoint.safe_psql("insert into t select generate_series(1, 540000), 'payload'")
+    sk = env.safekeepers[0]
+    http_cli = sk.http_client()
+
+    res = http_cli.timeline_verify_wal(tenant_id, timeline_id)
+    log.info(f"verify_wal: {res}")
+    assert res["error"] is None
+    assert res["records"] > 0
+    assert res["end_lsn"] == res["flush_lsn"]
+
+    endpoint.stop()
+    sk.stop()
+    tli_dir = sk.timeline_dir(tenant_id, timeline_id)
+
+    def verify_offline() -> subprocess.CompletedProcess[str]:
+        return subprocess.run(
+            [str(env.neon_binpath / "safekeeper"), "--verify-wal", str(tli_dir)],
+            capture_output=True,
+            text=True,
+        )
+
+    proc = verify_offline()
+    assert proc.returncode == 0, proc.stderr
+    assert json.loads(proc.stdout)["error"] is None
+
+    # Corrupt a byte in the middle of the first closed segment.
+    segment = sorted(s for s in sk.list_segments(tenant_id, timeline_id) if len(s) == 24)[0]
+    offset = 8 * 1024 * 1024 + 100
+    corrupted_lsn = Lsn(int(segment[8:16], 16) << 32 | int(segment[16:24], 16) << 24) + offset
+    with open(tli_dir / segment, "r+b") as f:
+        f.seek(offset)
+        byte = f.read(1)
+        f.seek(offset)
+        f.write(bytes([byte[0] ^ 0xFF]))
+
+    proc = verify_offline()
+    assert proc.returncode != 0
+    error = json.loads(proc.stdout.splitlines()[0])["error"]
+    log.info(f"offline verify_wal error: {error}")
+    assert Lsn(error["lsn"]) <= corrupted_lsn
+
+    sk.start()
+    res = http_cli.timeline_verify_wal(tenant_id, timeline_id)
+    assert res["error"] is not None
+    assert Lsn(res["error"]["lsn"]) <= corrupted_lsn
+    assert Lsn(res["end_lsn"]) <= corrupted_lsn
+
+
+def test_wal_download(neon_env_builder: NeonEnvBuilder):
+    """
+    Check that raw WAL of an LSN range can be downloaded from local segments,
+    including the .partial one, and from offloaded segments once the local
+    copy is gone.
+    """
+    neon_env_builder.num_safekeepers = 1
+    neon_env_builder.enable_safekeeper_remote_storage(default_remote_storage())
+    env = neon_env_builder.init_start()
+    tenant_id, timeline_id = env.create_tenant()
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("create table t(key int, value text)")
+    endpoint.safe_psql("insert into t select generate_series(1, 540000), 'payload'")
+    sk = env.safekeepers[0]
+    http_cli = sk.http_client()
+    tli_dir = sk.timeline_dir(tenant_id, timeline_id)
+    seg_size = 16 * 1024 * 1024
+
+    def segment_start(name: str) -> Lsn:
+        return Lsn(int(name[8:16], 16) << 32 | int(name[16:24], 16) << 24)
+
+    endpoint.stop()
+    flush_lsn = http_cli.timeline_status(tenant_id, timeline_id).flush_lsn
+    segments = sk.list_segments(tenant_id, timeline_id)
+
+    # A closed segment, and a range crossing into the next one. Skip the first
+    # segment, bytes before the timeline start are not on disk there.
+    closed = sorted(s for s in segments if len(s) == 24)[1]
+    start = segment_start(closed)
+    with open(tli_dir / closed, "rb") as f:
+        closed_content = f.read()
+    wal = http_cli.timeline_wal_download(tenant_id, timeline_id, start, start + seg_size)
+    assert wal == closed_content
+    wal = http_cli.timeline_wal_download(
+        tenant_id, timeline_id, start + 1000, start + seg_size + 10
+    )
+    assert wal[: seg_size - 1000] == closed_content[1000:]
+    assert len(wal) == seg_size - 1000 + 10
+
+    # The tail of WAL is served from the .partial segment up to flush_lsn.
+    partial_seg = [s for s in segments if s.endswith(".partial")][0]
+    partial_start = segment_start(partial_seg)
+    with open(tli_dir / partial_seg, "rb") as f:
+        partial_content = f.read()
+    wal = http_cli.timeline_wal_download(tenant_id, timeline_id, partial_start)
+    assert wal == partial_content[: flush_lsn - partial_start]
+
+    with pytest.raises(http_cli.HTTPError, match="400"):
+        http_cli.timeline_wal_download(tenant_id, timeline_id, partial_start, flush_lsn + 1)
+
+    # Once the local copy is gone, the segment is read from remote storage.
+    wait(
+        partial(is_segment_offloaded, sk, tenant_id, timeline_id, start + seg_size),
+        f"segment ending at {start + seg_size} get offloaded",
+    )
+    sk.stop()
+    os.remove(tli_dir / closed)
+    sk.start()
+    wal = http_cli.timeline_wal_download(tenant_id, timeline_id, start, start + seg_size)
+    assert wal == closed_content